@@ -0,0 +1,98 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Materialize-then-select versus the fused top-k scan, reporting time and
+//! peak heap growth per query. Peak memory of the fused scan stays O(k) as
+//! the row count grows. Run with `cargo bench --bench top_k_fused`.
+
+use scann::distance_measures::{top_k_one_to_many, DistanceMeasure, SquaredL2Distance};
+use scann::{DatapointPtr, DenseDataset};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const DIM: usize = 32;
+const K: usize = 10;
+const REPEATS: usize = 10;
+
+// Tracks live and peak heap bytes so each variant's scratch can be measured.
+struct CountingAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Runs `f` REPEATS times, returning ns per query and peak bytes allocated
+// above the starting level.
+fn measure<R>(mut f: impl FnMut() -> R) -> (f64, usize) {
+    let base = LIVE.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..REPEATS {
+        black_box(f());
+    }
+    let elapsed = start.elapsed();
+    (elapsed.as_nanos() as f64 / REPEATS as f64, PEAK.load(Ordering::Relaxed) - base)
+}
+
+fn main() {
+    // Deterministic pseudo-random data; the values do not matter here.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    };
+    let measure_fn = SquaredL2Distance::new();
+    for rows in [10_000usize, 100_000, 1_000_000] {
+        let data: Vec<Vec<f32>> = (0..rows).map(|_| (0..DIM).map(|_| next()).collect()).collect();
+        let dataset = DenseDataset::new(data, DIM);
+        let query = DatapointPtr::new((0..DIM).map(|_| next()).collect());
+
+        let materialized = || {
+            let mut distances = vec![0.0f32; rows];
+            measure_fn.compute_one_to_many(&query, &dataset, &mut distances);
+            let mut ranked: Vec<(usize, f32)> = distances.into_iter().enumerate().collect();
+            ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+            ranked.truncate(K);
+            ranked
+        };
+        assert_eq!(materialized(), top_k_one_to_many(&measure_fn, &query, &dataset, K), "top-k paths disagree");
+
+        let (materialized_ns, materialized_bytes) = measure(materialized);
+        let (fused_ns, fused_bytes) = measure(|| top_k_one_to_many(&measure_fn, &query, &dataset, K));
+
+        println!(
+            "rows {:>9}   materialized {:>10.0} ns {:>10} B   fused {:>10.0} ns {:>6} B",
+            rows, materialized_ns, materialized_bytes, fused_ns, fused_bytes
+        );
+    }
+}
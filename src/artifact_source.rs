@@ -0,0 +1,196 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access to artifact files by name, confined to one artifacts root.
+
+use super::{util, ScannError};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub trait ArtifactSource: Send + Sync {
+    fn open(&self, name: &str) -> Result<Box<dyn Read + '_>, Box<dyn Error>>;
+    fn create(&self, name: &str) -> Result<Box<dyn Write + '_>, Box<dyn Error>>;
+    fn exists(&self, name: &str) -> bool;
+}
+
+// Artifact names are relative paths made of normal components only;
+// absolute paths, drive prefixes and ".." are rejected before any access.
+pub fn validate_artifact_name(name: &str) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(util::invalid_argument_error(&format!(
+            "Artifact name '{}' must be a relative path inside the artifacts root",
+            name
+        )));
+    }
+    Ok(())
+}
+
+pub struct FsArtifactSource {
+    root: PathBuf,
+}
+
+impl FsArtifactSource {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, Box<dyn Error>> {
+        let root = root.as_ref();
+        let root = fs::canonicalize(root).map_err(|e| ScannError {
+            message: format!("Failed to open artifacts root {}: {}", root.display(), e),
+        })?;
+        Ok(FsArtifactSource { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Canonicalizes the deepest existing ancestor so symlinks pointing
+    // outside the root are caught as well as lexical traversal.
+    fn resolve(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        validate_artifact_name(name)?;
+        let joined = self.root.join(name);
+        let mut existing = joined.as_path();
+        let mut rest = Vec::new();
+        while !existing.exists() {
+            let (Some(parent), Some(file)) = (existing.parent(), existing.file_name()) else {
+                break;
+            };
+            rest.push(file.to_owned());
+            existing = parent;
+        }
+        let mut resolved = fs::canonicalize(existing).map_err(|e| ScannError {
+            message: format!("Failed to resolve artifact {}: {}", name, e),
+        })?;
+        for file in rest.into_iter().rev() {
+            resolved.push(file);
+        }
+        if !resolved.starts_with(&self.root) {
+            return Err(util::invalid_argument_error(&format!(
+                "Artifact '{}' resolves outside the artifacts root {}",
+                name,
+                self.root.display()
+            )));
+        }
+        Ok(resolved)
+    }
+}
+
+impl ArtifactSource for FsArtifactSource {
+    fn open(&self, name: &str) -> Result<Box<dyn Read + '_>, Box<dyn Error>> {
+        let path = self.resolve(name)?;
+        let file = File::open(&path).map_err(|e| ScannError {
+            message: format!("Failed to open artifact {}: {}", path.display(), e),
+        })?;
+        Ok(Box::new(file))
+    }
+
+    fn create(&self, name: &str) -> Result<Box<dyn Write + '_>, Box<dyn Error>> {
+        let path = self.resolve(name)?;
+        let file = File::create(&path).map_err(|e| ScannError {
+            message: format!("Failed to create artifact {}: {}", path.display(), e),
+        })?;
+        Ok(Box::new(file))
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.resolve(name).is_ok_and(|path| path.exists())
+    }
+}
+
+// Artifacts held in memory, for tests and targets without a filesystem.
+// Clones share the same files.
+#[derive(Clone, Default)]
+pub struct MemoryArtifactSource {
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemoryArtifactSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.files.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// Buffers writes and publishes the file when dropped, like closing a file.
+struct MemoryWriter {
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    name: String,
+    buffer: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.files.lock().unwrap().insert(self.name.clone(), self.buffer.clone());
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.files.lock().unwrap().insert(std::mem::take(&mut self.name), buffer);
+    }
+}
+
+impl ArtifactSource for MemoryArtifactSource {
+    fn open(&self, name: &str) -> Result<Box<dyn Read + '_>, Box<dyn Error>> {
+        validate_artifact_name(name)?;
+        match self.files.lock().unwrap().get(name) {
+            Some(bytes) => Ok(Box::new(Cursor::new(bytes.clone()))),
+            None => Err(util::invalid_argument_error(&format!("No in-memory artifact named {}", name))),
+        }
+    }
+
+    fn create(&self, name: &str) -> Result<Box<dyn Write + '_>, Box<dyn Error>> {
+        validate_artifact_name(name)?;
+        Ok(Box::new(MemoryWriter {
+            files: self.files.clone(),
+            name: name.to_string(),
+            buffer: Vec::new(),
+        }))
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        validate_artifact_name(name).is_ok() && self.files.lock().unwrap().contains_key(name)
+    }
+}
+
+pub fn read_artifact(source: &dyn ArtifactSource, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    source.open(name)?.read_to_end(&mut bytes).map_err(|e| ScannError {
+        message: format!("Failed to read artifact {}: {}", name, e),
+    })?;
+    Ok(bytes)
+}
+
+pub fn write_artifact(source: &dyn ArtifactSource, name: &str, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut writer = source.create(name)?;
+    writer.write_all(bytes).and_then(|_| writer.flush()).map_err(|e| ScannError {
+        message: format!("Failed to write artifact {}: {}", name, e),
+    })?;
+    Ok(())
+}
@@ -0,0 +1,834 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plain artifacts directories and merging several of them into one index.
+//!
+//! A directory holds `dataset.npy` (or `dataset.npy.zst`), `docids.txt` with
+//! one docid per row, `index_config.txt` with "key: value" lines, and
+//! optionally `index.blob` with a trained partitioning,
+//! `attributes.blob` with row-aligned attribute columns and
+//! `build_report.json` (see `build::save_build_report`).
+//!
+//! Writers hold an exclusive advisory lock on `.artifacts.lock` and finish
+//! by writing `manifest.txt` with an incremented generation and a checksum
+//! per file; loaders hold a shared lock and, after loading, re-check the
+//! manifest and checksums, retrying when the files changed mid-load. On
+//! filesystems without lock support a warning is printed and the generation
+//! check alone guards against mixed loads.
+
+use super::artifact_source::ArtifactSource;
+use super::{artifact_source, assets, attribute_store, blob, build, build_info, leaf_codes, npy, quantization, tree, util, ScannError};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const CONFIG_NAME: &str = "index_config.txt";
+const MANIFEST_NAME: &str = "manifest.txt";
+const LOCK_NAME: &str = ".artifacts.lock";
+const DOCIDS_NAME: &str = "docids.txt";
+const PROVENANCE_NAME: &str = "provenance.txt";
+const BLOB_NAME: &str = "index.blob";
+const LEAF_CODES_NAME: &str = "leaf_codes.bin";
+const ATTRIBUTES_NAME: &str = "attributes.blob";
+
+// Files covered by the manifest when present.
+const MANIFEST_FILES: &[&str] = &[
+    CONFIG_NAME,
+    DOCIDS_NAME,
+    "dataset.npy",
+    "dataset.npy.zst",
+    PROVENANCE_NAME,
+    BLOB_NAME,
+    LEAF_CODES_NAME,
+    ATTRIBUTES_NAME,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArtifactsLockOptions {
+    // How long to wait for the lock; None waits indefinitely and
+    // Some(Duration::ZERO) fails at once when the lock is held.
+    pub timeout: Option<Duration>,
+    pub poll_interval: Duration,
+    // Loads that observe a generation change are retried up to this many
+    // attempts in total.
+    pub max_load_attempts: usize,
+    // Proceed without a lock when the lock file cannot be opened for a load
+    // (e.g. a read-only directory) or the filesystem does not support
+    // locking. Otherwise those cases fail with ArtifactsLockUnavailable.
+    pub allow_unlocked: bool,
+}
+
+impl Default for ArtifactsLockOptions {
+    fn default() -> Self {
+        ArtifactsLockOptions {
+            timeout: Some(Duration::from_secs(30)),
+            poll_interval: Duration::from_millis(10),
+            max_load_attempts: 3,
+            allow_unlocked: false,
+        }
+    }
+}
+
+// The artifacts lock could not be taken for a reason other than another
+// holder. Callers can downcast to it and retry with
+// ArtifactsLockOptions::allow_unlocked.
+#[derive(Debug)]
+pub struct ArtifactsLockUnavailable {
+    pub path: std::path::PathBuf,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ArtifactsLockUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot lock {} ({}); set allow_unlocked to proceed without a lock",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl Error for ArtifactsLockUnavailable {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+// Held for the duration of a save or load; dropping the file releases the
+// lock. None when the lock is unavailable and the options allow going on
+// unlocked.
+fn acquire_lock(dir: &Path, mode: LockMode, options: &ArtifactsLockOptions) -> Result<Option<fs::File>, Box<dyn Error>> {
+    let path = dir.join(LOCK_NAME);
+    let unavailable = |reason: String| -> Result<Option<fs::File>, Box<dyn Error>> {
+        match options.allow_unlocked {
+            true => Ok(None),
+            false => Err(Box::new(ArtifactsLockUnavailable {
+                path: path.clone(),
+                reason,
+            })),
+        }
+    };
+    let file = match fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path) {
+        Ok(file) => file,
+        // Read-only directories can still be loaded from, just unlocked.
+        Err(e) if mode == LockMode::Shared => return unavailable(e.to_string()),
+        Err(e) => {
+            return Err(Box::new(ScannError {
+                message: format!("Failed to open lock file {}: {}", path.display(), e),
+            }))
+        }
+    };
+    let start = Instant::now();
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match attempt {
+            Ok(()) => return Ok(Some(file)),
+            Err(fs::TryLockError::WouldBlock) => {
+                if options.timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                    return Err(util::failed_precondition_error(&format!(
+                        "Timed out after {:?} waiting for {}",
+                        start.elapsed(),
+                        path.display()
+                    )));
+                }
+                std::thread::sleep(options.poll_interval);
+            }
+            Err(fs::TryLockError::Error(e)) => return unavailable(format!("locking unsupported: {}", e)),
+        }
+    }
+}
+
+// Generation and per-file fnv1a64 checksums of an artifacts directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArtifactsManifest {
+    pub generation: u64,
+    pub files: Vec<(String, u64)>,
+}
+
+impl ArtifactsManifest {
+    fn to_text(&self) -> String {
+        let mut text = format!("generation: {}\n", self.generation);
+        for (name, checksum) in &self.files {
+            text.push_str(&format!("file: {} {:016x}\n", name, checksum));
+        }
+        text
+    }
+
+    pub(crate) fn from_text(text: &str, origin: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = |line: &str| util::invalid_argument_error(&format!("{} has an invalid line '{}'", origin, line));
+        let mut manifest = ArtifactsManifest::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(invalid(line));
+            };
+            match key.trim() {
+                "generation" => manifest.generation = value.trim().parse().map_err(|_| invalid(line))?,
+                "file" => {
+                    let (name, checksum) = value.trim().rsplit_once(' ').ok_or_else(|| invalid(line))?;
+                    let checksum = u64::from_str_radix(checksum, 16).map_err(|_| invalid(line))?;
+                    manifest.files.push((name.to_string(), checksum));
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(manifest)
+    }
+
+    // Checksums the files as they are now.
+    fn capture(dir: &Path, generation: u64) -> Result<Self, Box<dyn Error>> {
+        let mut files = Vec::new();
+        for name in MANIFEST_FILES {
+            let path = dir.join(name);
+            if path.exists() {
+                files.push((name.to_string(), checksum_file(&path)?));
+            }
+        }
+        Ok(ArtifactsManifest { generation, files })
+    }
+
+    fn matches(&self, dir: &Path) -> Result<bool, Box<dyn Error>> {
+        for (name, checksum) in &self.files {
+            let path = dir.join(name);
+            if !path.exists() || checksum_file(&path)? != *checksum {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn checksum_file(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| ScannError {
+        message: format!("Failed to read {}: {}", path.display(), e),
+    })?;
+    Ok(blob::fnv1a64(&bytes))
+}
+
+// None for directories written before manifests existed.
+pub fn read_manifest<P: AsRef<Path>>(dir: P) -> Result<Option<ArtifactsManifest>, Box<dyn Error>> {
+    let path = dir.as_ref().join(MANIFEST_NAME);
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(ArtifactsManifest::from_text(&text, &path.display().to_string())?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(ScannError {
+            message: format!("Failed to read {}: {}", path.display(), e),
+        })),
+    }
+}
+
+// Records the next generation once all files are in place. Written to a
+// temporary name and renamed, so readers never see a partial manifest.
+fn commit_manifest(dir: &Path) -> Result<u64, Box<dyn Error>> {
+    let generation = read_manifest(dir)?.map_or(0, |m| m.generation) + 1;
+    let manifest = ArtifactsManifest::capture(dir, generation)?;
+    let temporary = format!("{}.tmp", MANIFEST_NAME);
+    write_text(dir, &temporary, &manifest.to_text())?;
+    fs::rename(dir.join(&temporary), dir.join(MANIFEST_NAME)).map_err(|e| ScannError {
+        message: format!("Failed to install {}: {}", dir.join(MANIFEST_NAME).display(), e),
+    })?;
+    Ok(generation)
+}
+
+// Removes a file an earlier save left behind that no longer matches.
+fn remove_stale(dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    match fs::remove_file(dir.join(name)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Box::new(ScannError {
+            message: format!("Failed to remove stale {}/{}: {}", dir.display(), name, e),
+        })),
+        _ => Ok(()),
+    }
+}
+
+fn create_dir(dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir).map_err(|e| {
+        Box::new(ScannError {
+            message: format!("Failed to create artifacts directory {}: {}", dir.display(), e),
+        }) as Box<dyn Error>
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArtifactsConfig {
+    pub distance_measure: String,
+    pub normalization: util::Normalization,
+    pub dimensionality: usize,
+}
+
+// Element type of the stored dataset.npy. Loaders always return f32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DatasetDtype {
+    F32,
+    #[cfg(feature = "f16")]
+    F16,
+}
+
+impl ArtifactsConfig {
+    fn to_text(&self) -> String {
+        self.to_text_with_dtype(DatasetDtype::F32)
+    }
+
+    fn to_text_with_dtype(&self, dtype: DatasetDtype) -> String {
+        let normalization = match self.normalization {
+            util::Normalization::None => "none",
+            util::Normalization::UnitL2 => "unit_l2",
+        };
+        let dtype = match dtype {
+            DatasetDtype::F32 => "f32\n",
+            // Older readers reject the dtype; newer ones built without the
+            // feature name it.
+            #[cfg(feature = "f16")]
+            DatasetDtype::F16 => "f16\nrequires_features: f16\n",
+        };
+        format!(
+            "distance_measure: {}\nnormalization: {}\ndimensionality: {}\ndtype: {}{}",
+            self.distance_measure,
+            normalization,
+            self.dimensionality,
+            dtype,
+            build_info::build_info().to_text()
+        )
+    }
+
+    pub(crate) fn from_text(text: &str, origin: &str) -> Result<Self, Box<dyn Error>> {
+        let fields: HashMap<&str, &str> = text
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        // Written by future versions that need features to read the files.
+        if let Some(required) = fields.get("requires_features") {
+            build_info::check_required_features(&build_info::parse_feature_list(required), origin)?;
+        }
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| util::invalid_argument_error(&format!("{} is missing field '{}'", origin, name)))
+        };
+        let normalization = match field("normalization")? {
+            "none" => util::Normalization::None,
+            "unit_l2" => util::Normalization::UnitL2,
+            other => {
+                return Err(util::invalid_argument_error(&format!(
+                    "{} has unknown normalization '{}'",
+                    origin, other
+                )))
+            }
+        };
+        match field("dtype")? {
+            "f32" => {}
+            "f16" => build_info::check_required_features(&["f16"], origin)?,
+            other => {
+                return Err(util::invalid_argument_error(&format!(
+                    "{} has dtype '{}'; only f32 and f16 artifacts are supported",
+                    origin, other
+                )))
+            }
+        }
+        let dimensionality = field("dimensionality")?.parse().map_err(|_| {
+            util::invalid_argument_error(&format!("{} has an invalid dimensionality", origin))
+        })?;
+        Ok(ArtifactsConfig {
+            distance_measure: field("distance_measure")?.to_string(),
+            normalization,
+            dimensionality,
+        })
+    }
+}
+
+pub struct Artifacts {
+    pub config: ArtifactsConfig,
+    pub dataset: util::DenseDataset<f32>,
+    pub docids: Vec<usize>,
+    pub tree: Option<tree::KMeansTree>,
+    pub attributes: Option<attribute_store::AttributeStore>,
+}
+
+fn write_text(dir: &Path, name: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let path = dir.join(name);
+    fs::write(&path, text).map_err(|e| {
+        Box::new(ScannError {
+            message: format!("Failed to write {}: {}", path.display(), e),
+        }) as Box<dyn Error>
+    })
+}
+
+pub fn save_artifacts<P: AsRef<Path>>(
+    dir: P,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+) -> Result<(), Box<dyn Error>> {
+    save_artifacts_with(dir, config, dataset, docids, &ArtifactsLockOptions::default())
+}
+
+pub fn save_artifacts_with<P: AsRef<Path>>(
+    dir: P,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+    lock_options: &ArtifactsLockOptions,
+) -> Result<(), Box<dyn Error>> {
+    let dir = dir.as_ref();
+    create_dir(dir)?;
+    let _lock = acquire_lock(dir, LockMode::Exclusive, lock_options)?;
+    write_artifact_files(dir, config, dataset, docids, None, DatasetDtype::F32)?;
+    commit_manifest(dir)?;
+    Ok(())
+}
+
+// Like save_artifacts, storing the dataset as half-precision floats. The
+// artifacts then need the `f16` feature to load, and load with the values
+// rounded to half precision.
+#[cfg(feature = "f16")]
+pub fn save_artifacts_f16<P: AsRef<Path>>(
+    dir: P,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+) -> Result<(), Box<dyn Error>> {
+    let dir = dir.as_ref();
+    create_dir(dir)?;
+    let _lock = acquire_lock(dir, LockMode::Exclusive, &ArtifactsLockOptions::default())?;
+    write_artifact_files(dir, config, dataset, docids, None, DatasetDtype::F16)?;
+    commit_manifest(dir)?;
+    Ok(())
+}
+
+// Like save_artifacts, also writing `attributes`, which must have one row
+// per dataset row, to attributes.blob.
+pub fn save_artifacts_with_attributes<P: AsRef<Path>>(
+    dir: P,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+    attributes: &attribute_store::AttributeStore,
+) -> Result<(), Box<dyn Error>> {
+    let dir = dir.as_ref();
+    create_dir(dir)?;
+    let _lock = acquire_lock(dir, LockMode::Exclusive, &ArtifactsLockOptions::default())?;
+    write_artifact_files(dir, config, dataset, docids, Some(attributes), DatasetDtype::F32)?;
+    commit_manifest(dir)?;
+    Ok(())
+}
+
+fn write_artifact_files(
+    dir: &Path,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+    attributes: Option<&attribute_store::AttributeStore>,
+    dtype: DatasetDtype,
+) -> Result<(), Box<dyn Error>> {
+    if dataset.dimensionality() != config.dimensionality || docids.len() != dataset.size() {
+        return Err(util::invalid_argument_error(&format!(
+            "Artifacts config declares dimensionality {}, dataset has {} rows of dimensionality {} and {} docids",
+            config.dimensionality,
+            dataset.size(),
+            dataset.dimensionality(),
+            docids.len()
+        )));
+    }
+    if let Some(attributes) = attributes {
+        if attributes.num_rows() != dataset.size() {
+            return Err(util::invalid_argument_error(&format!(
+                "Attribute store has {} rows for {} dataset rows",
+                attributes.num_rows(),
+                dataset.size()
+            )));
+        }
+    }
+    match dtype {
+        DatasetDtype::F32 => {
+            npy::save_dataset(dir.join("dataset.npy"), dataset, None)?;
+        }
+        #[cfg(feature = "f16")]
+        DatasetDtype::F16 => npy::save_dataset_f16(dir.join("dataset.npy"), dataset)?,
+    }
+    match attributes {
+        Some(attributes) => blob::write_blob(
+            dir.join(ATTRIBUTES_NAME),
+            &[(blob::SectionKind::Attributes, attributes.encode())],
+        )?,
+        // Attributes of an earlier save would no longer match the rows.
+        None => remove_stale(dir, ATTRIBUTES_NAME)?,
+    }
+    let docids: Vec<String> = docids.iter().map(|d| d.to_string()).collect();
+    write_text(dir, DOCIDS_NAME, &(docids.join("\n") + "\n"))?;
+    write_text(dir, CONFIG_NAME, &config.to_text_with_dtype(dtype))
+}
+
+// Like save_artifacts, also writing the retriever's partitioning (when it
+// has one) to index.blob and, with the serde feature, `report` to
+// build_report.json, all under one manifest generation.
+pub fn save_built_artifacts<P: AsRef<Path>>(
+    dir: P,
+    config: &ArtifactsConfig,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+    tree: Option<&tree::KMeansTree>,
+    report: &build::BuildReport,
+) -> Result<(), Box<dyn Error>> {
+    let dir = dir.as_ref();
+    create_dir(dir)?;
+    let _lock = acquire_lock(dir, LockMode::Exclusive, &ArtifactsLockOptions::default())?;
+    write_artifact_files(dir, config, dataset, docids, None, DatasetDtype::F32)?;
+    match tree {
+        Some(tree) => write_index_blob(dir, dataset, docids, tree)?,
+        None => remove_stale(dir, BLOB_NAME)?,
+    }
+    #[cfg(feature = "serde")]
+    write_text(dir, build::BUILD_REPORT_NAME, &report.to_json())?;
+    // A report from an earlier save would describe a different build.
+    #[cfg(not(feature = "serde"))]
+    {
+        let _ = report;
+        remove_stale(dir, build::BUILD_REPORT_NAME)?;
+    }
+    commit_manifest(dir)?;
+    Ok(())
+}
+
+fn write_index_blob(
+    dir: &Path,
+    dataset: &util::DenseDataset<f32>,
+    docids: &[usize],
+    tree: &tree::KMeansTree,
+) -> Result<(), Box<dyn Error>> {
+    let index_width = util::IndexWidth::for_size(docids.len().max(docids.iter().max().map_or(0, |&d| d + 1)));
+    blob::write_blob(
+        dir.join(BLOB_NAME),
+        &[
+            (blob::SectionKind::Dataset, blob::encode_dataset(dataset)),
+            (blob::SectionKind::IndexWidth, (index_width.bytes() as u32).to_le_bytes().to_vec()),
+            (blob::SectionKind::Docids, blob::encode_indices(docids, index_width)?),
+            (blob::SectionKind::Tree, blob::encode_tree(tree)),
+        ],
+    )
+}
+
+// Streaming variant of save_artifacts: rows are written to dataset.npy as
+// they arrive, so the dataset is never held in memory. The first error from
+// `rows` is returned before the manifest is committed.
+pub fn save_artifacts_streaming<P, I>(dir: P, config: &ArtifactsConfig, rows: I) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = Result<(usize, Vec<f32>), Box<dyn Error>>>,
+{
+    let dir = dir.as_ref();
+    create_dir(dir)?;
+    let _lock = acquire_lock(dir, LockMode::Exclusive, &ArtifactsLockOptions::default())?;
+    let mut writer = npy::NpyStreamWriter::create(dir.join("dataset.npy"), config.dimensionality)?;
+    let mut docids = String::new();
+    for row in rows {
+        let (docid, values) = row?;
+        writer.append_row(&values)?;
+        docids.push_str(&format!("{}\n", docid));
+    }
+    let num_rows = writer.close()?;
+    write_text(dir, DOCIDS_NAME, &docids)?;
+    write_text(dir, CONFIG_NAME, &config.to_text())?;
+    commit_manifest(dir)?;
+    Ok(num_rows)
+}
+
+pub fn load_artifacts<P: AsRef<Path>>(dir: P) -> Result<Artifacts, Box<dyn Error>> {
+    load_artifacts_with(dir, &ArtifactsLockOptions::default())
+}
+
+// Loads under a shared lock. With a manifest, the load only succeeds if
+// the manifest is unchanged afterwards and every file still matches its
+// checksum, so a result never mixes files of two generations.
+pub fn load_artifacts_with<P: AsRef<Path>>(
+    dir: P,
+    lock_options: &ArtifactsLockOptions,
+) -> Result<Artifacts, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let attempts = lock_options.max_load_attempts.max(1);
+    for _ in 0..attempts {
+        let _lock = acquire_lock(dir, LockMode::Shared, lock_options)?;
+        let before = read_manifest(dir)?;
+        let loaded = load_artifacts_unverified(dir);
+        let Some(before) = before else {
+            return loaded;
+        };
+        let unchanged = read_manifest(dir)?.as_ref() == Some(&before) && before.matches(dir)?;
+        match (loaded, unchanged) {
+            (Ok(artifacts), true) => return Ok(artifacts),
+            (Err(e), true) => return Err(e),
+            (_, false) => continue,
+        }
+    }
+    Err(util::failed_precondition_error(&format!(
+        "{} changed during each of {} load attempts",
+        dir.display(),
+        attempts
+    )))
+}
+
+fn load_artifacts_unverified(dir: &Path) -> Result<Artifacts, Box<dyn Error>> {
+    let source = artifact_source::FsArtifactSource::new(dir)?;
+    let origin = source.root().display().to_string();
+    let read_text = |name: &str| -> Result<String, Box<dyn Error>> {
+        String::from_utf8(artifact_source::read_artifact(&source, name)?)
+            .map_err(|_| util::invalid_argument_error(&format!("{}/{} is not UTF-8", origin, name)))
+    };
+    let config = ArtifactsConfig::from_text(&read_text(CONFIG_NAME)?, &format!("{}/{}", origin, CONFIG_NAME))?;
+    let dataset_name = if source.exists("dataset.npy") { "dataset.npy" } else { "dataset.npy.zst" };
+    if dataset_name == "dataset.npy.zst" {
+        build_info::check_required_features(&["zstd"], &format!("{}/{}", origin, dataset_name))?;
+    }
+    let dataset = npy::read_dataset(&source, dataset_name)?;
+    if dataset.dimensionality() != config.dimensionality {
+        return Err(util::invalid_argument_error(&format!(
+            "{}: dataset dimensionality {} does not match config dimensionality {}",
+            origin,
+            dataset.dimensionality(),
+            config.dimensionality
+        )));
+    }
+    let docids = if source.exists(DOCIDS_NAME) {
+        read_text(DOCIDS_NAME)?
+            .split_whitespace()
+            .map(|d| d.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| util::invalid_argument_error(&format!("{}/{} has a non-integer docid", origin, DOCIDS_NAME)))?
+    } else {
+        (0..dataset.size()).collect()
+    };
+    if docids.len() != dataset.size() {
+        return Err(util::invalid_argument_error(&format!(
+            "{}: {} docids for {} dataset rows",
+            origin,
+            docids.len(),
+            dataset.size()
+        )));
+    }
+    if let Some(docid) = util::first_duplicate(&docids) {
+        return Err(util::invalid_argument_error(&format!(
+            "{}/{} has duplicate docid {}",
+            origin, DOCIDS_NAME, docid
+        )));
+    }
+    let tree = if source.exists(BLOB_NAME) {
+        let bytes = artifact_source::read_artifact(&source, BLOB_NAME)?;
+        let sections = blob::decode_blob(&bytes)?;
+        match sections.get(&blob::SectionKind::Tree) {
+            Some(tree_bytes) => {
+                let mut tree = blob::decode_tree(tree_bytes, dataset.size(), dataset.dimensionality())?;
+                tree.compute_leaf_bounds(&dataset);
+                Some(tree)
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+    let attributes = if source.exists(ATTRIBUTES_NAME) {
+        let bytes = artifact_source::read_artifact(&source, ATTRIBUTES_NAME)?;
+        let sections = blob::decode_blob(&bytes)?;
+        let Some(section) = sections.get(&blob::SectionKind::Attributes) else {
+            return Err(util::invalid_argument_error(&format!(
+                "{}/{} has no attributes section",
+                origin, ATTRIBUTES_NAME
+            )));
+        };
+        let attributes = attribute_store::AttributeStore::decode(section)?;
+        if attributes.num_rows() != dataset.size() {
+            return Err(util::invalid_argument_error(&format!(
+                "{}/{} has {} rows for {} dataset rows",
+                origin,
+                ATTRIBUTES_NAME,
+                attributes.num_rows(),
+                dataset.size()
+            )));
+        }
+        Some(attributes)
+    } else {
+        None
+    };
+    Ok(Artifacts {
+        config,
+        dataset,
+        docids,
+        tree,
+        attributes,
+    })
+}
+
+#[derive(Clone)]
+pub struct MergeConfig {
+    // Added to every docid of the corresponding source; empty keeps docids
+    // as-is, in which case collisions are an error.
+    pub docid_offsets: Vec<usize>,
+    // Leaves of the merged partitioning; None writes no partitioning. Also
+    // skipped when the merged corpus is too small to partition.
+    pub num_leaves: Option<usize>,
+    // Seed k-means with the union of the sources' centers when available.
+    pub warm_start: bool,
+    pub training_options: tree::KMeansTreeTrainingOptions,
+    // Re-encode int8 codes per leaf into leaf_codes.bin.
+    pub int8_codes: Option<quantization::Int8QuantizationConfig>,
+}
+
+impl MergeConfig {
+    pub fn new() -> Self {
+        MergeConfig {
+            docid_offsets: Vec::new(),
+            num_leaves: None,
+            warm_start: true,
+            training_options: tree::KMeansTreeTrainingOptions::new(),
+            int8_codes: None,
+        }
+    }
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        MergeConfig::new()
+    }
+}
+
+fn check_compatible(first: &ArtifactsConfig, other: &ArtifactsConfig, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mismatch = |field: &str, a: String, b: String| {
+        util::invalid_argument_error(&format!(
+            "Cannot merge {}: {} is {} but the first source has {}",
+            dir.display(),
+            field,
+            b,
+            a
+        ))
+    };
+    if first.distance_measure != other.distance_measure {
+        return Err(mismatch("distance_measure", first.distance_measure.clone(), other.distance_measure.clone()));
+    }
+    if first.normalization != other.normalization {
+        return Err(mismatch(
+            "normalization",
+            format!("{:?}", first.normalization),
+            format!("{:?}", other.normalization),
+        ));
+    }
+    if first.dimensionality != other.dimensionality {
+        return Err(mismatch(
+            "dimensionality",
+            first.dimensionality.to_string(),
+            other.dimensionality.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Unions several artifacts directories into a fresh one at `output_dir`,
+// recording per-source row ranges and docid offsets in provenance.txt.
+pub fn merge_artifacts<P: AsRef<Path>>(
+    dirs: &[&Path],
+    output_dir: P,
+    config: &MergeConfig,
+) -> Result<ArtifactsConfig, Box<dyn Error>> {
+    let output_dir = output_dir.as_ref();
+    if dirs.is_empty() {
+        return Err(util::invalid_argument_error("merge_artifacts needs at least one source"));
+    }
+    if !config.docid_offsets.is_empty() && config.docid_offsets.len() != dirs.len() {
+        return Err(util::invalid_argument_error(&format!(
+            "Got {} docid offsets for {} sources",
+            config.docid_offsets.len(),
+            dirs.len()
+        )));
+    }
+    if config.int8_codes.is_some() && config.num_leaves.is_none() {
+        return Err(util::invalid_argument_error("int8_codes are stored per leaf and require num_leaves"));
+    }
+
+    let mut merged_config: Option<ArtifactsConfig> = None;
+    let mut rows = Vec::new();
+    let mut docids = Vec::new();
+    let mut seen = HashMap::new();
+    let mut warm_centers = Vec::new();
+    let mut attributes = attribute_store::AttributeStore::new(0);
+    let mut provenance = String::new();
+    for (s, dir) in dirs.iter().enumerate() {
+        let source = load_artifacts(dir)?;
+        match &merged_config {
+            Some(first) => check_compatible(first, &source.config, dir)?,
+            None => merged_config = Some(source.config.clone()),
+        }
+        let offset = config.docid_offsets.get(s).copied().unwrap_or(0);
+        provenance.push_str(&format!(
+            "source: {} rows: {}..{} docid_offset: {}\n",
+            dir.display(),
+            rows.len(),
+            rows.len() + source.dataset.size(),
+            offset
+        ));
+        for &docid in &source.docids {
+            let merged = docid.checked_add(offset).ok_or_else(|| {
+                util::invalid_argument_error(&format!("Docid {} + offset {} overflows", docid, offset))
+            })?;
+            if let Some(previous) = seen.insert(merged, s) {
+                return Err(util::invalid_argument_error(&format!(
+                    "Docid {} from {} collides with source {}; set docid_offsets",
+                    merged,
+                    dir.display(),
+                    dirs[previous].display()
+                )));
+            }
+            docids.push(merged);
+        }
+        if let Some(tree) = &source.tree {
+            warm_centers.extend(tree.centers().data.iter().cloned());
+        }
+        // Sources without attributes contribute missing values.
+        match &source.attributes {
+            Some(source_attributes) => attributes.append(source_attributes)?,
+            None => attributes.append(&attribute_store::AttributeStore::new(source.dataset.size()))?,
+        }
+        rows.extend(source.dataset.data);
+    }
+    let merged_config = merged_config.unwrap();
+    let dataset = util::DenseDataset::new(rows, merged_config.dimensionality);
+    create_dir(output_dir)?;
+    let _lock = acquire_lock(output_dir, LockMode::Exclusive, &ArtifactsLockOptions::default())?;
+    let attributes = (attributes.num_columns() > 0).then_some(&attributes);
+    write_artifact_files(output_dir, &merged_config, &dataset, &docids, attributes, DatasetDtype::F32)?;
+    write_text(output_dir, PROVENANCE_NAME, &provenance)?;
+
+    let num_leaves = config
+        .num_leaves
+        .filter(|&n| tree::partitioning_skip_reason(dataset.size(), n, &config.training_options).is_none());
+    if let Some(num_leaves) = num_leaves {
+        let mut options = config.training_options.clone();
+        if config.warm_start && !warm_centers.is_empty() {
+            options.warm_start_centers = Some(util::DenseDataset::new(warm_centers, merged_config.dimensionality));
+        }
+        let (tree, _) = tree::KMeansTree::train(&dataset, num_leaves, &options)?;
+        write_index_blob(output_dir, &dataset, &docids, &tree)?;
+        if let Some(int8_config) = &config.int8_codes {
+            let quantized = quantization::quantize_int8(&dataset, int8_config)?;
+            leaf_codes::write_leaf_codes(output_dir.join(LEAF_CODES_NAME), &tree, &quantized)?;
+        }
+    }
+    assets::populate_and_save_assets(&artifact_source::FsArtifactSource::new(output_dir)?)?;
+    commit_manifest(output_dir)?;
+    Ok(merged_config)
+}
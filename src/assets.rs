@@ -14,59 +14,95 @@
 
 //! Assets serialization for ScaNN.
 
-use super::{proto, ScannError};
+use super::{artifact_source, proto, util, ScannError};
 use std::error::Error;
-use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::Path;
 
-fn path_exists<P: AsRef<Path>>(path: P) -> bool {
-    path.as_ref().exists()
-}
+const ASSETS_PROTO_NAME: &str = "scann_assets.pb";
+const ASSETS_TEXT_NAME: &str = "scann_assets.pbtxt";
 
 pub fn populate_and_save_assets_proto<P: AsRef<Path>>(
     artifacts_dir: P,
 ) -> Result<proto::ScannAssets, Box<dyn Error>> {
-    let artifacts_dir = artifacts_dir.as_ref();
+    let source = artifact_source::FsArtifactSource::new(artifacts_dir)?;
+    populate_and_save_assets(&source)
+}
+
+// Asset paths in the manifest are names relative to the artifacts root, so
+// a manifest is valid for any source holding the same files.
+pub fn populate_and_save_assets(
+    source: &dyn artifact_source::ArtifactSource,
+) -> Result<proto::ScannAssets, Box<dyn Error>> {
     let mut assets = proto::ScannAssets {
         assets: Vec::new(),
     };
 
-    fn add_if_exists(
-        assets: &mut proto::ScannAssets,
-        artifacts_dir: &Path,
-        filename: &str,
-        asset_type: proto::AssetType,
-    ) {
-        let file_path = artifacts_dir.join(filename);
-        if path_exists(&file_path) {
+    let mut add_if_exists = |filename: &str, asset_type: proto::AssetType| {
+        if source.exists(filename) {
             assets.assets.push(proto::ScannAsset {
-                asset_path: file_path.to_string_lossy().into_owned(),
+                asset_path: filename.to_string(),
                 asset_type,
             });
         }
-    }
+    };
 
-    add_if_exists(&mut assets, artifacts_dir, "ah_codebook.pb", proto::AssetType::AhCenters);
-    add_if_exists(&mut assets, artifacts_dir, "serialized_partitioner.pb", proto::AssetType::Partitioner);
-    add_if_exists(&mut assets, artifacts_dir, "datapoint_to_token.npy", proto::AssetType::TokenizationNpy);
-    add_if_exists(&mut assets, artifacts_dir, "hashed_dataset.npy", proto::AssetType::AhDatasetNpy);
-    add_if_exists(&mut assets, artifacts_dir, "int8_dataset.npy", proto::AssetType::Int8DatasetNpy);
-    add_if_exists(&mut assets, artifacts_dir, "int8_multipliers.npy", proto::AssetType::Int8MultipliersNpy);
-    add_if_exists(&mut assets, artifacts_dir, "dp_norms.npy", proto::AssetType::Int8NormsNpy);
-    add_if_exists(&mut assets, artifacts_dir, "dataset.npy", proto::AssetType::DatasetNpy);
+    add_if_exists("ah_codebook.pb", proto::AssetType::AhCenters);
+    add_if_exists("serialized_partitioner.pb", proto::AssetType::Partitioner);
+    add_if_exists("datapoint_to_token.npy", proto::AssetType::TokenizationNpy);
+    add_if_exists("hashed_dataset.npy", proto::AssetType::AhDatasetNpy);
+    add_if_exists("int8_dataset.npy", proto::AssetType::Int8DatasetNpy);
+    add_if_exists("int8_multipliers.npy", proto::AssetType::Int8MultipliersNpy);
+    add_if_exists("dp_norms.npy", proto::AssetType::Int8NormsNpy);
+    add_if_exists("dataset.npy", proto::AssetType::DatasetNpy);
+    add_if_exists("dataset.npy.zst", proto::AssetType::DatasetNpy);
 
-    let output_path = artifacts_dir.join("scann_assets.pbtxt");
-    let mut file = File::create(&output_path).map_err(|e| {
-        ScannError {
-            message: format!("Failed to create file {}: {}", output_path.display(), e),
-        }
-    })?;
+    let output_name = ASSETS_TEXT_NAME;
+    let mut file = source.create(output_name)?;
     write!(file, "{}", assets).map_err(|e| {
         ScannError {
-            message: format!("Failed to write to file {}: {}", output_path.display(), e),
+            message: format!("Failed to write to file {}: {}", output_name, e),
         }
     })?;
+    #[cfg(feature = "proto-compat")]
+    artifact_source::write_artifact(source, ASSETS_PROTO_NAME, &assets.encode_to_vec())?;
 
     Ok(assets)
-}
\ No newline at end of file
+}
+
+// Reads the text manifest written by populate_and_save_assets.
+pub fn load_assets(source: &dyn artifact_source::ArtifactSource) -> Result<proto::ScannAssets, Box<dyn Error>> {
+    let bytes = artifact_source::read_artifact(source, ASSETS_TEXT_NAME)?;
+    let text = std::str::from_utf8(&bytes)
+        .map_err(|_| util::invalid_argument_error(&format!("{} is not valid UTF-8", ASSETS_TEXT_NAME)))?;
+    proto::ScannAssets::parse_text(text)
+}
+
+// Reads the binary manifest written next to scann_assets.pbtxt by builds
+// with the `proto-compat` feature.
+pub fn load_assets_proto(source: &dyn artifact_source::ArtifactSource) -> Result<proto::ScannAssets, Box<dyn Error>> {
+    decode_assets(&artifact_source::read_artifact(source, ASSETS_PROTO_NAME)?)
+}
+
+#[cfg(feature = "proto-compat")]
+fn decode_assets(bytes: &[u8]) -> Result<proto::ScannAssets, Box<dyn Error>> {
+    proto::ScannAssets::decode(bytes)
+        .map_err(|e| util::invalid_argument_error(&format!("Invalid {}: {}", ASSETS_PROTO_NAME, e)))
+}
+
+#[cfg(not(feature = "proto-compat"))]
+fn decode_assets(_bytes: &[u8]) -> Result<proto::ScannAssets, Box<dyn Error>> {
+    Err(util::failed_precondition_error(&format!(
+        "Reading {} requires building scann with the `proto-compat` feature",
+        ASSETS_PROTO_NAME
+    )))
+}
+
+// Opens an asset listed in a manifest. Manifests may come from untrusted
+// tenants, so the path is checked by the source rather than used verbatim.
+pub fn open_asset<'a>(
+    source: &'a dyn artifact_source::ArtifactSource,
+    asset: &proto::ScannAsset,
+) -> Result<Box<dyn Read + 'a>, Box<dyn Error>> {
+    source.open(&asset.asset_path)
+}
@@ -0,0 +1,167 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Asymmetric hashing (AH): product quantization of projected vectors,
+//! scored against unquantized queries through per-block lookup tables.
+
+use super::projection::{ChunkingProjection, PcaProjection};
+use super::{tree, util};
+use std::error::Error;
+
+// Codes are stored one byte per block.
+pub const MAX_CLUSTERS_PER_BLOCK: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct AsymmetricHasherConfig {
+    pub num_blocks: usize,
+    pub num_clusters_per_block: usize,
+    // Trains a PCA to this many dimensions and chunks its output instead of
+    // the raw input.
+    pub pca_dims: Option<usize>,
+    // With PCA, permutes its output so every block carries similar
+    // eigenvalue mass (see projection::balanced_block_assignment).
+    pub balance_blocks: bool,
+    pub training: tree::KMeansTreeTrainingOptions,
+}
+
+impl AsymmetricHasherConfig {
+    pub fn new(num_blocks: usize, num_clusters_per_block: usize) -> Self {
+        let mut training = tree::KMeansTreeTrainingOptions::new();
+        training.max_iterations = 10;
+        AsymmetricHasherConfig {
+            num_blocks,
+            num_clusters_per_block,
+            pca_dims: None,
+            balance_blocks: true,
+            training,
+        }
+    }
+}
+
+pub struct AsymmetricHasher {
+    projection: ChunkingProjection,
+    // One codebook per block, over that block's projected dimensions.
+    codebooks: Vec<util::DenseDataset<f32>>,
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn nearest_center(codebook: &util::DenseDataset<f32>, values: &[f32]) -> u8 {
+    let mut best = (0, f32::INFINITY);
+    for (c, center) in codebook.data.iter().enumerate() {
+        let distance = squared_l2(center, values);
+        if distance < best.1 {
+            best = (c, distance);
+        }
+    }
+    best.0 as u8
+}
+
+// Trains the chunking projection, then a k-means codebook per block on the
+// projected training data.
+pub fn train_asymmetric_hasher(
+    data: &util::DenseDataset<f32>,
+    config: &AsymmetricHasherConfig,
+) -> Result<AsymmetricHasher, Box<dyn Error>> {
+    if config.num_clusters_per_block == 0 || config.num_clusters_per_block > MAX_CLUSTERS_PER_BLOCK {
+        return Err(util::invalid_argument_error(&format!(
+            "num_clusters_per_block must be in [1, {}], got {}",
+            MAX_CLUSTERS_PER_BLOCK, config.num_clusters_per_block
+        )));
+    }
+    let dim = data.dimensionality();
+    let projection = match config.pca_dims {
+        Some(pca_dims) => {
+            let mut pca = PcaProjection::<f32>::new(dim as i32, pca_dims as i32)?;
+            pca.create(data, true, None)?;
+            ChunkingProjection::with_pca(pca, config.num_blocks, config.balance_blocks)?
+        }
+        None => ChunkingProjection::new(dim, config.num_blocks)?,
+    };
+    let projected = projection.project_dataset(data)?;
+    let codebooks = projection
+        .block_ranges()
+        .into_iter()
+        .map(|range| {
+            let rows = projected.data.iter().map(|row| row[range.clone()].to_vec()).collect();
+            let block = util::DenseDataset::new(rows, range.len());
+            Ok(tree::train_kmeans(&block, config.num_clusters_per_block, &config.training)?.centers)
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    Ok(AsymmetricHasher { projection, codebooks })
+}
+
+impl AsymmetricHasher {
+    pub fn projection(&self) -> &ChunkingProjection {
+        &self.projection
+    }
+
+    pub fn codebooks(&self) -> &[util::DenseDataset<f32>] {
+        &self.codebooks
+    }
+
+    fn encode_projected(&self, projected: &[f32]) -> Vec<u8> {
+        self.projection
+            .block_ranges()
+            .into_iter()
+            .zip(self.codebooks.iter())
+            .map(|(range, codebook)| nearest_center(codebook, &projected[range]))
+            .collect()
+    }
+
+    // Index of the nearest codebook center in every block.
+    pub fn encode(&self, row: &[f32]) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(self.encode_projected(&self.projection.project(row)?))
+    }
+
+    pub fn encode_dataset(&self, data: &util::DenseDataset<f32>) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let projected = self.projection.project_dataset(data)?;
+        Ok(projected.data.iter().map(|row| self.encode_projected(row)).collect())
+    }
+
+    // Squared L2 distance from the projected query to every center of every
+    // block; table[b][c] for block b, center c.
+    pub fn lookup_table(&self, query: &[f32]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let projected = self.projection.project(query)?;
+        Ok(self
+            .projection
+            .block_ranges()
+            .into_iter()
+            .zip(self.codebooks.iter())
+            .map(|(range, codebook)| {
+                let block = &projected[range];
+                codebook.data.iter().map(|center| squared_l2(center, block)).collect()
+            })
+            .collect())
+    }
+
+    // Approximate squared L2 distance between the query of `table` and the
+    // row encoded as `code`.
+    pub fn distance(&self, table: &[Vec<f32>], code: &[u8]) -> f32 {
+        table.iter().zip(code.iter()).map(|(distances, &c)| distances[c as usize]).sum()
+    }
+
+    // The k codes closest to `query` by approximate distance, closest first.
+    pub fn search(&self, codes: &[Vec<u8>], query: &[f32], k: usize) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        let table = self.lookup_table(query)?;
+        let mut scored: Vec<(usize, f32)> =
+            codes.iter().enumerate().map(|(i, code)| (i, self.distance(&table, code))).collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        Ok(scored)
+    }
+}
@@ -0,0 +1,1039 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named per-row attribute columns and filters over them.
+//!
+//! Columns hold i64, f32 or dictionary-encoded string values aligned with
+//! the rows of a dataset, and any cell may be missing. Filters are built
+//! with `col`, e.g. `col("price").lt(100) & col("region").eq("eu")`, or
+//! parsed from the same expression as text, `price < 100 && region == "eu"`,
+//! and evaluate to a bitset of matching rows. A comparison is false on a
+//! missing value, so negating one selects the rows without a value too.
+//!
+//! Section layout (all integers little-endian):
+//!   num_rows u64 | num_columns u64
+//!   num_columns x { name_len u64 | name | type u32 | validity | values }
+//! where validity is ceil(num_rows / 64) u64 words and values are num_rows
+//! i64s, f32s or, for dictionary columns, dict_len u64 | dict_len x
+//! { len u64 | utf-8 } followed by num_rows u32 codes. Missing cells hold 0.
+
+use super::{blob, util};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::ops::{BitAnd, BitOr, Not};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnType {
+    Int64 = 1,
+    Float32 = 2,
+    Dictionary = 3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ColumnData {
+    Int64(Vec<Option<i64>>),
+    Float32(Vec<Option<f32>>),
+    // Each distinct string is stored once; rows hold its index.
+    Dictionary { dictionary: Vec<String>, codes: Vec<Option<u32>> },
+}
+
+impl ColumnData {
+    fn missing(column_type: ColumnType, n: usize) -> Self {
+        match column_type {
+            ColumnType::Int64 => ColumnData::Int64(vec![None; n]),
+            ColumnType::Float32 => ColumnData::Float32(vec![None; n]),
+            ColumnType::Dictionary => ColumnData::Dictionary {
+                dictionary: Vec::new(),
+                codes: vec![None; n],
+            },
+        }
+    }
+
+    fn column_type(&self) -> ColumnType {
+        match self {
+            ColumnData::Int64(_) => ColumnType::Int64,
+            ColumnData::Float32(_) => ColumnType::Float32,
+            ColumnData::Dictionary { .. } => ColumnType::Dictionary,
+        }
+    }
+
+    fn is_present(&self, row: usize) -> bool {
+        match self {
+            ColumnData::Int64(values) => values[row].is_some(),
+            ColumnData::Float32(values) => values[row].is_some(),
+            ColumnData::Dictionary { codes, .. } => codes[row].is_some(),
+        }
+    }
+
+    fn value(&self, row: usize) -> Option<Literal> {
+        match self {
+            ColumnData::Int64(values) => values[row].map(Literal::Int),
+            ColumnData::Float32(values) => values[row].map(Literal::Float),
+            ColumnData::Dictionary { dictionary, codes } => {
+                codes[row].map(|code| Literal::Str(dictionary[code as usize].clone()))
+            }
+        }
+    }
+
+    // Fails when `value` is not of the column's type.
+    fn set_value(&mut self, row: usize, value: Option<&Literal>) -> Result<(), ColumnType> {
+        match (self, value) {
+            (ColumnData::Int64(values), None) => values[row] = None,
+            (ColumnData::Float32(values), None) => values[row] = None,
+            (ColumnData::Dictionary { codes, .. }, None) => codes[row] = None,
+            (ColumnData::Int64(values), Some(Literal::Int(v))) => values[row] = Some(*v),
+            (ColumnData::Float32(values), Some(Literal::Float(v))) => values[row] = Some(*v),
+            (ColumnData::Dictionary { dictionary, codes }, Some(Literal::Str(v))) => {
+                codes[row] = Some(intern(dictionary, v));
+            }
+            (column, Some(_)) => return Err(column.column_type()),
+        }
+        Ok(())
+    }
+
+    fn push_missing(&mut self) {
+        match self {
+            ColumnData::Int64(values) => values.push(None),
+            ColumnData::Float32(values) => values.push(None),
+            ColumnData::Dictionary { codes, .. } => codes.push(None),
+        }
+    }
+
+    fn select_rows(&self, old_to_new: &[Option<usize>], new_len: usize) -> Self {
+        fn select<T: Copy>(values: &[Option<T>], old_to_new: &[Option<usize>], new_len: usize) -> Vec<Option<T>> {
+            let mut out = vec![None; new_len];
+            for (value, new) in values.iter().zip(old_to_new.iter()) {
+                if let Some(new) = new {
+                    out[*new] = *value;
+                }
+            }
+            out
+        }
+        match self {
+            ColumnData::Int64(values) => ColumnData::Int64(select(values, old_to_new, new_len)),
+            ColumnData::Float32(values) => ColumnData::Float32(select(values, old_to_new, new_len)),
+            ColumnData::Dictionary { dictionary, codes } => ColumnData::Dictionary {
+                dictionary: dictionary.clone(),
+                codes: select(codes, old_to_new, new_len),
+            },
+        }
+    }
+
+    // Appends `other`'s rows; both columns must have the same type.
+    fn extend_from(&mut self, other: &ColumnData) {
+        match (self, other) {
+            (ColumnData::Int64(values), ColumnData::Int64(more)) => values.extend_from_slice(more),
+            (ColumnData::Float32(values), ColumnData::Float32(more)) => values.extend_from_slice(more),
+            (
+                ColumnData::Dictionary { dictionary, codes },
+                ColumnData::Dictionary {
+                    dictionary: other_dictionary,
+                    codes: other_codes,
+                },
+            ) => {
+                let recoded: Vec<u32> = other_dictionary.iter().map(|value| intern(dictionary, value)).collect();
+                codes.extend(other_codes.iter().map(|code| code.map(|c| recoded[c as usize])));
+            }
+            _ => unreachable!("extend_from called with mismatched column types"),
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        match self {
+            ColumnData::Int64(values) => values.len() * std::mem::size_of::<Option<i64>>(),
+            ColumnData::Float32(values) => values.len() * std::mem::size_of::<Option<f32>>(),
+            ColumnData::Dictionary { dictionary, codes } => {
+                dictionary.iter().map(|s| std::mem::size_of::<String>() + s.len()).sum::<usize>()
+                    + codes.len() * std::mem::size_of::<Option<u32>>()
+            }
+        }
+    }
+}
+
+fn intern(dictionary: &mut Vec<String>, value: &str) -> u32 {
+    match dictionary.iter().position(|d| d == value) {
+        Some(code) => code as u32,
+        None => {
+            dictionary.push(value.to_string());
+            (dictionary.len() - 1) as u32
+        }
+    }
+}
+
+// Columns aligned with the rows of one dataset. Every column always has
+// exactly num_rows cells.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttributeStore {
+    num_rows: usize,
+    columns: BTreeMap<String, ColumnData>,
+}
+
+impl AttributeStore {
+    pub fn new(num_rows: usize) -> Self {
+        AttributeStore {
+            num_rows,
+            columns: BTreeMap::new(),
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.keys().map(|name| name.as_str())
+    }
+
+    pub fn column_type(&self, name: &str) -> Option<ColumnType> {
+        self.columns.get(name).map(|column| column.column_type())
+    }
+
+    fn check_len(&self, name: &str, len: usize) -> Result<(), Box<dyn Error>> {
+        if len != self.num_rows {
+            return Err(util::invalid_argument_error(&format!(
+                "Attribute column '{}' has {} values for {} rows",
+                name, len, self.num_rows
+            )));
+        }
+        Ok(())
+    }
+
+    // The set_*_column methods replace any column of the same name,
+    // whatever its type.
+    pub fn set_i64_column(&mut self, name: &str, values: Vec<Option<i64>>) -> Result<(), Box<dyn Error>> {
+        self.check_len(name, values.len())?;
+        self.columns.insert(name.to_string(), ColumnData::Int64(values));
+        Ok(())
+    }
+
+    pub fn set_f32_column(&mut self, name: &str, values: Vec<Option<f32>>) -> Result<(), Box<dyn Error>> {
+        self.check_len(name, values.len())?;
+        self.columns.insert(name.to_string(), ColumnData::Float32(values));
+        Ok(())
+    }
+
+    pub fn set_str_column<S: AsRef<str>>(&mut self, name: &str, values: &[Option<S>]) -> Result<(), Box<dyn Error>> {
+        self.check_len(name, values.len())?;
+        let mut dictionary = Vec::new();
+        let mut lookup: HashMap<&str, u32> = HashMap::new();
+        let codes = values
+            .iter()
+            .map(|value| {
+                value.as_ref().map(|v| {
+                    *lookup.entry(v.as_ref()).or_insert_with(|| {
+                        dictionary.push(v.as_ref().to_string());
+                        (dictionary.len() - 1) as u32
+                    })
+                })
+            })
+            .collect();
+        self.columns
+            .insert(name.to_string(), ColumnData::Dictionary { dictionary, codes });
+        Ok(())
+    }
+
+    pub fn remove_column(&mut self, name: &str) -> bool {
+        self.columns.remove(name).is_some()
+    }
+
+    // The typed getters return None for a missing value, an unknown column,
+    // a column of another type, or a row out of range.
+    pub fn get_i64(&self, name: &str, row: usize) -> Option<i64> {
+        match self.columns.get(name)? {
+            ColumnData::Int64(values) => values.get(row).copied().flatten(),
+            _ => None,
+        }
+    }
+
+    // Every value of an i64 column in row order, or None when `name` is
+    // not an i64 column.
+    pub fn i64_values(&self, name: &str) -> Option<&[Option<i64>]> {
+        match self.columns.get(name)? {
+            ColumnData::Int64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn get_f32(&self, name: &str, row: usize) -> Option<f32> {
+        match self.columns.get(name)? {
+            ColumnData::Float32(values) => values.get(row).copied().flatten(),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str, row: usize) -> Option<&str> {
+        match self.columns.get(name)? {
+            ColumnData::Dictionary { dictionary, codes } => {
+                codes.get(row).copied().flatten().map(|code| dictionary[code as usize].as_str())
+            }
+            _ => None,
+        }
+    }
+
+    // Adds a row with every value missing.
+    pub fn push_row(&mut self) {
+        self.num_rows += 1;
+        for column in self.columns.values_mut() {
+            column.push_missing();
+        }
+    }
+
+    // Sets one cell, creating the column with the literal's type when it does
+    // not exist. None clears the cell. Fails on a row out of range or a
+    // value of another type than the existing column.
+    pub fn set_value(&mut self, name: &str, row: usize, value: Option<&Literal>) -> Result<(), Box<dyn Error>> {
+        if row >= self.num_rows {
+            return Err(util::invalid_argument_error(&format!(
+                "Row {} out of range for {} attribute rows",
+                row, self.num_rows
+            )));
+        }
+        let num_rows = self.num_rows;
+        let column = match (self.columns.get_mut(name), value) {
+            (Some(column), _) => column,
+            (None, None) => return Ok(()),
+            (None, Some(value)) => {
+                let column_type = match value {
+                    Literal::Int(_) => ColumnType::Int64,
+                    Literal::Float(_) => ColumnType::Float32,
+                    Literal::Str(_) => ColumnType::Dictionary,
+                };
+                self.columns
+                    .entry(name.to_string())
+                    .or_insert_with(|| ColumnData::missing(column_type, num_rows))
+            }
+        };
+        column.set_value(row, value).map_err(|column_type| {
+            util::invalid_argument_error(&format!(
+                "Attribute column '{}' is {:?}; got {:?}",
+                name, column_type, value
+            ))
+        })
+    }
+
+    // Copies every value of `other`'s row `other_row` into row `row`,
+    // creating columns this store lacks. Columns present only here keep
+    // their value.
+    pub fn copy_row_from(&mut self, row: usize, other: &AttributeStore, other_row: usize) -> Result<(), Box<dyn Error>> {
+        if other_row >= other.num_rows {
+            return Err(util::invalid_argument_error(&format!(
+                "Row {} out of range for {} attribute rows",
+                other_row, other.num_rows
+            )));
+        }
+        for (name, column) in &other.columns {
+            self.set_value(name, row, column.value(other_row).as_ref())?;
+        }
+        Ok(())
+    }
+
+    // Store for a compacted dataset: old row i moves to old_to_new[i], and
+    // rows mapped to None are dropped.
+    pub fn select_rows(&self, old_to_new: &[Option<usize>]) -> AttributeStore {
+        let num_rows = old_to_new.iter().flatten().count();
+        AttributeStore {
+            num_rows,
+            columns: self
+                .columns
+                .iter()
+                .map(|(name, column)| (name.clone(), column.select_rows(old_to_new, num_rows)))
+                .collect(),
+        }
+    }
+
+    // Appends `other`'s rows after this store's. Columns present on one side
+    // only are filled with missing values on the other; a column of the same
+    // name must have the same type in both.
+    pub fn append(&mut self, other: &AttributeStore) -> Result<(), Box<dyn Error>> {
+        for (name, column) in &other.columns {
+            if let Some(existing) = self.columns.get(name) {
+                if existing.column_type() != column.column_type() {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Attribute column '{}' is {:?} in one store and {:?} in the other",
+                        name,
+                        existing.column_type(),
+                        column.column_type()
+                    )));
+                }
+            }
+        }
+        let num_rows = self.num_rows;
+        for (name, column) in &other.columns {
+            self.columns
+                .entry(name.clone())
+                .or_insert_with(|| ColumnData::missing(column.column_type(), num_rows))
+                .extend_from(column);
+        }
+        for (name, column) in self.columns.iter_mut() {
+            if !other.columns.contains_key(name) {
+                column.extend_from(&ColumnData::missing(column.column_type(), other.num_rows));
+            }
+        }
+        self.num_rows += other.num_rows;
+        Ok(())
+    }
+
+    // Approximate resident bytes of the column data.
+    pub fn memory_bytes(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|(name, column)| std::mem::size_of::<String>() + name.len() + column.memory_bytes())
+            .sum()
+    }
+
+    fn column(&self, name: &str) -> Result<&ColumnData, Box<dyn Error>> {
+        self.columns
+            .get(name)
+            .ok_or_else(|| util::invalid_argument_error(&format!("Unknown attribute column '{}'", name)))
+    }
+
+    // Rows matching `filter`. Fails on unknown columns and on comparisons
+    // between a string and a numeric value.
+    pub fn evaluate(&self, filter: &Filter) -> Result<RowBitset, Box<dyn Error>> {
+        match filter {
+            Filter::Compare { column, op, value } => self.compare(column, *op, value),
+            Filter::Present(column) => {
+                let data = self.column(column)?;
+                Ok(RowBitset::from_fn(self.num_rows, |i| data.is_present(i)))
+            }
+            Filter::And(a, b) => {
+                let mut rows = self.evaluate(a)?;
+                rows.intersect_with(&self.evaluate(b)?);
+                Ok(rows)
+            }
+            Filter::Or(a, b) => {
+                let mut rows = self.evaluate(a)?;
+                rows.union_with(&self.evaluate(b)?);
+                Ok(rows)
+            }
+            Filter::Not(inner) => {
+                let mut rows = self.evaluate(inner)?;
+                rows.invert();
+                Ok(rows)
+            }
+        }
+    }
+
+    fn compare(&self, name: &str, op: CompareOp, value: &Literal) -> Result<RowBitset, Box<dyn Error>> {
+        let n = self.num_rows;
+        match (self.column(name)?, value) {
+            (ColumnData::Int64(values), Literal::Int(v)) => {
+                Ok(RowBitset::from_fn(n, |i| values[i].is_some_and(|x| op.holds(Some(x.cmp(v))))))
+            }
+            (ColumnData::Int64(values), Literal::Float(v)) => Ok(RowBitset::from_fn(n, |i| {
+                values[i].is_some_and(|x| op.holds((x as f64).partial_cmp(&(*v as f64))))
+            })),
+            (ColumnData::Float32(values), Literal::Int(v)) => Ok(RowBitset::from_fn(n, |i| {
+                values[i].is_some_and(|x| op.holds((x as f64).partial_cmp(&(*v as f64))))
+            })),
+            (ColumnData::Float32(values), Literal::Float(v)) => {
+                Ok(RowBitset::from_fn(n, |i| values[i].is_some_and(|x| op.holds(x.partial_cmp(v)))))
+            }
+            (ColumnData::Dictionary { dictionary, codes }, Literal::Str(s)) => {
+                if !matches!(op, CompareOp::Eq | CompareOp::Ne) {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Only == and != apply to string column '{}'",
+                        name
+                    )));
+                }
+                let target = dictionary.iter().position(|d| d == s).map(|code| code as u32);
+                let equal = op == CompareOp::Eq;
+                Ok(RowBitset::from_fn(n, |i| codes[i].is_some_and(|code| (Some(code) == target) == equal)))
+            }
+            (column, value) => Err(util::invalid_argument_error(&format!(
+                "Cannot compare {:?} column '{}' with {:?}",
+                column.column_type(),
+                name,
+                value
+            ))),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.num_rows as u64).to_le_bytes());
+        out.extend_from_slice(&(self.columns.len() as u64).to_le_bytes());
+        for (name, column) in &self.columns {
+            out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&(column.column_type() as u32).to_le_bytes());
+            for word in RowBitset::from_fn(self.num_rows, |i| column.is_present(i)).words {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            match column {
+                ColumnData::Int64(values) => {
+                    for v in values {
+                        out.extend_from_slice(&v.unwrap_or(0).to_le_bytes());
+                    }
+                }
+                ColumnData::Float32(values) => {
+                    for v in values {
+                        out.extend_from_slice(&v.unwrap_or(0.0).to_le_bytes());
+                    }
+                }
+                ColumnData::Dictionary { dictionary, codes } => {
+                    out.extend_from_slice(&(dictionary.len() as u64).to_le_bytes());
+                    for value in dictionary {
+                        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                        out.extend_from_slice(value.as_bytes());
+                    }
+                    for code in codes {
+                        out.extend_from_slice(&code.unwrap_or(0).to_le_bytes());
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<AttributeStore, Box<dyn Error>> {
+        let kind = blob::SectionKind::Attributes;
+        let mut reader = blob::SectionReader::new(kind, bytes);
+        let num_rows = reader.u64()? as usize;
+        let num_columns = reader.len(8)?;
+        let mut store = AttributeStore::new(num_rows);
+        let text = |bytes: &[u8]| {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|_| util::invalid_argument_error("Blob attribute section holds a non-UTF-8 string"))
+        };
+        for _ in 0..num_columns {
+            let name_len = reader.len(1)?;
+            let name = text(reader.take(name_len)?)?;
+            let raw_type = reader.u32()?;
+            reader.ensure(num_rows.div_ceil(64), 8)?;
+            let mut validity = RowBitset::new(num_rows);
+            for word in validity.words.iter_mut() {
+                *word = reader.u64()?;
+            }
+            let column = match raw_type {
+                1 => {
+                    reader.ensure(num_rows, 8)?;
+                    let mut values = Vec::with_capacity(num_rows);
+                    for i in 0..num_rows {
+                        let v = reader.u64()? as i64;
+                        values.push(validity.contains(i).then_some(v));
+                    }
+                    ColumnData::Int64(values)
+                }
+                2 => {
+                    reader.ensure(num_rows, 4)?;
+                    let mut values = Vec::with_capacity(num_rows);
+                    for i in 0..num_rows {
+                        let v = reader.f32()?;
+                        values.push(validity.contains(i).then_some(v));
+                    }
+                    ColumnData::Float32(values)
+                }
+                3 => {
+                    let dict_len = reader.len(8)?;
+                    let mut dictionary = Vec::with_capacity(dict_len);
+                    for _ in 0..dict_len {
+                        let len = reader.len(1)?;
+                        dictionary.push(text(reader.take(len)?)?);
+                    }
+                    reader.ensure(num_rows, 4)?;
+                    let mut codes = Vec::with_capacity(num_rows);
+                    for i in 0..num_rows {
+                        let code = reader.u32()?;
+                        if !validity.contains(i) {
+                            codes.push(None);
+                            continue;
+                        }
+                        if code as usize >= dictionary.len() {
+                            return Err(util::invalid_argument_error(&format!(
+                                "Blob attribute column '{}' has code {} for a dictionary of {} values",
+                                name,
+                                code,
+                                dictionary.len()
+                            )));
+                        }
+                        codes.push(Some(code));
+                    }
+                    ColumnData::Dictionary { dictionary, codes }
+                }
+                other => {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Blob attribute column '{}' has unknown type {}",
+                        name, other
+                    )))
+                }
+            };
+            store.columns.insert(name, column);
+        }
+        Ok(store)
+    }
+}
+
+// One bit per row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowBitset {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl RowBitset {
+    // All rows cleared.
+    pub fn new(len: usize) -> Self {
+        RowBitset {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    fn from_fn(len: usize, f: impl Fn(usize) -> bool) -> Self {
+        let mut rows = RowBitset::new(len);
+        for i in (0..len).filter(|&i| f(i)) {
+            rows.words[i / 64] |= 1 << (i % 64);
+        }
+        rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // False for rows past the end.
+    pub fn contains(&self, row: usize) -> bool {
+        row < self.len && self.words[row / 64] & (1 << (row % 64)) != 0
+    }
+
+    pub fn insert(&mut self, row: usize) {
+        assert!(row < self.len, "row {} out of range for a bitset of {} rows", row, self.len);
+        self.words[row / 64] |= 1 << (row % 64);
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&i| self.contains(i))
+    }
+
+    pub fn intersect_with(&mut self, other: &RowBitset) {
+        for (i, word) in self.words.iter_mut().enumerate() {
+            *word &= other.words.get(i).copied().unwrap_or(0);
+        }
+    }
+
+    pub fn union_with(&mut self, other: &RowBitset) {
+        for (word, &o) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= o;
+        }
+    }
+
+    pub fn invert(&mut self) {
+        for word in self.words.iter_mut() {
+            *word = !*word;
+        }
+        if !self.len.is_multiple_of(64) {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1u64 << (self.len % 64)) - 1;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Literal {
+    Int(i64),
+    Float(f32),
+    Str(String),
+}
+
+impl From<i64> for Literal {
+    fn from(v: i64) -> Self {
+        Literal::Int(v)
+    }
+}
+
+impl From<i32> for Literal {
+    fn from(v: i32) -> Self {
+        Literal::Int(v as i64)
+    }
+}
+
+impl From<f32> for Literal {
+    fn from(v: f32) -> Self {
+        Literal::Float(v)
+    }
+}
+
+impl From<&str> for Literal {
+    fn from(v: &str) -> Self {
+        Literal::Str(v.to_string())
+    }
+}
+
+impl From<String> for Literal {
+    fn from(v: String) -> Self {
+        Literal::Str(v)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CompareOp {
+    // None (a NaN operand) satisfies no comparison.
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self {
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Filter {
+    Compare {
+        column: String,
+        op: CompareOp,
+        value: Literal,
+    },
+    // Rows with a value in the column.
+    Present(String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+// Floats hash by bit pattern so filters can be part of cache keys.
+impl Hash for Filter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Filter::Compare { column, op, value } => {
+                column.hash(state);
+                op.hash(state);
+                std::mem::discriminant(value).hash(state);
+                match value {
+                    Literal::Int(v) => v.hash(state),
+                    Literal::Float(v) => v.to_bits().hash(state),
+                    Literal::Str(v) => v.hash(state),
+                }
+            }
+            Filter::Present(column) => column.hash(state),
+            Filter::And(a, b) | Filter::Or(a, b) => {
+                a.hash(state);
+                b.hash(state);
+            }
+            Filter::Not(inner) => inner.hash(state),
+        }
+    }
+}
+
+impl BitAnd for Filter {
+    type Output = Filter;
+
+    fn bitand(self, rhs: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl BitOr for Filter {
+    type Output = Filter;
+
+    fn bitor(self, rhs: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+pub struct ColumnRef {
+    name: String,
+}
+
+pub fn col(name: &str) -> ColumnRef {
+    ColumnRef { name: name.to_string() }
+}
+
+impl ColumnRef {
+    pub fn compare(self, op: CompareOp, value: impl Into<Literal>) -> Filter {
+        Filter::Compare {
+            column: self.name,
+            op,
+            value: value.into(),
+        }
+    }
+
+    pub fn lt(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Lt, value)
+    }
+
+    pub fn le(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Le, value)
+    }
+
+    pub fn gt(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Gt, value)
+    }
+
+    pub fn ge(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Ge, value)
+    }
+
+    pub fn eq(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Eq, value)
+    }
+
+    pub fn ne(self, value: impl Into<Literal>) -> Filter {
+        self.compare(CompareOp::Ne, value)
+    }
+
+    pub fn is_present(self) -> Filter {
+        Filter::Present(self.name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Literal),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn parse_error(text: &str, message: &str) -> Box<dyn Error> {
+    util::invalid_argument_error(&format!("Bad filter expression '{}': {}", text, message))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let (token, width) = match (c, next) {
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(CompareOp::Eq), 2),
+            ('!', Some('=')) => (Token::Op(CompareOp::Ne), 2),
+            ('<', Some('=')) => (Token::Op(CompareOp::Le), 2),
+            ('>', Some('=')) => (Token::Op(CompareOp::Ge), 2),
+            ('<', _) => (Token::Op(CompareOp::Lt), 1),
+            ('>', _) => (Token::Op(CompareOp::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('"' | '\'', _) => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(parse_error(text, "unterminated string")),
+                        Some(&q) if q == c => break,
+                        Some('\\') if j + 1 < chars.len() => {
+                            value.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&other) => {
+                            value.push(other);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Literal(Literal::Str(value)), j + 1 - i)
+            }
+            _ if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit() || n == '.')) => {
+                let mut j = i + 1;
+                while j < chars.len()
+                    && (chars[j].is_ascii_alphanumeric()
+                        || chars[j] == '.'
+                        || (matches!(chars[j], '+' | '-') && matches!(chars[j - 1], 'e' | 'E')))
+                {
+                    j += 1;
+                }
+                let number: String = chars[i..j].iter().collect();
+                let literal = match number.parse::<i64>() {
+                    Ok(v) => Literal::Int(v),
+                    Err(_) => Literal::Float(
+                        number
+                            .parse::<f32>()
+                            .map_err(|_| parse_error(text, &format!("'{}' is not a number", number)))?,
+                    ),
+                };
+                (Token::Literal(literal), j - i)
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut j = i + 1;
+                while j < chars.len() && (chars[j].is_alphanumeric() || chars[j] == '_') {
+                    j += 1;
+                }
+                (Token::Ident(chars[i..j].iter().collect()), j - i)
+            }
+            _ => return Err(parse_error(text, &format!("unexpected character '{}'", c))),
+        };
+        tokens.push(token);
+        i += width;
+    }
+    Ok(tokens)
+}
+
+// Deepest nesting of parentheses and `!` that Filter::parse accepts, so
+// untrusted text cannot exhaust the stack while parsing or evaluating.
+pub const MAX_FILTER_NESTING: usize = 64;
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+// Joins operands pairwise into a balanced tree, so a long && or || chain
+// adds only logarithmic depth.
+fn balanced(mut terms: Vec<Filter>, join: fn(Filter, Filter) -> Filter) -> Filter {
+    while terms.len() > 1 {
+        let mut joined = Vec::with_capacity(terms.len().div_ceil(2));
+        let mut pairs = terms.into_iter();
+        while let Some(a) = pairs.next() {
+            joined.push(match pairs.next() {
+                Some(b) => join(a, b),
+                None => a,
+            });
+        }
+        terms = joined;
+    }
+    terms.pop().expect("at least one operand")
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Filter, Box<dyn Error>> {
+        let mut terms = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            terms.push(self.and()?);
+        }
+        Ok(balanced(terms, |a, b| a | b))
+    }
+
+    fn and(&mut self) -> Result<Filter, Box<dyn Error>> {
+        let mut terms = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            terms.push(self.unary()?);
+        }
+        Ok(balanced(terms, |a, b| a & b))
+    }
+
+    // Parses `inner` one nesting level down.
+    fn nested(&mut self, inner: impl FnOnce(&mut Self) -> Result<Filter, Box<dyn Error>>) -> Result<Filter, Box<dyn Error>> {
+        if self.depth == MAX_FILTER_NESTING {
+            return Err(parse_error(
+                self.text,
+                &format!("nested deeper than {} levels", MAX_FILTER_NESTING),
+            ));
+        }
+        self.depth += 1;
+        let filter = inner(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn unary(&mut self) -> Result<Filter, Box<dyn Error>> {
+        match self.advance() {
+            Some(Token::Not) => Ok(!self.nested(Self::unary)?),
+            Some(Token::Open) => {
+                let filter = self.nested(Self::or)?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(filter),
+                    _ => Err(parse_error(self.text, "missing ')'")),
+                }
+            }
+            Some(Token::Ident(column)) => match (self.advance(), self.advance()) {
+                (Some(Token::Op(op)), Some(Token::Literal(value))) => Ok(col(&column).compare(op, value)),
+                _ => Err(parse_error(
+                    self.text,
+                    &format!("expected a comparison with a literal after '{}'", column),
+                )),
+            },
+            Some(token) => Err(parse_error(self.text, &format!("unexpected {:?}", token))),
+            None => Err(parse_error(self.text, "unexpected end of expression")),
+        }
+    }
+}
+
+impl Filter {
+    // Parses `column op literal` comparisons joined by &&, || and !, with
+    // parentheses; && binds tighter than ||. Literals are integers, floats
+    // or single- or double-quoted strings. Parentheses and ! nest at most
+    // MAX_FILTER_NESTING deep.
+    pub fn parse(text: &str) -> Result<Filter, Box<dyn Error>> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            pos: 0,
+            depth: 0,
+        };
+        let filter = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parse_error(text, &format!("unexpected {:?}", parser.tokens[parser.pos])));
+        }
+        Ok(filter)
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Descriptors and loaders for standard ANN benchmark suites.
+//!
+//! Nothing here downloads: the files are expected to already sit in a local
+//! directory under the names the descriptor lists. Loading checks every
+//! file against the descriptor and names the file and shape that differ.
+//! The results plug straight into `evaluation::tune`. Suites other than the
+//! built-in ones can be described by a JSON object with the fields of
+//! `BenchmarkDataset` (serde feature).
+
+use super::{npy, util, ScannError};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum VectorFormat {
+    // Records of a little-endian i32 dimension followed by that many f32s,
+    // as distributed with SIFT1M and GIST1M. Ground truth is always .ivecs,
+    // the same layout with i32 values.
+    Fvecs,
+    Npy,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BenchmarkDataset {
+    pub name: String,
+    // File names relative to the benchmark directory.
+    pub base: String,
+    pub queries: String,
+    pub ground_truth: String,
+    pub format: VectorFormat,
+    pub dimensionality: usize,
+    pub num_base: usize,
+    pub num_queries: usize,
+    // Neighbors listed per query in the ground truth file.
+    pub ground_truth_k: usize,
+    // Distance measure the ground truth was computed with.
+    pub measure: String,
+}
+
+pub const BUILTIN_BENCHMARKS: &[&str] = &["sift1m", "gist1m", "glove100"];
+
+fn fvecs_suite(name: &str, prefix: &str, dimensionality: usize, num_base: usize, num_queries: usize, measure: &str) -> BenchmarkDataset {
+    BenchmarkDataset {
+        name: name.to_string(),
+        base: format!("{}_base.fvecs", prefix),
+        queries: format!("{}_query.fvecs", prefix),
+        ground_truth: format!("{}_groundtruth.ivecs", prefix),
+        format: VectorFormat::Fvecs,
+        dimensionality,
+        num_base,
+        num_queries,
+        ground_truth_k: 100,
+        measure: measure.to_string(),
+    }
+}
+
+// Layouts of the public releases. GloVe-100 is the angular ann-benchmarks
+// split converted to .fvecs/.ivecs under the same naming scheme.
+pub fn builtin(name: &str) -> Option<BenchmarkDataset> {
+    match name {
+        "sift1m" => Some(fvecs_suite("sift1m", "sift", 128, 1_000_000, 10_000, "SquaredL2Distance")),
+        "gist1m" => Some(fvecs_suite("gist1m", "gist", 960, 1_000_000, 1_000, "SquaredL2Distance")),
+        "glove100" => Some(fvecs_suite("glove100", "glove-100", 100, 1_183_514, 10_000, "CosineDistance")),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "serde")]
+impl BenchmarkDataset {
+    // `origin` names the source in error messages. format is "fvecs" or
+    // "npy"; every other field is required.
+    pub fn from_json(text: &str, origin: &str) -> Result<Self, Box<dyn Error>> {
+        serde_json::from_str(text)
+            .map_err(|e| util::invalid_argument_error(&format!("{} is not a benchmark descriptor: {}", origin, e)))
+    }
+}
+
+pub struct LoadedBenchmark {
+    pub base: util::DenseDataset<f32>,
+    pub queries: util::DenseDataset<f32>,
+    // Row indices into `base`, nearest first.
+    pub ground_truth: Vec<Vec<usize>>,
+}
+
+fn file_error(path: &Path, message: String) -> Box<dyn Error> {
+    Box::new(ScannError {
+        message: format!("{}: {}", path.display(), message),
+    })
+}
+
+// Reads a .fvecs/.ivecs file record by record. Every record must have
+// `dimensionality` values and there must be exactly `count` records.
+fn read_vecs<T>(
+    path: &Path,
+    dimensionality: usize,
+    count: usize,
+    decode: fn([u8; 4]) -> T,
+) -> Result<Vec<Vec<T>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| file_error(path, format!("cannot open: {}", e)))?;
+    let mut reader = BufReader::new(file);
+    let mut rows = Vec::with_capacity(count);
+    let mut header = [0u8; 4];
+    let mut record = vec![0u8; dimensionality * 4];
+    loop {
+        match reader.read(&mut header[..1]) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return Err(file_error(path, format!("read failed: {}", e))),
+        }
+        let index = rows.len();
+        reader
+            .read_exact(&mut header[1..])
+            .map_err(|_| file_error(path, format!("record {} has a truncated header", index)))?;
+        let dim = i32::from_le_bytes(header);
+        if dim < 0 || dim as usize != dimensionality {
+            return Err(file_error(
+                path,
+                format!("record {} has dimension {}, expected {}", index, dim, dimensionality),
+            ));
+        }
+        if rows.len() == count {
+            return Err(file_error(path, format!("has more than the expected {} records", count)));
+        }
+        reader
+            .read_exact(&mut record)
+            .map_err(|_| file_error(path, format!("record {} is truncated", index)))?;
+        rows.push(record.chunks_exact(4).map(|b| decode([b[0], b[1], b[2], b[3]])).collect());
+    }
+    if rows.len() != count {
+        return Err(file_error(path, format!("has {} records, expected {}", rows.len(), count)));
+    }
+    Ok(rows)
+}
+
+fn load_vectors(path: &Path, format: VectorFormat, dimensionality: usize, count: usize) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+    match format {
+        VectorFormat::Fvecs => Ok(util::DenseDataset::new(
+            read_vecs(path, dimensionality, count, f32::from_le_bytes)?,
+            dimensionality,
+        )),
+        VectorFormat::Npy => {
+            let dataset = npy::load_dataset(path, npy::LoadMode::Owned)?;
+            if dataset.size() != count || dataset.dimensionality() != dimensionality {
+                return Err(file_error(
+                    path,
+                    format!(
+                        "has shape ({}, {}), expected ({}, {})",
+                        dataset.size(),
+                        dataset.dimensionality(),
+                        count,
+                        dimensionality
+                    ),
+                ));
+            }
+            Ok(dataset)
+        }
+    }
+}
+
+pub fn load_benchmark<P: AsRef<Path>>(descriptor: &BenchmarkDataset, dir: P) -> Result<LoadedBenchmark, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let base = load_vectors(
+        &dir.join(&descriptor.base),
+        descriptor.format,
+        descriptor.dimensionality,
+        descriptor.num_base,
+    )?;
+    let queries = load_vectors(
+        &dir.join(&descriptor.queries),
+        descriptor.format,
+        descriptor.dimensionality,
+        descriptor.num_queries,
+    )?;
+    let ground_truth_path = dir.join(&descriptor.ground_truth);
+    let ids = read_vecs(
+        &ground_truth_path,
+        descriptor.ground_truth_k,
+        descriptor.num_queries,
+        i32::from_le_bytes,
+    )?;
+    let mut ground_truth = Vec::with_capacity(ids.len());
+    for (q, row) in ids.into_iter().enumerate() {
+        let mut neighbors = Vec::with_capacity(row.len());
+        for id in row {
+            if id < 0 || id as usize >= descriptor.num_base {
+                return Err(file_error(
+                    &ground_truth_path,
+                    format!("query {} lists neighbor {}, outside [0, {})", q, id, descriptor.num_base),
+                ));
+            }
+            neighbors.push(id as usize);
+        }
+        ground_truth.push(neighbors);
+    }
+    Ok(LoadedBenchmark {
+        base,
+        queries,
+        ground_truth,
+    })
+}
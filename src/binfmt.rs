@@ -0,0 +1,353 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioned, endianness-tagged headers for flat binary artifacts.
+//!
+//! Header layout (32 bytes):
+//!   magic [8] | endianness u8 (0 little, 1 big) | reserved [3] |
+//!   version u32 | payload_len u64 | fnv1a64(payload) u64
+//! Every field after the endianness byte is encoded in the declared byte
+//! order, as is all numeric payload data. Readers byte-swap on mismatch.
+
+use super::{blob, ScannError};
+use std::error::Error;
+
+pub const HEADER_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+
+    fn flag(self) -> u8 {
+        match self {
+            Endianness::Little => 0,
+            Endianness::Big => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub magic: [u8; 8],
+    pub endianness: Endianness,
+    pub version: u32,
+    pub payload_len: u64,
+    pub checksum: u64,
+}
+
+fn format_error(message: String) -> Box<dyn Error> {
+    Box::new(ScannError { message })
+}
+
+// Numeric encoding in a chosen byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteOrder(pub Endianness);
+
+impl ByteOrder {
+    pub fn put_u32(self, out: &mut Vec<u8>, v: u32) {
+        out.extend_from_slice(&match self.0 {
+            Endianness::Little => v.to_le_bytes(),
+            Endianness::Big => v.to_be_bytes(),
+        });
+    }
+
+    pub fn put_u64(self, out: &mut Vec<u8>, v: u64) {
+        out.extend_from_slice(&match self.0 {
+            Endianness::Little => v.to_le_bytes(),
+            Endianness::Big => v.to_be_bytes(),
+        });
+    }
+
+    pub fn put_f32(self, out: &mut Vec<u8>, v: f32) {
+        self.put_u32(out, v.to_bits());
+    }
+
+    pub fn u32(self, bytes: &[u8], at: usize) -> u32 {
+        let b: [u8; 4] = bytes[at..at + 4].try_into().unwrap();
+        match self.0 {
+            Endianness::Little => u32::from_le_bytes(b),
+            Endianness::Big => u32::from_be_bytes(b),
+        }
+    }
+
+    pub fn u64(self, bytes: &[u8], at: usize) -> u64 {
+        let b: [u8; 8] = bytes[at..at + 8].try_into().unwrap();
+        match self.0 {
+            Endianness::Little => u64::from_le_bytes(b),
+            Endianness::Big => u64::from_be_bytes(b),
+        }
+    }
+
+    pub fn f32(self, bytes: &[u8], at: usize) -> f32 {
+        f32::from_bits(self.u32(bytes, at))
+    }
+}
+
+pub fn encode_header(header: &Header) -> Vec<u8> {
+    let order = ByteOrder(header.endianness);
+    let mut out = Vec::with_capacity(HEADER_LEN);
+    out.extend_from_slice(&header.magic);
+    out.push(header.endianness.flag());
+    out.extend_from_slice(&[0; 3]);
+    order.put_u32(&mut out, header.version);
+    order.put_u64(&mut out, header.payload_len);
+    order.put_u64(&mut out, header.checksum);
+    out
+}
+
+// Prepends a header for `payload`, which must already be encoded in
+// `endianness`.
+pub fn write_section(magic: &[u8; 8], version: u32, endianness: Endianness, payload: &[u8]) -> Vec<u8> {
+    let mut out = encode_header(&Header {
+        magic: *magic,
+        endianness,
+        version,
+        payload_len: payload.len() as u64,
+        checksum: blob::fnv1a64(payload),
+    });
+    out.extend_from_slice(payload);
+    out
+}
+
+// Parses and validates a header: magic, endianness flag and version. The
+// payload length is checked against `available` bytes following the
+// header, when known.
+pub fn read_header(
+    bytes: &[u8],
+    magic: &[u8; 8],
+    max_version: u32,
+    available: Option<u64>,
+) -> Result<Header, Box<dyn Error>> {
+    if bytes.len() < HEADER_LEN {
+        return Err(format_error(format!(
+            "Truncated header: {} bytes, need {}",
+            bytes.len(),
+            HEADER_LEN
+        )));
+    }
+    if &bytes[..8] != magic {
+        return Err(format_error(format!(
+            "Bad magic {:?}, expected {:?}",
+            String::from_utf8_lossy(&bytes[..8]),
+            String::from_utf8_lossy(magic)
+        )));
+    }
+    let endianness = match bytes[8] {
+        0 => Endianness::Little,
+        1 => Endianness::Big,
+        flag => return Err(format_error(format!("Invalid endianness flag {}", flag))),
+    };
+    if bytes[9..12] != [0, 0, 0] {
+        return Err(format_error("Reserved header bytes are not zero".to_string()));
+    }
+    let order = ByteOrder(endianness);
+    let header = Header {
+        magic: *magic,
+        endianness,
+        version: order.u32(bytes, 12),
+        payload_len: order.u64(bytes, 16),
+        checksum: order.u64(bytes, 24),
+    };
+    if header.version == 0 || header.version > max_version {
+        return Err(format_error(format!(
+            "Unsupported format version {} ({:?} byte order); this build reads versions 1..={}",
+            header.version, endianness, max_version
+        )));
+    }
+    if let Some(available) = available {
+        if header.payload_len != available {
+            return Err(format_error(format!(
+                "Header declares {} payload bytes but {} are present",
+                header.payload_len, available
+            )));
+        }
+    }
+    Ok(header)
+}
+
+// Reads a whole in-memory section and verifies its checksum.
+pub fn read_section<'a>(
+    bytes: &'a [u8],
+    magic: &[u8; 8],
+    max_version: u32,
+) -> Result<(Header, &'a [u8]), Box<dyn Error>> {
+    let available = bytes.len().saturating_sub(HEADER_LEN) as u64;
+    let header = read_header(bytes, magic, max_version, Some(available))?;
+    let payload = &bytes[HEADER_LEN..];
+    verify_checksum(&header, payload)?;
+    Ok((header, payload))
+}
+
+// Separate from read_header so lazily read artifacts can validate the
+// header without reading the whole payload.
+pub fn verify_checksum(header: &Header, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+    let actual = blob::fnv1a64(payload);
+    if actual != header.checksum {
+        return Err(format_error(format!(
+            "Payload checksum mismatch: header has {:#018x}, payload hashes to {:#018x}",
+            header.checksum, actual
+        )));
+    }
+    Ok(())
+}
+
+// Compact framing for small values that cross process boundaries, such as
+// search cursors and prepared queries:
+//   version u8 | kind u8 | payload | fnv1a64(version, kind, payload) u64
+// Payload integers and floats are little-endian. The version comes first
+// so a reader can reject a newer encoding before interpreting anything
+// else; the kind byte keeps one value's bytes from decoding as another's.
+// Compatibility rules: any change to a payload layout bumps that value's
+// version; readers keep decoding every older version and reject newer
+// ones with an error naming both, never by guessing at the payload; and
+// payloads must be consumed exactly, so trailing bytes are an error.
+pub const WIRE_OVERHEAD: usize = 2 + 8;
+
+// Kind bytes of the values framed with write_wire. Never reuse a retired
+// kind.
+pub const WIRE_KIND_SEARCH_CURSOR: u8 = 1;
+pub const WIRE_KIND_PREPARED_QUERY: u8 = 2;
+pub const WIRE_KIND_CALIBRATOR: u8 = 3;
+pub const WIRE_KIND_CANCELLATION_TOKEN: u8 = 4;
+
+pub fn write_wire(kind: u8, version: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + WIRE_OVERHEAD);
+    out.push(version);
+    out.push(kind);
+    out.extend_from_slice(payload);
+    let checksum = blob::fnv1a64(&out);
+    ByteOrder(Endianness::Little).put_u64(&mut out, checksum);
+    out
+}
+
+// Validates the framing of a value written by write_wire and returns its
+// version and payload. `name` labels errors. Versions above `max_version`
+// are rejected with an error naming both versions.
+pub fn read_wire<'a>(bytes: &'a [u8], kind: u8, max_version: u8, name: &str) -> Result<(u8, &'a [u8]), Box<dyn Error>> {
+    let Some(&version) = bytes.first() else {
+        return Err(format_error(format!("Empty {} encoding", name)));
+    };
+    if version == 0 || version > max_version {
+        return Err(format_error(format!(
+            "Unsupported {} wire version {}; this build reads versions 1..={}",
+            name, version, max_version
+        )));
+    }
+    if bytes.len() < WIRE_OVERHEAD {
+        return Err(format_error(format!(
+            "Truncated {} encoding: {} bytes, need at least {}",
+            name,
+            bytes.len(),
+            WIRE_OVERHEAD
+        )));
+    }
+    if bytes[1] != kind {
+        return Err(format_error(format!(
+            "Encoding holds value kind {}, not a {} (kind {})",
+            bytes[1], name, kind
+        )));
+    }
+    let body_end = bytes.len() - 8;
+    let expected = ByteOrder(Endianness::Little).u64(bytes, body_end);
+    let actual = blob::fnv1a64(&bytes[..body_end]);
+    if actual != expected {
+        return Err(format_error(format!(
+            "{} checksum mismatch: encoding has {:#018x}, contents hash to {:#018x}",
+            name, expected, actual
+        )));
+    }
+    Ok((version, &bytes[2..body_end]))
+}
+
+// Bounds-checked little-endian reads over a wire payload.
+pub struct WireReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    name: &'a str,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(bytes: &'a [u8], name: &'a str) -> Self {
+        WireReader { bytes, pos: 0, name }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.bytes.len() - self.pos < n {
+            return Err(format_error(format!(
+                "Truncated {} payload: needed {} bytes at offset {}, {} remain",
+                self.name,
+                n,
+                self.pos,
+                self.bytes.len() - self.pos
+            )));
+        }
+        let out = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(ByteOrder(Endianness::Little).u64(self.take(8)?, 0))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        Ok(ByteOrder(Endianness::Little).f32(self.take(4)?, 0))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, Box<dyn Error>> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    // Reads a u64 count of elements of `element_size` bytes, failing when
+    // the rest of the payload cannot hold them.
+    pub fn count(&mut self, element_size: usize) -> Result<usize, Box<dyn Error>> {
+        let n = self.u64()?;
+        let remaining = (self.bytes.len() - self.pos) as u64;
+        if n.checked_mul(element_size as u64).is_none_or(|bytes| bytes > remaining) {
+            return Err(format_error(format!(
+                "{} payload declares {} elements of {} bytes but {} bytes remain",
+                self.name, n, element_size, remaining
+            )));
+        }
+        Ok(n as usize)
+    }
+
+    // Fails when bytes are left over, which means the payload was written
+    // by an encoder this version does not understand.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.pos != self.bytes.len() {
+            return Err(format_error(format!(
+                "{} payload has {} trailing bytes",
+                self.name,
+                self.bytes.len() - self.pos
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Single-file relocatable index blob with a versioned section table.
+//!
+//! Layout (all integers little-endian):
+//!   magic "SCANNBLB" | version u32 | num_sections u32
+//!   num_sections x { kind u32 | reserved u32 | offset u64 | len u64 | checksum u64 }
+//!   section payloads, each starting on a 64-byte boundary.
+//! Offsets are relative to the start of the blob, so it can be mapped at any
+//! address. Version 2 adds a BuildInfo section recording the writer's build
+//! and the features a reader needs; version 1 blobs are still read.
+
+use super::{build_info, tree, util, ScannError};
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const BLOB_MAGIC: &[u8; 8] = b"SCANNBLB";
+pub const BLOB_VERSION: u32 = 2;
+pub const SUPPORTED_BLOB_VERSIONS: &[u32] = &[1, 2];
+pub const SECTION_ALIGNMENT: usize = 64;
+
+const HEADER_LEN: usize = 16;
+const SECTION_ENTRY_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SectionKind {
+    Dataset = 1,
+    Docids = 2,
+    Tree = 3,
+    RescoringDataset = 4,
+    RescoringMeasure = 5,
+    BuildInfo = 6,
+    Expiries = 7,
+    IndexWidth = 8,
+    Attributes = 9,
+}
+
+impl SectionKind {
+    fn from_u32(v: u32) -> Option<Self> {
+        match v {
+            1 => Some(SectionKind::Dataset),
+            2 => Some(SectionKind::Docids),
+            3 => Some(SectionKind::Tree),
+            4 => Some(SectionKind::RescoringDataset),
+            5 => Some(SectionKind::RescoringMeasure),
+            6 => Some(SectionKind::BuildInfo),
+            7 => Some(SectionKind::Expiries),
+            8 => Some(SectionKind::IndexWidth),
+            9 => Some(SectionKind::Attributes),
+            _ => None,
+        }
+    }
+}
+
+pub fn fnv1a64(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn blob_error(message: String) -> Box<dyn Error> {
+    Box::new(ScannError { message })
+}
+
+fn align_up(n: usize) -> usize {
+    n.div_ceil(SECTION_ALIGNMENT) * SECTION_ALIGNMENT
+}
+
+pub fn encode_blob(sections: &[(SectionKind, Vec<u8>)]) -> Vec<u8> {
+    encode_blob_with_requirements(sections, &[])
+}
+
+// Like encode_blob, additionally recording cargo features a reader must
+// have to interpret the sections.
+pub fn encode_blob_with_requirements(sections: &[(SectionKind, Vec<u8>)], required_features: &[&str]) -> Vec<u8> {
+    let build_text = format!(
+        "{}requires_features: {}\n",
+        build_info::build_info().to_text(),
+        required_features.join(",")
+    );
+    let sections: Vec<(SectionKind, &[u8])> = sections
+        .iter()
+        .filter(|(kind, _)| *kind != SectionKind::BuildInfo)
+        .map(|(kind, payload)| (*kind, payload.as_slice()))
+        .chain(std::iter::once((SectionKind::BuildInfo, build_text.as_bytes())))
+        .collect();
+    let table_end = HEADER_LEN + sections.len() * SECTION_ENTRY_LEN;
+    let mut offsets = Vec::with_capacity(sections.len());
+    let mut cursor = align_up(table_end);
+    for (_, payload) in &sections {
+        offsets.push(cursor);
+        cursor = align_up(cursor + payload.len());
+    }
+
+    let mut out = vec![0u8; cursor];
+    out[0..8].copy_from_slice(BLOB_MAGIC);
+    out[8..12].copy_from_slice(&BLOB_VERSION.to_le_bytes());
+    out[12..16].copy_from_slice(&(sections.len() as u32).to_le_bytes());
+    for (i, ((kind, payload), &offset)) in sections.iter().zip(offsets.iter()).enumerate() {
+        let entry = HEADER_LEN + i * SECTION_ENTRY_LEN;
+        out[entry..entry + 4].copy_from_slice(&(*kind as u32).to_le_bytes());
+        out[entry + 8..entry + 16].copy_from_slice(&(offset as u64).to_le_bytes());
+        out[entry + 16..entry + 24].copy_from_slice(&(payload.len() as u64).to_le_bytes());
+        out[entry + 24..entry + 32].copy_from_slice(&fnv1a64(payload).to_le_bytes());
+        out[offset..offset + payload.len()].copy_from_slice(payload);
+    }
+    out
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// Returns borrowed section payloads keyed by kind, after validating the
+// header, bounds and checksums.
+pub fn decode_blob(bytes: &[u8]) -> Result<BTreeMap<SectionKind, &[u8]>, Box<dyn Error>> {
+    if bytes.len() < HEADER_LEN || &bytes[0..8] != BLOB_MAGIC {
+        return Err(blob_error("Not a ScaNN blob: bad magic".to_string()));
+    }
+    let version = read_u32(bytes, 8);
+    if !SUPPORTED_BLOB_VERSIONS.contains(&version) {
+        return Err(blob_error(format!(
+            "Unsupported blob version {} (this build reads versions {:?})",
+            version, SUPPORTED_BLOB_VERSIONS
+        )));
+    }
+    let num_sections = read_u32(bytes, 12) as usize;
+    let table_end = num_sections
+        .checked_mul(SECTION_ENTRY_LEN)
+        .and_then(|n| n.checked_add(HEADER_LEN))
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| blob_error(format!("Blob section table for {} sections is truncated", num_sections)))?;
+
+    let mut sections = BTreeMap::new();
+    for i in 0..num_sections {
+        let entry = HEADER_LEN + i * SECTION_ENTRY_LEN;
+        let raw_kind = read_u32(bytes, entry);
+        let offset = read_u64(bytes, entry + 8);
+        let len = read_u64(bytes, entry + 16);
+        let checksum = read_u64(bytes, entry + 24);
+        let Some(kind) = SectionKind::from_u32(raw_kind) else {
+            return Err(blob_error(format!("Unknown blob section kind {} in entry {}", raw_kind, i)));
+        };
+        let end = offset.checked_add(len).filter(|&end| end <= bytes.len() as u64);
+        let Some(end) = end else {
+            return Err(blob_error(format!(
+                "Blob section {:?} [{}, +{}) exceeds blob size {}",
+                kind,
+                offset,
+                len,
+                bytes.len()
+            )));
+        };
+        if (offset as usize) < table_end || !(offset as usize).is_multiple_of(SECTION_ALIGNMENT) {
+            return Err(blob_error(format!("Blob section {:?} has invalid offset {}", kind, offset)));
+        }
+        let payload = &bytes[offset as usize..end as usize];
+        let actual = fnv1a64(payload);
+        if actual != checksum {
+            return Err(blob_error(format!(
+                "Checksum mismatch in blob section {:?}: stored {:016x}, computed {:016x}",
+                kind, checksum, actual
+            )));
+        }
+        if sections.insert(kind, payload).is_some() {
+            return Err(blob_error(format!("Duplicate blob section {:?}", kind)));
+        }
+    }
+    if let Some(build) = sections.get(&SectionKind::BuildInfo) {
+        let text = std::str::from_utf8(build).map_err(|_| blob_error("Blob build info is not UTF-8".to_string()))?;
+        let required = text
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim() == "requires_features")
+            .map(|(_, value)| build_info::parse_feature_list(value))
+            .unwrap_or_default();
+        build_info::check_required_features(&required, "This blob")?;
+    }
+    Ok(sections)
+}
+
+// Written to a temporary name and renamed, so retrievers still mapping an
+// earlier blob at `path` keep reading the old file.
+pub fn write_blob<P: AsRef<Path>>(path: P, sections: &[(SectionKind, Vec<u8>)]) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, encode_blob(sections))
+        .map_err(|e| blob_error(format!("Failed to write blob {}: {}", path.display(), e)))?;
+    fs::rename(&temporary, path).map_err(|e| blob_error(format!("Failed to install blob {}: {}", path.display(), e)))
+}
+
+// A blob file mapped read-only. Dereferences to the file bytes; clones
+// share the map, so sections can be borrowed for as long as any holder
+// lives.
+#[derive(Clone, Debug)]
+pub struct MappedBlob {
+    map: Arc<Mmap>,
+}
+
+impl std::ops::Deref for MappedBlob {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+pub fn read_blob_file<P: AsRef<Path>>(path: P) -> Result<MappedBlob, Box<dyn Error>> {
+    let path = path.as_ref();
+    let file = fs::File::open(path).map_err(|e| blob_error(format!("Failed to open blob {}: {}", path.display(), e)))?;
+    // The file is treated as immutable while mapped.
+    let map = unsafe { Mmap::map(&file) }
+        .map_err(|e| blob_error(format!("Failed to map blob {}: {}", path.display(), e)))?;
+    Ok(MappedBlob { map: Arc::new(map) })
+}
+
+pub(crate) struct SectionReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    kind: SectionKind,
+}
+
+impl<'a> SectionReader<'a> {
+    pub(crate) fn new(kind: SectionKind, bytes: &'a [u8]) -> Self {
+        SectionReader { bytes, pos: 0, kind }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(blob_error(format!(
+                "Blob section {:?} truncated: need {} bytes at offset {}, have {}",
+                self.kind,
+                n,
+                self.pos,
+                self.bytes.len()
+            )));
+        };
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn len(&mut self, element_size: usize) -> Result<usize, Box<dyn Error>> {
+        let n = self.u64()? as usize;
+        self.ensure(n, element_size)?;
+        Ok(n)
+    }
+
+    // Fails unless `n` elements of `element_size` bytes remain, so counts
+    // read from untrusted input can size allocations.
+    pub(crate) fn ensure(&self, n: usize, element_size: usize) -> Result<(), Box<dyn Error>> {
+        if n.saturating_mul(element_size) > self.bytes.len() - self.pos {
+            return Err(blob_error(format!(
+                "Blob section {:?} declares {} elements but only {} bytes remain",
+                self.kind,
+                n,
+                self.bytes.len() - self.pos
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn encode_dataset(dataset: &impl util::RowSource<f32>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16 + dataset.num_rows() * dataset.dimensionality() * 4);
+    out.extend_from_slice(&(dataset.num_rows() as u64).to_le_bytes());
+    out.extend_from_slice(&(dataset.dimensionality() as u64).to_le_bytes());
+    for i in 0..dataset.num_rows() {
+        for v in dataset.row(i) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    out
+}
+
+// Validates a dataset section header against the payload length and
+// returns (rows, dimensionality).
+fn dataset_shape(kind: SectionKind, bytes: &[u8]) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut reader = SectionReader::new(kind, bytes);
+    let n = reader.u64()? as usize;
+    let dim = reader.len(4)?;
+    // Zero-width rows take no bytes, so nothing else bounds their count.
+    if dim == 0 && n > 0 {
+        return Err(blob_error(format!(
+            "Blob {:?} section declares {} rows of dimensionality 0",
+            kind, n
+        )));
+    }
+    if n.saturating_mul(dim).saturating_mul(4) != bytes.len() - 16 {
+        return Err(blob_error(format!(
+            "Blob {:?} section holds {} bytes, expected {} x {} floats",
+            kind,
+            bytes.len() - 16,
+            n,
+            dim
+        )));
+    }
+    Ok((n, dim))
+}
+
+pub(crate) fn decode_dataset_section(
+    kind: SectionKind,
+    bytes: &[u8],
+) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+    let (n, dim) = dataset_shape(kind, bytes)?;
+    let mut reader = SectionReader::new(kind, &bytes[16..]);
+    let mut data = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut row = Vec::with_capacity(dim);
+        for _ in 0..dim {
+            row.push(reader.f32()?);
+        }
+        data.push(row);
+    }
+    Ok(util::DenseDataset::new(data, dim))
+}
+
+// The f32 payload of a dataset section, read in place from a mapped blob.
+#[derive(Debug)]
+struct MappedRows {
+    blob: MappedBlob,
+    offset: usize,
+    len: usize,
+}
+
+impl util::SharedRows<f32> for MappedRows {
+    fn values(&self) -> &[f32] {
+        // Validated in map_dataset_section: aligned, in bounds, little-endian.
+        let (prefix, values, _) = unsafe { self.blob[self.offset..self.offset + self.len * 4].align_to::<f32>() };
+        debug_assert!(prefix.is_empty());
+        values
+    }
+}
+
+// Rows of the dataset section `bytes`, which must borrow from `blob`,
+// without copying them: the values start 16 bytes into a 64-byte aligned
+// section of a page-aligned map. Big-endian hosts decode a copy instead.
+pub(crate) fn map_dataset_section(
+    blob: &MappedBlob,
+    kind: SectionKind,
+    bytes: &[u8],
+) -> Result<util::SegmentedDataset<f32>, Box<dyn Error>> {
+    if cfg!(target_endian = "big") {
+        return Ok(util::SegmentedDataset::from_dense(decode_dataset_section(kind, bytes)?));
+    }
+    let (n, dim) = dataset_shape(kind, bytes)?;
+    let section = (bytes.as_ptr() as usize)
+        .checked_sub(blob.as_ptr() as usize)
+        .filter(|&offset| offset + bytes.len() <= blob.len())
+        .ok_or_else(|| blob_error(format!("Blob {:?} section is not part of the mapped blob", kind)))?;
+    let offset = section + 16;
+    if !(blob.as_ptr() as usize + offset).is_multiple_of(std::mem::align_of::<f32>()) {
+        return Err(blob_error(format!("Blob {:?} section values are not f32-aligned", kind)));
+    }
+    let rows = MappedRows { blob: blob.clone(), offset, len: n * dim };
+    util::SegmentedDataset::from_shared(Arc::new(rows), n, dim)
+}
+
+pub(crate) fn encode_usizes(values: &[usize]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + values.len() * 8);
+    out.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    for &v in values {
+        out.extend_from_slice(&(v as u64).to_le_bytes());
+    }
+    out
+}
+
+// count u64 | count x index, each index 4 or 8 bytes per `width`. Blobs
+// without an IndexWidth section use 8-byte indices.
+pub(crate) fn encode_indices(values: &[usize], width: util::IndexWidth) -> Result<Vec<u8>, Box<dyn Error>> {
+    match width {
+        util::IndexWidth::U32 => encode_indices_as::<u32>(values),
+        util::IndexWidth::U64 => encode_indices_as::<u64>(values),
+    }
+}
+
+fn encode_indices_as<I: util::DatapointIndex>(values: &[usize]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::with_capacity(8 + values.len() * I::WIDTH.bytes());
+    out.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    for &v in values {
+        let Some(index) = I::from_usize(v) else {
+            return Err(blob_error(format!("Index {} does not fit a {:?} index", v, I::WIDTH)));
+        };
+        index.write_le(&mut out);
+    }
+    Ok(out)
+}
+
+pub(crate) fn decode_indices(
+    kind: SectionKind,
+    bytes: &[u8],
+    width: util::IndexWidth,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    match width {
+        util::IndexWidth::U32 => decode_indices_as::<u32>(kind, bytes),
+        util::IndexWidth::U64 => decode_indices_as::<u64>(kind, bytes),
+    }
+}
+
+fn decode_indices_as<I: util::DatapointIndex>(kind: SectionKind, bytes: &[u8]) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut reader = SectionReader::new(kind, bytes);
+    let size = I::WIDTH.bytes();
+    let n = reader.len(size)?;
+    (0..n).map(|_| reader.take(size).map(|b| I::read_le(b).to_usize())).collect()
+}
+
+pub(crate) fn decode_index_width(bytes: &[u8]) -> Result<util::IndexWidth, Box<dyn Error>> {
+    let mut reader = SectionReader::new(SectionKind::IndexWidth, bytes);
+    let bytes_per_index = reader.u32()? as usize;
+    util::IndexWidth::from_bytes(bytes_per_index)
+        .ok_or_else(|| blob_error(format!("Blob index width of {} bytes is not supported", bytes_per_index)))
+}
+
+// Written by older releases; expiries now live in the Attributes section.
+// count u64 | count x { docid u64 | expires_at i64 }
+pub(crate) fn decode_expiries(bytes: &[u8]) -> Result<Vec<(usize, i64)>, Box<dyn Error>> {
+    let mut reader = SectionReader::new(SectionKind::Expiries, bytes);
+    let n = reader.len(16)?;
+    let mut entries = Vec::with_capacity(n);
+    for _ in 0..n {
+        let docid = reader.u64()? as usize;
+        entries.push((docid, reader.u64()? as i64));
+    }
+    Ok(entries)
+}
+
+pub(crate) fn encode_tree(tree: &tree::KMeansTree) -> Vec<u8> {
+    let centers = tree.centers();
+    let mut out = Vec::new();
+    out.extend_from_slice(&(tree.num_leaves() as u64).to_le_bytes());
+    out.extend_from_slice(&(centers.dimensionality() as u64).to_le_bytes());
+    out.extend_from_slice(&tree.spilling_factor().to_le_bytes());
+    out.extend_from_slice(&(tree.max_spill_centers() as u32).to_le_bytes());
+    for center in &centers.data {
+        for v in center {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    for leaf in 0..tree.num_leaves() {
+        out.extend_from_slice(&encode_usizes(&tree.leaf(leaf).to_vec()));
+    }
+    out
+}
+
+pub(crate) fn decode_tree(
+    bytes: &[u8],
+    dataset_size: usize,
+    dataset_dim: usize,
+) -> Result<tree::KMeansTree, Box<dyn Error>> {
+    let mut reader = SectionReader::new(SectionKind::Tree, bytes);
+    let num_leaves = reader.len(4)?;
+    let dim = reader.len(4)?;
+    if dim != dataset_dim {
+        return Err(blob_error(format!(
+            "Blob tree centers have dimensionality {} but the dataset has {}",
+            dim, dataset_dim
+        )));
+    }
+    let spilling_factor = reader.f32()?;
+    let max_spill_centers = reader.u32()? as usize;
+    let mut centers = Vec::with_capacity(num_leaves);
+    for _ in 0..num_leaves {
+        let mut center = Vec::with_capacity(dim);
+        for _ in 0..dim {
+            center.push(reader.f32()?);
+        }
+        centers.push(center);
+    }
+    let mut leaves = Vec::with_capacity(num_leaves);
+    for leaf in 0..num_leaves {
+        let n = reader.len(8)?;
+        let mut members = Vec::with_capacity(n);
+        for _ in 0..n {
+            let index = reader.u64()? as usize;
+            if index >= dataset_size {
+                return Err(blob_error(format!(
+                    "Blob tree leaf {} references row {} but the dataset has {} rows",
+                    leaf, index, dataset_size
+                )));
+            }
+            members.push(index);
+        }
+        leaves.push(members);
+    }
+    Ok(tree::KMeansTree::from_parts(
+        util::DenseDataset::new(centers, dim),
+        leaves,
+        spilling_factor,
+        max_spill_centers,
+    ))
+}
@@ -0,0 +1,309 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Building a retriever from a BuildPlan and recording how it was built.
+//!
+//! With the `serde` feature the report is persisted next to the other
+//! artifacts as `build_report.json`; phases keep their pipeline order as an
+//! array.
+
+use super::{distance_measures, estimate, projection, quantization, retrieval, tree, util};
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+pub const BUILD_REPORT_NAME: &str = "build_report.json";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DatasetSummary {
+    pub num_points: usize,
+    pub dimensionality: usize,
+    pub min_norm: f32,
+    pub mean_norm: f32,
+    pub max_norm: f32,
+}
+
+impl DatasetSummary {
+    pub fn of(dataset: &util::DenseDataset<f32>) -> Self {
+        let mut accumulator = DatasetSummaryAccumulator::new(dataset.dimensionality());
+        for row in &dataset.data {
+            accumulator.add(row);
+        }
+        accumulator.finish()
+    }
+}
+
+// DatasetSummary of rows seen one at a time, for streamed datasets.
+pub struct DatasetSummaryAccumulator {
+    summary: DatasetSummary,
+    norm_sum: f64,
+}
+
+impl DatasetSummaryAccumulator {
+    pub fn new(dimensionality: usize) -> Self {
+        DatasetSummaryAccumulator {
+            summary: DatasetSummary {
+                dimensionality,
+                min_norm: f32::INFINITY,
+                ..Default::default()
+            },
+            norm_sum: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, row: &[f32]) {
+        let norm = row.iter().map(|v| v * v).sum::<f32>().sqrt();
+        self.summary.num_points += 1;
+        self.summary.min_norm = self.summary.min_norm.min(norm);
+        self.summary.max_norm = self.summary.max_norm.max(norm);
+        self.norm_sum += norm as f64;
+    }
+
+    pub fn finish(self) -> DatasetSummary {
+        let DatasetSummaryAccumulator { mut summary, norm_sum } = self;
+        if summary.num_points == 0 {
+            summary.min_norm = 0.0;
+        } else {
+            summary.mean_norm = (norm_sum / summary.num_points as f64) as f32;
+        }
+        summary
+    }
+}
+
+// Serialized as an object whose "kind" is "not_requested", "built" or
+// "skipped".
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum PartitioningRecord {
+    NotRequested,
+    Built {
+        num_leaves: usize,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "nan_if_null"))]
+        initial_objective: f64,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "nan_if_null"))]
+        final_objective: f64,
+        iterations: usize,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+// JSON has no NaN or infinity; serde_json writes them as null.
+#[cfg(feature = "serde")]
+fn nan_if_null<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(<Option<f64> as serde::Deserialize>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BuildReport {
+    pub library_version: String,
+    // Without rayon every phase runs in a fixed order, so a seeded build is
+    // bit-for-bit reproducible.
+    pub deterministic: bool,
+    pub dataset: DatasetSummary,
+    // (phase name, seconds) in pipeline order; names match BuildEstimate.
+    pub phases: Vec<(String, f64)>,
+    pub partitioning: PartitioningRecord,
+    pub quantization: Option<quantization::QuantizationErrorSummary>,
+    pub norm_cache: bool,
+}
+
+// Runs the phases of `plan` in the order BuildPlan::estimate lists them and
+// reports what each one did. Plans with projected_dims go through
+// build_projected_retriever, which also returns the projection queries need.
+pub fn build_retriever(
+    dataset: util::DenseDataset<f32>,
+    distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+    k: usize,
+    plan: &estimate::BuildPlan,
+    training_options: &tree::KMeansTreeTrainingOptions,
+) -> Result<(retrieval::ScannRetriever, BuildReport), Box<dyn Error>> {
+    if plan.projected_dims.is_some() {
+        return Err(util::invalid_argument_error(
+            "projected_dims needs build_projected_retriever, which returns the query projection",
+        ));
+    }
+    let report = BuildReport::new(DatasetSummary::of(&dataset));
+    build_phases(dataset, distance_measure, k, plan, training_options, report)
+}
+
+// Trains a PCA to plan.projected_dims on `dataset`, projects it in blocks
+// of rows and builds the rest of `plan` over the projected rows. Queries
+// must be projected with the returned projection before searching.
+pub fn build_projected_retriever(
+    dataset: util::DenseDataset<f32>,
+    distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+    k: usize,
+    plan: &estimate::BuildPlan,
+    training_options: &tree::KMeansTreeTrainingOptions,
+) -> Result<(retrieval::ScannRetriever, projection::PcaProjection<f32>, BuildReport), Box<dyn Error>> {
+    let Some(projected_dims) = plan.projected_dims else {
+        return Err(util::invalid_argument_error("build_projected_retriever needs projected_dims"));
+    };
+    let mut report = BuildReport::new(DatasetSummary::of(&dataset));
+    let start = Instant::now();
+    let mut pca = projection::PcaProjection::<f32>::new(dataset.dimensionality() as i32, projected_dims as i32)?;
+    pca.create(&dataset, true, None)?;
+    let projected = pca.project_dataset(&dataset)?;
+    report.phases.push(("projection".to_string(), start.elapsed().as_secs_f64()));
+    let (retriever, report) = build_phases(projected, distance_measure, k, plan, training_options, report)?;
+    Ok((retriever, pca, report))
+}
+
+fn build_phases(
+    dataset: util::DenseDataset<f32>,
+    distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+    k: usize,
+    plan: &estimate::BuildPlan,
+    training_options: &tree::KMeansTreeTrainingOptions,
+    mut report: BuildReport,
+) -> Result<(retrieval::ScannRetriever, BuildReport), Box<dyn Error>> {
+    report.norm_cache = plan.norm_cache;
+
+    let start = Instant::now();
+    let retriever = retrieval::ScannRetriever::try_new(dataset, distance_measure, k, util::NonFiniteHandling::default())?;
+    report.phases.push(("copy".to_string(), start.elapsed().as_secs_f64()));
+
+    if let Some(num_leaves) = plan.num_leaves {
+        let mut options = training_options.clone();
+        options.max_iterations = plan.kmeans_iterations.max(1) as i32;
+        let start = Instant::now();
+        report.partitioning = match retriever.build_partitions(num_leaves, &options)? {
+            tree::PartitioningOutcome::Built(stats) => PartitioningRecord::Built {
+                num_leaves,
+                initial_objective: stats.initial_objective,
+                final_objective: stats.final_objective,
+                iterations: stats.iterations,
+            },
+            tree::PartitioningOutcome::Skipped { reason } => PartitioningRecord::Skipped { reason },
+        };
+        report.phases.push(("kmeans".to_string(), start.elapsed().as_secs_f64()));
+    }
+
+    if plan.int8_codes {
+        let start = Instant::now();
+        retriever.register_derived_data(Box::new(retrieval::Int8Codes::new(
+            quantization::Int8QuantizationConfig::new(),
+        )))?;
+        report.phases.push(("quantize".to_string(), start.elapsed().as_secs_f64()));
+        report.quantization = Some(retriever.int8_error_summary()?);
+    }
+
+    if plan.norm_cache {
+        let start = Instant::now();
+        retriever.register_derived_data(Box::new(retrieval::NormCache::default()))?;
+        report.phases.push(("norms".to_string(), start.elapsed().as_secs_f64()));
+    }
+    Ok((retriever, report))
+}
+
+impl BuildReport {
+    // A report for a build of `dataset` by this library, with no phases
+    // recorded yet.
+    pub fn new(dataset: DatasetSummary) -> Self {
+        BuildReport {
+            library_version: env!("CARGO_PKG_VERSION").to_string(),
+            deterministic: cfg!(not(feature = "rayon")),
+            dataset,
+            phases: Vec::new(),
+            partitioning: PartitioningRecord::NotRequested,
+            quantization: None,
+            norm_cache: false,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("BuildReport always serializes") + "\n"
+    }
+
+    // `origin` names the source in error messages. Non-finite objectives were
+    // written as null and read back as NaN.
+    #[cfg(feature = "serde")]
+    pub fn from_json(text: &str, origin: &str) -> Result<Self, Box<dyn Error>> {
+        serde_json::from_str(text)
+            .map_err(|e| util::invalid_argument_error(&format!("{} is not a build report: {}", origin, e)))
+    }
+}
+
+// Human-readable rendering for operators.
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Built with scann {}{}",
+            self.library_version,
+            if self.deterministic { " (deterministic)" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "Dataset: {} points x {} dims, norms {:.4} / {:.4} / {:.4} (min / mean / max)",
+            self.dataset.num_points,
+            self.dataset.dimensionality,
+            self.dataset.min_norm,
+            self.dataset.mean_norm,
+            self.dataset.max_norm
+        )?;
+        for (name, seconds) in &self.phases {
+            writeln!(f, "  {:<10} {:>10.3}s", name, seconds)?;
+        }
+        match &self.partitioning {
+            PartitioningRecord::NotRequested => writeln!(f, "Partitioning: not requested")?,
+            PartitioningRecord::Built {
+                num_leaves,
+                initial_objective,
+                final_objective,
+                iterations,
+            } => writeln!(
+                f,
+                "Partitioning: {} leaves, objective {:.4} -> {:.4} in {} iterations",
+                num_leaves, initial_objective, final_objective, iterations
+            )?,
+            PartitioningRecord::Skipped { reason } => writeln!(f, "Partitioning: skipped ({})", reason)?,
+        }
+        if let Some(q) = &self.quantization {
+            writeln!(
+                f,
+                "Int8 quantization error: mean {:.6}, max {:.6}",
+                q.mean_abs_error, q.max_abs_error
+            )?;
+        }
+        write!(f, "Norm cache: {}", if self.norm_cache { "yes" } else { "no" })
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn save_build_report<P: AsRef<std::path::Path>>(dir: P, report: &BuildReport) -> Result<(), Box<dyn Error>> {
+    let path = dir.as_ref().join(BUILD_REPORT_NAME);
+    std::fs::write(&path, report.to_json()).map_err(|e| {
+        Box::new(super::ScannError {
+            message: format!("Failed to write {}: {}", path.display(), e),
+        }) as Box<dyn Error>
+    })
+}
+
+#[cfg(feature = "serde")]
+pub fn load_build_report<P: AsRef<std::path::Path>>(dir: P) -> Result<BuildReport, Box<dyn Error>> {
+    let path = dir.as_ref().join(BUILD_REPORT_NAME);
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        Box::new(super::ScannError {
+            message: format!("Failed to read {}: {}", path.display(), e),
+        }) as Box<dyn Error>
+    })?;
+    BuildReport::from_json(&text, &path.display().to_string())
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What this binary was compiled with, for compatibility checks.
+//!
+//! Writers record the build in artifacts manifests and blobs together with
+//! the cargo features a reader needs. Loaders compare those requirements
+//! against `build_info()` and name the missing feature instead of failing
+//! later with a parse error.
+
+use super::{blob, retro, util};
+use std::error::Error;
+
+// Every cargo feature of this crate, in manifest order.
+pub const KNOWN_FEATURES: &[&str] = &["f16", "proto-compat", "rayon", "serde", "simd", "torch", "zstd"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+    pub blob_versions: Vec<u32>,
+    pub chunk_store_versions: Vec<u32>,
+}
+
+impl BuildInfo {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.contains(&feature)
+    }
+
+    // "key: value" lines as recorded in manifests.
+    pub fn to_text(&self) -> String {
+        format!("scann_version: {}\nbuilt_with_features: {}\n", self.version, self.features.join(","))
+    }
+}
+
+pub fn build_info() -> BuildInfo {
+    let mut features = Vec::new();
+    if cfg!(feature = "f16") {
+        features.push("f16");
+    }
+    if cfg!(feature = "proto-compat") {
+        features.push("proto-compat");
+    }
+    if cfg!(feature = "rayon") {
+        features.push("rayon");
+    }
+    if cfg!(feature = "serde") {
+        features.push("serde");
+    }
+    if cfg!(feature = "simd") {
+        features.push("simd");
+    }
+    if cfg!(feature = "torch") {
+        features.push("torch");
+    }
+    if cfg!(feature = "zstd") {
+        features.push("zstd");
+    }
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features,
+        blob_versions: blob::SUPPORTED_BLOB_VERSIONS.to_vec(),
+        chunk_store_versions: vec![retro::chunk_store::CHUNK_STORE_VERSION],
+    }
+}
+
+fn feature_purpose(feature: &str) -> &'static str {
+    match feature {
+        "zstd" => "zstd-compressed sections",
+        "f16" => "half-precision sections",
+        "proto-compat" => "binary protobuf assets",
+        "torch" => "PyTorch weights",
+        "simd" => "SIMD kernels",
+        "rayon" => "parallel execution",
+        "serde" => "serde support",
+        _ => "an unknown capability",
+    }
+}
+
+// Parses a comma-separated feature list as written by manifests.
+pub fn parse_feature_list(text: &str) -> Vec<String> {
+    text.split(',').map(str::trim).filter(|f| !f.is_empty()).map(str::to_string).collect()
+}
+
+// Fails with an actionable message naming the first feature `what` needs
+// that this binary lacks.
+pub fn check_required_features<S: AsRef<str>>(required: &[S], what: &str) -> Result<(), Box<dyn Error>> {
+    let info = build_info();
+    for feature in required.iter().map(|f| f.as_ref()) {
+        if info.has_feature(feature) {
+            continue;
+        }
+        let purpose = feature_purpose(feature);
+        if !KNOWN_FEATURES.contains(&feature) {
+            return Err(util::failed_precondition_error(&format!(
+                "{} requires capability '{}', which scann {} does not know; upgrade scann or re-export it with this version",
+                what, feature, info.version
+            )));
+        }
+        return Err(util::failed_precondition_error(&format!(
+            "{} contains {} but this binary was built without the '{}' feature; rebuild scann with `--features {}` or re-export it without {}",
+            what, purpose, feature, feature, purpose
+        )));
+    }
+    Ok(())
+}
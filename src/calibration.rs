@@ -0,0 +1,167 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Platt scaling of raw distances into match probabilities.
+
+use super::{binfmt, retrieval, util, ScannError};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const CALIBRATOR_WIRE_VERSION: u8 = 1;
+
+// Maps a distance d to 1 / (1 + exp(a * d + b)). Fitted curves have a > 0,
+// so smaller distances give higher scores.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibrator {
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Calibrator {
+    pub fn apply(&self, distance: f32) -> f32 {
+        (1.0 / (1.0 + (self.a * distance as f64 + self.b).exp())) as f32
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        fs::write(path, format!("a: {:e}\nb: {:e}\n", self.a, self.b)).map_err(|e| {
+            Box::new(ScannError {
+                message: format!("Failed to write calibrator {}: {}", path.display(), e),
+            }) as Box<dyn Error>
+        })
+    }
+
+    // Wire encoding (binfmt::write_wire framing), version 1 payload:
+    //   a f64 | b f64
+    // stored as IEEE bits, so a round trip is exact.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = binfmt::ByteOrder(binfmt::Endianness::Little);
+        let mut payload = Vec::with_capacity(16);
+        order.put_u64(&mut payload, self.a.to_bits());
+        order.put_u64(&mut payload, self.b.to_bits());
+        binfmt::write_wire(binfmt::WIRE_KIND_CALIBRATOR, CALIBRATOR_WIRE_VERSION, &payload)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let name = "Calibrator";
+        let (_, payload) = binfmt::read_wire(bytes, binfmt::WIRE_KIND_CALIBRATOR, CALIBRATOR_WIRE_VERSION, name)?;
+        let mut reader = binfmt::WireReader::new(payload, name);
+        let a = reader.f64()?;
+        let b = reader.f64()?;
+        reader.finish()?;
+        if !a.is_finite() || !b.is_finite() {
+            return Err(util::invalid_argument_error(&format!(
+                "Calibrator parameters must be finite, got a = {}, b = {}",
+                a, b
+            )));
+        }
+        Ok(Calibrator { a, b })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ScannError {
+            message: format!("Failed to read calibrator {}: {}", path.display(), e),
+        })?;
+        let mut a = None;
+        let mut b = None;
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value: f64 = value.trim().parse().map_err(|_| ScannError {
+                message: format!("Invalid calibrator value in {}: '{}'", path.display(), line),
+            })?;
+            match key.trim() {
+                "a" => a = Some(value),
+                "b" => b = Some(value),
+                _ => {}
+            }
+        }
+        match (a, b) {
+            (Some(a), Some(b)) => Ok(Calibrator { a, b }),
+            _ => Err(util::invalid_argument_error(&format!(
+                "Calibrator file {} must define both a and b",
+                path.display()
+            ))),
+        }
+    }
+}
+
+// Fits the logistic curve by Newton's method (IRLS) on labeled distances,
+// using Platt's smoothed targets to avoid divergence on separable data.
+pub fn fit_logistic(distances: &[f32], labels: &[bool]) -> Result<Calibrator, Box<dyn Error>> {
+    if distances.len() != labels.len() || distances.is_empty() {
+        return Err(util::invalid_argument_error(
+            "Calibration needs the same, nonzero number of distances and labels",
+        ));
+    }
+    let num_pos = labels.iter().filter(|&&l| l).count() as f64;
+    let num_neg = labels.len() as f64 - num_pos;
+    if num_pos == 0.0 || num_neg == 0.0 {
+        return Err(util::invalid_argument_error(
+            "Calibration needs both positive and negative examples",
+        ));
+    }
+    let hi = (num_pos + 1.0) / (num_pos + 2.0);
+    let lo = 1.0 / (num_neg + 2.0);
+
+    // Parameterized as p = sigmoid(-(a * d + b)).
+    let mut a = 0.0f64;
+    let mut b = ((num_neg + 1.0) / (num_pos + 1.0)).ln();
+    for _ in 0..100 {
+        let (mut g_a, mut g_b) = (0.0, 0.0);
+        let (mut h_aa, mut h_ab, mut h_bb) = (1e-12, 0.0, 1e-12);
+        for (&d, &label) in distances.iter().zip(labels.iter()) {
+            let d = d as f64;
+            let t = if label { hi } else { lo };
+            let p = 1.0 / (1.0 + (a * d + b).exp());
+            let w = p * (1.0 - p);
+            // d(log-likelihood)/d(a*d+b) = p - t
+            g_a += (p - t) * d;
+            g_b += p - t;
+            h_aa += w * d * d;
+            h_ab += w * d;
+            h_bb += w;
+        }
+        let det = h_aa * h_bb - h_ab * h_ab;
+        if det.abs() < 1e-18 {
+            break;
+        }
+        let step_a = (h_bb * g_a - h_ab * g_b) / det;
+        let step_b = (h_aa * g_b - h_ab * g_a) / det;
+        a += step_a;
+        b += step_b;
+        if step_a.abs() < 1e-10 && step_b.abs() < 1e-10 {
+            break;
+        }
+    }
+    Ok(Calibrator { a, b })
+}
+
+pub fn fit_platt(
+    retriever: &retrieval::ScannRetriever,
+    labeled_pairs: &[(util::DatapointPtr<f32>, usize, usize)],
+) -> Result<Calibrator, Box<dyn Error>> {
+    let mut distances = Vec::with_capacity(labeled_pairs.len() * 2);
+    let mut labels = Vec::with_capacity(labeled_pairs.len() * 2);
+    for (query, positive, negative) in labeled_pairs {
+        distances.push(retriever.distance_to_docid(query, *positive)?);
+        labels.push(true);
+        distances.push(retriever.distance_to_docid(query, *negative)?);
+        labels.push(false);
+    }
+    fit_logistic(&distances, &labels)
+}
@@ -0,0 +1,184 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checked conversions between dataset element types.
+//!
+//! Promotion policy when a query and an index disagree on element type:
+//!   * float vs float: the query is converted to the index type (f64 queries
+//!     against an f32 index are narrowed with non-finite detection),
+//!   * float query vs integer-only index: the query is quantized with the
+//!     index's multipliers,
+//!   * integer query vs float index: the query is widened losslessly.
+
+use super::{quantization, util};
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DType {
+    F64,
+    F32,
+    I8,
+    U8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Promotion {
+    // Convert the query to the index element type.
+    ConvertQuery(DType),
+    // Quantize the float query with the index multipliers.
+    QuantizeQuery,
+    Identity,
+}
+
+pub fn promotion_for(query: DType, index: DType) -> Promotion {
+    match (query, index) {
+        _ if query == index => Promotion::Identity,
+        (DType::F64 | DType::F32, DType::I8) => Promotion::QuantizeQuery,
+        (_, index) => Promotion::ConvertQuery(index),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConversionStats {
+    pub max_abs_error: f64,
+    pub num_clipped: usize,
+}
+
+fn non_finite_error(row: Option<usize>, dim: usize, value: f64) -> Box<dyn Error> {
+    match row {
+        Some(row) => util::invalid_argument_error(&format!(
+            "Non-finite value {} at row {}, dimension {}",
+            value, row, dim
+        )),
+        None => util::invalid_argument_error(&format!("Non-finite value {} at dimension {}", value, dim)),
+    }
+}
+
+fn f64_row_to_f32(
+    row: &[f64],
+    row_index: Option<usize>,
+    stats: &mut ConversionStats,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut out = Vec::with_capacity(row.len());
+    for (dim, &v) in row.iter().enumerate() {
+        let narrowed = v as f32;
+        if !v.is_finite() || !narrowed.is_finite() {
+            return Err(non_finite_error(row_index, dim, v));
+        }
+        stats.max_abs_error = stats.max_abs_error.max((v - narrowed as f64).abs());
+        out.push(narrowed);
+    }
+    Ok(out)
+}
+
+fn f32_row_to_i8(row: &[f32], multipliers: &[f32], stats: &mut ConversionStats) -> Vec<i8> {
+    row.iter()
+        .zip(multipliers.iter())
+        .map(|(&v, &m)| {
+            let scaled = (v * m).round();
+            if scaled.abs() > 127.0 {
+                stats.num_clipped += 1;
+            }
+            let code = scaled.clamp(-127.0, 127.0);
+            let restored = if m == 0.0 { 0.0 } else { code / m };
+            stats.max_abs_error = stats.max_abs_error.max((v - restored).abs() as f64);
+            code as i8
+        })
+        .collect()
+}
+
+pub fn f64_to_f32_dataset(
+    data: &util::DenseDataset<f64>,
+) -> Result<(util::DenseDataset<f32>, ConversionStats), Box<dyn Error>> {
+    let mut stats = ConversionStats::default();
+    let rows = data
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, row)| f64_row_to_f32(row, Some(i), &mut stats))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((util::DenseDataset::new(rows, data.dimensionality()), stats))
+}
+
+pub fn f64_to_f32_datapoint(
+    query: &util::DatapointPtr<f64>,
+) -> Result<(util::DatapointPtr<f32>, ConversionStats), Box<dyn Error>> {
+    let mut stats = ConversionStats::default();
+    let values = f64_row_to_f32(query.values(), None, &mut stats)?;
+    Ok((util::DatapointPtr::new(values), stats))
+}
+
+// Uses the given per-dimension multipliers, or computes them from the
+// absolute max of each column when None.
+pub fn f32_to_i8_dataset(
+    data: &util::DenseDataset<f32>,
+    multipliers: Option<&[f32]>,
+) -> Result<(quantization::Int8QuantizedDataset, ConversionStats), Box<dyn Error>> {
+    let multipliers = match multipliers {
+        Some(m) => {
+            if m.len() != data.dimensionality() {
+                return Err(util::invalid_argument_error(&format!(
+                    "Got {} multipliers for dimensionality {}",
+                    m.len(),
+                    data.dimensionality()
+                )));
+            }
+            m.to_vec()
+        }
+        None => quantization::quantize_int8(data, &quantization::Int8QuantizationConfig::new())?.multipliers,
+    };
+    let mut stats = ConversionStats::default();
+    let codes = data
+        .data
+        .iter()
+        .map(|row| f32_row_to_i8(row, &multipliers, &mut stats))
+        .collect();
+    Ok((
+        quantization::Int8QuantizedDataset {
+            codes: util::DenseDataset::new(codes, data.dimensionality()),
+            multipliers,
+            clip_quantile: None,
+        },
+        stats,
+    ))
+}
+
+pub fn f32_to_i8_datapoint(
+    query: &util::DatapointPtr<f32>,
+    multipliers: &[f32],
+) -> Result<(util::DatapointPtr<i8>, ConversionStats), Box<dyn Error>> {
+    if multipliers.len() != query.values().len() {
+        return Err(util::invalid_argument_error(&format!(
+            "Got {} multipliers for a query of dimensionality {}",
+            multipliers.len(),
+            query.values().len()
+        )));
+    }
+    let mut stats = ConversionStats::default();
+    let codes = f32_row_to_i8(query.values(), multipliers, &mut stats);
+    Ok((util::DatapointPtr::new(codes), stats))
+}
+
+pub fn u8_to_f32_dataset(data: &util::DenseDataset<u8>) -> util::DenseDataset<f32> {
+    let rows = data
+        .data
+        .iter()
+        .map(|row| row.iter().map(|&v| v as f32).collect())
+        .collect();
+    util::DenseDataset::new(rows, data.dimensionality())
+}
+
+pub fn u8_to_f32_datapoint(query: &util::DatapointPtr<u8>) -> util::DatapointPtr<f32> {
+    util::DatapointPtr::new(query.values().iter().map(|&v| v as f32).collect())
+}
@@ -14,41 +14,1870 @@
 
 //! Distance measure factory for ScaNN.
 
-use super::{proto, utils, ScannError};
+#[cfg(feature = "simd")]
+use super::simd;
+use super::{proto, util, ScannError};
+use nalgebra::{DMatrix, DVector};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use std::error::Error;
-use ngalgebra::{DVector, Vector};
 
+// Squared norms in, so callers can share the accumulation loop.
+fn cosine_from_parts(dot: f32, norm_a: f32, norm_b: f32) -> f32 {
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 1.0;
+    }
+    1.0 - (dot / (norm_a.sqrt() * norm_b.sqrt())).clamp(-1.0, 1.0)
+}
+
+#[derive(Default)]
 pub struct CosineDistance;
 
-impl CosineDistance {
+impl CosineDistance {
+    pub fn new() -> Self {
+        CosineDistance
+    }
+}
+
+impl DistanceMeasure for CosineDistance {
+    fn name(&self) -> &str {
+        "CosineDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        if let (Some(a), Some(b)) = (T::as_f32_slice(a.values()), T::as_f32_slice(b.values())) {
+            return self.compute_distance_f32(a, b);
+        }
+        let a_vec: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b_vec: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        let a = DVector::from_vec(a_vec);
+        let b = DVector::from_vec(b_vec);
+        let norm_a = a.norm();
+        let norm_b = b.norm();
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 1.0; // Max distance if either vector is zero
+        }
+        1.0 - (a.dot(&b) / (norm_a * norm_b)).clamp(-1.0, 1.0)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        #[cfg(feature = "simd")]
+        if let Some((dot, norm_a, norm_b)) = simd::cosine_parts(a, b) {
+            return cosine_from_parts(dot, norm_a, norm_b);
+        }
+        let mut dot = 0.0f32;
+        let mut norm_a = 0.0f32;
+        let mut norm_b = 0.0f32;
+        for (&x, &y) in a.iter().zip(b.iter()) {
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        cosine_from_parts(dot, norm_a, norm_b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        let mut dot = 0.0f64;
+        let mut norm_a = 0.0f64;
+        let mut norm_b = 0.0f64;
+        for (&x, &y) in a.iter().zip(b.iter()) {
+            let (x, y) = (x as f64, y as f64);
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 1.0;
+        }
+        (1.0 - (dot / (norm_a.sqrt() * norm_b.sqrt())).clamp(-1.0, 1.0)) as f32
+    }
+
+    fn compute_distance_mixed<Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+        &self,
+        query: &util::DatapointPtr<Q>,
+        db: &util::DatapointPtr<D>,
+        multipliers: Option<&[f32]>,
+    ) -> f32 {
+        mixed_cosine(query.values(), db.values(), multipliers)
+    }
+
+    fn compute_distance_mixed_i8(&self, query: &[f32], db: &[i8], multipliers: Option<&[f32]>, _: &mut Vec<f32>) -> f32 {
+        mixed_cosine(query, db, multipliers)
+    }
+
+    // The query norm is accumulated in the same order as in
+    // compute_distance_f32, so hoisting it keeps results bit-identical.
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        #[cfg(feature = "simd")]
+        if let Some(norm_a) = simd::dot(query, query) {
+            for (row, slot) in rows.iter().zip(out.iter_mut()) {
+                let dot = simd::dot(query, row).unwrap();
+                let norm_b = simd::dot(row, row).unwrap();
+                *slot = cosine_from_parts(dot, norm_a, norm_b);
+            }
+            return;
+        }
+        let mut norm_a = 0.0f32;
+        for &x in query {
+            norm_a += x * x;
+        }
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            let mut dot = 0.0f32;
+            let mut norm_b = 0.0f32;
+            for (&x, &y) in query.iter().zip(row.iter()) {
+                dot += x * y;
+                norm_b += y * y;
+            }
+            *slot = cosine_from_parts(dot, norm_a, norm_b);
+        }
+    }
+}
+
+
+pub trait DistanceMeasure: Send + Sync {
+    // Registry name accepted by get_distance_measure_by_name.
+    fn name(&self) -> &str;
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32
+    where
+        Self: Sized;
+
+    // Distance between datapoints of different scalar types, e.g. an f32
+    // query against int8 database rows. `multipliers`, when given, holds the
+    // per-dimension int8 quantization multipliers of `db` (value = code /
+    // multiplier, 0 for a zero multiplier) and is applied on the fly.
+    fn compute_distance_mixed<Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+        &self,
+        query: &util::DatapointPtr<Q>,
+        db: &util::DatapointPtr<D>,
+        multipliers: Option<&[f32]>,
+    ) -> f32
+    where
+        Self: Sized,
+    {
+        let query: Vec<f32> = query.values().iter().map(|&x| x.to_f32()).collect();
+        let db = dequantized(db.values(), multipliers);
+        self.compute_distance_f32(&query, &db)
+    }
+
+    // Object-safe form of compute_distance_mixed for int8 rows, used by the
+    // int8 scans behind a `dyn DistanceMeasure`. Measures with a fused mixed
+    // kernel share it with compute_distance_mixed; the rest dequantize into
+    // `scratch`, which callers reuse across rows, and score with
+    // compute_distance_f32.
+    fn compute_distance_mixed_i8(
+        &self,
+        query: &[f32],
+        db: &[i8],
+        multipliers: Option<&[f32]>,
+        scratch: &mut Vec<f32>,
+    ) -> f32 {
+        scratch.clear();
+        match multipliers {
+            Some(multipliers) => scratch.extend(db.iter().zip(multipliers).map(|(&v, &m)| dequantize(v, m))),
+            None => scratch.extend(db.iter().map(|&v| v as f32)),
+        }
+        self.compute_distance_f32(query, scratch)
+    }
+
+    // Slice kernel used on the search hot path, where wrapping every row in
+    // a DatapointPtr would allocate. The object-safe entry point: the
+    // generic methods above are only callable on concrete measures.
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32;
+
+    // Same distance with f64 accumulation. Measures without a dedicated
+    // path fall back to f32.
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        self.compute_distance_f32(a, b)
+    }
+
+    fn compute_distance_with_precision(&self, a: &[f32], b: &[f32], precision: AccumulatorPrecision) -> f32 {
+        match precision {
+            AccumulatorPrecision::F32 => self.compute_distance_f32(a, b),
+            AccumulatorPrecision::F64 => self.compute_distance_f64_accumulated(a, b),
+        }
+    }
+
+    // Distances from `query` to every row of `dataset`; `out` holds one slot
+    // per row. Bit-identical to compute_distance_f32 row by row: overrides
+    // only hoist per-query work out of the loop.
+    fn compute_one_to_many(&self, query: &util::DatapointPtr<f32>, dataset: &util::DenseDataset<f32>, out: &mut [f32]) {
+        self.compute_one_to_many_rows(query.values(), (&dataset.data).into(), out)
+    }
+
+    // Same over a contiguous block of rows, so callers can interleave
+    // blocks with cancellation checks.
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = self.compute_distance_f32(query, row);
+        }
+    }
+
+    // Some(distance) when the distance is at most `threshold`, else None.
+    // Measures whose partial sums only grow override this to stop as soon
+    // as the threshold is exceeded, e.g. against the current k-th best.
+    fn compute_distance_with_threshold(&self, a: &[f32], b: &[f32], threshold: f32) -> Option<f32> {
+        let distance = self.compute_distance_f32(a, b);
+        (distance <= threshold).then_some(distance)
+    }
+
+    // Declared by measures whose compute_distance_with_threshold stops
+    // early. Fused top-k scans then score row by row against the k-th best
+    // instead of through the one-to-many kernel.
+    fn exits_early_on_threshold(&self) -> bool {
+        false
+    }
+
+    // Errors unless the measure can score datapoints of dimensionality
+    // `dim`. Search entry points call it before scoring; measures with a
+    // fixed dimensionality (weights, a precision matrix, a composite
+    // layout) override it.
+    fn check_dimensionality(&self, _dim: usize) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn as_composite(&self) -> Option<&CompositeDistance> {
+        None
+    }
+
+    fn as_limited_inner_product(&self) -> Option<&LimitedInnerProductDistance> {
+        None
+    }
+
+    // Declared only by measures whose compute_distance_f32 is exactly the
+    // kernel's formula, so the specialized path is a drop-in replacement.
+    fn low_dim_kernel(&self) -> Option<LowDimKernel> {
+        None
+    }
+}
+
+// Distance from `query` to row `row` of `dataset` without copying the row,
+// for either dataset layout. Panics when `row` is out of range.
+pub fn compute_distance_to_row<M: DistanceMeasure + ?Sized>(
+    measure: &M,
+    query: &[f32],
+    dataset: &impl util::RowSource<f32>,
+    row: usize,
+) -> f32 {
+    measure.compute_distance_f32(query, dataset.row(row))
+}
+
+pub fn dequantized<D: util::ToF32Scalar>(values: &[D], multipliers: Option<&[f32]>) -> Vec<f32> {
+    match multipliers {
+        Some(multipliers) => values.iter().zip(multipliers.iter()).map(|(&v, &m)| dequantize(v, m)).collect(),
+        None => values.iter().map(|&v| v.to_f32()).collect(),
+    }
+}
+
+#[inline(always)]
+fn dequantize<D: util::ToF32Scalar>(value: D, multiplier: f32) -> f32 {
+    if multiplier == 0.0 {
+        0.0
+    } else {
+        value.to_f32() / multiplier
+    }
+}
+
+// Iterates (query, db) coordinate pairs as f32 with the db side dequantized.
+fn mixed_pairs<'a, Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+    query: &'a [Q],
+    db: &'a [D],
+    multipliers: Option<&'a [f32]>,
+) -> impl Iterator<Item = (f32, f32)> + 'a {
+    query.iter().zip(db.iter()).enumerate().map(move |(i, (&q, &d))| {
+        let d = match multipliers {
+            Some(multipliers) => dequantize(d, multipliers[i]),
+            None => d.to_f32(),
+        };
+        (q.to_f32(), d)
+    })
+}
+
+// Fused mixed-type kernels behind compute_distance_mixed and
+// compute_distance_mixed_i8.
+fn mixed_squared_l2<Q: util::ToF32Scalar, D: util::ToF32Scalar>(query: &[Q], db: &[D], multipliers: Option<&[f32]>) -> f32 {
+    mixed_pairs(query, db, multipliers).map(|(q, d)| (q - d) * (q - d)).sum()
+}
+
+fn mixed_neg_dot<Q: util::ToF32Scalar, D: util::ToF32Scalar>(query: &[Q], db: &[D], multipliers: Option<&[f32]>) -> f32 {
+    -mixed_pairs(query, db, multipliers).map(|(q, d)| q * d).sum::<f32>()
+}
+
+fn mixed_cosine<Q: util::ToF32Scalar, D: util::ToF32Scalar>(query: &[Q], db: &[D], multipliers: Option<&[f32]>) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (q, d) in mixed_pairs(query, db, multipliers) {
+        dot += q * d;
+        norm_a += q * q;
+        norm_b += d * d;
+    }
+    cosine_from_parts(dot, norm_a, norm_b)
+}
+
+// Accumulator type for the per-dimension sums of a distance. F64 costs
+// roughly 2x on the scoring loop (half the SIMD lanes plus conversions) but
+// keeps rankings stable for high-dimensional near ties.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccumulatorPrecision {
+    #[default]
+    F32,
+    F64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LowDimKernel {
+    DotProduct,
+    SquaredL2,
+}
+
+pub type SliceKernel = fn(&[f32], &[f32]) -> f32;
+
+// Kernels return the measure's distance, so the dot-product ones negate.
+#[inline(always)]
+fn neg_dot_fixed<const D: usize>(a: &[f32], b: &[f32]) -> f32 {
+    let a: &[f32; D] = a.try_into().unwrap();
+    let b: &[f32; D] = b.try_into().unwrap();
+    let mut sum = 0.0f32;
+    for i in 0..D {
+        sum += a[i] * b[i];
+    }
+    -sum
+}
+
+#[inline(always)]
+fn squared_l2_fixed<const D: usize>(a: &[f32], b: &[f32]) -> f32 {
+    let a: &[f32; D] = a.try_into().unwrap();
+    let b: &[f32; D] = b.try_into().unwrap();
+    let mut sum = 0.0f32;
+    for i in 0..D {
+        let d = a[i] - b[i];
+        sum += d * d;
+    }
+    sum
+}
+
+// Fixed-dimensionality kernels whose loops the compiler fully unrolls.
+// Returns None when `dim` has no specialization.
+pub fn select_low_dim_kernel(kernel: LowDimKernel, dim: usize) -> Option<SliceKernel> {
+    // The fixed kernels sum in scalar order. With SIMD kernels active every
+    // path uses those instead, so single and one-to-many distances agree.
+    #[cfg(feature = "simd")]
+    if simd::available() {
+        return None;
+    }
+    let selected: SliceKernel = match (kernel, dim) {
+        (LowDimKernel::DotProduct, 2) => neg_dot_fixed::<2>,
+        (LowDimKernel::DotProduct, 3) => neg_dot_fixed::<3>,
+        (LowDimKernel::DotProduct, 4) => neg_dot_fixed::<4>,
+        (LowDimKernel::DotProduct, 8) => neg_dot_fixed::<8>,
+        (LowDimKernel::SquaredL2, 2) => squared_l2_fixed::<2>,
+        (LowDimKernel::SquaredL2, 3) => squared_l2_fixed::<3>,
+        (LowDimKernel::SquaredL2, 4) => squared_l2_fixed::<4>,
+        (LowDimKernel::SquaredL2, 8) => squared_l2_fixed::<8>,
+        _ => return None,
+    };
+    Some(selected)
+}
+
+pub struct CompositePart {
+    pub dims: std::ops::Range<usize>,
+    pub measure: Box<dyn DistanceMeasure>,
+    pub weight: f32,
+}
+
+// Weighted sum of per-part distances over a concatenated datapoint, e.g. a
+// text embedding followed by an image embedding.
+pub struct CompositeDistance {
+    parts: Vec<CompositePart>,
+}
+
+impl CompositeDistance {
+    // Parts must tile [0, dimensionality) contiguously and in order.
+    pub fn new(parts: Vec<CompositePart>, dimensionality: usize) -> Result<Self, Box<dyn Error>> {
+        if parts.is_empty() {
+            return Err(util::invalid_argument_error("CompositeDistance needs at least one part"));
+        }
+        let mut expected_start = 0;
+        for (i, part) in parts.iter().enumerate() {
+            if part.dims.start != expected_start || part.dims.end <= part.dims.start {
+                return Err(util::invalid_argument_error(&format!(
+                    "Composite part {} covers {:?}, expected a non-empty range starting at {}",
+                    i, part.dims, expected_start
+                )));
+            }
+            expected_start = part.dims.end;
+        }
+        if expected_start != dimensionality {
+            return Err(util::invalid_argument_error(&format!(
+                "Composite parts cover {} dimensions but the dataset has {}",
+                expected_start, dimensionality
+            )));
+        }
+        Ok(CompositeDistance { parts })
+    }
+
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn layout(&self) -> Vec<std::ops::Range<usize>> {
+        self.parts.iter().map(|p| p.dims.clone()).collect()
+    }
+
+    pub fn compute_weighted(&self, a: &[f32], b: &[f32], weights: &[f32]) -> Result<f32, Box<dyn Error>> {
+        if weights.len() != self.parts.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "Got {} part weights for {} composite parts",
+                weights.len(),
+                self.parts.len()
+            )));
+        }
+        Ok(self.weighted_sum(a, b, weights.iter().copied(), AccumulatorPrecision::F32))
+    }
+
+    pub(crate) fn weighted_sum(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        weights: impl Iterator<Item = f32>,
+        precision: AccumulatorPrecision,
+    ) -> f32 {
+        self.parts
+            .iter()
+            .zip(weights)
+            .filter(|(_, w)| *w != 0.0)
+            .map(|(part, w)| {
+                w * part
+                    .measure
+                    .compute_distance_with_precision(&a[part.dims.clone()], &b[part.dims.clone()], precision)
+            })
+            .sum()
+    }
+}
+
+impl DistanceMeasure for CompositeDistance {
+    // Not constructible by name; parts must be rebuilt by the caller.
+    fn name(&self) -> &str {
+        "CompositeDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        self.weighted_sum(a, b, self.parts.iter().map(|p| p.weight), AccumulatorPrecision::F32)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        self.weighted_sum(a, b, self.parts.iter().map(|p| p.weight), AccumulatorPrecision::F64)
+    }
+
+    fn check_dimensionality(&self, dim: usize) -> Result<(), Box<dyn Error>> {
+        check_same_dimensionality(dim, self.parts.last().map_or(0, |part| part.dims.end))?;
+        for part in &self.parts {
+            part.measure.check_dimensionality(part.dims.len())?;
+        }
+        Ok(())
+    }
+
+    fn as_composite(&self) -> Option<&CompositeDistance> {
+        Some(self)
+    }
+}
+
+// Kernels assume both sides have the same dimensionality. Search entry
+// points check the query and DistanceMeasure::check_dimensionality before
+// scoring, so a mismatch here is a caller bug.
+#[inline(always)]
+fn debug_check_dimensionality(a: usize, b: usize) {
+    debug_assert_eq!(a, b, "Dimensionality mismatch");
+}
+
+#[inline(always)]
+fn debug_check_rows(query: &[f32], rows: util::RowBlock<'_, f32>) {
+    debug_assert!(rows.is_empty() || rows.row(0).len() == query.len(), "Dimensionality mismatch");
+}
+
+fn check_same_dimensionality(a: usize, b: usize) -> Result<(), Box<dyn Error>> {
+    if a != b {
+        return Err(Box::new(ScannError {
+            message: format!("Dimensionality mismatch: {} vs {}", a, b),
+        }));
+    }
+    Ok(())
+}
+
+fn squared_l2_f32(a: &[f32], b: &[f32]) -> f32 {
+    #[cfg(feature = "simd")]
+    if let Some(distance) = simd::squared_l2(a, b) {
+        return distance;
+    }
+    a.iter().zip(b.iter()).map(|(&x, &y)| (x - y) * (x - y)).sum()
+}
+
+fn squared_l2_f64(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&x, &y)| {
+            let d = x as f64 - y as f64;
+            d * d
+        })
+        .sum()
+}
+
+#[derive(Default)]
+pub struct SquaredL2Distance;
+
+impl SquaredL2Distance {
+    pub fn new() -> Self {
+        SquaredL2Distance
+    }
+
+    // Like compute_distance_f32, but rejects inputs of different lengths
+    // instead of comparing their common prefix.
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        Ok(squared_l2_f32(a, b))
+    }
+}
+
+impl DistanceMeasure for SquaredL2Distance {
+    fn name(&self) -> &str {
+        "SquaredL2Distance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        if let (Some(a), Some(b)) = (T::as_f32_slice(a.values()), T::as_f32_slice(b.values())) {
+            return self.compute_distance_f32(a, b);
+        }
+        a.values()
+            .iter()
+            .zip(b.values().iter())
+            .map(|(&x, &y)| {
+                let d = x.to_f32() - y.to_f32();
+                d * d
+            })
+            .sum()
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        squared_l2_f32(a, b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        squared_l2_f64(a, b) as f32
+    }
+
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        let kernel = select_low_dim_kernel(LowDimKernel::SquaredL2, query.len()).unwrap_or(squared_l2_f32);
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = kernel(query, row);
+        }
+    }
+
+    fn compute_distance_mixed<Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+        &self,
+        query: &util::DatapointPtr<Q>,
+        db: &util::DatapointPtr<D>,
+        multipliers: Option<&[f32]>,
+    ) -> f32 {
+        mixed_squared_l2(query.values(), db.values(), multipliers)
+    }
+
+    fn compute_distance_mixed_i8(&self, query: &[f32], db: &[i8], multipliers: Option<&[f32]>, _: &mut Vec<f32>) -> f32 {
+        mixed_squared_l2(query, db, multipliers)
+    }
+
+    fn low_dim_kernel(&self) -> Option<LowDimKernel> {
+        Some(LowDimKernel::SquaredL2)
+    }
+}
+
+#[derive(Default)]
+pub struct L2Distance;
+
+impl L2Distance {
+    pub fn new() -> Self {
+        L2Distance
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        Ok(squared_l2_f32(a, b).sqrt())
+    }
+}
+
+impl DistanceMeasure for L2Distance {
+    fn name(&self) -> &str {
+        "L2Distance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        SquaredL2Distance.compute_distance(a, b).sqrt()
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        squared_l2_f32(a, b).sqrt()
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        squared_l2_f64(a, b).sqrt() as f32
+    }
+}
+
+#[derive(Default)]
+pub struct L1Distance;
+
+impl L1Distance {
+    pub fn new() -> Self {
+        L1Distance
+    }
+
+    pub fn try_distance<T: util::ToF32Scalar>(
+        &self,
+        a: &util::DatapointPtr<T>,
+        b: &util::DatapointPtr<T>,
+    ) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.values().len(), b.values().len())?;
+        Ok(self.compute_distance(a, b))
+    }
+
+    // Distances from `query` to every row of `dataset`, written into `out`
+    // (cleared first) without wrapping rows in DatapointPtr.
+    pub fn one_to_many(
+        &self,
+        query: &[f32],
+        dataset: &util::DenseDataset<f32>,
+        out: &mut Vec<f32>,
+    ) -> Result<(), Box<dyn Error>> {
+        check_same_dimensionality(query.len(), dataset.dimensionality())?;
+        out.clear();
+        out.extend(dataset.data.iter().map(|row| self.compute_distance_f32(query, row)));
+        Ok(())
+    }
+}
+
+impl DistanceMeasure for L1Distance {
+    fn name(&self) -> &str {
+        "L1Distance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        a.values()
+            .iter()
+            .zip(b.values().iter())
+            .map(|(&x, &y)| (x.to_f32() - y.to_f32()).abs())
+            .sum()
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        a.iter().zip(b.iter()).map(|(&x, &y)| (x - y).abs()).sum()
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        a.iter().zip(b.iter()).map(|(&x, &y)| (x as f64 - y as f64).abs()).sum::<f64>() as f32
+    }
+}
+
+// max_i |a_i - b_i|, the L-infinity distance: the worst single coordinate
+// deviation rather than an average over coordinates.
+#[derive(Default)]
+pub struct ChebyshevDistance;
+
+impl ChebyshevDistance {
+    pub fn new() -> Self {
+        ChebyshevDistance
+    }
+}
+
+impl DistanceMeasure for ChebyshevDistance {
+    fn name(&self) -> &str {
+        "ChebyshevDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        a.values()
+            .iter()
+            .zip(b.values().iter())
+            .map(|(&x, &y)| (x.to_f32() - y.to_f32()).abs())
+            .fold(0.0, f32::max)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        a.iter().zip(b.iter()).map(|(&x, &y)| (x - y).abs()).fold(0.0, f32::max)
+    }
+
+    // Exits at the first coordinate whose difference exceeds `threshold`.
+    fn compute_distance_with_threshold(&self, a: &[f32], b: &[f32], threshold: f32) -> Option<f32> {
+        let mut max = 0.0f32;
+        for (&x, &y) in a.iter().zip(b.iter()) {
+            let d = (x - y).abs();
+            if d > threshold {
+                return None;
+            }
+            max = max.max(d);
+        }
+        Some(max)
+    }
+
+    fn exits_early_on_threshold(&self) -> bool {
+        true
+    }
+}
+
+// Maps a CosineDistance value (1 - cos) to the angle in radians. Monotone
+// non-decreasing, so rankings match CosineDistance.
+fn angle_from_cosine_distance(distance: f32) -> f32 {
+    (1.0 - distance as f64).clamp(-1.0, 1.0).acos() as f32
+}
+
+// acos of the clamped cosine similarity, in radians in [0, pi]. Computed
+// through CosineDistance's kernels, including its hoisted query norm, so
+// the same top-k is returned. A zero vector on either side gives pi / 2.
+#[derive(Default)]
+pub struct AngularDistance;
+
+impl AngularDistance {
+    pub fn new() -> Self {
+        AngularDistance
+    }
+}
+
+impl DistanceMeasure for AngularDistance {
+    fn name(&self) -> &str {
+        "AngularDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        angle_from_cosine_distance(CosineDistance.compute_distance(a, b))
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        angle_from_cosine_distance(CosineDistance.compute_distance_f32(a, b))
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        angle_from_cosine_distance(CosineDistance.compute_distance_f64_accumulated(a, b))
+    }
+
+    fn compute_distance_mixed<Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+        &self,
+        query: &util::DatapointPtr<Q>,
+        db: &util::DatapointPtr<D>,
+        multipliers: Option<&[f32]>,
+    ) -> f32 {
+        angle_from_cosine_distance(CosineDistance.compute_distance_mixed(query, db, multipliers))
+    }
+
+    fn compute_distance_mixed_i8(&self, query: &[f32], db: &[i8], multipliers: Option<&[f32]>, _: &mut Vec<f32>) -> f32 {
+        angle_from_cosine_distance(mixed_cosine(query, db, multipliers))
+    }
+
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        CosineDistance.compute_one_to_many_rows(query, rows, out);
+        for slot in out.iter_mut().take(rows.len()) {
+            *slot = angle_from_cosine_distance(*slot);
+        }
+    }
+}
+
+// Number of coordinates whose values differ.
+#[derive(Default)]
+pub struct GeneralHammingDistance;
+
+impl GeneralHammingDistance {
+    pub fn new() -> Self {
+        GeneralHammingDistance
+    }
+}
+
+impl DistanceMeasure for GeneralHammingDistance {
+    fn name(&self) -> &str {
+        "GeneralHammingDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        a.values()
+            .iter()
+            .zip(b.values().iter())
+            .filter(|(&x, &y)| x.to_f32() != y.to_f32())
+            .count() as f32
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() as f32
+    }
+}
+
+pub fn packed_len(dimensionality_bits: usize) -> usize {
+    dimensionality_bits.div_ceil(8)
+}
+
+fn packed_hamming_unchecked(
+    a: impl Iterator<Item = u8>,
+    b: impl Iterator<Item = u8>,
+    dimensionality_bits: Option<usize>,
+) -> u32 {
+    let mut count = 0;
+    for (i, (x, y)) in a.zip(b).enumerate() {
+        let mut diff = x ^ y;
+        if let Some(bits) = dimensionality_bits {
+            let used = bits.saturating_sub(i * 8);
+            if used == 0 {
+                break;
+            }
+            if used < 8 {
+                // Bits are packed LSB first, so padding is in the high bits.
+                diff &= (1u8 << used) - 1;
+            }
+        }
+        count += diff.count_ones();
+    }
+    count
+}
+
+// Hamming distance between bit vectors packed 8 dimensions per byte, LSB
+// first. Pad bits past `dimensionality_bits` in the last byte are ignored.
+pub fn packed_hamming(a: &[u8], b: &[u8], dimensionality_bits: usize) -> Result<u32, Box<dyn Error>> {
+    let expected = packed_len(dimensionality_bits);
+    if a.len() != expected || b.len() != expected {
+        return Err(Box::new(ScannError {
+            message: format!(
+                "Packed vectors of {} bits need {} bytes, got {} and {}",
+                dimensionality_bits,
+                expected,
+                a.len(),
+                b.len()
+            ),
+        }));
+    }
+    Ok(packed_hamming_unchecked(a.iter().copied(), b.iter().copied(), Some(dimensionality_bits)))
+}
+
+// Hamming distance over packed bits. Values are bytes; the f32 path takes
+// byte values widened to f32, as produced by convert::u8_to_f32_dataset.
+#[derive(Default)]
+pub struct BinaryHammingDistance {
+    dimensionality_bits: Option<usize>,
+}
+
+impl BinaryHammingDistance {
+    // Every bit of every byte counts.
+    pub fn new() -> Self {
+        BinaryHammingDistance { dimensionality_bits: None }
+    }
+
+    pub fn with_dimensionality(dimensionality_bits: usize) -> Self {
+        BinaryHammingDistance {
+            dimensionality_bits: Some(dimensionality_bits),
+        }
+    }
+
+    pub fn dimensionality_bits(&self) -> Option<usize> {
+        self.dimensionality_bits
+    }
+}
+
+impl DistanceMeasure for BinaryHammingDistance {
+    fn name(&self) -> &str {
+        "BinaryHammingDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        packed_hamming_unchecked(
+            a.values().iter().map(|&x| x.to_f32() as u8),
+            b.values().iter().map(|&y| y.to_f32() as u8),
+            self.dimensionality_bits,
+        ) as f32
+    }
+
+    // Rows hold one byte per 8 bits when the bit count is known.
+    fn check_dimensionality(&self, dim: usize) -> Result<(), Box<dyn Error>> {
+        match self.dimensionality_bits {
+            Some(bits) => check_same_dimensionality(dim, packed_len(bits)),
+            None => Ok(()),
+        }
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        packed_hamming_unchecked(
+            a.iter().map(|&x| x as u8),
+            b.iter().map(|&y| y as u8),
+            self.dimensionality_bits,
+        ) as f32
+    }
+}
+
+// Two empty sets (all-zero vectors) are identical, so both Jaccard
+// distances are 0 there instead of 0/0.
+fn jaccard_from_parts(intersection: f64, union: f64) -> f32 {
+    if union <= 0.0 {
+        return 0.0;
+    }
+    (1.0 - intersection / union) as f32
+}
+
+// 1 - sum(min(a_i, b_i)) / sum(max(a_i, b_i)), defined for nonnegative values.
+#[derive(Default)]
+pub struct GeneralJaccardDistance;
+
+impl GeneralJaccardDistance {
+    pub fn new() -> Self {
+        GeneralJaccardDistance
+    }
+
+    fn from_values(pairs: impl Iterator<Item = (f32, f32)>) -> f32 {
+        let (mut intersection, mut union) = (0.0f64, 0.0f64);
+        for (x, y) in pairs {
+            intersection += x.min(y) as f64;
+            union += x.max(y) as f64;
+        }
+        jaccard_from_parts(intersection, union)
+    }
+}
+
+impl DistanceMeasure for GeneralJaccardDistance {
+    fn name(&self) -> &str {
+        "GeneralJaccardDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        Self::from_values(a.values().iter().zip(b.values().iter()).map(|(&x, &y)| (x.to_f32(), y.to_f32())))
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        Self::from_values(a.iter().copied().zip(b.iter().copied()))
+    }
+}
+
+// 1 - |a & b| / |a | b| where every nonzero coordinate is a set member.
+#[derive(Default)]
+pub struct BinaryJaccardDistance;
+
+impl BinaryJaccardDistance {
+    pub fn new() -> Self {
+        BinaryJaccardDistance
+    }
+
+    fn from_values(pairs: impl Iterator<Item = (f32, f32)>) -> f32 {
+        let (mut intersection, mut union) = (0usize, 0usize);
+        for (x, y) in pairs {
+            let (in_a, in_b) = (x != 0.0, y != 0.0);
+            intersection += (in_a && in_b) as usize;
+            union += (in_a || in_b) as usize;
+        }
+        jaccard_from_parts(intersection as f64, union as f64)
+    }
+}
+
+impl DistanceMeasure for BinaryJaccardDistance {
+    fn name(&self) -> &str {
+        "BinaryJaccardDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        Self::from_values(a.values().iter().zip(b.values().iter()).map(|(&x, &y)| (x.to_f32(), y.to_f32())))
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        Self::from_values(a.iter().copied().zip(b.iter().copied()))
+    }
+}
+
+// ScaNN's limited inner product: -dot(q, x) / (|q| * max(|q|, |x|)). Rows
+// no longer than the query score like plain MIPS scaled by |q|^2; longer
+// rows are capped, so a handful of huge-norm rows cannot win every query.
+// The |q| factor is the same for all rows of one query and does not change
+// the ranking. When either vector is zero the product is 0 anyway, so the
+// distance is defined as 0 rather than 0/0.
+#[derive(Default)]
+pub struct LimitedInnerProductDistance;
+
+impl LimitedInnerProductDistance {
+    pub fn new() -> Self {
+        LimitedInnerProductDistance
+    }
+
+    // Takes L2 norms computed elsewhere, e.g. the query norm once per query
+    // and row norms from the retriever's NormCache.
+    pub fn distance_with_norms(&self, query: &[f32], query_norm: f32, row: &[f32], row_norm: f32) -> f32 {
+        let dot: f64 = query.iter().zip(row.iter()).map(|(&x, &y)| x as f64 * y as f64).sum();
+        Self::from_parts(dot, query_norm as f64, row_norm as f64)
+    }
+
+    // One-to-many against rows whose norms are already known.
+    pub fn one_to_many_with_norms(
+        &self,
+        query: &[f32],
+        dataset: &util::DenseDataset<f32>,
+        row_norms: &[f32],
+        out: &mut Vec<f32>,
+    ) -> Result<(), Box<dyn Error>> {
+        check_same_dimensionality(query.len(), dataset.dimensionality())?;
+        if row_norms.len() != dataset.data.len() {
+            return Err(Box::new(ScannError {
+                message: format!("Got {} row norms for {} rows", row_norms.len(), dataset.data.len()),
+            }));
+        }
+        let query_norm = l2_norm(query);
+        out.clear();
+        out.extend(
+            dataset
+                .data
+                .iter()
+                .zip(row_norms.iter())
+                .map(|(row, &norm)| self.distance_with_norms(query, query_norm, row, norm)),
+        );
+        Ok(())
+    }
+
+    fn from_parts(dot: f64, query_norm: f64, row_norm: f64) -> f32 {
+        let denominator = query_norm * query_norm.max(row_norm);
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        (-dot / denominator) as f32
+    }
+}
+
+fn l2_norm_f64(values: &[f32]) -> f64 {
+    values.iter().map(|&v| v as f64 * v as f64).sum::<f64>().sqrt()
+}
+
+// L2 norm accumulated in f64 and rounded to f32: the norm every
+// LimitedInnerProductDistance path uses, so cached row norms and a query
+// norm computed once per query score exactly like compute_distance_f32.
+pub fn l2_norm(values: &[f32]) -> f32 {
+    l2_norm_f64(values) as f32
+}
+
+impl DistanceMeasure for LimitedInnerProductDistance {
+    fn name(&self) -> &str {
+        "LimitedInnerProductDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        self.distance_with_norms(a, l2_norm(a), b, l2_norm(b))
+    }
+
+    fn as_limited_inner_product(&self) -> Option<&LimitedInnerProductDistance> {
+        Some(self)
+    }
+}
+
+// sum(w_i * (a_i - b_i)^2) with fixed non-negative per-dimension weights,
+// for features known to be noisier than others.
+pub struct WeightedSquaredL2Distance {
+    weights: Vec<f32>,
+}
+
+impl WeightedSquaredL2Distance {
+    pub fn new(weights: Vec<f32>) -> Result<Self, Box<dyn Error>> {
+        if weights.is_empty() {
+            return Err(util::invalid_argument_error("WeightedSquaredL2Distance requires at least one weight"));
+        }
+        if let Some(i) = weights.iter().position(|w| !w.is_finite() || *w < 0.0) {
+            return Err(util::invalid_argument_error(&format!(
+                "WeightedSquaredL2Distance weight {} is {}, expected finite and non-negative",
+                i, weights[i]
+            )));
+        }
+        Ok(WeightedSquaredL2Distance { weights })
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        self.check_dimensionality(a.len())?;
+        Ok(self.weighted_sum(a, b))
+    }
+
+    fn weighted_sum(&self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .zip(self.weights.iter())
+            .map(|((&x, &y), &w)| w * (x - y) * (x - y))
+            .sum()
+    }
+}
+
+impl DistanceMeasure for WeightedSquaredL2Distance {
+    fn name(&self) -> &str {
+        "WeightedSquaredL2Distance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    // Errors unless datapoints of dimensionality `dim` match the weights.
+    fn check_dimensionality(&self, dim: usize) -> Result<(), Box<dyn Error>> {
+        check_same_dimensionality(dim, self.weights.len())
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        debug_check_dimensionality(a.len(), self.weights.len());
+        self.weighted_sum(a, b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        debug_check_dimensionality(a.len(), self.weights.len());
+        let sum: f64 = a
+            .iter()
+            .zip(b.iter())
+            .zip(self.weights.iter())
+            .map(|((&x, &y), &w)| {
+                let d = x as f64 - y as f64;
+                w as f64 * d * d
+            })
+            .sum();
+        sum as f32
+    }
+
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        debug_check_dimensionality(query.len(), self.weights.len());
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = self.weighted_sum(query, row);
+        }
+    }
+}
+
+// (sum |a_i - b_i|^p)^(1/p) for a configured p > 0. p = 1 and p = 2 use
+// the L1 and L2 kernels directly.
+pub struct MinkowskiDistance {
+    p: f32,
+}
+
+impl MinkowskiDistance {
+    pub fn new(p: f32) -> Result<Self, Box<dyn Error>> {
+        if !p.is_finite() || p <= 0.0 {
+            return Err(util::invalid_argument_error(&format!(
+                "MinkowskiDistance requires a finite p > 0, got {}",
+                p
+            )));
+        }
+        Ok(MinkowskiDistance { p })
+    }
+
+    pub fn p(&self) -> f32 {
+        self.p
+    }
+}
+
+impl DistanceMeasure for MinkowskiDistance {
+    fn name(&self) -> &str {
+        "MinkowskiDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        if self.p == 1.0 {
+            return L1Distance.compute_distance_f32(a, b);
+        }
+        if self.p == 2.0 {
+            return squared_l2_f32(a, b).sqrt();
+        }
+        let sum: f32 = a.iter().zip(b.iter()).map(|(&x, &y)| (x - y).abs().powf(self.p)).sum();
+        sum.powf(1.0 / self.p)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        let p = self.p as f64;
+        let sum: f64 = a.iter().zip(b.iter()).map(|(&x, &y)| (x as f64 - y as f64).abs().powf(p)).sum();
+        sum.powf(1.0 / p) as f32
+    }
+}
+
+// (a - b)^T P (a - b) for a fixed square precision matrix P.
+pub struct MahalanobisDistance {
+    precision: DMatrix<f32>,
+}
+
+impl MahalanobisDistance {
+    pub fn new(precision: DMatrix<f32>) -> Result<Self, Box<dyn Error>> {
+        if !precision.is_square() || precision.nrows() == 0 {
+            return Err(util::invalid_argument_error(&format!(
+                "Mahalanobis precision matrix must be square and non-empty, got {}x{}",
+                precision.nrows(),
+                precision.ncols()
+            )));
+        }
+        Ok(MahalanobisDistance { precision })
+    }
+
+    pub fn precision(&self) -> &DMatrix<f32> {
+        &self.precision
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        check_same_dimensionality(a.len(), self.precision.nrows())?;
+        Ok(self.quadratic_form(a, b))
+    }
+
+    fn quadratic_form(&self, a: &[f32], b: &[f32]) -> f32 {
+        let diff: Vec<f32> = a.iter().zip(b.iter()).map(|(&x, &y)| x - y).collect();
+        let mut sum = 0.0f32;
+        for (i, &di) in diff.iter().enumerate() {
+            let row: f32 = diff.iter().enumerate().map(|(j, &dj)| self.precision[(i, j)] * dj).sum();
+            sum += di * row;
+        }
+        sum
+    }
+}
+
+impl DistanceMeasure for MahalanobisDistance {
+    fn name(&self) -> &str {
+        "MahalanobisDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    fn check_dimensionality(&self, dim: usize) -> Result<(), Box<dyn Error>> {
+        check_same_dimensionality(dim, self.precision.nrows())
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        debug_check_dimensionality(a.len(), self.precision.nrows());
+        self.quadratic_form(a, b)
+    }
+}
+
+// Inverse of the sample covariance of `data` plus `ridge` on the diagonal,
+// for use with MahalanobisDistance. Accumulates in f64.
+pub fn estimate_precision(data: &util::DenseDataset<f32>, ridge: f32) -> Result<DMatrix<f32>, Box<dyn Error>> {
+    let (n, dim) = (data.size(), data.dimensionality());
+    if n < 2 || dim == 0 {
+        return Err(util::invalid_argument_error(&format!(
+            "Estimating a precision matrix needs at least 2 rows of positive dimensionality, got {} rows of dimensionality {}",
+            n, dim
+        )));
+    }
+    if ridge.is_nan() || ridge < 0.0 {
+        return Err(util::invalid_argument_error(&format!("Ridge must be >= 0, got {}", ridge)));
+    }
+    let mut mean = vec![0.0f64; dim];
+    for row in &data.data {
+        for (m, &v) in mean.iter_mut().zip(row.iter()) {
+            *m += v as f64;
+        }
+    }
+    mean.iter_mut().for_each(|m| *m /= n as f64);
+    let mut covariance = DMatrix::<f64>::zeros(dim, dim);
+    for row in &data.data {
+        let centered: Vec<f64> = row.iter().zip(mean.iter()).map(|(&v, &m)| v as f64 - m).collect();
+        for i in 0..dim {
+            for j in i..dim {
+                covariance[(i, j)] += centered[i] * centered[j];
+            }
+        }
+    }
+    for i in 0..dim {
+        for j in i..dim {
+            let value = covariance[(i, j)] / (n - 1) as f64;
+            covariance[(i, j)] = value;
+            covariance[(j, i)] = value;
+        }
+        covariance[(i, i)] += ridge as f64;
+    }
+    let precision = covariance.try_inverse().ok_or_else(|| {
+        util::failed_precondition_error("Sample covariance is singular; increase the ridge term")
+    })?;
+    Ok(precision.map(|v| v as f32))
+}
+
+fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+    #[cfg(feature = "simd")]
+    if let Some(dot) = simd::dot(a, b) {
+        return dot;
+    }
+    a.iter().zip(b.iter()).map(|(&x, &y)| x * y).sum()
+}
+
+fn neg_dot_f32(a: &[f32], b: &[f32]) -> f32 {
+    -dot_f32(a, b)
+}
+
+// -sum(a_i * b_i), so that ascending distance is descending inner product
+// as in ScaNN (maximum inner product search).
+#[derive(Default)]
+pub struct DotProductDistance;
+
+impl DotProductDistance {
+    pub fn new() -> Self {
+        DotProductDistance
+    }
+}
+
+impl DistanceMeasure for DotProductDistance {
+    fn name(&self) -> &str {
+        "DotProductDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        if let (Some(a), Some(b)) = (T::as_f32_slice(a.values()), T::as_f32_slice(b.values())) {
+            return self.compute_distance_f32(a, b);
+        }
+        -a.values()
+            .iter()
+            .zip(b.values().iter())
+            .map(|(&x, &y)| x.to_f32() * y.to_f32())
+            .sum::<f32>()
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        neg_dot_f32(a, b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -a.iter().zip(b.iter()).map(|(&x, &y)| x as f64 * y as f64).sum::<f64>() as f32
+    }
+
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        let kernel = select_low_dim_kernel(LowDimKernel::DotProduct, query.len()).unwrap_or(neg_dot_f32);
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = kernel(query, row);
+        }
+    }
+
+    fn compute_distance_mixed<Q: util::ToF32Scalar, D: util::ToF32Scalar>(
+        &self,
+        query: &util::DatapointPtr<Q>,
+        db: &util::DatapointPtr<D>,
+        multipliers: Option<&[f32]>,
+    ) -> f32 {
+        mixed_neg_dot(query.values(), db.values(), multipliers)
+    }
+
+    fn compute_distance_mixed_i8(&self, query: &[f32], db: &[i8], multipliers: Option<&[f32]>, _: &mut Vec<f32>) -> f32 {
+        mixed_neg_dot(query, db, multipliers)
+    }
+
+    fn low_dim_kernel(&self) -> Option<LowDimKernel> {
+        Some(LowDimKernel::DotProduct)
+    }
+}
+
+// 1 - sum(a_i * b_i). Equal to CosineDistance when both sides have unit
+// norm, at the cost of a dot product: normalize the dataset once (see
+// DenseDataset::normalize_rows or Normalization::UnitL2) and every query.
+#[derive(Default)]
+pub struct NormalizedDotProductDistance;
+
+impl NormalizedDotProductDistance {
     pub fn new() -> Self {
-        CosineDistance
+        NormalizedDotProductDistance
     }
 }
 
-impl DistanceMeasure for CosineDistance {
-    fn compute_distance<T: Copy + Into<f32>>(&self, a: &utils::DatapointPtr<T>, b: &utils::DatapointPtr<T>) -> f32 {
-        let a_vec: Vec<f32> = a.values().iter().map(|&x| x.into()).collect();
-        let b_vec: Vec<f32> = b.values().iter().map(|&x| x.into()).collect();
-        let a = DVector::from_vec(a_vec);
-        let b = DVector::from_vec(b_vec);
-        let norm_a = a.norm();
-        let norm_b = b.norm();
-        if norm_a == 0.0 || norm_b == 0.0 {
-            return 1.0; // Max distance if either vector is zero
+impl DistanceMeasure for NormalizedDotProductDistance {
+    fn name(&self) -> &str {
+        "NormalizedDotProductDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        1.0 + DotProductDistance.compute_distance(a, b)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        1.0 - dot_f32(a, b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        1.0 + DotProductDistance.compute_distance_f64_accumulated(a, b)
+    }
+
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        let kernel = select_low_dim_kernel(LowDimKernel::DotProduct, query.len()).unwrap_or(neg_dot_f32);
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = 1.0 + kernel(query, row);
         }
-        1.0 - (a.dot(&b) / (norm_a * norm_b)).max(-1.0).min(1.0)
     }
 }
 
+// -|sum(a_i * b_i)|: larger magnitude is closer regardless of sign.
+#[derive(Default)]
+pub struct AbsDotProductDistance;
 
-pub trait DistanceMeasure: Send + Sync {
-    fn compute_distance<T: Copy + Into<f32>>(&self, a: &utils::DatapointPtr<T>, b: &utils::DatapointPtr<T>) -> f32;
+impl AbsDotProductDistance {
+    pub fn new() -> Self {
+        AbsDotProductDistance
+    }
+}
+
+impl DistanceMeasure for AbsDotProductDistance {
+    fn name(&self) -> &str {
+        "AbsDotProductDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        -DotProductDistance.compute_distance(a, b).abs()
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -dot_f32(a, b).abs()
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -DotProductDistance.compute_distance_f64_accumulated(a, b).abs()
+    }
+}
+
+// -||a - b||^2.
+#[derive(Default)]
+pub struct NegatedSquaredL2Distance;
+
+impl NegatedSquaredL2Distance {
+    pub fn new() -> Self {
+        NegatedSquaredL2Distance
+    }
+}
+
+impl DistanceMeasure for NegatedSquaredL2Distance {
+    fn name(&self) -> &str {
+        "NegatedSquaredL2Distance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        -SquaredL2Distance.compute_distance(a, b)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -squared_l2_f32(a, b)
+    }
+
+    fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -(squared_l2_f64(a, b) as f32)
+    }
+}
+
+// Floor applied to every probability before taking its log, so zero
+// entries give a large finite distance instead of infinity.
+pub const PROBABILITY_EPSILON: f32 = 1e-10;
+
+// Factor that scales `values` to sum to 1 when renormalizing, else 1. None
+// when a value is negative or non-finite, or a renormalized row sums to 0.
+fn probability_scale(values: &[f32], renormalize: bool) -> Option<f32> {
+    if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        return None;
+    }
+    if !renormalize {
+        return Some(1.0);
+    }
+    let sum: f32 = values.iter().sum();
+    (sum > 0.0).then(|| 1.0 / sum)
+}
+
+fn check_probabilities(values: &[f32], renormalize: bool, side: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(i) = values.iter().position(|v| !v.is_finite() || *v < 0.0) {
+        return Err(util::invalid_argument_error(&format!(
+            "{} probability {} at dimension {} must be finite and non-negative",
+            side, values[i], i
+        )));
+    }
+    if renormalize && values.iter().sum::<f32>() <= 0.0 {
+        return Err(util::invalid_argument_error(&format!("{} probabilities sum to 0", side)));
+    }
+    Ok(())
+}
+
+// Pairs of floored probabilities (p from `a`, q from `b`), or None when
+// either side is not a valid probability vector.
+fn probability_pairs<'a>(
+    a: &'a [f32],
+    b: &'a [f32],
+    renormalize: bool,
+) -> Option<impl Iterator<Item = (f32, f32)> + 'a> {
+    let scale_a = probability_scale(a, renormalize)?;
+    let scale_b = probability_scale(b, renormalize)?;
+    Some(a.iter().zip(b.iter()).map(move |(&x, &y)| {
+        ((x * scale_a).max(PROBABILITY_EPSILON), (y * scale_b).max(PROBABILITY_EPSILON))
+    }))
+}
+
+// KL(a || b) = sum a_i * ln(a_i / b_i) over probability vectors. Asymmetric:
+// `a` is the query distribution and `b` the stored row, so swapping them
+// changes the result. Entries are floored at PROBABILITY_EPSILON; with
+// `renormalize` each side is first scaled to sum to 1.
+pub struct KLDivergenceDistance {
+    renormalize: bool,
+}
+
+impl KLDivergenceDistance {
+    pub fn new(renormalize: bool) -> Self {
+        KLDivergenceDistance { renormalize }
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        check_probabilities(a, self.renormalize, "Query")?;
+        check_probabilities(b, self.renormalize, "Datapoint")?;
+        Ok(self.compute_distance_f32(a, b))
+    }
+}
+
+impl DistanceMeasure for KLDivergenceDistance {
+    fn name(&self) -> &str {
+        "KLDivergenceDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    // NaN for negative or non-finite inputs, which search paths skip; use
+    // try_distance to get the error instead.
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        match probability_pairs(a, b, self.renormalize) {
+            Some(pairs) => pairs.map(|(p, q)| p * (p / q).ln()).sum(),
+            None => f32::NAN,
+        }
+    }
+}
+
+// Cross-entropy H(a, b) = -sum a_i * ln(b_i), which ranks rows like
+// KLDivergenceDistance for a fixed query since the two differ by the
+// query's entropy. Asymmetric, floored and optionally renormalized the
+// same way.
+pub struct CrossEntropyDistance {
+    renormalize: bool,
+}
+
+impl CrossEntropyDistance {
+    pub fn new(renormalize: bool) -> Self {
+        CrossEntropyDistance { renormalize }
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        check_probabilities(a, self.renormalize, "Query")?;
+        check_probabilities(b, self.renormalize, "Datapoint")?;
+        Ok(self.compute_distance_f32(a, b))
+    }
+}
+
+impl DistanceMeasure for CrossEntropyDistance {
+    fn name(&self) -> &str {
+        "CrossEntropyDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    // NaN on invalid inputs, as for KLDivergenceDistance.
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        match probability_pairs(a, b, self.renormalize) {
+            Some(pairs) => -pairs.map(|(p, q)| p * q.ln()).sum::<f32>(),
+            None => f32::NAN,
+        }
+    }
+}
+
+// Largest norm a point keeps when PoincareDistance clamps it into the ball.
+pub const POINCARE_MAX_NORM: f64 = 1.0 - 1e-5;
+
+// Hyperbolic distance between points of the Poincare ball,
+// arcosh(1 + 2|a - b|^2 / ((1 - |a|^2)(1 - |b|^2))). Points must lie strictly
+// inside the unit ball. With `clamp_to_ball`, points with norm >= 1 are
+// scaled back to POINCARE_MAX_NORM; otherwise they give NaN, which search
+// paths skip. Accumulates in f64 since the denominator vanishes near the
+// boundary.
+pub struct PoincareDistance {
+    clamp_to_ball: bool,
+}
+
+impl PoincareDistance {
+    pub fn new(clamp_to_ball: bool) -> Self {
+        PoincareDistance { clamp_to_ball }
+    }
+
+    pub fn try_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, Box<dyn Error>> {
+        check_same_dimensionality(a.len(), b.len())?;
+        for (side, values) in [("Query", a), ("Datapoint", b)] {
+            let sq_norm = squared_norm_f64(values);
+            if !sq_norm.is_finite() || (!self.clamp_to_ball && sq_norm >= 1.0) {
+                return Err(util::invalid_argument_error(&format!(
+                    "{} norm {} must be finite and below 1 in the Poincare ball",
+                    side,
+                    sq_norm.sqrt()
+                )));
+            }
+        }
+        Ok(self.compute_distance_f32(a, b))
+    }
+
+    // (factor scaling the point into the ball, squared norm after scaling),
+    // or None for a non-finite point or one outside the ball without
+    // clamping.
+    fn ball_scale(&self, values: &[f32]) -> Option<(f64, f64)> {
+        let sq_norm = squared_norm_f64(values);
+        if !sq_norm.is_finite() {
+            return None;
+        }
+        if sq_norm < 1.0 {
+            return Some((1.0, sq_norm));
+        }
+        self.clamp_to_ball
+            .then(|| (POINCARE_MAX_NORM / sq_norm.sqrt(), POINCARE_MAX_NORM * POINCARE_MAX_NORM))
+    }
+
+    fn from_scaled(a: &[f32], (scale_a, sq_a): (f64, f64), b: &[f32], (scale_b, sq_b): (f64, f64)) -> f32 {
+        let diff: f64 = a
+            .iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| {
+                let d = x as f64 * scale_a - y as f64 * scale_b;
+                d * d
+            })
+            .sum();
+        (1.0 + 2.0 * diff / ((1.0 - sq_a) * (1.0 - sq_b))).acosh() as f32
+    }
+}
+
+fn squared_norm_f64(values: &[f32]) -> f64 {
+    values.iter().map(|&v| v as f64 * v as f64).sum()
+}
+
+impl DistanceMeasure for PoincareDistance {
+    fn name(&self) -> &str {
+        "PoincareDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        let a: Vec<f32> = a.values().iter().map(|&x| x.to_f32()).collect();
+        let b: Vec<f32> = b.values().iter().map(|&x| x.to_f32()).collect();
+        self.compute_distance_f32(&a, &b)
+    }
+
+    // NaN for points outside the ball (unless clamping) or non-finite
+    // points; use try_distance to get the error instead.
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        match (self.ball_scale(a), self.ball_scale(b)) {
+            (Some(scaled_a), Some(scaled_b)) => Self::from_scaled(a, scaled_a, b, scaled_b),
+            _ => f32::NAN,
+        }
+    }
+
+    // Scales the query once for all rows.
+    fn compute_one_to_many_rows(&self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        let scaled_query = self.ball_scale(query);
+        for (row, slot) in rows.iter().zip(out.iter_mut()) {
+            *slot = match (scaled_query, self.ball_scale(row)) {
+                (Some(scaled_query), Some(scaled_row)) => {
+                    Self::from_scaled(query, scaled_query, row, scaled_row)
+                }
+                _ => f32::NAN,
+            };
+        }
+    }
+}
+
+// Built-in stateless measures with match-based dispatch. Unlike a boxed
+// DistanceMeasure the per-row call is static and can be inlined into the
+// scoring loop. Each arm calls the same kernel as the measure's trait impl,
+// so both paths return bit-identical values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistanceMeasureKind {
+    DotProduct,
+    AbsDotProduct,
+    SquaredL2,
+    NegatedSquaredL2,
+    L2,
+    L1,
+    Cosine,
+    NormalizedDotProduct,
+    GeneralHamming,
+    GeneralJaccard,
+    BinaryJaccard,
+    LimitedInnerProduct,
+}
+
+impl DistanceMeasureKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "DotProductDistance" => Some(DistanceMeasureKind::DotProduct),
+            "AbsDotProductDistance" => Some(DistanceMeasureKind::AbsDotProduct),
+            "SquaredL2Distance" => Some(DistanceMeasureKind::SquaredL2),
+            "NegatedSquaredL2Distance" => Some(DistanceMeasureKind::NegatedSquaredL2),
+            "L2Distance" => Some(DistanceMeasureKind::L2),
+            "L1Distance" => Some(DistanceMeasureKind::L1),
+            "CosineDistance" => Some(DistanceMeasureKind::Cosine),
+            "NormalizedDotProductDistance" => Some(DistanceMeasureKind::NormalizedDotProduct),
+            "GeneralHammingDistance" => Some(DistanceMeasureKind::GeneralHamming),
+            "GeneralJaccardDistance" => Some(DistanceMeasureKind::GeneralJaccard),
+            "BinaryJaccardDistance" => Some(DistanceMeasureKind::BinaryJaccard),
+            "LimitedInnerProductDistance" => Some(DistanceMeasureKind::LimitedInnerProduct),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DistanceMeasureKind::DotProduct => "DotProductDistance",
+            DistanceMeasureKind::AbsDotProduct => "AbsDotProductDistance",
+            DistanceMeasureKind::SquaredL2 => "SquaredL2Distance",
+            DistanceMeasureKind::NegatedSquaredL2 => "NegatedSquaredL2Distance",
+            DistanceMeasureKind::L2 => "L2Distance",
+            DistanceMeasureKind::L1 => "L1Distance",
+            DistanceMeasureKind::Cosine => "CosineDistance",
+            DistanceMeasureKind::NormalizedDotProduct => "NormalizedDotProductDistance",
+            DistanceMeasureKind::GeneralHamming => "GeneralHammingDistance",
+            DistanceMeasureKind::GeneralJaccard => "GeneralJaccardDistance",
+            DistanceMeasureKind::BinaryJaccard => "BinaryJaccardDistance",
+            DistanceMeasureKind::LimitedInnerProduct => "LimitedInnerProductDistance",
+        }
+    }
+
+    #[inline]
+    pub fn compute(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceMeasureKind::DotProduct => DotProductDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::AbsDotProduct => AbsDotProductDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::SquaredL2 => SquaredL2Distance.compute_distance_f32(a, b),
+            DistanceMeasureKind::NegatedSquaredL2 => NegatedSquaredL2Distance.compute_distance_f32(a, b),
+            DistanceMeasureKind::L2 => L2Distance.compute_distance_f32(a, b),
+            DistanceMeasureKind::L1 => L1Distance.compute_distance_f32(a, b),
+            DistanceMeasureKind::Cosine => CosineDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::NormalizedDotProduct => NormalizedDotProductDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::GeneralHamming => GeneralHammingDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::GeneralJaccard => GeneralJaccardDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::BinaryJaccard => BinaryJaccardDistance.compute_distance_f32(a, b),
+            DistanceMeasureKind::LimitedInnerProduct => LimitedInnerProductDistance.compute_distance_f32(a, b),
+        }
+    }
+
+    // Dispatches once per block rather than per row.
+    pub fn compute_one_to_many_rows(self, query: &[f32], rows: util::RowBlock<'_, f32>, out: &mut [f32]) {
+        debug_check_rows(query, rows);
+        match self {
+            DistanceMeasureKind::DotProduct => DotProductDistance.compute_one_to_many_rows(query, rows, out),
+            DistanceMeasureKind::SquaredL2 => SquaredL2Distance.compute_one_to_many_rows(query, rows, out),
+            DistanceMeasureKind::Cosine => CosineDistance.compute_one_to_many_rows(query, rows, out),
+            DistanceMeasureKind::NormalizedDotProduct => {
+                NormalizedDotProductDistance.compute_one_to_many_rows(query, rows, out)
+            }
+            _ => {
+                for (row, slot) in rows.iter().zip(out.iter_mut()) {
+                    *slot = self.compute(query, row);
+                }
+            }
+        }
+    }
+
+    // The boxed equivalent, for code that only takes the trait.
+    pub fn to_measure(self) -> Box<dyn DistanceMeasure> {
+        get_distance_measure_by_name(self.name()).unwrap()
+    }
+}
+
+pub fn get_distance_measure_kind(name: &str) -> Result<DistanceMeasureKind, Box<dyn Error>> {
+    DistanceMeasureKind::from_name(name).ok_or_else(|| {
+        Box::new(ScannError {
+            message: format!("'{}' is not a built-in distance measure with static dispatch", name),
+        }) as Box<dyn Error>
+    })
 }
 
 // Placeholder implementations for distance measures
 macro_rules! define_distance_measure {
     ($name:ident) => {
+        define_distance_measure!($name, None);
+    };
+    ($name:ident, $low_dim_kernel:expr) => {
+        #[derive(Default)]
         pub struct $name;
 
         impl $name {
@@ -58,43 +1887,409 @@ macro_rules! define_distance_measure {
         }
 
         impl DistanceMeasure for $name {
-            fn compute_distance<T: Copy + Into<f32>>(&self, a: &utils::DatapointPtr<T>, b: &utils::DatapointPtr<T>) -> f32 {
+            fn name(&self) -> &str {
+                stringify!($name)
+            }
+
+            fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+                debug_check_dimensionality(a.values().len(), b.values().len());
                 // Placeholder: Implement actual distance computation
                 // For example, DotProductDistance would compute sum(a[i] * b[i])
                 let sum: f32 = a
                     .values()
                     .iter()
                     .zip(b.values().iter())
-                    .map(|(&x, &y)| x.into() * y.into())
+                    .map(|(&x, &y)| x.to_f32() * y.to_f32())
                     .sum();
                 sum
             }
+
+            fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+                debug_check_dimensionality(a.len(), b.len());
+                a.iter().zip(b.iter()).map(|(&x, &y)| x * y).sum()
+            }
+
+            fn compute_distance_f64_accumulated(&self, a: &[f32], b: &[f32]) -> f32 {
+                debug_check_dimensionality(a.len(), b.len());
+                a.iter().zip(b.iter()).map(|(&x, &y)| x as f64 * y as f64).sum::<f64>() as f32
+            }
+
+            fn low_dim_kernel(&self) -> Option<LowDimKernel> {
+                $low_dim_kernel
+            }
         }
     };
 }
 
-define_distance_measure!(DotProductDistance);
-define_distance_measure!(BinaryDotProductDistance);
-define_distance_measure!(AbsDotProductDistance);
-define_distance_measure!(L2Distance);
-define_distance_measure!(SquaredL2Distance);
-define_distance_measure!(NegatedSquaredL2Distance);
-define_distance_measure!(L1Distance);
-define_distance_measure!(CosineDistance);
-define_distance_measure!(BinaryCosineDistance);
-define_distance_measure!(GeneralJaccardDistance);
-define_distance_measure!(BinaryJaccardDistance);
-define_distance_measure!(LimitedInnerProductDistance);
-define_distance_measure!(GeneralHammingDistance);
-define_distance_measure!(BinaryHammingDistance);
 define_distance_measure!(NonzeroIntersectDistance);
 
+// Nonzero coordinates as bits, 64 dimensions per word, LSB first. Pad bits
+// in the last word are zero, so they never count toward popcounts.
+pub fn pack_nonzero_bits(values: &[f32]) -> Vec<u64> {
+    let mut words = vec![0u64; values.len().div_ceil(64)];
+    for (i, &v) in values.iter().enumerate() {
+        if v != 0.0 {
+            words[i / 64] |= 1 << (i % 64);
+        }
+    }
+    words
+}
+
+fn check_packed_words(a: &[u64], b: &[u64]) -> Result<(), Box<dyn Error>> {
+    if a.len() != b.len() {
+        return Err(Box::new(ScannError {
+            message: format!("Packed vectors have {} and {} words", a.len(), b.len()),
+        }));
+    }
+    Ok(())
+}
+
+// (|a & b|, |a|, |b|) over binarized values.
+fn binary_counts(pairs: impl Iterator<Item = (f32, f32)>) -> (u32, u32, u32) {
+    let (mut both, mut count_a, mut count_b) = (0, 0, 0);
+    for (x, y) in pairs {
+        let (in_a, in_b) = (x != 0.0, y != 0.0);
+        both += (in_a && in_b) as u32;
+        count_a += in_a as u32;
+        count_b += in_b as u32;
+    }
+    (both, count_a, count_b)
+}
+
+fn packed_counts(a: &[u64], b: &[u64]) -> (u32, u32, u32) {
+    a.iter().zip(b.iter()).fold((0, 0, 0), |(both, count_a, count_b), (&x, &y)| {
+        (both + (x & y).count_ones(), count_a + x.count_ones(), count_b + y.count_ones())
+    })
+}
+
+// DotProductDistance over binarized inputs: every nonzero value counts as
+// 1, so the result is minus the number of coordinates nonzero in both.
+// Suited to sign-quantized embeddings.
+#[derive(Default)]
+pub struct BinaryDotProductDistance;
+
+impl BinaryDotProductDistance {
+    pub fn new() -> Self {
+        BinaryDotProductDistance
+    }
+
+    // Same distance over vectors packed by pack_nonzero_bits.
+    pub fn packed_distance(&self, a: &[u64], b: &[u64]) -> Result<f32, Box<dyn Error>> {
+        check_packed_words(a, b)?;
+        Ok(-(packed_counts(a, b).0 as f32))
+    }
+}
+
+impl DistanceMeasure for BinaryDotProductDistance {
+    fn name(&self) -> &str {
+        "BinaryDotProductDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        -(binary_counts(a.values().iter().zip(b.values().iter()).map(|(&x, &y)| (x.to_f32(), y.to_f32()))).0 as f32)
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        -(binary_counts(a.iter().copied().zip(b.iter().copied())).0 as f32)
+    }
+}
+
+// CosineDistance over binarized inputs: 1 - |a & b| / sqrt(|a| * |b|).
+// A vector with no nonzero coordinate gives 1, as CosineDistance does for
+// zero vectors.
+#[derive(Default)]
+pub struct BinaryCosineDistance;
+
+impl BinaryCosineDistance {
+    pub fn new() -> Self {
+        BinaryCosineDistance
+    }
+
+    // Same distance over vectors packed by pack_nonzero_bits.
+    pub fn packed_distance(&self, a: &[u64], b: &[u64]) -> Result<f32, Box<dyn Error>> {
+        check_packed_words(a, b)?;
+        Ok(Self::from_counts(packed_counts(a, b)))
+    }
+
+    fn from_counts((both, count_a, count_b): (u32, u32, u32)) -> f32 {
+        cosine_from_parts(both as f32, count_a as f32, count_b as f32)
+    }
+}
+
+impl DistanceMeasure for BinaryCosineDistance {
+    fn name(&self) -> &str {
+        "BinaryCosineDistance"
+    }
+
+    fn compute_distance<T: util::ToF32Scalar>(&self, a: &util::DatapointPtr<T>, b: &util::DatapointPtr<T>) -> f32 {
+        debug_check_dimensionality(a.values().len(), b.values().len());
+        Self::from_counts(binary_counts(
+            a.values().iter().zip(b.values().iter()).map(|(&x, &y)| (x.to_f32(), y.to_f32())),
+        ))
+    }
+
+    fn compute_distance_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        debug_check_dimensionality(a.len(), b.len());
+        Self::from_counts(binary_counts(a.iter().copied().zip(b.iter().copied())))
+    }
+}
+
+// All-pairs distances: entry (i, j) is the distance from row i of `a` to
+// row j of `b`. Dot products lower to one matrix multiply, whose summation
+// order may differ from the per-row kernel in the last bits.
+pub fn compute_pairwise_distances(
+    measure: &dyn DistanceMeasure,
+    a: &util::DenseDataset<f32>,
+    b: &util::DenseDataset<f32>,
+) -> Result<DMatrix<f32>, Box<dyn Error>> {
+    check_same_dimensionality(a.dimensionality(), b.dimensionality())?;
+    measure.check_dimensionality(a.dimensionality())?;
+    let (n, m, dim) = (a.size(), b.size(), a.dimensionality());
+    if measure.low_dim_kernel() == Some(LowDimKernel::DotProduct) {
+        let a_matrix = DMatrix::from_fn(n, dim, |i, j| a.data[i][j]);
+        let b_transposed = DMatrix::from_fn(dim, m, |i, j| b.data[j][i]);
+        return Ok(-util::matrix_multiply(&a_matrix, &b_transposed)?);
+    }
+    let row_distances = |query: &Vec<f32>| {
+        let mut out = vec![0.0f32; m];
+        measure.compute_one_to_many_rows(query, (&b.data).into(), &mut out);
+        out
+    };
+    #[cfg(feature = "rayon")]
+    let rows: Vec<Vec<f32>> = a.data.par_iter().map(row_distances).collect();
+    #[cfg(not(feature = "rayon"))]
+    let rows: Vec<Vec<f32>> = a.data.iter().map(row_distances).collect();
+    Ok(DMatrix::from_fn(n, m, |i, j| rows[i][j]))
+}
+
+// Rows scored per one-to-many call by the fused top-k scan, which bounds
+// its scratch independently of the dataset size.
+const TOP_K_BLOCK: usize = 256;
+
+// Heap entry ordered by distance, then index. The fused scan stores a
+// (docid, row) pair with the row at the dataset's IndexWidth.
+pub(crate) struct TopKEntry<I>(pub(crate) f32, pub(crate) I);
+
+impl<I: Ord> Eq for TopKEntry<I> {}
+
+impl<I: Ord> PartialEq for TopKEntry<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl<I: Ord> PartialOrd for TopKEntry<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<I: Ord> Ord for TopKEntry<I> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TopKScan {
+    // (row index, distance), ascending by distance, ties by docid.
+    pub neighbors: Vec<(usize, f32)>,
+    // Rows scored, excluding those rejected by the filter.
+    pub scored: usize,
+    // NaN distances, which have no place in the order and are never kept.
+    pub non_finite_skipped: usize,
+    // Rows a thresholded scan stopped scoring because they could not beat
+    // the k-th best; included in `scored`.
+    pub early_exits: usize,
+}
+
+// Exact top-k of `query` against `dataset` without materializing all N
+// distances: a bounded max-heap of k entries is maintained inside the scan,
+// so memory is O(k) plus one fixed block of distances. Results match
+// sorting every non-NaN distance, ties by row index, and keeping the first k.
+pub fn top_k_one_to_many<M: DistanceMeasure + ?Sized>(
+    measure: &M,
+    query: &util::DatapointPtr<f32>,
+    dataset: &util::DenseDataset<f32>,
+    k: usize,
+) -> Vec<(usize, f32)> {
+    top_k_one_to_many_rows(
+        query.values(),
+        (&dataset.data).into(),
+        k,
+        |q, rows, out| measure.compute_one_to_many_rows(q, rows, out),
+        |_| true,
+        |i| i,
+    )
+    .neighbors
+}
+
+// Same over raw rows with any one-to-many kernel, skipping rows for which
+// `keep` returns false and breaking distance ties by `docid` of the row.
+pub fn top_k_one_to_many_rows(
+    query: &[f32],
+    rows: util::RowBlock<'_, f32>,
+    k: usize,
+    kernel: impl Fn(&[f32], util::RowBlock<'_, f32>, &mut [f32]),
+    keep: impl Fn(usize) -> bool,
+    docid: impl Fn(usize) -> usize,
+) -> TopKScan {
+    top_k_one_to_many_segments(query, [rows], k, kernel, keep, docid)
+}
+
+// Same over rows stored as consecutive segments, e.g. those of a
+// util::SegmentedDataset. Row indices run across segments.
+pub fn top_k_one_to_many_segments<'a>(
+    query: &[f32],
+    segments: impl IntoIterator<Item = util::RowBlock<'a, f32>>,
+    k: usize,
+    kernel: impl Fn(&[f32], util::RowBlock<'_, f32>, &mut [f32]),
+    keep: impl Fn(usize) -> bool,
+    docid: impl Fn(usize) -> usize,
+) -> TopKScan {
+    let segments: Vec<_> = segments.into_iter().collect();
+    let num_rows = segments.iter().map(|rows| rows.len()).sum();
+    match util::IndexWidth::for_size(num_rows) {
+        util::IndexWidth::U32 => top_k_scan::<u32>(query, &segments, k, &kernel, &keep, &docid),
+        util::IndexWidth::U64 => top_k_scan::<u64>(query, &segments, k, &kernel, &keep, &docid),
+    }
+}
+
+// The fused scan with heap entries holding row indices as `I`.
+fn top_k_scan<I: util::DatapointIndex>(
+    query: &[f32],
+    segments: &[util::RowBlock<'_, f32>],
+    k: usize,
+    kernel: &impl Fn(&[f32], util::RowBlock<'_, f32>, &mut [f32]),
+    keep: &impl Fn(usize) -> bool,
+    docid: &impl Fn(usize) -> usize,
+) -> TopKScan {
+    let mut scan = TopKScan::default();
+    if k == 0 {
+        return scan;
+    }
+    let mut heap = std::collections::BinaryHeap::with_capacity(k + 1);
+    let mut block = [0.0f32; TOP_K_BLOCK];
+    let mut offset = 0;
+    for rows in segments {
+        for start in (0..rows.len()).step_by(TOP_K_BLOCK) {
+            let end = (start + TOP_K_BLOCK).min(rows.len());
+            let out = &mut block[..end - start];
+            kernel(query, rows.slice(start, end), out);
+            for (i, &distance) in (offset + start..offset + end).zip(out.iter()) {
+                if !keep(i) {
+                    continue;
+                }
+                scan.scored += 1;
+                if distance.is_nan() {
+                    scan.non_finite_skipped += 1;
+                    continue;
+                }
+                offer(&mut heap, k, TopKEntry(distance, (docid(i), I::from_usize(i).unwrap())));
+            }
+        }
+        offset += rows.len();
+    }
+    scan.neighbors = heap.into_sorted_vec().into_iter().map(|TopKEntry(d, (_, i))| (i.to_usize(), d)).collect();
+    scan
+}
+
+// Same top-k scored row by row through `score`, which is given the k-th best
+// distance once k rows are held and returns None for a row it stopped
+// scoring because the row cannot beat it. Pairs with
+// compute_distance_with_threshold for measures that exit early.
+pub fn top_k_with_threshold_segments<'a>(
+    query: &[f32],
+    segments: impl IntoIterator<Item = util::RowBlock<'a, f32>>,
+    k: usize,
+    score: impl Fn(&[f32], &[f32], Option<f32>) -> Option<f32>,
+    keep: impl Fn(usize) -> bool,
+    docid: impl Fn(usize) -> usize,
+) -> TopKScan {
+    let segments: Vec<_> = segments.into_iter().collect();
+    let num_rows = segments.iter().map(|rows| rows.len()).sum();
+    match util::IndexWidth::for_size(num_rows) {
+        util::IndexWidth::U32 => top_k_threshold_scan::<u32>(query, &segments, k, &score, &keep, &docid),
+        util::IndexWidth::U64 => top_k_threshold_scan::<u64>(query, &segments, k, &score, &keep, &docid),
+    }
+}
+
+fn top_k_threshold_scan<I: util::DatapointIndex>(
+    query: &[f32],
+    segments: &[util::RowBlock<'_, f32>],
+    k: usize,
+    score: &impl Fn(&[f32], &[f32], Option<f32>) -> Option<f32>,
+    keep: &impl Fn(usize) -> bool,
+    docid: &impl Fn(usize) -> usize,
+) -> TopKScan {
+    let mut scan = TopKScan::default();
+    if k == 0 {
+        return scan;
+    }
+    let mut heap = std::collections::BinaryHeap::<TopKEntry<(usize, I)>>::with_capacity(k + 1);
+    let mut offset = 0;
+    for rows in segments {
+        for j in 0..rows.len() {
+            let i = offset + j;
+            if !keep(i) {
+                continue;
+            }
+            scan.scored += 1;
+            // A row tied with the k-th best can still enter on a lower
+            // docid, so the threshold is inclusive.
+            let threshold = heap.peek().filter(|_| heap.len() == k).map(|worst| worst.0);
+            let Some(distance) = score(query, rows.row(j), threshold) else {
+                scan.early_exits += 1;
+                continue;
+            };
+            if distance.is_nan() {
+                scan.non_finite_skipped += 1;
+                continue;
+            }
+            offer(&mut heap, k, TopKEntry(distance, (docid(i), I::from_usize(i).unwrap())));
+        }
+        offset += rows.len();
+    }
+    scan.neighbors = heap.into_sorted_vec().into_iter().map(|TopKEntry(d, (_, i))| (i.to_usize(), d)).collect();
+    scan
+}
+
+// Keeps `entry` when the heap holds fewer than k entries or it beats the
+// worst of them.
+fn offer<E: Ord>(heap: &mut std::collections::BinaryHeap<E>, k: usize, entry: E) {
+    if heap.len() < k {
+        heap.push(entry);
+    } else if entry < *heap.peek().unwrap() {
+        heap.pop();
+        heap.push(entry);
+    }
+}
+
 pub fn get_distance_measure(config: &proto::DistanceMeasureConfig) -> Result<Box<dyn DistanceMeasure>, Box<dyn Error>> {
     if config.distance_measure().is_empty() {
         return Err(Box::new(ScannError {
             message: "Empty DistanceMeasureConfig proto! Must specify distance_measure.".to_string(),
         }));
     }
+    // Parameterized measures take their parameters from the config.
+    if config.distance_measure() == "MinkowskiDistance" {
+        let Some(p) = config.minkowski_p else {
+            return Err(util::invalid_argument_error(
+                "MinkowskiDistance requires minkowski_p in DistanceMeasureConfig",
+            ));
+        };
+        return Ok(Box::new(MinkowskiDistance::new(p)?));
+    }
+    if config.distance_measure() == "WeightedSquaredL2Distance" {
+        if config.weighted_l2_weights.is_empty() {
+            return Err(util::invalid_argument_error(
+                "WeightedSquaredL2Distance requires weighted_l2_weights in DistanceMeasureConfig",
+            ));
+        }
+        return Ok(Box::new(WeightedSquaredL2Distance::new(config.weighted_l2_weights.clone())?));
+    }
     get_distance_measure_by_name(config.distance_measure())
 }
 
@@ -107,7 +2302,10 @@ pub fn get_distance_measure_by_name(name: &str) -> Result<Box<dyn DistanceMeasur
         "SquaredL2Distance" => Ok(Box::new(SquaredL2Distance::new())),
         "NegatedSquaredL2Distance" => Ok(Box::new(NegatedSquaredL2Distance::new())),
         "L1Distance" => Ok(Box::new(L1Distance::new())),
+        "ChebyshevDistance" => Ok(Box::new(ChebyshevDistance::new())),
         "CosineDistance" => Ok(Box::new(CosineDistance::new())),
+        "AngularDistance" => Ok(Box::new(AngularDistance::new())),
+        "NormalizedDotProductDistance" => Ok(Box::new(NormalizedDotProductDistance::new())),
         "BinaryCosineDistance" => Ok(Box::new(BinaryCosineDistance::new())),
         "GeneralJaccardDistance" => Ok(Box::new(GeneralJaccardDistance::new())),
         "BinaryJaccardDistance" => Ok(Box::new(BinaryJaccardDistance::new())),
@@ -115,6 +2313,9 @@ pub fn get_distance_measure_by_name(name: &str) -> Result<Box<dyn DistanceMeasur
         "GeneralHammingDistance" => Ok(Box::new(GeneralHammingDistance::new())),
         "BinaryHammingDistance" => Ok(Box::new(BinaryHammingDistance::new())),
         "NonzeroIntersectDistance" => Ok(Box::new(NonzeroIntersectDistance::new())),
+        "KLDivergenceDistance" => Ok(Box::new(KLDivergenceDistance::new(false))),
+        "CrossEntropyDistance" => Ok(Box::new(CrossEntropyDistance::new(false))),
+        "PoincareDistance" => Ok(Box::new(PoincareDistance::new(false))),
         
         _ => Err(Box::new(ScannError {
             message: format!("Invalid distance_measure: '{}'", name),
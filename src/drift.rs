@@ -0,0 +1,253 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of query embedding drift against reference statistics.
+//!
+//! A changed upstream embedding model shifts query norms and means without
+//! any error, and recall drops silently. The monitor keeps moving averages
+//! of incoming query norms and coordinates and scores how far they sit from
+//! statistics captured from the indexed data or a known-good query sample.
+//! It never changes search results.
+
+use super::util;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftConfig {
+    // Effective number of recent queries in the moving averages.
+    pub window: usize,
+    // No score is reported before this many queries have been seen.
+    pub min_queries: usize,
+    // Scores above this raise the drift flag and fire the callback.
+    pub threshold: f32,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        DriftConfig {
+            window: 256,
+            min_queries: 32,
+            threshold: 0.5,
+        }
+    }
+}
+
+// Statistics of the reference distribution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriftReference {
+    pub mean_norm: f32,
+    pub norm_std: f32,
+    pub mean: Vec<f32>,
+    // Root-mean-square distance of the reference vectors from `mean`.
+    pub spread: f32,
+}
+
+impl DriftReference {
+    pub fn from_rows(rows: &[&[f32]]) -> Result<Self, Box<dyn Error>> {
+        let Some(first) = rows.first() else {
+            return Err(util::invalid_argument_error("Drift reference needs at least one vector"));
+        };
+        let dim = first.len();
+        if let Some(row) = rows.iter().find(|row| row.len() != dim) {
+            return Err(util::invalid_argument_error(&format!(
+                "Drift reference vectors have dimensionality {} and {}",
+                dim,
+                row.len()
+            )));
+        }
+        let n = rows.len() as f64;
+        let mut mean = vec![0.0f64; dim];
+        let mut norm_sum = 0.0f64;
+        let mut norm_sq_sum = 0.0f64;
+        for row in rows {
+            let norm = l2_norm(row) as f64;
+            norm_sum += norm;
+            norm_sq_sum += norm * norm;
+            for (m, &v) in mean.iter_mut().zip(row.iter()) {
+                *m += v as f64;
+            }
+        }
+        mean.iter_mut().for_each(|m| *m /= n);
+        let mean_norm = norm_sum / n;
+        let spread_sq = rows
+            .iter()
+            .map(|row| row.iter().zip(mean.iter()).map(|(&v, &m)| (v as f64 - m).powi(2)).sum::<f64>())
+            .sum::<f64>()
+            / n;
+        Ok(DriftReference {
+            mean_norm: mean_norm as f32,
+            norm_std: (norm_sq_sum / n - mean_norm * mean_norm).max(0.0).sqrt() as f32,
+            mean: mean.into_iter().map(|m| m as f32).collect(),
+            spread: spread_sq.sqrt() as f32,
+        })
+    }
+
+    // Reference from a reservoir sample of up to `sample_size` dataset rows.
+    pub fn from_dataset(
+        dataset: &util::DenseDataset<f32>,
+        sample_size: usize,
+        seed: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut rng = util::SplitMix64::new(seed);
+        let mut reservoir: Vec<&[f32]> = Vec::with_capacity(sample_size.min(dataset.size()));
+        for (i, row) in dataset.data.iter().enumerate() {
+            if reservoir.len() < sample_size {
+                reservoir.push(row);
+            } else {
+                let j = rng.next_below(i + 1);
+                if j < sample_size {
+                    reservoir[j] = row;
+                }
+            }
+        }
+        Self::from_rows(&reservoir)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DriftScore {
+    // Distance of the running mean norm from the reference mean norm, in
+    // reference norm standard deviations.
+    pub norm_shift: f32,
+    // Distance of the running mean vector from the reference mean, as a
+    // fraction of the reference spread.
+    pub mean_shift: f32,
+}
+
+impl DriftScore {
+    pub fn value(&self) -> f32 {
+        self.norm_shift.max(self.mean_shift)
+    }
+}
+
+struct RunningStats {
+    count: u64,
+    mean_norm: f64,
+    mean: Vec<f64>,
+}
+
+pub type DriftCallback = Box<dyn Fn(DriftScore) + Send + Sync>;
+
+pub struct QueryDriftMonitor {
+    reference: DriftReference,
+    config: DriftConfig,
+    running: Mutex<RunningStats>,
+    drifted: AtomicBool,
+    // Called once each time the score first exceeds the threshold.
+    on_drift: Option<DriftCallback>,
+}
+
+impl QueryDriftMonitor {
+    pub fn new(reference: DriftReference, config: DriftConfig) -> Result<Self, Box<dyn Error>> {
+        if config.window == 0 || config.threshold.is_nan() || config.threshold <= 0.0 {
+            return Err(util::invalid_argument_error(&format!(
+                "Drift window must be positive and threshold > 0, got {} and {}",
+                config.window, config.threshold
+            )));
+        }
+        let dim = reference.mean.len();
+        Ok(QueryDriftMonitor {
+            reference,
+            config,
+            running: Mutex::new(RunningStats {
+                count: 0,
+                mean_norm: 0.0,
+                mean: vec![0.0; dim],
+            }),
+            drifted: AtomicBool::new(false),
+            on_drift: None,
+        })
+    }
+
+    pub fn with_callback(mut self, on_drift: DriftCallback) -> Self {
+        self.on_drift = Some(on_drift);
+        self
+    }
+
+    pub fn reference(&self) -> &DriftReference {
+        &self.reference
+    }
+
+    // Folds one query into the running statistics. The first `window`
+    // queries are averaged uniformly, later ones exponentially. Queries of
+    // the wrong dimensionality are ignored; search rejects them anyway.
+    pub fn observe(&self, query: &[f32]) {
+        if query.len() != self.reference.mean.len() {
+            return;
+        }
+        let score = {
+            let mut running = self.running.lock().unwrap();
+            running.count += 1;
+            let weight = 1.0 / running.count.min(self.config.window as u64) as f64;
+            running.mean_norm += weight * (l2_norm(query) as f64 - running.mean_norm);
+            for (m, &v) in running.mean.iter_mut().zip(query.iter()) {
+                *m += weight * (v as f64 - *m);
+            }
+            self.score_of(&running)
+        };
+        let Some(score) = score else {
+            return;
+        };
+        let above = score.value() > self.config.threshold;
+        let was_above = self.drifted.swap(above, Ordering::AcqRel);
+        if above && !was_above {
+            if let Some(on_drift) = &self.on_drift {
+                on_drift(score);
+            }
+        }
+    }
+
+    // None until min_queries queries have been observed.
+    pub fn score(&self) -> Option<DriftScore> {
+        self.score_of(&self.running.lock().unwrap())
+    }
+
+    pub fn is_drifted(&self) -> bool {
+        self.drifted.load(Ordering::Acquire)
+    }
+
+    // Forgets observed queries, e.g. after re-embedding the corpus.
+    pub fn reset(&self) {
+        let mut running = self.running.lock().unwrap();
+        running.count = 0;
+        running.mean_norm = 0.0;
+        running.mean.iter_mut().for_each(|m| *m = 0.0);
+        self.drifted.store(false, Ordering::Release);
+    }
+
+    fn score_of(&self, running: &RunningStats) -> Option<DriftScore> {
+        if running.count < self.config.min_queries.max(1) as u64 {
+            return None;
+        }
+        let reference = &self.reference;
+        let norm_delta = (running.mean_norm - reference.mean_norm as f64).abs();
+        let mean_delta = running
+            .mean
+            .iter()
+            .zip(reference.mean.iter())
+            .map(|(&m, &r)| (m - r as f64).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        Some(DriftScore {
+            norm_shift: (norm_delta / (reference.norm_std as f64).max(f32::EPSILON as f64)) as f32,
+            mean_shift: (mean_delta / (reference.spread as f64).max(f32::EPSILON as f64)) as f32,
+        })
+    }
+}
+
+fn l2_norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
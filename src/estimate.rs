@@ -0,0 +1,207 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dry-run memory and time estimates for an index build.
+
+use super::{convert, quantization, tree, util};
+use std::error::Error;
+use std::time::Instant;
+
+// Sizing helpers shared with ScannRetriever::memory_usage so estimates and
+// measurements use the same accounting.
+pub(crate) fn row_bytes(dim: usize) -> usize {
+    dim * std::mem::size_of::<f32>() + std::mem::size_of::<Vec<f32>>()
+}
+
+pub(crate) fn dataset_bytes(n: usize, dim: usize) -> usize {
+    n * row_bytes(dim)
+}
+
+pub(crate) fn docid_bytes(n: usize) -> usize {
+    n * std::mem::size_of::<usize>() * 3
+}
+
+pub(crate) fn tree_bytes(num_leaves: usize, dim: usize, num_assignments: usize, width: util::IndexWidth) -> usize {
+    num_leaves * row_bytes(dim) + num_assignments * width.bytes()
+}
+
+pub(crate) fn int8_code_bytes(n: usize, dim: usize) -> usize {
+    n * (dim + std::mem::size_of::<Vec<i8>>()) + dim * std::mem::size_of::<f32>()
+}
+
+pub(crate) fn norm_bytes(n: usize) -> usize {
+    n * std::mem::size_of::<f32>()
+}
+
+fn dtype_bytes(dtype: convert::DType) -> usize {
+    match dtype {
+        convert::DType::F64 => 8,
+        convert::DType::F32 => 4,
+        convert::DType::I8 | convert::DType::U8 => 1,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DatasetMeta {
+    pub n: usize,
+    pub dim: usize,
+    pub dtype: convert::DType,
+}
+
+// Per-unit costs in nanoseconds. The defaults are rough figures for a
+// single modern core; estimate_calibrate replaces them with measurements.
+#[derive(Clone, Copy, Debug)]
+pub struct CostModel {
+    pub copy_ns_per_value: f64,
+    pub projection_ns_per_mac: f64,
+    pub kmeans_ns_per_mac: f64,
+    pub quantize_ns_per_value: f64,
+    pub norm_ns_per_value: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            copy_ns_per_value: 0.5,
+            projection_ns_per_mac: 0.5,
+            kmeans_ns_per_mac: 0.5,
+            quantize_ns_per_value: 2.0,
+            norm_ns_per_value: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BuildEstimate {
+    // (component, bytes), in build order.
+    pub memory: Vec<(String, usize)>,
+    pub peak_bytes: usize,
+    pub phase_seconds: Vec<(String, f64)>,
+    pub total_seconds: f64,
+    pub memory_budget: Option<usize>,
+    pub exceeds_budget: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BuildPlan {
+    pub num_leaves: Option<usize>,
+    pub kmeans_iterations: usize,
+    // Average number of leaves each row is stored in.
+    pub spilling_factor: f32,
+    pub projected_dims: Option<usize>,
+    pub int8_codes: bool,
+    pub norm_cache: bool,
+}
+
+impl BuildPlan {
+    pub fn new() -> Self {
+        BuildPlan {
+            num_leaves: None,
+            kmeans_iterations: 10,
+            spilling_factor: 1.0,
+            projected_dims: None,
+            int8_codes: false,
+            norm_cache: false,
+        }
+    }
+
+    pub fn estimate(&self, meta: &DatasetMeta, cost: &CostModel, memory_budget: Option<usize>) -> BuildEstimate {
+        let n = meta.n;
+        let dim = self.projected_dims.unwrap_or(meta.dim);
+        let mut estimate = BuildEstimate {
+            memory_budget,
+            ..Default::default()
+        };
+
+        // The input stays resident until the f32 copy is built.
+        estimate.memory.push(("input".to_string(), n * meta.dim * dtype_bytes(meta.dtype)));
+        estimate.memory.push(("dataset".to_string(), dataset_bytes(n, meta.dim)));
+        estimate.memory.push(("docids".to_string(), docid_bytes(n)));
+        let mut phases = vec![("copy".to_string(), (n * meta.dim) as f64 * cost.copy_ns_per_value)];
+        if let Some(projected_dims) = self.projected_dims {
+            estimate.memory.push(("projected_dataset".to_string(), dataset_bytes(n, projected_dims)));
+            phases.push((
+                "projection".to_string(),
+                (n * meta.dim * projected_dims) as f64 * cost.projection_ns_per_mac,
+            ));
+        }
+        if let Some(num_leaves) = self.num_leaves {
+            let assignments = (n as f64 * self.spilling_factor.max(1.0) as f64).ceil() as usize;
+            estimate.memory.push(("tree".to_string(), tree_bytes(num_leaves, dim, assignments, util::IndexWidth::for_size(n))));
+            phases.push((
+                "kmeans".to_string(),
+                self.kmeans_iterations as f64 * (n * num_leaves * dim) as f64 * cost.kmeans_ns_per_mac,
+            ));
+        }
+        if self.int8_codes {
+            estimate.memory.push(("int8_codes".to_string(), int8_code_bytes(n, dim)));
+            phases.push(("quantize".to_string(), (n * dim) as f64 * cost.quantize_ns_per_value));
+        }
+        if self.norm_cache {
+            estimate.memory.push(("norms".to_string(), norm_bytes(n)));
+            phases.push(("norms".to_string(), (n * dim) as f64 * cost.norm_ns_per_value));
+        }
+
+        estimate.peak_bytes = estimate.memory.iter().map(|(_, bytes)| bytes).sum();
+        estimate.phase_seconds = phases.into_iter().map(|(name, ns)| (name, ns * 1e-9)).collect();
+        estimate.total_seconds = estimate.phase_seconds.iter().map(|(_, s)| s).sum();
+        estimate.exceeds_budget = memory_budget.is_some_and(|budget| estimate.peak_bytes > budget);
+        estimate
+    }
+
+    // Times each phase of this plan on a small sample and derives per-unit
+    // costs for extrapolation. Projection is not timed and keeps its default.
+    pub fn estimate_calibrate(&self, sample: &util::DenseDataset<f32>) -> Result<CostModel, Box<dyn Error>> {
+        let n = sample.size();
+        let dim = sample.dimensionality();
+        if n == 0 || dim == 0 {
+            return Err(util::invalid_argument_error("Calibration sample must be non-empty"));
+        }
+        let values = (n * dim) as f64;
+        let mut cost = CostModel::default();
+
+        let start = Instant::now();
+        let copy = sample.clone();
+        cost.copy_ns_per_value = start.elapsed().as_nanos() as f64 / values;
+
+        if let Some(num_leaves) = self.num_leaves {
+            let num_leaves = num_leaves.clamp(1, n);
+            let mut options = tree::KMeansTreeTrainingOptions::new();
+            options.max_iterations = self.kmeans_iterations.max(1) as i32;
+            let start = Instant::now();
+            let result = tree::train_kmeans(&copy, num_leaves, &options)?;
+            let elapsed = start.elapsed().as_nanos() as f64;
+            let iterations = result.stats.iterations.max(1) as f64;
+            cost.kmeans_ns_per_mac = elapsed / (iterations * values * num_leaves as f64);
+        }
+
+        if self.int8_codes {
+            let start = Instant::now();
+            quantization::quantize_int8(&copy, &quantization::Int8QuantizationConfig::new())?;
+            cost.quantize_ns_per_value = start.elapsed().as_nanos() as f64 / values;
+        }
+
+        let start = Instant::now();
+        let norms: Vec<f32> = copy.data.iter().map(|row| row.iter().map(|v| v * v).sum::<f32>().sqrt()).collect();
+        cost.norm_ns_per_value = start.elapsed().as_nanos() as f64 / values;
+        std::hint::black_box(norms);
+        Ok(cost)
+    }
+}
+
+impl Default for BuildPlan {
+    fn default() -> Self {
+        BuildPlan::new()
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline evaluation helpers: recall, query-log replay and parameter tuning.
+
+use super::{query_log, reference, retrieval, util};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use std::time::Instant;
+
+// Fraction of `reference` ids present in `results`.
+pub fn recall(results: &[(usize, f32)], reference: &[(usize, f32)]) -> f32 {
+    if reference.is_empty() {
+        return 1.0;
+    }
+    let found: HashSet<usize> = results.iter().map(|&(id, _)| id).collect();
+    reference.iter().filter(|(id, _)| found.contains(id)).count() as f32 / reference.len() as f32
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayReport {
+    pub num_queries: usize,
+    pub mean_recall: f32,
+    pub mean_logged_latency_us: f64,
+    pub mean_replay_latency_us: f64,
+}
+
+// Re-runs logged queries, treating the logged results as the reference. Any
+// field set in `override_options` replaces the logged parameter.
+pub fn replay_log<P: AsRef<Path>>(
+    retriever: &retrieval::ScannRetriever,
+    log_path: P,
+    override_options: &retrieval::SearchOptions,
+) -> Result<ReplayReport, Box<dyn Error>> {
+    let records = query_log::read_query_log(log_path)?;
+    let mut report = ReplayReport {
+        num_queries: records.len(),
+        ..ReplayReport::default()
+    };
+    if records.is_empty() {
+        return Ok(report);
+    }
+    let mut recall_sum = 0.0f64;
+    for record in &records {
+        let mut options = override_options.clone();
+        options.k = options.k.or(Some(record.k));
+        options.leaves_to_search = options.leaves_to_search.or(record.leaves_to_search);
+        let start = Instant::now();
+        let (results, _) = retriever.search_with_options(&util::DatapointPtr::new(record.query.clone()), &options)?;
+        report.mean_replay_latency_us += start.elapsed().as_micros() as f64;
+        report.mean_logged_latency_us += record.latency_us as f64;
+        recall_sum += recall(&results, &record.results) as f64;
+    }
+    let n = records.len() as f64;
+    report.mean_recall = (recall_sum / n) as f32;
+    report.mean_logged_latency_us /= n;
+    report.mean_replay_latency_us /= n;
+    Ok(report)
+}
+
+// Exact top-k docids per query from the f64 reference searcher, in the
+// shape `tune` expects. Row i of `dataset` has docid `docids[i]`.
+pub fn exact_ground_truth(
+    dataset: &util::DenseDataset<f32>,
+    docids: Vec<usize>,
+    measure_name: &str,
+    queries: &util::DenseDataset<f32>,
+    k: usize,
+) -> Result<Vec<Vec<usize>>, Box<dyn Error>> {
+    let Some(metric) = reference::ReferenceMetric::from_measure_name(measure_name) else {
+        return Err(util::invalid_argument_error(&format!(
+            "No reference implementation for distance measure '{}'",
+            measure_name
+        )));
+    };
+    let searcher = reference::BruteForceF64Searcher::new(dataset, docids, metric)?;
+    Ok(searcher
+        .search_batched(queries, k)?
+        .into_iter()
+        .map(|results| results.into_iter().map(|(docid, _)| docid).collect())
+        .collect())
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TuningPoint {
+    pub options: retrieval::SearchOptions,
+    pub recall: f32,
+    pub mean_latency_us: f64,
+    // No other point has recall >= and latency <= with one strictly better.
+    pub pareto_optimal: bool,
+}
+
+// Cartesian product of leaves_to_search and rescore_candidates values over
+// `base`. Any other SearchOptions field can be tuned by building the grid
+// by hand.
+pub fn search_grid(
+    base: &retrieval::SearchOptions,
+    leaves_to_search: &[usize],
+    rescore_candidates: &[Option<usize>],
+) -> Vec<retrieval::SearchOptions> {
+    let mut grid = Vec::with_capacity(leaves_to_search.len() * rescore_candidates.len());
+    for &leaves in leaves_to_search {
+        for &candidates in rescore_candidates {
+            let mut options = base.clone();
+            options.leaves_to_search = Some(leaves);
+            options.rescore_candidates = candidates;
+            grid.push(options);
+        }
+    }
+    grid
+}
+
+fn mark_pareto_frontier(points: &mut [TuningPoint]) {
+    for i in 0..points.len() {
+        let dominated = points.iter().enumerate().any(|(j, other)| {
+            j != i
+                && other.recall >= points[i].recall
+                && other.mean_latency_us <= points[i].mean_latency_us
+                && (other.recall > points[i].recall || other.mean_latency_us < points[i].mean_latency_us)
+        });
+        points[i].pareto_optimal = !dominated;
+    }
+}
+
+// Evaluates every configuration in `param_grid` for recall@k against
+// `ground_truth` (docids, nearest first) and mean search latency. Points are
+// returned in grid order with the Pareto-optimal subset flagged.
+pub fn tune(
+    retriever: &retrieval::ScannRetriever,
+    queries: &util::DenseDataset<f32>,
+    ground_truth: &[Vec<usize>],
+    param_grid: &[retrieval::SearchOptions],
+) -> Result<Vec<TuningPoint>, Box<dyn Error>> {
+    if queries.size() != ground_truth.len() {
+        return Err(util::invalid_argument_error(&format!(
+            "Got {} queries but {} ground truth lists",
+            queries.size(),
+            ground_truth.len()
+        )));
+    }
+    if queries.size() == 0 {
+        return Err(util::invalid_argument_error("Tuning requires at least one query"));
+    }
+    let mut points = Vec::with_capacity(param_grid.len());
+    for options in param_grid {
+        let mut recall_sum = 0.0f64;
+        let mut latency_sum = 0.0f64;
+        for (query, truth) in queries.data.iter().zip(ground_truth.iter()) {
+            let start = Instant::now();
+            let (results, _) = retriever.search_with_options(&util::DatapointPtr::from_slice(query), options)?;
+            latency_sum += start.elapsed().as_micros() as f64;
+            let k = results.len().max(options.k.unwrap_or(0));
+            let reference: Vec<(usize, f32)> = truth.iter().take(k).map(|&id| (id, 0.0)).collect();
+            recall_sum += recall(&results, &reference) as f64;
+        }
+        let n = queries.size() as f64;
+        points.push(TuningPoint {
+            options: options.clone(),
+            recall: (recall_sum / n) as f32,
+            mean_latency_us: latency_sum / n,
+            pareto_optimal: false,
+        });
+    }
+    mark_pareto_frontier(&mut points);
+    Ok(points)
+}
+
+// Lowest-latency point reaching `target_recall`; ties go to the earlier
+// grid entry.
+pub fn cheapest_meeting_recall(points: &[TuningPoint], target_recall: f32) -> Option<&TuningPoint> {
+    points
+        .iter()
+        .filter(|p| p.recall >= target_recall)
+        .min_by(|a, b| a.mean_latency_us.total_cmp(&b.mean_latency_us))
+}
@@ -0,0 +1,116 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C ABI for serving from a packed blob.
+//!
+//! Handles are opaque `ScannRetriever` pointers. Functions never unwind
+//! across the boundary: failures come back as a null handle or a negative
+//! count.
+
+use super::{distance_measures, retrieval::ScannRetriever, util};
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Largest k served by scann_search_small_k.
+pub const SCANN_SMALL_K_MAX: usize = 4;
+
+/// Loads a blob written by `ScannRetriever::pack_blob`. Returns null on any
+/// failure. Release the handle with `scann_retriever_free`.
+///
+/// # Safety
+///
+/// `path` and `distance_measure` must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn scann_retriever_load_blob(
+    path: *const c_char,
+    distance_measure: *const c_char,
+    k: usize,
+) -> *mut ScannRetriever {
+    if path.is_null() || distance_measure.is_null() {
+        return std::ptr::null_mut();
+    }
+    let (Ok(path), Ok(name)) = (CStr::from_ptr(path).to_str(), CStr::from_ptr(distance_measure).to_str()) else {
+        return std::ptr::null_mut();
+    };
+    let loaded = catch_unwind(|| {
+        let measure = distance_measures::get_distance_measure_by_name(name)?;
+        ScannRetriever::load_blob(path, measure, k)
+    });
+    match loaded {
+        Ok(Ok(retriever)) => Box::into_raw(Box::new(retriever)),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `retriever` must be null or a handle from `scann_retriever_load_blob`
+/// that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn scann_retriever_free(retriever: *mut ScannRetriever) {
+    if !retriever.is_null() {
+        drop(Box::from_raw(retriever));
+    }
+}
+
+/// Exact top-`k` for 1 <= k <= SCANN_SMALL_K_MAX through
+/// `ScannRetriever::search_small_k`, without heap allocation. Writes `k`
+/// docids and distances (unfilled slots hold -1 and +inf) and returns the
+/// number filled, or -1 on invalid arguments or a rejected query.
+///
+/// # Safety
+///
+/// `retriever` must be a live handle, `query` must point to `dim` floats,
+/// and `ids` and `distances` must each have room for `k` values.
+#[no_mangle]
+pub unsafe extern "C" fn scann_search_small_k(
+    retriever: *const ScannRetriever,
+    query: *const f32,
+    dim: usize,
+    k: usize,
+    ids: *mut i64,
+    distances: *mut f32,
+) -> i64 {
+    if retriever.is_null() || query.is_null() || ids.is_null() || distances.is_null() {
+        return -1;
+    }
+    let retriever = &*retriever;
+    let query = std::slice::from_raw_parts(query, dim);
+    let ids = std::slice::from_raw_parts_mut(ids, k);
+    let distances = std::slice::from_raw_parts_mut(distances, k);
+    let searched = catch_unwind(AssertUnwindSafe(|| match k {
+        1 => small_k::<1>(retriever, query, ids, distances),
+        2 => small_k::<2>(retriever, query, ids, distances),
+        3 => small_k::<3>(retriever, query, ids, distances),
+        4 => small_k::<4>(retriever, query, ids, distances),
+        _ => None,
+    }));
+    match searched {
+        Ok(Some(filled)) => filled as i64,
+        _ => -1,
+    }
+}
+
+fn small_k<const K: usize>(
+    retriever: &ScannRetriever,
+    query: &[f32],
+    ids: &mut [i64],
+    distances: &mut [f32],
+) -> Option<usize> {
+    let (found_ids, found_distances, filled) =
+        retriever.search_small_k::<K>(util::DatapointRef::from_slice(query)).ok()?;
+    ids.copy_from_slice(&found_ids);
+    distances.copy_from_slice(&found_distances);
+    Some(filled)
+}
@@ -0,0 +1,86 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reciprocal rank fusion of result lists from several retrievers.
+
+use super::util;
+use std::collections::HashMap;
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contribution {
+    pub list: usize,
+    // 1-based rank within that list.
+    pub rank: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FusedResult {
+    pub docid: usize,
+    pub score: f32,
+    // Lists that contained the docid, in list order.
+    pub contributions: Vec<Contribution>,
+}
+
+// Standard RRF with every list weighted equally: score(d) is the sum over
+// lists containing d of 1 / (rrf_k + rank). Lists are (docid, score) pairs
+// already in rank order; their scores are not used.
+pub fn reciprocal_rank_fusion(
+    lists: &[&[(usize, f32)]],
+    k: usize,
+    rrf_k: f32,
+) -> Result<Vec<FusedResult>, Box<dyn Error>> {
+    weighted_reciprocal_rank_fusion(lists, &vec![1.0; lists.len()], k, rrf_k)
+}
+
+// Like reciprocal_rank_fusion with each list's term scaled by its weight.
+// A docid repeated within one list counts at its best rank only. Results
+// are ordered by descending score, ties by lowest docid.
+pub fn weighted_reciprocal_rank_fusion(
+    lists: &[&[(usize, f32)]],
+    weights: &[f32],
+    k: usize,
+    rrf_k: f32,
+) -> Result<Vec<FusedResult>, Box<dyn Error>> {
+    if weights.len() != lists.len() {
+        return Err(util::invalid_argument_error(&format!(
+            "Got {} weights for {} lists",
+            weights.len(),
+            lists.len()
+        )));
+    }
+    if rrf_k.is_nan() || rrf_k < 0.0 {
+        return Err(util::invalid_argument_error(&format!("rrf_k must be non-negative, got {}", rrf_k)));
+    }
+    let mut fused: HashMap<usize, FusedResult> = HashMap::new();
+    for (l, (list, &weight)) in lists.iter().zip(weights.iter()).enumerate() {
+        for (r, &(docid, _)) in list.iter().enumerate() {
+            let entry = fused.entry(docid).or_insert_with(|| FusedResult {
+                docid,
+                score: 0.0,
+                contributions: Vec::new(),
+            });
+            if entry.contributions.last().is_some_and(|c| c.list == l) {
+                continue;
+            }
+            let rank = r + 1;
+            entry.score += weight / (rrf_k + rank as f32);
+            entry.contributions.push(Contribution { list: l, rank });
+        }
+    }
+    let mut results: Vec<FusedResult> = fused.into_values().collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.docid.cmp(&b.docid)));
+    results.truncate(k);
+    Ok(results)
+}
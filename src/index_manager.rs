@@ -0,0 +1,182 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lazily loaded, memory-bounded collection of named retrievers.
+
+use super::{artifacts, quick, retrieval, util};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Builds an index on first use and again after each eviction.
+pub type IndexLoader = Arc<dyn Fn() -> Result<retrieval::ScannRetriever, Box<dyn Error>> + Send + Sync>;
+
+struct ManagedIndex {
+    loader: IndexLoader,
+    // Held for the whole load so concurrent first queries wait for a single
+    // load instead of racing to build duplicate copies.
+    loaded: Mutex<Option<Arc<retrieval::ScannRetriever>>>,
+    bytes: AtomicU64,
+    last_used: AtomicU64,
+}
+
+pub struct IndexManager {
+    indexes: RwLock<HashMap<String, Arc<ManagedIndex>>>,
+    pinned: RwLock<HashSet<String>>,
+    budget_bytes: AtomicU64,
+    clock: AtomicU64,
+}
+
+impl IndexManager {
+    pub fn new() -> Self {
+        IndexManager {
+            indexes: RwLock::new(HashMap::new()),
+            pinned: RwLock::new(HashSet::new()),
+            budget_bytes: AtomicU64::new(u64::MAX),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    // Registers the artifacts directory at `artifacts_path` under `name`.
+    // Nothing is read until the first search; the retriever returns
+    // DEFAULT_K neighbors unless SearchOptions::k says otherwise.
+    pub fn register<P: Into<PathBuf>>(&self, name: &str, artifacts_path: P) {
+        let path = artifacts_path.into();
+        self.register_loader(
+            name,
+            Arc::new(move || retrieval::ScannRetriever::from_artifacts(artifacts::load_artifacts(&path)?, quick::DEFAULT_K)),
+        );
+    }
+
+    // Registers an index built by `loader`, for indexes that do not come
+    // from an artifacts directory.
+    pub fn register_loader(&self, name: &str, loader: IndexLoader) {
+        self.indexes.write().unwrap().insert(
+            name.to_string(),
+            Arc::new(ManagedIndex {
+                loader,
+                loaded: Mutex::new(None),
+                bytes: AtomicU64::new(0),
+                last_used: AtomicU64::new(0),
+            }),
+        );
+    }
+
+    pub fn set_budget(&self, bytes: u64) {
+        self.budget_bytes.store(bytes, Ordering::SeqCst);
+        self.evict_to_budget(None);
+    }
+
+    pub fn pin(&self, name: &str) {
+        self.pinned.write().unwrap().insert(name.to_string());
+    }
+
+    pub fn unpin(&self, name: &str) {
+        self.pinned.write().unwrap().remove(name);
+    }
+
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.indexes
+            .read()
+            .unwrap()
+            .get(name)
+            .is_some_and(|entry| entry.loaded.lock().unwrap().is_some())
+    }
+
+    pub fn resident_bytes(&self) -> u64 {
+        self.indexes
+            .read()
+            .unwrap()
+            .values()
+            .map(|entry| entry.bytes.load(Ordering::SeqCst))
+            .sum()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<retrieval::ScannRetriever>, Box<dyn Error>> {
+        let entry = self
+            .indexes
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| util::invalid_argument_error(&format!("Unknown index: '{}'", name)))?;
+        entry.last_used.store(self.clock.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+
+        let retriever = {
+            let mut loaded = entry.loaded.lock().unwrap();
+            match loaded.as_ref() {
+                Some(retriever) => retriever.clone(),
+                None => {
+                    let retriever = Arc::new((entry.loader)()?);
+                    entry.bytes.store(retriever.memory_usage() as u64, Ordering::SeqCst);
+                    *loaded = Some(retriever.clone());
+                    retriever
+                }
+            }
+        };
+        self.evict_to_budget(Some(name));
+        Ok(retriever)
+    }
+
+    pub fn search(
+        &self,
+        name: &str,
+        query: &util::DatapointPtr<f32>,
+        options: &retrieval::SearchOptions,
+    ) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        let retriever = self.get(name)?;
+        retriever.search_with_options(query, options).map(|(results, _)| results)
+    }
+
+    // Evicts least recently used, unpinned indexes until resident bytes fit
+    // the budget. The index that triggered the check is never evicted, and
+    // searches already holding an evicted retriever finish on their Arc.
+    // Entry locks are only taken after the registry lock is released, so a
+    // slow load never blocks register or lookups of other indexes.
+    fn evict_to_budget(&self, keep: Option<&str>) {
+        let budget = self.budget_bytes.load(Ordering::SeqCst);
+        let pinned = self.pinned.read().unwrap().clone();
+        let (mut candidates, mut resident) = {
+            let indexes = self.indexes.read().unwrap();
+            let candidates: Vec<Arc<ManagedIndex>> = indexes
+                .iter()
+                .filter(|(name, entry)| {
+                    Some(name.as_str()) != keep && !pinned.contains(*name) && entry.bytes.load(Ordering::SeqCst) > 0
+                })
+                .map(|(_, entry)| entry.clone())
+                .collect();
+            let resident: u64 = indexes.values().map(|entry| entry.bytes.load(Ordering::SeqCst)).sum();
+            (candidates, resident)
+        };
+        candidates.sort_by_key(|entry| entry.last_used.load(Ordering::SeqCst));
+
+        for entry in candidates {
+            if resident <= budget {
+                break;
+            }
+            let mut loaded = entry.loaded.lock().unwrap();
+            if loaded.take().is_some() {
+                resident = resident.saturating_sub(entry.bytes.swap(0, Ordering::SeqCst));
+            }
+        }
+    }
+}
+
+impl Default for IndexManager {
+    fn default() -> Self {
+        IndexManager::new()
+    }
+}
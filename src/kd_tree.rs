@@ -0,0 +1,165 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exact k-d tree search under squared L2 for low-dimensional data.
+
+use super::util;
+use std::collections::BinaryHeap;
+use std::error::Error;
+
+pub const MAX_KD_TREE_DIMENSIONALITY: usize = 16;
+
+enum KdNode {
+    Leaf(Vec<usize>),
+    Split {
+        dim: usize,
+        value: f32,
+        left: Box<KdNode>,
+        right: Box<KdNode>,
+    },
+}
+
+#[derive(PartialEq)]
+struct Candidate(f32, usize);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+// Pruning structure, not an approximation: search returns exactly the
+// brute-force squared-L2 top-k.
+pub struct KdTree {
+    root: KdNode,
+    dimensionality: usize,
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(&x, &y)| (x - y) * (x - y)).sum()
+}
+
+fn build_node(data: &impl util::RowSource<f32>, mut indices: Vec<usize>, leaf_size: usize) -> KdNode {
+    if indices.len() <= leaf_size {
+        return KdNode::Leaf(indices);
+    }
+    let mut best_dim = 0;
+    let mut best_spread = -1.0f32;
+    for dim in 0..data.dimensionality() {
+        let (lo, hi) = indices.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &i| {
+            let v = data.row(i)[dim];
+            (lo.min(v), hi.max(v))
+        });
+        if hi - lo > best_spread {
+            best_spread = hi - lo;
+            best_dim = dim;
+        }
+    }
+    if best_spread <= 0.0 {
+        return KdNode::Leaf(indices);
+    }
+    let mid = indices.len() / 2;
+    indices.select_nth_unstable_by(mid, |&a, &b| data.row(a)[best_dim].total_cmp(&data.row(b)[best_dim]));
+    let value = data.row(indices[mid])[best_dim];
+    let right = indices.split_off(mid);
+    KdNode::Split {
+        dim: best_dim,
+        value,
+        left: Box::new(build_node(data, indices, leaf_size)),
+        right: Box::new(build_node(data, right, leaf_size)),
+    }
+}
+
+impl KdTree {
+    pub fn build(data: &impl util::RowSource<f32>, leaf_size: usize) -> Result<Self, Box<dyn Error>> {
+        if data.dimensionality() == 0 || data.dimensionality() > MAX_KD_TREE_DIMENSIONALITY {
+            return Err(util::invalid_argument_error(&format!(
+                "k-d tree supports dimensionality 1..={}, got {}",
+                MAX_KD_TREE_DIMENSIONALITY,
+                data.dimensionality()
+            )));
+        }
+        Ok(KdTree {
+            root: build_node(data, (0..data.num_rows()).collect(), leaf_size.max(1)),
+            dimensionality: data.dimensionality(),
+        })
+    }
+
+    pub fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    // Exact top-k rows by squared L2, ascending, skipping rows for which
+    // `keep` returns false. Ties are broken by row index.
+    pub fn search(
+        &self,
+        data: &impl util::RowSource<f32>,
+        query: &[f32],
+        k: usize,
+        keep: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search_node(&self.root, data, query, k, &keep, &mut heap);
+        }
+        let mut results: Vec<(usize, f32)> = heap.into_iter().map(|Candidate(d, i)| (i, d)).collect();
+        results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        results
+    }
+
+    fn search_node(
+        &self,
+        node: &KdNode,
+        data: &impl util::RowSource<f32>,
+        query: &[f32],
+        k: usize,
+        keep: &impl Fn(usize) -> bool,
+        heap: &mut BinaryHeap<Candidate>,
+    ) {
+        match node {
+            KdNode::Leaf(indices) => {
+                for &i in indices {
+                    if !keep(i) {
+                        continue;
+                    }
+                    let candidate = Candidate(squared_l2(query, data.row(i)), i);
+                    if heap.len() < k {
+                        heap.push(candidate);
+                    } else if candidate < *heap.peek().unwrap() {
+                        heap.pop();
+                        heap.push(candidate);
+                    }
+                }
+            }
+            KdNode::Split { dim, value, left, right } => {
+                let diff = query[*dim] - value;
+                let (near, far) = if diff < 0.0 { (left, right) } else { (right, left) };
+                self.search_node(near, data, query, k, keep, heap);
+                // Ties at the bound must still be visited so index-based
+                // tie-breaking matches brute force.
+                if heap.len() < k || diff * diff <= heap.peek().unwrap().0 {
+                    self.search_node(far, data, query, k, keep, heap);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-leaf int8 code storage, fully resident or loaded lazily from disk.
+//!
+//! Leaf code files are a binfmt section (magic "SCNLEAF1") whose payload is
+//!   num_leaves u64 | dim u64 | num_rows u64 | clip_quantile f32 (NaN = none)
+//!   dim x f32 multipliers | num_leaves x { file offset u64 | len u64 }
+//!   per leaf: count u64 | count x row u64 | count x dim x i8
+//! in the byte order declared by the header. Version 1 files lack the
+//! clip_quantile field and read as unclipped.
+
+use super::{binfmt, quantization, tree, util, ScannError};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const LEAF_CODES_MAGIC: &[u8; 8] = b"SCNLEAF1";
+const LEAF_CODES_VERSION: u32 = 2;
+
+// Codes of one leaf, row-major, with the dataset row each code belongs to.
+pub struct LeafCodes {
+    pub rows: util::IndexList,
+    pub codes: Vec<i8>,
+}
+
+impl LeafCodes {
+    pub fn code(&self, j: usize, dim: usize) -> &[i8] {
+        &self.codes[j * dim..(j + 1) * dim]
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.rows.heap_bytes() + self.codes.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LeafLoad {
+    pub cache_hit: bool,
+    pub load_micros: u64,
+}
+
+// A leaf's codes and how they were obtained.
+pub type LoadedLeaf = (Arc<LeafCodes>, LeafLoad);
+
+pub trait LeafCodeStore: Send + Sync {
+    fn num_leaves(&self) -> usize;
+    fn dimensionality(&self) -> usize;
+    // Dataset rows the store was built from; a mismatch means it is stale.
+    fn num_rows(&self) -> usize;
+    fn multipliers(&self) -> &[f32];
+    // Quantile the multipliers were clipped at when the codes were built.
+    fn clip_quantile(&self) -> Option<f32>;
+    fn load(&self, leaf: usize) -> Result<LoadedLeaf, Box<dyn Error>>;
+
+    // Loads every leaf selected for a query before scanning starts. The
+    // returned handles keep the leaves alive even if the cache evicts them
+    // mid-scan.
+    fn prefetch(&self, leaves: &[usize]) -> Result<Vec<LoadedLeaf>, Box<dyn Error>> {
+        leaves.iter().map(|&leaf| self.load(leaf)).collect()
+    }
+}
+
+pub fn dequantize_code(code: &[i8], multipliers: &[f32], out: &mut Vec<f32>) {
+    out.clear();
+    out.extend(
+        code.iter()
+            .zip(multipliers.iter())
+            .map(|(&c, &m)| if m == 0.0 { 0.0 } else { c as f32 / m }),
+    );
+}
+
+fn check_leaf(leaf: usize, num_leaves: usize) -> Result<(), Box<dyn Error>> {
+    if leaf >= num_leaves {
+        return Err(util::invalid_argument_error(&format!(
+            "Leaf {} out of range: store has {} leaves",
+            leaf, num_leaves
+        )));
+    }
+    Ok(())
+}
+
+fn gather_leaf(tree: &tree::KMeansTree, quantized: &quantization::Int8QuantizedDataset, leaf: usize) -> LeafCodes {
+    let rows = tree.leaf(leaf).clone();
+    let codes = rows.iter().flat_map(|i| quantized.codes.data[i].iter().copied()).collect();
+    LeafCodes { rows, codes }
+}
+
+pub struct InMemoryLeafCodeStore {
+    leaves: Vec<Arc<LeafCodes>>,
+    multipliers: Vec<f32>,
+    clip_quantile: Option<f32>,
+    dim: usize,
+    num_rows: usize,
+}
+
+impl InMemoryLeafCodeStore {
+    pub fn build(tree: &tree::KMeansTree, quantized: &quantization::Int8QuantizedDataset) -> Self {
+        InMemoryLeafCodeStore {
+            leaves: (0..tree.num_leaves()).map(|leaf| Arc::new(gather_leaf(tree, quantized, leaf))).collect(),
+            multipliers: quantized.multipliers.clone(),
+            clip_quantile: quantized.clip_quantile,
+            dim: quantized.codes.dimensionality(),
+            num_rows: quantized.codes.size(),
+        }
+    }
+}
+
+impl LeafCodeStore for InMemoryLeafCodeStore {
+    fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dim
+    }
+
+    fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn multipliers(&self) -> &[f32] {
+        &self.multipliers
+    }
+
+    fn clip_quantile(&self) -> Option<f32> {
+        self.clip_quantile
+    }
+
+    fn load(&self, leaf: usize) -> Result<LoadedLeaf, Box<dyn Error>> {
+        check_leaf(leaf, self.leaves.len())?;
+        Ok((
+            self.leaves[leaf].clone(),
+            LeafLoad {
+                cache_hit: true,
+                load_micros: 0,
+            },
+        ))
+    }
+}
+
+pub fn write_leaf_codes<P: AsRef<Path>>(
+    path: P,
+    tree: &tree::KMeansTree,
+    quantized: &quantization::Int8QuantizedDataset,
+) -> Result<(), Box<dyn Error>> {
+    write_leaf_codes_with_order(path, tree, quantized, binfmt::Endianness::Little)
+}
+
+pub fn write_leaf_codes_with_order<P: AsRef<Path>>(
+    path: P,
+    tree: &tree::KMeansTree,
+    quantized: &quantization::Int8QuantizedDataset,
+    endianness: binfmt::Endianness,
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let order = binfmt::ByteOrder(endianness);
+    let num_leaves = tree.num_leaves();
+    let dim = quantized.codes.dimensionality();
+    let mut leaf_data = Vec::new();
+    let mut table = Vec::with_capacity(num_leaves);
+    let data_start = binfmt::HEADER_LEN + 3 * 8 + 4 + dim * 4 + num_leaves * 16;
+    for leaf in 0..num_leaves {
+        let codes = gather_leaf(tree, quantized, leaf);
+        let start = leaf_data.len();
+        order.put_u64(&mut leaf_data, codes.rows.len() as u64);
+        for row in &codes.rows {
+            order.put_u64(&mut leaf_data, row as u64);
+        }
+        leaf_data.extend(codes.codes.iter().map(|&c| c as u8));
+        table.push(((data_start + start) as u64, (leaf_data.len() - start) as u64));
+    }
+
+    let mut payload = Vec::with_capacity(data_start - binfmt::HEADER_LEN + leaf_data.len());
+    for header in [num_leaves as u64, dim as u64, quantized.codes.size() as u64] {
+        order.put_u64(&mut payload, header);
+    }
+    order.put_f32(&mut payload, quantized.clip_quantile.unwrap_or(f32::NAN));
+    for &m in &quantized.multipliers {
+        order.put_f32(&mut payload, m);
+    }
+    for &(offset, len) in &table {
+        order.put_u64(&mut payload, offset);
+        order.put_u64(&mut payload, len);
+    }
+    payload.extend(leaf_data);
+    let out = binfmt::write_section(LEAF_CODES_MAGIC, LEAF_CODES_VERSION, endianness, &payload);
+    let mut file = File::create(path).map_err(|e| ScannError {
+        message: format!("Failed to create leaf codes {}: {}", path.display(), e),
+    })?;
+    file.write_all(&out).map_err(|e| ScannError {
+        message: format!("Failed to write leaf codes {}: {}", path.display(), e),
+    })?;
+    Ok(())
+}
+
+// Byte-capped LRU of decoded leaves.
+struct LeafCache {
+    leaves: HashMap<usize, (Arc<LeafCodes>, u64)>,
+    recency: BTreeMap<u64, usize>,
+    next_tick: u64,
+    bytes: usize,
+}
+
+impl LeafCache {
+    fn get(&mut self, leaf: usize) -> Option<Arc<LeafCodes>> {
+        let tick = self.next_tick;
+        let (codes, last_used) = self.leaves.get_mut(&leaf)?;
+        self.recency.remove(&*last_used);
+        *last_used = tick;
+        self.recency.insert(tick, leaf);
+        self.next_tick += 1;
+        Some(codes.clone())
+    }
+
+    fn insert(&mut self, leaf: usize, codes: Arc<LeafCodes>, capacity_bytes: usize) {
+        if self.leaves.contains_key(&leaf) {
+            return;
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.bytes += codes.size_bytes();
+        self.leaves.insert(leaf, (codes, tick));
+        self.recency.insert(tick, leaf);
+        // The newest leaf always stays, even if it alone exceeds the cap.
+        while self.bytes > capacity_bytes && self.leaves.len() > 1 {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((codes, _)) = self.leaves.remove(&oldest) {
+                self.bytes -= codes.size_bytes();
+            }
+        }
+    }
+}
+
+// Reads each leaf's byte range from a leaf codes file on first use and
+// keeps recently used leaves in a byte-capped LRU. Only the header is
+// validated at open; the payload checksum would require reading every leaf.
+pub struct FileLeafCodeStore {
+    path: PathBuf,
+    file: Mutex<File>,
+    order: binfmt::ByteOrder,
+    table: Vec<(u64, u64)>,
+    multipliers: Vec<f32>,
+    clip_quantile: Option<f32>,
+    dim: usize,
+    num_rows: usize,
+    capacity_bytes: usize,
+    cache: Mutex<LeafCache>,
+}
+
+impl FileLeafCodeStore {
+    pub fn open<P: AsRef<Path>>(path: P, capacity_bytes: usize) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let io_error = |e: std::io::Error| -> Box<dyn Error> {
+            Box::new(ScannError {
+                message: format!("Failed to read leaf codes {}: {}", path.display(), e),
+            })
+        };
+        let mut file = File::open(&path).map_err(io_error)?;
+        let file_len = file.metadata().map_err(io_error)?.len();
+        let mut header = [0u8; binfmt::HEADER_LEN + 24];
+        file.read_exact(&mut header).map_err(io_error)?;
+        let available = file_len.saturating_sub(binfmt::HEADER_LEN as u64);
+        let parsed = binfmt::read_header(&header, LEAF_CODES_MAGIC, LEAF_CODES_VERSION, Some(available))
+            .map_err(|e| util::invalid_argument_error(&format!("Leaf codes {}: {}", path.display(), e)))?;
+        let order = binfmt::ByteOrder(parsed.endianness);
+        let num_leaves = order.u64(&header, binfmt::HEADER_LEN) as usize;
+        let dim = order.u64(&header, binfmt::HEADER_LEN + 8) as usize;
+        let num_rows = order.u64(&header, binfmt::HEADER_LEN + 16) as usize;
+        let clip_bytes = if parsed.version >= 2 { 4 } else { 0 };
+        let table_bytes = (dim as u64)
+            .checked_mul(4)
+            .zip((num_leaves as u64).checked_mul(16))
+            .and_then(|(multipliers, entries)| multipliers.checked_add(entries))
+            .and_then(|n| n.checked_add(clip_bytes));
+        let Some(table_bytes) = table_bytes.filter(|&n| n <= file_len.saturating_sub(header.len() as u64)) else {
+            return Err(util::invalid_argument_error(&format!(
+                "Leaf codes {} is truncated: header declares {} leaves of dimensionality {}",
+                path.display(),
+                num_leaves,
+                dim
+            )));
+        };
+        let mut rest = vec![0u8; table_bytes as usize];
+        file.read_exact(&mut rest).map_err(io_error)?;
+        let clip_quantile = match clip_bytes {
+            0 => None,
+            _ => Some(order.f32(&rest, 0)).filter(|q| !q.is_nan()),
+        };
+        let rest = &rest[clip_bytes as usize..];
+        let multipliers = (0..dim).map(|d| order.f32(rest, d * 4)).collect();
+        let mut table = Vec::with_capacity(num_leaves);
+        for leaf in 0..num_leaves {
+            let at = dim * 4 + leaf * 16;
+            let (offset, len) = (order.u64(rest, at), order.u64(rest, at + 8));
+            if offset.checked_add(len).is_none_or(|end| end > file_len) {
+                return Err(util::invalid_argument_error(&format!(
+                    "Leaf {} range {}+{} exceeds leaf codes file length {}",
+                    leaf, offset, len, file_len
+                )));
+            }
+            table.push((offset, len));
+        }
+        Ok(FileLeafCodeStore {
+            path,
+            file: Mutex::new(file),
+            order,
+            table,
+            multipliers,
+            clip_quantile,
+            dim,
+            num_rows,
+            capacity_bytes,
+            cache: Mutex::new(LeafCache {
+                leaves: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_tick: 0,
+                bytes: 0,
+            }),
+        })
+    }
+
+    pub fn resident_bytes(&self) -> usize {
+        self.cache.lock().unwrap().bytes
+    }
+
+    fn read_leaf(&self, leaf: usize) -> Result<LeafCodes, Box<dyn Error>> {
+        let (offset, len) = self.table[leaf];
+        let mut bytes = vec![0u8; len as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut bytes))
+                .map_err(|e| ScannError {
+                    message: format!("Failed to read leaf {} from {}: {}", leaf, self.path.display(), e),
+                })?;
+        }
+        let corrupt = || {
+            util::invalid_argument_error(&format!("Leaf {} in {} is corrupt", leaf, self.path.display()))
+        };
+        if bytes.len() < 8 {
+            return Err(corrupt());
+        }
+        let count = self.order.u64(&bytes, 0) as usize;
+        let expected = count.checked_mul(8 + self.dim).and_then(|n| n.checked_add(8));
+        if expected != Some(bytes.len()) {
+            return Err(corrupt());
+        }
+        let width = util::IndexWidth::for_size(self.num_rows);
+        let rows = util::IndexList::from_indices(width, (0..count).map(|j| self.order.u64(&bytes, 8 + j * 8) as usize));
+        if rows.iter().any(|row| row >= self.num_rows) {
+            return Err(corrupt());
+        }
+        let codes = bytes[8 + count * 8..].iter().map(|&b| b as i8).collect();
+        Ok(LeafCodes { rows, codes })
+    }
+}
+
+impl LeafCodeStore for FileLeafCodeStore {
+    fn num_leaves(&self) -> usize {
+        self.table.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dim
+    }
+
+    fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn multipliers(&self) -> &[f32] {
+        &self.multipliers
+    }
+
+    fn clip_quantile(&self) -> Option<f32> {
+        self.clip_quantile
+    }
+
+    fn load(&self, leaf: usize) -> Result<LoadedLeaf, Box<dyn Error>> {
+        check_leaf(leaf, self.table.len())?;
+        if let Some(codes) = self.cache.lock().unwrap().get(leaf) {
+            return Ok((codes, LeafLoad { cache_hit: true, load_micros: 0 }));
+        }
+        // Read outside the cache lock; concurrent misses on the same leaf
+        // may both read it, and the first insert wins.
+        let start = Instant::now();
+        let codes = Arc::new(self.read_leaf(leaf)?);
+        let load_micros = start.elapsed().as_micros() as u64;
+        self.cache.lock().unwrap().insert(leaf, codes.clone(), self.capacity_bytes);
+        Ok((codes, LeafLoad { cache_hit: false, load_micros }))
+    }
+}
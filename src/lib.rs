@@ -13,22 +13,68 @@
 // limitations under the License.
 
 //! ScaNN (Scalable Nearest Neighbors) library with RETRO model integration.
+//!
+//! The `quick` module runs the whole pipeline in one call:
+//!
+//! ```
+//! let data = vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![0.0, 2.0]];
+//! let queries = vec![vec![0.9, 0.1]];
+//! let neighbors = scann::quick::nearest_neighbors(&data, &queries, 2, "SquaredL2Distance").unwrap();
+//! assert_eq!(neighbors[0][0].0, 1);
+//! ```
+//!
+//! `quick::build_index` returns the underlying `ScannRetriever` for
+//! repeated searches; `build::build_retriever` and `SearchOptions` expose
+//! every knob.
 
+pub mod artifact_source;
+pub mod artifacts;
+pub mod asymmetric_hashing;
 pub mod assets;
+pub mod attribute_store;
+pub mod benchmarks;
+pub mod binfmt;
+pub mod blob;
+pub mod build;
+pub mod build_info;
+pub mod calibration;
+pub mod convert;
 pub mod distance_measures;
+pub mod drift;
+pub mod estimate;
+pub mod evaluation;
+pub mod ffi;
+pub mod fusion;
+pub mod index_manager;
+pub mod kd_tree;
+pub mod leaf_codes;
+pub mod maintenance;
+pub mod metric_learning;
+pub mod npy;
 pub mod projection;
 pub mod proto;
+pub mod quantization;
+pub mod query_cache;
+pub mod query_log;
+pub mod quick;
+pub mod reference;
 pub mod retrieval;
 pub mod retro;
+pub mod score_modifier;
 pub mod serialize;
-pub mod trees;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod swappable;
+pub mod testing;
+pub mod tree;
 pub mod util;
 
 // Re-export key types
 pub use assets::populate_and_save_assets_proto;
+pub use build_info::build_info;
 pub use distance_measures::{get_distance_measure, DistanceMeasure};
 pub use projection::{PcaProjection, RandomOrthogonalProjection};
 pub use retrieval::ScannRetriever;
 pub use retro::RETRO;
-pub use trees::KMeansTreeTrainingOptions;
-pub use utils::{DenseDataset, DatapointPtr, ScannError};
\ No newline at end of file
+pub use tree::KMeansTreeTrainingOptions;
+pub use util::{DenseDataset, DatapointPtr, ScannError, ToF32Scalar};
\ No newline at end of file
@@ -1,43 +1,349 @@
-use scann::assets::populate_and_save_assets_proto;
-use scann::projection::PcaProjection;
-use scann::trees::KMeansTreeTrainingOptions;
-use scann::utils::{DenseDataset, DatapointPtr};
-use scann::proto::PartitioningConfig;
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Serialize assets
-    let assets = populate_and_save_assets_proto("path/to/artifacts")?;
-    println!("Assets found: {}", assets.assets.len());
-
-    // Create and use PCA projection
-    let mut pca = PcaProjection::<f32>::new(10, 5)?;
-    let data = DenseDataset::new(vec![vec![1.0; 10]; 100], 10);
-    pca.create(&data, true, None);
-    let input = DatapointPtr::new(vec![1.0; 10]);
-    let mut projected = DatapointPtr::new(vec![0.0; 5]);
-    pca.project_input(&input, &mut projected)?;
-    println!("Projected: {:?}", projected.values());
-
-    // Configure k-means tree training
-    let config = PartitioningConfig {
-        partitioning_type: scann::proto::PartitioningType::Default,
-        max_num_levels: 5,
-        max_leaf_size: 100,
-        database_spilling: scann::proto::DatabaseSpilling {
-            spilling_type: scann::proto::SpillingType::Default,
-            replication_factor: 1.0,
-            max_spill_centers: 1000,
-        },
-        max_clustering_iterations: 50,
-        clustering_convergence_tolerance: 0.01,
-        min_cluster_size: 10,
-        clustering_seed: 42,
-        balancing_type: scann::proto::BalancingType::GreedyBalanced,
-        trainer_type: scann::proto::TrainerType::PcaKmeansTrainer,
-        single_machine_center_initialization: scann::proto::CenterInitializationType::RandomInitialization,
-    };
-    let options = KMeansTreeTrainingOptions::from_config(&config);
-    println!("KMeansTreeTrainingOptions: {:?}", format!("{:?}", options));
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+//! The `scann` command line: `scann <command> [--flag value | --switch]...`.
+
+use scann::{artifacts, benchmarks, build, distance_measures, evaluation, npy, quick, retrieval, tree, util};
+use std::io::Write;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "usage: scann <command> [flags]
+
+commands:
+  build   --data <dataset.npy> --out <dir> [--config <quick config>] [--streaming]
+          Builds an index, saves it as an artifacts directory (with
+          build_report.json under the serde feature) and prints the report. --streaming copies the
+          rows without loading the dataset and only builds brute-force
+          indexes.
+  report  --artifacts <dir>
+          Prints the build report saved with an artifacts directory
+          (serde feature).
+  evaluate --benchmark <name | descriptor.json> --dir <dir> [--config <quick config>]
+           [--leaves <n,n,...>]
+          Builds an index over a downloaded benchmark suite (sift1m, gist1m,
+          glove100 or, with serde, a JSON BenchmarkDataset) and prints recall@k and mean
+          latency per leaves_to_search setting, marking the Pareto frontier.
+  inspect --tree --artifacts <dir> [--format dot|json] [--color-by-imbalance]
+          Writes the trained k-means tree with per-leaf stats to stdout
+          (Graphviz by default).
+  verify  --artifacts <dir>
+          Loads an artifacts directory and checks that its rows, docids,
+          partitioning and attributes agree; fails on any violation.";
+
+// Flags of one command: `--name value` pairs, plus switches (a flag
+// followed by another flag or by nothing).
+struct Flags {
+    values: HashMap<String, Option<String>>,
+}
+
+impl Flags {
+    fn parse(args: &[String], allowed: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut values = HashMap::new();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--").filter(|name| allowed.contains(name)) else {
+                return Err(util::invalid_argument_error(&format!(
+                    "Unexpected argument '{}'; expected one of --{}",
+                    arg,
+                    allowed.join(", --")
+                )));
+            };
+            let value = args.next_if(|next| !next.starts_with("--")).cloned();
+            if values.insert(name.to_string(), value).is_some() {
+                return Err(util::invalid_argument_error(&format!("--{} given twice", name)));
+            }
+        }
+        Ok(Flags { values })
+    }
+
+    fn value(&self, name: &str) -> Result<Option<&str>, Box<dyn Error>> {
+        match self.values.get(name) {
+            Some(Some(value)) => Ok(Some(value)),
+            Some(None) => Err(util::invalid_argument_error(&format!("--{} needs a value", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn switch(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        match self.values.get(name) {
+            Some(Some(value)) => Err(util::invalid_argument_error(&format!(
+                "--{} takes no value, got '{}'",
+                name, value
+            ))),
+            Some(None) => Ok(true),
+            None => Ok(false),
+        }
+    }
+
+    fn required(&self, name: &str) -> Result<&str, Box<dyn Error>> {
+        self.value(name)?
+            .ok_or_else(|| util::invalid_argument_error(&format!("--{} is required", name)))
+    }
+}
+
+fn read_file(path: &str) -> Result<String, Box<dyn Error>> {
+    fs::read_to_string(path).map_err(|e| util::invalid_argument_error(&format!("Failed to read {}: {}", path, e)))
+}
+
+// --config in either QuickConfig format; defaults when absent.
+fn quick_config(flags: &Flags) -> Result<quick::QuickConfig, Box<dyn Error>> {
+    let Some(path) = flags.value("config")? else {
+        return Ok(quick::QuickConfig::default());
+    };
+    Ok(quick::QuickConfig::parse_text_or_json(&read_file(path)?)?)
+}
+
+// Build reports and benchmark descriptors are JSON, read through serde.
+#[cfg(not(feature = "serde"))]
+fn needs_serde(what: &str) -> Box<dyn Error> {
+    util::failed_precondition_error(&format!("{} needs scann built with --features serde", what))
+}
+
+fn build_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = Flags::parse(args, &["data", "out", "config", "streaming"])?;
+    let config = quick_config(&flags)?;
+    if flags.switch("streaming")? {
+        return build_streaming(&flags, &config);
+    }
+    let dataset = npy::load_dataset(flags.required("data")?, npy::LoadMode::Owned)?;
+    let docids: Vec<usize> = (0..dataset.size()).collect();
+    let artifacts_config = artifacts::ArtifactsConfig {
+        distance_measure: config.measure.clone(),
+        normalization: util::Normalization::None,
+        dimensionality: dataset.dimensionality(),
+    };
+    let plan = config.build_plan(dataset.size());
+    let (retriever, report) = build::build_retriever(
+        dataset.clone(),
+        distance_measures::get_distance_measure_by_name(&config.measure)?,
+        config.k,
+        &plan,
+        &tree::KMeansTreeTrainingOptions::new(),
+    )?;
+    let partitioning = retriever.partitioning();
+    artifacts::save_built_artifacts(
+        flags.required("out")?,
+        &artifacts_config,
+        &dataset,
+        &docids,
+        partitioning.as_deref(),
+        &report,
+    )?;
+    println!("{}", report);
+    Ok(())
+}
+
+// Copies the input rows one at a time into dataset.npy. Partitioning and
+// the derived data need the whole dataset in memory, so only plans without
+// them are accepted.
+fn build_streaming(flags: &Flags, config: &quick::QuickConfig) -> Result<(), Box<dyn Error>> {
+    distance_measures::get_distance_measure_by_name(&config.measure)?;
+    let reader = npy::NpyStreamReader::open(flags.required("data")?)?;
+    let plan = config.build_plan(reader.rows());
+    if plan.num_leaves.is_some() || plan.int8_codes || plan.norm_cache {
+        return Err(util::invalid_argument_error(
+            "--streaming only builds brute-force indexes; set num_leaves to none and disable int8_codes and norm_cache",
+        ));
+    }
+    let artifacts_config = artifacts::ArtifactsConfig {
+        distance_measure: config.measure.clone(),
+        normalization: util::Normalization::None,
+        dimensionality: reader.dimensionality(),
+    };
+    let out = flags.required("out")?;
+    let mut summary = build::DatasetSummaryAccumulator::new(reader.dimensionality());
+    let start = Instant::now();
+    let rows = reader.enumerate().map(|(docid, row)| {
+        let row = row?;
+        summary.add(&row);
+        Ok((docid, row))
+    });
+    artifacts::save_artifacts_streaming(out, &artifacts_config, rows)?;
+    let mut report = build::BuildReport::new(summary.finish());
+    report.phases.push(("copy".to_string(), start.elapsed().as_secs_f64()));
+    #[cfg(feature = "serde")]
+    build::save_build_report(out, &report)?;
+    println!("{}", report);
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn report_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = Flags::parse(args, &["artifacts"])?;
+    println!("{}", build::load_build_report(flags.required("artifacts")?)?);
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn report_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    Flags::parse(args, &["artifacts"])?.required("artifacts")?;
+    Err(needs_serde("Reading build_report.json"))
+}
+
+// The index uses the suite's measure; the config supplies k and the build
+// plan. Without --leaves, partitioned indexes are swept over 1, 2, 4, ...
+// leaves up to all of them.
+fn evaluate_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = Flags::parse(args, &["benchmark", "dir", "config", "leaves"])?;
+    let name = flags.required("benchmark")?;
+    let descriptor = match benchmarks::builtin(name) {
+        Some(descriptor) => descriptor,
+        #[cfg(feature = "serde")]
+        None if name.ends_with(".json") => benchmarks::BenchmarkDataset::from_json(&read_file(name)?, name)?,
+        #[cfg(not(feature = "serde"))]
+        None if name.ends_with(".json") => return Err(needs_serde("A .json benchmark descriptor")),
+        None => {
+            return Err(util::invalid_argument_error(&format!(
+                "Unknown benchmark '{}'; expected one of {} or a .json descriptor",
+                name,
+                benchmarks::BUILTIN_BENCHMARKS.join(", ")
+            )))
+        }
+    };
+    let mut config = quick_config(&flags)?;
+    config.measure = descriptor.measure.clone();
+    if config.k > descriptor.ground_truth_k {
+        return Err(util::invalid_argument_error(&format!(
+            "k {} exceeds the {} ground truth neighbors of {}",
+            config.k, descriptor.ground_truth_k, descriptor.name
+        )));
+    }
+    let loaded = benchmarks::load_benchmark(&descriptor, flags.required("dir")?)?;
+    let plan = config.build_plan(loaded.base.size());
+    let (retriever, _) = build::build_retriever(
+        loaded.base,
+        distance_measures::get_distance_measure_by_name(&config.measure)?,
+        config.k,
+        &plan,
+        &tree::KMeansTreeTrainingOptions::new(),
+    )?;
+    let base = retrieval::SearchOptions {
+        k: Some(config.k),
+        ..retrieval::SearchOptions::default()
+    };
+    let leaves: Vec<usize> = match (flags.value("leaves")?, plan.num_leaves) {
+        (Some(list), _) => list
+            .split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| util::invalid_argument_error(&format!("--leaves '{}' is not a list of counts", list)))?,
+        (None, Some(num_leaves)) => {
+            let mut sweep: Vec<usize> =
+                std::iter::successors(Some(1), |n| Some(n * 2)).take_while(|&n| n < num_leaves).collect();
+            sweep.push(num_leaves);
+            sweep
+        }
+        (None, None) => Vec::new(),
+    };
+    // A brute-force index has a single configuration.
+    let grid = match leaves.is_empty() {
+        true => vec![base],
+        false => evaluation::search_grid(&base, &leaves, &[None]),
+    };
+    let points = evaluation::tune(&retriever, &loaded.queries, &loaded.ground_truth, &grid)?;
+    println!("{}: recall@{} over {} queries", descriptor.name, config.k, loaded.queries.size());
+    println!("{:>8} {:>8} {:>12} pareto", "leaves", "recall", "latency_us");
+    for point in &points {
+        println!(
+            "{:>8} {:>8.4} {:>12.1} {}",
+            point.options.leaves_to_search.map_or("all".to_string(), |n| n.to_string()),
+            point.recall,
+            point.mean_latency_us,
+            if point.pareto_optimal { "*" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn inspect_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = Flags::parse(args, &["tree", "artifacts", "format", "color-by-imbalance"])?;
+    if !flags.switch("tree")? {
+        return Err(util::invalid_argument_error("inspect needs --tree"));
+    }
+    let dir = flags.required("artifacts")?;
+    let loaded = artifacts::load_artifacts(dir)?;
+    let Some(tree) = &loaded.tree else {
+        return Err(util::failed_precondition_error(&format!("{} has no trained partitioning", dir)));
+    };
+    let mut out = std::io::stdout().lock();
+    match flags.value("format")?.unwrap_or("dot") {
+        "dot" => {
+            let options = tree::TreeExportOptions {
+                color_by_imbalance: flags.switch("color-by-imbalance")?,
+            };
+            tree.export_dot(&mut out, &loaded.dataset, &options)?
+        }
+        "json" => tree.export_json(&mut out, &loaded.dataset)?,
+        other => {
+            return Err(util::invalid_argument_error(&format!(
+                "Unknown --format '{}'; expected dot or json",
+                other
+            )))
+        }
+    }
+    out.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn verify_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = Flags::parse(args, &["artifacts"])?;
+    let dir = flags.required("artifacts")?;
+    let retriever = retrieval::ScannRetriever::from_artifacts(artifacts::load_artifacts(dir)?, quick::DEFAULT_K)?;
+    let report = retriever.verify_integrity()?;
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    for violation in &report.violations {
+        println!("{}: {}", violation.component, violation.message);
+    }
+    println!(
+        "{}: {} rows, {} checks, {} violations",
+        dir,
+        report.num_rows,
+        report.checks_run,
+        report.violations.len()
+    );
+    match report.is_ok() {
+        true => Ok(()),
+        false => Err(util::failed_precondition_error(&format!("{} failed verification", dir))),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) if command == "build" => build_command(rest),
+        Some((command, rest)) if command == "report" => report_command(rest),
+        Some((command, rest)) if command == "evaluate" => evaluate_command(rest),
+        Some((command, rest)) if command == "inspect" => inspect_command(rest),
+        Some((command, rest)) if command == "verify" => verify_command(rest),
+        Some((command, _)) if command == "help" || command == "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some((command, _)) => Err(util::invalid_argument_error(&format!("Unknown command '{}'", command))),
+        None => Err(util::invalid_argument_error("No command given")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("scann: {}\n\n{}", e, USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
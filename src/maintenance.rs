@@ -0,0 +1,240 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Periodic background maintenance on a dedicated thread.
+//!
+//! Tasks only touch the retriever through its thread-safe methods, so they
+//! never block searches for longer than those methods do. A task that
+//! returns an error or panics is recorded in its TaskStats and
+//! rescheduled; the thread keeps running until `shutdown` or drop.
+
+use super::{retrieval, util};
+use std::any::Any;
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub type MaintenanceFn = Box<dyn FnMut() -> Result<(), Box<dyn Error>> + Send>;
+
+pub struct PeriodicTask {
+    pub name: String,
+    pub interval: Duration,
+    pub run: MaintenanceFn,
+}
+
+impl PeriodicTask {
+    pub fn new(name: &str, interval: Duration, run: MaintenanceFn) -> Self {
+        PeriodicTask {
+            name: name.to_string(),
+            interval,
+            run,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    pub name: String,
+    pub runs: u64,
+    pub failures: u64,
+    pub panics: u64,
+    // Message of the most recent error or panic, cleared by a successful
+    // run.
+    pub last_error: Option<String>,
+}
+
+struct Shared {
+    shutdown: Mutex<bool>,
+    wake: Condvar,
+    stats: Mutex<Vec<TaskStats>>,
+}
+
+pub struct MaintenanceRunner {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceRunner {
+    // Each task first runs one interval after start.
+    pub fn start(tasks: Vec<PeriodicTask>) -> Result<Self, Box<dyn Error>> {
+        if let Some(task) = tasks.iter().find(|t| t.interval.is_zero()) {
+            return Err(util::invalid_argument_error(&format!(
+                "Maintenance task '{}' has a zero interval",
+                task.name
+            )));
+        }
+        let shared = Arc::new(Shared {
+            shutdown: Mutex::new(false),
+            wake: Condvar::new(),
+            stats: Mutex::new(
+                tasks
+                    .iter()
+                    .map(|t| TaskStats {
+                        name: t.name.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+        });
+        let thread_shared = shared.clone();
+        let handle = thread::Builder::new()
+            .name("scann-maintenance".to_string())
+            .spawn(move || run_loop(thread_shared, tasks))
+            .map_err(|e| util::failed_precondition_error(&format!("Failed to spawn maintenance thread: {}", e)))?;
+        Ok(MaintenanceRunner {
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stats(&self) -> Vec<TaskStats> {
+        self.shared.stats.lock().unwrap().clone()
+    }
+
+    // Stops scheduling, waits for a running task to finish and joins the
+    // thread. No task starts after this returns. Idempotent.
+    pub fn shutdown(&mut self) {
+        *self.shared.shutdown.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MaintenanceRunner {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run_loop(shared: Arc<Shared>, mut tasks: Vec<PeriodicTask>) {
+    let start = Instant::now();
+    let mut next_due: Vec<Instant> = tasks.iter().map(|t| start + t.interval).collect();
+    loop {
+        let Some(due) = (0..tasks.len()).min_by_key(|&i| next_due[i]) else {
+            // Nothing scheduled; just wait for shutdown.
+            let mut stopped = shared.shutdown.lock().unwrap();
+            while !*stopped {
+                stopped = shared.wake.wait(stopped).unwrap();
+            }
+            return;
+        };
+        {
+            let mut stopped = shared.shutdown.lock().unwrap();
+            loop {
+                if *stopped {
+                    return;
+                }
+                let now = Instant::now();
+                if now >= next_due[due] {
+                    break;
+                }
+                stopped = shared.wake.wait_timeout(stopped, next_due[due] - now).unwrap().0;
+            }
+        }
+
+        let task = &mut tasks[due];
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| (task.run)()));
+        {
+            let mut stats = shared.stats.lock().unwrap();
+            let stats = &mut stats[due];
+            stats.runs += 1;
+            match outcome {
+                Ok(Ok(())) => stats.last_error = None,
+                Ok(Err(e)) => {
+                    stats.failures += 1;
+                    stats.last_error = Some(e.to_string());
+                }
+                Err(payload) => {
+                    stats.panics += 1;
+                    stats.last_error = Some(panic_message(payload.as_ref()));
+                }
+            }
+        }
+        // Scheduled from completion so a slow task cannot pile up runs.
+        next_due[due] = Instant::now() + task.interval;
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message.as_str(),
+        _ => "non-string payload",
+    };
+    format!("panicked: {}", message)
+}
+
+// Checks unit-norm storage when the retriever normalizes its rows.
+pub fn normalization_check_task(
+    retriever: Arc<retrieval::ScannRetriever>,
+    interval: Duration,
+    tolerance: f32,
+) -> PeriodicTask {
+    PeriodicTask::new(
+        "normalization_check",
+        interval,
+        Box::new(move || retriever.debug_validate_normalization(tolerance)),
+    )
+}
+
+// Samples a certification run and hands the report to `on_report`, e.g. to
+// export recall as a health metric. The seed advances every run.
+pub fn health_check_task(
+    retriever: Arc<retrieval::ScannRetriever>,
+    interval: Duration,
+    sample_size: usize,
+    k: usize,
+    mut on_report: Box<dyn FnMut(retrieval::CertificationReport) + Send>,
+) -> PeriodicTask {
+    let mut seed = 0u64;
+    PeriodicTask::new(
+        "health_check",
+        interval,
+        Box::new(move || {
+            seed += 1;
+            on_report(retriever.certify(sample_size, k, seed, &retrieval::SearchOptions::default())?);
+            Ok(())
+        }),
+    )
+}
+
+// Drops cached results so the cache does not hold entries for queries that
+// stopped arriving.
+pub fn cache_trim_task(retriever: Arc<retrieval::ScannRetriever>, interval: Duration) -> PeriodicTask {
+    PeriodicTask::new(
+        "cache_trim",
+        interval,
+        Box::new(move || {
+            retriever.invalidate_result_cache();
+            Ok(())
+        }),
+    )
+}
+
+// Tombstones points that expired more than `grace` ago by the wall clock,
+// leaving them for the next compaction to remove.
+pub fn expiry_sweep_task(retriever: Arc<retrieval::ScannRetriever>, interval: Duration, grace: Duration) -> PeriodicTask {
+    PeriodicTask::new(
+        "expiry_sweep",
+        interval,
+        Box::new(move || {
+            retriever.tombstone_expired(retrieval::unix_now_secs(), grace.as_secs() as i64);
+            Ok(())
+        }),
+    )
+}
@@ -0,0 +1,202 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Learning per-dimension weights for WeightedSquaredL2Distance from
+//! labeled (query, positive, negative) triples.
+//!
+//! The objective is the mean triplet hinge loss
+//!   max(0, margin + d_w(q, p) - d_w(q, n)),  d_w(a, b) = sum w_i (a_i - b_i)^2
+//! plus an L1 penalty on w, minimized by full-batch projected gradient
+//! descent with w clamped to be non-negative after every step. The L1 term
+//! drives uninformative dimensions to exactly zero.
+
+use super::{distance_measures, util};
+use std::error::Error;
+
+// Indices of rows in the training dataset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Triple {
+    pub query: usize,
+    pub positive: usize,
+    pub negative: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiagonalFitOptions {
+    pub margin: f32,
+    pub learning_rate: f32,
+    pub epochs: usize,
+    pub l1_penalty: f32,
+    // Fraction of triples held out for validation accuracy.
+    pub validation_fraction: f32,
+    pub seed: u64,
+}
+
+impl DiagonalFitOptions {
+    pub fn new() -> Self {
+        DiagonalFitOptions {
+            margin: 1.0,
+            learning_rate: 0.01,
+            epochs: 200,
+            l1_penalty: 1e-3,
+            validation_fraction: 0.2,
+            seed: 0,
+        }
+    }
+}
+
+impl Default for DiagonalFitOptions {
+    fn default() -> Self {
+        DiagonalFitOptions::new()
+    }
+}
+
+// Triplet accuracy is the fraction of triples where the positive is
+// strictly closer than the negative.
+#[derive(Clone, Debug)]
+pub struct DiagonalFit {
+    pub weights: Vec<f32>,
+    pub train_accuracy_before: f32,
+    pub train_accuracy_after: f32,
+    pub validation_accuracy_before: f32,
+    pub validation_accuracy_after: f32,
+    pub final_loss: f32,
+}
+
+impl DiagonalFit {
+    pub fn distance_measure(&self) -> Result<distance_measures::WeightedSquaredL2Distance, Box<dyn Error>> {
+        distance_measures::WeightedSquaredL2Distance::new(self.weights.clone())
+    }
+}
+
+// Space-separated weights, the form stored in a distance measure config's
+// parameters.
+pub fn weights_to_parameter(weights: &[f32]) -> String {
+    weights.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+pub fn weights_from_parameter(parameter: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+    parameter
+        .split_whitespace()
+        .map(|w| {
+            w.parse::<f32>()
+                .ok()
+                .filter(|w| w.is_finite() && *w >= 0.0)
+                .ok_or_else(|| util::invalid_argument_error(&format!("Invalid metric weight '{}'", w)))
+        })
+        .collect()
+}
+
+fn squared_differences(dataset: &util::DenseDataset<f32>, a: usize, b: usize) -> Vec<f32> {
+    dataset.data[a]
+        .iter()
+        .zip(dataset.data[b].iter())
+        .map(|(&x, &y)| (x - y) * (x - y))
+        .collect()
+}
+
+// Per triple, (query-positive, query-negative) squared differences, which
+// are all the optimization needs.
+struct TripleTerms {
+    positive: Vec<f32>,
+    negative: Vec<f32>,
+}
+
+fn weighted(weights: &[f32], diffs: &[f32]) -> f32 {
+    weights.iter().zip(diffs.iter()).map(|(&w, &d)| w * d).sum()
+}
+
+fn accuracy(weights: &[f32], terms: &[TripleTerms]) -> f32 {
+    if terms.is_empty() {
+        return 0.0;
+    }
+    let correct = terms
+        .iter()
+        .filter(|t| weighted(weights, &t.positive) < weighted(weights, &t.negative))
+        .count();
+    correct as f32 / terms.len() as f32
+}
+
+pub fn fit_diagonal(
+    dataset: &util::DenseDataset<f32>,
+    triples: &[Triple],
+    options: &DiagonalFitOptions,
+) -> Result<DiagonalFit, Box<dyn Error>> {
+    if !(0.0..1.0).contains(&options.validation_fraction) {
+        return Err(util::invalid_argument_error(&format!(
+            "validation_fraction must be in [0, 1), got {}",
+            options.validation_fraction
+        )));
+    }
+    if let Some(t) = triples
+        .iter()
+        .find(|t| t.query.max(t.positive).max(t.negative) >= dataset.size())
+    {
+        return Err(util::invalid_argument_error(&format!(
+            "Triple {:?} references a row outside the {}-row dataset",
+            t,
+            dataset.size()
+        )));
+    }
+
+    let mut order: Vec<usize> = (0..triples.len()).collect();
+    let mut rng = util::SplitMix64::new(options.seed);
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.next_below(i + 1));
+    }
+    let num_validation = (triples.len() as f32 * options.validation_fraction).round() as usize;
+    let terms: Vec<TripleTerms> = order
+        .iter()
+        .map(|&i| TripleTerms {
+            positive: squared_differences(dataset, triples[i].query, triples[i].positive),
+            negative: squared_differences(dataset, triples[i].query, triples[i].negative),
+        })
+        .collect();
+    let (validation, train) = terms.split_at(num_validation);
+    if train.is_empty() {
+        return Err(util::invalid_argument_error("No training triples left after the validation split"));
+    }
+
+    let dim = dataset.dimensionality();
+    let mut weights = vec![1.0f32; dim];
+    let train_accuracy_before = accuracy(&weights, train);
+    let validation_accuracy_before = accuracy(&weights, validation);
+    let mut loss = 0.0f32;
+    for _ in 0..options.epochs {
+        let mut gradient = vec![options.l1_penalty; dim];
+        loss = 0.0;
+        for t in train {
+            let violation = options.margin + weighted(&weights, &t.positive) - weighted(&weights, &t.negative);
+            if violation <= 0.0 {
+                continue;
+            }
+            loss += violation;
+            for ((g, &p), &n) in gradient.iter_mut().zip(t.positive.iter()).zip(t.negative.iter()) {
+                *g += (p - n) / train.len() as f32;
+            }
+        }
+        loss = loss / train.len() as f32 + options.l1_penalty * weights.iter().sum::<f32>();
+        for (w, g) in weights.iter_mut().zip(gradient.iter()) {
+            *w = (*w - options.learning_rate * g).max(0.0);
+        }
+    }
+    Ok(DiagonalFit {
+        train_accuracy_before,
+        train_accuracy_after: accuracy(&weights, train),
+        validation_accuracy_before,
+        validation_accuracy_after: accuracy(&weights, validation),
+        final_loss: loss,
+        weights,
+    })
+}
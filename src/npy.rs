@@ -0,0 +1,578 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and writing 2-D little-endian float32 .npy artifacts.
+//!
+//! With the `zstd` feature, datasets can also be stored as `.npy.zst`: an
+//! 8-byte little-endian uncompressed size followed by one zstd frame holding
+//! the complete .npy file. With the `f16` feature, datasets can be stored as
+//! '<f2' arrays and are widened to f32 on load.
+
+use super::{artifact_source, util, ScannError};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadMode {
+    // Read the whole file into an owned dataset.
+    Owned,
+    // Memory-map the data section; only valid for uncompressed files.
+    Mmap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZstdCompression {
+    pub level: i32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SavedSizes {
+    pub uncompressed_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+fn io_error(path: &Path, action: &str, e: impl std::fmt::Display) -> Box<dyn Error> {
+    Box::new(ScannError {
+        message: format!("Failed to {} {}: {}", action, path.display(), e),
+    })
+}
+
+const SHAPE_KEY: &str = "'shape': (";
+// Width of the row count in streamed headers, enough for any u64.
+const STREAM_ROWS_WIDTH: usize = 20;
+
+pub fn encode_npy_f32(data: &util::DenseDataset<f32>) -> Vec<u8> {
+    let mut out = npy_header("<f4", &data.size().to_string(), data.dimensionality());
+    out.reserve(data.size() * data.dimensionality() * 4);
+    for row in &data.data {
+        for v in row {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    out
+}
+
+// Values rounded to the nearest half-precision float, so a round trip
+// through decode_npy_f32 is lossy.
+#[cfg(feature = "f16")]
+pub fn encode_npy_f16(data: &util::DenseDataset<f32>) -> Vec<u8> {
+    let mut out = npy_header("<f2", &data.size().to_string(), data.dimensionality());
+    out.reserve(data.size() * data.dimensionality() * 2);
+    for row in &data.data {
+        for &v in row {
+            out.extend_from_slice(&f32_to_f16_bits(v).to_le_bytes());
+        }
+    }
+    out
+}
+
+// Magic, version and padded header dict for a 2-D array of `descr`.
+fn npy_header(descr: &str, rows: &str, dim: usize) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, {}{}, {}), }}",
+        descr, SHAPE_KEY, rows, dim
+    );
+    // Pad so the data section starts on a 64-byte boundary, newline-terminated.
+    let unpadded = NPY_MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(NPY_MAGIC.len() + 4 + header.len());
+    out.extend_from_slice(NPY_MAGIC);
+    out.extend_from_slice(&[1, 0]);
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    out
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NpyDtype {
+    F32,
+    F16,
+}
+
+impl NpyDtype {
+    fn size(self) -> usize {
+        match self {
+            NpyDtype::F32 => 4,
+            NpyDtype::F16 => 2,
+        }
+    }
+}
+
+// Parses the header of a '<f4' array and returns (rows, cols, data offset).
+pub fn parse_npy_header(bytes: &[u8]) -> Result<(usize, usize, usize), Box<dyn Error>> {
+    match parse_header(bytes)? {
+        (NpyDtype::F32, rows, cols, data_start) => Ok((rows, cols, data_start)),
+        (NpyDtype::F16, ..) => Err(util::invalid_argument_error(
+            "Half-precision .npy files can only be loaded whole, not streamed",
+        )),
+    }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(NpyDtype, usize, usize, usize), Box<dyn Error>> {
+    if bytes.len() < 10 || &bytes[..6] != NPY_MAGIC {
+        return Err(util::invalid_argument_error("Not an .npy file: bad magic"));
+    }
+    let (header_len, header_start): (usize, usize) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => {
+            if bytes.len() < 12 {
+                return Err(util::invalid_argument_error("Truncated .npy header"));
+            }
+            (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12)
+        }
+        v => {
+            return Err(util::invalid_argument_error(&format!("Unsupported .npy version {}", v)));
+        }
+    };
+    let data_start = header_start
+        .checked_add(header_len)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| util::invalid_argument_error("Truncated .npy header"))?;
+    let header = std::str::from_utf8(&bytes[header_start..data_start])
+        .map_err(|_| util::invalid_argument_error("Non-UTF8 .npy header"))?;
+    let dtype = if header.contains("'descr': '<f4'") {
+        NpyDtype::F32
+    } else if header.contains("'descr': '<f2'") {
+        NpyDtype::F16
+    } else {
+        return Err(util::invalid_argument_error(&format!("Unsupported .npy dtype in header: {}", header.trim())));
+    };
+    if header.contains("'fortran_order': True") {
+        return Err(util::invalid_argument_error("Fortran-ordered .npy files are not supported"));
+    }
+    let shape = header
+        .split("'shape':")
+        .nth(1)
+        .and_then(|rest| rest.split('(').nth(1))
+        .and_then(|rest| rest.split(')').next())
+        .ok_or_else(|| util::invalid_argument_error("Missing shape in .npy header"))?;
+    let dims: Vec<usize> = shape
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| util::invalid_argument_error(&format!("Invalid .npy shape: ({})", shape)))?;
+    let (rows, cols) = match dims.as_slice() {
+        [rows, cols] => (*rows, *cols),
+        [rows] => (*rows, 1),
+        _ => {
+            return Err(util::invalid_argument_error(&format!(
+                "Expected a 2-D .npy array, got shape ({})",
+                shape
+            )))
+        }
+    };
+    if cols == 0 && rows > 0 {
+        return Err(util::invalid_argument_error(&format!(
+            ".npy shape ({}, 0) has rows but no columns",
+            rows
+        )));
+    }
+    Ok((dtype, rows, cols, data_start))
+}
+
+// Decodes a '<f4' array, or a '<f2' one widened to f32 when built with the
+// `f16` feature.
+pub fn decode_npy_f32(bytes: &[u8]) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+    let (dtype, rows, cols, data_start) = parse_header(bytes)?;
+    let expected = rows
+        .checked_mul(cols)
+        .and_then(|n| n.checked_mul(dtype.size()))
+        .ok_or_else(|| util::invalid_argument_error("Overflowing .npy shape"))?;
+    if bytes.len() - data_start != expected {
+        return Err(util::invalid_argument_error(&format!(
+            ".npy data section has {} bytes, expected {} for shape ({}, {})",
+            bytes.len() - data_start,
+            expected,
+            rows,
+            cols
+        )));
+    }
+    let data = match dtype {
+        NpyDtype::F32 => bytes[data_start..]
+            .chunks_exact(cols.max(1) * 4)
+            .take(rows)
+            .map(|row| row.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+            .collect(),
+        NpyDtype::F16 => decode_f16_rows(&bytes[data_start..], rows, cols)?,
+    };
+    Ok(util::DenseDataset::new(data, cols))
+}
+
+#[cfg(feature = "f16")]
+fn decode_f16_rows(data: &[u8], rows: usize, cols: usize) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    Ok(data
+        .chunks_exact(cols.max(1) * 2)
+        .take(rows)
+        .map(|row| row.chunks_exact(2).map(|b| f16_bits_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect())
+        .collect())
+}
+
+#[cfg(not(feature = "f16"))]
+fn decode_f16_rows(_data: &[u8], _rows: usize, _cols: usize) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    Err(util::failed_precondition_error(
+        "Loading half-precision .npy artifacts requires building scann with the `f16` feature",
+    ))
+}
+
+// Rounds to the nearest half-precision value, ties to even; out-of-range
+// values become infinities.
+#[cfg(feature = "f16")]
+fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let round = |value: u32, shift: u32| {
+        let truncated = value >> shift;
+        let rest = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        truncated + u32::from(rest > halfway || (rest == halfway && truncated & 1 == 1))
+    };
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        // Subnormal: count units of 2^-24, keeping the implicit leading bit.
+        return sign | round(mantissa | 0x80_0000, (14 - half_exponent) as u32) as u16;
+    }
+    // A carry out of the mantissa rounds up into the exponent, and from the
+    // largest finite value into infinity.
+    sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+#[cfg(feature = "f16")]
+fn f16_bits_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half & 0x8000) << 16;
+    let exponent = u32::from((half >> 10) & 0x1f);
+    let mantissa = u32::from(half & 0x3ff);
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if sign != 0 {
+                -magnitude
+            } else {
+                magnitude
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+pub fn is_compressed_path(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".npy.zst")
+}
+
+pub fn save_dataset<P: AsRef<Path>>(
+    path: P,
+    data: &util::DenseDataset<f32>,
+    compression: Option<ZstdCompression>,
+) -> Result<SavedSizes, Box<dyn Error>> {
+    let path = path.as_ref();
+    let npy = encode_npy_f32(data);
+    let stored = match compression {
+        None => npy.clone(),
+        Some(compression) => compress(&npy, compression)?,
+    };
+    fs::write(path, &stored).map_err(|e| io_error(path, "write", e))?;
+    Ok(SavedSizes {
+        uncompressed_bytes: npy.len() as u64,
+        stored_bytes: stored.len() as u64,
+    })
+}
+
+#[cfg(feature = "f16")]
+pub fn save_dataset_f16<P: AsRef<Path>>(path: P, data: &util::DenseDataset<f32>) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    fs::write(path, encode_npy_f16(data)).map_err(|e| io_error(path, "write", e))
+}
+
+// Loads `path`, decompressing `.npy.zst` files transparently. Memory-mapped
+// loads are refused for compressed files since there is no flat data
+// section to map.
+pub fn load_dataset<P: AsRef<Path>>(path: P, mode: LoadMode) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let compressed = is_compressed_path(path);
+    if compressed && mode == LoadMode::Mmap {
+        return Err(util::invalid_argument_error(&format!(
+            "Cannot memory-map compressed artifact {}; load it in owned mode or re-save uncompressed",
+            path.display()
+        )));
+    }
+    let bytes = fs::read(path).map_err(|e| io_error(path, "read", e))?;
+    if compressed {
+        decode_npy_f32(&decompress(&bytes)?)
+    } else {
+        decode_npy_f32(&bytes)
+    }
+}
+
+// Source-backed counterparts of save_dataset/load_dataset. The name is
+// resolved by the source, which rejects paths escaping its root.
+pub fn write_dataset(
+    source: &dyn artifact_source::ArtifactSource,
+    name: &str,
+    data: &util::DenseDataset<f32>,
+    compression: Option<ZstdCompression>,
+) -> Result<SavedSizes, Box<dyn Error>> {
+    let npy = encode_npy_f32(data);
+    let stored = match compression {
+        None => npy.clone(),
+        Some(compression) => compress(&npy, compression)?,
+    };
+    artifact_source::write_artifact(source, name, &stored)?;
+    Ok(SavedSizes {
+        uncompressed_bytes: npy.len() as u64,
+        stored_bytes: stored.len() as u64,
+    })
+}
+
+pub fn read_dataset(
+    source: &dyn artifact_source::ArtifactSource,
+    name: &str,
+) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+    let bytes = artifact_source::read_artifact(source, name)?;
+    if is_compressed_path(Path::new(name)) {
+        decode_npy_f32(&decompress(&bytes)?)
+    } else {
+        decode_npy_f32(&bytes)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn compress(npy: &[u8], compression: ZstdCompression) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = (npy.len() as u64).to_le_bytes().to_vec();
+    let frame = zstd::bulk::compress(npy, compression.level).map_err(|e| ScannError {
+        message: format!("zstd compression failed: {}", e),
+    })?;
+    out.extend(frame);
+    Ok(out)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if bytes.len() < 8 {
+        return Err(util::invalid_argument_error("Truncated .npy.zst size header"));
+    }
+    let size = u64::from_le_bytes([
+        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+    ]);
+    // The declared size is untrusted, so the output grows with the data
+    // actually decoded instead of being allocated up front; one byte past
+    // the declared size is enough to detect a mismatch.
+    let mut out = Vec::new();
+    zstd::stream::read::Decoder::new(&bytes[8..])
+        .and_then(|decoder| decoder.take(size.saturating_add(1)).read_to_end(&mut out))
+        .map_err(|e| ScannError {
+            message: format!("zstd decompression failed: {}", e),
+        })?;
+    if out.len() as u64 != size {
+        return Err(util::invalid_argument_error(&format!(
+            "Decompressed {} bytes, header declares {}",
+            out.len(),
+            size
+        )));
+    }
+    Ok(out)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_npy: &[u8], _compression: ZstdCompression) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err(util::failed_precondition_error(
+        "Compressed artifacts require building scann with the `zstd` feature",
+    ))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err(util::failed_precondition_error(
+        "Loading .npy.zst artifacts requires building scann with the `zstd` feature",
+    ))
+}
+
+// Writes a .npy file row by row without holding the dataset in memory. The
+// header is written up front with a placeholder row count of -1, which
+// every reader rejects, and patched by `close`. A writer dropped without
+// `close` therefore leaves a detectably invalid file.
+pub struct NpyStreamWriter {
+    path: std::path::PathBuf,
+    file: BufWriter<File>,
+    dim: usize,
+    rows: usize,
+    rows_offset: u64,
+}
+
+impl NpyStreamWriter {
+    pub fn create<P: AsRef<Path>>(path: P, dim: usize) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let header = npy_header("<f4", &format!("{:>width$}", -1, width = STREAM_ROWS_WIDTH), dim);
+        let rows_offset = header
+            .windows(SHAPE_KEY.len())
+            .position(|w| w == SHAPE_KEY.as_bytes())
+            .unwrap()
+            + SHAPE_KEY.len();
+        let mut file = BufWriter::new(File::create(&path).map_err(|e| io_error(&path, "create", e))?);
+        file.write_all(&header).map_err(|e| io_error(&path, "write", e))?;
+        Ok(NpyStreamWriter {
+            path,
+            file,
+            dim,
+            rows: 0,
+            rows_offset: rows_offset as u64,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn append_row(&mut self, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        if values.len() != self.dim {
+            return Err(util::invalid_argument_error(&format!(
+                "Dimension mismatch: expected {}, got {}",
+                self.dim,
+                values.len()
+            )));
+        }
+        for v in values {
+            self.file.write_all(&v.to_le_bytes()).map_err(|e| io_error(&self.path, "write", e))?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn append_batch(&mut self, batch: &util::DenseDataset<f32>) -> Result<(), Box<dyn Error>> {
+        for row in &batch.data {
+            self.append_row(row)?;
+        }
+        Ok(())
+    }
+
+    // Flushes the data and patches the final row count into the header.
+    pub fn close(self) -> Result<usize, Box<dyn Error>> {
+        let NpyStreamWriter { path, file, rows, rows_offset, .. } = self;
+        let mut file = file.into_inner().map_err(|e| io_error(&path, "flush", e.error()))?;
+        let patched = format!("{:>width$}", rows, width = STREAM_ROWS_WIDTH);
+        file.seek(SeekFrom::Start(rows_offset))
+            .and_then(|_| file.write_all(patched.as_bytes()))
+            .and_then(|_| file.sync_all())
+            .map_err(|e| io_error(&path, "finalize", e))?;
+        Ok(rows)
+    }
+}
+
+// Iterates the rows of an uncompressed .npy file without loading it whole.
+pub struct NpyStreamReader {
+    path: std::path::PathBuf,
+    file: BufReader<File>,
+    rows: usize,
+    dim: usize,
+    next: usize,
+}
+
+impl NpyStreamReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        if is_compressed_path(&path) {
+            return Err(util::invalid_argument_error(&format!(
+                "Cannot stream compressed artifact {}; use load_dataset",
+                path.display()
+            )));
+        }
+        let file = File::open(&path).map_err(|e| io_error(&path, "open", e))?;
+        let file_len = file.metadata().map_err(|e| io_error(&path, "stat", e))?.len();
+        let mut file = BufReader::new(file);
+        let read_error = |e: std::io::Error| io_error(&path, "read", e);
+        let mut header = vec![0u8; 10];
+        file.read_exact(&mut header).map_err(read_error)?;
+        let header_len = match header[6] {
+            1 => 10 + u16::from_le_bytes([header[8], header[9]]) as usize,
+            _ => {
+                let mut wide = [0u8; 2];
+                file.read_exact(&mut wide).map_err(read_error)?;
+                header.extend_from_slice(&wide);
+                12 + u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize
+            }
+        };
+        if (header_len as u64) > file_len {
+            return Err(util::invalid_argument_error(&format!("{} has a truncated .npy header", path.display())));
+        }
+        let mut rest = vec![0u8; header_len - header.len()];
+        file.read_exact(&mut rest).map_err(read_error)?;
+        header.extend(rest);
+        let (rows, dim, data_start) = parse_npy_header(&header)?;
+        let expected = (rows as u64)
+            .checked_mul(dim as u64)
+            .and_then(|n| n.checked_mul(4))
+            .and_then(|n| n.checked_add(data_start as u64));
+        if expected != Some(file_len) {
+            let expected = expected.map_or("more than 2^64".to_string(), |n| n.to_string());
+            return Err(util::invalid_argument_error(&format!(
+                "{} has {} bytes, expected {} for shape ({}, {})",
+                path.display(),
+                file_len,
+                expected,
+                rows,
+                dim
+            )));
+        }
+        Ok(NpyStreamReader {
+            path,
+            file,
+            rows,
+            dim,
+            next: 0,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn dimensionality(&self) -> usize {
+        self.dim
+    }
+}
+
+impl Iterator for NpyStreamReader {
+    type Item = Result<Vec<f32>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.rows {
+            return None;
+        }
+        self.next += 1;
+        let mut bytes = vec![0u8; self.dim * 4];
+        if let Err(e) = self.file.read_exact(&mut bytes) {
+            self.next = self.rows;
+            return Some(Err(io_error(&self.path, "read", e)));
+        }
+        Some(Ok(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()))
+    }
+}
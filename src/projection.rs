@@ -14,52 +14,178 @@
 
 //! PCA projection implementation for dimensionality reduction.
 
-use super::{failed_precondition_error, invalid_argument_error, proto, utils, ScannError};
+use super::util::{failed_precondition_error, invalid_argument_error};
+use super::{proto, util};
+use nalgebra::DMatrix;
 use std::error::Error;
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
-// Placeholder for PCA utilities
 mod pca_utils {
     use super::*;
 
+    // Principal directions of `data` with their eigenvalues (variances
+    // along them), largest first. `center` subtracts the mean first;
+    // otherwise the second moment about the origin is used. With
+    // `build_covariance` the d x d covariance is formed and eigendecomposed;
+    // without it a thin SVD of the n x d data matrix is used instead, which
+    // never allocates d x d and yields at most min(n, d) directions. Each
+    // direction's largest-magnitude component is made positive so both
+    // routes, and repeated runs, agree on signs. Accumulates in f64.
+    fn sorted_eigenpairs(center: bool, data: &util::DenseDataset<f32>, build_covariance: bool) -> Vec<(f32, Vec<f32>)> {
+        let dim = data.dimensionality();
+        let n = data.size();
+        if n == 0 || dim == 0 {
+            return Vec::new();
+        }
+        let mut mean = vec![0.0f64; dim];
+        if center {
+            for row in &data.data {
+                for (m, &v) in mean.iter_mut().zip(row.iter()) {
+                    *m += v as f64;
+                }
+            }
+            for m in mean.iter_mut() {
+                *m /= n as f64;
+            }
+        }
+        if build_covariance {
+            covariance_eigenpairs(data, &mean)
+        } else {
+            svd_eigenpairs(data, &mean)
+        }
+    }
+
+    fn covariance_eigenpairs(data: &util::DenseDataset<f32>, mean: &[f64]) -> Vec<(f32, Vec<f32>)> {
+        let dim = mean.len();
+        let n = data.size();
+        let mut covariance = DMatrix::<f64>::zeros(dim, dim);
+        let mut centered = vec![0.0f64; dim];
+        for row in &data.data {
+            for ((c, &v), &m) in centered.iter_mut().zip(row.iter()).zip(mean.iter()) {
+                *c = v as f64 - m;
+            }
+            for i in 0..dim {
+                for j in i..dim {
+                    covariance[(i, j)] += centered[i] * centered[j];
+                }
+            }
+        }
+        for i in 0..dim {
+            for j in i..dim {
+                let value = covariance[(i, j)] / n as f64;
+                covariance[(i, j)] = value;
+                covariance[(j, i)] = value;
+            }
+        }
+
+        let eigen = covariance.symmetric_eigen();
+        sorted_with_signs(
+            (0..dim)
+                .map(|k| (eigen.eigenvalues[k], eigen.eigenvectors.column(k).iter().copied().collect()))
+                .collect(),
+        )
+    }
+
+    // The right singular vectors of the centered n x d data matrix are the
+    // covariance eigenvectors, with eigenvalue sigma^2 / n.
+    fn svd_eigenpairs(data: &util::DenseDataset<f32>, mean: &[f64]) -> Vec<(f32, Vec<f32>)> {
+        let n = data.size();
+        let centered = DMatrix::<f64>::from_fn(n, mean.len(), |i, j| data.data[i][j] as f64 - mean[j]);
+        let svd = centered.svd(false, true);
+        let Some(v_t) = svd.v_t else {
+            return Vec::new();
+        };
+        sorted_with_signs(
+            (0..v_t.nrows())
+                .map(|k| {
+                    let sigma = svd.singular_values[k];
+                    (sigma * sigma / n as f64, v_t.row(k).iter().copied().collect())
+                })
+                .collect(),
+        )
+    }
+
+    fn sorted_with_signs(mut pairs: Vec<(f64, Vec<f64>)>) -> Vec<(f32, Vec<f32>)> {
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+        pairs
+            .into_iter()
+            .map(|(value, vector)| {
+                let pivot = vector.iter().copied().fold(0.0f64, |best, v| if v.abs() > best.abs() { v } else { best });
+                let sign = if pivot < 0.0 { -1.0 } else { 1.0 };
+                // Round-off can leave tiny negative eigenvalues on a
+                // positive semi-definite matrix.
+                (value.max(0.0) as f32, vector.iter().map(|&v| (sign * v) as f32).collect())
+            })
+            .collect()
+    }
+
+    fn store(eigenpairs: Vec<(f32, Vec<f32>)>, pca_vecs: &mut Vec<util::DatapointPtr<f32>>, eigen_vals: &mut Vec<f32>) {
+        pca_vecs.clear();
+        eigen_vals.clear();
+        for (value, vector) in eigenpairs {
+            pca_vecs.push(util::DatapointPtr::new(vector));
+            eigen_vals.push(value);
+        }
+    }
+
+    // Top `projected_dims` principal directions of `data` and their
+    // eigenvalues, largest first. Fewer come back when `projected_dims`
+    // exceeds the dimensionality, none for an empty dataset.
     pub fn compute_pca(
-        _center: bool,
-        _data: &utils::DenseDataset<f32>,
+        center: bool,
+        data: &util::DenseDataset<f32>,
         projected_dims: usize,
-        _build_covariance: bool,
-        pca_vecs: &mut Vec<utils::DatapointPtr<f32>>,
+        build_covariance: bool,
+        pca_vecs: &mut Vec<util::DatapointPtr<f32>>,
         eigen_vals: &mut Vec<f32>,
         _parallelization_pool: Option<&ParallelizationPool>,
     ) {
-        pca_vecs.clear();
-        eigen_vals.clear();
-        for _ in 0..projected_dims {
-            pca_vecs.push(utils::DatapointPtr::new(vec![0.0; _data.dimensionality]));
-            eigen_vals.push(1.0);
-        }
+        let mut eigenpairs = sorted_eigenpairs(center, data, build_covariance);
+        eigenpairs.truncate(projected_dims);
+        store(eigenpairs, pca_vecs, eigen_vals);
     }
 
+    // Keeps the leading directions until they explain at least
+    // `significance_threshold` of the total variance, capped at
+    // `truncation_threshold` of the input dimensionality; at least one
+    // direction is kept for non-empty data.
+    #[allow(clippy::too_many_arguments)] // Mirrors the C++ signature.
     pub fn compute_pca_with_significance_threshold(
-        _center: bool,
-        _data: &utils::DenseDataset<f32>,
-        _significance_threshold: f32,
-        _truncation_threshold: f32,
-        _build_covariance: bool,
-        pca_vecs: &mut Vec<utils::DatapointPtr<f32>>,
+        center: bool,
+        data: &util::DenseDataset<f32>,
+        significance_threshold: f32,
+        truncation_threshold: f32,
+        build_covariance: bool,
+        pca_vecs: &mut Vec<util::DatapointPtr<f32>>,
         eigen_vals: &mut Vec<f32>,
         _parallelization_pool: Option<&ParallelizationPool>,
     ) {
-        pca_vecs.clear();
-        eigen_vals.clear();
-        pca_vecs.push(utils::DatapointPtr::new(vec![0.0; _data.dimensionality]));
-        eigen_vals.push(1.0);
+        let mut eigenpairs = sorted_eigenpairs(center, data, build_covariance);
+        let total: f64 = eigenpairs.iter().map(|(value, _)| *value as f64).sum();
+        // Relative to the input dimensionality, which the SVD route may
+        // return fewer directions than.
+        let max_dims = ((truncation_threshold as f64 * data.dimensionality() as f64).floor() as usize)
+            .clamp(1, data.dimensionality().max(1));
+        let mut kept = 0;
+        let mut explained = 0.0f64;
+        while kept < max_dims.min(eigenpairs.len()) {
+            explained += eigenpairs[kept].0 as f64;
+            kept += 1;
+            if total <= 0.0 || explained >= significance_threshold as f64 * total {
+                break;
+            }
+        }
+        eigenpairs.truncate(kept);
+        store(eigenpairs, pca_vecs, eigen_vals);
     }
 }
 
 // Placeholder for parallelization pool
+#[derive(Default)]
 pub struct ParallelizationPool;
 
 impl ParallelizationPool {
@@ -68,12 +194,13 @@ impl ParallelizationPool {
     }
 }
 
-// Placeholder for random orthogonal projection
+// Rows are orthonormal directions drawn from the Gaussian ensemble, so the
+// same seed always yields the same rotation.
 pub struct RandomOrthogonalProjection {
     input_dims: usize,
     projected_dims: usize,
     seed: u64,
-    directions: Option<Arc<utils::DenseDataset<f32>>>,
+    directions: Option<Arc<util::DenseDataset<f32>>>,
 }
 
 impl RandomOrthogonalProjection {
@@ -87,45 +214,127 @@ impl RandomOrthogonalProjection {
     }
 
     pub fn create(&mut self) {
-        let mut data = vec![vec![0.0; self.input_dims]; self.projected_dims];
-        for i in 0..self.projected_dims {
-            data[i][i] = 1.0;
-        }
-        self.directions = Some(Arc::new(utils::DenseDataset::new(data, self.input_dims)));
+        let mut rng = util::SplitMix64::new(self.seed);
+        let gaussian = util::random_normal_matrix(self.input_dims, self.projected_dims, &mut rng);
+        let q = gaussian.qr().q();
+        let data = (0..self.projected_dims.min(q.ncols()))
+            .map(|j| q.column(j).iter().copied().collect())
+            .collect();
+        self.directions = Some(Arc::new(util::DenseDataset::new(data, self.input_dims)));
     }
 
-    pub fn get_directions(&self) -> Option<Arc<utils::DenseDataset<f32>>> {
+    pub fn get_directions(&self) -> Option<Arc<util::DenseDataset<f32>>> {
         self.directions.clone()
     }
 }
 
 // Placeholder for dot product utility
-fn dot_product<T: Copy + Into<f32>, U: Copy + Into<f32>>(a: &utils::DatapointPtr<T>, b: &utils::DatapointPtr<U>) -> f32 {
+fn dot_product<T: util::ToF32Scalar, U: util::ToF32Scalar>(a: &util::DatapointPtr<T>, b: &util::DatapointPtr<U>) -> f32 {
     a.values()
         .iter()
         .zip(b.values().iter())
-        .map(|(&x, &y)| x.into() * y.into())
+        .map(|(&x, &y)| x.to_f32() * y.to_f32())
         .sum()
 }
 
 // Placeholder for one-to-many dot product
-fn dense_dot_product_distance_one_to_many<T: Copy + Into<f32>, U: Copy + Into<f32>>(
-    input: &utils::DatapointPtr<T>,
-    dataset: &utils::DenseDataset<U>,
+fn dense_dot_product_distance_one_to_many<T: util::ToF32Scalar, U: util::ToF32Scalar>(
+    input: &util::DatapointPtr<T>,
+    dataset: &util::DenseDataset<U>,
     output: &mut [f32],
 ) {
     for (i, row) in dataset.data.iter().enumerate() {
-        output[i] = dot_product(input, &utils::DatapointPtr::new(row.clone()));
+        output[i] = dot_product(input, &util::DatapointPtr::new(row.clone()));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RankDeficiencyHandling {
+    // Shrink projected_dims to the numerical rank and record a warning.
+    Truncate,
+    // Fail the build instead of emitting directions with no variance.
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct PcaReport {
+    pub requested_dims: usize,
+    pub effective_dims: usize,
+    pub numerical_rank: usize,
+    pub warning: Option<String>,
+}
+
+// Eigenvalues at or below `tolerance * largest` are considered numerically
+// zero.
+fn numerical_rank(eigen_vals: &[f32], tolerance: f32) -> usize {
+    let largest = eigen_vals.iter().cloned().fold(0.0f32, f32::max);
+    if largest <= 0.0 {
+        return 0;
+    }
+    eigen_vals.iter().filter(|&&v| v > tolerance * largest).count()
+}
+
+// Permutation of projected dimensions for `num_blocks` contiguous PQ blocks
+// that greedily balances eigenvalue mass per block: dimensions are taken in
+// decreasing eigenvalue order and each goes to the lightest block with room
+// left. Block sizes match the contiguous split (the first dims % num_blocks
+// blocks get one extra). Output position p takes projected dimension
+// `permutation[p]`.
+pub fn balanced_block_assignment(eigenvalues: &[f32], num_blocks: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+    let dims = eigenvalues.len();
+    if num_blocks == 0 || num_blocks > dims {
+        return Err(invalid_argument_error(&format!(
+            "Cannot split {} dimensions into {} blocks",
+            dims, num_blocks
+        )));
+    }
+    let capacity: Vec<usize> = (0..num_blocks)
+        .map(|b| dims / num_blocks + usize::from(b < dims % num_blocks))
+        .collect();
+    let mut order: Vec<usize> = (0..dims).collect();
+    order.sort_by(|&a, &b| eigenvalues[b].total_cmp(&eigenvalues[a]).then(a.cmp(&b)));
+    let mut blocks: Vec<Vec<usize>> = vec![Vec::new(); num_blocks];
+    let mut mass = vec![0.0f64; num_blocks];
+    for dim in order {
+        let block = (0..num_blocks)
+            .filter(|&b| blocks[b].len() < capacity[b])
+            .min_by(|&a, &b| mass[a].total_cmp(&mass[b]))
+            .unwrap();
+        blocks[block].push(dim);
+        mass[block] += eigenvalues[dim] as f64;
+    }
+    Ok(blocks.concat())
+}
+
+// Total eigenvalue mass of each contiguous block after applying
+// `permutation`; max/min over the result is the variance spread.
+pub fn block_masses(eigenvalues: &[f32], permutation: &[usize], num_blocks: usize) -> Vec<f64> {
+    let dims = permutation.len();
+    let mut masses = Vec::with_capacity(num_blocks);
+    let mut start = 0;
+    for b in 0..num_blocks {
+        let size = dims / num_blocks + usize::from(b < dims % num_blocks);
+        masses.push(permutation[start..start + size].iter().map(|&d| eigenvalues[d] as f64).sum());
+        start += size;
     }
+    masses
 }
 
+// Rows per block in project_dataset.
+pub const DEFAULT_PROJECTION_BLOCK_ROWS: usize = 4096;
+
 pub struct PcaProjection<T> {
     input_dims: i32,
     projected_dims: i32,
-    pca_vecs: Option<Arc<utils::DenseDataset<f32>>>,
+    pca_vecs: Option<Arc<util::DenseDataset<f32>>>,
+    // Eigenvalue of each direction in `pca_vecs`, when trained here.
+    eigen_vals: Option<Vec<f32>>,
+    rank_deficiency_handling: RankDeficiencyHandling,
+    rank_tolerance: f32,
+    _input_type: PhantomData<fn(&T)>,
 }
 
-impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
+impl<T: util::ToF32Scalar + Send + Sync> PcaProjection<T> {
     pub fn new(input_dims: i32, projected_dims: i32) -> Result<Self, Box<dyn Error>> {
         if input_dims <= 0 {
             return Err(invalid_argument_error("Input dimensionality must be > 0"));
@@ -142,14 +351,33 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
             input_dims,
             projected_dims,
             pca_vecs: None,
+            eigen_vals: None,
+            rank_deficiency_handling: RankDeficiencyHandling::Truncate,
+            rank_tolerance: 1e-6,
+            _input_type: PhantomData,
         })
     }
 
-    pub fn create(&mut self, data: &utils::DenseDataset<f32>, build_covariance: bool, parallelization_pool: Option<&ParallelizationPool>) {
+    pub fn set_rank_deficiency_handling(&mut self, handling: RankDeficiencyHandling, relative_tolerance: f32) {
+        self.rank_deficiency_handling = handling;
+        self.rank_tolerance = relative_tolerance;
+    }
+
+    pub fn projected_dims(&self) -> usize {
+        self.projected_dims as usize
+    }
+
+    pub fn create(
+        &mut self,
+        data: &util::DenseDataset<f32>,
+        build_covariance: bool,
+        parallelization_pool: Option<&ParallelizationPool>,
+    ) -> Result<PcaReport, Box<dyn Error>> {
         let mut eigen_vals = Vec::new();
         let mut pca_vecs = Vec::new();
+        let center = true;
         pca_utils::compute_pca(
-            false,
+            center,
             data,
             self.projected_dims as usize,
             build_covariance,
@@ -158,16 +386,74 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
             parallelization_pool,
         );
 
-        let mut pca_vec_dataset = utils::DenseDataset::new(Vec::new(), data.dimensionality());
+        let requested_dims = self.projected_dims as usize;
+        // n points span at most n directions, and n - 1 once centered.
+        let max_rank = if center { data.size().saturating_sub(1) } else { data.size() };
+        let rank = numerical_rank(&eigen_vals, self.rank_tolerance).min(max_rank);
+        let mut report = PcaReport {
+            requested_dims,
+            effective_dims: requested_dims,
+            numerical_rank: rank,
+            warning: None,
+        };
+        if rank < requested_dims {
+            let message = format!(
+                "Requested {} PCA dimensions but the training data has numerical rank {}",
+                requested_dims, rank
+            );
+            match self.rank_deficiency_handling {
+                RankDeficiencyHandling::Error => return Err(failed_precondition_error(&message)),
+                RankDeficiencyHandling::Truncate => {
+                    // Identical points (including a single-point corpus) have
+                    // no variance at all; keep one direction so the projection
+                    // stays usable.
+                    let kept = rank.max(1).min(pca_vecs.len());
+                    if kept == 0 {
+                        return Err(failed_precondition_error(&message));
+                    }
+                    pca_vecs.truncate(kept);
+                    eigen_vals.truncate(kept);
+                    self.projected_dims = kept as i32;
+                    report.effective_dims = kept;
+                    report.warning = Some(format!("{}; truncated to {}", message, kept));
+                }
+            }
+        }
+
+        let mut pca_vec_dataset = util::DenseDataset::new(Vec::new(), data.dimensionality());
         for vec in pca_vecs {
-            pca_vec_dataset.append(vec.values(), "").unwrap();
+            pca_vec_dataset.append(vec.values(), "")?;
         }
         self.pca_vecs = Some(Arc::new(pca_vec_dataset));
+        self.eigen_vals = Some(eigen_vals);
+        Ok(report)
+    }
+
+    pub fn eigenvalues(&self) -> Option<&[f32]> {
+        self.eigen_vals.as_deref()
+    }
+
+    // Reorders the directions so that `num_blocks` contiguous blocks of the
+    // projected output carry similar variance (see
+    // balanced_block_assignment). The order is part of the directions, so
+    // serialize_to_proto persists it. Returns the permutation applied.
+    pub fn balance_for_blocks(&mut self, num_blocks: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+        let (Some(pca_vecs), Some(eigen_vals)) = (&self.pca_vecs, &self.eigen_vals) else {
+            return Err(failed_precondition_error(
+                "Block balancing needs PCA directions trained with eigenvalues.",
+            ));
+        };
+        let permutation = balanced_block_assignment(eigen_vals, num_blocks)?;
+        let rows = permutation.iter().map(|&d| pca_vecs.data[d].clone()).collect();
+        let eigen_vals = permutation.iter().map(|&d| eigen_vals[d]).collect();
+        self.pca_vecs = Some(Arc::new(util::DenseDataset::new(rows, pca_vecs.dimensionality())));
+        self.eigen_vals = Some(eigen_vals);
+        Ok(permutation)
     }
 
     pub fn create_with_thresholds(
         &mut self,
-        data: &utils::DenseDataset<f32>,
+        data: &util::DenseDataset<f32>,
         pca_significance_threshold: f32,
         pca_truncation_threshold: f32,
         build_covariance: bool,
@@ -176,7 +462,7 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
         let mut eigen_vals = Vec::new();
         let mut pca_vecs = Vec::new();
         pca_utils::compute_pca_with_significance_threshold(
-            false,
+            true,
             data,
             pca_significance_threshold,
             pca_truncation_threshold,
@@ -186,16 +472,18 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
             parallelization_pool,
         );
 
-        let mut pca_vec_dataset = utils::DenseDataset::new(Vec::new(), data.dimensionality());
+        self.projected_dims = pca_vecs.len() as i32;
+        let mut pca_vec_dataset = util::DenseDataset::new(Vec::new(), data.dimensionality());
         for vec in pca_vecs {
             pca_vec_dataset.append(vec.values(), "").unwrap();
         }
-        self.projected_dims = pca_vecs.len() as i32;
         self.pca_vecs = Some(Arc::new(pca_vec_dataset));
+        self.eigen_vals = Some(eigen_vals);
     }
 
-    pub fn create_from_eigenvectors(&mut self, eigenvectors: utils::DenseDataset<f32>) {
+    pub fn create_from_eigenvectors(&mut self, eigenvectors: util::DenseDataset<f32>) {
         self.pca_vecs = Some(Arc::new(eigenvectors));
+        self.eigen_vals = None;
     }
 
     pub fn create_from_serialized(
@@ -207,25 +495,39 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
                 "Serialized projection rotation matrix is empty in PcaProjection::create_from_serialized.",
             ));
         }
-        let mut pca_vecs = utils::DenseDataset::new(
-            Vec::new(),
-            serialized_projection.rotation_vec()[0].feature_value_float.len(),
-        );
+        let dim = serialized_projection.rotation_vec()[0].feature_value_float.len();
+        if dim != self.input_dims as usize {
+            return Err(invalid_argument_error(&format!(
+                "Serialized projection rotation vectors have dimensionality {}, projection expects {}",
+                dim, self.input_dims
+            )));
+        }
+        let mut pca_vecs = util::DenseDataset::new(Vec::new(), dim);
         pca_vecs.reserve(serialized_projection.rotation_vec_size());
         for gfv in serialized_projection.rotation_vec() {
             pca_vecs.append(&gfv.feature_value_float, "")?;
         }
+        // The serialized rotation holds only the effective (possibly
+        // truncated) directions.
+        self.projected_dims = pca_vecs.size() as i32;
         self.pca_vecs = Some(Arc::new(pca_vecs));
+        self.eigen_vals = None;
         Ok(())
     }
 
-    pub fn random_rotate_projection_matrix(&mut self) {
+    pub fn random_rotate_projection_matrix(&mut self) -> Result<(), Box<dyn Error>> {
         let Some(pca_vecs) = &self.pca_vecs else {
-            eprintln!("No PCA vectors to rotate.");
-            return;
+            return Err(failed_precondition_error("No PCA vectors to rotate."));
         };
-        assert_eq!(pca_vecs.size(), self.projected_dims as usize);
-        assert_eq!(pca_vecs.dimensionality(), self.input_dims as usize);
+        if pca_vecs.size() != self.projected_dims as usize || pca_vecs.dimensionality() != self.input_dims as usize {
+            return Err(failed_precondition_error(&format!(
+                "PCA vectors are {} x {}, projection expects {} x {}",
+                pca_vecs.size(),
+                pca_vecs.dimensionality(),
+                self.projected_dims,
+                self.input_dims
+            )));
+        }
 
         let mut ortho = RandomOrthogonalProjection::new(
             self.projected_dims as usize,
@@ -233,59 +535,109 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
             42,
         );
         ortho.create();
-        let ortho_vecs = ortho.get_directions().expect("Orthogonal vectors not initialized");
-        assert_eq!(ortho_vecs.size(), self.projected_dims as usize);
-        assert_eq!(ortho_vecs.dimensionality(), self.projected_dims as usize);
+        let ortho_vecs = ortho
+            .get_directions()
+            .ok_or_else(|| failed_precondition_error("Orthogonal vectors not initialized"))?;
 
         let mut rotated_matrix = vec![0.0; (self.input_dims * self.projected_dims) as usize];
         let mut col_vec = vec![0.0; self.projected_dims as usize];
 
         for col_idx in 0..self.input_dims as usize {
-            for row_idx in 0..self.projected_dims as usize {
-                col_vec[row_idx] = pca_vecs.data[row_idx][col_idx];
+            for (row_idx, value) in col_vec.iter_mut().enumerate() {
+                *value = pca_vecs.data[row_idx][col_idx];
             }
             for row_idx in 0..self.projected_dims as usize {
                 rotated_matrix[row_idx * self.input_dims as usize + col_idx] = dot_product(
-                    &utils::DatapointPtr::new(col_vec.clone()),
-                    &utils::DatapointPtr::new(ortho_vecs.data[row_idx].clone()),
+                    &util::DatapointPtr::new(col_vec.clone()),
+                    &util::DatapointPtr::new(ortho_vecs.data[row_idx].clone()),
                 );
             }
         }
-        self.pca_vecs = Some(Arc::new(utils::DenseDataset::new(
+        self.pca_vecs = Some(Arc::new(util::DenseDataset::new(
             rotated_matrix
                 .chunks(self.input_dims as usize)
                 .map(|chunk| chunk.to_vec())
                 .collect(),
             self.input_dims as usize,
         )));
+        // Rotated directions no longer align with individual eigenvalues.
+        self.eigen_vals = None;
+        Ok(())
     }
 
     pub fn project_input<FloatT: Copy + From<f32>>(
         &self,
-        input: &utils::DatapointPtr<T>,
-        projected: &mut utils::DatapointPtr<FloatT>,
+        input: &util::DatapointPtr<T>,
+        projected: &mut util::DatapointPtr<FloatT>,
     ) -> Result<(), Box<dyn Error>> {
         if self.pca_vecs.is_none() {
             return Err(failed_precondition_error("First compute the PCA directions."));
         }
+        input.check_dimensionality(Some(self.input_dims as usize), "PCA input")?;
         let pca_vecs = self.pca_vecs.as_ref().unwrap();
-        projected.values.clear();
-        projected.values.resize(self.projected_dims as usize, FloatT::from(0.0));
+        let mut values = vec![0.0; pca_vecs.size()];
+        dense_dot_product_distance_one_to_many(input, pca_vecs, &mut values);
+        *projected = util::DatapointPtr::new(values.into_iter().map(FloatT::from).collect());
+        Ok(())
+    }
 
-        if std::any::TypeId::of::<T>() == std::any::TypeId::of::<f32>() {
-            dense_dot_product_distance_one_to_many(input, pca_vecs, &mut projected.values);
-            for val in projected.values.iter_mut() {
-                *val = From::from(-f32::from(*val));
-            }
-        } else {
-            for (i, vec) in pca_vecs.data.iter().enumerate() {
-                projected.values[i] = From::from(dot_product(input, &utils::DatapointPtr::new(vec.clone())));
-            }
+    // Projects every row of `data` with one matrix multiply per block of
+    // rows; equivalent to project_input row by row.
+    pub fn project_dataset(&self, data: &util::DenseDataset<f32>) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+        let mut out = util::DenseDataset::new(Vec::new(), self.projected_dims as usize);
+        self.project_dataset_into(data, &mut out, DEFAULT_PROJECTION_BLOCK_ROWS)?;
+        Ok(out)
+    }
+
+    // Writes the projection of `data` into `out`, reusing its row
+    // allocations. Scratch memory is bounded by `block_rows` rows of input
+    // and output, whatever the dataset size; blocks run in parallel under
+    // the rayon feature.
+    pub fn project_dataset_into(
+        &self,
+        data: &util::DenseDataset<f32>,
+        out: &mut util::DenseDataset<f32>,
+        block_rows: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(pca_vecs) = &self.pca_vecs else {
+            return Err(failed_precondition_error("First compute the PCA directions."));
+        };
+        if block_rows == 0 {
+            return Err(invalid_argument_error("Projection block size must be > 0"));
+        }
+        let input_dims = self.input_dims as usize;
+        if data.dimensionality() != input_dims {
+            return Err(invalid_argument_error(&format!(
+                "PCA input has dimensionality {}, projection expects {}",
+                data.dimensionality(),
+                input_dims
+            )));
         }
+        let directions = DMatrix::from_fn(input_dims, pca_vecs.size(), |i, j| pca_vecs.data[j][i]);
+        let project_block = |rows: &[Vec<f32>], out_rows: &mut [Vec<f32>]| {
+            let block = DMatrix::from_fn(rows.len(), input_dims, |i, j| rows[i][j]);
+            let projected = block * &directions;
+            for (i, out_row) in out_rows.iter_mut().enumerate() {
+                out_row.clear();
+                out_row.extend(projected.row(i).iter());
+            }
+        };
+        out.data.resize(data.size(), Vec::new());
+        out.set_dimensionality(pca_vecs.size());
+        #[cfg(feature = "rayon")]
+        data.data
+            .par_chunks(block_rows)
+            .zip(out.data.par_chunks_mut(block_rows))
+            .for_each(|(rows, out_rows)| project_block(rows, out_rows));
+        #[cfg(not(feature = "rayon"))]
+        data.data
+            .chunks(block_rows)
+            .zip(out.data.chunks_mut(block_rows))
+            .for_each(|(rows, out_rows)| project_block(rows, out_rows));
         Ok(())
     }
 
-    pub fn get_directions(&self) -> Option<Arc<utils::DenseDataset<f32>>> {
+    pub fn get_directions(&self) -> Option<Arc<util::DenseDataset<f32>>> {
         self.pca_vecs.clone()
     }
 
@@ -296,16 +648,187 @@ impl<T: Copy + Into<f32> + Send + Sync> PcaProjection<T> {
         let mut result = proto::SerializedProjection::new();
         result.reserve_rotation_vec(pca_vecs.size());
         for eigenvector in &pca_vecs.data {
-            *result.add_rotation_vec() = utils::DatapointPtr::new(eigenvector.clone()).to_gfv();
+            *result.add_rotation_vec() = util::DatapointPtr::new(eigenvector.clone()).to_gfv();
         }
         Some(result)
     }
 }
 
-impl<T: Clone> utils::DatapointPtr<T> {
+// Splits vectors into `num_blocks` contiguous blocks for product
+// quantization, after an optional PCA and a permutation of its output
+// dimensions. Block sizes follow balanced_block_assignment: the first
+// dims % num_blocks blocks get one extra dimension.
+pub struct ChunkingProjection {
+    pca: Option<PcaProjection<f32>>,
+    permutation: Vec<usize>,
+    num_blocks: usize,
+}
+
+impl ChunkingProjection {
+    // Contiguous blocks of the input dimensions, in order.
+    pub fn new(dims: usize, num_blocks: usize) -> Result<Self, Box<dyn Error>> {
+        Self::with_permutation(None, (0..dims).collect(), num_blocks)
+    }
+
+    // Chunks the output of a trained `pca`. With `balance`, its dimensions
+    // are permuted so every block carries similar eigenvalue mass;
+    // otherwise blocks take them in decreasing eigenvalue order.
+    pub fn with_pca(pca: PcaProjection<f32>, num_blocks: usize, balance: bool) -> Result<Self, Box<dyn Error>> {
+        let dims = pca.get_directions().map_or(0, |directions| directions.size());
+        let permutation = match (balance, pca.eigenvalues()) {
+            (true, Some(eigen_vals)) => balanced_block_assignment(eigen_vals, num_blocks)?,
+            (true, None) => {
+                return Err(failed_precondition_error(
+                    "Block balancing needs PCA directions trained with eigenvalues.",
+                ))
+            }
+            (false, _) => (0..dims).collect(),
+        };
+        Self::with_permutation(Some(pca), permutation, num_blocks)
+    }
+
+    fn with_permutation(
+        pca: Option<PcaProjection<f32>>,
+        permutation: Vec<usize>,
+        num_blocks: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dims = permutation.len();
+        if num_blocks == 0 || num_blocks > dims {
+            return Err(invalid_argument_error(&format!(
+                "Cannot split {} dimensions into {} blocks",
+                dims, num_blocks
+            )));
+        }
+        let mut seen = vec![false; dims];
+        for &d in &permutation {
+            if d >= dims || std::mem::replace(&mut seen[d], true) {
+                return Err(invalid_argument_error(&format!(
+                    "Chunking permutation {:?} is not a permutation of 0..{}",
+                    permutation, dims
+                )));
+            }
+        }
+        Ok(ChunkingProjection { pca, permutation, num_blocks })
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    pub fn projected_dims(&self) -> usize {
+        self.permutation.len()
+    }
+
+    pub fn permutation(&self) -> &[usize] {
+        &self.permutation
+    }
+
+    pub fn pca(&self) -> Option<&PcaProjection<f32>> {
+        self.pca.as_ref()
+    }
+
+    // Output range of each block.
+    pub fn block_ranges(&self) -> Vec<std::ops::Range<usize>> {
+        let dims = self.permutation.len();
+        let mut start = 0;
+        (0..self.num_blocks)
+            .map(|b| {
+                let size = dims / self.num_blocks + usize::from(b < dims % self.num_blocks);
+                start += size;
+                start - size..start
+            })
+            .collect()
+    }
+
+    // The projected, permuted vector; block b is block_ranges()[b] of it.
+    pub fn project(&self, input: &[f32]) -> Result<Vec<f32>, Box<dyn Error>> {
+        let projected = match &self.pca {
+            Some(pca) => {
+                let mut projected = util::DatapointPtr::new(Vec::new());
+                pca.project_input(&util::DatapointPtr::new(input.to_vec()), &mut projected)?;
+                projected.values().to_vec()
+            }
+            None => {
+                if input.len() != self.permutation.len() {
+                    return Err(invalid_argument_error(&format!(
+                        "Chunking input has dimensionality {}, projection expects {}",
+                        input.len(),
+                        self.permutation.len()
+                    )));
+                }
+                input.to_vec()
+            }
+        };
+        Ok(self.permutation.iter().map(|&d| projected[d]).collect())
+    }
+
+    // project applied to every row, with the PCA done as a batch.
+    pub fn project_dataset(&self, data: &util::DenseDataset<f32>) -> Result<util::DenseDataset<f32>, Box<dyn Error>> {
+        let projected = match &self.pca {
+            Some(pca) => pca.project_dataset(data)?,
+            None => {
+                if data.dimensionality() != self.permutation.len() {
+                    return Err(invalid_argument_error(&format!(
+                        "Chunking input has dimensionality {}, projection expects {}",
+                        data.dimensionality(),
+                        self.permutation.len()
+                    )));
+                }
+                data.clone()
+            }
+        };
+        let rows = projected
+            .data
+            .iter()
+            .map(|row| self.permutation.iter().map(|&d| row[d]).collect())
+            .collect();
+        Ok(util::DenseDataset::new(rows, self.permutation.len()))
+    }
+
+    pub fn serialize_to_proto(&self) -> proto::SerializedChunkingProjection {
+        proto::SerializedChunkingProjection {
+            pca: self.pca.as_ref().and_then(|pca| pca.serialize_to_proto()),
+            permutation: self.permutation.iter().map(|&d| d as u32).collect(),
+            num_blocks: self.num_blocks as u32,
+        }
+    }
+
+    // `input_dims` is the dimensionality of the vectors fed to project.
+    pub fn create_from_serialized(
+        serialized: &proto::SerializedChunkingProjection,
+        input_dims: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let permutation: Vec<usize> = serialized.permutation.iter().map(|&d| d as usize).collect();
+        let pca = match &serialized.pca {
+            Some(projection) => {
+                let mut pca = PcaProjection::new(input_dims as i32, permutation.len() as i32)?;
+                pca.create_from_serialized(projection)?;
+                if pca.projected_dims() != permutation.len() {
+                    return Err(invalid_argument_error(&format!(
+                        "Serialized chunking projection has {} PCA directions for a permutation of {}",
+                        pca.projected_dims(),
+                        permutation.len()
+                    )));
+                }
+                Some(pca)
+            }
+            None if input_dims != permutation.len() => {
+                return Err(invalid_argument_error(&format!(
+                    "Serialized chunking projection permutes {} dimensions, input has {}",
+                    permutation.len(),
+                    input_dims
+                )))
+            }
+            None => None,
+        };
+        Self::with_permutation(pca, permutation, serialized.num_blocks as usize)
+    }
+}
+
+impl<T: util::ToF32Scalar> util::DatapointPtr<T> {
     pub fn to_gfv(&self) -> proto::GenericFeatureVector {
         proto::GenericFeatureVector {
-            feature_value_float: self.values.iter().map(|&v| v as f32).collect(),
+            feature_value_float: self.values().iter().map(|&v| v.to_f32()).collect(),
         }
     }
 }
\ No newline at end of file
@@ -1,4 +1,433 @@
-// ... (previous proto.rs content unchanged)
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Hand-written equivalents of the ScaNN protocol buffer messages this crate
+// uses. Field names follow the .proto definitions; accessors mirror the
+// generated getters so call sites read the same as the C++ code.
+
+use super::util;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AssetType {
+    #[default]
+    UnspecifiedType,
+    AhCenters,
+    Partitioner,
+    TokenizationNpy,
+    AhDatasetNpy,
+    Int8DatasetNpy,
+    Int8MultipliersNpy,
+    Int8NormsNpy,
+    DatasetNpy,
+}
+
+impl AssetType {
+    // Enum value name as written in text format.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            AssetType::UnspecifiedType => "UNSPECIFIED_TYPE",
+            AssetType::AhCenters => "AH_CENTERS",
+            AssetType::Partitioner => "PARTITIONER",
+            AssetType::TokenizationNpy => "TOKENIZATION_NPY",
+            AssetType::AhDatasetNpy => "AH_DATASET_NPY",
+            AssetType::Int8DatasetNpy => "INT8_DATASET_NPY",
+            AssetType::Int8MultipliersNpy => "INT8_MULTIPLIERS_NPY",
+            AssetType::Int8NormsNpy => "INT8_NORMS_NPY",
+            AssetType::DatasetNpy => "DATASET_NPY",
+        }
+    }
+
+    pub fn from_str_name(name: &str) -> Option<Self> {
+        (0..=8).filter_map(Self::from_i32).find(|t| t.as_str_name() == name)
+    }
+
+    pub fn from_i32(value: i32) -> Option<Self> {
+        Some(match value {
+            0 => AssetType::UnspecifiedType,
+            1 => AssetType::AhCenters,
+            2 => AssetType::Partitioner,
+            3 => AssetType::TokenizationNpy,
+            4 => AssetType::AhDatasetNpy,
+            5 => AssetType::Int8DatasetNpy,
+            6 => AssetType::Int8MultipliersNpy,
+            7 => AssetType::Int8NormsNpy,
+            8 => AssetType::DatasetNpy,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScannAsset {
+    pub asset_type: AssetType,
+    pub asset_path: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScannAssets {
+    pub assets: Vec<ScannAsset>,
+}
+
+// Text format, as written to scann_assets.pbtxt.
+impl fmt::Display for ScannAssets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for asset in &self.assets {
+            writeln!(f, "assets {{")?;
+            writeln!(f, "  asset_type: {}", asset.asset_type.as_str_name())?;
+            writeln!(f, "  asset_path: {:?}", asset.asset_path)?;
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+// Tokens of the text format: punctuation, bare words and quoted strings.
+#[derive(Debug, PartialEq)]
+enum TextToken {
+    Open,
+    Close,
+    Colon,
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize_text(text: &str) -> Result<Vec<TextToken>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(TextToken::Open),
+            '}' => tokens.push(TextToken::Close),
+            ':' => tokens.push(TextToken::Colon),
+            '#' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '"' => tokens.push(TextToken::Quoted(unescape_quoted(&mut chars)?)),
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                    word.push(c);
+                }
+                tokens.push(TextToken::Word(word));
+            }
+            c => return Err(util::invalid_argument_error(&format!("Unexpected character {:?} in text proto", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+// Reads a quoted string up to its closing quote, undoing the escapes the
+// Display impl writes (Rust debug escaping).
+fn unescape_quoted(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, Box<dyn Error>> {
+    let unterminated = || util::invalid_argument_error("Unterminated string in text proto");
+    let mut value = String::new();
+    loop {
+        match chars.next().ok_or_else(unterminated)? {
+            '"' => return Ok(value),
+            '\\' => match chars.next().ok_or_else(unterminated)? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                c @ ('\\' | '"' | '\'') => value.push(c),
+                'u' => {
+                    let invalid = || util::invalid_argument_error("Invalid \\u escape in text proto");
+                    if chars.next() != Some('{') {
+                        return Err(invalid());
+                    }
+                    let mut hex = String::new();
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                        hex.push(c);
+                    }
+                    let code = u32::from_str_radix(&hex, 16).map_err(|_| invalid())?;
+                    value.push(char::from_u32(code).ok_or_else(invalid)?);
+                }
+                c => return Err(util::invalid_argument_error(&format!("Invalid escape \\{} in text proto", c))),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+impl ScannAssets {
+    // Parses the text format written by Display. Fields may come in any
+    // order and on any line; unknown fields and enum names are errors.
+    pub fn parse_text(text: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = |what: &str| util::invalid_argument_error(&format!("Invalid scann_assets text proto: {}", what));
+        let mut tokens = tokenize_text(text)?.into_iter();
+        let mut assets = ScannAssets::default();
+        while let Some(token) = tokens.next() {
+            if token != TextToken::Word("assets".to_string()) {
+                return Err(invalid(&format!("expected 'assets', got {:?}", token)));
+            }
+            // The colon before a message value is optional in text format.
+            let mut token = tokens.next();
+            if token == Some(TextToken::Colon) {
+                token = tokens.next();
+            }
+            if token != Some(TextToken::Open) {
+                return Err(invalid("expected '{' after 'assets'"));
+            }
+            let mut asset = ScannAsset::default();
+            loop {
+                let field = match tokens.next() {
+                    Some(TextToken::Close) => break,
+                    Some(TextToken::Word(field)) => field,
+                    other => return Err(invalid(&format!("expected a field or '}}', got {:?}", other))),
+                };
+                if tokens.next() != Some(TextToken::Colon) {
+                    return Err(invalid(&format!("expected ':' after '{}'", field)));
+                }
+                match (field.as_str(), tokens.next()) {
+                    ("asset_type", Some(TextToken::Word(name))) => {
+                        asset.asset_type = AssetType::from_str_name(&name)
+                            .ok_or_else(|| invalid(&format!("unknown asset type '{}'", name)))?;
+                    }
+                    ("asset_path", Some(TextToken::Quoted(path))) => asset.asset_path = path,
+                    (field, value) => return Err(invalid(&format!("bad value {:?} for field '{}'", value, field))),
+                }
+            }
+            assets.assets.push(asset);
+        }
+        Ok(assets)
+    }
+}
+
+// Binary protobuf format of ScaNN's ScannAssets message, as written to
+// scann_assets.pb. Enum values follow the declaration order above.
+#[cfg(feature = "proto-compat")]
+impl ScannAssets {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for asset in &self.assets {
+            let mut message = Vec::new();
+            prost::encoding::int32::encode(1, &(asset.asset_type as i32), &mut message);
+            prost::encoding::string::encode(2, &asset.asset_path, &mut message);
+            prost::encoding::bytes::encode(1, &message, &mut out);
+        }
+        out
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self, prost::DecodeError> {
+        use prost::encoding::{decode_key, skip_field, DecodeContext};
+        let mut assets = ScannAssets::default();
+        while !buf.is_empty() {
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            if tag != 1 {
+                skip_field(wire_type, tag, &mut buf, DecodeContext::default())?;
+                continue;
+            }
+            let mut message = Vec::new();
+            prost::encoding::bytes::merge(wire_type, &mut message, &mut buf, DecodeContext::default())?;
+            let mut message = message.as_slice();
+            let mut asset = ScannAsset::default();
+            while !message.is_empty() {
+                let (tag, wire_type) = decode_key(&mut message)?;
+                match tag {
+                    1 => {
+                        let mut value = 0;
+                        prost::encoding::int32::merge(wire_type, &mut value, &mut message, DecodeContext::default())?;
+                        // Open enum: values this crate does not know read as unspecified.
+                        asset.asset_type = AssetType::from_i32(value).unwrap_or_default();
+                    }
+                    2 => prost::encoding::string::merge(
+                        wire_type,
+                        &mut asset.asset_path,
+                        &mut message,
+                        DecodeContext::default(),
+                    )?,
+                    _ => skip_field(wire_type, tag, &mut message, DecodeContext::default())?,
+                }
+            }
+            assets.assets.push(asset);
+        }
+        Ok(assets)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenericFeatureVector {
+    pub feature_value_float: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerializedProjection {
+    pub rotation_vec: Vec<GenericFeatureVector>,
+}
+
+impl SerializedProjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rotation_vec(&self) -> &[GenericFeatureVector] {
+        &self.rotation_vec
+    }
+
+    pub fn rotation_vec_size(&self) -> usize {
+        self.rotation_vec.len()
+    }
+
+    pub fn reserve_rotation_vec(&mut self, additional: usize) {
+        self.rotation_vec.reserve(additional);
+    }
+
+    pub fn add_rotation_vec(&mut self) -> &mut GenericFeatureVector {
+        self.rotation_vec.push(GenericFeatureVector::default());
+        self.rotation_vec.last_mut().unwrap()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerializedChunkingProjection {
+    // PCA applied before chunking, if any.
+    pub pca: Option<SerializedProjection>,
+    // Output position p takes dimension permutation[p] of the PCA output.
+    pub permutation: Vec<u32>,
+    pub num_blocks: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DistanceMeasureConfig {
+    pub distance_measure: String,
+    // Exponent of MinkowskiDistance.
+    pub minkowski_p: Option<f32>,
+    // Per-dimension weights of WeightedSquaredL2Distance.
+    pub weighted_l2_weights: Vec<f32>,
+}
+
+impl DistanceMeasureConfig {
+    pub fn distance_measure(&self) -> &str {
+        &self.distance_measure
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartitioningType {
+    #[default]
+    Default,
+    Generic,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpillingType {
+    #[default]
+    Default,
+    NoSpilling,
+    MultiplicativeDistanceThreshold,
+    AdditiveDistanceThreshold,
+    AbsoluteDistanceThreshold,
+    FixedNumberOfCenters,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BalancingType {
+    #[default]
+    DefaultUnbalanced,
+    GreedyBalanced,
+    UnbalancedFloat32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrainerType {
+    #[default]
+    DefaultSamplingTrainer,
+    FlumeKmeansTrainer,
+    PcaKmeansTrainer,
+    SamplingPcaKmeansTrainer,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CenterInitializationType {
+    #[default]
+    DefaultKmeansPlusPlus,
+    RandomInitialization,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DatabaseSpilling {
+    pub spilling_type: SpillingType,
+    pub replication_factor: f32,
+    pub max_spill_centers: i32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartitioningConfig {
+    pub partitioning_type: PartitioningType,
+    pub max_num_levels: i32,
+    pub max_leaf_size: i32,
+    pub database_spilling: DatabaseSpilling,
+    pub max_clustering_iterations: i32,
+    pub clustering_convergence_tolerance: f32,
+    pub min_cluster_size: i32,
+    pub clustering_seed: u64,
+    pub balancing_type: BalancingType,
+    pub trainer_type: TrainerType,
+    pub single_machine_center_initialization: CenterInitializationType,
+}
+
+impl PartitioningConfig {
+    pub fn partitioning_type(&self) -> PartitioningType {
+        self.partitioning_type
+    }
+
+    pub fn max_num_levels(&self) -> i32 {
+        self.max_num_levels
+    }
+
+    pub fn max_leaf_size(&self) -> i32 {
+        self.max_leaf_size
+    }
+
+    pub fn database_spilling(&self) -> &DatabaseSpilling {
+        &self.database_spilling
+    }
+
+    pub fn max_clustering_iterations(&self) -> i32 {
+        self.max_clustering_iterations
+    }
+
+    pub fn clustering_convergence_tolerance(&self) -> f32 {
+        self.clustering_convergence_tolerance
+    }
+
+    pub fn min_cluster_size(&self) -> i32 {
+        self.min_cluster_size
+    }
+
+    pub fn clustering_seed(&self) -> u64 {
+        self.clustering_seed
+    }
+
+    pub fn balancing_type(&self) -> BalancingType {
+        self.balancing_type
+    }
+
+    pub fn trainer_type(&self) -> TrainerType {
+        self.trainer_type
+    }
+
+    pub fn single_machine_center_initialization(&self) -> CenterInitializationType {
+        self.single_machine_center_initialization
+    }
+}
 
 #[derive(Clone, PartialEq)]
 pub struct RetroConfig {
@@ -45,4 +474,10 @@ impl RetroConfig {
             gated_rmsnorm: false,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for RetroConfig {
+    fn default() -> Self {
+        RetroConfig::new()
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scalar int8 quantization of dense datasets.
+
+use super::{distance_measures, util};
+use std::error::Error;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Int8QuantizationConfig {
+    // When set, each dimension is clipped at this quantile of its absolute
+    // value range (e.g. 0.999) instead of the absolute max, which keeps a
+    // single outlier from collapsing the resolution of the whole column.
+    pub clip_quantile: Option<f32>,
+}
+
+impl Int8QuantizationConfig {
+    pub fn new() -> Self {
+        Int8QuantizationConfig { clip_quantile: None }
+    }
+}
+
+#[derive(Clone)]
+pub struct Int8QuantizedDataset {
+    pub codes: util::DenseDataset<i8>,
+    // code = round(value * multiplier), so value ~= code / multiplier.
+    pub multipliers: Vec<f32>,
+    pub clip_quantile: Option<f32>,
+}
+
+impl Int8QuantizedDataset {
+    pub fn dequantize_row(&self, index: usize) -> Vec<f32> {
+        self.codes.data[index]
+            .iter()
+            .zip(self.multipliers.iter())
+            .map(|(&code, &m)| if m == 0.0 { 0.0 } else { code as f32 / m })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuantizationErrorSummary {
+    pub mean_abs_error: f32,
+    pub max_abs_error: f32,
+}
+
+// Per-value reconstruction error of `quantized` against the rows it encodes.
+pub fn error_summary(data: &util::DenseDataset<f32>, quantized: &Int8QuantizedDataset) -> QuantizationErrorSummary {
+    let mut sum = 0.0f64;
+    let mut max = 0.0f32;
+    let mut count = 0usize;
+    for (index, row) in data.data.iter().enumerate() {
+        for (&v, r) in row.iter().zip(quantized.dequantize_row(index)) {
+            let error = (v - r).abs();
+            sum += error as f64;
+            max = max.max(error);
+            count += 1;
+        }
+    }
+    QuantizationErrorSummary {
+        mean_abs_error: if count == 0 { 0.0 } else { (sum / count as f64) as f32 },
+        max_abs_error: max,
+    }
+}
+
+pub fn quantize_int8(
+    data: &util::DenseDataset<f32>,
+    config: &Int8QuantizationConfig,
+) -> Result<Int8QuantizedDataset, Box<dyn Error>> {
+    if let Some(q) = config.clip_quantile {
+        if !(q > 0.5 && q <= 1.0) {
+            return Err(util::invalid_argument_error(&format!(
+                "clip_quantile must be in (0.5, 1.0], got {}",
+                q
+            )));
+        }
+    }
+
+    let stats = data.column_stats();
+    let multipliers: Vec<f32> = stats
+        .iter()
+        .map(|column| {
+            let max_abs = match config.clip_quantile {
+                Some(q) => column.quantile(q).abs().max(column.quantile(1.0 - q).abs()),
+                None => column.max.abs().max(column.min.abs()),
+            };
+            // A column with no range (constant zero, or a single zero row)
+            // falls back to the identity scale so later rows still encode.
+            if max_abs > 0.0 {
+                127.0 / max_abs
+            } else {
+                1.0
+            }
+        })
+        .collect();
+
+    let codes = data
+        .data
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(multipliers.iter())
+                .map(|(&v, &m)| (v * m).round().clamp(-127.0, 127.0) as i8)
+                .collect()
+        })
+        .collect();
+
+    Ok(Int8QuantizedDataset {
+        codes: util::DenseDataset::new(codes, data.dimensionality()),
+        multipliers,
+        clip_quantile: config.clip_quantile,
+    })
+}
+
+// Distribution of how far the exact top-k neighbors drift in the approximate
+// (dequantized) ranking, measured on a sample of dataset rows used as
+// queries. Displacements are sorted ascending.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReorderingSummary {
+    pub sample_size: usize,
+    pub k: usize,
+    pub displacements: Vec<u32>,
+}
+
+impl ReorderingSummary {
+    // Smallest pre-reordering size expected to keep `target_recall` of the
+    // exact top-k among the approximate candidates.
+    pub fn suggest_reordering_size(&self, target_recall: f32, k: usize) -> usize {
+        if self.displacements.is_empty() {
+            return k;
+        }
+        let target = target_recall.clamp(0.0, 1.0) as f64;
+        let idx = ((target * self.displacements.len() as f64).ceil() as usize).clamp(1, self.displacements.len()) - 1;
+        k + self.displacements[idx] as usize
+    }
+}
+
+pub fn compute_reordering_summary(
+    data: &util::DenseDataset<f32>,
+    quantized: &Int8QuantizedDataset,
+    measure: &dyn distance_measures::DistanceMeasure,
+    sample_size: usize,
+    k: usize,
+    seed: u64,
+) -> ReorderingSummary {
+    let n = data.size();
+    let k = k.min(n);
+    let sample_size = sample_size.min(n);
+    let dequantized: Vec<Vec<f32>> = (0..n).map(|i| quantized.dequantize_row(i)).collect();
+    let mut rng = util::SplitMix64::new(seed);
+    let mut displacements = Vec::with_capacity(sample_size * k);
+
+    for _ in 0..sample_size {
+        let query = &data.data[rng.next_below(n)];
+        let mut exact: Vec<(usize, f32)> = data
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (i, measure.compute_distance_f32(query, row)))
+            .collect();
+        let mut approx: Vec<(usize, f32)> = dequantized
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (i, measure.compute_distance_f32(query, row)))
+            .collect();
+        exact.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        approx.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        let mut approx_rank = vec![0usize; n];
+        for (rank, &(i, _)) in approx.iter().enumerate() {
+            approx_rank[i] = rank;
+        }
+        for (exact_rank, &(i, _)) in exact.iter().take(k).enumerate() {
+            displacements.push(approx_rank[i].saturating_sub(exact_rank) as u32);
+        }
+    }
+    displacements.sort_unstable();
+    ReorderingSummary {
+        sample_size,
+        k,
+        displacements,
+    }
+}
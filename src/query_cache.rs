@@ -0,0 +1,193 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sharded LRU cache of search results for repeated identical queries.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const NUM_SHARDS: usize = 16;
+
+// Queries are keyed by their raw f32 bit patterns, so only bitwise
+// identical queries share an entry (0.0 and -0.0 are distinct, and no
+// rounding ever merges nearby queries). Search parameters are flattened to
+// integers the same way.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    query_bits: Vec<u32>,
+    params: Vec<u64>,
+}
+
+impl CacheKey {
+    pub fn new(query: &[f32], params: Vec<u64>) -> Self {
+        CacheKey {
+            query_bits: query.iter().map(|v| v.to_bits()).collect(),
+            params,
+        }
+    }
+
+    fn shard(&self) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish() as usize % NUM_SHARDS
+    }
+}
+
+struct Shard<V> {
+    entries: HashMap<CacheKey, (V, u64)>,
+    // Last-use tick -> key, oldest first. Ticks come from the cache-wide
+    // clock, so comparing shards' oldest entries finds the global LRU.
+    recency: BTreeMap<u64, CacheKey>,
+}
+
+impl<V> Shard<V> {
+    fn new() -> Self {
+        Shard {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    fn touch(&mut self, key: &CacheKey, tick: u64) -> Option<&V> {
+        let (_, last_used) = self.entries.get_mut(key)?;
+        self.recency.remove(&*last_used);
+        *last_used = tick;
+        self.recency.insert(tick, key.clone());
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    fn oldest_tick(&self) -> Option<u64> {
+        self.recency.first_key_value().map(|(&tick, _)| tick)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheCounters {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+// Holds at most `capacity` entries in total, however they hash across
+// shards; past it the least recently used entry of the whole cache goes.
+pub struct QueryCache<V> {
+    shards: Vec<Mutex<Shard<V>>>,
+    capacity: usize,
+    len: AtomicUsize,
+    next_tick: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<V: Clone> QueryCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        QueryCache {
+            shards: (0..NUM_SHARDS).map(|_| Mutex::new(Shard::new())).collect(),
+            capacity,
+            len: AtomicUsize::new(0),
+            next_tick: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<V> {
+        let tick = self.next_tick.fetch_add(1, Ordering::Relaxed);
+        let mut shard = self.shards[key.shard()].lock().unwrap();
+        let found = shard.touch(key, tick).cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn insert(&self, key: CacheKey, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut shard = self.shards[key.shard()].lock().unwrap();
+            match shard.entries.insert(key.clone(), (value, tick)) {
+                Some((_, last_used)) => {
+                    shard.recency.remove(&last_used);
+                }
+                None => {
+                    self.len.fetch_add(1, Ordering::AcqRel);
+                }
+            }
+            shard.recency.insert(tick, key);
+        }
+        self.evict_to_capacity();
+    }
+
+    // Evicts the globally oldest entry until the cache is within capacity.
+    // Shards are locked one at a time; an entry touched between finding it
+    // and evicting it is skipped and the search repeats.
+    fn evict_to_capacity(&self) {
+        while self.len.load(Ordering::Acquire) > self.capacity {
+            let oldest = self
+                .shards
+                .iter()
+                .enumerate()
+                .filter_map(|(i, shard)| shard.lock().unwrap().oldest_tick().map(|tick| (tick, i)))
+                .min();
+            let Some((tick, i)) = oldest else {
+                break;
+            };
+            let mut shard = self.shards[i].lock().unwrap();
+            if shard.oldest_tick() != Some(tick) {
+                continue;
+            }
+            if let Some((_, key)) = shard.recency.pop_first() {
+                shard.entries.remove(&key);
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            self.len.fetch_sub(shard.entries.len(), Ordering::AcqRel);
+            shard.entries.clear();
+            shard.recency.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn counters(&self) -> CacheCounters {
+        CacheCounters {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Opt-in, size-capped binary log of served queries for offline replay.
+//!
+//! Each record is framed as a 4-byte big-endian length followed by the
+//! serialize.rs key encodings of: timestamp_us u64, latency_us u32, k u32,
+//! leaves_to_search u32 (u32::MAX for none), dim u32, dim x f32,
+//! num_results u32, num_results x (docid u64, distance f32).
+
+use super::{retrieval, serialize, util, ScannError};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const NO_LEAVES: u32 = u32::MAX;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedQuery {
+    pub timestamp_us: u64,
+    pub latency_us: u32,
+    pub k: usize,
+    pub leaves_to_search: Option<usize>,
+    pub query: Vec<f32>,
+    pub results: Vec<(usize, f32)>,
+}
+
+fn take_bytes<'a>(body: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+    if *pos + n > body.len() {
+        return Err(util::invalid_argument_error("Truncated query log record"));
+    }
+    *pos += n;
+    Ok(&body[*pos - n..*pos])
+}
+
+impl LoggedQuery {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend(serialize::uint64_to_key(self.timestamp_us));
+        body.extend(serialize::uint32_to_key(self.latency_us));
+        body.extend(serialize::uint32_to_key(self.k as u32));
+        body.extend(serialize::uint32_to_key(
+            self.leaves_to_search.map_or(NO_LEAVES, |l| l as u32),
+        ));
+        body.extend(serialize::uint32_to_key(self.query.len() as u32));
+        for &v in &self.query {
+            body.extend(serialize::float_to_key(v));
+        }
+        body.extend(serialize::uint32_to_key(self.results.len() as u32));
+        for &(docid, distance) in &self.results {
+            body.extend(serialize::uint64_to_key(docid as u64));
+            body.extend(serialize::float_to_key(distance));
+        }
+        let mut framed = serialize::uint32_to_key(body.len() as u32);
+        framed.extend(body);
+        framed
+    }
+
+    fn decode(body: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut pos = 0;
+        let mut take = |n: usize| take_bytes(body, &mut pos, n);
+        let timestamp_us = serialize::key_to_uint64(take(8)?)?;
+        let latency_us = serialize::key_to_uint32(take(4)?)?;
+        let k = serialize::key_to_uint32(take(4)?)? as usize;
+        let leaves = serialize::key_to_uint32(take(4)?)?;
+        let dim = serialize::key_to_uint32(take(4)?)? as usize;
+        let mut query = Vec::with_capacity(dim.min(body.len() / 4));
+        for _ in 0..dim {
+            query.push(serialize::key_to_float(take(4)?)?);
+        }
+        let num_results = serialize::key_to_uint32(take(4)?)? as usize;
+        let mut results = Vec::with_capacity(num_results.min(body.len() / 12));
+        for _ in 0..num_results {
+            let docid = serialize::key_to_uint64(take(8)?)? as usize;
+            results.push((docid, serialize::key_to_float(take(4)?)?));
+        }
+        Ok(LoggedQuery {
+            timestamp_us,
+            latency_us,
+            k,
+            leaves_to_search: if leaves == NO_LEAVES { None } else { Some(leaves as usize) },
+            query,
+            results,
+        })
+    }
+}
+
+// Records are handed to a writer thread over a bounded channel; when the
+// channel is full or the size cap is reached records are dropped (and
+// counted) rather than stalling the search that produced them.
+pub struct QueryLogger {
+    sender: Mutex<Option<SyncSender<LoggedQuery>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    dropped: Arc<AtomicU64>,
+    path: PathBuf,
+}
+
+impl QueryLogger {
+    pub fn create<P: AsRef<Path>>(path: P, max_bytes: u64, channel_capacity: usize) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| ScannError {
+            message: format!("Failed to open query log {}: {}", path.display(), e),
+        })?;
+        let mut written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let (sender, receiver) = sync_channel::<LoggedQuery>(channel_capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = dropped.clone();
+        let writer = std::thread::spawn(move || {
+            let mut out = BufWriter::new(file);
+            for record in receiver {
+                let bytes = record.encode();
+                if written + bytes.len() as u64 > max_bytes || out.write_all(&bytes).is_err() {
+                    writer_dropped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                written += bytes.len() as u64;
+            }
+            let _ = out.flush();
+        });
+        Ok(QueryLogger {
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(Some(writer)),
+            dropped,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn log(
+        &self,
+        query: &[f32],
+        options: &retrieval::SearchOptions,
+        k: usize,
+        latency_us: u32,
+        results: &[(usize, f32)],
+    ) {
+        let sender = self.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        let record = LoggedQuery {
+            timestamp_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_micros() as u64),
+            latency_us,
+            k,
+            leaves_to_search: options.leaves_to_search,
+            query: query.to_vec(),
+            results: results.to_vec(),
+        };
+        if let Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) = sender.try_send(record) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Flushes pending records and stops the writer thread.
+    pub fn close(&self) {
+        self.sender.lock().unwrap().take();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Drop for QueryLogger {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+pub fn read_query_log<P: AsRef<Path>>(path: P) -> Result<Vec<LoggedQuery>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| ScannError {
+            message: format!("Failed to read query log {}: {}", path.display(), e),
+        })?;
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if pos + 4 > bytes.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "Truncated record header at byte {} of {}",
+                pos,
+                path.display()
+            )));
+        }
+        let len = serialize::key_to_uint32(&bytes[pos..pos + 4])? as usize;
+        pos += 4;
+        if pos + len > bytes.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "Truncated record at byte {} of {}",
+                pos,
+                path.display()
+            )));
+        }
+        records.push(LoggedQuery::decode(&bytes[pos..pos + len])?);
+        pos += len;
+    }
+    Ok(records)
+}
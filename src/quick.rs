@@ -0,0 +1,381 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! One-call entry points over the full build and search pipeline.
+//!
+//! These take plain row vectors, pick autopilot defaults (brute force for
+//! small data, roughly sqrt(n) k-means leaves otherwise) and report shape
+//! problems with the offending row. Anything beyond that should use
+//! `build::build_retriever` and `SearchOptions` directly.
+
+use super::{build, distance_measures, estimate, retrieval, tree, util, ScannError};
+use std::error::Error;
+
+pub const DEFAULT_MEASURE: &str = "SquaredL2Distance";
+pub const DEFAULT_K: usize = 10;
+// Below this many rows a partitioned index is rarely faster than a scan.
+pub const AUTOPILOT_MIN_POINTS_TO_PARTITION: usize = 20_000;
+
+const CONFIG_KEYS: &[&str] = &[
+    "measure",
+    "k",
+    "num_leaves",
+    "kmeans_iterations",
+    "spilling_factor",
+    "int8_codes",
+    "norm_cache",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LeafCount {
+    Auto,
+    None,
+    Fixed(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuickConfig {
+    pub measure: String,
+    pub k: usize,
+    pub num_leaves: LeafCount,
+    pub kmeans_iterations: usize,
+    pub spilling_factor: f32,
+    pub int8_codes: bool,
+    pub norm_cache: bool,
+}
+
+impl Default for QuickConfig {
+    fn default() -> Self {
+        QuickConfig {
+            measure: DEFAULT_MEASURE.to_string(),
+            k: DEFAULT_K,
+            num_leaves: LeafCount::Auto,
+            kmeans_iterations: 10,
+            spilling_factor: 1.0,
+            int8_codes: false,
+            norm_cache: false,
+        }
+    }
+}
+
+fn to_scann_error(e: Box<dyn Error>) -> ScannError {
+    ScannError { message: e.to_string() }
+}
+
+fn quick_error(message: String) -> ScannError {
+    ScannError { message }
+}
+
+// The fields of a JSON object in order, repeats included, so a key given
+// twice is rejected rather than silently keeping the last value.
+#[cfg(feature = "serde")]
+struct JsonFields(Vec<(String, serde_json::Value)>);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for JsonFields {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FieldsVisitor {
+            type Value = JsonFields;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a config object")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<JsonFields, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(JsonFields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+// Why a single config field was rejected; callers add where it came from.
+enum FieldError {
+    UnknownKey,
+    InvalidValue,
+}
+
+impl QuickConfig {
+    // Same "key: value" layout as the other text files in this crate; blank
+    // lines and lines starting with '#' are ignored and omitted keys keep
+    // their defaults. num_leaves takes a count, "auto" or "none".
+    pub fn parse(text: &str) -> Result<Self, ScannError> {
+        let mut config = QuickConfig::default();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(quick_error(format!(
+                    "Config line {} is not 'key: value': '{}'",
+                    line_number + 1,
+                    line
+                )));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match config.set_field(key, value) {
+                Ok(()) => {}
+                Err(FieldError::InvalidValue) => {
+                    return Err(quick_error(format!(
+                        "Config line {}: invalid value '{}' for '{}'",
+                        line_number + 1,
+                        value,
+                        key
+                    )))
+                }
+                Err(FieldError::UnknownKey) => {
+                    return Err(quick_error(format!(
+                        "Config line {}: unknown key '{}'; expected one of {}",
+                        line_number + 1,
+                        key,
+                        CONFIG_KEYS.join(", ")
+                    )))
+                }
+            }
+        }
+        config.validate()
+    }
+
+    // A flat JSON object with the same keys and value formats as `parse`:
+    // `{"measure": "DotProductDistance", "k": 5, "num_leaves": "auto"}`.
+    // Strings are only accepted for measure and num_leaves, and every other
+    // value must be a JSON number or boolean. Blank input selects every
+    // default.
+    #[cfg(feature = "serde")]
+    pub fn from_json(text: &str) -> Result<Self, ScannError> {
+        let mut config = QuickConfig::default();
+        if text.trim().is_empty() {
+            return Ok(config);
+        }
+        let JsonFields(fields) =
+            serde_json::from_str(text).map_err(|e| quick_error(format!("Config is not valid JSON: {}", e)))?;
+        let mut seen = std::collections::HashSet::new();
+        for (key, value) in fields {
+            if !seen.insert(key.clone()) {
+                return Err(quick_error(format!("Config key '{}' appears twice", key)));
+            }
+            let as_text = match &value {
+                serde_json::Value::String(s) if key == "measure" || key == "num_leaves" => s.clone(),
+                serde_json::Value::Number(n) if key != "measure" => n.to_string(),
+                serde_json::Value::Bool(b) if key != "measure" => b.to_string(),
+                _ if !CONFIG_KEYS.contains(&key.as_str()) => String::new(),
+                _ => return Err(quick_error(format!("Config key '{}': invalid value {}", key, value))),
+            };
+            match config.set_field(&key, &as_text) {
+                Ok(()) => {}
+                Err(FieldError::InvalidValue) => {
+                    return Err(quick_error(format!("Config key '{}': invalid value {}", key, value)))
+                }
+                Err(FieldError::UnknownKey) => {
+                    return Err(quick_error(format!(
+                        "Config key '{}' is unknown; expected one of {}",
+                        key,
+                        CONFIG_KEYS.join(", ")
+                    )))
+                }
+            }
+        }
+        config.validate()
+    }
+
+    // Inverse of `from_json`, with every key written out.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let num_leaves = match self.num_leaves {
+            LeafCount::Auto => serde_json::json!("auto"),
+            LeafCount::None => serde_json::json!("none"),
+            LeafCount::Fixed(n) => serde_json::json!(n),
+        };
+        serde_json::json!({
+            "measure": self.measure,
+            "k": self.k,
+            "num_leaves": num_leaves,
+            "kmeans_iterations": self.kmeans_iterations,
+            "spilling_factor": self.spilling_factor,
+            "int8_codes": self.int8_codes,
+            "norm_cache": self.norm_cache,
+        })
+        .to_string()
+    }
+
+    // `from_json` when the text starts with '{', `parse` otherwise. JSON
+    // needs the serde feature.
+    pub fn parse_text_or_json(text: &str) -> Result<Self, ScannError> {
+        if !text.trim_start().starts_with('{') {
+            return QuickConfig::parse(text);
+        }
+        #[cfg(feature = "serde")]
+        return QuickConfig::from_json(text);
+        #[cfg(not(feature = "serde"))]
+        Err(quick_error("A JSON config needs scann built with --features serde".to_string()))
+    }
+
+    fn set_field(&mut self, key: &str, value: &str) -> Result<(), FieldError> {
+        match key {
+            "measure" => self.measure = value.to_string(),
+            "k" => self.k = value.parse().map_err(|_| FieldError::InvalidValue)?,
+            "num_leaves" => {
+                self.num_leaves = match value {
+                    "auto" => LeafCount::Auto,
+                    "none" => LeafCount::None,
+                    n => LeafCount::Fixed(n.parse().map_err(|_| FieldError::InvalidValue)?),
+                }
+            }
+            "kmeans_iterations" => self.kmeans_iterations = value.parse().map_err(|_| FieldError::InvalidValue)?,
+            "spilling_factor" => {
+                self.spilling_factor = value.parse().map_err(|_| FieldError::InvalidValue)?;
+                if !self.spilling_factor.is_finite() {
+                    return Err(FieldError::InvalidValue);
+                }
+            }
+            "int8_codes" => self.int8_codes = value.parse().map_err(|_| FieldError::InvalidValue)?,
+            "norm_cache" => self.norm_cache = value.parse().map_err(|_| FieldError::InvalidValue)?,
+            _ => return Err(FieldError::UnknownKey),
+        }
+        Ok(())
+    }
+
+    fn validate(self) -> Result<Self, ScannError> {
+        if self.k == 0 {
+            return Err(quick_error("k must be at least 1".to_string()));
+        }
+        Ok(self)
+    }
+
+    // The BuildPlan this config resolves to for `num_points` rows.
+    pub fn build_plan(&self, num_points: usize) -> estimate::BuildPlan {
+        let mut plan = estimate::BuildPlan::new();
+        plan.num_leaves = self.resolved_num_leaves(num_points);
+        plan.kmeans_iterations = self.kmeans_iterations;
+        plan.spilling_factor = self.spilling_factor;
+        plan.int8_codes = self.int8_codes;
+        plan.norm_cache = self.norm_cache;
+        plan
+    }
+
+    fn resolved_num_leaves(&self, num_points: usize) -> Option<usize> {
+        match self.num_leaves {
+            LeafCount::Auto if num_points >= AUTOPILOT_MIN_POINTS_TO_PARTITION => {
+                Some(((num_points as f64).sqrt().round() as usize).max(1))
+            }
+            LeafCount::Auto | LeafCount::None => None,
+            LeafCount::Fixed(n) => Some(n),
+        }
+    }
+}
+
+// Roughly 10% of the leaves, which is where recall usually levels off for
+// sqrt(n) partitionings.
+pub fn autopilot_leaves_to_search(num_leaves: usize) -> usize {
+    num_leaves.div_ceil(10).max(1)
+}
+
+// Checks that `rows` is non-empty and that every row has the same nonzero
+// length (`dimensionality` when given), which is returned. `what` names the
+// rows in error messages.
+fn check_rows(rows: &[Vec<f32>], what: &str, dimensionality: Option<usize>) -> Result<usize, ScannError> {
+    let expected = match dimensionality.or_else(|| rows.first().map(|row| row.len())) {
+        Some(0) => return Err(quick_error(format!("{} rows have no values", what))),
+        Some(d) => d,
+        None => return Err(quick_error(format!("{} is empty", what))),
+    };
+    for (i, row) in rows.iter().enumerate() {
+        if row.len() != expected {
+            return Err(quick_error(format!(
+                "{} row {} has {} values, expected {}",
+                what,
+                i,
+                row.len(),
+                expected
+            )));
+        }
+    }
+    Ok(expected)
+}
+
+fn dense_from_rows(rows: &[Vec<f32>]) -> Result<util::DenseDataset<f32>, ScannError> {
+    let dimensionality = check_rows(rows, "data", None)?;
+    Ok(util::DenseDataset::new(rows.to_vec(), dimensionality))
+}
+
+fn build_with_config(
+    dataset: util::DenseDataset<f32>,
+    config: &QuickConfig,
+) -> Result<(retrieval::ScannRetriever, Option<usize>), ScannError> {
+    let measure = distance_measures::get_distance_measure_by_name(&config.measure).map_err(to_scann_error)?;
+    let plan = config.build_plan(dataset.size());
+    let (retriever, _) = build::build_retriever(
+        dataset,
+        measure,
+        config.k,
+        &plan,
+        &tree::KMeansTreeTrainingOptions::new(),
+    )
+    .map_err(to_scann_error)?;
+    Ok((retriever, plan.num_leaves))
+}
+
+// Builds a retriever over `data` (row i gets docid i). `config` is a
+// QuickConfig JSON object, or the "key: value" text format when it does not
+// start with '{'; an empty string selects every autopilot default.
+pub fn build_index(data: &[Vec<f32>], config: &str) -> Result<retrieval::ScannRetriever, ScannError> {
+    let config = QuickConfig::parse_text_or_json(config)?;
+    Ok(build_with_config(dense_from_rows(data)?, &config)?.0)
+}
+
+// Up to `k` (row index, distance) pairs per query, nearest first. The data
+// is still validated when `queries` is empty, but no index is built.
+pub fn nearest_neighbors(
+    data: &[Vec<f32>],
+    queries: &[Vec<f32>],
+    k: usize,
+    measure_name: &str,
+) -> Result<Vec<Vec<(usize, f32)>>, ScannError> {
+    if k == 0 {
+        return Err(quick_error("k must be at least 1".to_string()));
+    }
+    let dimensionality = check_rows(data, "data", None)?;
+    if queries.is_empty() {
+        return Ok(Vec::new());
+    }
+    check_rows(queries, "queries", Some(dimensionality))?;
+    let config = QuickConfig {
+        measure: measure_name.to_string(),
+        k,
+        ..QuickConfig::default()
+    };
+    let (retriever, num_leaves) = build_with_config(dense_from_rows(data)?, &config)?;
+    let options = retrieval::SearchOptions {
+        leaves_to_search: num_leaves.map(autopilot_leaves_to_search),
+        ..retrieval::SearchOptions::default()
+    };
+    queries
+        .iter()
+        .enumerate()
+        .map(|(i, query)| {
+            retriever
+                .search_with_options(&util::DatapointPtr::new(query.clone()), &options)
+                .map(|(results, _)| results)
+                .map_err(|e| quick_error(format!("query {}: {}", i, e)))
+        })
+        .collect()
+}
@@ -0,0 +1,177 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exact brute-force search in f64, used as the ground-truth oracle.
+//!
+//! Everything here trades speed for trustworthiness: rows are widened to f64
+//! and every sum is Kahan-compensated. It is meant for evaluation and
+//! certification and is never used on the serving path.
+
+use super::util;
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferenceMetric {
+    DotProduct,
+    SquaredL2,
+    L2,
+    Cosine,
+}
+
+impl ReferenceMetric {
+    // The reference counterpart of a registered distance measure, if any.
+    pub fn from_measure_name(name: &str) -> Option<Self> {
+        match name {
+            "DotProductDistance" => Some(ReferenceMetric::DotProduct),
+            "SquaredL2Distance" => Some(ReferenceMetric::SquaredL2),
+            "L2Distance" => Some(ReferenceMetric::L2),
+            "CosineDistance" => Some(ReferenceMetric::Cosine),
+            // Only meaningful on unit-norm data, where it equals cosine.
+            "NormalizedDotProductDistance" => Some(ReferenceMetric::Cosine),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct KahanSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl KahanSum {
+    fn add(&mut self, value: f64) {
+        let y = value - self.compensation;
+        let t = self.sum + y;
+        self.compensation = (t - self.sum) - y;
+        self.sum = t;
+    }
+}
+
+pub fn distance(metric: ReferenceMetric, a: &[f64], b: &[f64]) -> f64 {
+    match metric {
+        ReferenceMetric::DotProduct => {
+            let mut dot = KahanSum::default();
+            a.iter().zip(b.iter()).for_each(|(&x, &y)| dot.add(x * y));
+            -dot.sum
+        }
+        ReferenceMetric::SquaredL2 | ReferenceMetric::L2 => {
+            let mut sum = KahanSum::default();
+            a.iter().zip(b.iter()).for_each(|(&x, &y)| sum.add((x - y) * (x - y)));
+            if metric == ReferenceMetric::L2 {
+                sum.sum.sqrt()
+            } else {
+                sum.sum
+            }
+        }
+        ReferenceMetric::Cosine => {
+            let (mut dot, mut norm_a, mut norm_b) = (KahanSum::default(), KahanSum::default(), KahanSum::default());
+            for (&x, &y) in a.iter().zip(b.iter()) {
+                dot.add(x * y);
+                norm_a.add(x * x);
+                norm_b.add(y * y);
+            }
+            // Matches CosineDistance: a zero vector is at distance 1.
+            if norm_a.sum == 0.0 || norm_b.sum == 0.0 {
+                return 1.0;
+            }
+            1.0 - (dot.sum / (norm_a.sum.sqrt() * norm_b.sum.sqrt())).clamp(-1.0, 1.0)
+        }
+    }
+}
+
+fn widen(values: &[f32]) -> Vec<f64> {
+    values.iter().map(|&v| v as f64).collect()
+}
+
+// (docid, distance) pairs, nearest first.
+pub type ReferenceNeighbors = Vec<(usize, f64)>;
+
+pub struct BruteForceF64Searcher {
+    metric: ReferenceMetric,
+    dimensionality: usize,
+    rows: Vec<Vec<f64>>,
+    docids: Vec<usize>,
+}
+
+impl BruteForceF64Searcher {
+    pub fn new(
+        dataset: &util::DenseDataset<f32>,
+        docids: Vec<usize>,
+        metric: ReferenceMetric,
+    ) -> Result<Self, Box<dyn Error>> {
+        if docids.len() != dataset.size() {
+            return Err(util::invalid_argument_error(&format!(
+                "Got {} docids for {} rows",
+                docids.len(),
+                dataset.size()
+            )));
+        }
+        Ok(BruteForceF64Searcher {
+            metric,
+            dimensionality: dataset.dimensionality(),
+            rows: dataset.data.iter().map(|row| widen(row)).collect(),
+            docids,
+        })
+    }
+
+    pub fn metric(&self) -> ReferenceMetric {
+        self.metric
+    }
+
+    fn scored(&self, query: &[f32]) -> Result<ReferenceNeighbors, Box<dyn Error>> {
+        if query.len() != self.dimensionality {
+            return Err(util::invalid_argument_error(&format!(
+                "Query has dimensionality {}, reference dataset has {}",
+                query.len(),
+                self.dimensionality
+            )));
+        }
+        let query = widen(query);
+        Ok(self
+            .rows
+            .iter()
+            .zip(self.docids.iter())
+            .map(|(row, &docid)| (docid, distance(self.metric, &query, row)))
+            .filter(|(_, d)| !d.is_nan())
+            .collect())
+    }
+
+    // Exact top-k, ascending by distance with ties broken by docid.
+    pub fn search(&self, query: &[f32], k: usize) -> Result<ReferenceNeighbors, Box<dyn Error>> {
+        let mut scored = self.scored(query)?;
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        Ok(scored)
+    }
+
+    pub fn search_batched(
+        &self,
+        queries: &util::DenseDataset<f32>,
+        k: usize,
+    ) -> Result<Vec<ReferenceNeighbors>, Box<dyn Error>> {
+        queries.data.iter().map(|query| self.search(query, k)).collect()
+    }
+
+    // Every row within `radius` (inclusive), sorted like `search`.
+    pub fn search_range(&self, query: &[f32], radius: f64) -> Result<ReferenceNeighbors, Box<dyn Error>> {
+        let mut scored: Vec<(usize, f64)> = self.scored(query)?.into_iter().filter(|&(_, d)| d <= radius).collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        Ok(scored)
+    }
+
+    pub fn distance_to(&self, query: &[f32], index: usize) -> f64 {
+        distance(self.metric, &widen(query), &self.rows[index])
+    }
+}
@@ -14,42 +14,4155 @@
 
 //! Retrieval module for ScaNN-based nearest neighbor search.
 
-use super::{distance_measures, proto, utils, ScannError};
-use nalgebra::DVector;
+use super::{
+    artifacts, attribute_store, binfmt, blob, calibration, convert, distance_measures, drift, estimate, kd_tree, leaf_codes, quantization, query_cache,
+    query_log, reference, score_modifier, tree, util, ScannError,
+};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct ScannRetriever {
-    dataset: utils::DenseDataset<f32>,
+thread_local! {
+    // Per-thread scratch so concurrent searches never share visited state.
+    static VISITED: RefCell<util::VisitedSet> = RefCell::new(util::VisitedSet::new());
+    // Transient per-search buffers, recycled instead of reallocated.
+    static ARENA: RefCell<util::SearchArena> = RefCell::new(util::SearchArena::default());
+}
+
+// Scratch buffers of one search, taken from the thread's arena. Dropping
+// them hands every buffer back and resets the arena, so an early return
+// cannot strand them.
+struct ArenaScratch<'a> {
+    results: Vec<(usize, f32)>,
+    scratch: Vec<(usize, f32)>,
+    leaves: Vec<usize>,
+    row: Vec<f32>,
+    // Raised to the arena's high-water mark on drop.
+    high_water_bytes: &'a AtomicUsize,
+}
+
+impl<'a> ArenaScratch<'a> {
+    fn take(high_water_bytes: &'a AtomicUsize) -> Self {
+        ARENA.with(|arena| {
+            let mut arena = arena.borrow_mut();
+            ArenaScratch {
+                results: arena.take_candidates(),
+                scratch: arena.take_candidates(),
+                leaves: arena.take_indices(),
+                row: arena.take_floats(),
+                high_water_bytes,
+            }
+        })
+    }
+}
+
+impl Drop for ArenaScratch<'_> {
+    fn drop(&mut self) {
+        // Nothing to recycle into once the thread's arena is gone.
+        let _ = ARENA.try_with(|arena| {
+            let mut arena = arena.borrow_mut();
+            arena.recycle_candidates(std::mem::take(&mut self.results));
+            arena.recycle_candidates(std::mem::take(&mut self.scratch));
+            arena.recycle_indices(std::mem::take(&mut self.leaves));
+            arena.recycle_floats(std::mem::take(&mut self.row));
+            arena.reset();
+            self.high_water_bytes.fetch_max(arena.high_water_bytes(), Ordering::Relaxed);
+        });
+    }
+}
+
+const PARTITION_MAGIC: &[u8; 8] = b"SCNPART1";
+
+const CANCELLATION_CHECK_INTERVAL: usize = 1024;
+
+// Brute-force blocks must not straddle dataset segments.
+const _: () = assert!(util::SEGMENT_LEN.is_multiple_of(CANCELLATION_CHECK_INTERVAL));
+
+// Brute-force scans use the fused top-k kernel once N is at least this
+// many times k.
+const FUSED_TOP_K_MIN_RATIO: usize = 16;
+
+// Largest row capacity read_partition reserves before reading the floats.
+const PARTITION_ROW_PREALLOCATION: usize = 4096;
+
+// (leaf_id, vectors, docids) of one exported leaf.
+pub type ExportedPartition = (usize, util::DenseDataset<f32>, Vec<usize>);
+
+// Parses a stream written by ScannRetriever::export_partition.
+pub fn read_partition<R: std::io::Read>(reader: &mut R) -> Result<ExportedPartition, Box<dyn Error>> {
+    let io_error = |e: std::io::Error| -> Box<dyn Error> {
+        Box::new(ScannError {
+            message: format!("Failed to read partition: {}", e),
+        })
+    };
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(io_error)?;
+    if &magic != PARTITION_MAGIC {
+        return Err(util::invalid_argument_error("Not a partition export: bad magic"));
+    }
+    let mut word = [0u8; 8];
+    let mut header = [0u64; 3];
+    for h in header.iter_mut() {
+        reader.read_exact(&mut word).map_err(io_error)?;
+        *h = u64::from_le_bytes(word);
+    }
+    let [leaf_id, count, dim] = header;
+    let mut dataset = util::DenseDataset::new(Vec::new(), dim as usize);
+    let mut docids = Vec::new();
+    let mut float = [0u8; 4];
+    for _ in 0..count {
+        reader.read_exact(&mut word).map_err(io_error)?;
+        docids.push(u64::from_le_bytes(word) as usize);
+        // `dim` comes from the stream, so the row grows as floats arrive
+        // rather than trusting it for the allocation.
+        let mut row = Vec::with_capacity((dim as usize).min(PARTITION_ROW_PREALLOCATION));
+        for _ in 0..dim {
+            reader.read_exact(&mut float).map_err(io_error)?;
+            row.push(f32::from_le_bytes(float));
+        }
+        dataset.data.push(row);
+    }
+    Ok((leaf_id as usize, dataset, docids))
+}
+
+// Groups distance-sorted results into runs whose distances lie within
+// `epsilon` of the run's first element and sorts each run by docid. Anchoring
+// on the run head keeps grouping deterministic instead of chaining. A search
+// runs this before truncating to k, so a run straddling the top-k boundary
+// keeps its lowest docids. There is no crowding here, so docid is the whole
+// tie-break.
+fn order_near_ties(results: &mut [(usize, f32)], epsilon: f32) {
+    let mut start = 0;
+    while start < results.len() {
+        let head = results[start].1;
+        let mut end = start + 1;
+        while end < results.len() && results[end].1 - head <= epsilon {
+            end += 1;
+        }
+        results[start..end].sort_by_key(|&(docid, _)| docid);
+        start = end;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchOptions {
+    // Overrides the retriever's default k.
+    pub k: Option<usize>,
+    // Number of partitions to scan; ignored when no partitioning is built.
+    pub leaves_to_search: Option<usize>,
+    // Fill SearchStats::calibrated_scores using the retriever's calibrator.
+    pub return_calibrated_scores: bool,
+    // Per-part weights when the retriever uses a CompositeDistance.
+    pub part_weights: Option<Vec<f32>>,
+    // Distances within this threshold of the first member of a run are
+    // treated as tied and ordered by docid. 0 keeps plain distance order.
+    pub epsilon_tie_threshold: f32,
+    // Recompute final distances for the first-pass candidates with the
+    // attached rescoring dataset and measure.
+    pub rescore_with_attached: bool,
+    // First-pass candidates to rescore; defaults to (and is at least) k.
+    pub rescore_candidates: Option<usize>,
+    // None replaces the primary distance; Some(w) returns
+    // (1 - w) * primary + w * attached.
+    pub rescore_blend_weight: Option<f32>,
+    // Query in the rescoring representation's space; defaults to the
+    // primary query when both representations share a dimensionality.
+    pub rescoring_query: Option<Vec<f32>>,
+    // Accumulator precision for first-pass scoring and, independently, for
+    // the rescoring stage. F64 bypasses the low-dimensional kernels and the
+    // k-d tree.
+    pub accumulator_precision: distance_measures::AccumulatorPrecision,
+    pub rescoring_precision: distance_measures::AccumulatorPrecision,
+    // Checked at leaf boundaries, every CANCELLATION_CHECK_INTERVAL rows of
+    // a brute-force scan and between rescoring candidates. On trigger the
+    // best results found so far are returned with SearchStats::truncated,
+    // or an error when error_on_cancel is set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancellation: Option<util::CancellationToken>,
+    pub error_on_cancel: bool,
+    // Applied in rank order after rescoring until max(k, rescore_candidates)
+    // candidates survive; dropped candidates are backfilled from further
+    // down the list. Searches with a modifier bypass the result cache.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub score_modifier: Option<Arc<dyn score_modifier::ScoreModifier>>,
+    // Histogram of every first-pass distance computed by the scan, returned
+    // in SearchStats::histogram. Disables the k-d tree path, which does not
+    // score every row.
+    pub collect_histogram: Option<HistogramSpec>,
+    // Facet counts returned in SearchStats::facets. Counted over the final
+    // candidate ranking, after tombstones and score modifiers, and also
+    // disables the k-d tree path.
+    pub facets: Option<FacetSpec>,
+    // Epoch seconds at which expiries are evaluated: points whose expiry is
+    // at or before this time are skipped like tombstones. Defaults to the
+    // wall clock when any point has an expiry.
+    pub as_of: Option<i64>,
+    // Restricts results to rows whose attributes match. Evaluated once per
+    // search into a row bitset and skipped like tombstones.
+    pub filter: Option<attribute_store::Filter>,
+    // Scan every selected leaf even when its per-leaf bound shows it cannot
+    // improve the results. Pruning never changes results; this exists for
+    // comparing against unpruned scans.
+    pub disable_leaf_pruning: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistogramSpec {
+    pub bins: usize,
+    // Half-open [low, high). Distances below low are counted in the first
+    // bin and distances at or above high in the last, with the totals of
+    // such clamped values reported separately.
+    pub range: (f32, f32),
+}
+
+impl HistogramSpec {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let (low, high) = self.range;
+        if self.bins == 0 || !low.is_finite() || !high.is_finite() || low >= high {
+            return Err(util::invalid_argument_error(&format!(
+                "Histogram needs bins > 0 and a finite range with low < high, got {} bins over [{}, {})",
+                self.bins, low, high
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistanceHistogram {
+    pub spec: HistogramSpec,
+    pub counts: Vec<u64>,
+    pub below_range: u64,
+    pub above_range: u64,
+    // Distances came from dequantized codes rather than the raw vectors.
+    pub approximate: bool,
+}
+
+impl DistanceHistogram {
+    pub fn new(spec: HistogramSpec) -> Self {
+        DistanceHistogram {
+            spec,
+            counts: vec![0; spec.bins],
+            below_range: 0,
+            above_range: 0,
+            approximate: false,
+        }
+    }
+
+    pub fn record(&mut self, distance: f32) {
+        let (low, high) = self.spec.range;
+        let bin = if distance < low {
+            self.below_range += 1;
+            0
+        } else if distance >= high {
+            self.above_range += 1;
+            self.spec.bins - 1
+        } else {
+            (((distance - low) / (high - low) * self.spec.bins as f32) as usize).min(self.spec.bins - 1)
+        };
+        self.counts[bin] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+// Facet counts over the best `pool_size` candidates, by attribute value in
+// each named column (see ScannRetriever::set_attribute_column).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FacetSpec {
+    pub columns: Vec<String>,
+    // At least k. With rescoring the pool is limited to the rescored
+    // candidates.
+    pub pool_size: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FacetCounts {
+    pub column: String,
+    pub counts: BTreeMap<i64, usize>,
+    // Candidates without a value in this column.
+    pub missing: usize,
+    // Candidates actually counted; below pool_size when fewer survived.
+    pub pool_size: usize,
+}
+
+// The i64 attribute column holding each point's expiry in epoch seconds.
+// A point is expired at `as_of` once its expiry is <= as_of. set_expiry and
+// add_with_expiry write it; it can also be filtered on like any column.
+pub const EXPIRY_COLUMN: &str = "expires_at";
+
+// Rows expired at some as_of. The expired set only changes when as_of
+// crosses an expiry value, so a window is reused for searches between two
+// consecutive expiries against the same attribute store, and rebuilt when a
+// search falls outside it or the store changes.
+#[derive(Clone)]
+struct ExpiredWindow {
+    // Latest expiry at or before the as_of that built the window, or
+    // i64::MIN when nothing had expired yet.
+    start: i64,
+    // First expiry after it; None when no later expiry exists.
+    end: Option<i64>,
+    // The store the window was evaluated against; any write replaces it.
+    attributes: Arc<attribute_store::AttributeStore>,
+    rows: Arc<attribute_store::RowBitset>,
+}
+
+impl ExpiredWindow {
+    fn covers(&self, attributes: &Arc<attribute_store::AttributeStore>, as_of: i64) -> bool {
+        Arc::ptr_eq(&self.attributes, attributes) && self.start <= as_of && self.end.is_none_or(|end| as_of < end)
+    }
+
+    // None when the store has no expiry column.
+    fn evaluate(attributes: &Arc<attribute_store::AttributeStore>, as_of: i64) -> Option<ExpiredWindow> {
+        let expiries = attributes.i64_values(EXPIRY_COLUMN)?;
+        let mut start = i64::MIN;
+        let mut end: Option<i64> = None;
+        for &expiry in expiries.iter().flatten() {
+            if expiry <= as_of {
+                start = start.max(expiry);
+            } else {
+                end = Some(end.map_or(expiry, |e| e.min(expiry)));
+            }
+        }
+        let rows = attributes
+            .evaluate(&attribute_store::col(EXPIRY_COLUMN).le(as_of))
+            .expect("expiry column is i64");
+        Some(ExpiredWindow {
+            start,
+            end,
+            attributes: attributes.clone(),
+            rows: Arc::new(rows),
+        })
+    }
+}
+
+pub fn unix_now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CertificationReport {
+    pub seed: u64,
+    pub num_queries: usize,
+    pub k: usize,
+    pub mean_recall: f32,
+    pub min_recall: f32,
+    // Largest |reported - exact| distance over all returned results.
+    pub max_distance_error: f32,
+    // Human-readable descriptions of broken result invariants.
+    pub violations: Vec<String>,
+    // Zero vectors held out of search by ZeroVectorPolicy::Quarantine.
+    pub quarantined: usize,
+}
+
+impl CertificationReport {
+    pub fn passed(&self, min_mean_recall: f32, max_distance_error: f32) -> bool {
+        self.violations.is_empty()
+            && self.mean_recall >= min_mean_recall
+            && self.max_distance_error <= max_distance_error
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityViolation {
+    // Which relationship broke, e.g. "docid_map" or "tree".
+    pub component: &'static str,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityReport {
+    pub num_rows: usize,
+    pub checks_run: usize,
+    pub violations: Vec<IntegrityViolation>,
+    // Inconsistencies that searches tolerate.
+    pub warnings: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    fn check(&mut self, component: &'static str, ok: bool, message: impl FnOnce() -> String) {
+        self.checks_run += 1;
+        if !ok {
+            self.violations.push(IntegrityViolation {
+                component,
+                message: message(),
+            });
+        }
+    }
+}
+
+// Breaks one relationship that verify_integrity checks, so tests can show
+// each check catches it. Leaves the retriever unfit for anything else.
+#[doc(hidden)]
+pub enum IntegrityCorruption {
+    // Rows are checked against this dimensionality instead of their own.
+    DatasetDimensionality(usize),
+    // Maps `docid` to `row` in the reverse map, leaving the docids alone.
+    DocidMapEntry { docid: usize, row: usize },
+    NextDocid(usize),
+    // Replaces the norms of the registered NormCache.
+    Norms(Vec<f32>),
+    // Installs a partitioning without checking its leaves.
+    Tree(tree::KMeansTree),
+    // Hides a docid without checking that it is stored.
+    Tombstone(usize),
+    Quarantine(usize),
+    Attributes(attribute_store::AttributeStore),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetrieverMetrics {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub cache_evictions: u64,
+    pub cache_entries: usize,
+    // Largest capacity any thread's search arena has retained.
+    pub arena_high_water_bytes: usize,
+    // From the attached QueryDriftMonitor; None without one or before it
+    // has seen enough queries.
+    pub query_drift: Option<drift::DriftScore>,
+    pub query_drift_detected: bool,
+}
+
+type ResultCache = query_cache::QueryCache<(Neighbors, SearchStats)>;
+
+// Everything besides the query that affects a search's output, flattened
+// for the result cache key. Floats are keyed by their bit patterns.
+fn cache_params(options: &SearchOptions, k: usize, generation: u64) -> Vec<u64> {
+    let mut params = vec![
+        generation,
+        k as u64,
+        options.leaves_to_search.map_or(u64::MAX, |l| l as u64),
+        options.return_calibrated_scores as u64,
+        options.epsilon_tie_threshold.to_bits() as u64,
+        options.rescore_with_attached as u64,
+        options.rescore_candidates.map_or(u64::MAX, |c| c as u64),
+        options.rescore_blend_weight.map_or(u64::MAX, |w| w.to_bits() as u64),
+        options.accumulator_precision as u64,
+        options.rescoring_precision as u64,
+        options.disable_leaf_pruning as u64,
+    ];
+    match &options.collect_histogram {
+        Some(spec) => params.extend([
+            spec.bins as u64,
+            spec.range.0.to_bits() as u64,
+            spec.range.1.to_bits() as u64,
+        ]),
+        None => params.push(u64::MAX),
+    }
+    match &options.facets {
+        Some(spec) => {
+            params.push(spec.pool_size as u64);
+            params.push(spec.columns.len() as u64);
+            for column in &spec.columns {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                column.hash(&mut hasher);
+                params.push(hasher.finish());
+            }
+        }
+        None => params.push(u64::MAX),
+    }
+    match &options.filter {
+        Some(filter) => {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            filter.hash(&mut hasher);
+            params.push(hasher.finish());
+        }
+        None => params.push(u64::MAX),
+    }
+    for floats in [&options.part_weights, &options.rescoring_query] {
+        match floats {
+            Some(values) => {
+                params.push(values.len() as u64);
+                params.extend(values.iter().map(|v| v.to_bits() as u64));
+            }
+            None => params.push(u64::MAX),
+        }
+    }
+    params
+}
+
+// Changes applied by ScannRetriever::rebuild_with. Fields left at None keep
+// the source retriever's setting.
+#[derive(Clone, Default)]
+pub struct RebuildPlan {
+    pub k: Option<usize>,
+    // Some(Some(config)) re-encodes int8 codes with `config`; Some(None)
+    // drops them.
+    pub quantization: Option<Option<quantization::Int8QuantizationConfig>>,
+    // Retrains the partitioning with this many leaves, warm-started from the
+    // current centers when there are any.
+    pub retrain_partitions: Option<(usize, tree::KMeansTreeTrainingOptions)>,
+    // Some(false) detaches the rescoring dataset; Some(true) requires the
+    // source to have one.
+    pub reordering: Option<bool>,
+    // Normalization can only be turned on: unnormalized rows cannot be
+    // recovered from normalized ones.
+    pub normalization: Option<util::Normalization>,
+}
+
+impl RebuildPlan {
+    fn validate(&self, source: &ScannRetriever, snapshot: &RetrieverSnapshot) -> Result<(), Box<dyn Error>> {
+        if self.k == Some(0) {
+            return Err(util::invalid_argument_error("RebuildPlan k must be at least 1"));
+        }
+        if let Some(normalization) = self.normalization {
+            if normalization != source.normalization {
+                if source.normalization != util::Normalization::None {
+                    return Err(util::invalid_argument_error(
+                        "Cannot remove normalization from an already normalized retriever",
+                    ));
+                }
+                if snapshot.tree.is_some() && self.retrain_partitions.is_none() {
+                    return Err(util::invalid_argument_error(
+                        "Changing normalization moves every row; the plan must also retrain the partitioning",
+                    ));
+                }
+            }
+        }
+        if self.reordering == Some(true) && source.rescoring.read().unwrap().is_none() {
+            return Err(util::failed_precondition_error(
+                "Reordering requested but the source retriever has no rescoring dataset",
+            ));
+        }
+        if let Some((num_leaves, _)) = &self.retrain_partitions {
+            if *num_leaves == 0 {
+                return Err(util::invalid_argument_error("Cannot retrain with zero leaves"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Rocchio-style pseudo-relevance feedback: each round replaces the query
+// with alpha * query + (1 - alpha) * centroid(top m results).
+#[derive(Clone, Copy, Debug)]
+pub struct FeedbackOptions {
+    pub m: usize,
+    pub alpha: f32,
+    pub iterations: usize,
+}
+
+// (docid, distance) pairs, nearest first.
+pub type Neighbors = Vec<(usize, f32)>;
+
+// (docids, distances, number filled) from search_small_k.
+pub type SmallKResults<const K: usize> = ([i64; K], [f32; K], usize);
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchStats {
+    pub leaves_searched: usize,
+    // Leaves of a partitioned dot-product search skipped because their
+    // bound could not beat the k-th best distance found so far; not
+    // included in leaves_searched.
+    pub leaves_pruned: usize,
+    pub datapoints_scored: usize,
+    // Datapoints dropped or clamped because their distance was NaN/Inf.
+    pub non_finite_skipped: usize,
+    pub non_finite_clamped: usize,
+    // Datapoints whose distance stopped early against the k-th best, for
+    // measures that exit early on a threshold; included in
+    // datapoints_scored.
+    pub early_exits: usize,
+    // Aligned with the returned results when calibration was requested.
+    pub calibrated_scores: Option<Vec<f32>>,
+    // Leaf code store activity for partitioned searches.
+    pub leaf_cache_hits: usize,
+    pub leaf_cache_misses: usize,
+    pub leaf_load_micros: u64,
+    // The search was cancelled before scanning everything it planned to.
+    pub truncated: bool,
+    // Query validation passes and full leaf orderings computed by this call;
+    // both stay zero when a PreparedQuery already carried the work.
+    pub query_preparations: usize,
+    pub leaf_orderings: usize,
+    // Filled when SearchOptions::collect_histogram is set. Counts distances
+    // after non-finite handling, so it sums to datapoints_scored minus
+    // non_finite_skipped.
+    pub histogram: Option<DistanceHistogram>,
+    // One entry per SearchOptions::facets column, in the same order.
+    pub facets: Vec<FacetCounts>,
+}
+
+const SCORE_DISTRIBUTION_SEED: u64 = 0x5eed;
+
+static NEXT_RETRIEVER_ID: AtomicU64 = AtomicU64::new(0);
+
+// A validated query bound to the retriever that prepared it. The leaf
+// ordering is computed on first partitioned search and reused until the
+// retriever is mutated, so repeated multi-stage searches with the same query
+// skip it.
+pub struct PreparedQuery {
+    query: util::DatapointPtr<f32>,
+    retriever_id: u64,
+    // Only queries from `ScannRetriever::prepare` keep their leaf ordering.
+    retain: bool,
+    leaf_order: OnceLock<(u64, Vec<usize>)>,
+}
+
+// What a search reads from its query: the values and, for a prepared query
+// that keeps it, the cache of its leaf ordering. One-shot searches borrow
+// the caller's query instead of copying it into a PreparedQuery.
+#[derive(Clone, Copy)]
+struct QueryRef<'a> {
+    values: &'a [f32],
+    leaf_order: Option<&'a OnceLock<(u64, Vec<usize>)>>,
+}
+
+impl<'a> QueryRef<'a> {
+    fn one_shot(values: &'a [f32]) -> Self {
+        QueryRef { values, leaf_order: None }
+    }
+
+    fn values(&self) -> &'a [f32] {
+        self.values
+    }
+}
+
+const PREPARED_QUERY_WIRE_VERSION: u8 = 1;
+const SEARCH_CURSOR_WIRE_VERSION: u8 = 1;
+
+impl PreparedQuery {
+    pub fn values(&self) -> &[f32] {
+        self.query.values()
+    }
+
+    // Wire encoding (binfmt::write_wire framing), version 1 payload:
+    //   dim u64 | dim x f32 | retain u8
+    // Captures the query values and whether the query came from prepare.
+    // The retriever binding and the cached leaf ordering are local to one
+    // process and one index state, so they are not captured: from_bytes
+    // binds the query to `retriever` and recomputes the ordering on first
+    // use.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = binfmt::ByteOrder(binfmt::Endianness::Little);
+        let values = self.query.values();
+        let mut payload = Vec::with_capacity(8 + values.len() * 4 + 1);
+        order.put_u64(&mut payload, values.len() as u64);
+        for &v in values {
+            order.put_f32(&mut payload, v);
+        }
+        payload.push(self.retain as u8);
+        binfmt::write_wire(binfmt::WIRE_KIND_PREPARED_QUERY, PREPARED_QUERY_WIRE_VERSION, &payload)
+    }
+
+    // Decodes a query and validates it against `retriever` as prepare does.
+    pub fn from_bytes(bytes: &[u8], retriever: &ScannRetriever) -> Result<PreparedQuery, Box<dyn Error>> {
+        let name = "PreparedQuery";
+        let (_, payload) =
+            binfmt::read_wire(bytes, binfmt::WIRE_KIND_PREPARED_QUERY, PREPARED_QUERY_WIRE_VERSION, name)?;
+        let mut reader = binfmt::WireReader::new(payload, name);
+        let dim = reader.count(4)?;
+        let values = (0..dim).map(|_| reader.f32()).collect::<Result<Vec<f32>, _>>()?;
+        let retain = match reader.u8()? {
+            0 => false,
+            1 => true,
+            flag => {
+                return Err(util::invalid_argument_error(&format!(
+                    "PreparedQuery has invalid retain flag {}",
+                    flag
+                )))
+            }
+        };
+        reader.finish()?;
+        let mut prepared = retriever.prepare_once(util::DatapointPtr::new(values))?;
+        prepared.retain = retain;
+        Ok(prepared)
+    }
+}
+
+// Position in a paginated search, returned by ScannRetriever::search_page.
+// Stamped with the retriever's generation, which every mutation bumps, so a
+// cursor issued before a mutation is rejected instead of silently skipping
+// or repeating results. Generations count mutations since the retriever was
+// built or loaded: a cursor moves between replicas loaded from the same blob
+// only while they have seen the same mutations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchCursor {
+    generation: u64,
+    // Results already returned.
+    offset: usize,
+    query_fingerprint: u64,
+}
+
+impl SearchCursor {
+    // Wire encoding (binfmt::write_wire framing), version 1 payload:
+    //   generation u64 | offset u64 | query_fingerprint u64
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = binfmt::ByteOrder(binfmt::Endianness::Little);
+        let mut payload = Vec::with_capacity(24);
+        order.put_u64(&mut payload, self.generation);
+        order.put_u64(&mut payload, self.offset as u64);
+        order.put_u64(&mut payload, self.query_fingerprint);
+        binfmt::write_wire(binfmt::WIRE_KIND_SEARCH_CURSOR, SEARCH_CURSOR_WIRE_VERSION, &payload)
+    }
+
+    // Staleness is checked when the cursor is used, not here.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SearchCursor, Box<dyn Error>> {
+        let name = "SearchCursor";
+        let (_, payload) = binfmt::read_wire(bytes, binfmt::WIRE_KIND_SEARCH_CURSOR, SEARCH_CURSOR_WIRE_VERSION, name)?;
+        let mut reader = binfmt::WireReader::new(payload, name);
+        let generation = reader.u64()?;
+        let offset = reader.u64()?;
+        let query_fingerprint = reader.u64()?;
+        reader.finish()?;
+        let offset = usize::try_from(offset)
+            .map_err(|_| util::invalid_argument_error(&format!("SearchCursor offset {} does not fit usize", offset)))?;
+        Ok(SearchCursor {
+            generation,
+            offset,
+            query_fingerprint,
+        })
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+fn query_fingerprint(values: &[f32]) -> u64 {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    blob::fnv1a64(&bytes)
+}
+
+// Auxiliary per-row representation derived from the raw vectors (norms,
+// quantized codes, ...). Every registered structure is updated by the same
+// mutation that changes the row, so search never mixes stale and fresh data.
+pub trait DerivedData: Send + Sync {
+    fn name(&self) -> &str;
+    fn rebuild(&mut self, dataset: &util::DenseDataset<f32>) -> Result<(), Box<dyn Error>>;
+    fn push(&mut self, values: &[f32]) -> Result<(), Box<dyn Error>>;
+    fn update(&mut self, index: usize, values: &[f32]) -> Result<(), Box<dyn Error>>;
+    fn clone_box(&self) -> Box<dyn DerivedData>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+#[derive(Clone, Default)]
+pub struct NormCache {
+    pub norms: Vec<f32>,
+}
+
+
+impl DerivedData for NormCache {
+    fn name(&self) -> &str {
+        "norms"
+    }
+
+    fn rebuild(&mut self, dataset: &util::DenseDataset<f32>) -> Result<(), Box<dyn Error>> {
+        self.norms = dataset.data.iter().map(|row| distance_measures::l2_norm(row)).collect();
+        Ok(())
+    }
+
+    fn push(&mut self, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        self.norms.push(distance_measures::l2_norm(values));
+        Ok(())
+    }
+
+    fn update(&mut self, index: usize, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        self.norms[index] = distance_measures::l2_norm(values);
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn DerivedData> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Int8 codes keep the multipliers chosen at build time; updated rows are
+// re-encoded with them immediately.
+#[derive(Clone)]
+pub struct Int8Codes {
+    pub config: quantization::Int8QuantizationConfig,
+    pub quantized: Option<quantization::Int8QuantizedDataset>,
+}
+
+impl Int8Codes {
+    pub fn new(config: quantization::Int8QuantizationConfig) -> Self {
+        Int8Codes { config, quantized: None }
+    }
+
+    fn encode(&self, values: &[f32]) -> Result<Vec<i8>, Box<dyn Error>> {
+        let Some(quantized) = &self.quantized else {
+            return Err(util::failed_precondition_error("Int8 codes have not been built"));
+        };
+        Ok(values
+            .iter()
+            .zip(quantized.multipliers.iter())
+            .map(|(&v, &m)| (v * m).round().clamp(-127.0, 127.0) as i8)
+            .collect())
+    }
+}
+
+impl DerivedData for Int8Codes {
+    fn name(&self) -> &str {
+        "int8_codes"
+    }
+
+    fn rebuild(&mut self, dataset: &util::DenseDataset<f32>) -> Result<(), Box<dyn Error>> {
+        self.quantized = Some(quantization::quantize_int8(dataset, &self.config)?);
+        Ok(())
+    }
+
+    fn push(&mut self, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        let codes = self.encode(values)?;
+        self.quantized.as_mut().unwrap().codes.data.push(codes);
+        Ok(())
+    }
+
+    fn update(&mut self, index: usize, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        let codes = self.encode(values)?;
+        self.quantized.as_mut().unwrap().codes.data[index] = codes;
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn DerivedData> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Exact top-k of an f32 query over int8 rows, scored with the measure's
+// mixed int8 kernel. `docid_of` maps a row index to its docid, or None to
+// skip the row. Ascending by distance, ties by docid; NaN distances are
+// dropped.
+fn int8_top_k(
+    quantized: &quantization::Int8QuantizedDataset,
+    measure: &dyn distance_measures::DistanceMeasure,
+    query: &[f32],
+    k: usize,
+    docid_of: impl Fn(usize) -> Option<usize>,
+) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+    if query.len() != quantized.codes.dimensionality() {
+        return Err(util::invalid_argument_error(&format!(
+            "Query has dimensionality {}, int8 codes have {}",
+            query.len(),
+            quantized.codes.dimensionality()
+        )));
+    }
+    let mut scratch = Vec::new();
+    let mut results: Vec<(usize, f32)> = quantized
+        .codes
+        .data
+        .iter()
+        .enumerate()
+        .filter_map(|(i, row)| {
+            let docid = docid_of(i)?;
+            let distance = measure.compute_distance_mixed_i8(query, row, Some(&quantized.multipliers), &mut scratch);
+            (!distance.is_nan()).then_some((docid, distance))
+        })
+        .collect();
+    results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    results.truncate(k);
+    Ok(results)
+}
+
+// Brute-force retriever over int8 storage only: rows are kept as codes plus
+// per-dimension multipliers, never as f32, and f32 queries are scored with
+// the measure's mixed int8 kernel. Docids are row indices.
+pub struct Int8Retriever {
+    quantized: quantization::Int8QuantizedDataset,
     distance_measure: Box<dyn distance_measures::DistanceMeasure>,
     k: usize,
 }
 
-impl ScannRetriever {
-    pub fn new(
-        dataset: utils::DenseDataset<f32>,
-        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+impl Int8Retriever {
+    pub fn new(
+        quantized: quantization::Int8QuantizedDataset,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+    ) -> Self {
+        Int8Retriever { quantized, distance_measure, k }
+    }
+
+    // Quantizes `dataset` with `config` and keeps only the codes.
+    pub fn build(
+        dataset: &util::DenseDataset<f32>,
+        config: &quantization::Int8QuantizationConfig,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(quantization::quantize_int8(dataset, config)?, distance_measure, k))
+    }
+
+    pub fn dataset(&self) -> &util::DenseDataset<i8> {
+        &self.quantized.codes
+    }
+
+    pub fn multipliers(&self) -> &[f32] {
+        &self.quantized.multipliers
+    }
+
+    pub fn size(&self) -> usize {
+        self.quantized.codes.size()
+    }
+
+    pub fn search(&self, query: &[f32]) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        self.search_top_k(query, self.k)
+    }
+
+    pub fn search_top_k(&self, query: &[f32], k: usize) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        self.distance_measure.check_dimensionality(query.len())?;
+        int8_top_k(&self.quantized, self.distance_measure.as_ref(), query, k, Some)
+    }
+}
+
+// Second representation of the corpus, aligned with the primary dataset by
+// row index.
+struct RescoringAttachment {
+    dataset: Arc<util::DenseDataset<f32>>,
+    measure: Box<dyn distance_measures::DistanceMeasure>,
+}
+
+// Docid -> row index, sharded by docid range so a clone shares every shard
+// a write does not touch.
+#[derive(Clone, Default)]
+struct DocidIndex {
+    shards: HashMap<usize, Arc<HashMap<usize, usize>>>,
+    len: usize,
+}
+
+impl DocidIndex {
+    fn shard(docid: usize) -> usize {
+        docid / util::SEGMENT_LEN
+    }
+
+    fn get(&self, docid: &usize) -> Option<&usize> {
+        self.shards.get(&Self::shard(*docid)).and_then(|shard| shard.get(docid))
+    }
+
+    fn contains_key(&self, docid: &usize) -> bool {
+        self.get(docid).is_some()
+    }
+
+    fn insert(&mut self, docid: usize, index: usize) {
+        let shard = self.shards.entry(Self::shard(docid)).or_default();
+        if Arc::make_mut(shard).insert(docid, index).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&usize, &usize)> + '_ {
+        self.shards.values().flat_map(|shard| shard.iter())
+    }
+}
+
+impl std::ops::Index<&usize> for DocidIndex {
+    type Output = usize;
+
+    fn index(&self, docid: &usize) -> &usize {
+        self.get(docid).unwrap_or_else(|| panic!("Unknown docid: {}", docid))
+    }
+}
+
+impl FromIterator<(usize, usize)> for DocidIndex {
+    fn from_iter<I: IntoIterator<Item = (usize, usize)>>(iter: I) -> Self {
+        let mut index = DocidIndex::default();
+        for (docid, row) in iter {
+            index.insert(docid, row);
+        }
+        index
+    }
+}
+
+// Set of docids, sharded like DocidIndex so that tombstoning one docid
+// copies only its shard.
+#[derive(Clone, Default)]
+struct DocidSet {
+    shards: HashMap<usize, Arc<HashSet<usize>>>,
+    len: usize,
+}
+
+impl DocidSet {
+    fn contains(&self, docid: &usize) -> bool {
+        self.shards
+            .get(&DocidIndex::shard(*docid))
+            .is_some_and(|shard| shard.contains(docid))
+    }
+
+    fn insert(&mut self, docid: usize) -> bool {
+        let shard = self.shards.entry(DocidIndex::shard(docid)).or_default();
+        let inserted = Arc::make_mut(shard).insert(docid);
+        self.len += inserted as usize;
+        inserted
+    }
+
+    fn remove(&mut self, docid: &usize) -> bool {
+        let key = DocidIndex::shard(*docid);
+        let Some(shard) = self.shards.get_mut(&key) else {
+            return false;
+        };
+        if !shard.contains(docid) {
+            return false;
+        }
+        Arc::make_mut(shard).remove(docid);
+        if shard.is_empty() {
+            self.shards.remove(&key);
+        }
+        self.len -= 1;
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &usize> + '_ {
+        self.shards.values().flat_map(|shard| shard.iter())
+    }
+
+    fn to_hash_set(&self) -> HashSet<usize> {
+        self.iter().copied().collect()
+    }
+}
+
+impl Extend<usize> for DocidSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+        for docid in iter {
+            self.insert(docid);
+        }
+    }
+}
+
+impl FromIterator<usize> for DocidSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = DocidSet::default();
+        set.extend(iter);
+        set
+    }
+}
+
+// Docids every read path skips: tombstones plus quarantined zero vectors.
+// Borrowed from a snapshot, so a search never copies either set.
+#[derive(Clone, Copy)]
+struct HiddenDocids<'a> {
+    tombstones: &'a DocidSet,
+    quarantined: &'a DocidSet,
+}
+
+impl HiddenDocids<'_> {
+    fn contains(&self, docid: &usize) -> bool {
+        self.tombstones.contains(docid) || self.quarantined.contains(docid)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tombstones.is_empty() && self.quarantined.is_empty()
+    }
+}
+
+// Derived data to mutate, copied first when another snapshot shares it.
+fn derived_mut(derived: &mut Arc<dyn DerivedData>) -> &mut dyn DerivedData {
+    if Arc::get_mut(derived).is_none() {
+        *derived = Arc::from(derived.clone_box());
+    }
+    Arc::get_mut(derived).unwrap()
+}
+
+// Immutable view of the searchable storage. Searches clone the Arc and run
+// against it without holding any lock, so a concurrent compaction can never
+// expose a mix of old and new row layouts. Mutations copy on write: rows,
+// docids and the docid map are segmented and every other component sits
+// behind its own Arc, so a single-row write copies the segments it touches
+// and shares the rest with the previous snapshot.
+#[derive(Clone)]
+struct RetrieverSnapshot {
+    dataset: util::SegmentedDataset<f32>,
+    docids: util::SegmentedVec<usize>,
+    docid_to_index: DocidIndex,
+    next_docid: usize,
+    derived: Vec<Arc<dyn DerivedData>>,
+    tree: Option<Arc<tree::KMeansTree>>,
+    // Dropped by any mutation; rebuild with ScannRetriever::build_kd_tree.
+    kd_tree: Option<Arc<kd_tree::KdTree>>,
+    // When set, partitioned searches score dequantized int8 codes loaded per
+    // leaf instead of the raw vectors. Held here so a search always reads
+    // codes laid out for the rows it scores; dropped by any data or tree
+    // mutation in the same swap that publishes it.
+    leaf_code_store: Option<Arc<dyn leaf_codes::LeafCodeStore>>,
+    // Width every row index and docid must fit; recorded in blobs.
+    index_width: util::IndexWidth,
+    index_overflow: util::IndexOverflowPolicy,
+    // Row-aligned attribute columns used by facets and filters.
+    attributes: Arc<attribute_store::AttributeStore>,
+    // Column values set for docids not stored yet, applied when the docid's
+    // row is added.
+    pending_attributes: Arc<PendingAttributes>,
+    // Removed docids whose rows stay stored until compaction. Kept in the
+    // snapshot so a search sees the tombstones of the rows it reads.
+    tombstones: DocidSet,
+    // Docids of stored zero vectors that cannot be scored; treated like
+    // tombstones by every read path but kept through compaction.
+    quarantined: DocidSet,
+}
+
+impl RetrieverSnapshot {
+    fn new(dataset: util::DenseDataset<f32>, docids: Vec<usize>) -> Self {
+        Self::from_segmented(util::SegmentedDataset::from_dense(dataset), docids)
+    }
+
+    fn from_segmented(dataset: util::SegmentedDataset<f32>, docids: Vec<usize>) -> Self {
+        let docid_to_index = docids.iter().enumerate().map(|(i, &docid)| (docid, i)).collect();
+        let next_docid = docids.iter().max().map_or(0, |&max| max + 1);
+        let index_width = util::IndexWidth::for_size(docids.len().max(next_docid));
+        let attributes = Arc::new(attribute_store::AttributeStore::new(docids.len()));
+        RetrieverSnapshot {
+            dataset,
+            docids: util::SegmentedVec::from_vec(docids),
+            docid_to_index,
+            next_docid,
+            derived: Vec::new(),
+            tree: None,
+            kd_tree: None,
+            leaf_code_store: None,
+            index_width,
+            index_overflow: util::IndexOverflowPolicy::default(),
+            attributes,
+            pending_attributes: Arc::default(),
+            tombstones: DocidSet::default(),
+            quarantined: DocidSet::default(),
+        }
+    }
+
+    // Keeps the quarantine set in step with an ingested row.
+    fn set_quarantined(&mut self, docid: usize, quarantined: bool) {
+        if quarantined {
+            self.quarantined.insert(docid);
+        } else {
+            self.quarantined.remove(&docid);
+        }
+    }
+
+    fn hidden(&self) -> HiddenDocids<'_> {
+        HiddenDocids {
+            tombstones: &self.tombstones,
+            quarantined: &self.quarantined,
+        }
+    }
+
+    // Installs a partitioning with its leaves stored at the snapshot's index
+    // width.
+    fn set_tree(&mut self, mut tree: tree::KMeansTree) -> Result<(), Box<dyn Error>> {
+        tree.set_index_width(self.index_width)?;
+        self.tree = Some(Arc::new(tree));
+        Ok(())
+    }
+
+    // Checks that a new row and its docid fit the index width, widening it
+    // or failing per the overflow policy. Runs before any state changes.
+    fn reserve_index(&mut self, docid: usize) -> Result<(), Box<dyn Error>> {
+        let largest = docid.max(self.docids.len());
+        if self.index_width.fits(largest) {
+            return Ok(());
+        }
+        match self.index_overflow {
+            util::IndexOverflowPolicy::Upgrade => {
+                self.index_width = util::IndexWidth::U64;
+                if let Some(tree) = self.tree.as_mut() {
+                    Arc::make_mut(tree).set_index_width(util::IndexWidth::U64)?;
+                }
+                Ok(())
+            }
+            util::IndexOverflowPolicy::Error => Err(util::failed_precondition_error(&format!(
+                "Index {} exceeds the {:?} index limit of {}; use IndexOverflowPolicy::Upgrade",
+                largest,
+                self.index_width,
+                self.index_width.max_index()
+            ))),
+        }
+    }
+
+    // Moves the pending values of `docid` onto its freshly pushed row, the
+    // last attribute row.
+    fn apply_pending_attributes(&mut self, docid: usize) -> Result<(), Box<dyn Error>> {
+        if !self.pending_attributes.values().any(|pending| pending.contains_key(&docid)) {
+            return Ok(());
+        }
+        let row = self.attributes.num_rows() - 1;
+        let attributes = Arc::make_mut(&mut self.attributes);
+        for (name, pending) in Arc::make_mut(&mut self.pending_attributes).iter_mut() {
+            if let Some(value) = pending.remove(&docid) {
+                attributes.set_value(name, row, Some(&value))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push_row(&mut self, docid: usize, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        self.reserve_index(docid)?;
+        self.dataset.append(values)?;
+        self.kd_tree = None;
+        for derived in self.derived.iter_mut() {
+            derived_mut(derived).push(values)?;
+        }
+        if let Some(tree) = self.tree.as_mut() {
+            Arc::make_mut(tree).insert(self.docids.len(), values);
+        }
+        Arc::make_mut(&mut self.attributes).push_row();
+        self.apply_pending_attributes(docid)?;
+        self.docid_to_index.insert(docid, self.docids.len());
+        self.docids.push(docid);
+        self.next_docid = self.next_docid.max(docid + 1);
+        Ok(())
+    }
+
+    fn update_row(&mut self, index: usize, values: &[f32], reassign_leaf: bool) -> Result<(), Box<dyn Error>> {
+        if values.len() != self.dataset.dimensionality() {
+            return Err(util::invalid_argument_error(&format!(
+                "Dimension mismatch: expected {}, got {}",
+                self.dataset.dimensionality(),
+                values.len()
+            )));
+        }
+        self.dataset.set_row(index, values);
+        self.kd_tree = None;
+        for derived in self.derived.iter_mut() {
+            derived_mut(derived).update(index, values)?;
+        }
+        if reassign_leaf {
+            if let Some(tree) = self.tree.as_mut() {
+                Arc::make_mut(tree).reassign(index, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+// One active datapoint as seen by ScannRetriever::for_each_active. The
+// vector is borrowed from the retriever's storage.
+pub struct DatapointRecord<'a> {
+    pub docid: usize,
+    pub values: &'a [f32],
+    // First partition holding the row, when partitioned. Spilled rows also
+    // appear in other partitions.
+    pub partition: Option<usize>,
+    pub expires_at: Option<i64>,
+    index: usize,
+    attributes: &'a attribute_store::AttributeStore,
+}
+
+impl<'a> DatapointRecord<'a> {
+    fn new(
+        snapshot: &'a RetrieverSnapshot,
+        index: usize,
+        partitions: &[Option<usize>],
+    ) -> Self {
+        DatapointRecord {
+            docid: snapshot.docids[index],
+            values: snapshot.dataset.row(index),
+            partition: partitions.get(index).copied().flatten(),
+            expires_at: snapshot.attributes.get_i64(EXPIRY_COLUMN, index),
+            index,
+            attributes: &snapshot.attributes,
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<i64> {
+        self.attributes.get_i64(name, self.index)
+    }
+
+    pub fn attribute_f32(&self, name: &str) -> Option<f32> {
+        self.attributes.get_f32(name, self.index)
+    }
+
+    pub fn attribute_str(&self, name: &str) -> Option<&'a str> {
+        self.attributes.get_str(name, self.index)
+    }
+}
+
+// Attribute values keyed by column name, then by docid.
+type PendingAttributes = HashMap<String, HashMap<usize, attribute_store::Literal>>;
+
+// Row-aligned column from (docid, value) pairs. Values for docids not
+// currently stored become the column's pending values.
+fn column_by_docid<T: Into<attribute_store::Literal>>(
+    snapshot: &RetrieverSnapshot,
+    values: impl IntoIterator<Item = (usize, T)>,
+) -> (Vec<Option<T>>, HashMap<usize, attribute_store::Literal>) {
+    let mut column: Vec<Option<T>> = (0..snapshot.docids.len()).map(|_| None).collect();
+    let mut pending = HashMap::new();
+    for (docid, value) in values {
+        match snapshot.docid_to_index.get(&docid) {
+            Some(&index) => column[index] = Some(value),
+            None => {
+                pending.insert(docid, value.into());
+            }
+        }
+    }
+    (column, pending)
+}
+
+// First leaf of each row, or empty when the snapshot is unpartitioned.
+fn row_partitions(snapshot: &RetrieverSnapshot) -> Vec<Option<usize>> {
+    let Some(tree) = &snapshot.tree else {
+        return Vec::new();
+    };
+    let mut partitions = vec![None; snapshot.dataset.size()];
+    for leaf in 0..tree.num_leaves() {
+        for i in tree.leaf(leaf) {
+            partitions[i].get_or_insert(leaf);
+        }
+    }
+    partitions
+}
+
+// What a fork sees of the retriever it was made from.
+struct ForkBase {
+    // Shares the parent's snapshot as of the fork. Its tombstones also hold
+    // every base docid the fork hides, so searching it skips them.
+    view: ScannRetriever,
+    // Base docids hidden by the fork: removed, or shadowed by a copy in the
+    // fork's own snapshot after an upsert or attribute change.
+    hidden: RwLock<HashSet<usize>>,
+}
+
+impl ForkBase {
+    // Hides a visible base row. Returns false when `docid` is not stored in
+    // the base.
+    fn hide(&self, docid: usize) -> bool {
+        if !self.view.current_snapshot().docid_to_index.contains_key(&docid) {
+            return false;
+        }
+        self.hidden.write().unwrap().insert(docid);
+        Arc::make_mut(&mut self.view.snapshot.write().unwrap()).tombstones.insert(docid);
+        true
+    }
+}
+
+pub struct ScannRetriever {
+    snapshot: RwLock<Arc<RetrieverSnapshot>>,
+    // Shared with replicas made by rebuild_with.
+    distance_measure: Arc<dyn distance_measures::DistanceMeasure>,
+    k: usize,
+    calibrator: RwLock<Option<calibration::Calibrator>>,
+    reordering_summary: RwLock<Option<quantization::ReorderingSummary>>,
+    non_finite_handling: util::NonFiniteHandling,
+    normalization: util::Normalization,
+    zero_vector_policy: util::ZeroVectorPolicy,
+    // Unrolled kernel for dims 2, 3, 4 and 8 when the measure supports it.
+    low_dim_kernel: Option<distance_measures::SliceKernel>,
+    // Set when built from a DistanceMeasureKind; the scoring loops then use
+    // static dispatch instead of calling through `distance_measure`.
+    measure_kind: Option<distance_measures::DistanceMeasureKind>,
+    query_logger: RwLock<Option<Arc<query_log::QueryLogger>>>,
+    drift_monitor: RwLock<Option<Arc<drift::QueryDriftMonitor>>>,
+    rescoring: RwLock<Option<Arc<RescoringAttachment>>>,
+    result_cache: RwLock<Option<Arc<ResultCache>>>,
+    // Bumped after every mutation and part of each cache key, so results
+    // computed against an older state are never served afterwards.
+    cache_generation: AtomicU64,
+    arena_high_water_bytes: AtomicUsize,
+    // Last expired set computed by a search, reused while it still applies.
+    expired: Mutex<Option<ExpiredWindow>>,
+    // Set on a retriever made by fork(); its own snapshot then holds only
+    // the rows the fork added or shadowed.
+    fork: Option<Box<ForkBase>>,
+    // Distinguishes retrievers so a PreparedQuery is never used against
+    // another index's partitioning.
+    id: u64,
+}
+
+impl ScannRetriever {
+    pub fn new(
+        dataset: util::DenseDataset<f32>,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+    ) -> Self {
+        let docids = (0..dataset.size()).collect();
+        Self::from_snapshot(RetrieverSnapshot::new(dataset, docids), distance_measure, k)
+    }
+
+    // Cosine-distance retriever. With `normalized` the rows are scaled to
+    // unit norm once (queries on every search) and scored as
+    // 1 - dot, which ranks identically at the cost of a dot product; zero
+    // rows are then rejected or quarantined per the zero-vector policy.
+    pub fn new_cosine(dataset: util::DenseDataset<f32>, k: usize, normalized: bool) -> Result<Self, Box<dyn Error>> {
+        if !normalized {
+            return Ok(Self::new(dataset, Box::new(distance_measures::CosineDistance::new()), k));
+        }
+        Self::new(dataset, Box::new(distance_measures::NormalizedDotProductDistance::new()), k)
+            .with_normalization(util::Normalization::UnitL2)
+    }
+
+    // Same as `new` with a built-in measure, scored through match-based
+    // dispatch. Results are identical to `new` with the boxed measure.
+    pub fn with_measure_kind(
+        dataset: util::DenseDataset<f32>,
+        kind: distance_measures::DistanceMeasureKind,
+        k: usize,
+    ) -> Self {
+        let mut retriever = Self::new(dataset, kind.to_measure(), k);
+        retriever.measure_kind = Some(kind);
+        retriever
+    }
+
+    pub fn measure_kind(&self) -> Option<distance_measures::DistanceMeasureKind> {
+        self.measure_kind
+    }
+
+    // Overrides the index width picked from the dataset size and sets what
+    // add and upsert do once a U32 index is full. Fails if the stored rows
+    // or docids do not fit `width`.
+    pub fn with_index_width(
+        self,
+        width: util::IndexWidth,
+        overflow: util::IndexOverflowPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        self.check_not_fork("with_index_width")?;
+        {
+            let mut guard = self.snapshot.write().unwrap();
+            let largest = guard.docids.len().max(guard.next_docid.saturating_sub(1));
+            if !width.fits(largest) {
+                return Err(util::invalid_argument_error(&format!(
+                    "Index {} does not fit a {:?} index",
+                    largest, width
+                )));
+            }
+            let mut updated = (**guard).clone();
+            updated.index_width = width;
+            updated.index_overflow = overflow;
+            if let Some(tree) = updated.tree.as_mut() {
+                Arc::make_mut(tree).set_index_width(width)?;
+            }
+            *guard = Arc::new(updated);
+        }
+        Ok(self)
+    }
+
+    pub fn index_width(&self) -> util::IndexWidth {
+        self.current_snapshot().index_width
+    }
+
+    fn from_snapshot(
+        snapshot: RetrieverSnapshot,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+    ) -> Self {
+        let low_dim_kernel = distance_measure
+            .low_dim_kernel()
+            .and_then(|kernel| distance_measures::select_low_dim_kernel(kernel, snapshot.dataset.dimensionality()));
+        ScannRetriever {
+            low_dim_kernel,
+            measure_kind: None,
+            snapshot: RwLock::new(Arc::new(snapshot)),
+            distance_measure: Arc::from(distance_measure),
+            k,
+            calibrator: RwLock::new(None),
+            reordering_summary: RwLock::new(None),
+            non_finite_handling: util::NonFiniteHandling::default(),
+            normalization: util::Normalization::default(),
+            zero_vector_policy: util::ZeroVectorPolicy::default(),
+            query_logger: RwLock::new(None),
+            drift_monitor: RwLock::new(None),
+            rescoring: RwLock::new(None),
+            result_cache: RwLock::new(None),
+            cache_generation: AtomicU64::new(0),
+            arena_high_water_bytes: AtomicUsize::new(0),
+            expired: Mutex::new(None),
+            fork: None,
+            id: NEXT_RETRIEVER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // Index over bit vectors packed 8 dimensions per byte, searched by
+    // Hamming distance. Rows are stored as byte values widened to f32.
+    pub fn new_packed_binary(
+        dataset: &util::DenseDataset<u8>,
+        dimensionality_bits: usize,
+        k: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let expected = distance_measures::packed_len(dimensionality_bits);
+        if dataset.dimensionality() != expected {
+            return Err(util::invalid_argument_error(&format!(
+                "{} bit vectors pack into {} bytes, dataset has dimensionality {}",
+                dimensionality_bits,
+                expected,
+                dataset.dimensionality()
+            )));
+        }
+        Ok(Self::new(
+            convert::u8_to_f32_dataset(dataset),
+            Box::new(distance_measures::BinaryHammingDistance::with_dimensionality(dimensionality_bits)),
+            k,
+        ))
+    }
+
+    pub fn search_packed(&self, query: &util::DatapointPtr<u8>) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        self.search(&convert::u8_to_f32_datapoint(query))
+    }
+
+    // Like `new`, but validates or sanitizes the initial dataset according
+    // to `non_finite_handling`, which also governs later adds and searches.
+    pub fn try_new(
+        dataset: util::DenseDataset<f32>,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+        non_finite_handling: util::NonFiniteHandling,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut rows = Vec::with_capacity(dataset.size());
+        for (i, row) in dataset.data.iter().enumerate() {
+            rows.push(util::apply_non_finite_policy(row, non_finite_handling).map_err(|e| {
+                util::invalid_argument_error(&format!("Row {}: {}", i, e))
+            })?);
+        }
+        let dataset = util::DenseDataset::new(rows, dataset.dimensionality());
+        let mut retriever = Self::new(dataset, distance_measure, k);
+        retriever.non_finite_handling = non_finite_handling;
+        Ok(retriever)
+    }
+
+    // Normalizes the stored rows once and rebuilds derived data; afterwards
+    // add, upsert and partition imports normalize only the incoming vector.
+    // Zero rows follow the zero vector policy, so set that first.
+    pub fn with_normalization(mut self, normalization: util::Normalization) -> Result<Self, Box<dyn Error>> {
+        self.check_not_fork("with_normalization")?;
+        {
+            let mut guard = self.snapshot.write().unwrap();
+            let mut updated = (**guard).clone();
+            let quarantine = normalization == util::Normalization::UnitL2
+                && self.zero_vector_policy == util::ZeroVectorPolicy::Quarantine;
+            let mut dataset = updated.dataset.to_dense();
+            for (i, row) in dataset.data.iter_mut().enumerate() {
+                if quarantine && util::is_zero_vector(row) {
+                    let docid = updated.docids[i];
+                    updated.quarantined.insert(docid);
+                    continue;
+                }
+                *row = util::apply_normalization(std::mem::take(row), normalization)
+                    .map_err(|e| util::invalid_argument_error(&format!("Row {}: {}", i, e)))?;
+            }
+            updated.kd_tree = None;
+            for derived in updated.derived.iter_mut() {
+                derived_mut(derived).rebuild(&dataset)?;
+            }
+            updated.dataset = util::SegmentedDataset::from_dense(dataset);
+            *guard = Arc::new(updated);
+            self.mutated(&mut guard);
+        }
+        self.normalization = normalization;
+        Ok(self)
+    }
+
+    // Sets how zero vectors are handled under cosine distance or unit-L2
+    // normalization, applying it to the rows already stored.
+    pub fn with_zero_vector_policy(mut self, policy: util::ZeroVectorPolicy) -> Result<Self, Box<dyn Error>> {
+        self.check_not_fork("with_zero_vector_policy")?;
+        self.zero_vector_policy = policy;
+        if !self.zero_sensitive() {
+            return Ok(self);
+        }
+        let mut guard = self.snapshot.write().unwrap();
+        let mut updated = (**guard).clone();
+        for (row, &docid) in guard.dataset.rows().zip(guard.docids.iter()) {
+            if !util::is_zero_vector(row) {
+                continue;
+            }
+            match policy {
+                util::ZeroVectorPolicy::Reject => {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Docid {} is a zero vector, which {} cannot score",
+                        docid,
+                        self.distance_measure.name()
+                    )))
+                }
+                util::ZeroVectorPolicy::Quarantine => {
+                    updated.quarantined.insert(docid);
+                }
+            }
+        }
+        *guard = Arc::new(updated);
+        self.mutated(&mut guard);
+        drop(guard);
+        Ok(self)
+    }
+
+    // Zero vectors have no direction under cosine distance or normalization.
+    fn zero_sensitive(&self) -> bool {
+        self.normalization == util::Normalization::UnitL2
+            || matches!(self.distance_measure.name(), "CosineDistance" | "AngularDistance")
+    }
+
+    pub fn num_quarantined(&self) -> usize {
+        self.current_snapshot().quarantined.len()
+    }
+
+    // Applies the ingest-side non-finite policy, zero vector policy and
+    // normalization to one incoming vector. The flag is set when the row
+    // must be quarantined; it is then stored unnormalized.
+    fn prepare_row(&self, values: &[f32]) -> Result<(Vec<f32>, bool), Box<dyn Error>> {
+        let values = util::apply_non_finite_policy(values, self.non_finite_handling)?;
+        if self.zero_sensitive() && util::is_zero_vector(&values) {
+            return match self.zero_vector_policy {
+                util::ZeroVectorPolicy::Reject => Err(util::invalid_argument_error(&format!(
+                    "Zero vectors cannot be scored by {}",
+                    self.distance_measure.name()
+                ))),
+                util::ZeroVectorPolicy::Quarantine => Ok((values, true)),
+            };
+        }
+        Ok((util::apply_normalization(values, self.normalization)?, false))
+    }
+
+
+    // Checks that every active row has unit L2 norm within `tolerance` when
+    // UnitL2 normalization is enabled. Intended for tests and debug builds.
+    pub fn debug_validate_normalization(&self, tolerance: f32) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("debug_validate_normalization")?;
+        if self.normalization != util::Normalization::UnitL2 {
+            return Ok(());
+        }
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        for (row, docid) in snapshot.dataset.rows().zip(snapshot.docids.iter()) {
+            if tombstones.contains(docid) {
+                continue;
+            }
+            let norm = row.iter().map(|v| v * v).sum::<f32>().sqrt();
+            if (norm - 1.0).abs() > tolerance {
+                return Err(util::failed_precondition_error(&format!(
+                    "Docid {} has L2 norm {}, expected 1 within {}",
+                    docid, norm, tolerance
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Packs the storage (dataset, docids and partitioning) into one
+    // relocatable blob. Pending removals must be compacted away first so the
+    // blob never carries tombstoned rows.
+    pub fn pack_blob<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("pack_blob")?;
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        if !tombstones.is_empty() {
+            return Err(util::failed_precondition_error(
+                "Compact the retriever before packing a blob: it has pending removals",
+            ));
+        }
+        let mut sections = vec![
+            (blob::SectionKind::Dataset, blob::encode_dataset(&snapshot.dataset)),
+            (
+                blob::SectionKind::IndexWidth,
+                (snapshot.index_width.bytes() as u32).to_le_bytes().to_vec(),
+            ),
+            (
+                blob::SectionKind::Docids,
+                blob::encode_indices(&snapshot.docids.to_vec(), snapshot.index_width)?,
+            ),
+        ];
+        if let Some(tree) = &snapshot.tree {
+            sections.push((blob::SectionKind::Tree, blob::encode_tree(tree)));
+        }
+        if let Some(rescoring) = self.rescoring.read().unwrap().as_ref() {
+            let name = rescoring.measure.name();
+            if distance_measures::get_distance_measure_by_name(name).is_err() {
+                return Err(util::failed_precondition_error(&format!(
+                    "Rescoring measure {} cannot be recorded in a blob",
+                    name
+                )));
+            }
+            sections.push((blob::SectionKind::RescoringDataset, blob::encode_dataset(rescoring.dataset.as_ref())));
+            sections.push((blob::SectionKind::RescoringMeasure, name.as_bytes().to_vec()));
+        }
+        if snapshot.attributes.num_columns() > 0 {
+            sections.push((blob::SectionKind::Attributes, snapshot.attributes.encode()));
+        }
+        blob::write_blob(path, &sections)
+    }
+
+    pub fn load_blob<P: AsRef<std::path::Path>>(
+        path: P,
+        distance_measure: Box<dyn distance_measures::DistanceMeasure>,
+        k: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let bytes = blob::read_blob_file(path)?;
+        let sections = blob::decode_blob(&bytes)?;
+        let Some(dataset_bytes) = sections.get(&blob::SectionKind::Dataset) else {
+            return Err(util::invalid_argument_error("Blob has no dataset section"));
+        };
+        let dataset = blob::map_dataset_section(&bytes, blob::SectionKind::Dataset, dataset_bytes)?;
+        let index_width = match sections.get(&blob::SectionKind::IndexWidth) {
+            Some(bytes) => Some(blob::decode_index_width(bytes)?),
+            None => None,
+        };
+        let docids = match sections.get(&blob::SectionKind::Docids) {
+            Some(bytes) => blob::decode_indices(
+                blob::SectionKind::Docids,
+                bytes,
+                index_width.unwrap_or(util::IndexWidth::U64),
+            )?,
+            None => (0..dataset.size()).collect(),
+        };
+        if docids.len() != dataset.size() {
+            return Err(util::invalid_argument_error(&format!(
+                "Blob has {} docids for {} dataset rows",
+                docids.len(),
+                dataset.size()
+            )));
+        }
+        if let Some(docid) = util::first_duplicate(&docids) {
+            return Err(util::invalid_argument_error(&format!("Blob has duplicate docid {}", docid)));
+        }
+        let tree = match sections.get(&blob::SectionKind::Tree) {
+            Some(bytes) => Some(blob::decode_tree(bytes, dataset.size(), dataset.dimensionality())?),
+            None => None,
+        };
+
+        let rescoring = match (
+            sections.get(&blob::SectionKind::RescoringDataset),
+            sections.get(&blob::SectionKind::RescoringMeasure),
+        ) {
+            (Some(dataset_bytes), Some(name_bytes)) => {
+                let rescoring_dataset =
+                    blob::decode_dataset_section(blob::SectionKind::RescoringDataset, dataset_bytes)?;
+                let name = std::str::from_utf8(name_bytes)
+                    .map_err(|_| util::invalid_argument_error("Blob rescoring measure name is not UTF-8"))?;
+                Some((rescoring_dataset, distance_measures::get_distance_measure_by_name(name)?))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(util::invalid_argument_error(
+                    "Blob has a rescoring dataset or measure section without the other",
+                ))
+            }
+        };
+
+        // Blobs written before expiries moved into the attribute store keep
+        // them in a section of their own.
+        let legacy_expiries = match sections.get(&blob::SectionKind::Expiries) {
+            Some(bytes) => blob::decode_expiries(bytes)?,
+            None => Vec::new(),
+        };
+
+        let attributes = match sections.get(&blob::SectionKind::Attributes) {
+            Some(bytes) => Some(attribute_store::AttributeStore::decode(bytes)?),
+            None => None,
+        };
+
+        let mut snapshot = RetrieverSnapshot::from_segmented(dataset, docids);
+        if let Some(width) = index_width {
+            snapshot.index_width = width;
+        }
+        if let Some(mut tree) = tree {
+            tree.compute_leaf_bounds(&snapshot.dataset);
+            snapshot.set_tree(tree)?;
+        }
+        if let Some(attributes) = attributes {
+            if attributes.num_rows() != snapshot.docids.len() {
+                return Err(util::invalid_argument_error(&format!(
+                    "Blob has attributes for {} rows but {} dataset rows",
+                    attributes.num_rows(),
+                    snapshot.docids.len()
+                )));
+            }
+            snapshot.attributes = Arc::new(attributes);
+        }
+        for (docid, expires_at) in legacy_expiries {
+            let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+                return Err(util::invalid_argument_error(&format!(
+                    "Blob has an expiry for unknown docid {}",
+                    docid
+                )));
+            };
+            Arc::make_mut(&mut snapshot.attributes).set_value(
+                EXPIRY_COLUMN,
+                index,
+                Some(&attribute_store::Literal::Int(expires_at)),
+            )?;
+        }
+        let retriever = Self::from_snapshot(snapshot, distance_measure, k);
+        if let Some((rescoring_dataset, measure)) = rescoring {
+            retriever.attach_rescoring_dataset(Arc::new(rescoring_dataset), measure)?;
+        }
+        Ok(retriever)
+    }
+
+    // Builds a retriever over loaded artifacts, with the measure named in
+    // their config. A saved tree and attribute store are kept; unit-L2
+    // artifacts normalize queries like the retriever that wrote them.
+    pub fn from_artifacts(artifacts: artifacts::Artifacts, k: usize) -> Result<Self, Box<dyn Error>> {
+        let distance_measure = distance_measures::get_distance_measure_by_name(&artifacts.config.distance_measure)?;
+        let mut snapshot = RetrieverSnapshot::new(artifacts.dataset, artifacts.docids);
+        if let Some(tree) = artifacts.tree {
+            snapshot.set_tree(tree)?;
+        }
+        if let Some(attributes) = artifacts.attributes {
+            snapshot.attributes = Arc::new(attributes);
+        }
+        let retriever = Self::from_snapshot(snapshot, distance_measure, k);
+        match artifacts.config.normalization {
+            util::Normalization::None => Ok(retriever),
+            normalization => retriever.with_normalization(normalization),
+        }
+    }
+
+    // Caches up to `capacity` result lists for bitwise identical queries
+    // with identical search options. Any mutation invalidates the cache.
+    pub fn enable_result_cache(&self, capacity: usize) {
+        *self.result_cache.write().unwrap() = Some(Arc::new(query_cache::QueryCache::new(capacity)));
+    }
+
+    pub fn disable_result_cache(&self) {
+        *self.result_cache.write().unwrap() = None;
+    }
+
+    pub fn invalidate_result_cache(&self) {
+        self.cache_generation.fetch_add(1, Ordering::AcqRel);
+        if let Some(cache) = self.result_cache.read().unwrap().as_ref() {
+            cache.clear();
+        }
+    }
+
+    // Replaces the named i64 column. Values for docids not currently stored
+    // are kept and applied when a row with that docid is added; stored
+    // docids without a value are reported as missing by facet counts. On a
+    // fork only the fork's own rows are covered; use set_attribute to change
+    // a base point.
+    pub fn set_attribute_column(&self, name: &str, values: impl IntoIterator<Item = (usize, i64)>) {
+        self.update_attributes(name, |snapshot| {
+            let (column, pending) = column_by_docid(snapshot, values);
+            Arc::make_mut(&mut snapshot.attributes).set_i64_column(name, column)?;
+            Ok(pending)
+        });
+    }
+
+    pub fn set_f32_attribute_column(&self, name: &str, values: impl IntoIterator<Item = (usize, f32)>) {
+        self.update_attributes(name, |snapshot| {
+            let (column, pending) = column_by_docid(snapshot, values);
+            Arc::make_mut(&mut snapshot.attributes).set_f32_column(name, column)?;
+            Ok(pending)
+        });
+    }
+
+    // String values are dictionary-encoded; meant for low-cardinality
+    // labels such as regions or categories.
+    pub fn set_str_attribute_column<S: AsRef<str>>(&self, name: &str, values: impl IntoIterator<Item = (usize, S)>) {
+        self.update_attributes(name, |snapshot| {
+            let values = values.into_iter().map(|(docid, value)| (docid, value.as_ref().to_string()));
+            let (column, pending) = column_by_docid(snapshot, values);
+            Arc::make_mut(&mut snapshot.attributes).set_str_column(name, &column)?;
+            Ok(pending)
+        });
+    }
+
+    // Columns always match the snapshot's row count, so setting one cannot
+    // fail. `update` returns the column's new pending values.
+    fn update_attributes(
+        &self,
+        name: &str,
+        update: impl FnOnce(&mut RetrieverSnapshot) -> Result<HashMap<usize, attribute_store::Literal>, Box<dyn Error>>,
+    ) {
+        let mut guard = self.snapshot.write().unwrap();
+        let snapshot = Arc::make_mut(&mut guard);
+        let pending = update(snapshot).expect("attribute column built for the current rows");
+        let columns = Arc::make_mut(&mut snapshot.pending_attributes);
+        if pending.is_empty() {
+            columns.remove(name);
+        } else {
+            columns.insert(name.to_string(), pending);
+        }
+        self.invalidate_result_cache();
+    }
+
+    pub fn remove_attribute_column(&self, name: &str) -> bool {
+        let mut guard = self.snapshot.write().unwrap();
+        if guard.attributes.column_type(name).is_none() && !guard.pending_attributes.contains_key(name) {
+            return false;
+        }
+        let snapshot = Arc::make_mut(&mut guard);
+        Arc::make_mut(&mut snapshot.attributes).remove_column(name);
+        Arc::make_mut(&mut snapshot.pending_attributes).remove(name);
+        self.invalidate_result_cache();
+        true
+    }
+
+    // On a fork, docids not stored in the fork itself are read from the
+    // base.
+    pub fn attribute(&self, name: &str, docid: usize) -> Option<i64> {
+        let snapshot = self.current_snapshot();
+        let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+            return self.fork.as_ref()?.view.attribute(name, docid);
+        };
+        snapshot.attributes.get_i64(name, index)
+    }
+
+    pub fn attribute_f32(&self, name: &str, docid: usize) -> Option<f32> {
+        let snapshot = self.current_snapshot();
+        let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+            return self.fork.as_ref()?.view.attribute_f32(name, docid);
+        };
+        snapshot.attributes.get_f32(name, index)
+    }
+
+    pub fn attribute_str(&self, name: &str, docid: usize) -> Option<String> {
+        let snapshot = self.current_snapshot();
+        let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+            return self.fork.as_ref()?.view.attribute_str(name, docid);
+        };
+        Some(snapshot.attributes.get_str(name, index)?.to_string())
+    }
+
+    // Sets or clears one attribute of a stored point, creating the column
+    // with the value's type if needed. On a fork, a base point is first
+    // copied into the fork so the base keeps its value.
+    pub fn set_attribute(
+        &self,
+        docid: usize,
+        name: &str,
+        value: Option<attribute_store::Literal>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.shadow_base_row(docid)?;
+        let mut guard = self.snapshot.write().unwrap();
+        let Some(&index) = guard.docid_to_index.get(&docid) else {
+            return Err(util::invalid_argument_error(&format!("Unknown docid: {}", docid)));
+        };
+        let mut attributes = (*guard.attributes).clone();
+        attributes.set_value(name, index, value.as_ref())?;
+        Arc::make_mut(&mut guard).attributes = Arc::new(attributes);
+        drop(guard);
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    // Replaces every attribute column with `attributes`, which must have one
+    // row per stored row, in storage order, and drops pending column
+    // values. Expiries are kept unless `attributes` has its own
+    // EXPIRY_COLUMN.
+    pub fn set_attribute_store(&self, mut attributes: attribute_store::AttributeStore) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("set_attribute_store")?;
+        let mut guard = self.snapshot.write().unwrap();
+        if attributes.num_rows() != guard.docids.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "Attribute store has {} rows but the index has {}",
+                attributes.num_rows(),
+                guard.docids.len()
+            )));
+        }
+        if attributes.column_type(EXPIRY_COLUMN).is_none() {
+            if let Some(expiries) = guard.attributes.i64_values(EXPIRY_COLUMN) {
+                attributes.set_i64_column(EXPIRY_COLUMN, expiries.to_vec())?;
+            }
+        }
+        let snapshot = Arc::make_mut(&mut guard);
+        snapshot.attributes = Arc::new(attributes);
+        snapshot.pending_attributes = Arc::default();
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    // Runs `f` against the current attribute columns, aligned with storage
+    // rows (tombstoned rows included).
+    pub fn with_attributes<R>(&self, f: impl FnOnce(&attribute_store::AttributeStore) -> R) -> R {
+        f(&self.current_snapshot().attributes)
+    }
+
+    // Sets or clears the expiry of a stored point, in epoch seconds. The
+    // point stays stored and is only skipped by searches whose as_of is at
+    // or after the expiry.
+    pub fn set_expiry(&self, docid: usize, expires_at: Option<i64>) -> Result<(), Box<dyn Error>> {
+        self.set_attribute(docid, EXPIRY_COLUMN, expires_at.map(attribute_store::Literal::Int))
+    }
+
+    pub fn expiry(&self, docid: usize) -> Option<i64> {
+        self.attribute(EXPIRY_COLUMN, docid)
+    }
+
+    // Expired rows of a snapshot with these attributes, or None when no
+    // point has an expiry.
+    fn expired_window(
+        &self,
+        attributes: &Arc<attribute_store::AttributeStore>,
+        as_of: Option<i64>,
+    ) -> Option<ExpiredWindow> {
+        attributes.i64_values(EXPIRY_COLUMN)?;
+        let as_of = as_of.unwrap_or_else(unix_now_secs);
+        let mut cached = self.expired.lock().unwrap();
+        if let Some(window) = cached.as_ref().filter(|window| window.covers(attributes, as_of)) {
+            return Some(window.clone());
+        }
+        let window = ExpiredWindow::evaluate(attributes, as_of)?;
+        *cached = Some(window.clone());
+        Some(window)
+    }
+
+    // Tombstones every point that expired at or before `now - grace_secs`,
+    // making it eligible for compaction. Returns the number newly
+    // tombstoned; searches at earlier as_of times no longer see them.
+    pub fn tombstone_expired(&self, now: i64, grace_secs: i64) -> usize {
+        let cutoff = now.saturating_sub(grace_secs);
+        let mut guard = self.snapshot.write().unwrap();
+        let expired: Vec<usize> = match ExpiredWindow::evaluate(&guard.attributes, cutoff) {
+            Some(window) => window
+                .rows
+                .iter()
+                .map(|row| guard.docids[row])
+                .filter(|docid| !guard.tombstones.contains(docid))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut added = expired.len();
+        if added > 0 {
+            Arc::make_mut(&mut guard).tombstones.extend(expired);
+        }
+        drop(guard);
+        if let Some(base) = &self.fork {
+            added += base.view.tombstone_expired(now, grace_secs);
+        }
+        if added > 0 {
+            self.invalidate_result_cache();
+        }
+        added
+    }
+
+    // Called after every change to rows or partitioning, with the snapshot
+    // write lock still held so the leaf codes go in the same swap.
+    fn mutated(&self, snapshot: &mut Arc<RetrieverSnapshot>) {
+        if snapshot.leaf_code_store.is_some() {
+            Arc::make_mut(snapshot).leaf_code_store = None;
+        }
+        self.invalidate_result_cache();
+    }
+
+    fn int8_codes(snapshot: &RetrieverSnapshot) -> Result<&quantization::Int8QuantizedDataset, Box<dyn Error>> {
+        snapshot
+            .derived
+            .iter()
+            .find_map(|d| d.as_any().downcast_ref::<Int8Codes>())
+            .and_then(|codes| codes.quantized.as_ref())
+            .ok_or_else(|| util::failed_precondition_error("Int8 codes must be registered as derived data"))
+    }
+
+    // Exact top-k of an f32 query scored directly against the registered
+    // Int8Codes with the measure's mixed int8 kernel, dequantizing per
+    // dimension inside the distance. Ascending by distance, ties by docid.
+    pub fn search_int8_codes(&self, query: &[f32], k: usize) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        self.check_not_fork("search_int8_codes")?;
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let codes = Self::int8_codes(&snapshot)?;
+        self.distance_measure.check_dimensionality(query.len())?;
+        int8_top_k(codes, self.distance_measure.as_ref(), query, k, |i| {
+            let docid = snapshot.docids[i];
+            (!tombstones.contains(&docid)).then_some(docid)
+        })
+    }
+
+    // Reconstruction error of the registered Int8Codes over every row.
+    pub fn int8_error_summary(&self) -> Result<quantization::QuantizationErrorSummary, Box<dyn Error>> {
+        self.check_not_fork("int8_error_summary")?;
+        let snapshot = self.current_snapshot();
+        Ok(quantization::error_summary(&snapshot.dataset.to_dense(), Self::int8_codes(&snapshot)?))
+    }
+
+    // Keeps every leaf's int8 codes resident in a LeafCodeStore.
+    pub fn build_leaf_code_store(&self) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("build_leaf_code_store")?;
+        let snapshot = self.current_snapshot();
+        let Some(tree) = &snapshot.tree else {
+            return Err(util::failed_precondition_error("Retriever has no partitioning"));
+        };
+        let store = leaf_codes::InMemoryLeafCodeStore::build(tree, Self::int8_codes(&snapshot)?);
+        self.set_leaf_code_store(Some(Arc::new(store)))
+    }
+
+    // Writes per-leaf codes for a FileLeafCodeStore.
+    pub fn write_leaf_codes<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("write_leaf_codes")?;
+        let snapshot = self.current_snapshot();
+        let Some(tree) = &snapshot.tree else {
+            return Err(util::failed_precondition_error("Retriever has no partitioning"));
+        };
+        leaf_codes::write_leaf_codes(path, tree, Self::int8_codes(&snapshot)?)
+    }
+
+    pub fn set_leaf_code_store(&self, store: Option<Arc<dyn leaf_codes::LeafCodeStore>>) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("set_leaf_code_store")?;
+        // Checked and installed under the write lock, so no mutation can
+        // land in between.
+        let mut snapshot = self.snapshot.write().unwrap();
+        if let Some(store) = &store {
+            let num_leaves = snapshot.tree.as_ref().map_or(0, |tree| tree.num_leaves());
+            if store.num_leaves() != num_leaves
+                || store.num_rows() != snapshot.dataset.size()
+                || store.dimensionality() != snapshot.dataset.dimensionality()
+            {
+                return Err(util::invalid_argument_error(&format!(
+                    "Leaf code store ({} leaves, {} rows, dim {}) does not match the index ({} leaves, {} rows, dim {})",
+                    store.num_leaves(),
+                    store.num_rows(),
+                    store.dimensionality(),
+                    num_leaves,
+                    snapshot.dataset.size(),
+                    snapshot.dataset.dimensionality()
+                )));
+            }
+            // Codes clipped at a different quantile dequantize on another
+            // scale than the registered Int8Codes.
+            if let Ok(codes) = Self::int8_codes(&snapshot) {
+                if store.clip_quantile() != codes.clip_quantile {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Leaf code store was quantized with clip_quantile {:?}, the index with {:?}",
+                        store.clip_quantile(),
+                        codes.clip_quantile
+                    )));
+                }
+            }
+        }
+        Arc::make_mut(&mut snapshot).leaf_code_store = store;
+        drop(snapshot);
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> RetrieverMetrics {
+        let mut metrics = RetrieverMetrics {
+            arena_high_water_bytes: self.arena_high_water_bytes.load(Ordering::Relaxed),
+            ..RetrieverMetrics::default()
+        };
+        if let Some(cache) = self.result_cache.read().unwrap().as_ref() {
+            let counters = cache.counters();
+            metrics.cache_hits = counters.hits;
+            metrics.cache_misses = counters.misses;
+            metrics.cache_evictions = counters.evictions;
+            metrics.cache_entries = cache.len();
+        }
+        if let Some(monitor) = self.drift_monitor.read().unwrap().as_ref() {
+            metrics.query_drift = monitor.score();
+            metrics.query_drift_detected = monitor.is_drifted();
+        }
+        metrics
+    }
+
+    // Every searched query, cache hits included, is folded into the
+    // monitor's running statistics. Results are unaffected.
+    pub fn set_drift_monitor(&self, monitor: Option<Arc<drift::QueryDriftMonitor>>) {
+        *self.drift_monitor.write().unwrap() = monitor;
+    }
+
+    // Monitor whose reference is a reservoir sample of the stored rows.
+    pub fn drift_monitor_from_data(
+        &self,
+        sample_size: usize,
+        seed: u64,
+        config: drift::DriftConfig,
+    ) -> Result<drift::QueryDriftMonitor, Box<dyn Error>> {
+        self.check_not_fork("drift_monitor_from_data")?;
+        let snapshot = self.current_snapshot();
+        let reference = drift::DriftReference::from_dataset(&snapshot.dataset.to_dense(), sample_size, seed)?;
+        drift::QueryDriftMonitor::new(reference, config)
+    }
+
+    fn observe_drift(&self, query: &[f32]) {
+        if let Some(monitor) = self.drift_monitor.read().unwrap().as_ref() {
+            monitor.observe(query);
+        }
+    }
+
+    fn log_query(
+        &self,
+        query: &[f32],
+        options: &SearchOptions,
         k: usize,
-    ) -> Self {
+        start: std::time::Instant,
+        results: &[(usize, f32)],
+    ) {
+        if let Some(logger) = self.query_logger.read().unwrap().as_ref() {
+            let latency_us = start.elapsed().as_micros().min(u32::MAX as u128) as u32;
+            logger.log(query, options, k, latency_us, results);
+        }
+    }
+
+    pub fn set_query_logger(&self, logger: Option<Arc<query_log::QueryLogger>>) {
+        *self.query_logger.write().unwrap() = logger;
+    }
+
+    // Attaches a second representation of the corpus, aligned with the
+    // primary dataset by row index, for SearchOptions::rescore_with_attached.
+    // Mutations that change the row count require re-attaching.
+    pub fn attach_rescoring_dataset(
+        &self,
+        dataset: Arc<util::DenseDataset<f32>>,
+        measure: Box<dyn distance_measures::DistanceMeasure>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("attach_rescoring_dataset")?;
+        let size = self.current_snapshot().dataset.size();
+        if dataset.size() != size {
+            return Err(util::invalid_argument_error(&format!(
+                "Rescoring dataset has {} rows but the index has {}",
+                dataset.size(),
+                size
+            )));
+        }
+        *self.rescoring.write().unwrap() = Some(Arc::new(RescoringAttachment { dataset, measure }));
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    pub fn detach_rescoring_dataset(&self) {
+        *self.rescoring.write().unwrap() = None;
+        self.invalidate_result_cache();
+    }
+
+    pub fn set_calibrator(&self, calibrator: Option<calibration::Calibrator>) {
+        *self.calibrator.write().unwrap() = calibrator;
+        self.invalidate_result_cache();
+    }
+
+    // Measures int8 quantization noise on a sample of rows. Requires Int8Codes
+    // to be registered as derived data.
+    pub fn build_reordering_summary(&self, sample_size: usize, k: usize, seed: u64) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("build_reordering_summary")?;
+        let snapshot = self.current_snapshot();
+        let quantized = snapshot
+            .derived
+            .iter()
+            .find_map(|d| d.as_any().downcast_ref::<Int8Codes>())
+            .and_then(|codes| codes.quantized.as_ref())
+            .ok_or_else(|| util::failed_precondition_error("Int8 codes must be registered before measuring reordering noise"))?;
+        let summary = quantization::compute_reordering_summary(
+            &snapshot.dataset.to_dense(),
+            quantized,
+            self.distance_measure.as_ref(),
+            sample_size,
+            k,
+            seed,
+        );
+        *self.reordering_summary.write().unwrap() = Some(summary);
+        Ok(())
+    }
+
+    pub fn reordering_summary(&self) -> Option<quantization::ReorderingSummary> {
+        self.reordering_summary.read().unwrap().clone()
+    }
+
+    pub fn set_reordering_summary(&self, summary: Option<quantization::ReorderingSummary>) {
+        *self.reordering_summary.write().unwrap() = summary;
+    }
+
+    pub fn suggest_reordering_size(&self, target_recall: f32, k: usize) -> usize {
+        match self.reordering_summary.read().unwrap().as_ref() {
+            Some(summary) => summary.suggest_reordering_size(target_recall, k),
+            None => k,
+        }
+    }
+
+    // Exact distances from `query` to a seeded uniform sample of active rows,
+    // binned over the sampled [min, max] without any top-k selection. Repeated
+    // calls on an unchanged index sample the same rows.
+    pub fn score_distribution(
+        &self,
+        query: &util::DatapointPtr<f32>,
+        sample_fraction: f32,
+        bins: usize,
+    ) -> Result<DistanceHistogram, Box<dyn Error>> {
+        self.check_not_fork("score_distribution")?;
+        if !(sample_fraction > 0.0 && sample_fraction <= 1.0) {
+            return Err(util::invalid_argument_error(&format!(
+                "sample_fraction must be in (0, 1], got {}",
+                sample_fraction
+            )));
+        }
+        let snapshot = self.current_snapshot();
+        self.validate_query(query.values(), snapshot.dataset.dimensionality())?;
+        let tombstones = snapshot.hidden();
+        let mut rng = util::SplitMix64::new(SCORE_DISTRIBUTION_SEED);
+        let mut distances = Vec::new();
+        for (row, docid) in snapshot.dataset.rows().zip(snapshot.docids.iter()) {
+            if rng.next_f32() >= sample_fraction || tombstones.contains(docid) {
+                continue;
+            }
+            let distance = self.distance_measure.compute_distance_f32(query.values(), row);
+            if distance.is_finite() {
+                distances.push(distance);
+            }
+        }
+        if distances.is_empty() {
+            return Err(util::failed_precondition_error("No rows with a finite distance were sampled"));
+        }
+        let low = distances.iter().cloned().fold(f32::INFINITY, f32::min);
+        let high = distances.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        // Widen the half-open range just enough that the maximum is in range.
+        let high = high + (high - low).max(high.abs()).max(1.0) * 4.0 * f32::EPSILON;
+        let spec = HistogramSpec { bins, range: (low, high) };
+        spec.validate()?;
+        let mut histogram = DistanceHistogram::new(spec);
+        for distance in distances {
+            histogram.record(distance);
+        }
+        Ok(histogram)
+    }
+
+    pub fn distance_to_docid(&self, query: &util::DatapointPtr<f32>, docid: usize) -> Result<f32, Box<dyn Error>> {
+        let snapshot = self.current_snapshot();
+        query.check_dimensionality(Some(snapshot.dataset.dimensionality()), "Query")?;
+        self.distance_measure.check_dimensionality(query.values().len())?;
+        let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+            if let Some(base) = &self.fork {
+                return base.view.distance_to_docid(query, docid);
+            }
+            return Err(util::invalid_argument_error(&format!("Unknown docid: {}", docid)));
+        };
+        Ok(distance_measures::compute_distance_to_row(
+            self.distance_measure.as_ref(),
+            query.values(),
+            &snapshot.dataset,
+            index,
+        ))
+    }
+
+    // Copy of the vector stored under `docid`, tombstoned or not.
+    pub fn get_by_docid(&self, docid: usize) -> Option<Vec<f32>> {
+        let snapshot = self.current_snapshot();
+        let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+            return self.fork.as_ref()?.view.get_by_docid(docid);
+        };
+        Some(snapshot.dataset.row(index).to_vec())
+    }
+
+    // Calls `visitor` for every active datapoint in storage order, skipping
+    // tombstoned and quarantined ones, until it returns Break. Records borrow
+    // the stored vectors, so nothing is copied; the snapshot is pinned for
+    // the duration, and concurrent mutations are not observed. A fork visits
+    // the base rows it has not hidden before its own.
+    pub fn for_each_active(
+        &self,
+        mut visitor: impl FnMut(DatapointRecord<'_>) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        self.visit_active(&mut visitor)
+    }
+
+    // Takes the visitor as a trait object so that recursing into a fork's
+    // base does not instantiate a new closure type per level.
+    fn visit_active(&self, visitor: &mut dyn FnMut(DatapointRecord<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+        if let Some(base) = &self.fork {
+            base.view.visit_active(visitor)?;
+        }
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let partitions = row_partitions(&snapshot);
+        for i in 0..snapshot.dataset.size() {
+            if tombstones.contains(&snapshot.docids[i]) {
+                continue;
+            }
+            visitor(DatapointRecord::new(&snapshot, i, &partitions))?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Parallel for_each_active over contiguous row ranges. Records arrive in
+    // no particular order; after a Break, the other threads stop at their
+    // next record.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_active(
+        &self,
+        visitor: impl Fn(DatapointRecord<'_>) -> ControlFlow<()> + Sync,
+    ) -> ControlFlow<()> {
+        self.par_visit_active(&visitor)
+    }
+
+    #[cfg(feature = "rayon")]
+    fn par_visit_active(&self, visitor: &(dyn Fn(DatapointRecord<'_>) -> ControlFlow<()> + Sync)) -> ControlFlow<()> {
+        use rayon::prelude::*;
+        if let Some(base) = &self.fork {
+            base.view.par_visit_active(visitor)?;
+        }
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let partitions = row_partitions(&snapshot);
+        let stopped = std::sync::atomic::AtomicBool::new(false);
+        (0..snapshot.dataset.size()).into_par_iter().for_each(|i| {
+            if stopped.load(Ordering::Relaxed) || tombstones.contains(&snapshot.docids[i]) {
+                return;
+            }
+            let record = DatapointRecord::new(&snapshot, i, &partitions);
+            if visitor(record).is_break() {
+                stopped.store(true, Ordering::Relaxed);
+            }
+        });
+        match stopped.into_inner() {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        }
+    }
+
+    fn current_snapshot(&self) -> Arc<RetrieverSnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    // The trained k-means partitioning over the stored rows, if any.
+    pub fn partitioning(&self) -> Option<Arc<tree::KMeansTree>> {
+        self.current_snapshot().tree.clone()
+    }
+
+    // Builds an exact k-d tree used by unpartitioned searches. Only valid for
+    // squared L2 over finite data of dimensionality <= 16.
+    pub fn build_kd_tree(&self, leaf_size: usize) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("build_kd_tree")?;
+        if self.distance_measure.low_dim_kernel() != Some(distance_measures::LowDimKernel::SquaredL2) {
+            return Err(util::failed_precondition_error("k-d tree search requires a squared L2 measure"));
+        }
+        let mut guard = self.snapshot.write().unwrap();
+        if guard.dataset.rows().any(|row| row.iter().any(|v| !v.is_finite())) {
+            return Err(util::failed_precondition_error("k-d tree search requires finite data"));
+        }
+        let kd_tree = kd_tree::KdTree::build(&guard.dataset, leaf_size)?;
+        Arc::make_mut(&mut guard).kd_tree = Some(Arc::new(kd_tree));
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    pub fn build_partitions(
+        &self,
+        num_leaves: usize,
+        options: &tree::KMeansTreeTrainingOptions,
+    ) -> Result<tree::PartitioningOutcome, Box<dyn Error>> {
+        self.check_not_fork("build_partitions")?;
+        let mut guard = self.snapshot.write().unwrap();
+        // A corpus too small to partition is searched brute force; any stale
+        // tree from a larger corpus is dropped.
+        if let Some(reason) = tree::partitioning_skip_reason(guard.dataset.size(), num_leaves, options) {
+            Arc::make_mut(&mut guard).tree = None;
+            self.mutated(&mut guard);
+            return Ok(tree::PartitioningOutcome::Skipped { reason });
+        }
+        let mut options = options.clone();
+        options.reinitialize_zero_centers |= self.zero_sensitive();
+        let (tree, stats) = tree::KMeansTree::train(&guard.dataset.to_dense(), num_leaves, &options)?;
+        Arc::make_mut(&mut guard).set_tree(tree)?;
+        self.mutated(&mut guard);
+        Ok(tree::PartitioningOutcome::Built(stats))
+    }
+
+    // Builds a second retriever from this one in memory. The snapshot (rows,
+    // derived data and partitioning), measure, rescoring dataset and leaf
+    // code store are shared by Arc unless the plan changes them; changed
+    // storage is copied once and edited. This retriever keeps serving
+    // throughout and is not modified. The replica starts without a result
+    // cache or query logger.
+    pub fn rebuild_with(&self, plan: &RebuildPlan) -> Result<ScannRetriever, Box<dyn Error>> {
+        self.check_not_fork("rebuild_with")?;
+        let snapshot = self.current_snapshot();
+        plan.validate(self, &snapshot)?;
+        let normalization = plan.normalization.unwrap_or(self.normalization);
+        let mut updated: Option<RetrieverSnapshot> = None;
+
+        if plan.quantization.is_some() {
+            let edited = updated.get_or_insert_with(|| (*snapshot).clone());
+            edited.derived.retain(|d| d.as_any().downcast_ref::<Int8Codes>().is_none());
+        }
+        if normalization != self.normalization {
+            let edited = updated.get_or_insert_with(|| (*snapshot).clone());
+            let quarantine = self.zero_vector_policy == util::ZeroVectorPolicy::Quarantine;
+            let mut dataset = edited.dataset.to_dense();
+            for (i, row) in dataset.data.iter_mut().enumerate() {
+                let docid = edited.docids[i];
+                if quarantine && util::is_zero_vector(row) {
+                    edited.quarantined.insert(docid);
+                }
+                if edited.quarantined.contains(&docid) {
+                    continue;
+                }
+                *row = util::apply_normalization(std::mem::take(row), normalization)
+                    .map_err(|e| util::invalid_argument_error(&format!("Row {}: {}", i, e)))?;
+            }
+            edited.kd_tree = None;
+            for derived in edited.derived.iter_mut() {
+                derived_mut(derived).rebuild(&dataset)?;
+            }
+            edited.dataset = util::SegmentedDataset::from_dense(dataset);
+        }
+        if let Some(Some(config)) = &plan.quantization {
+            let edited = updated.get_or_insert_with(|| (*snapshot).clone());
+            let mut codes = Int8Codes::new(config.clone());
+            codes.rebuild(&edited.dataset.to_dense())?;
+            edited.derived.push(Arc::new(codes));
+        }
+        if let Some((num_leaves, options)) = &plan.retrain_partitions {
+            let edited = updated.get_or_insert_with(|| (*snapshot).clone());
+            let mut options = options.clone();
+            if let Some(tree) = &snapshot.tree {
+                options.warm_start_centers = Some(tree.centers().clone());
+            }
+            edited.tree = None;
+            if tree::partitioning_skip_reason(edited.dataset.size(), *num_leaves, &options).is_none() {
+                edited.set_tree(tree::KMeansTree::train(&edited.dataset.to_dense(), *num_leaves, &options)?.0)?;
+            }
+        }
+
+        let storage_changed = updated.is_some();
+        let rescoring = match plan.reordering {
+            Some(false) => None,
+            _ => self.rescoring.read().unwrap().clone(),
+        };
+        // Changed storage no longer matches the leaf codes.
+        let updated = updated.map(|mut edited| {
+            edited.leaf_code_store = None;
+            Arc::new(edited)
+        });
+        Ok(ScannRetriever {
+            snapshot: RwLock::new(updated.unwrap_or(snapshot)),
+            distance_measure: self.distance_measure.clone(),
+            k: plan.k.unwrap_or(self.k),
+            calibrator: RwLock::new(*self.calibrator.read().unwrap()),
+            reordering_summary: RwLock::new(match storage_changed {
+                true => None,
+                false => self.reordering_summary.read().unwrap().clone(),
+            }),
+            non_finite_handling: self.non_finite_handling,
+            normalization,
+            zero_vector_policy: self.zero_vector_policy,
+            low_dim_kernel: self.low_dim_kernel,
+            measure_kind: self.measure_kind,
+            query_logger: RwLock::new(None),
+            drift_monitor: RwLock::new(None),
+            rescoring: RwLock::new(rescoring),
+            result_cache: RwLock::new(None),
+            cache_generation: AtomicU64::new(0),
+            arena_high_water_bytes: AtomicUsize::new(0),
+            expired: Mutex::new(None),
+            fork: None,
+            id: NEXT_RETRIEVER_ID.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    // Copy-on-write child for experiments. The fork shares this retriever's
+    // current snapshot by Arc and keeps its changes in an overlay: rows it
+    // adds or upserts, the base docids it removes, and base rows copied over
+    // by upsert, set_expiry or set_attribute. Searches merge the base and
+    // the overlay, so neither retriever observes the other's later changes.
+    // Whole-column attribute setters and with_attributes see only the
+    // fork's own rows.
+    // Operations over the whole storage (compaction, partitioning, blobs,
+    // quantized codes, rescoring) are rejected on a fork until it is
+    // materialized, and so are forks of forks.
+    pub fn fork(&self) -> Result<ScannRetriever, Box<dyn Error>> {
+        if self.fork.is_some() {
+            return Err(util::failed_precondition_error(
+                "Cannot fork a fork; materialize it first",
+            ));
+        }
+        let snapshot = self.current_snapshot();
+        let mut overlay = RetrieverSnapshot::new(
+            util::DenseDataset::new(Vec::new(), snapshot.dataset.dimensionality()),
+            Vec::new(),
+        );
+        overlay.next_docid = snapshot.next_docid;
+        overlay.index_width = snapshot.index_width;
+        overlay.index_overflow = snapshot.index_overflow;
+        let view = self.detached(snapshot);
+        *view.reordering_summary.write().unwrap() = self.reordering_summary.read().unwrap().clone();
+        let mut fork = self.detached(Arc::new(overlay));
+        fork.calibrator = RwLock::new(*self.calibrator.read().unwrap());
+        fork.fork = Some(Box::new(ForkBase {
+            view,
+            hidden: RwLock::new(HashSet::new()),
+        }));
+        Ok(fork)
+    }
+
+    pub fn is_fork(&self) -> bool {
+        self.fork.is_some()
+    }
+
+    // Flattens a fork into a standalone retriever. Shadowed base rows are
+    // dropped, rows the fork removed stay tombstoned, and the fork's rows
+    // are appended after the base rows, joining its partitioning and derived
+    // data. The fork and its base are unchanged. Like rebuild_with, the
+    // result starts without a result cache or query logger.
+    pub fn materialize(&self) -> Result<ScannRetriever, Box<dyn Error>> {
+        let Some(base) = &self.fork else {
+            return Err(util::failed_precondition_error("Only a fork can be materialized"));
+        };
+        let overlay = self.current_snapshot();
+        let old = base.view.current_snapshot();
+        let mut data = Vec::with_capacity(old.dataset.size() + overlay.dataset.size());
+        let mut docids = Vec::with_capacity(old.dataset.size() + overlay.dataset.size());
+        let mut old_to_new = Vec::with_capacity(old.dataset.size());
+        for (row, &docid) in old.dataset.rows().zip(old.docids.iter()) {
+            if overlay.docid_to_index.contains_key(&docid) {
+                old_to_new.push(None);
+            } else {
+                old_to_new.push(Some(docids.len()));
+                data.push(row.to_vec());
+                docids.push(docid);
+            }
+        }
+        let dataset = util::DenseDataset::new(data, old.dataset.dimensionality());
+        let mut derived = Vec::with_capacity(old.derived.len());
+        for original in &old.derived {
+            let mut rebuilt = original.clone_box();
+            rebuilt.rebuild(&dataset)?;
+            derived.push(Arc::from(rebuilt));
+        }
+        let mut merged = RetrieverSnapshot::new(dataset, docids);
+        merged.derived = derived;
+        merged.next_docid = merged.next_docid.max(old.next_docid).max(overlay.next_docid);
+        merged.index_width = overlay.index_width;
+        merged.index_overflow = overlay.index_overflow;
+        merged.attributes = Arc::new(old.attributes.select_rows(&old_to_new));
+        merged.pending_attributes = overlay.pending_attributes.clone();
+        if let Some(tree) = &old.tree {
+            let mut remapped = (**tree).clone();
+            remapped.remap(&old_to_new);
+            merged.set_tree(remapped)?;
+        }
+        for (i, values) in overlay.dataset.rows().enumerate() {
+            let row = merged.docids.len();
+            merged.push_row(overlay.docids[i], values)?;
+            Arc::make_mut(&mut merged.attributes).copy_row_from(row, &overlay.attributes, i)?;
+        }
+
+        let unshadowed = |set: &DocidSet| -> Vec<usize> {
+            set.iter().copied().filter(|docid| !overlay.docid_to_index.contains_key(docid)).collect()
+        };
+        merged.tombstones.extend(unshadowed(&old.tombstones));
+        merged.tombstones.extend(overlay.tombstones.iter().copied());
+        merged.quarantined.extend(unshadowed(&old.quarantined));
+        merged.quarantined.extend(overlay.quarantined.iter().copied());
+        let standalone = self.detached(Arc::new(merged));
+        *standalone.calibrator.write().unwrap() = *self.calibrator.read().unwrap();
+        Ok(standalone)
+    }
+
+    // Retriever over `snapshot` with this one's measure, k and ingest
+    // policies, and none of its attachments.
+    fn detached(&self, snapshot: Arc<RetrieverSnapshot>) -> ScannRetriever {
         ScannRetriever {
-            dataset,
-            distance_measure,
+            snapshot: RwLock::new(snapshot),
+            distance_measure: self.distance_measure.clone(),
+            k: self.k,
+            calibrator: RwLock::new(None),
+            reordering_summary: RwLock::new(None),
+            non_finite_handling: self.non_finite_handling,
+            normalization: self.normalization,
+            zero_vector_policy: self.zero_vector_policy,
+            low_dim_kernel: self.low_dim_kernel,
+            measure_kind: self.measure_kind,
+            query_logger: RwLock::new(None),
+            drift_monitor: RwLock::new(None),
+            rescoring: RwLock::new(None),
+            result_cache: RwLock::new(None),
+            cache_generation: AtomicU64::new(0),
+            arena_high_water_bytes: AtomicUsize::new(0),
+            expired: Mutex::new(None),
+            fork: None,
+            id: NEXT_RETRIEVER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    fn check_not_fork(&self, operation: &str) -> Result<(), Box<dyn Error>> {
+        match self.fork {
+            Some(_) => Err(util::failed_precondition_error(&format!(
+                "{} is not supported on a fork; materialize it first",
+                operation
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Copies a visible base row, with its attributes, expiry, tombstone and
+    // quarantine state, into a fork's own snapshot and hides it in the base,
+    // so it can be changed without touching the base. Does nothing when
+    // `docid` is already the fork's or is not a visible base row.
+    fn shadow_base_row(&self, docid: usize) -> Result<(), Box<dyn Error>> {
+        let Some(base) = &self.fork else {
+            return Ok(());
+        };
+        let mut hidden = base.hidden.write().unwrap();
+        let mut base_guard = base.view.snapshot.write().unwrap();
+        let source = base_guard.clone();
+        let mut guard = self.snapshot.write().unwrap();
+        if hidden.contains(&docid) || guard.docid_to_index.contains_key(&docid) {
+            return Ok(());
+        }
+        let Some(&index) = source.docid_to_index.get(&docid) else {
+            return Ok(());
+        };
+        let mut updated = (**guard).clone();
+        let row = updated.docids.len();
+        updated.push_row(docid, source.dataset.row(index))?;
+        Arc::make_mut(&mut updated.attributes).copy_row_from(row, &source.attributes, index)?;
+        if source.tombstones.contains(&docid) {
+            updated.tombstones.insert(docid);
+        }
+        updated.set_quarantined(docid, source.quarantined.contains(&docid));
+        Arc::make_mut(&mut base_guard).tombstones.insert(docid);
+        hidden.insert(docid);
+        *guard = Arc::new(updated);
+        self.mutated(&mut guard);
+        Ok(())
+    }
+
+    // Administrative merge of two partitions without retraining. Row
+    // membership is unchanged, so exhaustive searches return the same results.
+    pub fn merge_leaves(&self, a: usize, b: usize) -> Result<tree::TokenRemapping, Box<dyn Error>> {
+        self.check_not_fork("merge_leaves")?;
+        let mut guard = self.snapshot.write().unwrap();
+        let mut tree = guard
+            .tree
+            .as_deref()
+            .cloned()
+            .ok_or_else(|| util::failed_precondition_error("Retriever has no partitioning"))?;
+        let remapping = tree.merge_leaves(a, b)?;
+        Arc::make_mut(&mut guard).tree = Some(Arc::new(tree));
+        self.mutated(&mut guard);
+        Ok(remapping)
+    }
+
+    pub fn split_leaf(
+        &self,
+        leaf: usize,
+        k: usize,
+        options: &tree::KMeansTreeTrainingOptions,
+    ) -> Result<tree::TokenRemapping, Box<dyn Error>> {
+        self.check_not_fork("split_leaf")?;
+        let mut guard = self.snapshot.write().unwrap();
+        let mut tree = guard
+            .tree
+            .as_deref()
+            .cloned()
+            .ok_or_else(|| util::failed_precondition_error("Retriever has no partitioning"))?;
+        let remapping = tree.split_leaf(leaf, k, &guard.dataset, options)?;
+        Arc::make_mut(&mut guard).tree = Some(Arc::new(tree));
+        self.mutated(&mut guard);
+        Ok(remapping)
+    }
+
+    // Results are (docid, distance) pairs. Docids are assigned from the row
+    // index at construction and stay stable across compaction.
+    pub fn search(&self, query: &util::DatapointPtr<f32>) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        self.search_with_options(query, &SearchOptions::default())
+            .map(|(results, _)| results)
+    }
+
+    // f64 queries are narrowed to the f32 index type per the convert module's
+    // promotion policy; non-finite or out-of-range values are rejected.
+    pub fn search_f64(&self, query: &util::DatapointPtr<f64>) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        let (query, _) = convert::f64_to_f32_datapoint(query)?;
+        self.search(&query)
+    }
+
+    // Validates `query` once so it can be searched repeatedly, possibly from
+    // other threads, without redoing per-query work.
+    pub fn prepare(&self, query: &util::DatapointPtr<f32>) -> Result<PreparedQuery, Box<dyn Error>> {
+        let mut prepared = self.prepare_once(query.clone())?;
+        prepared.retain = true;
+        Ok(prepared)
+    }
+
+    fn prepare_once(&self, query: util::DatapointPtr<f32>) -> Result<PreparedQuery, Box<dyn Error>> {
+        self.validate_query(query.values(), self.current_snapshot().dataset.dimensionality())?;
+        Ok(PreparedQuery {
+            query,
+            retriever_id: self.id,
+            retain: false,
+            leaf_order: OnceLock::new(),
+        })
+    }
+
+    // Checks shared by every search entry point. Allocates only on error.
+    fn validate_query(&self, query: &[f32], dimensionality: usize) -> Result<(), Box<dyn Error>> {
+        if query.is_empty() {
+            return Err(util::invalid_argument_error("Empty Query"));
+        }
+        if query.len() != dimensionality {
+            return Err(util::invalid_argument_error(&format!(
+                "Query has dimensionality {}, expected {}",
+                query.len(),
+                dimensionality
+            )));
+        }
+        self.distance_measure.check_dimensionality(dimensionality)?;
+        if let Some(dim) = query.iter().position(|v| !v.is_finite()) {
+            return Err(util::invalid_argument_error(&format!(
+                "Query has non-finite value {} at dimension {}",
+                query[dim], dim
+            )));
+        }
+        if self.zero_sensitive() && util::is_zero_vector(query) {
+            return Err(util::invalid_argument_error(&format!(
+                "Query is a zero vector, which has no direction under {}",
+                self.distance_measure.name()
+            )));
+        }
+        Ok(())
+    }
+
+    // f32 distance for one row through the fastest available kernel: the
+    // fixed-dimension one, then match-based dispatch, then the boxed measure.
+    fn score_f32(&self, query: &[f32], row: &[f32]) -> f32 {
+        match (self.low_dim_kernel, self.measure_kind) {
+            (Some(kernel), _) => kernel(query, row),
+            (None, Some(kind)) => kind.compute(query, row),
+            (None, None) => self.distance_measure.compute_distance_f32(query, row),
+        }
+    }
+
+    pub fn search_with_options(
+        &self,
+        query: &util::DatapointPtr<f32>,
+        options: &SearchOptions,
+    ) -> Result<(Neighbors, SearchStats), Box<dyn Error>> {
+        self.validate_query(query.values(), self.current_snapshot().dataset.dimensionality())?;
+        let (results, mut stats) = self.search_query(QueryRef::one_shot(query.values()), options)?;
+        stats.query_preparations += 1;
+        Ok((results, stats))
+    }
+
+    // One page of options.k (or the default k) results. Pass None for the
+    // first page and the returned cursor for each later one; no cursor is
+    // returned once a page comes back short. Each page re-runs the search
+    // for offset + k results, so deep pages cost more. Fails when the
+    // retriever was mutated after the cursor was issued or the cursor
+    // belongs to another query.
+    pub fn search_page(
+        &self,
+        query: &util::DatapointPtr<f32>,
+        options: &SearchOptions,
+        cursor: Option<&SearchCursor>,
+    ) -> Result<(Neighbors, Option<SearchCursor>), Box<dyn Error>> {
+        let generation = self.cache_generation.load(Ordering::Acquire);
+        let fingerprint = query_fingerprint(query.values());
+        let offset = match cursor {
+            None => 0,
+            Some(cursor) => {
+                if cursor.generation != generation {
+                    return Err(util::failed_precondition_error(&format!(
+                        "Stale SearchCursor: issued at index generation {}, index is now at generation {}",
+                        cursor.generation, generation
+                    )));
+                }
+                if cursor.query_fingerprint != fingerprint {
+                    return Err(util::invalid_argument_error("SearchCursor was issued for a different query"));
+                }
+                if cursor.offset > self.stored_rows() {
+                    return Err(util::invalid_argument_error(&format!(
+                        "SearchCursor offset {} is past the {} stored rows",
+                        cursor.offset,
+                        self.stored_rows()
+                    )));
+                }
+                cursor.offset
+            }
+        };
+        let page_size = options.k.unwrap_or(self.k);
+        let Some(total) = offset.checked_add(page_size) else {
+            return Err(util::invalid_argument_error(&format!(
+                "SearchCursor offset {} plus page size {} overflows",
+                offset, page_size
+            )));
+        };
+        let page_options = SearchOptions {
+            k: Some(total),
+            ..options.clone()
+        };
+        let (mut results, _) = self.search_with_options(query, &page_options)?;
+        let next = (page_size > 0 && results.len() == total).then_some(SearchCursor {
+            generation,
+            offset: total,
+            query_fingerprint: fingerprint,
+        });
+        let page = results.split_off(offset.min(results.len()));
+        Ok((page, next))
+    }
+
+    pub fn search_prepared(
+        &self,
+        prepared: &PreparedQuery,
+        options: &SearchOptions,
+    ) -> Result<(Neighbors, SearchStats), Box<dyn Error>> {
+        if prepared.retriever_id != self.id {
+            return Err(util::invalid_argument_error(
+                "PreparedQuery was prepared by a different retriever",
+            ));
+        }
+        let query = QueryRef {
+            values: prepared.values(),
+            leaf_order: prepared.retain.then_some(&prepared.leaf_order),
+        };
+        self.search_query(query, options)
+    }
+
+    fn search_query(
+        &self,
+        query: QueryRef<'_>,
+        options: &SearchOptions,
+    ) -> Result<(Neighbors, SearchStats), Box<dyn Error>> {
+        match &self.fork {
+            Some(base) => self.search_fork(base, query, options),
+            None => self.search_own(query, options, true),
+        }
+    }
+
+    // Searches this retriever's own snapshot. With `observe` the query feeds
+    // the drift monitor and query logger; a fork observes its merged search
+    // instead.
+    fn search_own(
+        &self,
+        query: QueryRef<'_>,
+        options: &SearchOptions,
+        observe: bool,
+    ) -> Result<(Neighbors, SearchStats), Box<dyn Error>> {
+        let start = std::time::Instant::now();
+        if observe {
+            self.observe_drift(query.values());
+        }
+        let generation = self.cache_generation.load(Ordering::Acquire);
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let expired = self.expired_window(&snapshot.attributes, options.as_of);
+        let k = options.k.unwrap_or(self.k);
+        let cache = match options.score_modifier {
+            Some(_) => None,
+            None => self.result_cache.read().unwrap().clone(),
+        };
+        // Keyed by the expiry window rather than as_of itself, so searches
+        // between the same two expiries share entries.
+        let cache_key = cache.as_ref().map(|_| {
+            let mut params = cache_params(options, k, generation);
+            match &expired {
+                Some(window) => params.extend([1, window.start as u64]),
+                None => params.push(0),
+            }
+            query_cache::CacheKey::new(query.values(), params)
+        });
+        if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+            if let Some((results, stats)) = cache.get(key) {
+                if observe {
+                    self.log_query(query.values(), options, k, start, &results);
+                }
+                return Ok((results, stats));
+            }
+        }
+        let rescoring = if options.rescore_with_attached {
+            let Some(rescoring) = self.rescoring.read().unwrap().clone() else {
+                return Err(util::failed_precondition_error(
+                    "rescore_with_attached requires an attached rescoring dataset",
+                ));
+            };
+            if rescoring.dataset.size() != snapshot.dataset.size() {
+                return Err(util::failed_precondition_error(&format!(
+                    "Rescoring dataset has {} rows but the index now has {}; re-attach it",
+                    rescoring.dataset.size(),
+                    snapshot.dataset.size()
+                )));
+            }
+            let rescoring_query = options.rescoring_query.as_deref().unwrap_or(query.values());
+            if rescoring_query.len() != rescoring.dataset.dimensionality() {
+                return Err(util::invalid_argument_error(&format!(
+                    "Rescoring query has dimensionality {}, rescoring dataset has {}",
+                    rescoring_query.len(),
+                    rescoring.dataset.dimensionality()
+                )));
+            }
+            if let Some(w) = options.rescore_blend_weight {
+                if !(0.0..=1.0).contains(&w) {
+                    return Err(util::invalid_argument_error(&format!(
+                        "rescore_blend_weight must be in [0, 1], got {}",
+                        w
+                    )));
+                }
+            }
+            Some(rescoring)
+        } else {
+            None
+        };
+        let first_pass_k = match &rescoring {
+            Some(_) => options.rescore_candidates.unwrap_or(k).max(k),
+            None => k,
+        };
+        if let Some(spec) = &options.collect_histogram {
+            spec.validate()?;
+        }
+        if let Some(spec) = &options.facets {
+            if spec.pool_size < k {
+                return Err(util::invalid_argument_error(&format!(
+                    "Facet pool_size {} must be at least k = {}",
+                    spec.pool_size, k
+                )));
+            }
+            for name in &spec.columns {
+                match snapshot.attributes.column_type(name) {
+                    Some(attribute_store::ColumnType::Int64) => {}
+                    Some(other) => {
+                        return Err(util::invalid_argument_error(&format!(
+                            "Facet column '{}' is {:?}; facets count i64 columns",
+                            name, other
+                        )))
+                    }
+                    None => {
+                        return Err(util::invalid_argument_error(&format!("Unknown attribute column '{}'", name)))
+                    }
+                }
+            }
+        }
+        let allowed = match &options.filter {
+            Some(filter) => Some(snapshot.attributes.evaluate(filter)?),
+            None => None,
+        };
+        // Tombstoned, quarantined, expired and filtered-out rows.
+        let excluded = |i: usize| {
+            tombstones.contains(&snapshot.docids[i])
+                || expired.as_ref().is_some_and(|window| window.rows.contains(i))
+                || allowed.as_ref().is_some_and(|rows| !rows.contains(i))
+        };
+        let mut stats = SearchStats {
+            histogram: options.collect_histogram.map(DistanceHistogram::new),
+            ..SearchStats::default()
+        };
+        // Scratch drawn from the thread's arena and handed back when `buffers`
+        // drops, including on an early return; only the returned results are
+        // freshly allocated.
+        let mut buffers = ArenaScratch::take(&self.arena_high_water_bytes);
+        let ArenaScratch {
+            results,
+            scratch,
+            leaves,
+            row,
+            ..
+        } = &mut buffers;
+        let composite = match &options.part_weights {
+            Some(weights) => {
+                let Some(composite) = self.distance_measure.as_composite() else {
+                    return Err(util::invalid_argument_error(
+                        "part_weights requires the retriever to use a CompositeDistance",
+                    ));
+                };
+                if weights.len() != composite.num_parts() {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Got {} part weights for {} composite parts",
+                        weights.len(),
+                        composite.num_parts()
+                    )));
+                }
+                Some((composite, weights.as_slice()))
+            }
+            None => None,
+        };
+
+        // LimitedInnerProductDistance takes row norms from a registered
+        // NormCache instead of recomputing one per scored row.
+        let limited = self.distance_measure.as_limited_inner_product().and_then(|measure| {
+            snapshot
+                .derived
+                .iter()
+                .find_map(|d| d.as_any().downcast_ref::<NormCache>())
+                .filter(|cache| cache.norms.len() == snapshot.dataset.size())
+                .map(|cache| (measure, distance_measures::l2_norm(query.values()), cache.norms.as_slice()))
+        });
+
+        // Counts and keeps one distance for a live row.
+        let record = |i: usize, distance: f32, results: &mut Vec<(usize, f32)>, stats: &mut SearchStats| {
+            stats.datapoints_scored += 1;
+            let distance = if distance.is_finite() {
+                distance
+            } else if self.non_finite_handling == util::NonFiniteHandling::Clamp {
+                stats.non_finite_clamped += 1;
+                util::clamp_non_finite_distance(distance)
+            } else {
+                stats.non_finite_skipped += 1;
+                return;
+            };
+            if let Some(histogram) = stats.histogram.as_mut() {
+                histogram.record(distance);
+            }
+            results.push((snapshot.docids[i], distance));
+        };
+        let score_row = |i: usize, row: &[f32], results: &mut Vec<(usize, f32)>, stats: &mut SearchStats| {
+            if excluded(i) {
+                return;
+            }
+            let distance = match (composite, limited) {
+                (Some((composite, weights)), _) => {
+                    composite.weighted_sum(query.values(), row, weights.iter().copied(), options.accumulator_precision)
+                }
+                (None, Some((measure, query_norm, norms))) => {
+                    measure.distance_with_norms(query.values(), query_norm, row, norms[i])
+                }
+                (None, None) => match options.accumulator_precision {
+                    distance_measures::AccumulatorPrecision::F32 => self.score_f32(query.values(), row),
+                    precision => self.distance_measure.compute_distance_with_precision(query.values(), row, precision),
+                },
+            };
+            record(i, distance, results, stats);
+        };
+        let score = |i: usize, results: &mut Vec<(usize, f32)>, stats: &mut SearchStats| {
+            score_row(i, snapshot.dataset.row(i), results, stats)
+        };
+
+        let cancelled = || options.cancellation.as_ref().is_some_and(|token| token.is_cancelled());
+        let use_kd_tree = options.part_weights.is_none()
+            && options.score_modifier.is_none()
+            && options.collect_histogram.is_none()
+            && options.facets.is_none()
+            && options.epsilon_tie_threshold == 0.0
+            && options.accumulator_precision == distance_measures::AccumulatorPrecision::F32;
+        match (&snapshot.tree, options.leaves_to_search) {
+            (_, None) if use_kd_tree && snapshot.kd_tree.is_some() => {
+                let kd_tree = snapshot.kd_tree.as_ref().unwrap();
+                let keep = |i: usize| !excluded(i);
+                results.extend(
+                    kd_tree
+                        .search(&snapshot.dataset, query.values(), first_pass_k, keep)
+                        .into_iter()
+                        .map(|(i, d)| (snapshot.docids[i], d)),
+                );
+            }
+            (Some(tree), Some(leaves_to_search)) => {
+                let cached = query.leaf_order.and_then(|order| order.get()).filter(|(g, _)| *g == generation);
+                let order = match cached {
+                    Some((_, order)) => order.as_slice(),
+                    None => {
+                        stats.leaf_orderings += 1;
+                        tree.tokens_for_query_into(query.values(), tree.num_leaves(), scratch, leaves);
+                        if let Some(order) = query.leaf_order {
+                            let _ = order.set((generation, leaves.clone()));
+                        }
+                        leaves.as_slice()
+                    }
+                };
+                let selected = &order[..leaves_to_search.min(order.len())];
+                // Under the dot-product measures a row's distance is
+                // offset - <q, x>, so the top of a leaf's dot range bounds
+                // the best distance it holds. Options that need more than
+                // the best first_pass_k distances disable pruning.
+                let dot_offset = match self.distance_measure.name() {
+                    "DotProductDistance" => Some(0.0),
+                    "NormalizedDotProductDistance" => Some(1.0),
+                    _ => None,
+                }
+                .filter(|_| {
+                    !options.disable_leaf_pruning
+                        && tree.has_leaf_bounds()
+                        && composite.is_none()
+                        && options.score_modifier.is_none()
+                        && options.collect_histogram.is_none()
+                        && options.facets.is_none()
+                        && options.epsilon_tie_threshold == 0.0
+                });
+                let leaf_code_store = snapshot.leaf_code_store.clone();
+                let loaded = match &leaf_code_store {
+                    Some(store) => store.prefetch(selected)?,
+                    None => Vec::new(),
+                };
+                // Spilled rows live in several leaves; each is scored at most
+                // once per query.
+                VISITED.with(|visited| {
+                    let mut visited = visited.borrow_mut();
+                    visited.begin(snapshot.dataset.size());
+                    match &leaf_code_store {
+                        Some(store) => {
+                            let dim = store.dimensionality();
+                            if let Some(histogram) = stats.histogram.as_mut() {
+                                histogram.approximate = true;
+                            }
+                            for (codes, load) in &loaded {
+                                if cancelled() {
+                                    stats.truncated = true;
+                                    break;
+                                }
+                                stats.leaves_searched += 1;
+                                if load.cache_hit {
+                                    stats.leaf_cache_hits += 1;
+                                } else {
+                                    stats.leaf_cache_misses += 1;
+                                }
+                                stats.leaf_load_micros += load.load_micros;
+                                visited.visit_new(&codes.rows, |j, i| {
+                                    leaf_codes::dequantize_code(codes.code(j, dim), store.multipliers(), row);
+                                    score_row(i, row, results, &mut stats);
+                                });
+                            }
+                        }
+                        None => {
+                            // Worst of the best first_pass_k distances so far;
+                            // only the distances are needed, so entries carry
+                            // no index.
+                            let mut best: BinaryHeap<distance_measures::TopKEntry<()>> = BinaryHeap::new();
+                            for &leaf in selected {
+                                if cancelled() {
+                                    stats.truncated = true;
+                                    break;
+                                }
+                                let kth = best.peek().filter(|_| best.len() == first_pass_k).map(|e| e.0 as f64);
+                                if let (Some(offset), Some(kth)) = (dot_offset, kth) {
+                                    let bound = tree.leaf_dot_range(leaf, query.values()).map(|(_, high)| offset - high);
+                                    if bound.is_some_and(|bound| bound > kth) {
+                                        stats.leaves_pruned += 1;
+                                        continue;
+                                    }
+                                }
+                                stats.leaves_searched += 1;
+                                let scanned_from = results.len();
+                                visited.visit_new(tree.leaf(leaf), |_, i| score(i, results, &mut stats));
+                                if dot_offset.is_some() {
+                                    for &(_, distance) in &results[scanned_from..] {
+                                        best.push(distance_measures::TopKEntry(distance, ()));
+                                        if best.len() > first_pass_k {
+                                            best.pop();
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+            _ => {
+                // Plain distances are computed a block at a time through the
+                // measure's one-to-many kernel, reusing `row` for the block.
+                let batched = composite.is_none()
+                    && limited.is_none()
+                    && options.accumulator_precision == distance_measures::AccumulatorPrecision::F32;
+                let n = snapshot.dataset.size();
+                // With k much smaller than N and nothing that needs every
+                // distance, the fused scan keeps only the best k. Near-tie
+                // ordering needs the rows just past the k-th, and the scan
+                // has no cancellation points, so both searches skip it.
+                let fused = batched
+                    && first_pass_k.saturating_mul(FUSED_TOP_K_MIN_RATIO) <= n
+                    && options.cancellation.is_none()
+                    && options.score_modifier.is_none()
+                    && options.collect_histogram.is_none()
+                    && options.facets.is_none()
+                    && options.epsilon_tie_threshold == 0.0
+                    && self.non_finite_handling != util::NonFiniteHandling::Clamp;
+                if fused {
+                    let keep = |i: usize| !excluded(i);
+                    let docid = |i: usize| snapshot.docids[i];
+                    // The scan orders infinities and skips only NaN; this
+                    // retriever skips every non-finite distance, so the
+                    // infinities become NaN before the scan sees them.
+                    let skip_infinite = |out: &mut [f32]| {
+                        out.iter_mut().filter(|d| d.is_infinite()).for_each(|d| *d = f32::NAN);
+                    };
+                    let scan = match self.measure_kind {
+                        Some(kind) => distance_measures::top_k_one_to_many_segments(
+                            query.values(),
+                            snapshot.dataset.segments(),
+                            first_pass_k,
+                            |q, rows, out| {
+                                kind.compute_one_to_many_rows(q, rows, out);
+                                skip_infinite(out);
+                            },
+                            keep,
+                            docid,
+                        ),
+                        None if self.distance_measure.exits_early_on_threshold() => {
+                            let measure = self.distance_measure.as_ref();
+                            distance_measures::top_k_with_threshold_segments(
+                                query.values(),
+                                snapshot.dataset.segments(),
+                                first_pass_k,
+                                |q, row, threshold| {
+                                    let distance = match threshold {
+                                        Some(threshold) => measure.compute_distance_with_threshold(q, row, threshold)?,
+                                        None => measure.compute_distance_f32(q, row),
+                                    };
+                                    Some(if distance.is_infinite() { f32::NAN } else { distance })
+                                },
+                                keep,
+                                docid,
+                            )
+                        }
+                        None => distance_measures::top_k_one_to_many_segments(
+                            query.values(),
+                            snapshot.dataset.segments(),
+                            first_pass_k,
+                            |q, rows, out| {
+                                self.distance_measure.compute_one_to_many_rows(q, rows, out);
+                                skip_infinite(out);
+                            },
+                            keep,
+                            docid,
+                        ),
+                    };
+                    stats.datapoints_scored += scan.scored;
+                    stats.non_finite_skipped += scan.non_finite_skipped;
+                    stats.early_exits += scan.early_exits;
+                    results.extend(scan.neighbors.into_iter().map(|(i, d)| (snapshot.docids[i], d)));
+                }
+                let unfused_rows = if fused { 0 } else { n };
+                for start in (0..unfused_rows).step_by(CANCELLATION_CHECK_INTERVAL) {
+                    if cancelled() {
+                        stats.truncated = true;
+                        break;
+                    }
+                    let end = (start + CANCELLATION_CHECK_INTERVAL).min(n);
+                    if !batched {
+                        for i in start..end {
+                            score(i, results, &mut stats);
+                        }
+                        continue;
+                    }
+                    row.clear();
+                    row.resize(end - start, 0.0);
+                    let block = snapshot.dataset.block(start, end);
+                    match self.measure_kind {
+                        Some(kind) => kind.compute_one_to_many_rows(query.values(), block, row),
+                        None => self.distance_measure.compute_one_to_many_rows(query.values(), block, row),
+                    }
+                    for (i, &distance) in (start..end).zip(row.iter()) {
+                        if !excluded(i) {
+                            record(i, distance, results, &mut stats);
+                        }
+                    }
+                }
+            }
+        }
+
+        results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        if let Some(rescoring) = &rescoring {
+            let rescoring_query = options.rescoring_query.as_deref().unwrap_or(query.values());
+            results.truncate(first_pass_k);
+            // Candidates not reached before cancellation keep no distance
+            // from the other representation, so they are dropped.
+            let mut rescored = results.len();
+            for (c, (docid, distance)) in results.iter_mut().enumerate() {
+                if cancelled() {
+                    stats.truncated = true;
+                    rescored = c;
+                    break;
+                }
+                let row = &rescoring.dataset.data[snapshot.docid_to_index[docid]];
+                let attached = rescoring.measure.compute_distance_with_precision(
+                    rescoring_query,
+                    row,
+                    options.rescoring_precision,
+                );
+                *distance = match options.rescore_blend_weight {
+                    Some(w) => (1.0 - w) * *distance + w * attached,
+                    None => attached,
+                };
+            }
+            results.truncate(rescored);
+            results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        }
+        if let Some(modifier) = &options.score_modifier {
+            let facet_pool = options.facets.as_ref().map_or(0, |spec| spec.pool_size);
+            let pool = options.rescore_candidates.unwrap_or(k).max(k).max(facet_pool);
+            scratch.clear();
+            for &(docid, distance) in results.iter() {
+                if scratch.len() == pool {
+                    break;
+                }
+                let modified = modifier.modify(snapshot.docid_to_index[&docid], docid, distance);
+                if modified != f32::INFINITY {
+                    scratch.push((docid, modified));
+                }
+            }
+            scratch.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            std::mem::swap(results, scratch);
+        }
+        if stats.truncated && options.error_on_cancel {
+            return Err(Box::new(ScannError {
+                message: "Search cancelled before completion".to_string(),
+            }));
+        }
+        if options.epsilon_tie_threshold > 0.0 {
+            order_near_ties(results, options.epsilon_tie_threshold);
+        }
+        if let Some(spec) = &options.facets {
+            let pool = &results[..spec.pool_size.min(results.len())];
+            stats.facets = spec
+                .columns
+                .iter()
+                .map(|name| {
+                    let mut facet = FacetCounts {
+                        column: name.clone(),
+                        pool_size: pool.len(),
+                        ..FacetCounts::default()
+                    };
+                    for (docid, _) in pool {
+                        match snapshot.attributes.get_i64(name, snapshot.docid_to_index[docid]) {
+                            Some(value) => *facet.counts.entry(value).or_insert(0) += 1,
+                            None => facet.missing += 1,
+                        }
+                    }
+                    facet
+                })
+                .collect();
+        }
+        // The best k move into the returned Vec, sized for them; the
+        // candidate buffer goes back to the arena with its capacity.
+        let results = results[..k.min(results.len())].to_vec();
+        drop(buffers);
+        if options.return_calibrated_scores {
+            let calibrator = self.calibrator.read().unwrap();
+            let Some(calibrator) = calibrator.as_ref() else {
+                return Err(util::failed_precondition_error(
+                    "Calibrated scores requested but no calibrator is set",
+                ));
+            };
+            stats.calibrated_scores = Some(results.iter().map(|&(_, d)| calibrator.apply(d)).collect());
+        }
+        if let (Some(cache), Some(key), false) = (cache, cache_key, stats.truncated) {
+            cache.insert(key, (results.clone(), stats.clone()));
+        }
+        if observe {
+            self.log_query(query.values(), options, k, start, &results);
+        }
+        Ok((results, stats))
+    }
+
+    // Searches the base and the fork's own rows with the same options and
+    // merges the two rankings by distance, ties by docid. Calibration,
+    // logging and drift observation apply once, to the merged results.
+    // Rescoring, histograms and facets need a single snapshot and are
+    // rejected.
+    fn search_fork(
+        &self,
+        base: &ForkBase,
+        query: QueryRef<'_>,
+        options: &SearchOptions,
+    ) -> Result<(Neighbors, SearchStats), Box<dyn Error>> {
+        if options.rescore_with_attached || options.collect_histogram.is_some() || options.facets.is_some() {
+            return Err(util::failed_precondition_error(
+                "Rescoring, histograms and facets are not supported on a fork; materialize it first",
+            ));
+        }
+        let start = std::time::Instant::now();
+        self.observe_drift(query.values());
+        let k = options.k.unwrap_or(self.k);
+        let calibrator = match options.return_calibrated_scores {
+            true => match *self.calibrator.read().unwrap() {
+                Some(calibrator) => Some(calibrator),
+                None => {
+                    return Err(util::failed_precondition_error(
+                        "Calibrated scores requested but no calibrator is set",
+                    ))
+                }
+            },
+            false => None,
+        };
+        let part_options = SearchOptions {
+            k: Some(k),
+            return_calibrated_scores: false,
+            ..options.clone()
+        };
+        let base_snapshot = base.view.current_snapshot();
+        base.view.validate_query(query.values(), base_snapshot.dataset.dimensionality())?;
+        let base_query = QueryRef::one_shot(query.values());
+        let (mut results, mut stats) = base.view.search_own(base_query, &part_options, false)?;
+        let (own_results, own_stats) = self.search_own(query, &part_options, false)?;
+        results.extend(own_results);
+        results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        if options.epsilon_tie_threshold > 0.0 {
+            order_near_ties(&mut results, options.epsilon_tie_threshold);
+        }
+        results.truncate(k);
+        stats.leaves_searched += own_stats.leaves_searched;
+        stats.leaves_pruned += own_stats.leaves_pruned;
+        stats.datapoints_scored += own_stats.datapoints_scored;
+        stats.non_finite_skipped += own_stats.non_finite_skipped;
+        stats.non_finite_clamped += own_stats.non_finite_clamped;
+        stats.early_exits += own_stats.early_exits;
+        stats.leaf_cache_hits += own_stats.leaf_cache_hits;
+        stats.leaf_cache_misses += own_stats.leaf_cache_misses;
+        stats.leaf_load_micros += own_stats.leaf_load_micros;
+        stats.truncated |= own_stats.truncated;
+        stats.leaf_orderings += own_stats.leaf_orderings;
+        if let Some(calibrator) = calibrator {
+            stats.calibrated_scores = Some(results.iter().map(|&(_, d)| calibrator.apply(d)).collect());
+        }
+        self.log_query(query.values(), options, k, start, &results);
+        Ok((results, stats))
+    }
+
+    // Returns the final results together with the expanded query so callers
+    // can log what was actually searched. The centroid is taken over the
+    // stored vectors of the top `m` results of the previous round.
+    pub fn search_with_feedback(
+        &self,
+        query: &util::DatapointPtr<f32>,
+        k: usize,
+        feedback: &FeedbackOptions,
+    ) -> Result<(Neighbors, Vec<f32>), Box<dyn Error>> {
+        self.check_not_fork("search_with_feedback")?;
+        if !(0.0..=1.0).contains(&feedback.alpha) {
+            return Err(util::invalid_argument_error(&format!(
+                "Feedback alpha must be in [0, 1], got {}",
+                feedback.alpha
+            )));
+        }
+        let options = SearchOptions {
+            k: Some(k.max(feedback.m)),
+            ..Default::default()
+        };
+        let original = query.values().to_vec();
+        let mut expanded = original.clone();
+        let (mut results, _) = self.search_with_options(query, &options)?;
+        for _ in 0..feedback.iterations {
+            if feedback.m == 0 || results.is_empty() {
+                break;
+            }
+            let snapshot = self.current_snapshot();
+            let mut centroid = vec![0.0f32; original.len()];
+            let mut count = 0;
+            for &(docid, _) in results.iter().take(feedback.m) {
+                // A docid compacted away since the previous round is skipped.
+                let Some(&index) = snapshot.docid_to_index.get(&docid) else {
+                    continue;
+                };
+                for (c, &v) in centroid.iter_mut().zip(snapshot.dataset.row(index).iter()) {
+                    *c += v;
+                }
+                count += 1;
+            }
+            if count == 0 {
+                break;
+            }
+            expanded = original
+                .iter()
+                .zip(centroid.iter())
+                .map(|(&q, &c)| feedback.alpha * q + (1.0 - feedback.alpha) * c / count as f32)
+                .collect();
+            if self.normalization == util::Normalization::UnitL2 {
+                expanded = util::apply_normalization(expanded, self.normalization)?;
+            }
+            results = self.search_with_options(&util::DatapointPtr::new(expanded.clone()), &options)?.0;
+        }
+        results.truncate(k);
+        Ok((results, expanded))
+    }
+
+    // Cross-checks the retriever's components against each other: row
+    // shapes, the docid list against its reverse map (which must be a
+    // bijection), derived data lengths, tree leaf ranges and coverage,
+    // tombstone and quarantine membership, and the sizes of attached leaf
+    // codes and rescoring data. Every violation names the component and the
+    // offending index. Only reads; safe to call on a serving index.
+    pub fn verify_integrity(&self) -> Result<IntegrityReport, Box<dyn Error>> {
+        self.check_not_fork("verify_integrity")?;
+        let snapshot = self.current_snapshot();
+        let n = snapshot.dataset.size();
+        let dim = snapshot.dataset.dimensionality();
+        let mut report = IntegrityReport {
+            num_rows: n,
+            ..IntegrityReport::default()
+        };
+
+        for (i, row) in snapshot.dataset.rows().enumerate() {
+            report.check("dataset", row.len() == dim, || {
+                format!("row {} has {} values, dataset dimensionality is {}", i, row.len(), dim)
+            });
+        }
+
+        report.check("docids", snapshot.docids.len() == n, || {
+            format!("{} docids for {} rows", snapshot.docids.len(), n)
+        });
+        report.check("docid_map", snapshot.docid_to_index.len() == snapshot.docids.len(), || {
+            format!(
+                "reverse map has {} entries for {} docids (duplicate or missing docids)",
+                snapshot.docid_to_index.len(),
+                snapshot.docids.len()
+            )
+        });
+        for (i, &docid) in snapshot.docids.iter().enumerate() {
+            let mapped = snapshot.docid_to_index.get(&docid).copied();
+            report.check("docid_map", mapped == Some(i), || {
+                format!("docid {} at row {} maps back to {:?}", docid, i, mapped)
+            });
+        }
+        for (&docid, &index) in snapshot.docid_to_index.iter() {
+            report.check("docid_map", index < snapshot.docids.len(), || {
+                format!("docid {} maps to row {}, past {} rows", docid, index, snapshot.docids.len())
+            });
+        }
+        let max_docid = snapshot.docids.iter().max().copied();
+        report.check("docids", max_docid.is_none_or(|max| snapshot.next_docid > max), || {
+            format!("next docid {} does not exceed largest docid {:?}", snapshot.next_docid, max_docid)
+        });
+
+        if let Some(norms) = snapshot.derived.iter().find_map(|d| d.as_any().downcast_ref::<NormCache>()) {
+            report.check("norm_cache", norms.norms.len() == n, || {
+                format!("norm cache has {} entries for {} rows", norms.norms.len(), n)
+            });
+        }
+
+        if let Some(tree) = &snapshot.tree {
+            let centers = tree.centers();
+            report.check("tree", centers.size() == tree.num_leaves(), || {
+                format!("{} centers for {} leaves", centers.size(), tree.num_leaves())
+            });
+            report.check("tree", centers.dimensionality() == dim, || {
+                format!("centers have dimensionality {}, dataset has {}", centers.dimensionality(), dim)
+            });
+            let mut covered = vec![false; n];
+            for leaf in 0..tree.num_leaves() {
+                let mut seen = HashSet::new();
+                for (position, index) in tree.leaf(leaf).iter().enumerate() {
+                    report.check("tree", index < n, || {
+                        format!("leaf {} position {} references row {}, past {} rows", leaf, position, index, n)
+                    });
+                    report.check("tree", seen.insert(index), || {
+                        format!("leaf {} lists row {} more than once", leaf, index)
+                    });
+                    if index < n {
+                        covered[index] = true;
+                    }
+                }
+            }
+            let uncovered = covered.iter().filter(|&&c| !c).count();
+            report.check("tree", uncovered == 0, || {
+                format!(
+                    "{} rows belong to no leaf, first is row {}",
+                    uncovered,
+                    covered.iter().position(|&c| !c).unwrap_or(0)
+                )
+            });
+        }
+
+        for (component, set) in [("tombstones", &snapshot.tombstones), ("quarantine", &snapshot.quarantined)] {
+            for &docid in set.iter() {
+                report.check(component, snapshot.docid_to_index.contains_key(&docid), || {
+                    format!("docid {} is not stored", docid)
+                });
+            }
+        }
+
+        if let Some(store) = snapshot.leaf_code_store.as_ref() {
+            report.check("leaf_codes", store.num_rows() == n, || {
+                format!("leaf codes were built from {} rows, dataset has {}", store.num_rows(), n)
+            });
+            report.check("leaf_codes", store.dimensionality() == dim, || {
+                format!("leaf codes have dimensionality {}, dataset has {}", store.dimensionality(), dim)
+            });
+            let leaves = snapshot.tree.as_ref().map_or(0, |tree| tree.num_leaves());
+            report.check("leaf_codes", store.num_leaves() == leaves, || {
+                format!("leaf codes cover {} leaves, tree has {}", store.num_leaves(), leaves)
+            });
+        }
+
+        if let Some(rescoring) = self.rescoring.read().unwrap().as_ref() {
+            report.check("rescoring", rescoring.dataset.size() == n, || {
+                format!("rescoring dataset has {} rows for {} rows", rescoring.dataset.size(), n)
+            });
+        }
+
+        report.check("attributes", snapshot.attributes.num_rows() == n, || {
+            format!("attribute store has {} rows for {} rows", snapshot.attributes.num_rows(), n)
+        });
+        Ok(report)
+    }
+
+    #[doc(hidden)]
+    pub fn corrupt_for_testing(&self, corruption: IntegrityCorruption) -> Result<(), Box<dyn Error>> {
+        let mut guard = self.snapshot.write().unwrap();
+        let snapshot = Arc::make_mut(&mut guard);
+        match corruption {
+            IntegrityCorruption::DatasetDimensionality(dimensionality) => {
+                snapshot.dataset.dimensionality = dimensionality
+            }
+            IntegrityCorruption::DocidMapEntry { docid, row } => snapshot.docid_to_index.insert(docid, row),
+            IntegrityCorruption::NextDocid(next_docid) => snapshot.next_docid = next_docid,
+            IntegrityCorruption::Norms(norms) => {
+                let Some(position) = snapshot.derived.iter().position(|d| d.as_any().is::<NormCache>()) else {
+                    return Err(util::failed_precondition_error("No NormCache is registered"));
+                };
+                snapshot.derived[position] = Arc::new(NormCache { norms });
+            }
+            IntegrityCorruption::Tree(tree) => snapshot.tree = Some(Arc::new(tree)),
+            IntegrityCorruption::Tombstone(docid) => {
+                snapshot.tombstones.insert(docid);
+            }
+            IntegrityCorruption::Quarantine(docid) => {
+                snapshot.quarantined.insert(docid);
+            }
+            IntegrityCorruption::Attributes(attributes) => snapshot.attributes = Arc::new(attributes),
+        }
+        Ok(())
+    }
+
+    // Samples active datapoints (every other query perturbed with small
+    // noise), runs them through the configured search and an exact pass over
+    // the raw vectors, and reports recall, distance error and invariant
+    // violations. The same seed reproduces the same queries.
+    pub fn certify(
+        &self,
+        sample_size: usize,
+        k: usize,
+        seed: u64,
+        options: &SearchOptions,
+    ) -> Result<CertificationReport, Box<dyn Error>> {
+        self.check_not_fork("certify")?;
+        let snapshot = self.current_snapshot();
+        // A certification run is a batch job, so it works on its own copy
+        // of the hidden docids.
+        let mut tombstones = snapshot.tombstones.to_hash_set();
+        tombstones.extend(snapshot.quarantined.iter().copied());
+        // Every sampled search and the ground truth see the same expired
+        // rows, even as the wall clock moves.
+        let as_of = options.as_of.unwrap_or_else(unix_now_secs);
+        if let Some(window) = self.expired_window(&snapshot.attributes, Some(as_of)) {
+            tombstones.extend(window.rows.iter().map(|row| snapshot.docids[row]));
+        }
+        let active: Vec<usize> = (0..snapshot.docids.len())
+            .filter(|&i| !tombstones.contains(&snapshot.docids[i]))
+            .collect();
+        let mut report = CertificationReport {
+            seed,
             k,
+            min_recall: 1.0,
+            quarantined: self.num_quarantined(),
+            ..Default::default()
+        };
+        if active.is_empty() || sample_size == 0 || k == 0 {
+            return Ok(report);
+        }
+        // Ground truth comes from the f64 reference searcher whenever the
+        // measure has a reference counterpart.
+        let reference = match reference::ReferenceMetric::from_measure_name(self.distance_measure.name()) {
+            Some(metric) => Some(reference::BruteForceF64Searcher::new(
+                &snapshot.dataset.to_dense(),
+                snapshot.docids.to_vec(),
+                metric,
+            )?),
+            None => None,
+        };
+        let mut rng = util::SplitMix64::new(seed);
+        let mut options = options.clone();
+        options.k = Some(k);
+        options.as_of = Some(as_of);
+        // Ground truth ranks every active row, so results must too.
+        options.filter = None;
+        let mut recall_sum = 0.0f64;
+        for q in 0..sample_size {
+            let row = snapshot.dataset.row(active[rng.next_below(active.len())]);
+            let mut query = row.to_vec();
+            if q % 2 == 1 {
+                let scale = row.iter().map(|v| v.abs()).sum::<f32>() / row.len().max(1) as f32;
+                for v in query.iter_mut() {
+                    *v += (rng.next_f32() - 0.5) * 0.1 * scale.max(f32::MIN_POSITIVE);
+                }
+            }
+            let (results, _) = self.search_with_options(&util::DatapointPtr::new(query.clone()), &options)?;
+
+            let exact: Vec<(usize, f32)> = match &reference {
+                Some(reference) => reference
+                    .search(&query, k + tombstones.len())?
+                    .into_iter()
+                    .filter(|(docid, _)| !tombstones.contains(docid))
+                    .take(k)
+                    .map(|(docid, d)| (docid, d as f32))
+                    .collect(),
+                None => {
+                    let mut exact: Vec<(usize, f32)> = active
+                        .iter()
+                        .map(|&i| {
+                            let d = self.distance_measure.compute_distance_f32(&query, snapshot.dataset.row(i));
+                            (snapshot.docids[i], d)
+                        })
+                        .filter(|(_, d)| !d.is_nan())
+                        .collect();
+                    exact.sort_by(|a, b| a.1.total_cmp(&b.1));
+                    exact.truncate(k);
+                    exact
+                }
+            };
+
+            let mut seen = HashSet::new();
+            for (r, &(docid, distance)) in results.iter().enumerate() {
+                if !seen.insert(docid) {
+                    report.violations.push(format!("Query {}: docid {} returned twice", q, docid));
+                }
+                if tombstones.contains(&docid) {
+                    report.violations.push(format!("Query {}: removed or expired docid {} returned", q, docid));
+                }
+                if r > 0 && results[r - 1].1 > distance {
+                    report.violations.push(format!("Query {}: results unsorted at rank {}", q, r));
+                }
+                match snapshot.docid_to_index.get(&docid) {
+                    Some(&index) => {
+                        let true_distance = match &reference {
+                            Some(reference) => reference.distance_to(&query, index) as f32,
+                            None => self.distance_measure.compute_distance_f32(&query, snapshot.dataset.row(index)),
+                        };
+                        report.max_distance_error = report.max_distance_error.max((distance - true_distance).abs());
+                    }
+                    None => report.violations.push(format!("Query {}: unknown docid {} returned", q, docid)),
+                }
+            }
+            let found: HashSet<usize> = results.iter().map(|&(docid, _)| docid).collect();
+            let recall = if exact.is_empty() {
+                1.0
+            } else {
+                exact.iter().filter(|(docid, _)| found.contains(docid)).count() as f32 / exact.len() as f32
+            };
+            report.min_recall = report.min_recall.min(recall);
+            recall_sum += recall as f64;
+        }
+        report.num_queries = sample_size;
+        report.mean_recall = (recall_sum / sample_size as f64) as f32;
+        Ok(report)
+    }
+
+    // Allocation-free top-K for tiny K: the running best list lives in stack
+    // arrays and rows are scored with the same slice kernel as the general
+    // path. Queries are validated and rows excluded as in search_with_options
+    // with default options. Returns (docids, distances, number filled);
+    // unfilled slots hold -1 and +inf. Ties fall to the lower docid.
+    pub fn search_small_k<const K: usize>(
+        &self,
+        query: util::DatapointRef<'_, f32>,
+    ) -> Result<SmallKResults<K>, Box<dyn Error>> {
+        self.check_not_fork("search_small_k")?;
+        let snapshot = self.current_snapshot();
+        let hidden = snapshot.hidden();
+        self.validate_query(query.values(), snapshot.dataset.dimensionality())?;
+        // The window is cached between expiries, so this only allocates
+        // when an expiry has passed since the last search.
+        let expired = self.expired_window(&snapshot.attributes, None);
+        let excluded = |i: usize| {
+            let docid = &snapshot.docids[i];
+            hidden.contains(docid) || expired.as_ref().is_some_and(|window| window.rows.contains(i))
+        };
+
+        let mut ids = [-1i64; K];
+        let mut dists = [f32::INFINITY; K];
+        let mut filled = 0;
+        if K == 0 {
+            return Ok((ids, dists, filled));
+        }
+        for (i, row) in snapshot.dataset.rows().enumerate() {
+            if excluded(i) {
+                continue;
+            }
+            let docid = snapshot.docids[i] as i64;
+            let mut distance = self.score_f32(query.values(), row);
+            if !distance.is_finite() {
+                if self.non_finite_handling != util::NonFiniteHandling::Clamp {
+                    continue;
+                }
+                distance = util::clamp_non_finite_distance(distance);
+            }
+            // Ordered by (distance, docid) like the general path's results.
+            let precedes = |d: f32, id: i64| distance.total_cmp(&d).then(docid.cmp(&id)).is_lt();
+            if filled == K && !precedes(dists[K - 1], ids[K - 1]) {
+                continue;
+            }
+            let mut pos = if filled < K { filled } else { K - 1 };
+            while pos > 0 && precedes(dists[pos - 1], ids[pos - 1]) {
+                dists[pos] = dists[pos - 1];
+                ids[pos] = ids[pos - 1];
+                pos -= 1;
+            }
+            dists[pos] = distance;
+            ids[pos] = docid;
+            filled = (filled + 1).min(K);
+        }
+        Ok((ids, dists, filled))
+    }
+
+    pub fn register_derived_data(&self, mut derived: Box<dyn DerivedData>) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("register_derived_data")?;
+        let mut guard = self.snapshot.write().unwrap();
+        derived.rebuild(&guard.dataset.to_dense())?;
+        Arc::make_mut(&mut guard).derived.push(Arc::from(derived));
+        Ok(())
+    }
+
+    pub fn with_derived_data<R>(&self, name: &str, f: impl FnOnce(&dyn DerivedData) -> R) -> Option<R> {
+        let snapshot = self.current_snapshot();
+        let found = snapshot.derived.iter().find(|d| d.name() == name);
+        found.map(|d| f(d.as_ref()))
+    }
+
+    pub fn add(&self, values: &[f32]) -> Result<usize, Box<dyn Error>> {
+        self.add_with_expiry(values, None)
+    }
+
+    // Like `add`, recording an expiry in epoch seconds (see set_expiry).
+    pub fn add_with_expiry(&self, values: &[f32], expires_at: Option<i64>) -> Result<usize, Box<dyn Error>> {
+        let (values, quarantine) = self.prepare_row(values)?;
+        let mut guard = self.snapshot.write().unwrap();
+        let mut updated = (**guard).clone();
+        let docid = updated.next_docid;
+        updated.push_row(docid, &values)?;
+        if let Some(expires_at) = expires_at {
+            let row = updated.docids.len() - 1;
+            Arc::make_mut(&mut updated.attributes).set_value(
+                EXPIRY_COLUMN,
+                row,
+                Some(&attribute_store::Literal::Int(expires_at)),
+            )?;
+        }
+        updated.set_quarantined(docid, quarantine);
+        *guard = Arc::new(updated);
+        self.mutated(&mut guard);
+        Ok(docid)
+    }
+
+    // Replaces the vector stored under `docid`, or inserts it if the docid is
+    // unknown. All registered derived data is re-encoded for the row before
+    // the new snapshot becomes visible. An existing expiry is kept.
+    pub fn upsert(&self, docid: usize, values: &[f32]) -> Result<(), Box<dyn Error>> {
+        let (values, quarantine) = self.prepare_row(values)?;
+        self.shadow_base_row(docid)?;
+        let mut guard = self.snapshot.write().unwrap();
+        let mut updated = (**guard).clone();
+        match updated.docid_to_index.get(&docid).copied() {
+            Some(index) => updated.update_row(index, &values, true)?,
+            None => updated.push_row(docid, &values)?,
+        }
+        updated.tombstones.remove(&docid);
+        updated.set_quarantined(docid, quarantine);
+        *guard = Arc::new(updated);
+        self.mutated(&mut guard);
+        Ok(())
+    }
+
+    // Streams one leaf's active rows in the partition framing:
+    //   magic "SCNPART1" | leaf_id u64 | count u64 | dim u64
+    //   count x { docid u64 | dim x f32 }
+    // with all integers and floats little-endian.
+    pub fn export_partition<W: std::io::Write>(&self, leaf_id: usize, writer: &mut W) -> Result<usize, Box<dyn Error>> {
+        self.check_not_fork("export_partition")?;
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let Some(tree) = &snapshot.tree else {
+            return Err(util::failed_precondition_error("Retriever has no partitioning"));
+        };
+        if leaf_id >= tree.num_leaves() {
+            return Err(util::invalid_argument_error(&format!(
+                "Leaf {} out of range: retriever has {} leaves",
+                leaf_id,
+                tree.num_leaves()
+            )));
+        }
+        let rows: Vec<usize> = tree
+            .leaf(leaf_id)
+            .iter()
+            .filter(|&i| !tombstones.contains(&snapshot.docids[i]))
+            .collect();
+        let io_error = |e: std::io::Error| -> Box<dyn Error> {
+            Box::new(ScannError {
+                message: format!("Failed to export partition {}: {}", leaf_id, e),
+            })
+        };
+        writer.write_all(PARTITION_MAGIC).map_err(io_error)?;
+        for header in [leaf_id as u64, rows.len() as u64, snapshot.dataset.dimensionality() as u64] {
+            writer.write_all(&header.to_le_bytes()).map_err(io_error)?;
+        }
+        for &i in &rows {
+            writer.write_all(&(snapshot.docids[i] as u64).to_le_bytes()).map_err(io_error)?;
+            for v in snapshot.dataset.row(i) {
+                writer.write_all(&v.to_le_bytes()).map_err(io_error)?;
+            }
+        }
+        Ok(rows.len())
+    }
+
+    // Replaces the vectors of the given docids, all of which must belong to
+    // `leaf_id`. Leaf membership is kept as-is so other partitions are
+    // untouched, and derived data is re-encoded. Either every row is updated
+    // or none is.
+    pub fn import_reembedded_partition(
+        &self,
+        leaf_id: usize,
+        dataset: &util::DenseDataset<f32>,
+        docids: &[usize],
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_not_fork("import_reembedded_partition")?;
+        if dataset.size() != docids.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "Got {} vectors for {} docids",
+                dataset.size(),
+                docids.len()
+            )));
+        }
+        let mut guard = self.snapshot.write().unwrap();
+        let Some(tree) = &guard.tree else {
+            return Err(util::failed_precondition_error("Retriever has no partitioning"));
+        };
+        if leaf_id >= tree.num_leaves() {
+            return Err(util::invalid_argument_error(&format!(
+                "Leaf {} out of range: retriever has {} leaves",
+                leaf_id,
+                tree.num_leaves()
+            )));
+        }
+        let leaf_rows: HashSet<usize> = tree.leaf(leaf_id).iter().collect();
+        let mut rows = Vec::with_capacity(docids.len());
+        for &docid in docids {
+            match guard.docid_to_index.get(&docid) {
+                Some(&index) if leaf_rows.contains(&index) => rows.push(index),
+                _ => {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Docid {} is not stored in leaf {}",
+                        docid, leaf_id
+                    )))
+                }
+            }
+        }
+
+        let mut updated = (**guard).clone();
+        let mut quarantine = Vec::with_capacity(rows.len());
+        for (&index, values) in rows.iter().zip(dataset.data.iter()) {
+            let (values, zero) = self.prepare_row(values)?;
+            updated.update_row(index, &values, false)?;
+            quarantine.push(zero);
+        }
+        // Rows changed without moving leaves, so the bounds of every leaf
+        // holding one, spilled copies included, are recomputed.
+        if let Some(tree) = updated.tree.as_mut() {
+            let changed: HashSet<usize> = rows.iter().copied().collect();
+            let tree = Arc::make_mut(tree);
+            for leaf in 0..tree.num_leaves() {
+                if tree.leaf(leaf).iter().any(|i| changed.contains(&i)) {
+                    tree.recompute_leaf_bound(leaf, &updated.dataset);
+                }
+            }
+        }
+        for (&docid, zero) in docids.iter().zip(quarantine) {
+            updated.set_quarantined(docid, zero);
+        }
+        *guard = Arc::new(updated);
+        self.mutated(&mut guard);
+        Ok(())
+    }
+
+    pub fn remove(&self, docid: usize) -> Result<(), Box<dyn Error>> {
+        let mut guard = self.snapshot.write().unwrap();
+        if !guard.docid_to_index.contains_key(&docid) {
+            drop(guard);
+            if self.fork.as_ref().is_some_and(|base| base.hide(docid)) {
+                self.invalidate_result_cache();
+                return Ok(());
+            }
+            return Err(util::invalid_argument_error(&format!("Unknown docid: {}", docid)));
+        }
+        Arc::make_mut(&mut guard).tombstones.insert(docid);
+        drop(guard);
+        self.invalidate_result_cache();
+        Ok(())
+    }
+
+    // Approximate resident bytes of the searchable storage. A fork counts
+    // only its own rows and the base docids it hides, not the shared base.
+    // Rows still read in place from a mapped blob are shared page cache and
+    // are reported by mapped_memory_usage instead.
+    pub fn memory_usage(&self) -> usize {
+        let snapshot = self.current_snapshot();
+        let dim = snapshot.dataset.dimensionality();
+        let heap_rows = snapshot.dataset.size() - snapshot.dataset.shared_rows();
+        let mut bytes = estimate::dataset_bytes(heap_rows, dim);
+        bytes += estimate::docid_bytes(snapshot.docids.len());
+        bytes += snapshot.attributes.memory_bytes();
+        if let Some(tree) = &snapshot.tree {
+            let assignments = (0..tree.num_leaves()).map(|leaf| tree.leaf(leaf).len()).sum::<usize>();
+            bytes += estimate::tree_bytes(tree.num_leaves(), dim, assignments, tree.index_width());
         }
+        if let Some(base) = &self.fork {
+            bytes += estimate::docid_bytes(base.hidden.read().unwrap().len());
+        }
+        bytes
+    }
+
+    // Bytes of dataset rows borrowed from a mapped blob. Every retriever
+    // loaded from the same file maps the same pages; a write to a row
+    // copies its segment onto the heap, moving it to memory_usage.
+    pub fn mapped_memory_usage(&self) -> usize {
+        let snapshot = self.current_snapshot();
+        snapshot.dataset.shared_rows() * snapshot.dataset.dimensionality() * std::mem::size_of::<f32>()
+    }
+
+    // Rows in storage, tombstoned or not, including a fork's base.
+    fn stored_rows(&self) -> usize {
+        let own = self.current_snapshot().docids.len();
+        own + self.fork.as_ref().map_or(0, |base| base.view.stored_rows())
+    }
+
+    pub fn num_active(&self) -> usize {
+        let snapshot = self.current_snapshot();
+        let tombstones = snapshot.hidden();
+        let own = snapshot.docids.iter().filter(|docid| !tombstones.contains(docid)).count();
+        own + self.fork.as_ref().map_or(0, |base| base.view.num_active())
     }
 
-    pub fn search(&self, query: &utils::DatapointPtr<f32>) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
-        let query_vec = DVector::from_vec(query.values().to_vec());
-        let mut results = Vec::new();
-        for (i, data_point) in self.dataset.data.iter().enumerate() {
-            let data_vec = DVector::from_vec(data_point.clone());
-            let distance = self.distance_measure.compute_distance(
-                &utils::DatapointPtr::new(query_vec.as_slice().to_vec()),
-                &utils::DatapointPtr::new(data_vec.as_slice().to_vec()),
-            );
-            results.push((i, distance));
+    // Rebuilds the storage without tombstoned rows. The new snapshot is built
+    // off to the side and swapped in under a brief write lock; in-flight
+    // searches keep using the snapshot they started with.
+    pub fn compact(&self) -> Result<usize, Box<dyn Error>> {
+        self.check_not_fork("compact")?;
+        let old = self.current_snapshot();
+        let removed = &old.tombstones;
+        let mut data = Vec::with_capacity(old.dataset.size());
+        let mut docids = Vec::with_capacity(old.dataset.size());
+        let mut old_to_new = Vec::with_capacity(old.dataset.size());
+        for (row, &docid) in old.dataset.rows().zip(old.docids.iter()) {
+            if removed.contains(&docid) {
+                old_to_new.push(None);
+            } else {
+                old_to_new.push(Some(docids.len()));
+                data.push(row.to_vec());
+                docids.push(docid);
+            }
+        }
+        let num_removed = old.docids.len() - docids.len();
+        let dataset = util::DenseDataset::new(data, old.dataset.dimensionality());
+        let mut derived = Vec::with_capacity(old.derived.len());
+        for original in &old.derived {
+            let mut rebuilt = original.clone_box();
+            rebuilt.rebuild(&dataset)?;
+            derived.push(Arc::from(rebuilt));
+        }
+        let mut compacted = RetrieverSnapshot::new(dataset, docids);
+        compacted.derived = derived;
+        compacted.next_docid = compacted.next_docid.max(old.next_docid);
+        compacted.index_width = old.index_width;
+        compacted.index_overflow = old.index_overflow;
+        compacted.attributes = Arc::new(old.attributes.select_rows(&old_to_new));
+        compacted.pending_attributes = old.pending_attributes.clone();
+        // Compacted docids no longer exist, so the new snapshot starts
+        // without tombstones; quarantined rows that survive are kept.
+        compacted.quarantined = old.quarantined.iter().copied().filter(|docid| !removed.contains(docid)).collect();
+        if let Some(tree) = &old.tree {
+            let mut remapped = (**tree).clone();
+            remapped.remap(&old_to_new);
+            compacted.set_tree(remapped)?;
+        }
+
+        let mut snapshot = self.snapshot.write().unwrap();
+        if !Arc::ptr_eq(&snapshot, &old) {
+            return Err(Box::new(ScannError {
+                message: "Retriever storage changed during compaction".to_string(),
+            }));
         }
-        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-        Ok(results.into_iter().take(self.k).collect())
+        *snapshot = Arc::new(compacted);
+        self.mutated(&mut snapshot);
+        Ok(num_removed)
     }
 
     pub fn retrieve_chunks(
@@ -61,4 +4174,4 @@ impl ScannRetriever {
         // Actual implementation would use ScaNN's ANN search with trees/projection
         Ok(vec![vec![vec![0; chunk_size]; self.k]; input_seq.len() / chunk_size])
     }
-}
\ No newline at end of file
+}
@@ -17,7 +17,7 @@
 use nalgebra::{DMatrix, DVector};
 use std::error::Error;
 
-use super::utils;
+use crate::util;
 
 pub struct RotaryEmbedding {
     inv_freq: DVector<f32>,
@@ -33,7 +33,7 @@ impl RotaryEmbedding {
 
     pub fn forward(&self, max_seq_len: usize, offset: usize) -> DMatrix<f32> {
         let seq = DVector::from_fn(max_seq_len, |i, _| (i + offset) as f32);
-        let freqs = seq * &self.inv_freq;
+        let freqs = seq * self.inv_freq.transpose();
         let mut emb = DMatrix::zeros(max_seq_len, self.inv_freq.len() * 2);
         for i in 0..max_seq_len {
             for j in 0..self.inv_freq.len() {
@@ -60,10 +60,21 @@ impl RMSNorm {
     }
 
     pub fn forward(&self, x: &DMatrix<f32>) -> Result<DMatrix<f32>, Box<dyn Error>> {
-        let scale = (x.ncols() as f32).sqrt();
-        let norm = x.map(|v| v * v).row_sum().map(|v| (v / x.ncols() as f32).sqrt() * scale);
-        let norm_clamped = norm.map(|v| v.max(self.eps));
-        Ok(x.component_div(&norm_clamped) * &self.gamma)
+        if x.ncols() != self.gamma.len() {
+            return Err(util::invalid_argument_error(&format!(
+                "RMSNorm expects {} columns, got {}",
+                self.gamma.len(),
+                x.ncols()
+            )));
+        }
+        let mut out = x.clone();
+        for mut row in out.row_iter_mut() {
+            let rms = (row.iter().map(|v| v * v).sum::<f32>() / row.len() as f32).sqrt().max(self.eps);
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = *v / rms * self.gamma[j];
+            }
+        }
+        Ok(out)
     }
 }
 
@@ -81,17 +92,16 @@ pub struct Attention {
 impl Attention {
     pub fn new(dim: u32, context_dim: u32, heads: u32, dim_head: u32, causal: bool) -> Self {
         let inner_dim = heads * dim_head;
-        let normal = Normal::new(0.0, 1.0);
-        let mut rng = rand::thread_rng();
+        let mut rng = util::SplitMix64::from_entropy();
         Attention {
             heads,
             dim_head,
             scale: (dim_head as f32).powf(-0.5),
             causal,
-            to_q: DMatrix::from_fn(inner_dim as usize, dim as usize, |_, _| normal.sample(&mut rng) as f32),
-            to_k: DMatrix::from_fn(inner_dim as usize, context_dim as usize, |_, _| normal.sample(&mut rng) as f32),
-            to_v: DMatrix::from_fn(inner_dim as usize, context_dim as usize, |_, _| normal.sample(&mut rng) as f32),
-            to_out: DMatrix::from_fn(dim as usize, inner_dim as usize, |_, _| normal.sample(&mut rng) as f32),
+            to_q: util::random_normal_matrix(inner_dim as usize, dim as usize, &mut rng),
+            to_k: util::random_normal_matrix(inner_dim as usize, context_dim as usize, &mut rng),
+            to_v: util::random_normal_matrix(inner_dim as usize, context_dim as usize, &mut rng),
+            to_out: util::random_normal_matrix(dim as usize, inner_dim as usize, &mut rng),
         }
     }
 
@@ -99,29 +109,146 @@ impl Attention {
         &self,
         x: &DMatrix<f32>,
         context: Option<&DMatrix<f32>>,
-        pos_emb: Option<&DMatrix<f32>>,
+        _pos_emb: Option<&DMatrix<f32>>,
     ) -> Result<DMatrix<f32>, Box<dyn Error>> {
-        let kv_input = context.unwrap_or(x);
-        let inner_dim = self.heads * self.dim_head;
+        self.forward_masked(x, context, None)
+    }
 
-        let q = utils::matrix_multiply(x, &self.to_q.transpose())? * self.scale;
-        let k = utils::matrix_multiply(kv_input, &self.to_k.transpose())?;
-        let v = utils::matrix_multiply(kv_input, &self.to_v.transpose())?;
+    // Like `forward`; keys whose `key_mask` entry is false (padding) get no
+    // attention. A query with every key masked attends to nothing and
+    // outputs zeros.
+    pub fn forward_masked(
+        &self,
+        x: &DMatrix<f32>,
+        context: Option<&DMatrix<f32>>,
+        key_mask: Option<&[bool]>,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let (k, v) = self.project_kv(context.unwrap_or(x))?;
+        self.attend_masked(x, &k, &v, 0, key_mask)
+    }
 
-        let q = q.reshape((q.nrows(), self.heads as usize, self.dim_head as usize));
-        let k = k.reshape((k.nrows(), self.heads as usize, self.dim_head as usize));
-        let v = v.reshape((v.nrows(), self.heads as usize, self.dim_head as usize));
+    // Key and value projections of `kv_input`, one row per input row, so
+    // incremental decoding can cache them for positions already seen.
+    pub fn project_kv(&self, kv_input: &DMatrix<f32>) -> Result<(DMatrix<f32>, DMatrix<f32>), Box<dyn Error>> {
+        let k = util::matrix_multiply(kv_input, &self.to_k.transpose())?;
+        let v = util::matrix_multiply(kv_input, &self.to_v.transpose())?;
+        Ok((k, v))
+    }
 
-        let sim = utils::matrix_multiply(&q, &k.transpose())?;
+    // Attention of `x` over projected keys and values. Under the causal
+    // mask query row i sits at position `offset + i`, so queries for new
+    // positions can attend over keys cached from earlier ones.
+    pub fn attend(
+        &self,
+        x: &DMatrix<f32>,
+        k: &DMatrix<f32>,
+        v: &DMatrix<f32>,
+        offset: usize,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        self.attend_masked(x, k, v, offset, None)
+    }
 
+    pub fn attend_masked(
+        &self,
+        x: &DMatrix<f32>,
+        k: &DMatrix<f32>,
+        v: &DMatrix<f32>,
+        offset: usize,
+        key_mask: Option<&[bool]>,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let inner_dim = (self.heads * self.dim_head) as usize;
+        let dim_head = self.dim_head as usize;
+        if k.ncols() != inner_dim || v.ncols() != inner_dim || k.nrows() != v.nrows() {
+            return Err(util::invalid_argument_error(&format!(
+                "Attention expects keys and values of width {}, got {}x{} and {}x{}",
+                inner_dim,
+                k.nrows(),
+                k.ncols(),
+                v.nrows(),
+                v.ncols()
+            )));
+        }
+        check_key_mask(key_mask, k.nrows())?;
+        let q = util::matrix_multiply(x, &self.to_q.transpose())? * self.scale;
+
+        // Heads occupy consecutive column blocks of the projections.
+        let mut out = DMatrix::zeros(x.nrows(), inner_dim);
+        for h in 0..self.heads as usize {
+            let mut sim = q.columns(h * dim_head, dim_head) * k.columns(h * dim_head, dim_head).transpose();
+            self.mask_scores(&mut sim, offset, key_mask);
+            let attn = row_softmax(&sim);
+            out.columns_mut(h * dim_head, dim_head).copy_from(&(attn * v.columns(h * dim_head, dim_head)));
+        }
+        util::matrix_multiply(&out, &self.to_out.transpose())
+    }
+
+    pub fn num_heads(&self) -> usize {
+        self.heads as usize
+    }
+
+    // Per-head attention weights of shape (queries, keys), each row a proper
+    // softmax over the keys. Only computed when capture is requested; the
+    // value and output projections are skipped.
+    pub fn attention_weights(
+        &self,
+        x: &DMatrix<f32>,
+        context: Option<&DMatrix<f32>>,
+        key_mask: Option<&[bool]>,
+    ) -> Result<Vec<DMatrix<f32>>, Box<dyn Error>> {
+        let kv_input = context.unwrap_or(x);
+        check_key_mask(key_mask, kv_input.nrows())?;
+        let dim_head = self.dim_head as usize;
+        let q = util::matrix_multiply(x, &self.to_q.transpose())? * self.scale;
+        let k = util::matrix_multiply(kv_input, &self.to_k.transpose())?;
+        let mut weights = Vec::with_capacity(self.heads as usize);
+        for h in 0..self.heads as usize {
+            let mut sim = q.columns(h * dim_head, dim_head) * k.columns(h * dim_head, dim_head).transpose();
+            self.mask_scores(&mut sim, 0, key_mask);
+            weights.push(row_softmax(&sim));
+        }
+        Ok(weights)
+    }
+
+    // Sets the scores of keys hidden by the causal mask or by `key_mask` to
+    // -inf, so softmax gives them no weight.
+    fn mask_scores(&self, sim: &mut DMatrix<f32>, offset: usize, key_mask: Option<&[bool]>) {
         if self.causal {
-            let mask = DMatrix::from_fn(sim.nrows(), sim.ncols(), |i, j| if j > i { f32::NEG_INFINITY } else { 0.0 });
-            sim += &mask;
+            for i in 0..sim.nrows() {
+                for j in (i + offset + 1)..sim.ncols() {
+                    sim[(i, j)] = f32::NEG_INFINITY;
+                }
+            }
+        }
+        if let Some(key_mask) = key_mask {
+            for (j, _) in key_mask.iter().enumerate().filter(|(_, &keep)| !keep) {
+                sim.column_mut(j).fill(f32::NEG_INFINITY);
+            }
         }
+    }
+}
 
-        let attn = utils::softmax(&sim);
-        let out = utils::matrix_multiply(&attn, &v)?;
-        let out = out.reshape((out.nrows(), inner_dim as usize));
-        utils::matrix_multiply(&out, &self.to_out.transpose())
+fn check_key_mask(key_mask: Option<&[bool]>, num_keys: usize) -> Result<(), Box<dyn Error>> {
+    match key_mask {
+        Some(mask) if mask.len() != num_keys => Err(util::invalid_argument_error(&format!(
+            "Key mask has {} entries for {} keys",
+            mask.len(),
+            num_keys
+        ))),
+        _ => Ok(()),
     }
-}
\ No newline at end of file
+}
+
+fn row_softmax(x: &DMatrix<f32>) -> DMatrix<f32> {
+    let mut out = x.clone();
+    for mut row in out.row_iter_mut() {
+        let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        if max == f32::NEG_INFINITY {
+            row.fill(0.0);
+            continue;
+        }
+        row.apply(|v| *v = (*v - max).exp());
+        let sum = row.sum();
+        row /= sum;
+    }
+    out
+}
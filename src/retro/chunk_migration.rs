@@ -0,0 +1,134 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Re-chunking an existing chunk store to a new chunk size.
+//!
+//! Documents are rebuilt from the stored token stream, so nothing is
+//! re-tokenized. A new chunk that starts where an old chunk started and has
+//! the same length has the same tokens, and its embedding is reused; only
+//! the remaining chunks go through the embedder.
+
+use super::chunk_store::{ChunkStore, InMemoryChunkStore};
+use crate::proto::RetroConfig;
+use crate::util;
+use std::error::Error;
+
+pub struct ChunkSizeMigration {
+    pub store: InMemoryChunkStore,
+    // For each new chunk, the old chunk with identical tokens, if any.
+    pub reused_from: Vec<Option<usize>>,
+}
+
+impl ChunkSizeMigration {
+    pub fn num_reused(&self) -> usize {
+        self.reused_from.iter().filter(|r| r.is_some()).count()
+    }
+}
+
+// Returns `config` with the new chunk size, or explains why the model
+// cannot run with it. Sequences are split into whole chunks for chunked
+// cross-attention, so the size must divide max_seq_len; it must also divide
+// or be a multiple of the old size so the new grid lines up with the old one.
+pub fn migrate_config(config: &RetroConfig, new_chunk_size: usize) -> Result<RetroConfig, Box<dyn Error>> {
+    let old = config.chunk_size as usize;
+    if new_chunk_size == 0 || new_chunk_size > u32::MAX as usize {
+        return Err(util::invalid_argument_error(&format!(
+            "Chunk size {} is out of range",
+            new_chunk_size
+        )));
+    }
+    if !(config.max_seq_len as usize).is_multiple_of(new_chunk_size) {
+        return Err(util::invalid_argument_error(&format!(
+            "Chunk size {} does not divide max_seq_len {}; chunked cross-attention needs whole chunks",
+            new_chunk_size, config.max_seq_len
+        )));
+    }
+    if old != 0 && !new_chunk_size.is_multiple_of(old) && !old.is_multiple_of(new_chunk_size) {
+        return Err(util::invalid_argument_error(&format!(
+            "Chunk size {} is neither a divisor nor a multiple of the current size {}",
+            new_chunk_size, old
+        )));
+    }
+    let mut migrated = config.clone();
+    migrated.chunk_size = new_chunk_size as u32;
+    Ok(migrated)
+}
+
+pub fn migrate_chunk_size(store: &dyn ChunkStore, new_chunk_size: usize) -> Result<ChunkSizeMigration, Box<dyn Error>> {
+    let old_size = store.chunk_size();
+    // (document tokens, old chunk ids in order) per document.
+    let mut documents: Vec<(Vec<u32>, Vec<usize>)> = vec![(Vec::new(), Vec::new()); store.num_documents()];
+    for chunk_id in 0..store.num_chunks() {
+        let (tokens, chunks) = &mut documents[store.document_of(chunk_id)?];
+        tokens.extend(store.get(chunk_id)?.to_vec());
+        chunks.push(chunk_id);
+    }
+
+    let mut reused_from = Vec::new();
+    for (tokens, old_chunks) in &documents {
+        for (j, chunk) in tokens.chunks(new_chunk_size).enumerate() {
+            let offset = j * new_chunk_size;
+            let reused = if offset.is_multiple_of(old_size) {
+                let old_id = old_chunks[offset / old_size];
+                (store.get(old_id)?.len() == chunk.len()).then_some(old_id)
+            } else {
+                None
+            };
+            reused_from.push(reused);
+        }
+    }
+    let texts: Vec<Vec<u32>> = documents.into_iter().map(|(tokens, _)| tokens).collect();
+    Ok(ChunkSizeMigration {
+        store: InMemoryChunkStore::from_documents(&texts, new_chunk_size)?,
+        reused_from,
+    })
+}
+
+// Embeddings for the migrated chunks, copying rows of `old_embeddings`
+// (indexed by old chunk id) where possible and calling `embed` only for
+// chunks whose tokens changed. The result can be indexed directly.
+pub fn migrate_embeddings<F>(
+    migration: &ChunkSizeMigration,
+    old_embeddings: &util::DenseDataset<f32>,
+    mut embed: F,
+) -> Result<util::DenseDataset<f32>, Box<dyn Error>>
+where
+    F: FnMut(&[u32]) -> Result<Vec<f32>, Box<dyn Error>>,
+{
+    let dimensionality = old_embeddings.dimensionality();
+    let mut rows = Vec::with_capacity(migration.reused_from.len());
+    for (chunk_id, reused) in migration.reused_from.iter().enumerate() {
+        let row = match reused {
+            Some(old_id) if *old_id < old_embeddings.size() => old_embeddings.data[*old_id].clone(),
+            Some(old_id) => {
+                return Err(util::invalid_argument_error(&format!(
+                    "Old embeddings have {} rows; chunk {} is missing",
+                    old_embeddings.size(),
+                    old_id
+                )))
+            }
+            None => embed(&migration.store.get(chunk_id)?.to_vec())?,
+        };
+        if row.len() != dimensionality {
+            return Err(util::invalid_argument_error(&format!(
+                "Embedding for chunk {} has {} values, expected {}",
+                chunk_id,
+                row.len(),
+                dimensionality
+            )));
+        }
+        rows.push(row);
+    }
+    Ok(util::DenseDataset::new(rows, dimensionality))
+}
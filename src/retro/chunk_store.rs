@@ -0,0 +1,459 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tokenized retrieval corpora split into fixed-size chunks.
+//!
+//! Chunks never cross document boundaries; the last chunk of a document may
+//! be short. A chunk's continuation is the next chunk of the same document.
+//!
+//! Chunk store files are little-endian:
+//!   magic "SCNCHNK1" | version u32 | token width u8 | 3 reserved bytes
+//!   num_tokens u64 | num_chunks u64 | num_documents u64 | tables offset u64
+//!   chunk_size u64 | 8 reserved bytes (header is 64 bytes)
+//!   num_tokens x u16 or u32 tokens, zero-padded to a multiple of 8 bytes
+//!   (num_chunks + 1) x u64 chunk start token
+//!   (num_documents + 1) x u64 document start chunk
+
+use crate::{util, ScannError};
+use memmap2::Mmap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const CHUNK_STORE_MAGIC: &[u8; 8] = b"SCNCHNK1";
+pub(crate) const CHUNK_STORE_VERSION: u32 = 1;
+const HEADER_BYTES: usize = 64;
+
+// Files at or above this size are memory-mapped by `open_chunk_store`;
+// smaller ones are read into an InMemoryChunkStore.
+pub const DEFAULT_MMAP_THRESHOLD_BYTES: u64 = 256 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenWidth {
+    U16,
+    U32,
+}
+
+impl TokenWidth {
+    fn bytes(self) -> usize {
+        match self {
+            TokenWidth::U16 => 2,
+            TokenWidth::U32 => 4,
+        }
+    }
+
+    // Narrowest width holding every token below `vocab_size`.
+    pub fn for_vocab(vocab_size: u64) -> Self {
+        if vocab_size <= u16::MAX as u64 + 1 {
+            TokenWidth::U16
+        } else {
+            TokenWidth::U32
+        }
+    }
+}
+
+// Tokens of one chunk, borrowed from the store in its storage width.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkTokens<'a> {
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl<'a> ChunkTokens<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            ChunkTokens::U16(tokens) => tokens.len(),
+            ChunkTokens::U32(tokens) => tokens.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<u32> {
+        match self {
+            ChunkTokens::U16(tokens) => tokens.get(i).map(|&t| t as u32),
+            ChunkTokens::U32(tokens) => tokens.get(i).copied(),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u32> {
+        match self {
+            ChunkTokens::U16(tokens) => tokens.iter().map(|&t| t as u32).collect(),
+            ChunkTokens::U32(tokens) => tokens.to_vec(),
+        }
+    }
+}
+
+pub trait ChunkStore: Send + Sync {
+    fn chunk_size(&self) -> usize;
+    fn num_chunks(&self) -> usize;
+    fn num_documents(&self) -> usize;
+    fn get(&self, chunk_id: usize) -> Result<ChunkTokens<'_>, Box<dyn Error>>;
+    fn document_of(&self, chunk_id: usize) -> Result<usize, Box<dyn Error>>;
+
+    // The chunk following `chunk_id` in the same document, if any.
+    fn continuation(&self, chunk_id: usize) -> Result<Option<ChunkTokens<'_>>, Box<dyn Error>> {
+        let next = chunk_id + 1;
+        if next >= self.num_chunks() || self.document_of(next)? != self.document_of(chunk_id)? {
+            return Ok(None);
+        }
+        self.get(next).map(Some)
+    }
+}
+
+fn check_chunk(chunk_id: usize, num_chunks: usize) -> Result<(), Box<dyn Error>> {
+    if chunk_id >= num_chunks {
+        return Err(util::invalid_argument_error(&format!(
+            "Chunk {} out of range: store has {} chunks",
+            chunk_id, num_chunks
+        )));
+    }
+    Ok(())
+}
+
+// Index of the document whose chunk range contains `chunk_id`, given the
+// (num_documents + 1) document start chunks.
+fn document_for_chunk(document_starts: &[u64], chunk_id: usize) -> usize {
+    document_starts.partition_point(|&start| start <= chunk_id as u64) - 1
+}
+
+pub struct InMemoryChunkStore {
+    chunk_size: usize,
+    tokens: Vec<u32>,
+    chunk_starts: Vec<u64>,
+    document_starts: Vec<u64>,
+}
+
+impl InMemoryChunkStore {
+    pub fn from_documents(documents: &[Vec<u32>], chunk_size: usize) -> Result<Self, Box<dyn Error>> {
+        if chunk_size == 0 {
+            return Err(util::invalid_argument_error("Chunk size must be > 0"));
+        }
+        let mut store = InMemoryChunkStore {
+            chunk_size,
+            tokens: Vec::new(),
+            chunk_starts: vec![0],
+            document_starts: vec![0],
+        };
+        for document in documents {
+            for chunk in document.chunks(chunk_size) {
+                store.tokens.extend_from_slice(chunk);
+                store.chunk_starts.push(store.tokens.len() as u64);
+            }
+            store.document_starts.push((store.chunk_starts.len() - 1) as u64);
+        }
+        Ok(store)
+    }
+}
+
+impl ChunkStore for InMemoryChunkStore {
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn num_chunks(&self) -> usize {
+        self.chunk_starts.len() - 1
+    }
+
+    fn num_documents(&self) -> usize {
+        self.document_starts.len() - 1
+    }
+
+    fn get(&self, chunk_id: usize) -> Result<ChunkTokens<'_>, Box<dyn Error>> {
+        check_chunk(chunk_id, self.num_chunks())?;
+        let (start, end) = (self.chunk_starts[chunk_id] as usize, self.chunk_starts[chunk_id + 1] as usize);
+        Ok(ChunkTokens::U32(&self.tokens[start..end]))
+    }
+
+    fn document_of(&self, chunk_id: usize) -> Result<usize, Box<dyn Error>> {
+        check_chunk(chunk_id, self.num_chunks())?;
+        Ok(document_for_chunk(&self.document_starts, chunk_id))
+    }
+}
+
+fn io_error(path: &Path, action: &str, e: std::io::Error) -> Box<dyn Error> {
+    Box::new(ScannError {
+        message: format!("Failed to {} chunk store {}: {}", action, path.display(), e),
+    })
+}
+
+// Streams documents to a chunk store file. Tokens go straight to disk; only
+// the chunk and document tables are kept in memory until `finish`.
+pub struct ChunkStoreWriter {
+    file: BufWriter<File>,
+    path: std::path::PathBuf,
+    chunk_size: usize,
+    width: TokenWidth,
+    num_tokens: u64,
+    chunk_starts: Vec<u64>,
+    document_starts: Vec<u64>,
+}
+
+impl ChunkStoreWriter {
+    pub fn create<P: AsRef<Path>>(path: P, chunk_size: usize, width: TokenWidth) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        if chunk_size == 0 {
+            return Err(util::invalid_argument_error("Chunk size must be > 0"));
+        }
+        let file = File::create(path).map_err(|e| io_error(path, "create", e))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&[0u8; HEADER_BYTES]).map_err(|e| io_error(path, "write", e))?;
+        Ok(ChunkStoreWriter {
+            file,
+            path: path.to_path_buf(),
+            chunk_size,
+            width,
+            num_tokens: 0,
+            chunk_starts: vec![0],
+            document_starts: vec![0],
+        })
+    }
+
+    // Out-of-range tokens for a u16 store are rejected before anything from
+    // the document is written.
+    pub fn add_document(&mut self, tokens: &[u32]) -> Result<(), Box<dyn Error>> {
+        if self.width == TokenWidth::U16 {
+            if let Some(&token) = tokens.iter().find(|&&t| t > u16::MAX as u32) {
+                return Err(util::invalid_argument_error(&format!(
+                    "Token id {} does not fit a u16 chunk store",
+                    token
+                )));
+            }
+        }
+        let mut bytes = Vec::with_capacity(tokens.len() * self.width.bytes());
+        for &token in tokens {
+            match self.width {
+                TokenWidth::U16 => bytes.extend_from_slice(&(token as u16).to_le_bytes()),
+                TokenWidth::U32 => bytes.extend_from_slice(&token.to_le_bytes()),
+            }
+        }
+        self.file.write_all(&bytes).map_err(|e| io_error(&self.path, "write", e))?;
+        for chunk in tokens.chunks(self.chunk_size) {
+            self.num_tokens += chunk.len() as u64;
+            self.chunk_starts.push(self.num_tokens);
+        }
+        self.document_starts.push((self.chunk_starts.len() - 1) as u64);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        let token_bytes = self.num_tokens as usize * self.width.bytes();
+        let padding = (8 - token_bytes % 8) % 8;
+        let tables_offset = (HEADER_BYTES + token_bytes + padding) as u64;
+        let mut tail = vec![0u8; padding];
+        for &value in self.chunk_starts.iter().chain(self.document_starts.iter()) {
+            tail.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut header = Vec::with_capacity(HEADER_BYTES);
+        header.extend_from_slice(CHUNK_STORE_MAGIC);
+        header.extend_from_slice(&CHUNK_STORE_VERSION.to_le_bytes());
+        header.push(self.width.bytes() as u8);
+        header.extend_from_slice(&[0u8; 3]);
+        for value in [
+            self.num_tokens,
+            (self.chunk_starts.len() - 1) as u64,
+            (self.document_starts.len() - 1) as u64,
+            tables_offset,
+            self.chunk_size as u64,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.resize(HEADER_BYTES, 0);
+
+        let path = self.path.clone();
+        self.file.write_all(&tail).map_err(|e| io_error(&path, "write", e))?;
+        let mut file = self.file.into_inner().map_err(|e| io_error(&path, "write", e.into_error()))?;
+        file.seek(SeekFrom::Start(0)).map_err(|e| io_error(&path, "write", e))?;
+        file.write_all(&header).map_err(|e| io_error(&path, "write", e))?;
+        file.sync_all().map_err(|e| io_error(&path, "sync", e))
+    }
+}
+
+// Writes `documents` to `path`, using u16 tokens when every id fits.
+pub fn write_chunk_store<P: AsRef<Path>>(
+    path: P,
+    documents: &[Vec<u32>],
+    chunk_size: usize,
+) -> Result<(), Box<dyn Error>> {
+    let max_token = documents.iter().flatten().copied().max().unwrap_or(0);
+    let mut writer = ChunkStoreWriter::create(path, chunk_size, TokenWidth::for_vocab(max_token as u64 + 1))?;
+    for document in documents {
+        writer.add_document(document)?;
+    }
+    writer.finish()
+}
+
+struct Layout {
+    width: TokenWidth,
+    chunk_size: usize,
+    num_tokens: usize,
+    num_chunks: usize,
+    num_documents: usize,
+    tables_offset: usize,
+}
+
+fn parse_header(bytes: &[u8], origin: &Path) -> Result<Layout, Box<dyn Error>> {
+    let corrupt = |what: &str| util::invalid_argument_error(&format!("{}: {}", origin.display(), what));
+    if bytes.len() < HEADER_BYTES || &bytes[..8] != CHUNK_STORE_MAGIC {
+        return Err(corrupt("not a chunk store file"));
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version != CHUNK_STORE_VERSION {
+        return Err(corrupt(&format!("unsupported chunk store version {}", version)));
+    }
+    let width = match bytes[12] {
+        2 => TokenWidth::U16,
+        4 => TokenWidth::U32,
+        other => return Err(corrupt(&format!("invalid token width {}", other))),
+    };
+    let field = |i: usize| u64::from_le_bytes(bytes[16 + 8 * i..24 + 8 * i].try_into().unwrap()) as usize;
+    let layout = Layout {
+        width,
+        num_tokens: field(0),
+        num_chunks: field(1),
+        num_documents: field(2),
+        tables_offset: field(3),
+        chunk_size: field(4),
+    };
+    // Header fields are untrusted, so every size is computed with overflow
+    // checks; an overflowing layout cannot match any real file.
+    let token_end = layout.num_tokens.checked_mul(width.bytes()).and_then(|n| n.checked_add(HEADER_BYTES));
+    let expected_len = layout
+        .num_chunks
+        .checked_add(layout.num_documents)
+        .and_then(|n| n.checked_add(2))
+        .and_then(|n| n.checked_mul(8))
+        .and_then(|n| n.checked_add(layout.tables_offset));
+    let fits = token_end.is_some_and(|end| end <= layout.tables_offset);
+    if !fits || !layout.tables_offset.is_multiple_of(8) || expected_len != Some(bytes.len()) {
+        return Err(corrupt("chunk store tables do not match the file length"));
+    }
+    Ok(layout)
+}
+
+// Memory-mapped chunk store. Token and table sections are read in place, so
+// `get` borrows directly from the map.
+pub struct FileChunkStore {
+    map: Mmap,
+    layout: Layout,
+}
+
+impl FileChunkStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        if cfg!(target_endian = "big") {
+            return Err(util::failed_precondition_error(
+                "Memory-mapped chunk stores require a little-endian host",
+            ));
+        }
+        let file = File::open(path).map_err(|e| io_error(path, "open", e))?;
+        // The file is treated as immutable while mapped.
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| io_error(path, "map", e))?;
+        let layout = parse_header(&map, path)?;
+        let store = FileChunkStore { map, layout };
+        let chunk_starts = store.chunk_starts();
+        let document_starts = store.document_starts();
+        let valid = chunk_starts.first() == Some(&0)
+            && chunk_starts.last() == Some(&(store.layout.num_tokens as u64))
+            && chunk_starts.windows(2).all(|w| w[0] <= w[1])
+            && document_starts.first() == Some(&0)
+            && document_starts.last() == Some(&(store.layout.num_chunks as u64))
+            && document_starts.windows(2).all(|w| w[0] <= w[1]);
+        if !valid {
+            return Err(util::invalid_argument_error(&format!(
+                "{}: chunk store tables are not monotonic",
+                path.display()
+            )));
+        }
+        Ok(store)
+    }
+
+    fn table(&self, offset: usize, len: usize) -> &[u64] {
+        // The map is page aligned and tables start at a multiple of 8.
+        let (prefix, values, _) = unsafe { self.map[offset..offset + len * 8].align_to::<u64>() };
+        debug_assert!(prefix.is_empty());
+        values
+    }
+
+    fn chunk_starts(&self) -> &[u64] {
+        self.table(self.layout.tables_offset, self.layout.num_chunks + 1)
+    }
+
+    fn document_starts(&self) -> &[u64] {
+        self.table(
+            self.layout.tables_offset + (self.layout.num_chunks + 1) * 8,
+            self.layout.num_documents + 1,
+        )
+    }
+}
+
+impl ChunkStore for FileChunkStore {
+    fn chunk_size(&self) -> usize {
+        self.layout.chunk_size
+    }
+
+    fn num_chunks(&self) -> usize {
+        self.layout.num_chunks
+    }
+
+    fn num_documents(&self) -> usize {
+        self.layout.num_documents
+    }
+
+    fn get(&self, chunk_id: usize) -> Result<ChunkTokens<'_>, Box<dyn Error>> {
+        check_chunk(chunk_id, self.num_chunks())?;
+        let starts = self.chunk_starts();
+        let (start, end) = (starts[chunk_id] as usize, starts[chunk_id + 1] as usize);
+        let width = self.layout.width.bytes();
+        let bytes = &self.map[HEADER_BYTES + start * width..HEADER_BYTES + end * width];
+        // The token section starts at a 64-byte offset into a page-aligned
+        // map, so it is aligned for both widths.
+        Ok(match self.layout.width {
+            TokenWidth::U16 => ChunkTokens::U16(unsafe { bytes.align_to::<u16>().1 }),
+            TokenWidth::U32 => ChunkTokens::U32(unsafe { bytes.align_to::<u32>().1 }),
+        })
+    }
+
+    fn document_of(&self, chunk_id: usize) -> Result<usize, Box<dyn Error>> {
+        check_chunk(chunk_id, self.num_chunks())?;
+        Ok(document_for_chunk(self.document_starts(), chunk_id))
+    }
+}
+
+// Opens a chunk store file, mapping it when it is at least
+// `mmap_threshold_bytes` and otherwise loading it into memory.
+pub fn open_chunk_store<P: AsRef<Path>>(
+    path: P,
+    mmap_threshold_bytes: u64,
+) -> Result<Box<dyn ChunkStore>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let len = std::fs::metadata(path).map_err(|e| io_error(path, "stat", e))?.len();
+    let mapped = FileChunkStore::open(path)?;
+    if len >= mmap_threshold_bytes {
+        return Ok(Box::new(mapped));
+    }
+    let mut store = InMemoryChunkStore {
+        chunk_size: mapped.chunk_size(),
+        tokens: Vec::with_capacity(mapped.layout.num_tokens),
+        chunk_starts: mapped.chunk_starts().to_vec(),
+        document_starts: mapped.document_starts().to_vec(),
+    };
+    for chunk_id in 0..mapped.num_chunks() {
+        store.tokens.extend(mapped.get(chunk_id)?.to_vec());
+    }
+    Ok(Box::new(store))
+}
@@ -17,7 +17,8 @@
 use nalgebra::DMatrix;
 use std::error::Error;
 
-use super::{attention, encoder, utils};
+use super::{attention, encoder};
+use crate::util;
 
 pub struct ChunkedCrossAttention {
     chunk_size: u32,
@@ -36,27 +37,238 @@ impl ChunkedCrossAttention {
         &self,
         x: &DMatrix<f32>,
         context: &DMatrix<f32>,
+        context_mask: Option<&[bool]>,
         pos_emb: (&DMatrix<f32>, &DMatrix<f32>),
     ) -> Result<DMatrix<f32>, Box<dyn Error>> {
-        let chunk_size = self.chunk_size as usize;
-        let (q_pos_emb, k_pos_emb) = pos_emb;
-        if x.nrows() < chunk_size {
+        let num_chunks = x.nrows() / self.chunk_size as usize;
+        if num_chunks == 0 {
             return Ok(DMatrix::zeros(x.nrows(), x.ncols()));
         }
+        self.forward_from(x, 0, context, context_mask, num_chunks, pos_emb)
+    }
+}
+
+impl ChunkedCrossAttention {
+    // Cross-attention for rows `offset..offset + x.nrows()` of a sequence
+    // whose first `num_chunks` chunks are complete; `context` holds their
+    // encoded neighbors, chunk after chunk, and `context_mask` marks its
+    // padding rows, which get no attention. Rows past the last complete
+    // chunk get no cross-attention.
+    pub fn forward_from(
+        &self,
+        x: &DMatrix<f32>,
+        offset: usize,
+        context: &DMatrix<f32>,
+        context_mask: Option<&[bool]>,
+        num_chunks: usize,
+        _pos_emb: (&DMatrix<f32>, &DMatrix<f32>),
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let chunk_size = self.chunk_size as usize;
+        let mut out = DMatrix::zeros(x.nrows(), x.ncols());
+        if num_chunks == 0 {
+            return Ok(out);
+        }
+        let rows_per_chunk = context.nrows() / num_chunks;
+        check_context_mask(context_mask, context.nrows())?;
+        let end = (offset + x.nrows()).min(num_chunks * chunk_size);
+        let mut row = offset;
+        while row < end {
+            let chunk = row / chunk_size;
+            let chunk_end = ((chunk + 1) * chunk_size).min(end);
+            let queries = x.rows(row - offset, chunk_end - row).into_owned();
+            let keys = context.rows(chunk * rows_per_chunk, rows_per_chunk).into_owned();
+            let mask = context_mask.map(|mask| &mask[chunk * rows_per_chunk..(chunk + 1) * rows_per_chunk]);
+            let attended = self.cross_attn.forward_masked(&queries, Some(&keys), mask)?;
+            out.rows_mut(row - offset, chunk_end - row).copy_from(&attended);
+            row = chunk_end;
+        }
+        Ok(out)
+    }
+}
 
-        let causal_padding = chunk_size - 1;
-        let mut x_padded = DMatrix::zeros(x.nrows() + causal_padding, x.ncols());
-        x_padded.rows_mut(0, x.nrows()).copy_from(x);
+fn check_context_mask(context_mask: Option<&[bool]>, context_rows: usize) -> Result<(), Box<dyn Error>> {
+    match context_mask {
+        Some(mask) if mask.len() != context_rows => Err(util::invalid_argument_error(&format!(
+            "Retrieved mask has {} entries for {} retrieved rows",
+            mask.len(),
+            context_rows
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn vstack(top: &DMatrix<f32>, bottom: &DMatrix<f32>) -> DMatrix<f32> {
+    if top.nrows() == 0 {
+        return bottom.clone();
+    }
+    let mut out = DMatrix::zeros(top.nrows() + bottom.nrows(), top.ncols());
+    out.rows_mut(0, top.nrows()).copy_from(top);
+    out.rows_mut(top.nrows(), bottom.nrows()).copy_from(bottom);
+    out
+}
+
+// Cross-attention mass one decoder layer assigned to each retrieved
+// neighbor. `mass[chunk][neighbor]` is summed over heads and query positions
+// and divided by their product, so each chunk's masses sum to at most 1.
+#[derive(Clone, Debug, Default)]
+pub struct LayerAttentionSummary {
+    // 1-based, matching `dec_cross_attn_layers`.
+    pub layer: u32,
+    pub mass: Vec<Vec<f32>>,
+    // `head_weights[chunk][head]`, (chunk_size, context rows). Only kept
+    // when the layer's total element count is within the capture limit.
+    pub head_weights: Option<Vec<Vec<DMatrix<f32>>>>,
+}
 
+#[derive(Clone, Copy, Debug)]
+pub struct AttentionCapture {
+    pub neighbors_per_chunk: usize,
+    // Largest number of weight elements per layer to keep as full matrices;
+    // 0 keeps summaries only.
+    pub max_matrix_elements: usize,
+}
+
+impl ChunkedCrossAttention {
+    pub fn attention_summary(
+        &self,
+        layer: u32,
+        x: &DMatrix<f32>,
+        context: &DMatrix<f32>,
+        context_mask: Option<&[bool]>,
+        capture: &AttentionCapture,
+    ) -> Result<LayerAttentionSummary, Box<dyn Error>> {
+        let chunk_size = self.chunk_size as usize;
         let num_chunks = x.nrows() / chunk_size;
-        let seq_index = num_chunks * chunk_size;
-        let x = x_padded.rows(0, seq_index).into_owned();
+        let mut summary = LayerAttentionSummary {
+            layer,
+            ..LayerAttentionSummary::default()
+        };
+        if num_chunks == 0 {
+            return Ok(summary);
+        }
+        let rows_per_chunk = context.nrows() / num_chunks;
+        let neighbors = capture.neighbors_per_chunk;
+        if neighbors == 0 || rows_per_chunk == 0 || !rows_per_chunk.is_multiple_of(neighbors) {
+            return Err(util::invalid_argument_error(&format!(
+                "Cannot split {} context rows per chunk across {} neighbors",
+                rows_per_chunk, neighbors
+            )));
+        }
+        check_context_mask(context_mask, context.nrows())?;
+        let rows_per_neighbor = rows_per_chunk / neighbors;
+        let keep_matrices = capture.max_matrix_elements > 0
+            && num_chunks * self.cross_attn.num_heads() * chunk_size * rows_per_chunk <= capture.max_matrix_elements;
+        let mut head_weights = Vec::new();
+        for c in 0..num_chunks {
+            let queries = x.rows(c * chunk_size, chunk_size).into_owned();
+            let keys = context.rows(c * rows_per_chunk, rows_per_chunk).into_owned();
+            let mask = context_mask.map(|mask| &mask[c * rows_per_chunk..(c + 1) * rows_per_chunk]);
+            let weights = self.cross_attn.attention_weights(&queries, Some(&keys), mask)?;
+            let total = (weights.len() * chunk_size) as f32;
+            let mass = (0..neighbors)
+                .map(|n| {
+                    weights
+                        .iter()
+                        .map(|w| w.columns(n * rows_per_neighbor, rows_per_neighbor).sum())
+                        .sum::<f32>()
+                        / total
+                })
+                .collect();
+            summary.mass.push(mass);
+            if keep_matrices {
+                head_weights.push(weights);
+            }
+        }
+        if keep_matrices {
+            summary.head_weights = Some(head_weights);
+        }
+        Ok(summary)
+    }
+}
 
-        let x = x.reshape((num_chunks, chunk_size, x.ncols()));
-        let context = context.reshape((num_chunks, context.nrows() / num_chunks, context.ncols()));
-        let out = self.cross_attn.forward(&x, Some(&context), Some(&q_pos_emb))?;
-        let out = out.reshape((seq_index, x.ncols()));
-        Ok(out)
+type RetrievedKey = Vec<(usize, Vec<usize>)>;
+
+// Encoded neighbors from the latest retrieval event, keyed by
+// (chunk index, neighbor ids) for every complete chunk. A different key,
+// i.e. a new retrieval at a chunk boundary, replaces the entry.
+#[derive(Default)]
+pub struct RetrievedCache {
+    key: RetrievedKey,
+    encoded: Option<DMatrix<f32>>,
+    encoder_calls: usize,
+}
+
+impl RetrievedCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &[(usize, Vec<usize>)]) -> Option<&DMatrix<f32>> {
+        self.encoded.as_ref().filter(|_| self.key == key)
+    }
+
+    fn insert(&mut self, key: RetrievedKey, encoded: DMatrix<f32>) {
+        self.key = key;
+        self.encoded = Some(encoded);
+        self.encoder_calls += 1;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.key.clear();
+        self.encoded = None;
+    }
+
+    // Number of times the encoder ran on a cache miss.
+    pub fn encoder_calls(&self) -> usize {
+        self.encoder_calls
+    }
+}
+
+// Per-sequence state carried across steps of incremental decoding.
+#[derive(Default)]
+pub struct DecoderState {
+    pub retrieved_cache: RetrievedCache,
+    forward_calls: usize,
+    rows_decoded: usize,
+}
+
+impl DecoderState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Decoder passes run through the prefix and continuation entry points.
+    pub fn forward_calls(&self) -> usize {
+        self.forward_calls
+    }
+
+    // Sequence positions those passes pushed through the decoder layers;
+    // positions served from a KvCache are not counted.
+    pub fn rows_decoded(&self) -> usize {
+        self.rows_decoded
+    }
+}
+
+// Per-layer self-attention keys and values of a decoded prefix, plus what
+// chunked cross-attention needs to extend it: the prefix rows the encoder
+// reads as context and the neighbors encoded for its complete chunks.
+#[derive(Clone, Default)]
+pub struct KvCache {
+    len: usize,
+    kv: Vec<(DMatrix<f32>, DMatrix<f32>)>,
+    encoder_context: Option<DMatrix<f32>>,
+    // (complete chunks, encoded neighbors of those chunks)
+    encoded: Option<(usize, DMatrix<f32>)>,
+}
+
+impl KvCache {
+    // Number of prefix positions held.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 }
 
@@ -97,30 +309,216 @@ impl Decoder {
         encoder: &encoder::Encoder,
         retrieved: Option<&DMatrix<f32>>,
     ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        self.forward_with_capture(x, encoder, retrieved, None, None).map(|(out, _)| out)
+    }
+
+    // Like `forward`, additionally summarizing cross-attention per layer
+    // when `capture` is set. Without it nothing extra is computed.
+    // `retrieved_mask` has one entry per row of `retrieved`; rows marked
+    // false are padding and get no attention.
+    pub fn forward_with_capture(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+        capture: Option<&AttentionCapture>,
+    ) -> Result<(DMatrix<f32>, Vec<LayerAttentionSummary>), Box<dyn Error>> {
+        self.run(x, encoder, retrieved, retrieved_mask, capture, None)
+    }
+
+    // Like `forward`, reusing the encoded neighbors held in `state` while
+    // `neighbor_ids` (one list per complete chunk) is unchanged. Completed
+    // chunks never change under causal decoding, so during generation the
+    // encoder runs once per retrieval event instead of once per token.
+    pub fn forward_with_state(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: &DMatrix<f32>,
+        neighbor_ids: &[Vec<usize>],
+        state: &mut DecoderState,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let num_chunks = x.nrows() / self.chunk_size as usize;
+        if neighbor_ids.len() != num_chunks {
+            return Err(util::invalid_argument_error(&format!(
+                "Got neighbor ids for {} chunks, sequence has {} complete chunks",
+                neighbor_ids.len(),
+                num_chunks
+            )));
+        }
+        let key: RetrievedKey = neighbor_ids.iter().cloned().enumerate().collect();
+        self.run(x, encoder, Some(retrieved), None, None, Some((key, &mut state.retrieved_cache)))
+            .map(|(out, _)| out)
+    }
+
+    // Decodes `x` as a prefix, returning its outputs and the cache that
+    // `forward_continuation` extends. `retrieved` covers the prefix's
+    // complete chunks; `retrieved_mask` marks its padding rows as in
+    // `forward_with_capture`.
+    pub fn forward_prefix(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+        state: &mut DecoderState,
+    ) -> Result<(DMatrix<f32>, KvCache), Box<dyn Error>> {
+        let mut cache = KvCache::default();
+        let past = KvCache::default();
+        let out = self.run_incremental(x, encoder, retrieved, retrieved_mask, &past, Some(&mut cache), state)?;
+        Ok((out, cache))
+    }
+
+    // Outputs for the rows `x` that follow the prefix held in `cache`,
+    // without decoding the prefix again. `retrieved` covers every complete
+    // chunk of prefix and continuation together; while that is no more
+    // chunks than the prefix had, the cached encoded neighbors are reused.
+    pub fn forward_continuation(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+        cache: &KvCache,
+        state: &mut DecoderState,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        self.run_incremental(x, encoder, retrieved, retrieved_mask, cache, None, state)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_incremental(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+        past: &KvCache,
+        mut record: Option<&mut KvCache>,
+        state: &mut DecoderState,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let chunk_size = self.chunk_size as usize;
+        let offset = past.len;
+        let num_chunks = (offset + x.nrows()) / chunk_size;
+        state.forward_calls += 1;
+        state.rows_decoded += x.nrows();
+        let mut x = x.clone();
+        let mut encoded: Option<DMatrix<f32>> = None;
+
+        for (i, (norm, attn, cross_attn, ff)) in self.layers.iter().enumerate() {
+            x = norm.forward(&x)? + &x;
+            let (k, v) = attn.project_kv(&x)?;
+            let (k, v) = match past.kv.get(i) {
+                Some((past_k, past_v)) => (vstack(past_k, &k), vstack(past_v, &v)),
+                None => (k, v),
+            };
+            x = attn.attend(&x, &k, &v, offset)?;
+            if let Some(record) = record.as_deref_mut() {
+                record.kv.push((k, v));
+            }
+            if let (Some(cross_attn), Some(retrieved)) = (cross_attn, retrieved) {
+                // As in `run`, the encoder reads the rows entering the first
+                // cross-attention layer.
+                if encoded.is_none() {
+                    let context = match &past.encoder_context {
+                        Some(prefix_rows) => vstack(prefix_rows, &x),
+                        None => x.clone(),
+                    };
+                    let neighbors = match &past.encoded {
+                        Some((chunks, cached)) if *chunks == num_chunks => cached.clone(),
+                        _ if num_chunks == 0 => DMatrix::zeros(0, x.ncols()),
+                        _ => {
+                            state.retrieved_cache.encoder_calls += 1;
+                            let seq_as_context = context.rows(0, num_chunks * chunk_size).into_owned();
+                            encoder.forward_masked(retrieved, &seq_as_context, retrieved_mask)?
+                        }
+                    };
+                    if let Some(record) = record.as_deref_mut() {
+                        record.encoder_context = Some(context);
+                        record.encoded = Some((num_chunks, neighbors.clone()));
+                    }
+                    encoded = Some(neighbors);
+                }
+                let cross_attn_pos_emb = (
+                    self.rotary_pos_emb.forward(chunk_size, chunk_size - 1),
+                    self.rotary_pos_emb.forward(chunk_size, 0),
+                );
+                x = cross_attn.forward_from(
+                    &x,
+                    offset,
+                    encoded.as_ref().unwrap(),
+                    retrieved_mask,
+                    num_chunks,
+                    (&cross_attn_pos_emb.0, &cross_attn_pos_emb.1),
+                )? + &x;
+            }
+            x = ff.forward(&x)? + &x;
+        }
+        if let Some(record) = record {
+            record.len = offset + x.nrows();
+        }
+        self.norm_out.forward(&x)
+    }
+
+    fn run(
+        &self,
+        x: &DMatrix<f32>,
+        encoder: &encoder::Encoder,
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+        capture: Option<&AttentionCapture>,
+        mut cache: Option<(RetrievedKey, &mut RetrievedCache)>,
+    ) -> Result<(DMatrix<f32>, Vec<LayerAttentionSummary>), Box<dyn Error>> {
+        let mut summaries = Vec::new();
         let seq_len = x.nrows();
         let self_attn_pos_emb = self.rotary_pos_emb.forward(seq_len, 0);
         let mut x = x.clone();
         let mut retrieved_encoded = None;
 
-        for (norm, attn, cross_attn, ff) in &self.layers {
+        for (i, (norm, attn, cross_attn, ff)) in self.layers.iter().enumerate() {
             x = norm.forward(&x)? + &x;
             x = attn.forward(&x, None, Some(&self_attn_pos_emb))?;
             if let (Some(cross_attn), Some(retrieved)) = (cross_attn, retrieved) {
+                // Encoded once per forward pass and shared by every
+                // cross-attention layer.
                 if retrieved_encoded.is_none() {
-                    let num_chunks = seq_len / self.chunk_size as usize;
-                    let seq_index = num_chunks * self.chunk_size as usize;
-                    let seq_as_context = x.rows(0, seq_index).into_owned();
-                    let retrieved_encoded_res = encoder.forward(retrieved, &seq_as_context)?;
-                    retrieved_encoded = Some(retrieved_encoded_res);
+                    let cached = cache.as_ref().and_then(|(key, cache)| cache.get(key).cloned());
+                    retrieved_encoded = Some(match cached {
+                        Some(encoded) => encoded,
+                        None => {
+                            let num_chunks = seq_len / self.chunk_size as usize;
+                            let seq_index = num_chunks * self.chunk_size as usize;
+                            let seq_as_context = x.rows(0, seq_index).into_owned();
+                            let encoded = encoder.forward_masked(retrieved, &seq_as_context, retrieved_mask)?;
+                            if let Some((key, cache)) = cache.as_mut() {
+                                cache.insert(std::mem::take(key), encoded.clone());
+                            }
+                            encoded
+                        }
+                    });
                 }
                 let cross_attn_pos_emb = (
                     self.rotary_pos_emb.forward(self.chunk_size as usize, self.chunk_size as usize - 1),
                     self.rotary_pos_emb.forward(self.chunk_size as usize, 0),
                 );
-                x = cross_attn.forward(&x, retrieved_encoded.as_ref().unwrap(), &cross_attn_pos_emb)? + &x;
+                if let Some(capture) = capture {
+                    summaries.push(cross_attn.attention_summary(
+                        i as u32 + 1,
+                        &x,
+                        retrieved_encoded.as_ref().unwrap(),
+                        retrieved_mask,
+                        capture,
+                    )?);
+                }
+                x = cross_attn.forward(
+                    &x,
+                    retrieved_encoded.as_ref().unwrap(),
+                    retrieved_mask,
+                    (&cross_attn_pos_emb.0, &cross_attn_pos_emb.1),
+                )? + &x;
             }
             x = ff.forward(&x)? + &x;
         }
-        self.norm_out.forward(&x)
+        Ok((self.norm_out.forward(&x)?, summaries))
     }
 }
\ No newline at end of file
@@ -14,29 +14,35 @@
 
 //! Token and positional embeddings for RETRO.
 
-use nalgebra::{DMatrix, DVector};
-use rand::distributions::{Distribution, Normal};
+use nalgebra::DMatrix;
 use std::error::Error;
 
+use crate::util;
+
 pub struct TokenEmbedding {
     weights: DMatrix<f32>,
 }
 
 impl TokenEmbedding {
     pub fn new(num_tokens: u32, dim: u32) -> Self {
-        let normal = Normal::new(0.0, 1.0);
-        let mut rng = rand::thread_rng();
-        let weights = DMatrix::from_fn(num_tokens as usize, dim as usize, |_, _| {
-            normal.sample(&mut rng) as f32
-        });
+        let weights = util::random_normal_matrix(num_tokens as usize, dim as usize, &mut util::SplitMix64::from_entropy());
+        TokenEmbedding { weights }
+    }
+
+    pub fn from_weights(weights: DMatrix<f32>) -> Self {
         TokenEmbedding { weights }
     }
 
+    // One row per token id.
+    pub fn weights(&self) -> &DMatrix<f32> {
+        &self.weights
+    }
+
     pub fn forward(&self, tokens: &[u32]) -> Result<DMatrix<f32>, Box<dyn Error>> {
         let mut result = DMatrix::zeros(tokens.len(), self.weights.ncols());
         for (i, &token) in tokens.iter().enumerate() {
             if token as usize >= self.weights.nrows() {
-                return Err(super::utils::invalid_argument_error(&format!(
+                return Err(util::invalid_argument_error(&format!(
                     "Token ID {} exceeds vocabulary size {}",
                     token, self.weights.nrows()
                 )));
@@ -53,17 +59,13 @@ pub struct PositionalEmbedding {
 
 impl PositionalEmbedding {
     pub fn new(max_seq_len: u32, dim: u32) -> Self {
-        let normal = Normal::new(0.0, 1.0);
-        let mut rng = rand::thread_rng();
-        let weights = DMatrix::from_fn(max_seq_len as usize, dim as usize, |_, _| {
-            normal.sample(&mut rng) as f32
-        });
+        let weights = util::random_normal_matrix(max_seq_len as usize, dim as usize, &mut util::SplitMix64::from_entropy());
         PositionalEmbedding { weights }
     }
 
     pub fn forward(&self, seq_len: usize) -> Result<DMatrix<f32>, Box<dyn Error>> {
         if seq_len > self.weights.nrows() {
-            return Err(super::utils::invalid_argument_error(&format!(
+            return Err(util::invalid_argument_error(&format!(
                 "Sequence length {} exceeds max sequence length {}",
                 seq_len, self.weights.nrows()
             )));
@@ -17,7 +17,8 @@
 use nalgebra::DMatrix;
 use std::error::Error;
 
-use super::{attention, utils};
+use super::attention;
+use crate::util;
 
 pub struct FeedForward {
     w1: DMatrix<f32>,
@@ -27,17 +28,16 @@ pub struct FeedForward {
 impl FeedForward {
     pub fn new(dim: u32, mult: u32) -> Self {
         let inner_dim = dim * mult;
-        let normal = Normal::new(0.0, 1.0);
-        let mut rng = rand::thread_rng();
+        let mut rng = util::SplitMix64::from_entropy();
         FeedForward {
-            w1: DMatrix::from_fn(inner_dim as usize, dim as usize, |_, _| normal.sample(&mut rng) as f32),
-            w2: DMatrix::from_fn(dim as usize, inner_dim as usize, |_, _| normal.sample(&mut rng) as f32),
+            w1: util::random_normal_matrix(inner_dim as usize, dim as usize, &mut rng),
+            w2: util::random_normal_matrix(dim as usize, inner_dim as usize, &mut rng),
         }
     }
 
     pub fn forward(&self, x: &DMatrix<f32>) -> Result<DMatrix<f32>, Box<dyn Error>> {
-        let hidden = utils::matrix_multiply(x, &self.w1.transpose())?.map(|v| v.max(0.0)); // GELU approximation
-        utils::matrix_multiply(&hidden, &self.w2.transpose())
+        let hidden = util::matrix_multiply(x, &self.w1.transpose())?.map(|v| v.max(0.0)); // GELU approximation
+        util::matrix_multiply(&hidden, &self.w2.transpose())
     }
 }
 
@@ -84,21 +84,31 @@ impl Encoder {
         x: &DMatrix<f32>,
         chunked_seq: &DMatrix<f32>,
     ) -> Result<DMatrix<f32>, Box<dyn Error>> {
-        let chunk_size = chunked_seq.nrows();
+        self.forward_masked(x, chunked_seq, None)
+    }
+
+    // Like `forward`, with rows of `x` whose `mask` entry is false treated
+    // as padding: no row attends to them. Their own outputs are meaningless
+    // and must be masked by the reader too.
+    pub fn forward_masked(
+        &self,
+        x: &DMatrix<f32>,
+        chunked_seq: &DMatrix<f32>,
+        mask: Option<&[bool]>,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
         let seq_len = x.nrows();
-        let q_pos_emb = self.rotary_pos_emb.forward(chunk_size, 0);
         let k_pos_emb = self.rotary_pos_emb.forward(seq_len, 0);
 
         let mut x = x.clone();
         for (norm, attn, cross_attn, ff) in &self.layers {
             x = norm.forward(&x)? + &x;
-            x = attn.forward(&x, None, Some(&q_pos_emb))?;
+            x = attn.forward_masked(&x, None, mask)?;
             if let Some(cross_attn) = cross_attn {
                 x = cross_attn.forward(&x, Some(chunked_seq), Some(&k_pos_emb))?;
             }
             x = ff.forward(&x)? + &x;
         }
         x = self.norm_out.forward(&x)?;
-        utils::matrix_multiply(&x, &self.project_out.transpose())
+        util::matrix_multiply(&x, &self.project_out.transpose())
     }
 }
\ No newline at end of file
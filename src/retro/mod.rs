@@ -15,9 +15,13 @@
 //! RETRO model implementation.
 
 pub mod attention;
+pub mod chunk_migration;
+pub mod chunk_store;
 pub mod decoder;
 pub mod embeddings;
 pub mod encoder;
 pub mod model;
+pub mod vocab_remap;
 
-pub use model::RETRO;
\ No newline at end of file
+pub use model::{ContextPolicy, GenerateOptions, GenerateOutput, RETRO};
+pub use vocab_remap::VocabRemap;
\ No newline at end of file
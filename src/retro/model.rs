@@ -16,11 +16,74 @@
 
 use nalgebra::DMatrix;
 use std::error::Error;
+use std::sync::Mutex;
 
-use super::{decoder, embeddings, encoder, utils};
+use super::{decoder, embeddings, encoder, vocab_remap};
+use crate::util;
 use crate::proto::RetroConfig;
 use crate::retrieval::ScannRetriever;
 
+// What `generate` does once the context outgrows max_seq_len.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContextPolicy {
+    #[default]
+    Error,
+    // Decode from the most recent tokens only. The window start is rounded
+    // up to a chunk boundary, so at most `window` tokens are used and chunks
+    // seen by retrieval are never split.
+    SlidingWindow { window: usize },
+}
+
+#[derive(Clone, Debug)]
+pub struct GenerateOptions {
+    pub max_new_tokens: usize,
+    pub context_policy: ContextPolicy,
+    // Stop after emitting this token.
+    pub eos_id: Option<u32>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            max_new_tokens: 64,
+            context_policy: ContextPolicy::default(),
+            eos_id: None,
+        }
+    }
+}
+
+// Source of retrieved neighbors: for every complete chunk of `seq`, the
+// token ids of each neighbor. Neighbors may differ in length and chunks in
+// neighbor count; trailing pad_id tokens are padding.
+pub trait ChunkRetriever: Send + Sync {
+    fn retrieve_chunks(&self, seq: &[u32], chunk_size: usize) -> Result<Vec<Vec<Vec<u32>>>, Box<dyn Error>>;
+}
+
+impl ChunkRetriever for ScannRetriever {
+    fn retrieve_chunks(&self, seq: &[u32], chunk_size: usize) -> Result<Vec<Vec<Vec<u32>>>, Box<dyn Error>> {
+        ScannRetriever::retrieve_chunks(self, seq, chunk_size)
+    }
+}
+
+// Embedded neighbors, one row per neighbor slot, chunk after chunk. Chunks
+// with fewer neighbors than the most populated one are filled with padding
+// rows; `mask` is false for those and for neighbors with no tokens besides
+// padding.
+struct RetrievedRows {
+    rows: DMatrix<f32>,
+    mask: Vec<bool>,
+    neighbors_per_chunk: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GenerateOutput {
+    // Prompt followed by the generated tokens.
+    pub tokens: Vec<u32>,
+    // Offset into `tokens` of the context window used at each step; always a
+    // multiple of chunk_size.
+    pub window_starts: Vec<usize>,
+}
+
 pub struct RETRO {
     token_emb: embeddings::TokenEmbedding,
     pos_emb: embeddings::PositionalEmbedding,
@@ -31,16 +94,24 @@ pub struct RETRO {
     seq_len: u32,
     chunk_size: u32,
     pad_id: u32,
-    retriever: Option<ScannRetriever>,
+    retriever: Option<Box<dyn ChunkRetriever>>,
+    // Maps input and retrieved-neighbor token ids, which share the corpus
+    // tokenization, into the model's vocabulary before embedding.
+    input_remap: Option<vocab_remap::VocabRemap>,
+    capture_attention: bool,
+    attention_matrix_limit: usize,
+    // Summaries from the most recent forward pass with capture enabled.
+    attention_summary: Mutex<Vec<decoder::LayerAttentionSummary>>,
 }
 
 impl RETRO {
     pub fn new(config: RetroConfig, retriever: Option<ScannRetriever>) -> Self {
+        let mut rng = util::SplitMix64::from_entropy();
         let to_decoder_model_dim = if config.enc_dim != config.dec_dim {
             DMatrix::from_fn(
                 config.dec_dim as usize,
                 config.enc_dim as usize,
-                |_, _| rand::random::<f32>(),
+                |_, _| rng.next_f32(),
             )
         } else {
             DMatrix::identity(config.enc_dim as usize, config.enc_dim as usize)
@@ -68,56 +139,369 @@ impl RETRO {
             to_logits: DMatrix::from_fn(
                 config.num_tokens as usize,
                 config.dec_dim as usize,
-                |_, _| rand::random::<f32>(),
+                |_, _| rng.next_f32(),
             ),
             seq_len: config.max_seq_len,
             chunk_size: config.chunk_size,
             pad_id: config.pad_id,
-            retriever,
+            retriever: retriever.map(|r| Box::new(r) as Box<dyn ChunkRetriever>),
+            input_remap: None,
+            capture_attention: false,
+            attention_matrix_limit: 0,
+            attention_summary: Mutex::new(Vec::new()),
         }
     }
 
+    pub fn set_retriever(&mut self, retriever: Option<Box<dyn ChunkRetriever>>) {
+        self.retriever = retriever;
+    }
+
+    pub fn set_input_remap(&mut self, remap: Option<vocab_remap::VocabRemap>) {
+        self.input_remap = remap;
+    }
+
+    // Off by default. While on, each retrieval forward pass records how much
+    // cross-attention every decoder layer gives each retrieved neighbor.
+    pub fn set_capture_attention(&mut self, capture: bool) {
+        self.capture_attention = capture;
+        if !capture {
+            self.attention_summary.lock().unwrap().clear();
+        }
+    }
+
+    // Keep full per-head matrices for layers with at most this many weight
+    // elements; 0 (the default) keeps summaries only.
+    pub fn set_attention_matrix_limit(&mut self, max_elements: usize) {
+        self.attention_matrix_limit = max_elements;
+    }
+
+    // One entry per decoder cross-attention layer from the last forward pass
+    // that used retrieval; empty when capture is off.
+    pub fn attention_summary(&self) -> Vec<decoder::LayerAttentionSummary> {
+        self.attention_summary.lock().unwrap().clone()
+    }
+
     pub fn forward_without_retrieval(&self, seq: &[u32]) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let remapped = vocab_remap::remap_tokens(self.input_remap.as_ref(), seq)?;
+        let seq: &[u32] = &remapped;
         let embed = self.token_emb.forward(seq)?;
         let pos_emb = self.pos_emb.forward(embed.nrows())?;
         let embed = embed + pos_emb;
-        let embed = utils::matrix_multiply(&embed, &self.to_decoder_model_dim.transpose())?;
+        let embed = util::matrix_multiply(&embed, &self.to_decoder_model_dim.transpose())?;
         let decoded = self.decoder.forward(&embed, &self.encoder, None)?;
-        utils::matrix_multiply(&decoded, &self.to_logits.transpose())
+        util::matrix_multiply(&decoded, &self.to_logits.transpose())
+    }
+
+    // One step of incremental decoding with explicitly retrieved neighbors.
+    // `neighbor_ids` identifies the neighbors of each complete chunk; while
+    // it is unchanged the encoded neighbors in `state` are reused.
+    pub fn forward_with_state(
+        &self,
+        seq: &[u32],
+        retrieved: &DMatrix<f32>,
+        neighbor_ids: &[Vec<usize>],
+        state: &mut decoder::DecoderState,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let remapped = vocab_remap::remap_tokens(self.input_remap.as_ref(), seq)?;
+        let embed = self.token_emb.forward(&remapped)?;
+        let pos_emb = self.pos_emb.forward(embed.nrows())?;
+        let embed = util::matrix_multiply(&(embed + pos_emb), &self.to_decoder_model_dim.transpose())?;
+        let decoded = self
+            .decoder
+            .forward_with_state(&embed, &self.encoder, retrieved, neighbor_ids, state)?;
+        util::matrix_multiply(&decoded, &self.to_logits.transpose())
     }
 
     pub fn forward(&self, seq: &[u32], retrieved: Option<&DMatrix<f32>>) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        self.forward_impl(seq, retrieved, None)
+    }
+
+    // Like `forward` with explicit neighbors, where rows of `retrieved`
+    // whose `retrieved_mask` entry is false are padding: neither the
+    // encoder nor cross-attention attends to them.
+    pub fn forward_with_mask(
+        &self,
+        seq: &[u32],
+        retrieved: &DMatrix<f32>,
+        retrieved_mask: &[bool],
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        if retrieved_mask.len() != retrieved.nrows() {
+            return Err(util::invalid_argument_error(&format!(
+                "Retrieved mask has {} entries for {} retrieved rows",
+                retrieved_mask.len(),
+                retrieved.nrows()
+            )));
+        }
+        self.forward_impl(seq, Some(retrieved), Some(retrieved_mask))
+    }
+
+    fn forward_impl(
+        &self,
+        seq: &[u32],
+        retrieved: Option<&DMatrix<f32>>,
+        retrieved_mask: Option<&[bool]>,
+    ) -> Result<DMatrix<f32>, Box<dyn Error>> {
         if retrieved.is_none() && self.retriever.is_none() {
             return self.forward_without_retrieval(seq);
         }
 
+        let remapped = vocab_remap::remap_tokens(self.input_remap.as_ref(), seq)?;
+        let seq: &[u32] = &remapped;
         let embed = self.token_emb.forward(seq)?;
         let pos_emb = self.pos_emb.forward(embed.nrows())?;
         let embed = embed + pos_emb;
 
-        let retrieved = if let Some(retrieved) = retrieved {
-            retrieved.clone()
+        // Each row of an explicit `retrieved` matrix counts as one neighbor.
+        let mut neighbors_per_chunk = 0;
+        let fetched;
+        let (retrieved, retrieved_mask) = if let Some(retrieved) = retrieved {
+            (retrieved, retrieved_mask)
         } else if let Some(retriever) = &self.retriever {
-            let chunks = retriever.retrieve_chunks(seq, self.chunk_size as usize)?;
-            let mut retrieved_data = Vec::new();
-            for chunk in chunks {
-                let mut chunk_data = Vec::new();
-                for neighbor in chunk {
-                    chunk_data.push(self.token_emb.forward(&neighbor)?);
+            fetched = self.embed_retrieved(retriever.retrieve_chunks(seq, self.chunk_size as usize)?)?;
+            neighbors_per_chunk = fetched.neighbors_per_chunk;
+            (&fetched.rows, Some(fetched.mask.as_slice()))
+        } else {
+            return Err(util::invalid_argument_error("No retrieved data or retriever provided"));
+        };
+
+        let embed = util::matrix_multiply(&embed, &self.to_decoder_model_dim.transpose())?;
+        let capture = self.capture_attention.then(|| {
+            let num_chunks = (embed.nrows() / self.chunk_size as usize).max(1);
+            if neighbors_per_chunk == 0 {
+                neighbors_per_chunk = retrieved.nrows() / num_chunks;
+            }
+            decoder::AttentionCapture {
+                neighbors_per_chunk,
+                max_matrix_elements: self.attention_matrix_limit,
+            }
+        });
+        let (decoded, summaries) = self.decoder.forward_with_capture(
+            &embed,
+            &self.encoder,
+            Some(retrieved),
+            retrieved_mask,
+            capture.as_ref(),
+        )?;
+        if capture.is_some() {
+            *self.attention_summary.lock().unwrap() = summaries;
+        }
+        util::matrix_multiply(&decoded, &self.to_logits.transpose())
+    }
+
+    // One row per neighbor, each the embedding of the neighbor's first
+    // token. No chunks give an empty matrix.
+    fn embed_retrieved(&self, chunks: Vec<Vec<Vec<u32>>>) -> Result<RetrievedRows, Box<dyn Error>> {
+        let dim = self.token_emb.weights().ncols();
+        let neighbors_per_chunk = chunks.iter().map(|chunk| chunk.len()).max().unwrap_or(0);
+        let mut rows = DMatrix::zeros(chunks.len() * neighbors_per_chunk, dim);
+        let mut mask = vec![false; rows.nrows()];
+        for (c, chunk) in chunks.iter().enumerate() {
+            for (n, neighbor) in chunk.iter().enumerate() {
+                let neighbor = strip_padding(neighbor, self.pad_id);
+                let Some(&first) = neighbor.first() else {
+                    continue;
+                };
+                let first = [first];
+                let first = vocab_remap::remap_tokens(self.input_remap.as_ref(), &first)?;
+                let row = c * neighbors_per_chunk + n;
+                rows.row_mut(row).copy_from(&self.token_emb.forward(&first)?.row(0));
+                mask[row] = true;
+            }
+        }
+        Ok(RetrievedRows {
+            rows,
+            mask,
+            neighbors_per_chunk,
+        })
+    }
+
+    // Decoder-width embeddings of already remapped `tokens` placed at
+    // positions `offset..`.
+    fn embed_at(&self, tokens: &[u32], offset: usize) -> Result<DMatrix<f32>, Box<dyn Error>> {
+        let embed = self.token_emb.forward(tokens)?;
+        let pos_emb = self.pos_emb.forward(offset + tokens.len())?.rows(offset, tokens.len()).into_owned();
+        util::matrix_multiply(&(embed + pos_emb), &self.to_decoder_model_dim.transpose())
+    }
+
+    // Length-normalized log-likelihood of each candidate continuation of
+    // `prefix` under teacher forcing: the mean over the candidate's tokens
+    // of ln p(token | prefix, preceding candidate tokens). Trailing pad_id
+    // tokens are padding and are not scored, so candidates may be passed
+    // padded to a common length.
+    pub fn score_continuations(&self, prefix: &[u32], candidates: &[Vec<u32>]) -> Result<Vec<f32>, Box<dyn Error>> {
+        self.score_continuations_with_state(prefix, candidates, &mut decoder::DecoderState::new())
+    }
+
+    // Like `score_continuations`, counting decoder work in `state`. The
+    // prefix is retrieved for and decoded once; each candidate then decodes
+    // only its own tokens against the prefix's cached keys and values, and
+    // retrieves only for chunks it completes.
+    pub fn score_continuations_with_state(
+        &self,
+        prefix: &[u32],
+        candidates: &[Vec<u32>],
+        state: &mut decoder::DecoderState,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        if prefix.is_empty() {
+            return Err(util::invalid_argument_error("Scoring needs a non-empty prefix"));
+        }
+        let candidates: Vec<&[u32]> = candidates.iter().map(|c| strip_padding(c, self.pad_id)).collect();
+        for (i, candidate) in candidates.iter().enumerate() {
+            if candidate.is_empty() {
+                return Err(util::invalid_argument_error(&format!(
+                    "Candidate {} has no tokens besides padding",
+                    i
+                )));
+            }
+            // The last candidate token is only predicted, never fed back.
+            let len = prefix.len() + candidate.len() - 1;
+            if len > self.seq_len as usize {
+                return Err(util::invalid_argument_error(&format!(
+                    "Prefix and candidate {} need {} positions, max_seq_len is {}",
+                    i, len, self.seq_len
+                )));
+            }
+        }
+
+        let chunk_size = self.chunk_size as usize;
+        let prefix = vocab_remap::remap_tokens(self.input_remap.as_ref(), prefix)?;
+        let prefix_chunks = prefix.len() / chunk_size;
+        let prefix_neighbors = match &self.retriever {
+            Some(retriever) => Some(retriever.retrieve_chunks(&prefix, chunk_size)?),
+            None => None,
+        };
+        let prefix_retrieved = match &prefix_neighbors {
+            Some(neighbors) => Some(self.embed_retrieved(neighbors.clone())?),
+            None => None,
+        };
+        let (decoded, cache) = self.decoder.forward_prefix(
+            &self.embed_at(&prefix, 0)?,
+            &self.encoder,
+            prefix_retrieved.as_ref().map(|r| &r.rows),
+            prefix_retrieved.as_ref().map(|r| r.mask.as_slice()),
+            state,
+        )?;
+        let last = decoded.rows(decoded.nrows() - 1, 1).into_owned();
+        let prefix_logits = util::matrix_multiply(&last, &self.to_logits.transpose())?;
+
+        let mut scores = Vec::with_capacity(candidates.len());
+        for candidate in candidates {
+            let candidate = vocab_remap::remap_tokens(self.input_remap.as_ref(), candidate)?;
+            let mut total = token_log_prob(&prefix_logits, 0, candidate[0])?;
+            let inputs = &candidate[..candidate.len() - 1];
+            if !inputs.is_empty() {
+                let extended;
+                let retrieved = match (&self.retriever, &prefix_neighbors) {
+                    (Some(retriever), Some(prefix_neighbors))
+                        if (prefix.len() + inputs.len()) / chunk_size > prefix_chunks =>
+                    {
+                        let seq: Vec<u32> = prefix.iter().chain(inputs).copied().collect();
+                        let new_chunks = retriever.retrieve_chunks(&seq[prefix_chunks * chunk_size..], chunk_size)?;
+                        // Embedded together so every chunk is padded to the
+                        // same neighbor count.
+                        let chunks = prefix_neighbors.iter().cloned().chain(new_chunks).collect();
+                        extended = self.embed_retrieved(chunks)?;
+                        Some(&extended)
+                    }
+                    _ => prefix_retrieved.as_ref(),
+                };
+                let decoded = self.decoder.forward_continuation(
+                    &self.embed_at(inputs, prefix.len())?,
+                    &self.encoder,
+                    retrieved.map(|r| &r.rows),
+                    retrieved.map(|r| r.mask.as_slice()),
+                    &cache,
+                    state,
+                )?;
+                let logits = util::matrix_multiply(&decoded, &self.to_logits.transpose())?;
+                for (row, &token) in candidate[1..].iter().enumerate() {
+                    total += token_log_prob(&logits, row, token)?;
                 }
-                retrieved_data.push(chunk_data);
             }
-            DMatrix::from_fn(
-                retrieved_data.len(),
-                retrieved_data[0].len() * retrieved_data[0][0].ncols(),
-                |i, j| retrieved_data[i][j / retrieved_data[0][0].ncols()][0][j % retrieved_data[0][0].ncols()],
-            )
-        } else {
-            return Err(utils::invalid_argument_error("No retrieved data or retriever provided"));
+            scores.push((total / candidate.len() as f64) as f32);
+        }
+        Ok(scores)
+    }
+
+    // Greedy decoding. Each step runs a full forward pass over the current
+    // window, retrieving afresh when a retriever is attached, so nothing
+    // cached for evicted chunks survives a window shift.
+    pub fn generate(&self, prompt: &[u32], options: &GenerateOptions) -> Result<GenerateOutput, Box<dyn Error>> {
+        if prompt.is_empty() {
+            return Err(util::invalid_argument_error("Generation needs a non-empty prompt"));
+        }
+        let max_seq_len = self.seq_len as usize;
+        let chunk_size = self.chunk_size as usize;
+        if let ContextPolicy::SlidingWindow { window } = options.context_policy {
+            if window < chunk_size || window > max_seq_len {
+                return Err(util::invalid_argument_error(&format!(
+                    "Sliding window {} must be between chunk_size {} and max_seq_len {}",
+                    window, chunk_size, max_seq_len
+                )));
+            }
+        }
+        let mut output = GenerateOutput {
+            tokens: prompt.to_vec(),
+            window_starts: Vec::with_capacity(options.max_new_tokens),
         };
+        for _ in 0..options.max_new_tokens {
+            let len = output.tokens.len();
+            let start = match options.context_policy {
+                ContextPolicy::Error if len > max_seq_len => {
+                    return Err(util::invalid_argument_error(&format!(
+                        "Context of {} tokens exceeds max_seq_len {}; use ContextPolicy::SlidingWindow to continue",
+                        len, max_seq_len
+                    )));
+                }
+                ContextPolicy::Error => 0,
+                ContextPolicy::SlidingWindow { window } => len.saturating_sub(window).div_ceil(chunk_size) * chunk_size,
+            };
+            let logits = self.forward(&output.tokens[start..], None)?;
+            let last = logits.row(logits.nrows() - 1);
+            let next = last
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.is_finite())
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(token, _)| token as u32)
+                .ok_or_else(|| util::failed_precondition_error("Decoder produced no finite logits"))?;
+            output.window_starts.push(start);
+            output.tokens.push(next);
+            if options.eos_id == Some(next) {
+                break;
+            }
+        }
+        Ok(output)
+    }
+}
 
-        let embed = utils::matrix_multiply(&embed, &self.to_decoder_model_dim.transpose())?;
-        let decoded = self.decoder.forward(&embed, &self.encoder, Some(&retrieved))?;
-        utils::matrix_multiply(&decoded, &self.to_logits.transpose())
+fn strip_padding(tokens: &[u32], pad_id: u32) -> &[u32] {
+    let end = tokens.iter().rposition(|&t| t != pad_id).map_or(0, |i| i + 1);
+    &tokens[..end]
+}
+
+// ln softmax(logits[row])[token], accumulated in f64. Non-finite logits
+// carry no probability mass.
+fn token_log_prob(logits: &DMatrix<f32>, row: usize, token: u32) -> Result<f64, Box<dyn Error>> {
+    let row = logits.row(row);
+    let Some(&logit) = row.iter().nth(token as usize) else {
+        return Err(util::invalid_argument_error(&format!(
+            "Token {} is outside the {}-token vocabulary",
+            token,
+            row.len()
+        )));
+    };
+    let max = row.iter().copied().filter(|v| v.is_finite()).fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return Err(util::failed_precondition_error("Decoder produced no finite logits"));
     }
-}
\ No newline at end of file
+    if !logit.is_finite() {
+        return Ok(f64::NEG_INFINITY);
+    }
+    let sum: f64 = row
+        .iter()
+        .filter(|v| v.is_finite())
+        .map(|&v| (v as f64 - max as f64).exp())
+        .sum();
+    Ok(logit as f64 - max as f64 - sum.ln())
+}
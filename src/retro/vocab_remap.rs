@@ -0,0 +1,193 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token id remapping between the tokenizer a model was trained with and
+//! the one used to tokenize the retrieval corpus.
+
+use nalgebra::DMatrix;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+
+use super::chunk_store::ChunkStore;
+use super::embeddings::TokenEmbedding;
+use crate::util;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnmappedPolicy {
+    Error,
+    // Replace unmapped ids with this (new-vocabulary) id.
+    Unk(u32),
+}
+
+// How embedding rows for new ids that no old id maps to are filled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnmappedRowInit {
+    Zeros,
+    MeanOfMapped,
+    Normal { std: f32, seed: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct VocabRemap {
+    old_to_new: HashMap<u32, u32>,
+    policy: UnmappedPolicy,
+}
+
+impl VocabRemap {
+    // `pairs` are (old, new). An old id may appear once, and no two old ids
+    // may share a new id, so the remap is invertible.
+    pub fn from_pairs(pairs: &[(u32, u32)]) -> Result<Self, Box<dyn Error>> {
+        let mut old_to_new = HashMap::with_capacity(pairs.len());
+        let mut new_to_old = HashMap::with_capacity(pairs.len());
+        for &(old, new) in pairs {
+            if let Some(previous) = old_to_new.insert(old, new) {
+                return Err(util::invalid_argument_error(&format!(
+                    "Old token {} is mapped to both {} and {}",
+                    old, previous, new
+                )));
+            }
+            if let Some(previous) = new_to_old.insert(new, old) {
+                return Err(util::invalid_argument_error(&format!(
+                    "New token {} is the target of both {} and {}",
+                    new, previous, old
+                )));
+            }
+        }
+        Ok(VocabRemap {
+            old_to_new,
+            policy: UnmappedPolicy::Error,
+        })
+    }
+
+    pub fn with_unmapped_policy(mut self, policy: UnmappedPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.old_to_new.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old_to_new.is_empty()
+    }
+
+    pub fn map_token(&self, old: u32) -> Result<u32, Box<dyn Error>> {
+        match (self.old_to_new.get(&old), self.policy) {
+            (Some(&new), _) => Ok(new),
+            (None, UnmappedPolicy::Unk(unk)) => Ok(unk),
+            (None, UnmappedPolicy::Error) => Err(util::invalid_argument_error(&format!(
+                "Token {} has no mapping in the vocabulary remap",
+                old
+            ))),
+        }
+    }
+
+    pub fn apply(&self, tokens: &[u32]) -> Result<Vec<u32>, Box<dyn Error>> {
+        tokens.iter().map(|&t| self.map_token(t)).collect()
+    }
+
+    // Moves each old id's row to its new id in a `new_vocab_size`-row
+    // matrix. Old ids outside `embedding` or new ids outside the new
+    // vocabulary are errors; rows no old id maps to are filled per `init`.
+    pub fn remap_embedding(
+        &self,
+        embedding: &TokenEmbedding,
+        new_vocab_size: usize,
+        init: UnmappedRowInit,
+    ) -> Result<TokenEmbedding, Box<dyn Error>> {
+        let old = embedding.weights();
+        let dim = old.ncols();
+        let mut weights = DMatrix::zeros(new_vocab_size, dim);
+        let mut filled = vec![false; new_vocab_size];
+        for (&old_id, &new_id) in &self.old_to_new {
+            if old_id as usize >= old.nrows() || new_id as usize >= new_vocab_size {
+                return Err(util::invalid_argument_error(&format!(
+                    "Remap pair ({}, {}) is outside vocabularies of size {} and {}",
+                    old_id,
+                    new_id,
+                    old.nrows(),
+                    new_vocab_size
+                )));
+            }
+            weights.row_mut(new_id as usize).copy_from(&old.row(old_id as usize));
+            filled[new_id as usize] = true;
+        }
+        match init {
+            UnmappedRowInit::Zeros => {}
+            UnmappedRowInit::MeanOfMapped => {
+                if !self.old_to_new.is_empty() {
+                    let mut mean = DMatrix::zeros(1, dim);
+                    for (row, _) in filled.iter().enumerate().filter(|(_, &f)| f) {
+                        mean += weights.row(row);
+                    }
+                    mean /= self.old_to_new.len() as f32;
+                    for (row, _) in filled.iter().enumerate().filter(|(_, &f)| !f) {
+                        weights.row_mut(row).copy_from(&mean);
+                    }
+                }
+            }
+            UnmappedRowInit::Normal { std, seed } => {
+                let mut rng = util::SplitMix64::new(seed);
+                for (row, _) in filled.iter().enumerate().filter(|(_, &f)| !f) {
+                    for col in 0..dim {
+                        // Box-Muller; 1 - u keeps the logarithm finite.
+                        let u1 = 1.0 - rng.next_f32();
+                        let u2 = rng.next_f32();
+                        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos();
+                        weights[(row, col)] = std * z;
+                    }
+                }
+            }
+        }
+        Ok(TokenEmbedding::from_weights(weights))
+    }
+}
+
+// Read-only view of a chunk store in the remapped vocabulary. Chunks are
+// remapped as they are read, so the corpus itself is never copied.
+pub struct RemappedChunkStore<'a> {
+    store: &'a dyn ChunkStore,
+    remap: &'a VocabRemap,
+}
+
+impl<'a> RemappedChunkStore<'a> {
+    pub fn new(store: &'a dyn ChunkStore, remap: &'a VocabRemap) -> Self {
+        RemappedChunkStore { store, remap }
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.store.num_chunks()
+    }
+
+    pub fn get(&self, chunk_id: usize) -> Result<Vec<u32>, Box<dyn Error>> {
+        self.remap.apply(&self.store.get(chunk_id)?.to_vec())
+    }
+
+    pub fn continuation(&self, chunk_id: usize) -> Result<Option<Vec<u32>>, Box<dyn Error>> {
+        match self.store.continuation(chunk_id)? {
+            Some(tokens) => Ok(Some(self.remap.apply(&tokens.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Applies an optional remap without copying when there is none.
+pub(crate) fn remap_tokens<'a>(remap: Option<&VocabRemap>, tokens: &'a [u32]) -> Result<Cow<'a, [u32]>, Box<dyn Error>> {
+    match remap {
+        Some(remap) => Ok(Cow::Owned(remap.apply(tokens)?)),
+        None => Ok(Cow::Borrowed(tokens)),
+    }
+}
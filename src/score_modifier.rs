@@ -0,0 +1,72 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rank-time score modifiers for business-logic boosting.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// Applied to surviving candidates after rescoring and before the final
+// top-k cut. Called at most once per candidate per search; returning
+// f32::INFINITY drops the candidate. `index` is the storage row and is only
+// stable until the next compaction.
+pub trait ScoreModifier: Send + Sync + Debug {
+    fn modify(&self, index: usize, docid: usize, raw_distance: f32) -> f32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoostMode {
+    // distance + weight * field
+    Add,
+    // distance * field
+    Multiply,
+}
+
+// Reads a little-endian f32 at `offset` of each document's payload. Documents
+// without a payload, or whose payload is too short, keep their distance.
+#[derive(Clone, Debug)]
+pub struct PayloadFieldBoost {
+    payloads: Arc<HashMap<usize, Vec<u8>>>,
+    offset: usize,
+    mode: BoostMode,
+    weight: f32,
+}
+
+impl PayloadFieldBoost {
+    pub fn new(payloads: Arc<HashMap<usize, Vec<u8>>>, offset: usize, mode: BoostMode, weight: f32) -> Self {
+        PayloadFieldBoost {
+            payloads,
+            offset,
+            mode,
+            weight,
+        }
+    }
+
+    fn field(&self, docid: usize) -> Option<f32> {
+        let payload = self.payloads.get(&docid)?;
+        let bytes = payload.get(self.offset..self.offset.checked_add(4)?)?;
+        Some(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl ScoreModifier for PayloadFieldBoost {
+    fn modify(&self, _index: usize, docid: usize, raw_distance: f32) -> f32 {
+        match (self.field(docid), self.mode) {
+            (Some(field), BoostMode::Add) => raw_distance + self.weight * field,
+            (Some(field), BoostMode::Multiply) => raw_distance * field,
+            (None, _) => raw_distance,
+        }
+    }
+}
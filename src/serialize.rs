@@ -13,37 +13,81 @@
 // limitations under the License.
 
 //! Serialization utilities for converting between integers, floats, and binary keys.
+//!
+//! Every key is fixed width and big-endian, and comparing two keys of the
+//! same type bytewise gives the same order as comparing the values. Signed
+//! integers flip the sign bit; floats use the usual sign-magnitude flip.
+//!
+//! Floats: -0.0 encodes as +0.0, since the two compare equal. Every NaN
+//! encodes as the canonical quiet NaN (`f32::NAN` / `f64::NAN`), which sorts
+//! after +infinity, so float keys are totally ordered. The lenient
+//! `key_to_float` decodes any other NaN pattern to the canonical NaN;
+//! `try_key_to_float` rejects it.
+//!
+//! The plain conversions never panic. `try_` variants report lossy inputs
+//! (out-of-range widths, non-canonical keys) as errors and `saturating_`
+//! variants clamp them.
 
 use super::ScannError;
 use std::error::Error;
 
-fn uint_from_ieee754<FloatType, UintType>(f: FloatType) -> UintType
-where
-    FloatType: Into<u32> + Copy,
-    UintType: From<u32> + std::ops::Not<Output = UintType> + std::ops::BitAnd<Output = UintType>,
-{
-    let n: u32 = unsafe { std::mem::transmute(f) };
-    let sign_bit = !(!0u32 >> 1);
-    if (n & sign_bit) == 0 {
-        UintType::from(n + sign_bit)
+const SIGN_32: u32 = 1 << 31;
+const SIGN_64: u64 = 1 << 63;
+
+fn length_error(expected: usize, got: usize) -> Box<dyn Error> {
+    Box::new(ScannError {
+        message: format!("Invalid key length: expected {}, got {}", expected, got),
+    })
+}
+
+fn fixed<const N: usize>(key: &[u8]) -> Result<[u8; N], Box<dyn Error>> {
+    key.try_into().map_err(|_| length_error(N, key.len()))
+}
+
+fn canonical_f32(x: f32) -> f32 {
+    if x.is_nan() {
+        f32::NAN
+    } else if x == 0.0 {
+        0.0
     } else {
-        UintType::from(0 - n)
+        x
     }
 }
 
-fn ieee754_from_uint<FloatType, UintType>(n: UintType) -> FloatType
-where
-    UintType: Into<u32> + std::ops::Not<Output = UintType> + std::ops::BitAnd<Output = UintType>,
-    FloatType: From<u32>,
-{
-    let n: u32 = n.into();
-    let sign_bit = !(!0u32 >> 1);
-    let adjusted = if n & sign_bit != 0 {
-        n - sign_bit
+fn canonical_f64(x: f64) -> f64 {
+    if x.is_nan() {
+        f64::NAN
+    } else if x == 0.0 {
+        0.0
     } else {
-        0 - n
-    };
-    unsafe { std::mem::transmute(adjusted) }
+        x
+    }
+}
+
+fn uint_from_ieee754_f32(x: f32) -> u32 {
+    let n = canonical_f32(x).to_bits();
+    if n & SIGN_32 == 0 {
+        n | SIGN_32
+    } else {
+        !n
+    }
+}
+
+fn ieee754_f32_from_uint(n: u32) -> f32 {
+    f32::from_bits(if n & SIGN_32 != 0 { n & !SIGN_32 } else { !n })
+}
+
+fn uint_from_ieee754_f64(x: f64) -> u64 {
+    let n = canonical_f64(x).to_bits();
+    if n & SIGN_64 == 0 {
+        n | SIGN_64
+    } else {
+        !n
+    }
+}
+
+fn ieee754_f64_from_uint(n: u64) -> f64 {
+    f64::from_bits(if n & SIGN_64 != 0 { n & !SIGN_64 } else { !n })
 }
 
 fn key_from_uint32(u32: u32, key: &mut Vec<u8>) {
@@ -65,7 +109,7 @@ pub fn uint32_to_key(u32: u32) -> Vec<u8> {
 
 #[inline]
 pub fn int32_to_key(i32: i32) -> Vec<u8> {
-    uint32_to_key(i32 as u32)
+    uint32_to_key(i32 as u32 ^ SIGN_32)
 }
 
 #[inline]
@@ -75,36 +119,58 @@ pub fn uint64_to_key(u64: u64) -> Vec<u8> {
     key
 }
 
+#[inline]
+pub fn int64_to_key(i64: i64) -> Vec<u8> {
+    uint64_to_key(i64 as u64 ^ SIGN_64)
+}
+
+// For counts and lengths held in wider types.
+pub fn try_uint32_to_key(n: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let n = u32::try_from(n).map_err(|_| {
+        Box::new(ScannError {
+            message: format!("Value {} does not fit a 32-bit key", n),
+        }) as Box<dyn Error>
+    })?;
+    Ok(uint32_to_key(n))
+}
+
+pub fn saturating_uint32_to_key(n: u64) -> Vec<u8> {
+    uint32_to_key(n.min(u32::MAX as u64) as u32)
+}
+
+pub fn try_int32_to_key(n: i64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let n = i32::try_from(n).map_err(|_| {
+        Box::new(ScannError {
+            message: format!("Value {} does not fit a signed 32-bit key", n),
+        }) as Box<dyn Error>
+    })?;
+    Ok(int32_to_key(n))
+}
+
+pub fn saturating_int32_to_key(n: i64) -> Vec<u8> {
+    int32_to_key(n.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+}
+
 pub fn key_to_uint32(key: &[u8]) -> Result<u32, Box<dyn Error>> {
-    if key.len() != std::mem::size_of::<u32>() {
-        return Err(Box::new(ScannError {
-            message: format!("Invalid key length: expected {}, got {}", std::mem::size_of::<u32>(), key.len()),
-        }));
-    }
-    let mut bytes = [0u8; 4];
-    bytes.copy_from_slice(key);
-    Ok(u32::from_be_bytes(bytes))
+    Ok(u32::from_be_bytes(fixed(key)?))
 }
 
 #[inline]
 pub fn key_to_int32(key: &[u8]) -> Result<i32, Box<dyn Error>> {
-    key_to_uint32(key).map(|v| v as i32)
+    key_to_uint32(key).map(|v| (v ^ SIGN_32) as i32)
 }
 
 pub fn key_to_uint64(key: &[u8]) -> Result<u64, Box<dyn Error>> {
-    if key.len() != std::mem::size_of::<u64>() {
-        return Err(Box::new(ScannError {
-            message: format!("Invalid key length: expected {}, got {}", std::mem::size_of::<u64>(), key.len()),
-        }));
-    }
-    let mut bytes = [0u8; 8];
-    bytes.copy_from_slice(key);
-    Ok(u64::from_be_bytes(bytes))
+    Ok(u64::from_be_bytes(fixed(key)?))
+}
+
+#[inline]
+pub fn key_to_int64(key: &[u8]) -> Result<i64, Box<dyn Error>> {
+    key_to_uint64(key).map(|v| (v ^ SIGN_64) as i64)
 }
 
 pub fn key_from_float(x: f32, key: &mut Vec<u8>) {
-    let n = uint_from_ieee754::<f32, u32>(x);
-    key_from_uint32(n, key);
+    key_from_uint32(uint_from_ieee754_f32(x), key);
 }
 
 #[inline]
@@ -115,6 +181,153 @@ pub fn float_to_key(x: f32) -> Vec<u8> {
 }
 
 pub fn key_to_float(key: &[u8]) -> Result<f32, Box<dyn Error>> {
+    Ok(canonical_f32(ieee754_f32_from_uint(key_to_uint32(key)?)))
+}
+
+// Rejects keys this module would never produce: non-canonical NaNs and
+// negative zero.
+pub fn try_key_to_float(key: &[u8]) -> Result<f32, Box<dyn Error>> {
     let n = key_to_uint32(key)?;
-    Ok(ieee754_from_uint::<f32, u32>(n))
-}
\ No newline at end of file
+    let x = ieee754_f32_from_uint(n);
+    if uint_from_ieee754_f32(x) != n {
+        return Err(Box::new(ScannError {
+            message: format!("Non-canonical float key {:08x}", n),
+        }));
+    }
+    Ok(x)
+}
+
+pub fn key_from_double(x: f64, key: &mut Vec<u8>) {
+    key_from_uint64(uint_from_ieee754_f64(x), key);
+}
+
+#[inline]
+pub fn double_to_key(x: f64) -> Vec<u8> {
+    let mut key = Vec::new();
+    key_from_double(x, &mut key);
+    key
+}
+
+pub fn key_to_double(key: &[u8]) -> Result<f64, Box<dyn Error>> {
+    Ok(canonical_f64(ieee754_f64_from_uint(key_to_uint64(key)?)))
+}
+
+pub fn try_key_to_double(key: &[u8]) -> Result<f64, Box<dyn Error>> {
+    let n = key_to_uint64(key)?;
+    let x = ieee754_f64_from_uint(n);
+    if uint_from_ieee754_f64(x) != n {
+        return Err(Box::new(ScannError {
+            message: format!("Non-canonical double key {:016x}", n),
+        }));
+    }
+    Ok(x)
+}
+
+// Concatenates fixed-width keys. Because every part has a fixed width,
+// bytewise order of two composite keys with the same layout is the
+// lexicographic order of their parts.
+#[derive(Clone, Debug, Default)]
+pub struct KeyBuilder {
+    key: Vec<u8>,
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        KeyBuilder::default()
+    }
+
+    pub fn push_uint32(mut self, n: u32) -> Self {
+        self.key.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    pub fn push_int32(mut self, n: i32) -> Self {
+        self.key.extend(int32_to_key(n));
+        self
+    }
+
+    pub fn push_uint64(mut self, n: u64) -> Self {
+        self.key.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    pub fn push_int64(mut self, n: i64) -> Self {
+        self.key.extend(int64_to_key(n));
+        self
+    }
+
+    pub fn push_float(mut self, x: f32) -> Self {
+        self.key.extend(float_to_key(x));
+        self
+    }
+
+    pub fn push_double(mut self, x: f64) -> Self {
+        self.key.extend(double_to_key(x));
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.key
+    }
+}
+
+// Reads a composite key back in the order it was built. Decoding is strict:
+// float parts must be canonical and `finish` rejects trailing bytes.
+pub struct KeyReader<'a> {
+    key: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> KeyReader<'a> {
+    pub fn new(key: &'a [u8]) -> Self {
+        KeyReader { key, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.key.len() - self.pos < n {
+            return Err(Box::new(ScannError {
+                message: format!(
+                    "Composite key truncated: need {} bytes at offset {}, have {}",
+                    n,
+                    self.pos,
+                    self.key.len() - self.pos
+                ),
+            }));
+        }
+        self.pos += n;
+        Ok(&self.key[self.pos - n..self.pos])
+    }
+
+    pub fn read_uint32(&mut self) -> Result<u32, Box<dyn Error>> {
+        key_to_uint32(self.take(4)?)
+    }
+
+    pub fn read_int32(&mut self) -> Result<i32, Box<dyn Error>> {
+        key_to_int32(self.take(4)?)
+    }
+
+    pub fn read_uint64(&mut self) -> Result<u64, Box<dyn Error>> {
+        key_to_uint64(self.take(8)?)
+    }
+
+    pub fn read_int64(&mut self) -> Result<i64, Box<dyn Error>> {
+        key_to_int64(self.take(8)?)
+    }
+
+    pub fn read_float(&mut self) -> Result<f32, Box<dyn Error>> {
+        try_key_to_float(self.take(4)?)
+    }
+
+    pub fn read_double(&mut self) -> Result<f64, Box<dyn Error>> {
+        try_key_to_double(self.take(8)?)
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.pos != self.key.len() {
+            return Err(Box::new(ScannError {
+                message: format!("Composite key has {} trailing bytes", self.key.len() - self.pos),
+            }));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! AVX2/FMA and NEON kernels for the dense f32 distances, enabled by the
+//! `simd` feature.
+//!
+//! Each function returns None when the running CPU lacks the instructions
+//! and the caller falls back to its scalar loop. Inputs of different
+//! lengths are compared over their common prefix, like the scalar code.
+//! Results differ from the scalar sums only by rounding, but every kernel
+//! accumulates the same way, so e.g. `dot(a, a)` equals the first norm of
+//! `cosine_parts(a, b)` bit for bit.
+
+#[cfg(target_arch = "x86_64")]
+fn avx2_available() -> bool {
+    // std caches the CPUID probe.
+    is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+}
+
+// Whether the kernels below run on this CPU instead of returning None.
+pub fn available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return avx2_available();
+    #[cfg(target_arch = "aarch64")]
+    return true;
+    #[allow(unreachable_code)]
+    false
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> Option<f32> {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        return Some(unsafe { x86::dot(a, b) });
+    }
+    #[cfg(target_arch = "aarch64")]
+    return Some(unsafe { neon::dot(a, b) });
+    #[allow(unreachable_code)]
+    None
+}
+
+pub fn squared_l2(a: &[f32], b: &[f32]) -> Option<f32> {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        return Some(unsafe { x86::squared_l2(a, b) });
+    }
+    #[cfg(target_arch = "aarch64")]
+    return Some(unsafe { neon::squared_l2(a, b) });
+    #[allow(unreachable_code)]
+    None
+}
+
+// (dot(a, b), |a|^2, |b|^2) in one pass.
+pub fn cosine_parts(a: &[f32], b: &[f32]) -> Option<(f32, f32, f32)> {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        return Some(unsafe { x86::cosine_parts(a, b) });
+    }
+    #[cfg(target_arch = "aarch64")]
+    return Some(unsafe { neon::cosine_parts(a, b) });
+    #[allow(unreachable_code)]
+    None
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn horizontal_sum(v: __m256) -> f32 {
+        let mut lanes = [0.0f32; LANES];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), v);
+        lanes.iter().sum()
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..body).step_by(LANES) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            acc = _mm256_fmadd_ps(x, y, acc);
+        }
+        let mut sum = horizontal_sum(acc);
+        for i in body..n {
+            sum += a[i] * b[i];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..body).step_by(LANES) {
+            let d = _mm256_sub_ps(_mm256_loadu_ps(a.as_ptr().add(i)), _mm256_loadu_ps(b.as_ptr().add(i)));
+            acc = _mm256_fmadd_ps(d, d, acc);
+        }
+        let mut sum = horizontal_sum(acc);
+        for i in body..n {
+            let d = a[i] - b[i];
+            sum += d * d;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn cosine_parts(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let (mut dot, mut norm_a, mut norm_b) = (_mm256_setzero_ps(), _mm256_setzero_ps(), _mm256_setzero_ps());
+        for i in (0..body).step_by(LANES) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            dot = _mm256_fmadd_ps(x, y, dot);
+            norm_a = _mm256_fmadd_ps(x, x, norm_a);
+            norm_b = _mm256_fmadd_ps(y, y, norm_b);
+        }
+        let (mut dot, mut norm_a, mut norm_b) = (horizontal_sum(dot), horizontal_sum(norm_a), horizontal_sum(norm_b));
+        for i in body..n {
+            dot += a[i] * b[i];
+            norm_a += a[i] * a[i];
+            norm_b += b[i] * b[i];
+        }
+        (dot, norm_a, norm_b)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    const LANES: usize = 4;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in (0..body).step_by(LANES) {
+            acc = vfmaq_f32(acc, vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+        }
+        let mut sum = vaddvq_f32(acc);
+        for i in body..n {
+            sum += a[i] * b[i];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in (0..body).step_by(LANES) {
+            let d = vsubq_f32(vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+            acc = vfmaq_f32(acc, d, d);
+        }
+        let mut sum = vaddvq_f32(acc);
+        for i in body..n {
+            let d = a[i] - b[i];
+            sum += d * d;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn cosine_parts(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len().min(b.len());
+        let body = n - n % LANES;
+        let (mut dot, mut norm_a, mut norm_b) = (vdupq_n_f32(0.0), vdupq_n_f32(0.0), vdupq_n_f32(0.0));
+        for i in (0..body).step_by(LANES) {
+            let x = vld1q_f32(a.as_ptr().add(i));
+            let y = vld1q_f32(b.as_ptr().add(i));
+            dot = vfmaq_f32(dot, x, y);
+            norm_a = vfmaq_f32(norm_a, x, x);
+            norm_b = vfmaq_f32(norm_b, y, y);
+        }
+        let (mut dot, mut norm_a, mut norm_b) = (vaddvq_f32(dot), vaddvq_f32(norm_a), vaddvq_f32(norm_b));
+        for i in body..n {
+            dot += a[i] * b[i];
+            norm_a += a[i] * a[i];
+            norm_b += b[i] * b[i];
+        }
+        (dot, norm_a, norm_b)
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable handle over a retriever that can be replaced while serving.
+
+use super::{evaluation, retrieval, util};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Comparison of shadow results against the serving index, accumulated since
+// shadowing started.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowAgreement {
+    pub queries: u64,
+    // Queries where both indexes returned the same docids in the same order.
+    pub exact_matches: u64,
+    // Mean fraction of serving results also returned by the shadow.
+    pub mean_overlap: f64,
+    // Shadow searches that failed; these never affect the served response.
+    pub errors: u64,
+}
+
+struct Shadow {
+    candidate: Arc<retrieval::ScannRetriever>,
+    fraction: f32,
+}
+
+// Searches take a reference to the current retriever up front, so a search
+// in flight when `swap` runs completes entirely on the old index and the old
+// index is freed once the last such search drops it.
+pub struct SwappableRetriever {
+    current: RwLock<Arc<retrieval::ScannRetriever>>,
+    shadow: RwLock<Option<Shadow>>,
+    agreement: Mutex<ShadowAgreement>,
+    queries: AtomicU64,
+}
+
+impl SwappableRetriever {
+    pub fn new(retriever: Arc<retrieval::ScannRetriever>) -> Self {
+        SwappableRetriever {
+            current: RwLock::new(retriever),
+            shadow: RwLock::new(None),
+            agreement: Mutex::new(ShadowAgreement::default()),
+            queries: AtomicU64::new(0),
+        }
+    }
+
+    pub fn current(&self) -> Arc<retrieval::ScannRetriever> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Installs `retriever` for all subsequent searches and returns the one
+    // it replaced. Any shadow candidate is dropped.
+    pub fn swap(&self, retriever: Arc<retrieval::ScannRetriever>) -> Arc<retrieval::ScannRetriever> {
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), retriever);
+        self.stop_shadow();
+        old
+    }
+
+    // Sends `fraction` of queries to `candidate` as well and compares its
+    // results with the served ones. Resets the agreement counters.
+    pub fn start_shadow(&self, candidate: Arc<retrieval::ScannRetriever>, fraction: f32) -> Result<(), Box<dyn Error>> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(util::invalid_argument_error(&format!(
+                "Shadow fraction must be in [0, 1], got {}",
+                fraction
+            )));
+        }
+        let mut shadow = self.shadow.write().unwrap();
+        *self.agreement.lock().unwrap() = ShadowAgreement::default();
+        *shadow = Some(Shadow { candidate, fraction });
+        Ok(())
+    }
+
+    pub fn stop_shadow(&self) -> Option<Arc<retrieval::ScannRetriever>> {
+        self.shadow.write().unwrap().take().map(|shadow| shadow.candidate)
+    }
+
+    // Promotes the shadow candidate to serving and returns the old index.
+    pub fn commit_shadow(&self) -> Result<Arc<retrieval::ScannRetriever>, Box<dyn Error>> {
+        let Some(candidate) = self.stop_shadow() else {
+            return Err(util::failed_precondition_error("No shadow candidate to commit"));
+        };
+        Ok(self.swap(candidate))
+    }
+
+    pub fn shadow_agreement(&self) -> ShadowAgreement {
+        self.agreement.lock().unwrap().clone()
+    }
+
+    pub fn search(&self, query: &util::DatapointPtr<f32>) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+        Ok(self.search_with_options(query, &retrieval::SearchOptions::default())?.0)
+    }
+
+    pub fn search_with_options(
+        &self,
+        query: &util::DatapointPtr<f32>,
+        options: &retrieval::SearchOptions,
+    ) -> Result<(retrieval::Neighbors, retrieval::SearchStats), Box<dyn Error>> {
+        let retriever = self.current();
+        let served = retriever.search_with_options(query, options)?;
+        let sequence = self.queries.fetch_add(1, Ordering::Relaxed);
+        let candidate = match self.shadow.read().unwrap().as_ref() {
+            Some(shadow) if util::SplitMix64::new(sequence).next_f32() < shadow.fraction => {
+                Some(shadow.candidate.clone())
+            }
+            _ => None,
+        };
+        if let Some(candidate) = candidate {
+            self.record_shadow(&served.0, candidate.search_with_options(query, options));
+        }
+        Ok(served)
+    }
+
+    fn record_shadow(
+        &self,
+        served: &[(usize, f32)],
+        shadow: Result<(retrieval::Neighbors, retrieval::SearchStats), Box<dyn Error>>,
+    ) {
+        let mut agreement = self.agreement.lock().unwrap();
+        let Ok((shadow, _)) = shadow else {
+            agreement.errors += 1;
+            return;
+        };
+        let overlap = evaluation::recall(&shadow, served) as f64;
+        agreement.queries += 1;
+        agreement.mean_overlap += (overlap - agreement.mean_overlap) / agreement.queries as f64;
+        if served.iter().map(|r| r.0).eq(shadow.iter().map(|r| r.0)) {
+            agreement.exact_matches += 1;
+        }
+    }
+
+    pub fn num_active(&self) -> usize {
+        self.current().num_active()
+    }
+
+    pub fn metrics(&self) -> retrieval::RetrieverMetrics {
+        self.current().metrics()
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Seeded synthetic datasets with known structure.
+//!
+//! Every generator draws from `SplitMix64` and uses only +, *, / and sqrt
+//! (all correctly rounded in IEEE 754), so the same spec yields the same
+//! bits on every platform. Gaussian noise is the Irwin-Hall approximation
+//! (sum of 12 uniforms minus 6) for that reason: it avoids ln/cos, whose
+//! results vary between libm implementations.
+
+use crate::{evaluation, util};
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+pub struct GeneratedDataset {
+    pub dataset: util::DenseDataset<f32>,
+    // Generating cluster per row; empty when the generator has none.
+    pub labels: Vec<usize>,
+    // (original, near duplicate) row pairs planted by the generator.
+    pub planted_pairs: Vec<(usize, usize)>,
+    // Rows span this subspace before noise; empty when not low rank.
+    pub latent_basis: Vec<Vec<f32>>,
+}
+
+impl GeneratedDataset {
+    fn plain(rows: Vec<Vec<f32>>, dimensionality: usize) -> Self {
+        GeneratedDataset {
+            dataset: util::DenseDataset::new(rows, dimensionality),
+            labels: Vec::new(),
+            planted_pairs: Vec::new(),
+            latent_basis: Vec::new(),
+        }
+    }
+}
+
+fn standard_normal(rng: &mut util::SplitMix64) -> f32 {
+    rng.next_normal()
+}
+
+fn normal_vector(rng: &mut util::SplitMix64, dimensionality: usize, std: f32) -> Vec<f32> {
+    (0..dimensionality).map(|_| standard_normal(rng) * std).collect()
+}
+
+fn check_shape(num_points: usize, dimensionality: usize) -> Result<(), Box<dyn Error>> {
+    if num_points == 0 || dimensionality == 0 {
+        return Err(util::invalid_argument_error(&format!(
+            "Generated datasets need at least one point and one dimension, got {} x {}",
+            num_points, dimensionality
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct BlobsSpec {
+    pub num_points: usize,
+    pub dimensionality: usize,
+    pub num_clusters: usize,
+    // Standard deviation of the cluster centers around the origin.
+    pub center_spread: f32,
+    // Standard deviation of points around their center.
+    pub cluster_std: f32,
+    pub seed: u64,
+}
+
+// Isotropic Gaussian clusters; `labels` holds each row's cluster.
+pub fn gaussian_blobs(spec: &BlobsSpec) -> Result<GeneratedDataset, Box<dyn Error>> {
+    check_shape(spec.num_points, spec.dimensionality)?;
+    if spec.num_clusters == 0 {
+        return Err(util::invalid_argument_error("gaussian_blobs needs at least one cluster"));
+    }
+    let mut rng = util::SplitMix64::new(spec.seed);
+    let centers: Vec<Vec<f32>> = (0..spec.num_clusters)
+        .map(|_| normal_vector(&mut rng, spec.dimensionality, spec.center_spread))
+        .collect();
+    let mut rows = Vec::with_capacity(spec.num_points);
+    let mut labels = Vec::with_capacity(spec.num_points);
+    for _ in 0..spec.num_points {
+        let label = rng.next_below(spec.num_clusters);
+        let row = centers[label]
+            .iter()
+            .map(|&c| c + standard_normal(&mut rng) * spec.cluster_std)
+            .collect();
+        rows.push(row);
+        labels.push(label);
+    }
+    Ok(GeneratedDataset {
+        labels,
+        ..GeneratedDataset::plain(rows, spec.dimensionality)
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct LowRankSpec {
+    pub num_points: usize,
+    pub dimensionality: usize,
+    pub rank: usize,
+    pub noise_std: f32,
+    pub seed: u64,
+}
+
+// Rows are standard normal combinations of `rank` random basis vectors
+// plus isotropic noise, for PCA tests. The basis is returned in
+// `latent_basis` and is not orthogonalized.
+pub fn low_rank_plus_noise(spec: &LowRankSpec) -> Result<GeneratedDataset, Box<dyn Error>> {
+    check_shape(spec.num_points, spec.dimensionality)?;
+    if spec.rank == 0 || spec.rank > spec.dimensionality {
+        return Err(util::invalid_argument_error(&format!(
+            "Rank must be in [1, {}], got {}",
+            spec.dimensionality, spec.rank
+        )));
+    }
+    let mut rng = util::SplitMix64::new(spec.seed);
+    let basis: Vec<Vec<f32>> = (0..spec.rank)
+        .map(|_| normal_vector(&mut rng, spec.dimensionality, 1.0))
+        .collect();
+    let rows = (0..spec.num_points)
+        .map(|_| {
+            let mut row = normal_vector(&mut rng, spec.dimensionality, spec.noise_std);
+            for direction in &basis {
+                let coefficient = standard_normal(&mut rng);
+                for (value, &b) in row.iter_mut().zip(direction.iter()) {
+                    *value += coefficient * b;
+                }
+            }
+            row
+        })
+        .collect();
+    Ok(GeneratedDataset {
+        latent_basis: basis,
+        ..GeneratedDataset::plain(rows, spec.dimensionality)
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct HeavyTailSpec {
+    pub num_points: usize,
+    pub dimensionality: usize,
+    // Norms follow a Pareto(1) tail truncated to [1, max_norm].
+    pub max_norm: f32,
+    pub seed: u64,
+}
+
+// Uniformly random directions with heavy-tailed norms, for MIPS tests where
+// a few long rows dominate inner products.
+pub fn heavy_tailed_norms(spec: &HeavyTailSpec) -> Result<GeneratedDataset, Box<dyn Error>> {
+    check_shape(spec.num_points, spec.dimensionality)?;
+    if !spec.max_norm.is_finite() || spec.max_norm < 1.0 {
+        return Err(util::invalid_argument_error(&format!(
+            "max_norm must be finite and at least 1, got {}",
+            spec.max_norm
+        )));
+    }
+    let mut rng = util::SplitMix64::new(spec.seed);
+    let min_u = 1.0 / spec.max_norm;
+    let rows = (0..spec.num_points)
+        .map(|_| {
+            let mut row = normal_vector(&mut rng, spec.dimensionality, 1.0);
+            let length = row.iter().map(|v| v * v).sum::<f32>().sqrt();
+            // Inverse CDF of Pareto(1) on a uniform drawn from [min_u, 1).
+            let norm = 1.0 / (min_u + (1.0 - min_u) * rng.next_f32());
+            let scale = if length > 0.0 { norm / length } else { 0.0 };
+            row.iter_mut().for_each(|v| *v *= scale);
+            row
+        })
+        .collect();
+    Ok(GeneratedDataset::plain(rows, spec.dimensionality))
+}
+
+#[derive(Clone, Debug)]
+pub struct NearDuplicateSpec {
+    pub num_points: usize,
+    pub dimensionality: usize,
+    // Copies appended after the random rows; each flips up to `max_flips`
+    // coordinates of its original.
+    pub num_planted: usize,
+    pub max_flips: usize,
+    pub seed: u64,
+}
+
+// 0/1 rows with planted near duplicates, listed as (original, copy) in
+// `planted_pairs`. The first num_points - num_planted rows are uniformly
+// random and the copies follow them.
+pub fn binary_near_duplicates(spec: &NearDuplicateSpec) -> Result<GeneratedDataset, Box<dyn Error>> {
+    check_shape(spec.num_points, spec.dimensionality)?;
+    if spec.num_planted >= spec.num_points {
+        return Err(util::invalid_argument_error(&format!(
+            "Cannot plant {} duplicates among {} points",
+            spec.num_planted, spec.num_points
+        )));
+    }
+    let mut rng = util::SplitMix64::new(spec.seed);
+    let num_random = spec.num_points - spec.num_planted;
+    let mut rows: Vec<Vec<f32>> = (0..num_random)
+        .map(|_| {
+            (0..spec.dimensionality)
+                .map(|_| (rng.next_u64() >> 63) as f32)
+                .collect()
+        })
+        .collect();
+    let mut planted_pairs = Vec::with_capacity(spec.num_planted);
+    for copy in num_random..spec.num_points {
+        let original = rng.next_below(num_random);
+        let mut row = rows[original].clone();
+        let flips = rng.next_below(spec.max_flips.min(spec.dimensionality) + 1);
+        for _ in 0..flips {
+            let d = rng.next_below(spec.dimensionality);
+            row[d] = 1.0 - row[d];
+        }
+        rows.push(row);
+        planted_pairs.push((original, copy));
+    }
+    Ok(GeneratedDataset {
+        planted_pairs,
+        ..GeneratedDataset::plain(rows, spec.dimensionality)
+    })
+}
+
+// Exact top-k row indices for each query, from the f64 reference searcher.
+pub fn ground_truth(
+    generated: &GeneratedDataset,
+    queries: &util::DenseDataset<f32>,
+    k: usize,
+    measure_name: &str,
+) -> Result<Vec<Vec<usize>>, Box<dyn Error>> {
+    let docids = (0..generated.dataset.size()).collect();
+    evaluation::exact_ground_truth(&generated.dataset, docids, measure_name, queries, k)
+}
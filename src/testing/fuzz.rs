@@ -0,0 +1,239 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bodies of the cargo-fuzz targets under `fuzz/`, shared with the test
+//! that replays the checked-in corpus on stable.
+//!
+//! Each function feeds untrusted bytes to one family of decoders. Decoding
+//! errors are expected; a panic, or a broken invariant on a value that did
+//! decode, is a bug. Decoders that only read files get the input through a
+//! temporary file; point TMPDIR at a tmpfs when fuzzing.
+
+use crate::distance_measures::SquaredL2Distance;
+use crate::leaf_codes::{FileLeafCodeStore, LeafCodeStore};
+use crate::projection::{ChunkingProjection, PcaProjection};
+use crate::retrieval::{PreparedQuery, ScannRetriever, SearchCursor, SearchOptions};
+use crate::retro::chunk_store::{self, ChunkStore};
+use crate::{artifacts, binfmt, calibration, npy, proto, tree, util};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Per process and target, so parallel test threads never share a file.
+fn scratch_path(target: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("scann-fuzz-{}-{}", target, std::process::id()))
+}
+
+// Section headers, wire framing and leaf codes files.
+pub fn binfmt(data: &[u8]) {
+    // The expected magic is taken from the input, so inputs get past the
+    // magic check to the version, length and checksum checks behind it.
+    if let Some(magic) = data.get(..8) {
+        let _ = binfmt::read_section(data, magic.try_into().unwrap(), u32::MAX);
+    }
+
+    // Any kind byte and version, then the payload read back as a sequence of
+    // fields chosen by the payload itself.
+    if let Some(&kind) = data.get(1) {
+        if let Ok((_, payload)) = binfmt::read_wire(data, kind, u8::MAX, "fuzz") {
+            let mut reader = binfmt::WireReader::new(payload, "fuzz");
+            while let Ok(tag) = reader.u8() {
+                let read = match tag % 4 {
+                    0 => reader.u64().map(drop),
+                    1 => reader.f32().map(drop),
+                    2 => reader.f64().map(drop),
+                    _ => reader.count(4).map(drop),
+                };
+                if read.is_err() {
+                    break;
+                }
+            }
+            let _ = reader.finish();
+        }
+    }
+    let _ = calibration::Calibrator::from_bytes(data);
+    let _ = util::CancellationToken::from_bytes(data);
+
+    let path = scratch_path("leaf-codes");
+    std::fs::write(&path, data).unwrap();
+    if let Ok(store) = FileLeafCodeStore::open(&path, 1 << 20) {
+        for leaf in 0..store.num_leaves().min(64) {
+            let _ = store.load(leaf);
+        }
+    }
+}
+
+// Blob framing and a full retriever load.
+pub fn blob(data: &[u8]) {
+    let _ = crate::blob::decode_blob(data);
+
+    let path = scratch_path("blob");
+    std::fs::write(&path, data).unwrap();
+    let Ok(retriever) = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 3) else {
+        return;
+    };
+    // Whatever loads must be safe to check and to search.
+    let _ = retriever.verify_integrity();
+    let mut query = None;
+    let _ = retriever.for_each_active(|record| {
+        query = Some(record.values.to_vec());
+        ControlFlow::Break(())
+    });
+    if let Some(query) = query {
+        let options = SearchOptions {
+            leaves_to_search: Some(usize::MAX),
+            ..SearchOptions::default()
+        };
+        let _ = retriever.search_with_options(&util::DatapointPtr::new(query), &options);
+    }
+}
+
+// The retriever PreparedQuery inputs are decoded against: 64 points in 4
+// dimensions, in 4 partitions. The corpus was generated against it.
+pub fn prepared_query_retriever() -> &'static ScannRetriever {
+    static RETRIEVER: OnceLock<ScannRetriever> = OnceLock::new();
+    RETRIEVER.get_or_init(|| {
+        let mut rng = util::SplitMix64::new(1);
+        let rows = (0..64).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+        let retriever = ScannRetriever::new(util::DenseDataset::new(rows, 4), Box::new(SquaredL2Distance::new()), 3);
+        let mut options = tree::KMeansTreeTrainingOptions::new();
+        options.max_iterations = 3;
+        retriever.build_partitions(4, &options).unwrap();
+        retriever
+    })
+}
+
+pub fn prepared_query(data: &[u8]) {
+    let retriever = prepared_query_retriever();
+    let Ok(prepared) = PreparedQuery::from_bytes(data, retriever) else {
+        return;
+    };
+    // A decoded query passed the same validation as prepare, so searching
+    // with it must succeed, and it must survive a round trip.
+    retriever.search_prepared(&prepared, &SearchOptions::default()).unwrap();
+    let reencoded = PreparedQuery::from_bytes(&prepared.to_bytes(), retriever).unwrap();
+    assert_eq!(reencoded.to_bytes(), prepared.to_bytes());
+}
+
+// The cursor encoding is canonical: whatever decodes re-encodes to the
+// same bytes.
+pub fn search_cursor(data: &[u8]) {
+    if let Ok(cursor) = SearchCursor::from_bytes(data) {
+        assert_eq!(cursor.to_bytes(), data);
+    }
+}
+
+// .npy headers and bodies, decoded whole and streamed from a file.
+pub fn npy(data: &[u8]) {
+    if let Ok((rows, cols, data_start)) = npy::parse_npy_header(data) {
+        assert!(data_start <= data.len());
+        assert!(cols > 0 || rows == 0);
+    }
+    if let Ok(dataset) = npy::decode_npy_f32(data) {
+        assert!(dataset.data.iter().all(|row| row.len() == dataset.dimensionality()));
+        assert!(dataset.dimensionality() > 0 || dataset.size() == 0);
+    }
+
+    let path = scratch_path("npy");
+    std::fs::write(&path, data).unwrap();
+    if let Ok(reader) = npy::NpyStreamReader::open(&path) {
+        let dim = reader.dimensionality();
+        for row in reader.take(1024) {
+            match row {
+                Ok(row) => assert_eq!(row.len(), dim),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+// Text manifests: scann_assets.pbtxt, the artifacts manifest and
+// index_config.txt, plus the binary scann_assets.pb under proto-compat.
+pub fn manifest(data: &[u8]) {
+    #[cfg(feature = "proto-compat")]
+    let _ = proto::ScannAssets::decode(data);
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Whatever parses prints back to text that parses to the same assets.
+    if let Ok(assets) = proto::ScannAssets::parse_text(text) {
+        assert_eq!(proto::ScannAssets::parse_text(&assets.to_string()).unwrap(), assets);
+    }
+    let _ = artifacts::ArtifactsManifest::from_text(text, "fuzz");
+    let _ = artifacts::ArtifactsConfig::from_text(text, "fuzz");
+}
+
+// RETRO chunk store files: header, tables, and every chunk of what opens.
+pub fn chunk_store(data: &[u8]) {
+    let path = scratch_path("chunk-store");
+    std::fs::write(&path, data).unwrap();
+    let Ok(store) = chunk_store::FileChunkStore::open(&path) else {
+        return;
+    };
+    for chunk in 0..store.num_chunks().min(256) {
+        let tokens = store.get(chunk).unwrap();
+        assert_eq!(tokens.to_vec().len(), tokens.len());
+        assert!(store.document_of(chunk).unwrap() < store.num_documents());
+        let _ = store.continuation(chunk).unwrap();
+    }
+    assert!(store.get(store.num_chunks()).is_err());
+}
+
+// Splits fuzz bytes into a serialized chunking projection: an input
+// dimensionality, rotation GFVs of input-chosen lengths, a permutation and
+// a block count.
+fn serialized_projection(data: &[u8]) -> Option<(usize, proto::SerializedChunkingProjection)> {
+    let (&input_dims, rest) = data.split_first()?;
+    let (&num_vecs, mut rest) = rest.split_first()?;
+    let mut pca = proto::SerializedProjection::new();
+    for _ in 0..num_vecs % 16 {
+        let (&len, tail) = rest.split_first()?;
+        let len = (len % 32) as usize;
+        let values = tail.get(..len * 4)?;
+        pca.add_rotation_vec().feature_value_float =
+            values.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        rest = &tail[len * 4..];
+    }
+    let (&num_blocks, rest) = rest.split_first()?;
+    let serialized = proto::SerializedChunkingProjection {
+        pca: (num_vecs >= 16).then_some(pca),
+        permutation: rest.iter().map(|&d| d as u32).collect(),
+        num_blocks: num_blocks as u32,
+    };
+    Some(((input_dims % 32) as usize, serialized))
+}
+
+// GenericFeatureVector rotations loaded into PCA and chunking projections,
+// then used to project.
+pub fn gfv(data: &[u8]) {
+    let Some((input_dims, serialized)) = serialized_projection(data) else {
+        return;
+    };
+    if let Some(rotation) = &serialized.pca {
+        if let Ok(mut pca) = PcaProjection::<f32>::new(input_dims as i32, 1) {
+            if pca.create_from_serialized(rotation).is_ok() {
+                let mut projected = util::DatapointPtr::new(Vec::<f32>::new());
+                pca.project_input(&util::DatapointPtr::new(vec![1.0; input_dims]), &mut projected).unwrap();
+                assert_eq!(projected.values().len(), pca.projected_dims());
+            }
+        }
+    }
+    if let Ok(projection) = ChunkingProjection::create_from_serialized(&serialized, input_dims) {
+        let projected = projection.project(&vec![1.0; input_dims]).unwrap();
+        assert_eq!(projected.len(), projection.projected_dims());
+        let blocks = projection.block_ranges();
+        assert_eq!(blocks.len(), projection.num_blocks());
+        assert_eq!(blocks.last().map_or(0, |block| block.end), projected.len());
+    }
+}
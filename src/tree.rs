@@ -14,31 +14,36 @@
 
 //! K-means tree training options for data partitioning.
 
-use super::proto;
+use super::{distance_measures, proto, util, ScannError};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
 
 // Placeholder for GmmUtils options
 mod gmm_utils {
-    #[derive(Clone, PartialEq)]
+    #[derive(Clone, Debug, PartialEq)]
     pub enum BalancingType {
         Unbalanced,
         GreedyBalanced,
         UnbalancedFloat32,
     }
 
-    #[derive(Clone, PartialEq)]
+    #[derive(Clone, Debug, PartialEq)]
     pub enum ReassignmentType {
         RandomReassignment,
         PcaSplitting,
     }
 
-    #[derive(Clone, PartialEq)]
+    #[derive(Clone, Debug, PartialEq)]
     pub enum CenterInitializationType {
         KmeansPlusPlus,
         RandomInitialization,
     }
 }
 
-#[derive(Clone)]
+pub const DEFAULT_MIN_POINTS_TO_PARTITION: usize = 2;
+
+#[derive(Clone, Debug)]
 pub struct KMeansTreeTrainingOptions {
     pub partitioning_type: proto::PartitioningType,
     pub max_num_levels: i32,
@@ -53,6 +58,16 @@ pub struct KMeansTreeTrainingOptions {
     pub balancing_type: gmm_utils::BalancingType,
     pub reassignment_type: gmm_utils::ReassignmentType,
     pub center_initialization_type: gmm_utils::CenterInitializationType,
+    // Centers from a previous build used to seed Lloyd iterations instead of
+    // the configured initialization.
+    pub warm_start_centers: Option<util::DenseDataset<f32>>,
+    // Corpora smaller than this are left unpartitioned and searched brute
+    // force.
+    pub min_points_to_partition: usize,
+    // Re-seed centers that collapse to (near) zero from a random nonzero
+    // point. Set for cosine and normalized data, where a zero center has no
+    // direction.
+    pub reinitialize_zero_centers: bool,
 }
 
 impl KMeansTreeTrainingOptions {
@@ -71,6 +86,9 @@ impl KMeansTreeTrainingOptions {
             balancing_type: gmm_utils::BalancingType::Unbalanced,
             reassignment_type: gmm_utils::ReassignmentType::RandomReassignment,
             center_initialization_type: gmm_utils::CenterInitializationType::KmeansPlusPlus,
+            warm_start_centers: None,
+            min_points_to_partition: DEFAULT_MIN_POINTS_TO_PARTITION,
+            reinitialize_zero_centers: false,
         }
     }
 
@@ -103,7 +121,7 @@ impl KMeansTreeTrainingOptions {
             partitioning_type: config.partitioning_type(),
             max_num_levels: config.max_num_levels(),
             max_leaf_size: config.max_leaf_size(),
-            learned_spilling_type: config.database_spilling().spilling_type.clone(),
+            learned_spilling_type: config.database_spilling().spilling_type,
             per_node_spilling_factor: config.database_spilling().replication_factor,
             max_spill_centers: config.database_spilling().max_spill_centers,
             max_iterations: config.max_clustering_iterations(),
@@ -113,6 +131,846 @@ impl KMeansTreeTrainingOptions {
             balancing_type,
             reassignment_type,
             center_initialization_type,
+            warm_start_centers: None,
+            min_points_to_partition: DEFAULT_MIN_POINTS_TO_PARTITION,
+            reinitialize_zero_centers: false,
+        }
+    }
+}
+
+impl Default for KMeansTreeTrainingOptions {
+    fn default() -> Self {
+        KMeansTreeTrainingOptions::new()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct KMeansTrainingStats {
+    pub initial_objective: f64,
+    pub final_objective: f64,
+    pub iterations: usize,
+}
+
+// Outcome of a partitioning request. Degenerate corpora are not an error;
+// the build proceeds without a tree and records why.
+#[derive(Clone, Debug)]
+pub enum PartitioningOutcome {
+    Built(KMeansTrainingStats),
+    Skipped { reason: String },
+}
+
+impl PartitioningOutcome {
+    pub fn stats(&self) -> Option<&KMeansTrainingStats> {
+        match self {
+            PartitioningOutcome::Built(stats) => Some(stats),
+            PartitioningOutcome::Skipped { .. } => None,
+        }
+    }
+
+    pub fn skip_reason(&self) -> Option<&str> {
+        match self {
+            PartitioningOutcome::Built(_) => None,
+            PartitioningOutcome::Skipped { reason } => Some(reason),
+        }
+    }
+}
+
+// Why `num_points` rows should not be split into `num_leaves` partitions, if
+// they should not.
+pub fn partitioning_skip_reason(
+    num_points: usize,
+    num_leaves: usize,
+    options: &KMeansTreeTrainingOptions,
+) -> Option<String> {
+    if num_points < options.min_points_to_partition {
+        return Some(format!(
+            "Corpus has {} points, below the partitioning minimum of {}",
+            num_points, options.min_points_to_partition
+        ));
+    }
+    if num_points < num_leaves {
+        return Some(format!(
+            "Corpus has {} points, fewer than the {} requested leaves",
+            num_points, num_leaves
+        ));
+    }
+    None
+}
+
+pub struct KMeansResult {
+    pub centers: util::DenseDataset<f32>,
+    pub assignments: Vec<usize>,
+    pub stats: KMeansTrainingStats,
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(&x, &y)| (x - y) * (x - y)).sum()
+}
+
+// Squared L2 from `center` to row `row` of `data`, read in place.
+fn squared_l2_to_row(center: &[f32], data: &util::DenseDataset<f32>, row: usize) -> f32 {
+    distance_measures::compute_distance_to_row(&distance_measures::SquaredL2Distance, center, data, row)
+}
+
+fn nearest_center(data: &util::DenseDataset<f32>, row: usize, centers: &[Vec<f32>]) -> (usize, f32) {
+    let mut best = (0, f32::INFINITY);
+    for (c, center) in centers.iter().enumerate() {
+        let d = squared_l2_to_row(center, data, row);
+        if d < best.1 {
+            best = (c, d);
+        }
+    }
+    best
+}
+
+fn assign(data: &util::DenseDataset<f32>, centers: &[Vec<f32>], assignments: &mut [usize]) -> f64 {
+    let mut objective = 0.0f64;
+    for (i, assignment) in assignments.iter_mut().enumerate().take(data.size()) {
+        let (c, d) = nearest_center(data, i, centers);
+        *assignment = c;
+        objective += d as f64;
+    }
+    objective
+}
+
+fn cluster_sizes(assignments: &[usize], num_centers: usize) -> Vec<usize> {
+    let mut sizes = vec![0; num_centers];
+    for &c in assignments {
+        sizes[c] += 1;
+    }
+    sizes
+}
+
+fn initial_centers(
+    data: &util::DenseDataset<f32>,
+    num_centers: usize,
+    options: &KMeansTreeTrainingOptions,
+    rng: &mut util::SplitMix64,
+) -> Vec<Vec<f32>> {
+    match options.center_initialization_type {
+        gmm_utils::CenterInitializationType::RandomInitialization => (0..num_centers)
+            .map(|_| data.data[rng.next_below(data.size())].clone())
+            .collect(),
+        gmm_utils::CenterInitializationType::KmeansPlusPlus => {
+            let mut centers = vec![data.data[rng.next_below(data.size())].clone()];
+            let mut min_dists: Vec<f32> = (0..data.size()).map(|i| squared_l2_to_row(&centers[0], data, i)).collect();
+            while centers.len() < num_centers {
+                let total: f64 = min_dists.iter().map(|&d| d as f64).sum();
+                let next = if total <= 0.0 {
+                    rng.next_below(data.size())
+                } else {
+                    let mut target = rng.next_f32() as f64 * total;
+                    let mut chosen = data.size() - 1;
+                    for (i, &d) in min_dists.iter().enumerate() {
+                        target -= d as f64;
+                        if target <= 0.0 {
+                            chosen = i;
+                            break;
+                        }
+                    }
+                    chosen
+                };
+                centers.push(data.data[next].clone());
+                let newest = centers.last().unwrap();
+                for (i, min_dist) in min_dists.iter_mut().enumerate() {
+                    *min_dist = min_dist.min(squared_l2_to_row(newest, data, i));
+                }
+            }
+            centers
         }
     }
-}
\ No newline at end of file
+}
+
+// Adapts warm-start centers to the requested count: surplus centers owning
+// the fewest points are dropped, and missing ones are produced by splitting
+// the most populated clusters with a small perturbation.
+fn adapt_warm_start_centers(
+    data: &util::DenseDataset<f32>,
+    warm_start: &util::DenseDataset<f32>,
+    num_centers: usize,
+    rng: &mut util::SplitMix64,
+) -> Vec<Vec<f32>> {
+    let mut centers = warm_start.data.clone();
+    let mut assignments = vec![0; data.size()];
+    while centers.len() > num_centers {
+        assign(data, &centers, &mut assignments);
+        let sizes = cluster_sizes(&assignments, centers.len());
+        let smallest = (0..centers.len()).min_by_key(|&c| sizes[c]).unwrap();
+        centers.remove(smallest);
+    }
+    while centers.len() < num_centers {
+        assign(data, &centers, &mut assignments);
+        let sizes = cluster_sizes(&assignments, centers.len());
+        let largest = (0..centers.len()).max_by_key(|&c| sizes[c]).unwrap();
+        let split: Vec<f32> = centers[largest]
+            .iter()
+            .map(|&v| v + (rng.next_f32() - 0.5) * 1e-3 * v.abs().max(1.0))
+            .collect();
+        centers.push(split);
+    }
+    centers
+}
+
+const ZERO_CENTER_NORM: f32 = 1e-6;
+
+// Replaces centers whose norm is below ZERO_CENTER_NORM with random nonzero
+// data points. Leaves them alone when the data has no nonzero point.
+fn reinitialize_zero_centers(
+    data: &util::DenseDataset<f32>,
+    centers: &mut [Vec<f32>],
+    rng: &mut util::SplitMix64,
+) {
+    let nonzero: Vec<usize> = (0..data.size())
+        .filter(|&i| data.data[i].iter().map(|v| v * v).sum::<f32>().sqrt() >= ZERO_CENTER_NORM)
+        .collect();
+    if nonzero.is_empty() {
+        return;
+    }
+    for center in centers.iter_mut() {
+        if center.iter().map(|v| v * v).sum::<f32>().sqrt() < ZERO_CENTER_NORM {
+            *center = data.data[nonzero[rng.next_below(nonzero.len())]].clone();
+        }
+    }
+}
+
+pub fn train_kmeans(
+    data: &util::DenseDataset<f32>,
+    num_centers: usize,
+    options: &KMeansTreeTrainingOptions,
+) -> Result<KMeansResult, Box<dyn Error>> {
+    if num_centers == 0 {
+        return Err(util::invalid_argument_error("Number of centers must be > 0"));
+    }
+    if data.size() < num_centers {
+        return Err(util::invalid_argument_error(&format!(
+            "Cannot train {} centers on {} datapoints",
+            num_centers,
+            data.size()
+        )));
+    }
+
+    let mut rng = util::SplitMix64::new(options.seed);
+    let mut centers = match &options.warm_start_centers {
+        Some(warm_start) => {
+            if warm_start.dimensionality() != data.dimensionality() || warm_start.size() == 0 {
+                return Err(util::invalid_argument_error(&format!(
+                    "Warm-start centers have dimensionality {} and size {}, expected dimensionality {}",
+                    warm_start.dimensionality(),
+                    warm_start.size(),
+                    data.dimensionality()
+                )));
+            }
+            adapt_warm_start_centers(data, warm_start, num_centers, &mut rng)
+        }
+        None => initial_centers(data, num_centers, options, &mut rng),
+    };
+    if options.reinitialize_zero_centers {
+        reinitialize_zero_centers(data, &mut centers, &mut rng);
+    }
+
+    let mut assignments = vec![0; data.size()];
+    let mut objective = assign(data, &centers, &mut assignments);
+    let mut stats = KMeansTrainingStats {
+        initial_objective: objective,
+        final_objective: objective,
+        iterations: 0,
+    };
+
+    let max_iterations = options.max_iterations.max(1) as usize;
+    while stats.iterations < max_iterations {
+        let dim = data.dimensionality();
+        let mut sums = vec![vec![0.0f64; dim]; num_centers];
+        let sizes = cluster_sizes(&assignments, num_centers);
+        for (point, &c) in data.data.iter().zip(assignments.iter()) {
+            for (s, &v) in sums[c].iter_mut().zip(point.iter()) {
+                *s += v as f64;
+            }
+        }
+        for c in 0..num_centers {
+            if sizes[c] > 0 {
+                centers[c] = sums[c].iter().map(|&s| (s / sizes[c] as f64) as f32).collect();
+            }
+        }
+        if options.reinitialize_zero_centers {
+            reinitialize_zero_centers(data, &mut centers, &mut rng);
+        }
+
+        let new_objective = assign(data, &centers, &mut assignments);
+        stats.iterations += 1;
+        let improvement = objective - new_objective;
+        objective = new_objective;
+        if improvement <= options.convergence_epsilon as f64 * objective.max(f64::MIN_POSITIVE) {
+            break;
+        }
+    }
+    stats.final_objective = objective;
+
+    Ok(KMeansResult {
+        centers: util::DenseDataset::new(centers, data.dimensionality()),
+        assignments,
+        stats,
+    })
+}
+// Maps every leaf id before an administrative merge or split to the leaf
+// ids that hold its rows afterwards, so systems tracking tokens externally
+// can follow along. Stored as text, one "old: new..." line per old leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenRemapping {
+    pub old_to_new: Vec<Vec<usize>>,
+}
+
+impl TokenRemapping {
+    fn identity(num_leaves: usize) -> Self {
+        TokenRemapping {
+            old_to_new: (0..num_leaves).map(|leaf| vec![leaf]).collect(),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        for (old, new) in self.old_to_new.iter().enumerate() {
+            let new: Vec<String> = new.iter().map(|leaf| leaf.to_string()).collect();
+            text.push_str(&format!("{}: {}\n", old, new.join(" ")));
+        }
+        fs::write(path, text).map_err(|e| {
+            Box::new(ScannError {
+                message: format!("Failed to write token remapping {}: {}", path.display(), e),
+            }) as Box<dyn Error>
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ScannError {
+            message: format!("Failed to read token remapping {}: {}", path.display(), e),
+        })?;
+        let mut old_to_new = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let invalid = || util::invalid_argument_error(&format!("Invalid token remapping line in {}: '{}'", path.display(), line));
+            let (old, new) = line.split_once(':').ok_or_else(invalid)?;
+            if old.trim().parse::<usize>().ok() != Some(old_to_new.len()) {
+                return Err(invalid());
+            }
+            let new = new
+                .split_whitespace()
+                .map(|leaf| leaf.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?;
+            old_to_new.push(new);
+        }
+        Ok(TokenRemapping { old_to_new })
+    }
+}
+
+// Upper bounds over the points ever inserted into one leaf. Removing or
+// moving points leaves them in place, so they stay valid but may loosen
+// until recomputed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LeafBound {
+    pub max_norm: f32,
+    // Largest L2 distance from a point to the leaf center.
+    pub radius: f32,
+}
+
+impl LeafBound {
+    fn include(&mut self, point: &[f32], center: &[f32]) {
+        self.max_norm = self.max_norm.max(l2_norm(point));
+        self.radius = self.radius.max(squared_l2(point, center).sqrt());
+    }
+}
+
+fn l2_norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+// Single-level k-means partitioning. Each leaf lists the dataset rows it
+// holds; with spilling enabled a row may appear in several leaves. Rows are
+// stored at `index_width`, the narrowest width for the dataset unless a
+// retriever sets its own.
+#[derive(Clone)]
+pub struct KMeansTree {
+    centers: util::DenseDataset<f32>,
+    leaves: Vec<util::IndexList>,
+    index_width: util::IndexWidth,
+    spilling_factor: f32,
+    max_spill_centers: usize,
+    // Per-leaf bounds kept up to date by insert; None for trees assembled
+    // from parts until compute_leaf_bounds runs.
+    bounds: Option<Vec<LeafBound>>,
+}
+
+impl KMeansTree {
+    pub fn train(
+        data: &util::DenseDataset<f32>,
+        num_leaves: usize,
+        options: &KMeansTreeTrainingOptions,
+    ) -> Result<(Self, KMeansTrainingStats), Box<dyn Error>> {
+        let result = train_kmeans(data, num_leaves, options)?;
+        let index_width = util::IndexWidth::for_size(data.size());
+        let mut tree = KMeansTree {
+            centers: result.centers,
+            leaves: vec![util::IndexList::new(index_width); num_leaves],
+            index_width,
+            spilling_factor: options.per_node_spilling_factor,
+            max_spill_centers: options.max_spill_centers.max(1) as usize,
+            bounds: Some(vec![LeafBound::default(); num_leaves]),
+        };
+        for (index, point) in data.data.iter().enumerate() {
+            tree.insert(index, point);
+        }
+        Ok((tree, result.stats))
+    }
+
+    pub fn from_parts(
+        centers: util::DenseDataset<f32>,
+        leaves: Vec<Vec<usize>>,
+        spilling_factor: f32,
+        max_spill_centers: usize,
+    ) -> Self {
+        let largest = leaves.iter().flatten().copied().max().unwrap_or(0);
+        let index_width = util::IndexWidth::for_size(largest.saturating_add(1));
+        KMeansTree {
+            centers,
+            leaves: leaves.into_iter().map(|leaf| util::IndexList::from_indices(index_width, leaf)).collect(),
+            index_width,
+            spilling_factor,
+            max_spill_centers: max_spill_centers.max(1),
+            bounds: None,
+        }
+    }
+
+    pub fn spilling_factor(&self) -> f32 {
+        self.spilling_factor
+    }
+
+    pub fn max_spill_centers(&self) -> usize {
+        self.max_spill_centers
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn centers(&self) -> &util::DenseDataset<f32> {
+        &self.centers
+    }
+
+    pub fn leaf(&self, leaf_id: usize) -> &util::IndexList {
+        &self.leaves[leaf_id]
+    }
+
+    pub fn index_width(&self) -> util::IndexWidth {
+        self.index_width
+    }
+
+    // Re-encodes every leaf at `width`. Fails, leaving the tree unchanged,
+    // if a row does not fit.
+    pub fn set_index_width(&mut self, width: util::IndexWidth) -> Result<(), Box<dyn Error>> {
+        let mut leaves = self.leaves.clone();
+        for leaf in leaves.iter_mut() {
+            leaf.set_width(width)?;
+        }
+        self.leaves = leaves;
+        self.index_width = width;
+        Ok(())
+    }
+
+    pub fn leaf_bound(&self, leaf_id: usize) -> Option<LeafBound> {
+        self.bounds.as_ref()?.get(leaf_id).copied()
+    }
+
+    pub fn has_leaf_bounds(&self) -> bool {
+        self.bounds.is_some()
+    }
+
+    // Tightens every leaf bound to the rows currently in the leaf.
+    pub fn compute_leaf_bounds(&mut self, data: &impl util::RowSource<f32>) {
+        let bounds = (0..self.num_leaves()).map(|leaf| self.exact_leaf_bound(leaf, data)).collect();
+        self.bounds = Some(bounds);
+    }
+
+    // Recomputes one leaf's bound after its rows changed in place. A no-op
+    // without bounds.
+    pub fn recompute_leaf_bound(&mut self, leaf: usize, data: &impl util::RowSource<f32>) {
+        let bound = self.exact_leaf_bound(leaf, data);
+        if let Some(slot) = self.bounds.as_mut().and_then(|bounds| bounds.get_mut(leaf)) {
+            *slot = bound;
+        }
+    }
+
+    fn exact_leaf_bound(&self, leaf: usize, data: &impl util::RowSource<f32>) -> LeafBound {
+        let mut bound = LeafBound::default();
+        for i in &self.leaves[leaf] {
+            bound.include(data.row(i), &self.centers.data[leaf]);
+        }
+        bound
+    }
+
+    // Interval containing <query, x> for every point x in the leaf:
+    // <q, c> -/+ |q| * radius, clipped to -/+ |q| * max_norm. Widened to
+    // cover f32 rounding in both the bounds and the scored dot products,
+    // so a row whose computed dot product falls outside it cannot exist.
+    // None without bounds or for an empty leaf.
+    pub fn leaf_dot_range(&self, leaf: usize, query: &[f32]) -> Option<(f64, f64)> {
+        let bound = self.bounds.as_ref()?.get(leaf)?;
+        if self.leaves[leaf].is_empty() {
+            return None;
+        }
+        let center = &self.centers.data[leaf];
+        let center_dot: f64 = query.iter().zip(center.iter()).map(|(&q, &c)| q as f64 * c as f64).sum();
+        let center_norm = center.iter().map(|&c| c as f64 * c as f64).sum::<f64>().sqrt();
+        let query_norm = query.iter().map(|&q| q as f64 * q as f64).sum::<f64>().sqrt();
+        let spread = query_norm * bound.radius as f64;
+        let cap = query_norm * bound.max_norm as f64;
+        let scale = query_norm * (bound.max_norm as f64 + bound.radius as f64 + center_norm) + 1.0;
+        let slack = 2.0 * (query.len() + 2) as f64 * f32::EPSILON as f64 * scale;
+        Some(((center_dot - spread).max(-cap) - slack, (center_dot + spread).min(cap) + slack))
+    }
+
+    // Leaves for a point: the nearest center plus, when spilling is enabled,
+    // any center within `spilling_factor` times the nearest distance, up to
+    // `max_spill_centers` in total.
+    fn leaves_for_point(&self, point: &[f32]) -> Vec<usize> {
+        let mut dists: Vec<(usize, f32)> = self
+            .centers
+            .data
+            .iter()
+            .enumerate()
+            .map(|(c, center)| (c, squared_l2(point, center)))
+            .collect();
+        dists.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        let nearest = dists[0].1;
+        if self.spilling_factor <= 1.0 || self.max_spill_centers <= 1 {
+            return vec![dists[0].0];
+        }
+        let limit = nearest * self.spilling_factor * self.spilling_factor;
+        dists
+            .iter()
+            .take(self.max_spill_centers)
+            .filter(|&&(_, d)| d <= limit)
+            .map(|&(c, _)| c)
+            .collect()
+    }
+
+    pub fn insert(&mut self, index: usize, point: &[f32]) {
+        for leaf in self.leaves_for_point(point) {
+            let rows = &mut self.leaves[leaf];
+            rows.push(index);
+            self.index_width = self.index_width.max(rows.width());
+            if let Some(bounds) = self.bounds.as_mut() {
+                bounds[leaf].include(point, &self.centers.data[leaf]);
+            }
+        }
+    }
+
+    pub fn reassign(&mut self, index: usize, point: &[f32]) {
+        for leaf in self.leaves.iter_mut() {
+            leaf.retain(|i| i != index);
+        }
+        self.insert(index, point);
+    }
+
+    // Applies a row renumbering after compaction; rows mapped to None are
+    // dropped from every leaf.
+    pub fn remap(&mut self, old_to_new: &[Option<usize>]) {
+        for leaf in self.leaves.iter_mut() {
+            *leaf = util::IndexList::from_indices(self.index_width, leaf.iter().filter_map(|i| old_to_new[i]));
+        }
+    }
+
+    // Merges leaf `b` into leaf `a`. The merged center is the size-weighted
+    // mean of both centers and takes the lower of the two ids; leaves after
+    // the higher id shift down by one.
+    pub fn merge_leaves(&mut self, a: usize, b: usize) -> Result<TokenRemapping, Box<dyn Error>> {
+        let num_leaves = self.num_leaves();
+        if a >= num_leaves || b >= num_leaves || a == b {
+            return Err(util::invalid_argument_error(&format!(
+                "Cannot merge leaves {} and {} of a tree with {} leaves",
+                a, b, num_leaves
+            )));
+        }
+        let (keep, gone) = (a.min(b), a.max(b));
+        let (wk, wg) = (self.leaves[keep].len() as f32, self.leaves[gone].len() as f32);
+        let total = wk + wg;
+        let (wk, wg) = if total == 0.0 { (0.5, 0.5) } else { (wk / total, wg / total) };
+        let merged: Vec<f32> = self.centers.data[keep]
+            .iter()
+            .zip(self.centers.data[gone].iter())
+            .map(|(&x, &y)| wk * x + wg * y)
+            .collect();
+
+        // The merged center moved, so each radius grows by how far its old
+        // center is from the new one.
+        if let Some(bounds) = self.bounds.as_mut() {
+            let widened = |leaf: usize, bound: LeafBound| match self.leaves[leaf].is_empty() {
+                true => 0.0,
+                false => bound.radius + squared_l2(&self.centers.data[leaf], &merged).sqrt(),
+            };
+            let merged_bound = LeafBound {
+                max_norm: bounds[keep].max_norm.max(bounds[gone].max_norm),
+                radius: widened(keep, bounds[keep]).max(widened(gone, bounds[gone])),
+            };
+            bounds[keep] = merged_bound;
+            bounds.remove(gone);
+        }
+        let gone_rows = self.leaves.remove(gone);
+        self.centers.data.remove(gone);
+        self.centers.data[keep] = merged;
+        let leaf = &mut self.leaves[keep];
+        leaf.extend(&gone_rows);
+        // A row spilled into both leaves is listed once.
+        leaf.sort_dedup();
+
+        let mut remapping = TokenRemapping::identity(num_leaves);
+        for (old, new) in remapping.old_to_new.iter_mut().enumerate() {
+            *new = vec![if old == gone {
+                keep
+            } else if old > gone {
+                old - 1
+            } else {
+                old
+            }];
+        }
+        Ok(remapping)
+    }
+
+    // Splits `leaf` into `k` leaves by running k-means over its rows only.
+    // The first sub-cluster keeps the leaf id; the rest are appended.
+    pub fn split_leaf(
+        &mut self,
+        leaf: usize,
+        k: usize,
+        data: &impl util::RowSource<f32>,
+        options: &KMeansTreeTrainingOptions,
+    ) -> Result<TokenRemapping, Box<dyn Error>> {
+        let num_leaves = self.num_leaves();
+        if leaf >= num_leaves {
+            return Err(util::invalid_argument_error(&format!(
+                "Leaf {} out of range: tree has {} leaves",
+                leaf, num_leaves
+            )));
+        }
+        let rows = self.leaves[leaf].clone();
+        if k < 2 || rows.len() < k {
+            return Err(util::invalid_argument_error(&format!(
+                "Cannot split leaf {} with {} rows into {} leaves",
+                leaf,
+                rows.len(),
+                k
+            )));
+        }
+        let subset = util::DenseDataset::new(
+            rows.iter().map(|i| data.row(i).to_vec()).collect(),
+            data.dimensionality(),
+        );
+        let mut options = options.clone();
+        options.warm_start_centers = None;
+        let result = train_kmeans(&subset, k, &options)?;
+
+        let mut new_ids = vec![leaf];
+        new_ids.extend(num_leaves..num_leaves + k - 1);
+        let mut split_leaves = vec![util::IndexList::new(self.index_width); k];
+        for (row, &cluster) in rows.iter().zip(result.assignments.iter()) {
+            split_leaves[cluster].push(row);
+        }
+        let mut split_leaves = split_leaves.into_iter();
+        let mut centers = result.centers.data.into_iter();
+        self.leaves[leaf] = split_leaves.next().unwrap();
+        self.centers.data[leaf] = centers.next().unwrap();
+        self.leaves.extend(split_leaves);
+        self.centers.data.extend(centers);
+        if self.bounds.is_some() {
+            let split_bounds: Vec<LeafBound> = new_ids.iter().map(|&id| self.exact_leaf_bound(id, data)).collect();
+            let num_leaves = self.leaves.len();
+            if let Some(bounds) = self.bounds.as_mut() {
+                bounds.resize(num_leaves, LeafBound::default());
+                for (&id, bound) in new_ids.iter().zip(split_bounds) {
+                    bounds[id] = bound;
+                }
+            }
+        }
+
+        let mut remapping = TokenRemapping::identity(num_leaves);
+        remapping.old_to_new[leaf] = new_ids;
+        Ok(remapping)
+    }
+
+    // Leaves whose centers are closest to the query, nearest first.
+    pub fn tokens_for_query(&self, query: &[f32], leaves_to_search: usize) -> Vec<usize> {
+        let mut tokens = Vec::new();
+        self.tokens_for_query_into(query, leaves_to_search, &mut Vec::new(), &mut tokens);
+        tokens
+    }
+
+    // Allocation-free variant for the search path; `scratch` and `tokens`
+    // are cleared and reused.
+    pub fn tokens_for_query_into(
+        &self,
+        query: &[f32],
+        leaves_to_search: usize,
+        scratch: &mut Vec<(usize, f32)>,
+        tokens: &mut Vec<usize>,
+    ) {
+        scratch.clear();
+        scratch.extend(
+            self.centers
+                .data
+                .iter()
+                .enumerate()
+                .map(|(c, center)| (c, squared_l2(query, center))),
+        );
+        scratch.sort_unstable_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        tokens.clear();
+        tokens.extend(scratch.iter().take(leaves_to_search).map(|&(c, _)| c));
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeExportOptions {
+    // Fill leaves by size relative to the mean leaf size.
+    pub color_by_imbalance: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeafStats {
+    pub leaf: usize,
+    pub size: usize,
+    pub center_norm: f32,
+    // Mean L2 distance from the leaf's points to its center; None for an
+    // empty leaf.
+    pub mean_distance_to_center: Option<f32>,
+}
+
+// Exports for inspecting partition quality. Nodes are always written in
+// leaf id order so output from two builds diffs cleanly.
+impl KMeansTree {
+    pub fn leaf_stats(&self, data: &util::DenseDataset<f32>) -> Result<Vec<LeafStats>, Box<dyn Error>> {
+        self.leaves
+            .iter()
+            .enumerate()
+            .map(|(leaf, members)| {
+                let center = &self.centers.data[leaf];
+                let mut total = 0.0f64;
+                for index in members {
+                    let point = data.data.get(index).ok_or_else(|| {
+                        util::invalid_argument_error(&format!(
+                            "Leaf {} holds row {} but the dataset has {} rows",
+                            leaf,
+                            index,
+                            data.data.len()
+                        ))
+                    })?;
+                    total += squared_l2(point, center).sqrt() as f64;
+                }
+                Ok(LeafStats {
+                    leaf,
+                    size: members.len(),
+                    center_norm: center.iter().map(|x| x * x).sum::<f32>().sqrt(),
+                    mean_distance_to_center: (!members.is_empty()).then(|| (total / members.len() as f64) as f32),
+                })
+            })
+            .collect()
+    }
+
+    pub fn export_dot<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        data: &util::DenseDataset<f32>,
+        options: &TreeExportOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let stats = self.leaf_stats(data)?;
+        let mean_size = mean_leaf_size(&stats);
+        writeln!(writer, "digraph kmeans_tree {{")?;
+        writeln!(writer, "  node [shape=box];")?;
+        writeln!(writer, "  root [label=\"root\\nleaves={}\"];", stats.len())?;
+        for s in &stats {
+            let distance = match s.mean_distance_to_center {
+                Some(d) => format!("{:.4}", d),
+                None => "-".to_string(),
+            };
+            let mut attrs = format!("label=\"leaf {}\\nsize={}\\nmean_dist={}\"", s.leaf, s.size, distance);
+            if options.color_by_imbalance {
+                attrs.push_str(&format!(", style=filled, fillcolor=\"{}\"", imbalance_color(s.size, mean_size)));
+            }
+            writeln!(writer, "  leaf_{} [{}];", s.leaf, attrs)?;
+        }
+        for s in &stats {
+            writeln!(writer, "  root -> leaf_{};", s.leaf)?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    // Hierarchy as a flat node list with parent/children ids: node 0 is the
+    // root and node `i + 1` is leaf `i`.
+    pub fn export_json<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        data: &util::DenseDataset<f32>,
+    ) -> Result<(), Box<dyn Error>> {
+        let stats = self.leaf_stats(data)?;
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"num_leaves\": {},", stats.len())?;
+        writeln!(writer, "  \"num_points\": {},", data.data.len())?;
+        writeln!(writer, "  \"dimensionality\": {},", self.centers.dimensionality())?;
+        writeln!(writer, "  \"spilling_factor\": {},", json_number(self.spilling_factor))?;
+        writeln!(writer, "  \"nodes\": [")?;
+        let children: Vec<String> = (1..=stats.len()).map(|id| id.to_string()).collect();
+        write!(
+            writer,
+            "    {{\"id\": 0, \"parent\": null, \"children\": [{}], \"size\": {}}}",
+            children.join(", "),
+            data.data.len()
+        )?;
+        for s in &stats {
+            let distance = s.mean_distance_to_center.map_or("null".to_string(), json_number);
+            write!(
+                writer,
+                ",\n    {{\"id\": {}, \"parent\": 0, \"children\": [], \"leaf\": {}, \"size\": {}, \"center_norm\": {}, \"mean_distance_to_center\": {}}}",
+                s.leaf + 1,
+                s.leaf,
+                s.size,
+                json_number(s.center_norm),
+                distance
+            )?;
+        }
+        writeln!(writer, "\n  ]")?;
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+}
+
+fn mean_leaf_size(stats: &[LeafStats]) -> f64 {
+    if stats.is_empty() {
+        return 0.0;
+    }
+    stats.iter().map(|s| s.size as f64).sum::<f64>() / stats.len() as f64
+}
+
+fn imbalance_color(size: usize, mean_size: f64) -> &'static str {
+    if mean_size <= 0.0 {
+        return "white";
+    }
+    let ratio = size as f64 / mean_size;
+    if size == 0 {
+        "gray"
+    } else if ratio >= 2.0 {
+        "red"
+    } else if ratio >= 1.25 {
+        "orange"
+    } else if ratio <= 0.5 {
+        "lightblue"
+    } else {
+        "white"
+    }
+}
+
+// JSON has no NaN or infinity.
+fn json_number(x: f32) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        "null".to_string()
+    }
+}
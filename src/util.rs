@@ -14,9 +14,16 @@
 
 //! Shared utility types for the ScaNN library.
 
-use nalgebra::{DMatrix, DVector};
+use super::binfmt;
+use nalgebra::DMatrix;
 use std::error::Error;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[derive(Debug)]
 pub struct ScannError {
@@ -43,7 +50,374 @@ pub fn failed_precondition_error(msg: &str) -> Box<dyn Error> {
     })
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFiniteHandling {
+    // NaN/Inf values are rejected when vectors are added.
+    #[default]
+    RejectAtIngest,
+    // Stored as-is; datapoints whose distance is non-finite are never
+    // returned.
+    SkipAtSearch,
+    // Non-finite values and distances are replaced with +/-f32::MAX.
+    Clamp,
+}
+
+fn clamp_non_finite(v: f32) -> f32 {
+    if v.is_nan() || v == f32::INFINITY {
+        f32::MAX
+    } else if v == f32::NEG_INFINITY {
+        -f32::MAX
+    } else {
+        v
+    }
+}
+
+// Applies the ingest side of `policy` to a vector about to be stored.
+pub fn apply_non_finite_policy(values: &[f32], policy: NonFiniteHandling) -> Result<Vec<f32>, Box<dyn Error>> {
+    match policy {
+        NonFiniteHandling::RejectAtIngest => {
+            if let Some(dim) = values.iter().position(|v| !v.is_finite()) {
+                return Err(invalid_argument_error(&format!(
+                    "Non-finite value {} at dimension {}",
+                    values[dim], dim
+                )));
+            }
+            Ok(values.to_vec())
+        }
+        NonFiniteHandling::SkipAtSearch => Ok(values.to_vec()),
+        NonFiniteHandling::Clamp => Ok(values.iter().map(|&v| clamp_non_finite(v)).collect()),
+    }
+}
+
+pub fn clamp_non_finite_distance(distance: f32) -> f32 {
+    clamp_non_finite(distance)
+}
+
+// What happens to zero-norm vectors when the configuration cannot score
+// them (cosine distance or unit-L2 normalization).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroVectorPolicy {
+    // Zero vectors are rejected when they are added.
+    #[default]
+    Reject,
+    // Stored but never searchable. They are skipped by search and export,
+    // and counted by ScannRetriever::num_quarantined.
+    Quarantine,
+}
+
+// Width of the internal row and docid indices. U32 halves their memory and
+// is chosen at construction whenever the dataset fits; public APIs always
+// take and return usize.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexWidth {
+    U32,
+    U64,
+}
+
+impl IndexWidth {
+    // Narrowest width that can index `n` rows.
+    pub fn for_size(n: usize) -> Self {
+        if n <= u32::MAX as usize {
+            IndexWidth::U32
+        } else {
+            IndexWidth::U64
+        }
+    }
+
+    pub fn max_index(self) -> usize {
+        match self {
+            IndexWidth::U32 => u32::MAX as usize,
+            IndexWidth::U64 => u64::MAX as usize,
+        }
+    }
+
+    pub fn fits(self, index: usize) -> bool {
+        index <= self.max_index()
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            IndexWidth::U32 => 4,
+            IndexWidth::U64 => 8,
+        }
+    }
+
+    pub fn from_bytes(bytes: usize) -> Option<Self> {
+        match bytes {
+            4 => Some(IndexWidth::U32),
+            8 => Some(IndexWidth::U64),
+            _ => None,
+        }
+    }
+}
+
+// What happens when an added row or docid no longer fits a U32 index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexOverflowPolicy {
+    // The index switches to U64 and the add succeeds.
+    #[default]
+    Upgrade,
+    // The add fails and the index is left unchanged.
+    Error,
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+// Storage type behind an IndexWidth; implemented only for u32 and u64.
+pub trait DatapointIndex: Copy + Ord + sealed::Sealed {
+    const WIDTH: IndexWidth;
+
+    fn from_usize(index: usize) -> Option<Self>;
+
+    fn to_usize(self) -> usize;
+
+    fn write_le(self, out: &mut Vec<u8>);
+
+    // `bytes` holds exactly WIDTH.bytes() bytes.
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+impl DatapointIndex for u32 {
+    const WIDTH: IndexWidth = IndexWidth::U32;
+
+    fn from_usize(index: usize) -> Option<Self> {
+        u32::try_from(index).ok()
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl DatapointIndex for u64 {
+    const WIDTH: IndexWidth = IndexWidth::U64;
+
+    fn from_usize(index: usize) -> Option<Self> {
+        Some(index as u64)
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+// Row indices stored at an IndexWidth: four bytes each under U32, eight
+// under U64. Pushing an index the width cannot hold widens the list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IndexList {
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+}
+
+impl Default for IndexList {
+    fn default() -> Self {
+        IndexList::U32(Vec::new())
+    }
+}
+
+impl IndexList {
+    pub fn new(width: IndexWidth) -> Self {
+        match width {
+            IndexWidth::U32 => IndexList::U32(Vec::new()),
+            IndexWidth::U64 => IndexList::U64(Vec::new()),
+        }
+    }
+
+    pub fn from_indices(width: IndexWidth, indices: impl IntoIterator<Item = usize>) -> Self {
+        let mut list = IndexList::new(width);
+        list.extend(indices);
+        list
+    }
+
+    pub fn width(&self) -> IndexWidth {
+        match self {
+            IndexList::U32(_) => IndexWidth::U32,
+            IndexList::U64(_) => IndexWidth::U64,
+        }
+    }
+
+    // Re-encodes the list at `width`. Fails, leaving the list unchanged, if
+    // an index does not fit.
+    pub fn set_width(&mut self, width: IndexWidth) -> Result<(), Box<dyn Error>> {
+        if self.width() == width {
+            return Ok(());
+        }
+        if let Some(index) = self.iter().find(|&i| !width.fits(i)) {
+            return Err(invalid_argument_error(&format!(
+                "Index {} does not fit a {:?} index",
+                index, width
+            )));
+        }
+        *self = IndexList::from_indices(width, self.iter());
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexList::U32(indices) => indices.len(),
+            IndexList::U64(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, position: usize) -> Option<usize> {
+        match self {
+            IndexList::U32(indices) => indices.get(position).map(|&i| i.to_usize()),
+            IndexList::U64(indices) => indices.get(position).map(|&i| i.to_usize()),
+        }
+    }
+
+    pub fn iter(&self) -> IndexListIter<'_> {
+        match self {
+            IndexList::U32(indices) => IndexListIter::U32(indices.iter()),
+            IndexList::U64(indices) => IndexListIter::U64(indices.iter()),
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.iter().any(|i| i == index)
+    }
+
+    pub fn to_vec(&self) -> Vec<usize> {
+        self.iter().collect()
+    }
+
+    pub fn push(&mut self, index: usize) {
+        if let IndexList::U32(indices) = self {
+            match u32::from_usize(index) {
+                Some(index) => return indices.push(index),
+                None => *self = IndexList::U64(indices.iter().map(|&i| i as u64).collect()),
+            }
+        }
+        if let IndexList::U64(indices) = self {
+            indices.push(index as u64);
+        }
+    }
+
+    pub fn extend(&mut self, indices: impl IntoIterator<Item = usize>) {
+        for index in indices {
+            self.push(index);
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        match self {
+            IndexList::U32(indices) => indices.retain(|&i| keep(i.to_usize())),
+            IndexList::U64(indices) => indices.retain(|&i| keep(i.to_usize())),
+        }
+    }
+
+    // Sorts ascending and drops repeated indices.
+    pub fn sort_dedup(&mut self) {
+        match self {
+            IndexList::U32(indices) => {
+                indices.sort_unstable();
+                indices.dedup();
+            }
+            IndexList::U64(indices) => {
+                indices.sort_unstable();
+                indices.dedup();
+            }
+        }
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.len() * self.width().bytes()
+    }
+}
+
+impl<'a> IntoIterator for &'a IndexList {
+    type Item = usize;
+    type IntoIter = IndexListIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub enum IndexListIter<'a> {
+    U32(std::slice::Iter<'a, u32>),
+    U64(std::slice::Iter<'a, u64>),
+}
+
+impl Iterator for IndexListIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            IndexListIter::U32(indices) => indices.next().map(|&i| i.to_usize()),
+            IndexListIter::U64(indices) => indices.next().map(|&i| i.to_usize()),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IndexListIter::U32(indices) => indices.size_hint(),
+            IndexListIter::U64(indices) => indices.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for IndexListIter<'_> {}
+
+// First id that occurs twice, for validating docid lists read from disk.
+pub fn first_duplicate(ids: &[usize]) -> Option<usize> {
+    let mut seen = std::collections::HashSet::with_capacity(ids.len());
+    ids.iter().copied().find(|&id| !seen.insert(id))
+}
+
+pub fn is_zero_vector(values: &[f32]) -> bool {
+    values.iter().all(|&v| v == 0.0)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    #[default]
+    None,
+    // Every stored vector is scaled to unit L2 norm as it is ingested.
+    UnitL2,
+}
+
+pub fn apply_normalization(values: Vec<f32>, normalization: Normalization) -> Result<Vec<f32>, Box<dyn Error>> {
+    match normalization {
+        Normalization::None => Ok(values),
+        Normalization::UnitL2 => {
+            let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+            if norm == 0.0 || !norm.is_finite() {
+                return Err(invalid_argument_error(&format!(
+                    "Cannot normalize a vector with L2 norm {}",
+                    norm
+                )));
+            }
+            Ok(values.into_iter().map(|v| v / norm).collect())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct DenseDataset<T> {
     pub data: Vec<Vec<T>>,
     pub dimensionality: usize,
@@ -81,6 +455,675 @@ impl<T: Clone> DenseDataset<T> {
     pub fn dimensionality(&self) -> usize {
         self.dimensionality
     }
+
+    // Borrowed view of row `i`; panics when `i` is out of range, like
+    // indexing `data` directly.
+    pub fn get_point(&self, i: usize) -> DatapointRef<'_, T> {
+        DatapointRef { values: &self.data[i] }
+    }
+}
+
+impl DenseDataset<f32> {
+    // Scales every row to unit L2 norm. Fails on the first zero or
+    // non-finite row, naming it, and leaves the dataset unchanged.
+    pub fn normalize_rows(&mut self) -> Result<(), Box<dyn Error>> {
+        let normalized = self
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                apply_normalization(row.clone(), Normalization::UnitL2)
+                    .map_err(|e| invalid_argument_error(&format!("Row {}: {}", i, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.data = normalized;
+        Ok(())
+    }
+}
+
+// Elements per SegmentedVec segment.
+pub const SEGMENT_LEN: usize = 1024;
+
+// A Vec split into SEGMENT_LEN-element segments behind Arcs. Clones share
+// every segment and a write copies only the segment it lands in, so
+// copy-on-write snapshots pay per touched segment rather than per element.
+#[derive(Clone, Debug)]
+pub struct SegmentedVec<T> {
+    segments: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> Default for SegmentedVec<T> {
+    fn default() -> Self {
+        SegmentedVec { segments: Vec::new(), len: 0 }
+    }
+}
+
+impl<T: Clone> SegmentedVec<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_vec(values: Vec<T>) -> Self {
+        let len = values.len();
+        let mut segments = Vec::with_capacity(len.div_ceil(SEGMENT_LEN));
+        let mut values = values.into_iter();
+        while segments.len() * SEGMENT_LEN < len {
+            segments.push(Arc::new(values.by_ref().take(SEGMENT_LEN).collect()));
+        }
+        SegmentedVec { segments, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.segments.get(i / SEGMENT_LEN).and_then(|segment| segment.get(i % SEGMENT_LEN))
+    }
+
+    pub fn push(&mut self, value: T) {
+        match self.segments.last_mut() {
+            Some(last) if last.len() < SEGMENT_LEN => Arc::make_mut(last).push(value),
+            _ => {
+                let mut segment = Vec::with_capacity(SEGMENT_LEN);
+                segment.push(value);
+                self.segments.push(Arc::new(segment));
+            }
+        }
+        self.len += 1;
+    }
+
+    // Replaces element `i`; panics when `i` is out of range.
+    pub fn set(&mut self, i: usize, value: T) {
+        assert!(i < self.len, "index {} out of range for length {}", i, self.len);
+        Arc::make_mut(&mut self.segments[i / SEGMENT_LEN])[i % SEGMENT_LEN] = value;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.segments.iter().flat_map(|segment| segment.iter())
+    }
+
+    // The segments in order; all but the last hold exactly SEGMENT_LEN
+    // elements.
+    pub fn segments(&self) -> impl Iterator<Item = &[T]> + '_ {
+        self.segments.iter().map(|segment| segment.as_slice())
+    }
+
+    // Elements start..end, which must lie within one segment: the range
+    // may not cross a multiple of SEGMENT_LEN.
+    pub fn slice(&self, start: usize, end: usize) -> &[T] {
+        assert!(start <= end && end <= self.len, "range {}..{} out of range for length {}", start, end, self.len);
+        if start == end {
+            return &[];
+        }
+        let segment = start / SEGMENT_LEN;
+        assert_eq!(segment, (end - 1) / SEGMENT_LEN, "range {}..{} crosses a segment boundary", start, end);
+        &self.segments[segment][start % SEGMENT_LEN..end - segment * SEGMENT_LEN]
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+
+    // Number of segments stored at the same address in both vectors.
+    pub fn shared_segments(&self, other: &SegmentedVec<T>) -> usize {
+        self.segments.iter().zip(other.segments.iter()).filter(|(a, b)| Arc::ptr_eq(a, b)).count()
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+impl<T: Clone> std::ops::Index<usize> for SegmentedVec<T> {
+    type Output = T;
+
+    fn index(&self, i: usize) -> &T {
+        self.get(i).unwrap_or_else(|| panic!("index {} out of range for length {}", i, self.len))
+    }
+}
+
+impl<T: Clone> FromIterator<T> for SegmentedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut segmented = SegmentedVec::new();
+        for value in iter {
+            segmented.push(value);
+        }
+        segmented
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for SegmentedVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+// A block of equal-width rows: separate row vectors, or one row-major flat
+// slice such as a region borrowed from a mapped file.
+#[derive(Debug)]
+pub enum RowBlock<'a, T> {
+    Rows(&'a [Vec<T>]),
+    Flat { values: &'a [T], dimensionality: usize, len: usize },
+}
+
+// Manual impls: derived ones would require T: Copy.
+impl<T> Clone for RowBlock<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for RowBlock<'_, T> {}
+
+impl<'a, T> RowBlock<'a, T> {
+    // `values` must hold exactly `len` rows of `dimensionality` values.
+    pub fn flat(values: &'a [T], dimensionality: usize, len: usize) -> Self {
+        assert_eq!(values.len(), dimensionality * len, "flat block of {} values is not {} x {}", values.len(), len, dimensionality);
+        RowBlock::Flat { values, dimensionality, len }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RowBlock::Rows(rows) => rows.len(),
+            RowBlock::Flat { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn row(&self, i: usize) -> &'a [T] {
+        match *self {
+            RowBlock::Rows(rows) => &rows[i],
+            RowBlock::Flat { values, dimensionality, len } => {
+                assert!(i < len, "row {} out of range for {} rows", i, len);
+                &values[i * dimensionality..(i + 1) * dimensionality]
+            }
+        }
+    }
+
+    // Rows start..end.
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        match *self {
+            RowBlock::Rows(rows) => RowBlock::Rows(&rows[start..end]),
+            RowBlock::Flat { values, dimensionality, len } => {
+                assert!(start <= end && end <= len, "rows {}..{} out of range for {} rows", start, end, len);
+                RowBlock::Flat {
+                    values: &values[start * dimensionality..end * dimensionality],
+                    dimensionality,
+                    len: end - start,
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a [T]> + 'a
+    where
+        T: 'a,
+    {
+        let block = *self;
+        (0..block.len()).map(move |i| block.row(i))
+    }
+}
+
+impl<'a, T> From<&'a [Vec<T>]> for RowBlock<'a, T> {
+    fn from(rows: &'a [Vec<T>]) -> Self {
+        RowBlock::Rows(rows)
+    }
+}
+
+impl<'a, T> From<&'a Vec<Vec<T>>> for RowBlock<'a, T> {
+    fn from(rows: &'a Vec<Vec<T>>) -> Self {
+        RowBlock::Rows(rows)
+    }
+}
+
+// Read-only row-major values owned elsewhere and shared by every dataset
+// that borrows them, e.g. a mapped blob section.
+pub trait SharedRows<T>: Send + Sync + fmt::Debug {
+    fn values(&self) -> &[T];
+}
+
+// One SegmentedDataset segment: rows on the heap, or rows start..start+len
+// of a shared flat source. Writing to a shared segment first copies it.
+#[derive(Clone, Debug)]
+enum RowSegment<T> {
+    Owned(Arc<Vec<Vec<T>>>),
+    Shared { source: Arc<dyn SharedRows<T>>, start: usize, len: usize },
+}
+
+impl<T: Clone> RowSegment<T> {
+    fn len(&self) -> usize {
+        match self {
+            RowSegment::Owned(rows) => rows.len(),
+            RowSegment::Shared { len, .. } => *len,
+        }
+    }
+
+    fn block(&self, dimensionality: usize) -> RowBlock<'_, T> {
+        match self {
+            RowSegment::Owned(rows) => RowBlock::Rows(rows),
+            RowSegment::Shared { source, start, len } => RowBlock::Flat {
+                values: &source.values()[start * dimensionality..(start + len) * dimensionality],
+                dimensionality,
+                len: *len,
+            },
+        }
+    }
+
+    fn make_owned(&mut self, dimensionality: usize) -> &mut Vec<Vec<T>> {
+        if let RowSegment::Shared { .. } = self {
+            let rows = self.block(dimensionality).iter().map(|row| row.to_vec()).collect();
+            *self = RowSegment::Owned(Arc::new(rows));
+        }
+        match self {
+            RowSegment::Owned(rows) => Arc::make_mut(rows),
+            RowSegment::Shared { .. } => unreachable!(),
+        }
+    }
+
+    fn same_storage(&self, other: &RowSegment<T>) -> bool {
+        match (self, other) {
+            (RowSegment::Owned(a), RowSegment::Owned(b)) => Arc::ptr_eq(a, b),
+            (
+                RowSegment::Shared { source: a, start: a_start, .. },
+                RowSegment::Shared { source: b, start: b_start, .. },
+            ) => Arc::ptr_eq(a, b) && a_start == b_start,
+            _ => false,
+        }
+    }
+}
+
+// DenseDataset counterpart split into SEGMENT_LEN-row segments, for
+// storage that is cloned on write. Clones share every segment and a write
+// copies only the segment it lands in. Segments may borrow a SharedRows
+// source, so a mapped file is read in place until a row is written.
+#[derive(Clone, Debug)]
+pub struct SegmentedDataset<T> {
+    segments: Vec<RowSegment<T>>,
+    len: usize,
+    pub dimensionality: usize,
+}
+
+impl<T: Clone> SegmentedDataset<T> {
+    pub fn from_dense(dataset: DenseDataset<T>) -> Self {
+        let len = dataset.data.len();
+        let mut segments = Vec::with_capacity(len.div_ceil(SEGMENT_LEN));
+        let mut rows = dataset.data.into_iter();
+        while segments.len() * SEGMENT_LEN < len {
+            segments.push(RowSegment::Owned(Arc::new(rows.by_ref().take(SEGMENT_LEN).collect())));
+        }
+        SegmentedDataset { segments, len, dimensionality: dataset.dimensionality }
+    }
+
+    // Borrows the first `len` rows of `source` without copying them.
+    pub fn from_shared(source: Arc<dyn SharedRows<T>>, len: usize, dimensionality: usize) -> Result<Self, Box<dyn Error>> {
+        if len.checked_mul(dimensionality) != Some(source.values().len()) {
+            return Err(invalid_argument_error(&format!(
+                "Shared rows hold {} values, expected {} x {}",
+                source.values().len(),
+                len,
+                dimensionality
+            )));
+        }
+        let segments = (0..len)
+            .step_by(SEGMENT_LEN)
+            .map(|start| RowSegment::Shared {
+                source: source.clone(),
+                start,
+                len: SEGMENT_LEN.min(len - start),
+            })
+            .collect();
+        Ok(SegmentedDataset { segments, len, dimensionality })
+    }
+
+    pub fn to_dense(&self) -> DenseDataset<T> {
+        DenseDataset::new(self.rows().map(|row| row.to_vec()).collect(), self.dimensionality)
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    pub fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    // Row `i`; panics when `i` is out of range.
+    pub fn row(&self, i: usize) -> &[T] {
+        assert!(i < self.len, "row {} out of range for {} rows", i, self.len);
+        self.segments[i / SEGMENT_LEN].block(self.dimensionality).row(i % SEGMENT_LEN)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        self.segments().flat_map(|block| block.iter())
+    }
+
+    // The segments in order; all but the last hold exactly SEGMENT_LEN
+    // rows.
+    pub fn segments(&self) -> impl Iterator<Item = RowBlock<'_, T>> + '_ {
+        self.segments.iter().map(|segment| segment.block(self.dimensionality))
+    }
+
+    // Rows start..end, which must lie within one segment: the range may not
+    // cross a multiple of SEGMENT_LEN.
+    pub fn block(&self, start: usize, end: usize) -> RowBlock<'_, T> {
+        assert!(start <= end && end <= self.len, "rows {}..{} out of range for {} rows", start, end, self.len);
+        if start == end {
+            return RowBlock::Rows(&[]);
+        }
+        let segment = start / SEGMENT_LEN;
+        assert_eq!(segment, (end - 1) / SEGMENT_LEN, "rows {}..{} cross a segment boundary", start, end);
+        self.segments[segment]
+            .block(self.dimensionality)
+            .slice(start % SEGMENT_LEN, end - segment * SEGMENT_LEN)
+    }
+
+    // Replaces row `i`, copying its segment if it is shared; panics when
+    // `i` is out of range.
+    pub fn set_row(&mut self, i: usize, values: &[T]) {
+        assert!(i < self.len, "row {} out of range for {} rows", i, self.len);
+        let dimensionality = self.dimensionality;
+        self.segments[i / SEGMENT_LEN].make_owned(dimensionality)[i % SEGMENT_LEN] = values.to_vec();
+    }
+
+    pub fn append(&mut self, values: &[T]) -> Result<(), Box<dyn Error>> {
+        if values.len() != self.dimensionality {
+            return Err(invalid_argument_error(&format!(
+                "Dimension mismatch: expected {}, got {}",
+                self.dimensionality,
+                values.len()
+            )));
+        }
+        let dimensionality = self.dimensionality;
+        match self.segments.last_mut() {
+            Some(last) if last.len() < SEGMENT_LEN => last.make_owned(dimensionality).push(values.to_vec()),
+            _ => {
+                let mut rows = Vec::with_capacity(SEGMENT_LEN);
+                rows.push(values.to_vec());
+                self.segments.push(RowSegment::Owned(Arc::new(rows)));
+            }
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    // Number of segments backed by the same storage in both datasets.
+    pub fn shared_segments(&self, other: &SegmentedDataset<T>) -> usize {
+        self.segments.iter().zip(other.segments.iter()).filter(|(a, b)| a.same_storage(b)).count()
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    // Rows read in place from a SharedRows source rather than held on the
+    // heap.
+    pub fn shared_rows(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| matches!(segment, RowSegment::Shared { .. }))
+            .map(RowSegment::len)
+            .sum()
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for SegmentedDataset<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.dimensionality == other.dimensionality && self.rows().eq(other.rows())
+    }
+}
+
+// Read access to rows by index, for code that accepts either dataset
+// layout.
+pub trait RowSource<T> {
+    fn num_rows(&self) -> usize;
+    fn dimensionality(&self) -> usize;
+    fn row(&self, i: usize) -> &[T];
+}
+
+impl<T: Clone> RowSource<T> for DenseDataset<T> {
+    fn num_rows(&self) -> usize {
+        self.data.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn row(&self, i: usize) -> &[T] {
+        &self.data[i]
+    }
+}
+
+impl<T: Clone> RowSource<T> for SegmentedDataset<T> {
+    fn num_rows(&self) -> usize {
+        self.len
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn row(&self, i: usize) -> &[T] {
+        SegmentedDataset::row(self, i)
+    }
+}
+
+pub const COLUMN_STATS_HISTOGRAM_BINS: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct ColumnStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    pub std: f64,
+    pub histogram: Vec<u64>,
+}
+
+impl ColumnStats {
+    // Approximate quantile read off the fixed-bin histogram, interpolating
+    // linearly inside the bin that contains the requested rank.
+    pub fn quantile(&self, q: f32) -> f32 {
+        let total: u64 = self.histogram.iter().sum();
+        if total == 0 || self.max <= self.min {
+            return self.min;
+        }
+        let q = q.clamp(0.0, 1.0) as f64;
+        let target = q * total as f64;
+        let bin_width = (self.max - self.min) as f64 / self.histogram.len() as f64;
+        let mut cumulative = 0.0f64;
+        for (bin, &count) in self.histogram.iter().enumerate() {
+            let next = cumulative + count as f64;
+            if next >= target && count > 0 {
+                let fraction = (target - cumulative) / count as f64;
+                return (self.min as f64 + (bin as f64 + fraction) * bin_width) as f32;
+            }
+            cumulative = next;
+        }
+        self.max
+    }
+}
+
+// Running moments of every column over a block of rows, merged across
+// blocks with Chan's update so the parallel build matches a sequential one
+// up to rounding.
+struct ColumnMoments {
+    count: usize,
+    min: Vec<f32>,
+    max: Vec<f32>,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+}
+
+impl ColumnMoments {
+    fn new(dim: usize) -> Self {
+        ColumnMoments {
+            count: 0,
+            min: vec![f32::INFINITY; dim],
+            max: vec![f32::NEG_INFINITY; dim],
+            mean: vec![0.0; dim],
+            m2: vec![0.0; dim],
+        }
+    }
+
+    fn over<T: ToF32Scalar>(rows: &[Vec<T>], dim: usize) -> Self {
+        let mut moments = ColumnMoments::new(dim);
+        for row in rows {
+            moments.count += 1;
+            let n = moments.count as f64;
+            for (d, &value) in row[..dim].iter().enumerate() {
+                let v = value.to_f32();
+                moments.min[d] = moments.min[d].min(v);
+                moments.max[d] = moments.max[d].max(v);
+                let delta = v as f64 - moments.mean[d];
+                moments.mean[d] += delta / n;
+                moments.m2[d] += delta * (v as f64 - moments.mean[d]);
+            }
+        }
+        moments
+    }
+
+    #[cfg(feature = "rayon")]
+    fn merge(mut self, other: ColumnMoments) -> Self {
+        if other.count == 0 {
+            return self;
+        }
+        if self.count == 0 {
+            return other;
+        }
+        let (a, b) = (self.count as f64, other.count as f64);
+        for d in 0..self.mean.len() {
+            self.min[d] = self.min[d].min(other.min[d]);
+            self.max[d] = self.max[d].max(other.max[d]);
+            let delta = other.mean[d] - self.mean[d];
+            self.mean[d] += delta * b / (a + b);
+            self.m2[d] += other.m2[d] + delta * delta * a * b / (a + b);
+        }
+        self.count += other.count;
+        self
+    }
+}
+
+// Histogram of every column over a block of rows. `lower` and `width` are
+// the per-column bin bounds, computed once from the merged moments.
+fn column_histograms<T: ToF32Scalar>(rows: &[Vec<T>], lower: &[f32], width: &[f32]) -> Vec<u64> {
+    let bins = COLUMN_STATS_HISTOGRAM_BINS;
+    let mut histograms = vec![0u64; lower.len() * bins];
+    for row in rows {
+        for (d, &value) in row[..lower.len()].iter().enumerate() {
+            let bin = (((value.to_f32() - lower[d]) / width[d]) * bins as f32) as usize;
+            histograms[d * bins + bin.min(bins - 1)] += 1;
+        }
+    }
+    histograms
+}
+
+// Rows per block handed to a worker when rayon is enabled.
+#[cfg(feature = "rayon")]
+const COLUMN_STATS_BLOCK_ROWS: usize = 4096;
+
+// Statistics of every column, reading rows in storage order: one pass for
+// the moments and range, then one for the histograms.
+fn compute_column_stats<T: ToF32Scalar + Send + Sync>(data: &[Vec<T>], dim: usize) -> Vec<ColumnStats> {
+    #[cfg(feature = "rayon")]
+    let moments = data
+        .par_chunks(COLUMN_STATS_BLOCK_ROWS)
+        .map(|rows| ColumnMoments::over(rows, dim))
+        .reduce(|| ColumnMoments::new(dim), ColumnMoments::merge);
+    #[cfg(not(feature = "rayon"))]
+    let moments = ColumnMoments::over(data, dim);
+
+    if data.is_empty() {
+        return (0..dim)
+            .map(|_| ColumnStats {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                std: 0.0,
+                histogram: vec![0; COLUMN_STATS_HISTOGRAM_BINS],
+            })
+            .collect();
+    }
+
+    let width: Vec<f32> = (0..dim)
+        .map(|d| (moments.max[d] - moments.min[d]).max(f32::MIN_POSITIVE))
+        .collect();
+    #[cfg(feature = "rayon")]
+    let histograms = data
+        .par_chunks(COLUMN_STATS_BLOCK_ROWS)
+        .map(|rows| column_histograms(rows, &moments.min, &width))
+        .reduce(
+            || vec![0u64; dim * COLUMN_STATS_HISTOGRAM_BINS],
+            |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            },
+        );
+    #[cfg(not(feature = "rayon"))]
+    let histograms = column_histograms(data, &moments.min, &width);
+
+    let count = moments.count as f64;
+    (0..dim)
+        .map(|d| ColumnStats {
+            min: moments.min[d],
+            max: moments.max[d],
+            mean: moments.mean[d],
+            std: (moments.m2[d] / count).sqrt(),
+            histogram: histograms[d * COLUMN_STATS_HISTOGRAM_BINS..(d + 1) * COLUMN_STATS_HISTOGRAM_BINS].to_vec(),
+        })
+        .collect()
+}
+
+impl<T: ToF32Scalar + Send + Sync> DenseDataset<T> {
+    pub fn column_stats(&self) -> Vec<ColumnStats> {
+        compute_column_stats(&self.data, self.dimensionality)
+    }
+}
+
+// Scalar types a datapoint may hold. Every distance widens or narrows
+// coordinates to f32 through this trait; the f64 conversion rounds, which
+// `Into<f32>` cannot express.
+pub trait ToF32Scalar: Copy {
+    fn to_f32(self) -> f32;
+
+    // The values themselves when they already are f32, so distances can run
+    // the slice kernels without converting.
+    fn as_f32_slice(_values: &[Self]) -> Option<&[f32]> {
+        None
+    }
+}
+
+macro_rules! impl_to_f32_scalar {
+    ($($t:ty),*) => {
+        $(impl ToF32Scalar for $t {
+            #[inline(always)]
+            fn to_f32(self) -> f32 {
+                self as f32
+            }
+        })*
+    };
+}
+
+// The `as` casts are exact for every type but f64, which rounds to nearest.
+impl_to_f32_scalar!(f64, i8, u8, i16, u16);
+
+impl ToF32Scalar for f32 {
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn as_f32_slice(values: &[f32]) -> Option<&[f32]> {
+        Some(values)
+    }
 }
 
 #[derive(Clone)]
@@ -89,21 +1132,369 @@ pub struct DatapointPtr<T> {
 }
 
 impl<T: Clone> DatapointPtr<T> {
+    // Unchecked; internal hot paths use this. Public entry points validate
+    // with `try_new` or `check_dimensionality`.
     pub fn new(values: Vec<T>) -> Self {
         DatapointPtr { values }
     }
 
+    pub fn try_new(values: Vec<T>, expected_dim: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let datapoint = DatapointPtr { values };
+        datapoint.check_dimensionality(expected_dim, "datapoint")?;
+        Ok(datapoint)
+    }
+
+    pub fn from_slice(values: &[T]) -> Self {
+        DatapointPtr { values: values.to_vec() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn dimensionality(&self) -> usize {
+        self.values.len()
+    }
+
+    // Rejects empty datapoints and, when given, a dimensionality other than
+    // `expected_dim`. `context` names the caller's argument in the error.
+    pub fn check_dimensionality(&self, expected_dim: Option<usize>, context: &str) -> Result<(), Box<dyn Error>> {
+        if self.values.is_empty() {
+            return Err(invalid_argument_error(&format!("Empty {}", context)));
+        }
+        if let Some(expected) = expected_dim {
+            if self.values.len() != expected {
+                return Err(invalid_argument_error(&format!(
+                    "{} has dimensionality {}, expected {}",
+                    context,
+                    self.values.len(),
+                    expected
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn values(&self) -> &[T] {
         &self.values
     }
 }
 
+// Zero-copy counterpart of DatapointPtr over a row that lives elsewhere,
+// typically in a DenseDataset.
+#[derive(Clone, Copy)]
+pub struct DatapointRef<'a, T> {
+    values: &'a [T],
+}
+
+impl<'a, T: Clone> DatapointRef<'a, T> {
+    pub fn from_slice(values: &'a [T]) -> Self {
+        DatapointRef { values }
+    }
+
+    pub fn dimensionality(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn values(&self) -> &'a [T] {
+        self.values
+    }
+
+    pub fn to_owned(&self) -> DatapointPtr<T> {
+        DatapointPtr::from_slice(self.values)
+    }
+}
+
+// Small deterministic generator so seeded training is reproducible across
+// platforms without pulling in an RNG crate.
+#[derive(Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn next_below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Seeded from the per-process hasher keys, for weights that only need
+    // to differ between instances.
+    pub fn from_entropy() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+        SplitMix64::new(std::collections::hash_map::RandomState::new().build_hasher().finish())
+    }
+
+    // Irwin-Hall approximation of a standard normal draw.
+    pub fn next_normal(&mut self) -> f32 {
+        (0..12).map(|_| self.next_f32()).sum::<f32>() - 6.0
+    }
+}
+
+pub fn random_normal_matrix(rows: usize, cols: usize, rng: &mut SplitMix64) -> DMatrix<f32> {
+    DMatrix::from_fn(rows, cols, |_, _| rng.next_normal())
+}
+
+// Cooperative cancellation for long-running searches. Clones share the flag,
+// so cancelling one cancels every search holding a clone (e.g. all shards of
+// a fanned-out request).
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_deadline(deadline: Instant) -> Self {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(deadline),
+        }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Wire encoding (binfmt::write_wire framing), version 1 payload:
+    //   flags u8 (bit 0 cancelled, bit 1 has deadline) | remaining_nanos u64
+    // Instants only mean something inside one process, so the deadline is
+    // sent as the time left and re-anchored on the reader's clock; time in
+    // transit is not subtracted. A decoded token has its own cancellation
+    // flag and does not observe cancel() on the sender's clones.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0u8;
+        if self.cancelled.load(Ordering::Relaxed) {
+            flags |= TOKEN_CANCELLED;
+        }
+        let remaining = match self.deadline {
+            Some(deadline) => {
+                flags |= TOKEN_HAS_DEADLINE;
+                let nanos = deadline.saturating_duration_since(Instant::now()).as_nanos();
+                nanos.min(u64::MAX as u128) as u64
+            }
+            None => 0,
+        };
+        let mut payload = vec![flags];
+        binfmt::ByteOrder(binfmt::Endianness::Little).put_u64(&mut payload, remaining);
+        binfmt::write_wire(binfmt::WIRE_KIND_CANCELLATION_TOKEN, TOKEN_WIRE_VERSION, &payload)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let name = "CancellationToken";
+        let (_, payload) = binfmt::read_wire(bytes, binfmt::WIRE_KIND_CANCELLATION_TOKEN, TOKEN_WIRE_VERSION, name)?;
+        let mut reader = binfmt::WireReader::new(payload, name);
+        let flags = reader.u8()?;
+        let remaining = reader.u64()?;
+        reader.finish()?;
+        if flags & !(TOKEN_CANCELLED | TOKEN_HAS_DEADLINE) != 0 {
+            return Err(invalid_argument_error(&format!("CancellationToken has unknown flags {:#04x}", flags)));
+        }
+        let deadline = match flags & TOKEN_HAS_DEADLINE {
+            0 if remaining != 0 => {
+                return Err(invalid_argument_error(
+                    "CancellationToken without a deadline has a nonzero remaining time",
+                ))
+            }
+            0 => None,
+            _ => Some(Instant::now().checked_add(Duration::from_nanos(remaining)).ok_or_else(|| {
+                invalid_argument_error(&format!("CancellationToken deadline {}ns away is out of range", remaining))
+            })?),
+        };
+        Ok(CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(flags & TOKEN_CANCELLED != 0)),
+            deadline,
+        })
+    }
+}
+
+const TOKEN_WIRE_VERSION: u8 = 1;
+const TOKEN_CANCELLED: u8 = 1;
+const TOKEN_HAS_DEADLINE: u8 = 2;
+
+// Epoch-stamped visited marker reused across queries. Starting a new query
+// only bumps the epoch, so no O(n) clear is needed except on wraparound.
+#[derive(Default)]
+pub struct VisitedSet {
+    stamps: Vec<u32>,
+    epoch: u32,
+}
+
+impl VisitedSet {
+    pub fn new() -> Self {
+        VisitedSet::default()
+    }
+
+    pub fn begin(&mut self, size: usize) {
+        if self.stamps.len() < size {
+            self.stamps.resize(size, 0);
+        }
+        self.epoch = self.epoch.wrapping_add(1);
+        if self.epoch == 0 {
+            self.stamps.iter_mut().for_each(|s| *s = 0);
+            self.epoch = 1;
+        }
+    }
+
+    // Returns true the first time `index` is seen in the current epoch.
+    pub fn insert(&mut self, index: usize) -> bool {
+        if self.stamps[index] == self.epoch {
+            return false;
+        }
+        self.stamps[index] = self.epoch;
+        true
+    }
+
+    // Calls `f` with the position and row of each entry of `rows` not yet
+    // seen in the current epoch, reading the rows at their stored width.
+    pub fn visit_new(&mut self, rows: &IndexList, mut f: impl FnMut(usize, usize)) {
+        match rows {
+            IndexList::U32(rows) => self.visit_new_at(rows, &mut f),
+            IndexList::U64(rows) => self.visit_new_at(rows, &mut f),
+        }
+    }
+
+    fn visit_new_at<I: DatapointIndex>(&mut self, rows: &[I], f: &mut impl FnMut(usize, usize)) {
+        for (position, &row) in rows.iter().enumerate() {
+            let row = row.to_usize();
+            if self.insert(row) {
+                f(position, row);
+            }
+        }
+    }
+}
+
+// Per-thread pool of transient search buffers. Buffers are taken at the
+// start of a search and handed back at the end; `reset` keeps their capacity
+// for the next search instead of freeing it. Buffers above
+// `max_retained_bytes` are released on reset so one huge query cannot pin
+// memory for the life of the thread.
+pub struct SearchArena {
+    candidates: Vec<Vec<(usize, f32)>>,
+    floats: Vec<Vec<f32>>,
+    indices: Vec<Vec<usize>>,
+    max_retained_bytes: usize,
+    high_water_bytes: usize,
+}
+
+pub const DEFAULT_ARENA_MAX_RETAINED_BYTES: usize = 64 << 20;
+
+impl Default for SearchArena {
+    fn default() -> Self {
+        SearchArena::new(DEFAULT_ARENA_MAX_RETAINED_BYTES)
+    }
+}
+
+impl SearchArena {
+    pub fn new(max_retained_bytes: usize) -> Self {
+        SearchArena {
+            candidates: Vec::new(),
+            floats: Vec::new(),
+            indices: Vec::new(),
+            max_retained_bytes,
+            high_water_bytes: 0,
+        }
+    }
+
+    pub fn take_candidates(&mut self) -> Vec<(usize, f32)> {
+        self.candidates.pop().unwrap_or_default()
+    }
+
+    pub fn take_floats(&mut self) -> Vec<f32> {
+        self.floats.pop().unwrap_or_default()
+    }
+
+    pub fn take_indices(&mut self) -> Vec<usize> {
+        self.indices.pop().unwrap_or_default()
+    }
+
+    pub fn recycle_candidates(&mut self, mut buffer: Vec<(usize, f32)>) {
+        buffer.clear();
+        self.candidates.push(buffer);
+    }
+
+    pub fn recycle_floats(&mut self, mut buffer: Vec<f32>) {
+        buffer.clear();
+        self.floats.push(buffer);
+    }
+
+    pub fn recycle_indices(&mut self, mut buffer: Vec<usize>) {
+        buffer.clear();
+        self.indices.push(buffer);
+    }
+
+    // Capacity currently held by pooled buffers.
+    pub fn retained_bytes(&self) -> usize {
+        fn bytes<T>(pool: &[Vec<T>]) -> usize {
+            pool.iter().map(|b| b.capacity() * std::mem::size_of::<T>()).sum()
+        }
+        bytes(&self.candidates) + bytes(&self.floats) + bytes(&self.indices)
+    }
+
+    // Largest `retained_bytes` observed at a reset.
+    pub fn high_water_bytes(&self) -> usize {
+        self.high_water_bytes
+    }
+
+    // Ends a search. Pooled buffers keep their capacity unless the pool has
+    // grown past `max_retained_bytes`, in which case the largest are dropped.
+    pub fn reset(&mut self) {
+        self.high_water_bytes = self.high_water_bytes.max(self.retained_bytes());
+        while self.retained_bytes() > self.max_retained_bytes {
+            let largest = [
+                self.candidates.iter().map(|b| b.capacity() * std::mem::size_of::<(usize, f32)>()).max(),
+                self.floats.iter().map(|b| b.capacity() * std::mem::size_of::<f32>()).max(),
+                self.indices.iter().map(|b| b.capacity() * std::mem::size_of::<usize>()).max(),
+            ];
+            let pool = (0..3).max_by_key(|&p| largest[p].unwrap_or(0)).unwrap();
+            match pool {
+                0 => drop_largest(&mut self.candidates),
+                1 => drop_largest(&mut self.floats),
+                _ => drop_largest(&mut self.indices),
+            }
+        }
+    }
+}
+
+fn drop_largest<T>(pool: &mut Vec<Vec<T>>) {
+    if let Some(index) = (0..pool.len()).max_by_key(|&i| pool[i].capacity()) {
+        pool.swap_remove(index);
+    }
+}
+
 // New: Matrix utilities for RETRO
-pub fn dot_product<T: Copy + Into<f32>>(a: &DatapointPtr<T>, b: &DatapointPtr<T>) -> f32 {
+pub fn dot_product<T: ToF32Scalar>(a: &DatapointPtr<T>, b: &DatapointPtr<T>) -> f32 {
     a.values()
         .iter()
         .zip(b.values().iter())
-        .map(|(&x, &y)| x.into() * y.into())
+        .map(|(&x, &y)| x.to_f32() * y.to_f32())
         .sum()
 }
 
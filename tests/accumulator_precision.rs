@@ -0,0 +1,106 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! f32 vs f64 accumulation on dot products whose large terms cancel.
+
+use scann::distance_measures::{AccumulatorPrecision, DistanceMeasure, DotProductDistance, SquaredL2Distance};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+use std::sync::Arc;
+
+const DIM: usize = 17;
+
+// A leading small term followed by eight +1e8 and eight -1e8 terms. The
+// exact dot with an all-ones query is `small`, but f32 absorbs it into the
+// first 1e8 in any summation order, so every row scores 0.
+fn cancelling_row(small: f32) -> Vec<f32> {
+    let mut row = vec![small];
+    row.extend(std::iter::repeat_n(1e8, 8));
+    row.extend(std::iter::repeat_n(-1e8, 8));
+    row
+}
+
+fn rows() -> Vec<Vec<f32>> {
+    vec![cancelling_row(1.0), cancelling_row(3.0), cancelling_row(2.0), cancelling_row(4.0)]
+}
+
+fn exact_dot(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum()
+}
+
+fn docids(results: &[(usize, f32)]) -> Vec<usize> {
+    results.iter().map(|&(docid, _)| docid).collect()
+}
+
+#[test]
+fn f64_accumulation_recovers_terms_that_f32_cancels() {
+    let query = vec![1.0; DIM];
+    let measure = DotProductDistance::new();
+    for row in rows() {
+        let exact = -exact_dot(&query, &row) as f32;
+        assert_eq!(measure.compute_distance_with_precision(&query, &row, AccumulatorPrecision::F64), exact);
+        assert_ne!(measure.compute_distance_with_precision(&query, &row, AccumulatorPrecision::F32), exact);
+    }
+    assert_eq!(AccumulatorPrecision::default(), AccumulatorPrecision::F32);
+}
+
+#[test]
+fn f64_search_produces_the_exact_ordering_and_f32_does_not() {
+    let retriever = ScannRetriever::new(DenseDataset::new(rows(), DIM), Box::new(DotProductDistance::new()), 4);
+    let query = DatapointPtr::new(vec![1.0; DIM]);
+
+    let mut expected: Vec<(usize, f64)> =
+        rows().iter().enumerate().map(|(i, row)| (i, -exact_dot(query.values(), row))).collect();
+    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let expected: Vec<usize> = expected.into_iter().map(|(docid, _)| docid).collect();
+    assert_eq!(expected, vec![3, 1, 2, 0]);
+
+    let f64_options = SearchOptions { accumulator_precision: AccumulatorPrecision::F64, ..SearchOptions::default() };
+    let (results, _) = retriever.search_with_options(&query, &f64_options).unwrap();
+    assert_eq!(docids(&results), expected);
+    assert_eq!(results[0].1, -4.0);
+
+    // Every row ties at the cancelled score under f32.
+    let (results, _) = retriever.search_with_options(&query, &SearchOptions::default()).unwrap();
+    assert!(results.iter().all(|&(_, distance)| distance == results[0].1), "{:?}", results);
+    assert_ne!(docids(&results), expected);
+}
+
+#[test]
+fn rescoring_precision_applies_independently_of_first_pass_precision() {
+    // The primary index is a plain 1-d line; reranking uses the cancelling
+    // rows under a dot product.
+    let primary: Vec<Vec<f32>> = (0..4).map(|i| vec![i as f32]).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(primary, 1), Box::new(SquaredL2Distance::new()), 4);
+    retriever
+        .attach_rescoring_dataset(Arc::new(DenseDataset::new(rows(), DIM)), Box::new(DotProductDistance::new()))
+        .unwrap();
+    let query = DatapointPtr::new(vec![0.0]);
+    let rescore = |first_pass, rescoring| SearchOptions {
+        rescore_with_attached: true,
+        rescoring_query: Some(vec![1.0; DIM]),
+        accumulator_precision: first_pass,
+        rescoring_precision: rescoring,
+        ..SearchOptions::default()
+    };
+
+    for first_pass in [AccumulatorPrecision::F32, AccumulatorPrecision::F64] {
+        let options = rescore(first_pass, AccumulatorPrecision::F64);
+        let (results, _) = retriever.search_with_options(&query, &options).unwrap();
+        assert_eq!(docids(&results), vec![3, 1, 2, 0]);
+        let options = rescore(first_pass, AccumulatorPrecision::F32);
+        let (results, _) = retriever.search_with_options(&query, &options).unwrap();
+        assert!(results.iter().all(|&(_, distance)| distance == results[0].1), "{:?}", results);
+    }
+}
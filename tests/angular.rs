@@ -0,0 +1,97 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! AngularDistance: angles in radians, the zero-vector sentinel, and the
+//! same rankings as CosineDistance.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{self, AngularDistance, CosineDistance, DistanceMeasure};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, ZeroVectorPolicy};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3, PI};
+
+const DIM: usize = 8;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= 1e-5
+}
+
+#[test]
+fn known_angles() {
+    let measure = AngularDistance::new();
+    let angle = |a: &[f32], b: &[f32]| measure.compute_distance_f32(a, b);
+    assert!(close(angle(&[1.0, 0.0], &[3.0, 0.0]), 0.0));
+    assert!(close(angle(&[1.0, 0.0], &[0.0, 2.0]), FRAC_PI_2));
+    assert!(close(angle(&[1.0, 0.0], &[-1.0, 0.0]), PI));
+    assert!(close(angle(&[1.0, 0.0], &[0.5, 0.75f32.sqrt()]), FRAC_PI_3));
+    // Zero vectors have no direction and sit at a right angle to everything.
+    assert_eq!(angle(&[0.0, 0.0], &[1.0, 2.0]), FRAC_PI_2);
+    assert_eq!(angle(&[1.0, 2.0], &[0.0, 0.0]), FRAC_PI_2);
+    assert_eq!(angle(&[0.0, 0.0], &[0.0, 0.0]), FRAC_PI_2);
+    let by_name = distance_measures::get_distance_measure_by_name("AngularDistance").unwrap();
+    assert_eq!(by_name.name(), "AngularDistance");
+}
+
+#[test]
+fn angle_is_acos_of_cosine_similarity_on_every_path() {
+    let measure = AngularDistance::new();
+    let rows = random_rows(64, DIM, 1);
+    let query = &random_rows(1, DIM, 2)[0];
+    let mut batch = vec![0.0; rows.len()];
+    measure.compute_one_to_many(&DatapointPtr::new(query.clone()), &DenseDataset::new(rows.clone(), DIM), &mut batch);
+    for (row, &batched) in rows.iter().zip(&batch) {
+        let expected = (1.0 - CosineDistance::new().compute_distance_f32(query, row)).clamp(-1.0, 1.0).acos();
+        assert!(close(measure.compute_distance_f32(query, row), expected));
+        assert!(close(measure.compute_distance_f64_accumulated(query, row), expected));
+        assert!(close(batched, expected));
+        let generic = measure.compute_distance(&DatapointPtr::new(query.clone()), &DatapointPtr::new(row.clone()));
+        assert!(close(generic, expected));
+        assert!((0.0..=PI).contains(&batched));
+        // Angles ignore scale.
+        let scaled: Vec<f32> = row.iter().map(|x| x * 7.5).collect();
+        assert!(close(measure.compute_distance_f32(query, &scaled), expected));
+    }
+}
+
+#[test]
+fn retriever_returns_the_same_top_k_as_cosine() {
+    let rows = random_rows(1000, DIM, 3);
+    let angular = ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), Box::new(AngularDistance::new()), 10);
+    let cosine = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(CosineDistance::new()), 10);
+    for query in random_rows(25, DIM, 4) {
+        let query = DatapointPtr::new(query);
+        let by_angle = angular.search(&query).unwrap();
+        let by_cosine = cosine.search(&query).unwrap();
+        let ids = |results: &[(usize, f32)]| results.iter().map(|&(docid, _)| docid).collect::<Vec<_>>();
+        assert_eq!(ids(&by_angle), ids(&by_cosine));
+        for (&(_, angle), &(_, cosine)) in by_angle.iter().zip(&by_cosine) {
+            assert!(close(angle, (1.0 - cosine).clamp(-1.0, 1.0).acos()));
+        }
+    }
+}
+
+#[test]
+fn zero_rows_are_quarantined_like_cosine() {
+    let mut rows = random_rows(20, DIM, 5);
+    rows[3] = vec![0.0; DIM];
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(AngularDistance::new()), 20)
+        .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+        .unwrap();
+    assert_eq!(retriever.num_quarantined(), 1);
+    let results = retriever.search(&DatapointPtr::new(vec![1.0; DIM])).unwrap();
+    assert_eq!(results.len(), 19);
+    assert!(results.iter().all(|&(docid, distance)| docid != 3 && !distance.is_nan()));
+}
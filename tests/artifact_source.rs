@@ -0,0 +1,146 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Root-confined filesystem artifacts and the in-memory source.
+
+mod common;
+use common::scratch_dir;
+
+use scann::artifact_source::{self, ArtifactSource, FsArtifactSource, MemoryArtifactSource};
+use scann::assets;
+use scann::npy;
+use scann::proto::{AssetType, ScannAsset, ScannAssets};
+use scann::util::DenseDataset;
+use std::io::Read;
+use std::path::PathBuf;
+
+// A scratch directory with an empty `artifacts` root inside it.
+fn scratch_root(name: &str) -> PathBuf {
+    let dir = scratch_dir(name);
+    std::fs::create_dir_all(dir.join("artifacts")).unwrap();
+    dir
+}
+
+fn dataset() -> DenseDataset<f32> {
+    DenseDataset::new((0..6).map(|i| vec![i as f32, -(i as f32), 0.5]).collect(), 3)
+}
+
+#[test]
+fn manifests_cannot_reach_outside_the_root() {
+    let dir = scratch_root("traversal");
+    std::fs::write(dir.join("secret.txt"), b"outside").unwrap();
+    let source = FsArtifactSource::new(dir.join("artifacts")).unwrap();
+
+    let asset = ScannAsset {
+        asset_type: AssetType::DatasetNpy,
+        asset_path: "../../etc/passwd".to_string(),
+    };
+    let message = assets::open_asset(&source, &asset).err().unwrap().to_string();
+    assert!(message.contains("must be a relative path inside the artifacts root"), "{}", message);
+
+    for name in ["../secret.txt", "/etc/passwd", "nested/../../secret.txt", ""] {
+        assert!(source.open(name).is_err(), "{}", name);
+        assert!(source.create(name).is_err(), "{}", name);
+        assert!(!source.exists(name), "{}", name);
+    }
+    assert_eq!(std::fs::read(dir.join("secret.txt")).unwrap(), b"outside");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_escaping_the_root_are_rejected() {
+    let dir = scratch_root("symlink");
+    std::fs::create_dir_all(dir.join("outside")).unwrap();
+    std::fs::write(dir.join("outside/secret.txt"), b"outside").unwrap();
+    std::os::unix::fs::symlink(dir.join("outside"), dir.join("artifacts/escape")).unwrap();
+    let source = FsArtifactSource::new(dir.join("artifacts")).unwrap();
+
+    let message = source.open("escape/secret.txt").err().unwrap().to_string();
+    assert!(message.contains("resolves outside the artifacts root"), "{}", message);
+    assert!(source.create("escape/new.txt").is_err());
+    assert!(!dir.join("outside/new.txt").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filesystem_source_reads_and_writes_inside_the_root() {
+    let dir = scratch_root("fs");
+    let source = FsArtifactSource::new(dir.join("artifacts")).unwrap();
+    npy::write_dataset(&source, "dataset.npy", &dataset(), None).unwrap();
+    assert!(source.exists("dataset.npy") && source.exists("./dataset.npy"));
+    assert!(dir.join("artifacts/dataset.npy").exists());
+    assert_eq!(npy::read_dataset(&source, "dataset.npy").unwrap().data, dataset().data);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn full_save_load_cycle_in_memory() {
+    let source = MemoryArtifactSource::new();
+    npy::write_dataset(&source, "dataset.npy", &dataset(), None).unwrap();
+    artifact_source::write_artifact(&source, "dp_norms.npy", b"norms").unwrap();
+    let manifest = assets::populate_and_save_assets(&source).unwrap();
+
+    let listed: Vec<(AssetType, &str)> =
+        manifest.assets.iter().map(|a| (a.asset_type, a.asset_path.as_str())).collect();
+    assert_eq!(listed, vec![(AssetType::Int8NormsNpy, "dp_norms.npy"), (AssetType::DatasetNpy, "dataset.npy")]);
+    let text = String::from_utf8(artifact_source::read_artifact(&source, "scann_assets.pbtxt").unwrap()).unwrap();
+    assert_eq!(text, manifest.to_string());
+    assert_eq!(assets::load_assets(&source).unwrap(), manifest);
+
+    // Everything listed in the manifest loads back through the same source.
+    let dataset_asset = manifest.assets.iter().find(|a| a.asset_type == AssetType::DatasetNpy).unwrap();
+    let mut bytes = Vec::new();
+    assets::open_asset(&source, dataset_asset).unwrap().read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, npy::encode_npy_f32(&dataset()));
+    assert_eq!(npy::read_dataset(&source, &dataset_asset.asset_path).unwrap().data, dataset().data);
+
+    // Clones share files; nothing touched the filesystem.
+    let clone = source.clone();
+    assert!(clone.exists("scann_assets.pbtxt"));
+    assert!(source.names().contains(&"dataset.npy".to_string()));
+    assert!(clone.open("missing.npy").err().unwrap().to_string().contains("No in-memory artifact named missing.npy"));
+    assert!(source.open("../dataset.npy").is_err() && !source.exists("../dataset.npy"));
+}
+
+#[test]
+fn text_manifests_parse_back() {
+    let manifest = ScannAssets {
+        assets: vec![
+            ScannAsset { asset_type: AssetType::Partitioner, asset_path: "dir/serialized partitioner.pb".to_string() },
+            ScannAsset { asset_type: AssetType::DatasetNpy, asset_path: "quote\"back\\slash\ttab\u{7f}".to_string() },
+        ],
+    };
+    assert_eq!(ScannAssets::parse_text(&manifest.to_string()).unwrap(), manifest);
+    // Field order, line breaks, comments and the optional colon are free.
+    let compact = "# written by hand\nassets: { asset_path: \"a.npy\" asset_type: DATASET_NPY }";
+    let parsed = ScannAssets::parse_text(compact).unwrap();
+    assert_eq!(parsed.assets[0].asset_type, AssetType::DatasetNpy);
+    assert_eq!(parsed.assets[0].asset_path, "a.npy");
+    assert_eq!(ScannAssets::parse_text("").unwrap(), ScannAssets::default());
+
+    for bad in [
+        "assets {",
+        "assets { asset_type: NOT_A_TYPE }",
+        "assets { asset_path: unquoted }",
+        "assets { asset_path: \"unterminated }",
+        "assets { asset_path: \"\\q\" }",
+        "assets { size: 3 }",
+        "asset { }",
+        "assets { } }",
+    ] {
+        assert!(ScannAssets::parse_text(bad).is_err(), "{}", bad);
+    }
+}
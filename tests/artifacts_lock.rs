@@ -0,0 +1,141 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Artifacts locking: loads racing a rewriting writer never mix generations,
+//! manifests track generations, and loads that cannot take the lock fail
+//! with a typed error unless unlocked loading is opted into.
+
+mod common;
+
+use common::scratch_dir;
+use scann::artifacts::{self, ArtifactsConfig, ArtifactsLockOptions, ArtifactsLockUnavailable};
+use scann::util::{DenseDataset, Normalization};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const GENERATIONS: usize = 12;
+
+fn config() -> ArtifactsConfig {
+    ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: 2,
+    }
+}
+
+// Generation `g` holds 2 + g % 5 rows of [g, g] under docids 1000 * g + i,
+// so every file of a load identifies the generation it came from.
+fn save_generation(dir: &std::path::Path, g: usize) {
+    let n = 2 + g % 5;
+    let dataset = DenseDataset::new(vec![vec![g as f32; 2]; n], 2);
+    let docids: Vec<usize> = (0..n).map(|i| 1000 * g + i).collect();
+    artifacts::save_artifacts(dir, &config(), &dataset, &docids).unwrap();
+}
+
+#[test]
+fn loads_racing_a_writer_never_mix_generations() {
+    let dir = scratch_dir("race");
+    save_generation(&dir, 0);
+    let done = AtomicBool::new(false);
+    let loads = thread::scope(|scope| {
+        scope.spawn(|| {
+            for g in 1..=GENERATIONS {
+                save_generation(&dir, g);
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+        let reader = scope.spawn(|| {
+            let mut loads = 0;
+            let mut last = 0;
+            while !done.load(Ordering::SeqCst) || loads == 0 {
+                let loaded = artifacts::load_artifacts(&dir).unwrap();
+                let g = loaded.dataset.data[0][0] as usize;
+                assert!(g >= last, "generation went back from {} to {}", last, g);
+                assert_eq!(loaded.dataset.size(), 2 + g % 5);
+                assert!(loaded.dataset.data.iter().all(|row| row == &[g as f32; 2]), "mixed rows in generation {}", g);
+                assert_eq!(loaded.docids, (0..2 + g % 5).map(|i| 1000 * g + i).collect::<Vec<_>>());
+                last = g;
+                loads += 1;
+                // Leave the writer a window between shared locks.
+                thread::sleep(Duration::from_millis(1));
+            }
+            loads
+        });
+        reader.join().unwrap()
+    });
+    assert!(loads > 0);
+    let last = artifacts::load_artifacts(&dir).unwrap();
+    assert_eq!(last.dataset.data[0][0], GENERATIONS as f32);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn manifests_count_generations_and_catch_changed_files() {
+    let dir = scratch_dir("manifest");
+    save_generation(&dir, 1);
+    let first = artifacts::read_manifest(&dir).unwrap().unwrap();
+    save_generation(&dir, 2);
+    let second = artifacts::read_manifest(&dir).unwrap().unwrap();
+    assert_eq!(second.generation, first.generation + 1);
+    assert!(second.files.iter().any(|(name, _)| name == "dataset.npy"), "{:?}", second.files);
+    assert_ne!(first.files, second.files);
+
+    // A file rewritten behind the writer's back no longer matches the
+    // manifest, so every load attempt is rejected.
+    std::fs::write(dir.join("docids.txt"), "2000 2001 2002\n").unwrap();
+    let options = ArtifactsLockOptions {
+        max_load_attempts: 2,
+        ..ArtifactsLockOptions::default()
+    };
+    let error = artifacts::load_artifacts_with(&dir, &options).err().unwrap();
+    assert!(error.to_string().contains("changed during each of 2 load attempts"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_unopenable_lock_fails_the_load_unless_unlocked_is_allowed() {
+    let dir = std::env::temp_dir().join(format!("scann_artifacts_lock_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: 2,
+    };
+    let dataset = DenseDataset::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]], 2);
+    artifacts::save_artifacts(&dir, &config, &dataset, &[7, 9]).unwrap();
+    // A directory where the lock file belongs cannot be opened for writing,
+    // even by a privileged user.
+    let lock = dir.join(".artifacts.lock");
+    std::fs::remove_file(&lock).unwrap();
+    std::fs::create_dir(&lock).unwrap();
+
+    let error = artifacts::load_artifacts(&dir).err().expect("load without a lock must fail");
+    let unavailable = error.downcast_ref::<ArtifactsLockUnavailable>().expect("typed lock error");
+    assert_eq!(unavailable.path, lock);
+    assert!(error.to_string().contains("allow_unlocked"), "{}", error);
+
+    let options = ArtifactsLockOptions {
+        allow_unlocked: true,
+        ..ArtifactsLockOptions::default()
+    };
+    let loaded = artifacts::load_artifacts_with(&dir, &options).unwrap();
+    assert_eq!(loaded.docids, [7, 9]);
+    assert_eq!(loaded.dataset.data, dataset.data);
+
+    // Saving always needs the exclusive lock.
+    let save = artifacts::save_artifacts_with(&dir, &config, &dataset, &[7, 9], &options);
+    assert!(save.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
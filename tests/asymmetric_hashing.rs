@@ -0,0 +1,171 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Asymmetric hashing over PCA output: balancing the chunking projection's
+//! blocks by eigenvalue mass evens out their variance and improves recall.
+
+use scann::asymmetric_hashing::{train_asymmetric_hasher, AsymmetricHasherConfig};
+use scann::projection::{balanced_block_assignment, block_masses, ChunkingProjection, PcaProjection};
+use scann::util::{DenseDataset, SplitMix64};
+use std::collections::HashSet;
+
+const DIM: usize = 16;
+const NUM_BLOCKS: usize = 4;
+
+// Gaussian rows whose variance decays geometrically across latent
+// directions, mixed by a random matrix so every input dimension is
+// correlated with the others.
+fn correlated(n: usize, seed: u64) -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(99);
+    let mixing: Vec<Vec<f32>> = (0..DIM).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    let mut rng = SplitMix64::new(seed);
+    let rows = (0..n)
+        .map(|_| {
+            let latent: Vec<f32> = (0..DIM).map(|i| rng.next_normal() * 0.75f32.powi(i as i32)).collect();
+            (0..DIM)
+                .map(|j| (0..DIM).map(|i| latent[i] * mixing[i][j]).sum())
+                .collect()
+        })
+        .collect();
+    DenseDataset::new(rows, DIM)
+}
+
+fn spread(masses: &[f64]) -> f64 {
+    let max = masses.iter().cloned().fold(f64::MIN, f64::max);
+    let min = masses.iter().cloned().fold(f64::MAX, f64::min);
+    max / min
+}
+
+fn trained_pca(data: &DenseDataset<f32>) -> PcaProjection<f32> {
+    let mut pca = PcaProjection::<f32>::new(DIM as i32, DIM as i32).unwrap();
+    pca.create(data, true, None).unwrap();
+    pca
+}
+
+// Variance of every block of the projected data, summed over its
+// dimensions.
+fn block_variances(chunking: &ChunkingProjection, data: &DenseDataset<f32>) -> Vec<f64> {
+    let projected = chunking.project_dataset(data).unwrap();
+    let n = projected.size() as f64;
+    chunking
+        .block_ranges()
+        .into_iter()
+        .map(|range| {
+            range
+                .map(|d| {
+                    let mean = projected.data.iter().map(|row| row[d] as f64).sum::<f64>() / n;
+                    projected.data.iter().map(|row| (row[d] as f64 - mean).powi(2)).sum::<f64>() / n
+                })
+                .sum()
+        })
+        .collect()
+}
+
+#[test]
+fn balanced_blocks_have_a_smaller_variance_spread() {
+    let data = correlated(2000, 1);
+    let pca = trained_pca(&data);
+    let eigenvalues = pca.eigenvalues().unwrap().to_vec();
+    let contiguous: Vec<usize> = (0..DIM).collect();
+    let permutation = balanced_block_assignment(&eigenvalues, NUM_BLOCKS).unwrap();
+    let naive_spread = spread(&block_masses(&eigenvalues, &contiguous, NUM_BLOCKS));
+    let balanced_spread = spread(&block_masses(&eigenvalues, &permutation, NUM_BLOCKS));
+    assert!(balanced_spread < naive_spread / 10.0, "{} vs {}", balanced_spread, naive_spread);
+
+    // The projection applies the same permutation, so the measured
+    // per-block variance of its output shrinks the same way.
+    let naive = ChunkingProjection::with_pca(trained_pca(&data), NUM_BLOCKS, false).unwrap();
+    let balanced = ChunkingProjection::with_pca(pca, NUM_BLOCKS, true).unwrap();
+    assert_eq!(balanced.permutation(), permutation.as_slice());
+    assert_eq!(naive.permutation(), contiguous.as_slice());
+    let naive_variances = block_variances(&naive, &data);
+    let balanced_variances = block_variances(&balanced, &data);
+    assert!(spread(&balanced_variances) < spread(&naive_variances) / 10.0);
+    for (measured, mass) in balanced_variances.iter().zip(block_masses(&eigenvalues, &permutation, NUM_BLOCKS)) {
+        assert!((measured - mass).abs() <= 1e-3 * mass, "{} vs {}", measured, mass);
+    }
+}
+
+fn recall(balance_blocks: bool) -> f32 {
+    let data = correlated(3000, 2);
+    let queries = correlated(100, 3);
+    let mut config = AsymmetricHasherConfig::new(NUM_BLOCKS, 16);
+    config.pca_dims = Some(DIM);
+    config.balance_blocks = balance_blocks;
+    let hasher = train_asymmetric_hasher(&data, &config).unwrap();
+    let codes = hasher.encode_dataset(&data).unwrap();
+    let docids = (0..data.size()).collect();
+    let exact = scann::evaluation::exact_ground_truth(&data, docids, "SquaredL2Distance", &queries, 10).unwrap();
+    let mut found = 0;
+    for (query, truth) in queries.data.iter().zip(exact.iter()) {
+        let truth: HashSet<usize> = truth.iter().copied().collect();
+        let results = hasher.search(&codes, query, 10).unwrap();
+        found += results.iter().filter(|(i, _)| truth.contains(i)).count();
+    }
+    found as f32 / (10 * queries.size()) as f32
+}
+
+#[test]
+fn balanced_blocks_improve_pq_recall_on_correlated_data() {
+    let naive = recall(false);
+    let balanced = recall(true);
+    assert!(balanced > naive + 0.1, "balanced {} vs contiguous {}", balanced, naive);
+}
+
+#[test]
+fn the_trained_projection_round_trips_through_its_proto() {
+    let data = correlated(500, 4);
+    let mut config = AsymmetricHasherConfig::new(NUM_BLOCKS, 8);
+    config.pca_dims = Some(12);
+    let hasher = train_asymmetric_hasher(&data, &config).unwrap();
+    let chunking = hasher.projection();
+    assert_eq!(chunking.projected_dims(), 12);
+    assert_eq!(hasher.codebooks().len(), NUM_BLOCKS);
+    assert!(hasher.codebooks().iter().all(|codebook| codebook.size() == 8 && codebook.dimensionality() == 3));
+
+    let serialized = chunking.serialize_to_proto();
+    let restored = ChunkingProjection::create_from_serialized(&serialized, DIM).unwrap();
+    assert_eq!(restored.permutation(), chunking.permutation());
+    for row in data.data.iter().take(20) {
+        let (expected, actual) = (chunking.project(row).unwrap(), restored.project(row).unwrap());
+        assert!(expected.iter().zip(actual.iter()).all(|(a, b)| (a - b).abs() <= 1e-5));
+    }
+    let projected = chunking.project_dataset(&data).unwrap();
+    let single = chunking.project(&data.data[7]).unwrap();
+    assert!(projected.data[7].iter().zip(single.iter()).all(|(a, b)| (a - b).abs() <= 1e-4));
+    assert_eq!(hasher.encode(&data.data[7]).unwrap(), hasher.encode_dataset(&data).unwrap()[7]);
+
+    let mut corrupt = serialized.clone();
+    corrupt.permutation[0] = corrupt.permutation[1];
+    assert!(ChunkingProjection::create_from_serialized(&corrupt, DIM).is_err());
+    assert!(ChunkingProjection::create_from_serialized(&serialized, DIM + 1).is_err());
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let data = correlated(100, 5);
+    assert!(ChunkingProjection::new(4, 5).is_err());
+    assert!(ChunkingProjection::new(4, 0).is_err());
+    let mut config = AsymmetricHasherConfig::new(NUM_BLOCKS, 257);
+    assert!(train_asymmetric_hasher(&data, &config).is_err());
+    config.num_clusters_per_block = 8;
+    config.num_blocks = DIM + 1;
+    assert!(train_asymmetric_hasher(&data, &config).is_err());
+    // Without PCA the raw dimensions are chunked in order.
+    config.num_blocks = NUM_BLOCKS;
+    let hasher = train_asymmetric_hasher(&data, &config).unwrap();
+    assert_eq!(hasher.projection().permutation(), (0..DIM).collect::<Vec<_>>().as_slice());
+    assert!(hasher.encode(&[0.0; 3]).is_err());
+}
@@ -0,0 +1,307 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Attribute columns: filter expressions, dictionary encoding, length
+//! validation and the expiry column stored among them.
+
+mod common;
+
+use common::scratch_dir;
+use scann::artifacts::{self, ArtifactsConfig};
+use scann::attribute_store::{col, AttributeStore, ColumnType, Filter, MAX_FILTER_NESTING};
+use scann::blob::{self, SectionKind};
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions, EXPIRY_COLUMN};
+use scann::util::{DatapointPtr, DenseDataset, Normalization};
+
+// Row i is [i, 0], so a query at the origin ranks docids in order.
+fn line(n: usize) -> DenseDataset<f32> {
+    DenseDataset::new((0..n).map(|i| vec![i as f32, 0.0]).collect(), 2)
+}
+
+fn docids_as_of(retriever: &ScannRetriever, as_of: i64) -> Vec<usize> {
+    let options = SearchOptions {
+        k: Some(100),
+        as_of: Some(as_of),
+        ..SearchOptions::default()
+    };
+    let (results, _) = retriever.search_with_options(&DatapointPtr::new(vec![0.0, 0.0]), &options).unwrap();
+    results.into_iter().map(|(docid, _)| docid).collect()
+}
+
+#[test]
+fn expiries_are_an_i64_column() {
+    let retriever = ScannRetriever::new(line(6), Box::new(SquaredL2Distance::new()), 10);
+    retriever.set_expiry(1, Some(100)).unwrap();
+    retriever.set_expiry(3, Some(200)).unwrap();
+    let added = retriever.add_with_expiry(&[6.0, 0.0], Some(150)).unwrap();
+
+    retriever.with_attributes(|attributes| {
+        let expiries = attributes.i64_values(EXPIRY_COLUMN).unwrap();
+        assert_eq!(expiries[1], Some(100));
+        assert_eq!(expiries[added], Some(150));
+        let expired = attributes.evaluate(&col(EXPIRY_COLUMN).le(150)).unwrap();
+        assert_eq!(expired.iter().collect::<Vec<_>>(), vec![1, added]);
+    });
+    assert_eq!(retriever.expiry(3), Some(200));
+    assert_eq!(retriever.attribute(EXPIRY_COLUMN, 3), Some(200));
+
+    // Boundary: a point expiring at as_of is already gone.
+    assert_eq!(docids_as_of(&retriever, 99), vec![0, 1, 2, 3, 4, 5, added]);
+    assert_eq!(docids_as_of(&retriever, 100), vec![0, 2, 3, 4, 5, added]);
+    assert_eq!(docids_as_of(&retriever, 150), vec![0, 2, 3, 4, 5]);
+    // Back in time after a later window was cached.
+    assert_eq!(docids_as_of(&retriever, 50), vec![0, 1, 2, 3, 4, 5, added]);
+
+    // Replacing the store keeps expiries it does not set itself.
+    let mut attributes = AttributeStore::new(7);
+    attributes.set_str_column("region", &[Some("eu"); 7]).unwrap();
+    retriever.set_attribute_store(attributes).unwrap();
+    assert_eq!(retriever.expiry(1), Some(100));
+    assert_eq!(docids_as_of(&retriever, 100), vec![0, 2, 3, 4, 5, added]);
+
+    retriever.set_expiry(1, None).unwrap();
+    assert_eq!(docids_as_of(&retriever, 100), vec![0, 1, 2, 3, 4, 5, added]);
+}
+
+#[test]
+fn expiries_survive_compaction_forks_and_blobs() {
+    let retriever = ScannRetriever::new(line(5), Box::new(SquaredL2Distance::new()), 10);
+    retriever.set_expiry(2, Some(10)).unwrap();
+    retriever.set_expiry(4, Some(20)).unwrap();
+    retriever.remove(0).unwrap();
+    retriever.compact().unwrap();
+    assert_eq!(retriever.expiry(2), Some(10));
+    assert_eq!(retriever.expiry(4), Some(20));
+    assert_eq!(docids_as_of(&retriever, 10), vec![1, 3, 4]);
+
+    let fork = retriever.fork().unwrap();
+    fork.set_expiry(3, Some(5)).unwrap();
+    assert_eq!(retriever.expiry(3), None);
+    assert_eq!(fork.expiry(4), Some(20));
+    assert_eq!(docids_as_of(&fork, 10), vec![1, 4]);
+    let materialized = fork.materialize().unwrap();
+    assert_eq!(docids_as_of(&materialized, 10), vec![1, 4]);
+    assert_eq!(docids_as_of(&materialized, 20), vec![1]);
+
+    let dir = scratch_dir("round_trip");
+    let path = dir.join("index.blob");
+    materialized.pack_blob(&path).unwrap();
+    let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+    assert_eq!(loaded.expiry(3), Some(5));
+    assert_eq!(docids_as_of(&loaded, 10), vec![1, 4]);
+    assert_eq!(materialized.tombstone_expired(30, 15), 2);
+    assert_eq!(docids_as_of(&materialized, 0), vec![1, 4]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn legacy_expiry_sections_load_into_the_column() {
+    let (n, dim) = (3u64, 2u64);
+    let mut dataset = Vec::new();
+    dataset.extend_from_slice(&n.to_le_bytes());
+    dataset.extend_from_slice(&dim.to_le_bytes());
+    for i in 0..n {
+        for v in [i as f32, 0.0] {
+            dataset.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    let mut expiries = Vec::new();
+    expiries.extend_from_slice(&1u64.to_le_bytes());
+    expiries.extend_from_slice(&1u64.to_le_bytes());
+    expiries.extend_from_slice(&42i64.to_le_bytes());
+    let dir = scratch_dir("legacy");
+    let path = dir.join("index.blob");
+    blob::write_blob(&path, &[(SectionKind::Dataset, dataset), (SectionKind::Expiries, expiries)]).unwrap();
+
+    let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+    assert_eq!(loaded.expiry(1), Some(42));
+    assert_eq!(docids_as_of(&loaded, 42), vec![0, 2]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filter_nesting_is_limited() {
+    let nested = |depth: usize| format!("{}price < 1{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(Filter::parse(&nested(MAX_FILTER_NESTING)).is_ok());
+    let error = Filter::parse(&nested(MAX_FILTER_NESTING + 1)).unwrap_err();
+    assert!(error.to_string().contains("nested deeper"), "{}", error);
+    assert!(Filter::parse(&format!("{}price < 1", "!".repeat(MAX_FILTER_NESTING + 1))).is_err());
+    // Far deeper than any stack allows; parsing stops at the limit.
+    assert!(Filter::parse(&nested(1_000_000)).is_err());
+
+    // Long chains are not nesting; they parse and evaluate.
+    let mut attributes = AttributeStore::new(4);
+    attributes.set_i64_column("id", (0..4).map(Some).collect()).unwrap();
+    let chain = (0..100_000).map(|i| format!("id == {}", i % 3)).collect::<Vec<_>>().join(" || ");
+    let filter = Filter::parse(&chain).unwrap();
+    assert_eq!(attributes.evaluate(&filter).unwrap().iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+fn certify_ground_truth_skips_expired_points() {
+    let retriever = ScannRetriever::new(line(40), Box::new(SquaredL2Distance::new()), 10);
+    for docid in (0..40).step_by(2) {
+        retriever.set_expiry(docid, Some(100)).unwrap();
+    }
+    let options = SearchOptions {
+        as_of: Some(100),
+        ..SearchOptions::default()
+    };
+    let report = retriever.certify(20, 5, 7, &options).unwrap();
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.mean_recall, 1.0);
+
+    // Unset as_of pins the wall clock once for the whole run.
+    let report = retriever.certify(20, 5, 7, &SearchOptions::default()).unwrap();
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.mean_recall, 1.0);
+}
+
+// Six rows of price, region and score; row 4 has no price and row 5 no
+// region.
+fn catalog() -> AttributeStore {
+    let mut attributes = AttributeStore::new(6);
+    attributes.set_i64_column("price", vec![Some(50), Some(150), Some(99), Some(100), None, Some(20)]).unwrap();
+    let regions = [Some("eu"), Some("us"), Some("eu"), Some("apac"), Some("eu"), None];
+    attributes.set_str_column("region", &regions).unwrap();
+    attributes.set_f32_column("score", vec![Some(0.5), Some(1.5), None, Some(2.0), Some(0.1), Some(3.0)]).unwrap();
+    attributes
+}
+
+fn rows(attributes: &AttributeStore, filter: &Filter) -> Vec<usize> {
+    attributes.evaluate(filter).unwrap().iter().collect()
+}
+
+#[test]
+fn filter_expressions_evaluate_like_their_parsed_text() {
+    let attributes = catalog();
+    let cases = [
+        (col("price").lt(100) & col("region").eq("eu"), "price < 100 && region == 'eu'", vec![0, 2]),
+        (col("price").ge(100) | col("score").gt(2.5f32), "price >= 100 || score > 2.5", vec![1, 3, 5]),
+        (!col("region").eq("eu"), "!(region == \"eu\")", vec![1, 3, 5]),
+        (col("region").ne("eu"), "region != 'eu'", vec![1, 3]),
+        (col("price").le(99.5f32), "price <= 99.5", vec![0, 2, 5]),
+        (col("score").eq(2), "score == 2", vec![3]),
+        (col("region").eq("mars"), "region == \"mars\"", vec![]),
+    ];
+    for (built, text, expected) in cases {
+        assert_eq!(Filter::parse(text).unwrap(), built, "{}", text);
+        assert_eq!(rows(&attributes, &built), expected, "{}", text);
+    }
+    // Comparisons never match a missing value.
+    let both = col("price").is_present() & col("region").is_present();
+    assert_eq!(rows(&attributes, &both), vec![0, 1, 2, 3]);
+    assert_eq!(rows(&attributes, &Filter::parse("price < 1000 && region != 'x'").unwrap()), vec![0, 1, 2, 3]);
+    // && binds tighter than ||.
+    let filter = Filter::parse("price < 30 || price > 120 && region == 'us'").unwrap();
+    assert_eq!(rows(&attributes, &filter), vec![1, 5]);
+
+    for bad in ["region < 'eu'", "price == 'eu'", "missing == 1"] {
+        assert!(attributes.evaluate(&Filter::parse(bad).unwrap()).is_err(), "{}", bad);
+    }
+    for bad in ["price <", "price < 1 &&", "(price < 1", "price ~ 1", "'eu' == region"] {
+        assert!(Filter::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn dictionary_columns_round_trip_through_artifacts() {
+    let attributes = catalog();
+    assert_eq!(attributes.column_type("region"), Some(ColumnType::Dictionary));
+    assert_eq!(attributes.column_names().collect::<Vec<_>>(), vec!["price", "region", "score"]);
+    // Each distinct string is stored once.
+    let mut repeated = AttributeStore::new(1000);
+    let many: Vec<Option<&str>> = (0..1000).map(|i| Some(["north", "south"][i % 2])).collect();
+    repeated.set_str_column("side", &many).unwrap();
+    let mut distinct = AttributeStore::new(1000);
+    let names: Vec<Option<String>> = (0..1000).map(|i| Some(format!("name-{:05}", i))).collect();
+    distinct.set_str_column("side", &names).unwrap();
+    assert!(repeated.memory_bytes() < distinct.memory_bytes());
+
+    let dir = scratch_dir("dictionary");
+    let config = ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: 2,
+    };
+    artifacts::save_artifacts_with_attributes(&dir, &config, &line(6), &[0, 1, 2, 3, 4, 5], &attributes).unwrap();
+    let loaded = artifacts::load_artifacts(&dir).unwrap().attributes.unwrap();
+    assert_eq!(loaded, attributes);
+    assert_eq!(loaded.get_str("region", 3), Some("apac"));
+    assert_eq!(loaded.get_str("region", 5), None);
+    assert_eq!(loaded.get_i64("price", 4), None);
+    assert_eq!(loaded.get_f32("score", 1), Some(1.5));
+    // Typed getters do not convert between column types.
+    assert_eq!(loaded.get_i64("score", 1), None);
+    assert_eq!(loaded.get_str("price", 0), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn column_lengths_must_match_the_row_count() {
+    let mut attributes = AttributeStore::new(3);
+    assert!(attributes.set_i64_column("a", vec![Some(1); 2]).is_err());
+    assert!(attributes.set_f32_column("b", vec![None; 4]).is_err());
+    assert!(attributes.set_str_column("c", &[Some("x")]).is_err());
+    assert_eq!(attributes.num_columns(), 0);
+    attributes.set_i64_column("a", vec![Some(1); 3]).unwrap();
+    attributes.push_row();
+    assert_eq!((attributes.num_rows(), attributes.get_i64("a", 3)), (4, None));
+
+    // Stores for another row count are rejected by the retriever and by
+    // artifacts.
+    let retriever = ScannRetriever::new(line(5), Box::new(SquaredL2Distance::new()), 3);
+    assert!(retriever.set_attribute_store(attributes.clone()).is_err());
+    retriever.set_attribute_store(AttributeStore::new(5)).unwrap();
+    let dir = scratch_dir("lengths");
+    let config = ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: 2,
+    };
+    assert!(artifacts::save_artifacts_with_attributes(&dir, &config, &line(5), &[0, 1, 2, 3, 4], &attributes).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn values_set_before_their_row_is_added_apply_when_it_arrives() {
+    let retriever = ScannRetriever::new(line(3), Box::new(SquaredL2Distance::new()), 3);
+    // Docid 3 is the next add; docid 7 is upserted later.
+    retriever.set_attribute_column("shard", [(0, 10), (3, 13), (7, 17)]);
+    retriever.set_f32_attribute_column("price", [(3, 1.5)]);
+    retriever.set_str_attribute_column("region", [(7, "eu")]);
+    assert_eq!(retriever.attribute("shard", 0), Some(10));
+    assert_eq!(retriever.attribute("shard", 3), None);
+
+    assert_eq!(retriever.add(&[3.0, 0.0]).unwrap(), 3);
+    retriever.upsert(7, &[7.0, 0.0]).unwrap();
+    retriever.add(&[8.0, 0.0]).unwrap();
+    assert_eq!(retriever.attribute("shard", 3), Some(13));
+    assert_eq!(retriever.attribute_f32("price", 3), Some(1.5));
+    assert_eq!(retriever.attribute("shard", 7), Some(17));
+    assert_eq!(retriever.attribute_str("region", 7), Some("eu".to_string()));
+    assert_eq!(retriever.attribute("shard", 8), None);
+
+    // Replacing or removing a column drops its pending values.
+    retriever.set_attribute_column("shard", [(20, 1)]);
+    retriever.set_attribute_column("shard", [(0, 2)]);
+    assert!(retriever.remove_attribute_column("price"));
+    retriever.set_f32_attribute_column("price", [(21, 2.5)]);
+    assert!(retriever.remove_attribute_column("price"));
+    retriever.upsert(20, &[20.0, 0.0]).unwrap();
+    retriever.upsert(21, &[21.0, 0.0]).unwrap();
+    assert_eq!(retriever.attribute("shard", 20), None);
+    assert_eq!(retriever.attribute_f32("price", 21), None);
+}
@@ -0,0 +1,182 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmark suite loading against tiny fabricated files: valid suites load
+//! ready for tuning, and every mismatch names the file and shape.
+
+mod common;
+
+use common::scratch_dir;
+use scann::benchmarks::{self, BenchmarkDataset, VectorFormat};
+use scann::npy;
+use scann::util::DenseDataset;
+use std::path::Path;
+
+fn write_vecs(path: &Path, rows: &[Vec<[u8; 4]>]) {
+    let mut bytes = Vec::new();
+    for row in rows {
+        bytes.extend_from_slice(&(row.len() as i32).to_le_bytes());
+        row.iter().for_each(|value| bytes.extend_from_slice(value));
+    }
+    std::fs::write(path, bytes).unwrap();
+}
+
+fn fvecs(path: &Path, rows: &[Vec<f32>]) {
+    let rows: Vec<Vec<[u8; 4]>> = rows.iter().map(|row| row.iter().map(|v| v.to_le_bytes()).collect()).collect();
+    write_vecs(path, &rows);
+}
+
+fn ivecs(path: &Path, rows: &[Vec<i32>]) {
+    let rows: Vec<Vec<[u8; 4]>> = rows.iter().map(|row| row.iter().map(|v| v.to_le_bytes()).collect()).collect();
+    write_vecs(path, &rows);
+}
+
+// Six points on a line; the ground truth of each query lists the two
+// nearest base rows.
+fn tiny_suite(dir: &Path, format: VectorFormat) -> BenchmarkDataset {
+    let base: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32, 0.0]).collect();
+    let queries = vec![vec![0.1, 0.0], vec![4.8, 0.0]];
+    let extension = match format {
+        VectorFormat::Fvecs => {
+            fvecs(&dir.join("tiny_base.fvecs"), &base);
+            fvecs(&dir.join("tiny_query.fvecs"), &queries);
+            "fvecs"
+        }
+        VectorFormat::Npy => {
+            npy::save_dataset(dir.join("tiny_base.npy"), &DenseDataset::new(base, 2), None).unwrap();
+            npy::save_dataset(dir.join("tiny_query.npy"), &DenseDataset::new(queries, 2), None).unwrap();
+            "npy"
+        }
+    };
+    ivecs(&dir.join("tiny_groundtruth.ivecs"), &[vec![0, 1], vec![5, 4]]);
+    BenchmarkDataset {
+        name: "tiny".to_string(),
+        base: format!("tiny_base.{}", extension),
+        queries: format!("tiny_query.{}", extension),
+        ground_truth: "tiny_groundtruth.ivecs".to_string(),
+        format,
+        dimensionality: 2,
+        num_base: 6,
+        num_queries: 2,
+        ground_truth_k: 2,
+        measure: "SquaredL2Distance".to_string(),
+    }
+}
+
+#[test]
+fn conforming_files_load_in_both_formats() {
+    for format in [VectorFormat::Fvecs, VectorFormat::Npy] {
+        let dir = scratch_dir(&format!("{:?}", format));
+        let descriptor = tiny_suite(&dir, format);
+        let loaded = benchmarks::load_benchmark(&descriptor, &dir).unwrap();
+        assert_eq!(loaded.base.size(), 6);
+        assert_eq!(loaded.base.data[5], vec![5.0, 0.0]);
+        assert_eq!(loaded.queries.data, vec![vec![0.1, 0.0], vec![4.8, 0.0]]);
+        assert_eq!(loaded.ground_truth, vec![vec![0, 1], vec![5, 4]]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+#[test]
+fn mismatches_name_the_file_and_shape() {
+    let dir = scratch_dir("mismatch");
+    let descriptor = tiny_suite(&dir, VectorFormat::Fvecs);
+    let check = |descriptor: &BenchmarkDataset, needles: &[&str]| {
+        let err = benchmarks::load_benchmark(descriptor, &dir).err().expect("load should fail").to_string();
+        for needle in needles {
+            assert!(err.contains(needle), "expected '{}' in '{}'", needle, err);
+        }
+    };
+    check(
+        &BenchmarkDataset {
+            num_base: 7,
+            ..descriptor.clone()
+        },
+        &["tiny_base.fvecs", "has 6 records, expected 7"],
+    );
+    check(
+        &BenchmarkDataset {
+            num_queries: 1,
+            ..descriptor.clone()
+        },
+        &["tiny_query.fvecs", "more than the expected 1 records"],
+    );
+    check(
+        &BenchmarkDataset {
+            dimensionality: 3,
+            ..descriptor.clone()
+        },
+        &["tiny_base.fvecs", "record 0 has dimension 2, expected 3"],
+    );
+    check(
+        &BenchmarkDataset {
+            ground_truth_k: 3,
+            ..descriptor.clone()
+        },
+        &["tiny_groundtruth.ivecs", "dimension 2, expected 3"],
+    );
+
+    ivecs(&dir.join("tiny_groundtruth.ivecs"), &[vec![0, 1], vec![6, 4]]);
+    check(&descriptor, &["tiny_groundtruth.ivecs", "query 1 lists neighbor 6, outside [0, 6)"]);
+
+    let mut truncated = std::fs::read(dir.join("tiny_query.fvecs")).unwrap();
+    truncated.truncate(truncated.len() - 2);
+    std::fs::write(dir.join("tiny_query.fvecs"), truncated).unwrap();
+    check(&descriptor, &["tiny_query.fvecs", "record 1 is truncated"]);
+    check(
+        &BenchmarkDataset {
+            base: "missing.fvecs".to_string(),
+            ..descriptor.clone()
+        },
+        &["missing.fvecs", "cannot open"],
+    );
+
+    let npy_descriptor = tiny_suite(&dir, VectorFormat::Npy);
+    check(
+        &BenchmarkDataset {
+            num_base: 5,
+            ..npy_descriptor
+        },
+        &["tiny_base.npy", "has shape (6, 2), expected (5, 2)"],
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn builtin_descriptors_cover_the_listed_suites() {
+    for name in benchmarks::BUILTIN_BENCHMARKS {
+        let descriptor = benchmarks::builtin(name).unwrap();
+        assert_eq!(descriptor.name, *name);
+        assert_eq!(descriptor.format, VectorFormat::Fvecs);
+        assert!(descriptor.ground_truth.ends_with(".ivecs"));
+    }
+    let sift = benchmarks::builtin("sift1m").unwrap();
+    assert_eq!((sift.dimensionality, sift.num_base, sift.num_queries), (128, 1_000_000, 10_000));
+    assert_eq!(sift.base, "sift_base.fvecs");
+    assert!(benchmarks::builtin("deep1b").is_none());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn descriptors_parse_from_json() {
+    let text = r#"{"name": "tiny", "base": "b.npy", "queries": "q.npy", "ground_truth": "gt.ivecs",
+        "format": "npy", "dimensionality": 2, "num_base": 6, "num_queries": 2, "ground_truth_k": 2,
+        "measure": "SquaredL2Distance"}"#;
+    let descriptor = BenchmarkDataset::from_json(text, "tiny.json").unwrap();
+    assert_eq!(descriptor.format, VectorFormat::Npy);
+    assert_eq!((descriptor.num_base, descriptor.ground_truth_k), (6, 2));
+    let err = BenchmarkDataset::from_json(&text.replace("\"npy\"", "\"hdf5\""), "tiny.json").unwrap_err();
+    assert!(err.to_string().contains("tiny.json is not a benchmark descriptor"), "{}", err);
+    assert!(err.to_string().contains("unknown variant `hdf5`"), "{}", err);
+}
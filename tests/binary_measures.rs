@@ -0,0 +1,104 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BinaryDotProductDistance and BinaryCosineDistance: binarized inputs and
+//! agreement between the float and packed-word paths.
+
+use scann::distance_measures::{
+    self, pack_nonzero_bits, BinaryCosineDistance, BinaryDotProductDistance, DistanceMeasure,
+};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+// Sparse vector with roughly a third of the coordinates nonzero, using
+// values of both signs and magnitudes other than 1.
+fn sparse(rng: &mut SplitMix64, dim: usize) -> Vec<f32> {
+    (0..dim).map(|_| if rng.next_below(3) == 0 { rng.next_normal() * 3.0 } else { 0.0 }).collect()
+}
+
+fn binarized(values: &[f32]) -> Vec<f32> {
+    values.iter().map(|&v| (v != 0.0) as u8 as f32).collect()
+}
+
+#[test]
+fn nonzero_values_count_as_one() {
+    let dot = BinaryDotProductDistance::new();
+    let cosine = BinaryCosineDistance::new();
+    let a = [0.25, -3.0, 0.0, 7.0];
+    let b = [-1.0, 0.5, 2.0, 0.0];
+    // Both nonzero in coordinates 0 and 1; a has 3 nonzeros and b has 3.
+    assert_eq!(dot.compute_distance_f32(&a, &b), -2.0);
+    assert!((cosine.compute_distance_f32(&a, &b) - (1.0 - 2.0 / 3.0)).abs() < 1e-6);
+    assert_eq!(dot.compute_distance_f32(&a, &b), dot.compute_distance_f32(&binarized(&a), &binarized(&b)));
+    let typed = dot.compute_distance(&DatapointPtr::new(vec![0i8, 5, -1]), &DatapointPtr::new(vec![3i8, 2, 0]));
+    assert_eq!(typed, -1.0);
+
+    // Identical supports are at cosine distance 0; an empty one is at 1.
+    assert!(cosine.compute_distance_f32(&a, &[9.0, 9.0, 0.0, 9.0]).abs() < 1e-6);
+    assert_eq!(cosine.compute_distance_f32(&[0.0; 4], &a), 1.0);
+    assert_eq!(dot.compute_distance_f32(&[0.0; 4], &a), 0.0);
+}
+
+#[test]
+fn packing_sets_one_bit_per_nonzero_coordinate() {
+    assert_eq!(pack_nonzero_bits(&[]), Vec::<u64>::new());
+    assert_eq!(pack_nonzero_bits(&[1.0, 0.0, -2.0]), vec![0b101]);
+    let mut values = vec![0.0; 65];
+    values[63] = 1.0;
+    values[64] = -1.0;
+    assert_eq!(pack_nonzero_bits(&values), vec![1 << 63, 1]);
+}
+
+#[test]
+fn packed_distances_match_the_float_paths() {
+    let dot = BinaryDotProductDistance::new();
+    let cosine = BinaryCosineDistance::new();
+    let mut rng = SplitMix64::new(1);
+    for dim in [1, 7, 63, 64, 65, 130, 256] {
+        for _ in 0..20 {
+            let (a, b) = (sparse(&mut rng, dim), sparse(&mut rng, dim));
+            let (packed_a, packed_b) = (pack_nonzero_bits(&a), pack_nonzero_bits(&b));
+            assert_eq!(packed_a.len(), dim.div_ceil(64));
+            assert_eq!(dot.packed_distance(&packed_a, &packed_b).unwrap(), dot.compute_distance_f32(&a, &b));
+            assert_eq!(cosine.packed_distance(&packed_a, &packed_b).unwrap(), cosine.compute_distance_f32(&a, &b));
+            // The general measures agree once the inputs are binarized.
+            let general = distance_measures::get_distance_measure_by_name("DotProductDistance").unwrap();
+            assert_eq!(general.compute_distance_f32(&binarized(&a), &binarized(&b)), dot.compute_distance_f32(&a, &b));
+            let general = distance_measures::get_distance_measure_by_name("CosineDistance").unwrap();
+            let expected = general.compute_distance_f32(&binarized(&a), &binarized(&b));
+            assert!((expected - cosine.compute_distance_f32(&a, &b)).abs() < 1e-5, "dim {}", dim);
+        }
+    }
+    assert!(dot.packed_distance(&[1, 2], &[1]).is_err());
+    assert!(cosine.packed_distance(&[1], &[1, 2]).is_err());
+}
+
+#[test]
+fn retriever_ranks_by_overlap() {
+    let mut rng = SplitMix64::new(2);
+    let rows: Vec<Vec<f32>> = (0..300).map(|_| sparse(&mut rng, 96)).collect();
+    let query = sparse(&mut rng, 96);
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(rows.clone(), 96), Box::new(BinaryDotProductDistance::new()), 10);
+    let results = retriever.search(&DatapointPtr::new(query.clone())).unwrap();
+    let overlap = |row: &[f32]| row.iter().zip(&query).filter(|(x, y)| **x != 0.0 && **y != 0.0).count();
+    let best = rows.iter().map(|row| overlap(row)).max().unwrap();
+    assert_eq!(results[0].1, -(best as f32));
+    for &(docid, distance) in &results {
+        assert_eq!(distance, -(overlap(&rows[docid]) as f32));
+    }
+    // No row outside the results overlaps more than the tenth result.
+    let closer = rows.iter().filter(|row| -(overlap(row) as f32) < results[9].1).count();
+    assert!(closer < 10, "{} rows beat the tenth result", closer);
+}
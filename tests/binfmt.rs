@@ -0,0 +1,182 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Endianness-tagged section headers: converting foreign byte orders and
+//! rejecting headers that would otherwise misload.
+
+mod common;
+
+use common::scratch_dir;
+use scann::binfmt::{self, ByteOrder, Endianness, HEADER_LEN};
+use scann::leaf_codes::{self, FileLeafCodeStore, LeafCodeStore};
+use scann::quantization::{self, Int8QuantizationConfig};
+use scann::tree::{KMeansTree, KMeansTreeTrainingOptions};
+use scann::util::{DenseDataset, SplitMix64};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"TESTSECT";
+const NUM_LEAVES: usize = 4;
+const DIM: usize = 5;
+
+fn write_codes(path: &Path, endianness: Endianness) {
+    let mut rng = SplitMix64::new(1);
+    let rows = (0..300).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    let data = DenseDataset::new(rows, DIM);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    let (tree, _) = KMeansTree::train(&data, NUM_LEAVES, &options).unwrap();
+    let config = Int8QuantizationConfig { clip_quantile: Some(0.99) };
+    let quantized = quantization::quantize_int8(&data, &config).unwrap();
+    leaf_codes::write_leaf_codes_with_order(path, &tree, &quantized, endianness).unwrap();
+}
+
+fn swap(bytes: &mut [u8], at: usize, width: usize) {
+    bytes[at..at + width].reverse();
+}
+
+// Rewrites a big-endian leaf codes file as little-endian by hand, field by
+// field, following the layout documented in leaf_codes.rs.
+fn big_to_little(bytes: &mut [u8]) {
+    let big = ByteOrder(Endianness::Big);
+    let num_leaves = big.u64(bytes, HEADER_LEN) as usize;
+    let dim = big.u64(bytes, HEADER_LEN + 8) as usize;
+    bytes[8] = 0;
+    for (at, width) in [(12, 4), (16, 8), (24, 8)] {
+        swap(bytes, at, width);
+    }
+    let mut at = HEADER_LEN;
+    for _ in 0..3 {
+        swap(bytes, at, 8);
+        at += 8;
+    }
+    for _ in 0..1 + dim {
+        swap(bytes, at, 4);
+        at += 4;
+    }
+    for _ in 0..2 * num_leaves {
+        swap(bytes, at, 8);
+        at += 8;
+    }
+    for _ in 0..num_leaves {
+        let count = big.u64(bytes, at) as usize;
+        for _ in 0..1 + count {
+            swap(bytes, at, 8);
+            at += 8;
+        }
+        at += count * dim;
+    }
+    assert_eq!(at, bytes.len());
+}
+
+fn all_leaves(store: &FileLeafCodeStore) -> Vec<(Vec<usize>, Vec<i8>)> {
+    (0..store.num_leaves())
+        .map(|leaf| {
+            let (codes, _) = store.load(leaf).unwrap();
+            (codes.rows.iter().collect(), codes.codes.clone())
+        })
+        .collect()
+}
+
+#[test]
+fn sections_round_trip_in_both_byte_orders() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let order = ByteOrder(endianness);
+        let mut payload = Vec::new();
+        order.put_u64(&mut payload, 0x0102_0304_0506_0708);
+        order.put_u32(&mut payload, 0xdead_beef);
+        order.put_f32(&mut payload, -1.5);
+        let bytes = binfmt::write_section(MAGIC, 3, endianness, &payload);
+        let (header, read) = binfmt::read_section(&bytes, MAGIC, 3).unwrap();
+        assert_eq!((header.endianness, header.version, header.payload_len), (endianness, 3, 16));
+        assert_eq!(read, &payload[..]);
+        assert_eq!(order.u64(read, 0), 0x0102_0304_0506_0708);
+        assert_eq!(order.u32(read, 8), 0xdead_beef);
+        assert_eq!(order.f32(read, 12), -1.5);
+    }
+    let little = binfmt::write_section(MAGIC, 1, Endianness::Little, &[]);
+    let big = binfmt::write_section(MAGIC, 1, Endianness::Big, &[]);
+    assert_eq!((little[8], big[8]), (0, 1));
+    assert_eq!((&little[12..16], &big[12..16]), (&[1, 0, 0, 0][..], &[0, 0, 0, 1][..]));
+}
+
+#[test]
+fn big_endian_leaf_codes_load_like_little_endian_ones() {
+    let dir = scratch_dir("convert");
+    let (little_path, big_path) = (dir.join("little.bin"), dir.join("big.bin"));
+    write_codes(&little_path, Endianness::Little);
+    write_codes(&big_path, Endianness::Big);
+    let little = std::fs::read(&little_path).unwrap();
+    let big = std::fs::read(&big_path).unwrap();
+    assert_ne!(little, big);
+
+    // The reader swaps on mismatch, whatever the host order.
+    let little_store = FileLeafCodeStore::open(&little_path, 1 << 20).unwrap();
+    let big_store = FileLeafCodeStore::open(&big_path, 1 << 20).unwrap();
+    assert_eq!(big_store.multipliers(), little_store.multipliers());
+    assert_eq!(big_store.clip_quantile(), Some(0.99));
+    assert_eq!((big_store.num_rows(), big_store.dimensionality()), (300, DIM));
+    assert_eq!(all_leaves(&big_store), all_leaves(&little_store));
+
+    // Swapping every field by hand yields the little-endian file, apart
+    // from the checksum, which covers the payload bytes as written.
+    let mut converted = big.clone();
+    big_to_little(&mut converted);
+    assert!(converted[..24] == little[..24] && converted[32..] == little[32..]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flipping_the_flag_without_the_bytes_fails_precisely() {
+    let dir = scratch_dir("flip");
+    let path = dir.join("codes.bin");
+    for (endianness, flipped) in [(Endianness::Little, 1), (Endianness::Big, 0)] {
+        write_codes(&path, endianness);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[8] = flipped;
+        std::fs::write(&path, &bytes).unwrap();
+        // The version is read byte-swapped and rejected before anything
+        // else is interpreted.
+        let error = FileLeafCodeStore::open(&path, 1 << 20).err().unwrap().to_string();
+        assert!(error.contains("Unsupported format version"), "{}", error);
+        assert!(error.contains("this build reads versions 1..=2"), "{}", error);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn malformed_headers_are_rejected() {
+    let payload = [1u8, 2, 3, 4];
+    let section = binfmt::write_section(MAGIC, 2, Endianness::Little, &payload);
+    let expect_error = |bytes: &[u8], needle: &str| {
+        let error = binfmt::read_section(bytes, MAGIC, 2).unwrap_err().to_string();
+        assert!(error.contains(needle), "{}: {}", needle, error);
+    };
+
+    expect_error(&section[..HEADER_LEN - 1], "Truncated header");
+    expect_error(&section[..section.len() - 1], "Header declares 4 payload bytes but 3 are present");
+    let mut bytes = section.clone();
+    bytes[0] = b'X';
+    expect_error(&bytes, "Bad magic");
+    let mut bytes = section.clone();
+    bytes[8] = 7;
+    expect_error(&bytes, "Invalid endianness flag 7");
+    let mut bytes = section.clone();
+    bytes[10] = 1;
+    expect_error(&bytes, "Reserved header bytes are not zero");
+    let newer = binfmt::write_section(MAGIC, 3, Endianness::Big, &payload);
+    expect_error(&newer, "Unsupported format version 3 (Big byte order)");
+    let mut bytes = section.clone();
+    *bytes.last_mut().unwrap() ^= 1;
+    expect_error(&bytes, "Payload checksum mismatch");
+}
@@ -0,0 +1,116 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Loaded blobs read the dataset section in place from a shared map and
+//! copy a segment onto the heap only when a row in it is written.
+
+mod common;
+
+use common::{random_dataset, scratch_dir};
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, SEGMENT_LEN};
+
+const NUM_LEAVES: usize = 8;
+
+fn exhaustive(k: usize) -> SearchOptions {
+    SearchOptions {
+        k: Some(k),
+        leaves_to_search: Some(NUM_LEAVES),
+        ..SearchOptions::default()
+    }
+}
+
+fn packed(data: &DenseDataset<f32>, path: &std::path::Path) {
+    let retriever = ScannRetriever::new(data.clone(), Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    retriever.pack_blob(path).unwrap();
+}
+
+#[test]
+fn mapped_blob_round_trips_rows_and_results() {
+    let dim = 12;
+    // Several segments, the last partly filled.
+    let data = random_dataset(2 * SEGMENT_LEN + 300, dim, 1);
+    let dir = scratch_dir("round_trip");
+    let path = dir.join("index.blob");
+    packed(&data, &path);
+    let original = ScannRetriever::new(data.clone(), Box::new(SquaredL2Distance::new()), 10);
+    let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+
+    assert_eq!(loaded.mapped_memory_usage(), data.size() * dim * 4);
+    for (docid, row) in data.data.iter().enumerate() {
+        assert_eq!(loaded.get_by_docid(docid).unwrap(), *row, "docid {}", docid);
+    }
+    for query in random_dataset(5, dim, 2).data {
+        let query = DatapointPtr::new(query);
+        let (expected, _) = original.search_with_options(&query, &exhaustive(10)).unwrap();
+        let (actual, _) = loaded.search_with_options(&query, &exhaustive(10)).unwrap();
+        assert_eq!(actual, expected);
+    }
+    let report = loaded.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+
+    // Packing a mapped retriever writes the same rows, even over the file
+    // it is mapping.
+    loaded.pack_blob(&path).unwrap();
+    let reloaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+    for (docid, row) in data.data.iter().enumerate().step_by(97) {
+        assert_eq!(loaded.get_by_docid(docid).unwrap(), *row);
+        assert_eq!(reloaded.get_by_docid(docid).unwrap(), *row);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn writes_move_only_touched_segments_off_the_map() {
+    let dim = 8;
+    let n = 3 * SEGMENT_LEN;
+    let data = random_dataset(n, dim, 3);
+    let dir = scratch_dir("accounting");
+    let path = dir.join("index.blob");
+    packed(&data, &path);
+    let first = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+    let second = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+    let in_memory = ScannRetriever::new(data.clone(), Box::new(SquaredL2Distance::new()), 10);
+    in_memory.build_partitions(NUM_LEAVES, &KMeansTreeTrainingOptions::new()).unwrap();
+
+    // Both loads borrow every row from the map, so neither counts the
+    // dataset as heap.
+    let segment_bytes = SEGMENT_LEN * dim * 4;
+    let mapped = n * dim * 4;
+    assert_eq!(first.mapped_memory_usage(), mapped);
+    assert_eq!(second.mapped_memory_usage(), mapped);
+    assert_eq!(in_memory.mapped_memory_usage(), 0);
+    assert!(first.memory_usage() + mapped <= in_memory.memory_usage());
+    let heap_before = first.memory_usage();
+
+    let row = vec![0.5f32; dim];
+    first.upsert(SEGMENT_LEN + 3, &row).unwrap();
+    assert_eq!(first.mapped_memory_usage(), mapped - segment_bytes);
+    assert!(first.memory_usage() >= heap_before + segment_bytes);
+    assert_eq!(second.mapped_memory_usage(), mapped);
+    assert_eq!(first.get_by_docid(SEGMENT_LEN + 3).unwrap(), row);
+    assert_eq!(second.get_by_docid(SEGMENT_LEN + 3).unwrap(), data.data[SEGMENT_LEN + 3]);
+    // Neighbouring rows of the copied segment keep their values.
+    assert_eq!(first.get_by_docid(SEGMENT_LEN + 4).unwrap(), data.data[SEGMENT_LEN + 4]);
+
+    // Appending a new row starts a heap segment and leaves the map alone.
+    first.upsert(n + 10, &row).unwrap();
+    assert_eq!(first.mapped_memory_usage(), mapped - segment_bytes);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,232 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Build info recorded in artifacts, and loads refusing files that need a
+//! feature this binary lacks.
+
+mod common;
+
+use common::scratch_dir;
+use scann::artifact_source::FsArtifactSource;
+use scann::artifacts::{self, ArtifactsConfig};
+use scann::build_info::{self, BuildInfo, KNOWN_FEATURES};
+use scann::util::{DenseDataset, Normalization};
+use scann::{assets, blob};
+use std::fs;
+use std::path::Path;
+
+fn config() -> ArtifactsConfig {
+    ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: 2,
+    }
+}
+
+fn dataset() -> DenseDataset<f32> {
+    DenseDataset::new(vec![vec![0.5, -2.0], vec![1024.0, 0.25]], 2)
+}
+
+// A known feature this binary was built without, if any.
+fn missing_feature(info: &BuildInfo) -> Option<&'static str> {
+    KNOWN_FEATURES.iter().copied().find(|feature| !info.has_feature(feature))
+}
+
+// Rewrites index_config.txt and drops the manifest whose checksum it would
+// no longer match.
+fn edit_config(dir: &Path, edit: impl Fn(&str) -> String) {
+    let path = dir.join("index_config.txt");
+    let text = fs::read_to_string(&path).unwrap();
+    fs::write(&path, edit(&text)).unwrap();
+    fs::remove_file(dir.join("manifest.txt")).unwrap();
+}
+
+#[test]
+fn build_info_lists_the_enabled_features() {
+    let info = scann::build_info();
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    let enabled = [
+        ("f16", cfg!(feature = "f16")),
+        ("proto-compat", cfg!(feature = "proto-compat")),
+        ("rayon", cfg!(feature = "rayon")),
+        ("serde", cfg!(feature = "serde")),
+        ("simd", cfg!(feature = "simd")),
+        ("torch", cfg!(feature = "torch")),
+        ("zstd", cfg!(feature = "zstd")),
+    ];
+    assert_eq!(enabled.map(|(name, _)| name), KNOWN_FEATURES);
+    for (name, on) in enabled {
+        assert_eq!(info.has_feature(name), on, "{}", name);
+    }
+    assert!(info.blob_versions.contains(&blob::BLOB_VERSION));
+}
+
+#[test]
+fn same_feature_round_trips_pass_the_check() {
+    let info = scann::build_info();
+    build_info::check_required_features(&info.features, "test").unwrap();
+
+    let dir = scratch_dir("round_trip");
+    artifacts::save_artifacts(&dir, &config(), &dataset(), &[3, 4]).unwrap();
+    let text = fs::read_to_string(dir.join("index_config.txt")).unwrap();
+    assert!(text.contains(&format!("built_with_features: {}\n", info.features.join(","))), "{}", text);
+    let loaded = artifacts::load_artifacts(&dir).unwrap();
+    assert_eq!(loaded.dataset.data, dataset().data);
+
+    let bytes = blob::encode_blob_with_requirements(
+        &[(blob::SectionKind::IndexWidth, 4u32.to_le_bytes().to_vec())],
+        &info.features,
+    );
+    assert!(blob::decode_blob(&bytes).unwrap().contains_key(&blob::SectionKind::IndexWidth));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_manifest_requiring_a_missing_feature_names_it() {
+    let Some(feature) = missing_feature(&scann::build_info()) else {
+        return;
+    };
+    let dir = scratch_dir("missing_feature");
+    artifacts::save_artifacts(&dir, &config(), &dataset(), &[3, 4]).unwrap();
+    edit_config(&dir, |text| format!("{}requires_features: {}\n", text, feature));
+
+    let err = artifacts::load_artifacts(&dir).err().expect("load needs the missing feature").to_string();
+    assert!(err.contains(&format!("without the '{}' feature", feature)), "{}", err);
+    assert!(err.contains(&format!("rebuild scann with `--features {}`", feature)), "{}", err);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_unknown_capability_asks_for_an_upgrade() {
+    let dir = scratch_dir("unknown_capability");
+    artifacts::save_artifacts(&dir, &config(), &dataset(), &[3, 4]).unwrap();
+    edit_config(&dir, |text| format!("{}requires_features: bf16-tiles\n", text));
+    let err = artifacts::load_artifacts(&dir).err().expect("unknown capability").to_string();
+    assert!(err.contains("requires capability 'bf16-tiles'"), "{}", err);
+    assert!(err.contains("upgrade scann"), "{}", err);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_blob_requiring_a_missing_feature_names_it() {
+    let Some(feature) = missing_feature(&scann::build_info()) else {
+        return;
+    };
+    let bytes = blob::encode_blob_with_requirements(
+        &[(blob::SectionKind::IndexWidth, 4u32.to_le_bytes().to_vec())],
+        &[feature],
+    );
+    let err = blob::decode_blob(&bytes).unwrap_err().to_string();
+    assert!(err.contains(&format!("--features {}", feature)), "{}", err);
+}
+
+#[cfg(not(feature = "f16"))]
+#[test]
+fn f16_artifacts_need_the_f16_feature() {
+    let dir = scratch_dir("f16_missing");
+    artifacts::save_artifacts(&dir, &config(), &dataset(), &[3, 4]).unwrap();
+    edit_config(&dir, |text| text.replace("dtype: f32", "dtype: f16"));
+    let err = artifacts::load_artifacts(&dir).err().expect("f16 dtype").to_string();
+    assert!(err.contains("half-precision sections"), "{}", err);
+    assert!(err.contains("`--features f16`"), "{}", err);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "f16")]
+#[test]
+fn f16_artifacts_round_trip_at_half_precision() {
+    let dir = scratch_dir("f16_round_trip");
+    let dataset = DenseDataset::new(vec![vec![0.5, -2.0], vec![1024.0, 0.1]], 2);
+    artifacts::save_artifacts_f16(&dir, &config(), &dataset, &[3, 4]).unwrap();
+    let text = fs::read_to_string(dir.join("index_config.txt")).unwrap();
+    assert!(text.contains("dtype: f16\nrequires_features: f16\n"), "{}", text);
+    // Two bytes per value instead of four, under a same-sized header.
+    let f32_len = scann::npy::encode_npy_f32(&dataset).len() as u64;
+    assert_eq!(fs::metadata(dir.join("dataset.npy")).unwrap().len(), f32_len - 4 * 2);
+
+    let loaded = artifacts::load_artifacts(&dir).unwrap();
+    assert_eq!(loaded.docids, [3, 4]);
+    assert_eq!(&loaded.dataset.data[0], &[0.5, -2.0]);
+    assert_eq!(loaded.dataset.data[1][0], 1024.0);
+    assert_eq!(loaded.dataset.data[1][1], 0.099975586);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "f16")]
+#[test]
+fn f16_encoding_rounds_to_nearest_even_and_saturates_to_infinity() {
+    let values = vec![
+        65504.0,
+        65520.0,
+        -1e6,
+        1.0 + 1.0 / 2048.0,
+        1.0 + 3.0 / 2048.0,
+        2f32.powi(-24),
+        2f32.powi(-26),
+        -0.0,
+        f32::NAN,
+    ];
+    let dataset = DenseDataset::new(vec![values], 9);
+    let decoded = scann::npy::decode_npy_f32(&scann::npy::encode_npy_f16(&dataset)).unwrap();
+    let row = &decoded.data[0];
+    assert_eq!(row[0], 65504.0);
+    assert_eq!(row[1], f32::INFINITY);
+    assert_eq!(row[2], f32::NEG_INFINITY);
+    // Ties go to the even mantissa.
+    assert_eq!(row[3], 1.0);
+    assert_eq!(row[4], 1.0 + 4.0 / 2048.0);
+    assert_eq!(row[5], 2f32.powi(-24));
+    assert_eq!(row[6], 0.0);
+    assert!(row[7] == 0.0 && row[7].is_sign_negative());
+    assert!(row[8].is_nan());
+}
+
+#[cfg(feature = "proto-compat")]
+#[test]
+fn binary_assets_round_trip_in_the_protobuf_wire_format() {
+    use scann::proto::{AssetType, ScannAsset, ScannAssets};
+
+    let one = ScannAssets {
+        assets: vec![ScannAsset {
+            asset_type: AssetType::Partitioner,
+            asset_path: "a".to_string(),
+        }],
+    };
+    assert_eq!(one.encode_to_vec(), [0x0a, 0x05, 0x08, 0x02, 0x12, 0x01, b'a']);
+    assert_eq!(ScannAssets::decode(&one.encode_to_vec()).unwrap(), one);
+    assert!(ScannAssets::decode(&[0x0a, 0x05, 0x08]).is_err());
+
+    let dir = scratch_dir("proto_compat");
+    artifacts::save_artifacts(&dir, &config(), &dataset(), &[3, 4]).unwrap();
+    let written = assets::populate_and_save_assets_proto(&dir).unwrap();
+    assert!(written.assets.iter().any(|asset| asset.asset_type == AssetType::DatasetNpy));
+    let source = FsArtifactSource::new(&dir).unwrap();
+    assert_eq!(assets::load_assets_proto(&source).unwrap(), written);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(feature = "proto-compat"))]
+#[test]
+fn binary_assets_need_the_proto_compat_feature() {
+    let dir = scratch_dir("proto_compat_missing");
+    fs::create_dir_all(&dir).unwrap();
+    assets::populate_and_save_assets_proto(&dir).unwrap();
+    assert!(!dir.join("scann_assets.pb").exists());
+    fs::write(dir.join("scann_assets.pb"), [0x0a, 0x00]).unwrap();
+    let source = FsArtifactSource::new(&dir).unwrap();
+    let err = assets::load_assets_proto(&source).unwrap_err().to_string();
+    assert!(err.contains("`proto-compat` feature"), "{}", err);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,185 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build reports: the phases follow the configured pipeline and, with the
+//! serde feature, the report round-trips through build_report.json with
+//! every enum readable.
+
+mod common;
+
+use common::random_dataset;
+use scann::build::{self, DatasetSummary, PartitioningRecord};
+use scann::convert::DType;
+use scann::distance_measures::SquaredL2Distance;
+use scann::estimate::{BuildPlan, CostModel, DatasetMeta};
+use scann::tree::KMeansTreeTrainingOptions;
+
+fn phase_names(phases: &[(String, f64)]) -> Vec<&str> {
+    phases.iter().map(|(name, _)| name.as_str()).collect()
+}
+
+#[test]
+fn phases_match_the_configured_pipeline() {
+    let mut plan = BuildPlan::new();
+    plan.num_leaves = Some(8);
+    plan.int8_codes = true;
+    plan.norm_cache = true;
+    let data = random_dataset(400, 4, 3);
+    let (_, report) = build::build_retriever(
+        data.clone(),
+        Box::new(SquaredL2Distance::new()),
+        5,
+        &plan,
+        &KMeansTreeTrainingOptions::new(),
+    )
+    .unwrap();
+    assert_eq!(phase_names(&report.phases), vec!["copy", "kmeans", "quantize", "norms"]);
+    // Same names and order as the estimate of the same plan.
+    let meta = DatasetMeta {
+        n: 400,
+        dim: 4,
+        dtype: DType::F32,
+    };
+    let estimate = plan.estimate(&meta, &CostModel::default(), None);
+    assert_eq!(phase_names(&report.phases), phase_names(&estimate.phase_seconds));
+    assert!(matches!(report.partitioning, PartitioningRecord::Built { num_leaves: 8, .. }));
+    assert!(report.quantization.is_some());
+    assert_eq!(report.dataset, DatasetSummary::of(&data));
+
+    let (_, report) = build::build_retriever(
+        data,
+        Box::new(SquaredL2Distance::new()),
+        5,
+        &BuildPlan::new(),
+        &KMeansTreeTrainingOptions::new(),
+    )
+    .unwrap();
+    assert_eq!(phase_names(&report.phases), vec!["copy"]);
+    assert_eq!(report.partitioning, PartitioningRecord::NotRequested);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn reports_round_trip_through_json() {
+    use common::scratch_dir;
+    use scann::build::BuildReport;
+    use scann::quantization::QuantizationErrorSummary;
+
+    let base = BuildReport {
+        library_version: "1.2.3".to_string(),
+        deterministic: true,
+        dataset: DatasetSummary {
+            num_points: 10,
+            dimensionality: 3,
+            min_norm: 0.5,
+            mean_norm: 1.25,
+            max_norm: 2.0,
+        },
+        phases: vec![("copy".to_string(), 0.125), ("kmeans".to_string(), 3.5)],
+        partitioning: PartitioningRecord::NotRequested,
+        quantization: None,
+        norm_cache: false,
+    };
+    let variants = [
+        base.clone(),
+        BuildReport {
+            partitioning: PartitioningRecord::Built {
+                num_leaves: 4,
+                initial_objective: 10.5,
+                final_objective: 2.25,
+                iterations: 7,
+            },
+            quantization: Some(QuantizationErrorSummary {
+                mean_abs_error: 0.001,
+                max_abs_error: 0.0625,
+            }),
+            norm_cache: true,
+            ..base.clone()
+        },
+        BuildReport {
+            partitioning: PartitioningRecord::Skipped {
+                reason: "only 3 points for \"8\" leaves".to_string(),
+            },
+            deterministic: false,
+            ..base.clone()
+        },
+    ];
+    for (i, report) in variants.iter().enumerate() {
+        let dir = scratch_dir(&format!("round_trip_{}", i));
+        build::save_build_report(&dir, report).unwrap();
+        let text = std::fs::read_to_string(dir.join(build::BUILD_REPORT_NAME)).unwrap();
+        assert_eq!(build::load_build_report(&dir).unwrap(), *report, "{}", text);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    let text = variants[1].to_json();
+    assert!(text.contains("\"kind\": \"built\""), "{}", text);
+    let text = variants[2].to_json();
+    assert!(text.contains("\"kind\": \"skipped\""), "{}", text);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn non_finite_objectives_are_written_as_null() {
+    use scann::build::BuildReport;
+
+    let report = BuildReport {
+        library_version: "1".to_string(),
+        deterministic: true,
+        dataset: DatasetSummary::default(),
+        phases: Vec::new(),
+        partitioning: PartitioningRecord::Built {
+            num_leaves: 2,
+            initial_objective: f64::INFINITY,
+            final_objective: 1.0,
+            iterations: 1,
+        },
+        quantization: None,
+        norm_cache: false,
+    };
+    let text = report.to_json();
+    assert!(text.contains("\"initial_objective\": null"), "{}", text);
+    let loaded = BuildReport::from_json(&text, "report").unwrap();
+    let PartitioningRecord::Built { initial_objective, .. } = loaded.partitioning else {
+        panic!("{:?}", loaded.partitioning);
+    };
+    assert!(initial_objective.is_nan());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn malformed_reports_name_the_bad_field() {
+    use scann::build::BuildReport;
+
+    for (text, needle) in [
+        ("not json", "is not a build report"),
+        ("{}", "missing field `library_version`"),
+        (
+            r#"{"library_version": "1", "deterministic": true,
+                "dataset": {"num_points": 1, "dimensionality": 1, "min_norm": 0, "mean_norm": 0, "max_norm": 0},
+                "phases": [], "partitioning": {"kind": "built"}, "quantization": null, "norm_cache": false}"#,
+            "missing field `num_leaves`",
+        ),
+        (
+            r#"{"library_version": "1", "deterministic": true,
+                "dataset": {"num_points": 1, "dimensionality": 1, "min_norm": 0, "mean_norm": 0, "max_norm": 0},
+                "phases": [], "partitioning": {"kind": "sideways"}, "quantization": null, "norm_cache": false}"#,
+            "unknown variant `sideways`",
+        ),
+    ] {
+        let err = BuildReport::from_json(text, "report.json").unwrap_err();
+        assert!(err.to_string().contains(needle), "{}: {}", text, err);
+        assert!(err.to_string().contains("report.json"), "{}", err);
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Platt calibration of distances into match probabilities.
+
+use scann::calibration::{self, Calibrator};
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+const PAIRS: usize = 200;
+
+// Row 2i is a positive for query i at distance ~1, row 2i + 1 a negative at
+// distance ~3, with enough noise that the classes overlap.
+fn labeled_data() -> (ScannRetriever, Vec<(DatapointPtr<f32>, usize, usize)>) {
+    let mut rng = SplitMix64::new(5);
+    let mut rows = Vec::new();
+    let mut pairs = Vec::new();
+    for i in 0..PAIRS {
+        let query = [10.0 * i as f32, 0.0];
+        rows.push(vec![query[0] + 1.0 + 0.6 * rng.next_normal(), 0.0]);
+        rows.push(vec![query[0] + 3.0 + 0.6 * rng.next_normal(), 0.0]);
+        pairs.push((DatapointPtr::new(query.to_vec()), 2 * i, 2 * i + 1));
+    }
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 5);
+    (retriever, pairs)
+}
+
+// Fraction of (positive, negative) pairs ranked correctly by `score`,
+// counting ties as half.
+fn auc(positives: &[f32], negatives: &[f32]) -> f64 {
+    let mut correct = 0.0;
+    for p in positives {
+        for n in negatives {
+            correct += match p.partial_cmp(n).unwrap() {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Equal => 0.5,
+                std::cmp::Ordering::Less => 0.0,
+            };
+        }
+    }
+    correct / (positives.len() * negatives.len()) as f64
+}
+
+#[test]
+fn fitted_curve_is_monotone_and_keeps_the_raw_ranking() {
+    let (retriever, pairs) = labeled_data();
+    let calibrator = calibration::fit_platt(&retriever, &pairs).unwrap();
+    assert!(calibrator.a > 0.0, "{:?}", calibrator);
+
+    let mut previous = f32::INFINITY;
+    for step in 0..200 {
+        let score = calibrator.apply(step as f32 * 0.1);
+        assert!((0.0..=1.0).contains(&score));
+        assert!(score <= previous, "not monotone at {}", step);
+        previous = score;
+    }
+    // Close positives score well above the negatives' typical distance.
+    assert!(calibrator.apply(1.0) > 0.9 && calibrator.apply(9.0) < 0.1, "{:?}", calibrator);
+
+    let distances = |pick: fn(&(DatapointPtr<f32>, usize, usize)) -> usize| -> Vec<f32> {
+        pairs.iter().map(|pair| retriever.distance_to_docid(&pair.0, pick(pair)).unwrap()).collect()
+    };
+    let (positive, negative) = (distances(|p| p.1), distances(|p| p.2));
+    let negated = |d: &[f32]| d.iter().map(|d| -d).collect::<Vec<_>>();
+    let calibrated = |d: &[f32]| d.iter().map(|&d| calibrator.apply(d)).collect::<Vec<_>>();
+    let raw_auc = auc(&negated(&positive), &negated(&negative));
+    let calibrated_auc = auc(&calibrated(&positive), &calibrated(&negative));
+    assert!(raw_auc > 0.8 && raw_auc < 1.0, "{}", raw_auc);
+    assert!((raw_auc - calibrated_auc).abs() < 1e-3, "{} vs {}", raw_auc, calibrated_auc);
+}
+
+#[test]
+fn fitting_needs_both_classes() {
+    assert!(calibration::fit_logistic(&[1.0, 2.0], &[true, true]).is_err());
+    assert!(calibration::fit_logistic(&[1.0], &[true, false]).is_err());
+    assert!(calibration::fit_logistic(&[], &[]).is_err());
+}
+
+#[test]
+fn fitted_parameters_persist() {
+    let calibrator = Calibrator { a: 1.25, b: -3.5e-3 };
+    assert_eq!(Calibrator::from_bytes(&calibrator.to_bytes()).unwrap(), calibrator);
+
+    let path = std::env::temp_dir().join(format!("scann_calibration_{}.txt", std::process::id()));
+    calibrator.save(&path).unwrap();
+    assert_eq!(Calibrator::load(&path).unwrap(), calibrator);
+    std::fs::write(&path, "a: 1.0\n").unwrap();
+    assert!(Calibrator::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn searches_return_calibrated_scores_on_request() {
+    let (retriever, pairs) = labeled_data();
+    let calibrator = calibration::fit_platt(&retriever, &pairs).unwrap();
+    let query = &pairs[7].0;
+
+    let (_, stats) = retriever.search_with_options(query, &SearchOptions::default()).unwrap();
+    assert!(stats.calibrated_scores.is_none());
+
+    retriever.set_calibrator(Some(calibrator));
+    let options = SearchOptions {
+        return_calibrated_scores: true,
+        ..SearchOptions::default()
+    };
+    let (results, stats) = retriever.search_with_options(query, &options).unwrap();
+    let expected: Vec<f32> = results.iter().map(|&(_, distance)| calibrator.apply(distance)).collect();
+    assert_eq!(stats.calibrated_scores, Some(expected));
+}
@@ -0,0 +1,141 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cooperative cancellation: pre-cancelled tokens, deadlines firing
+//! mid-scan, shared tokens and the error mode.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{DistanceMeasure, SquaredL2Distance};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{CancellationToken, DatapointPtr, DenseDataset};
+use std::time::Duration;
+
+const DIM: usize = 16;
+const K: usize = 10;
+
+fn retriever(rows: Vec<Vec<f32>>) -> ScannRetriever {
+    ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(SquaredL2Distance::new()), K)
+}
+
+// The kernel the search itself scores with, so the comparison holds under
+// every kernel build (the SIMD kernels sum in a different order).
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    SquaredL2Distance::new().compute_distance_f32(a, b)
+}
+
+fn cancelled_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    token.cancel();
+    token
+}
+
+fn with_token(token: CancellationToken) -> SearchOptions {
+    SearchOptions { cancellation: Some(token), ..SearchOptions::default() }
+}
+
+#[test]
+fn a_cancelled_token_returns_immediately() {
+    let rows = random_rows(5000, DIM, 1);
+    let query = DatapointPtr::new(rows[0].clone());
+    let brute_force = retriever(rows.clone());
+    let (results, stats) = brute_force.search_with_options(&query, &with_token(cancelled_token())).unwrap();
+    assert!(stats.truncated);
+    assert!(results.is_empty() && stats.datapoints_scored == 0, "{:?}", results);
+
+    let partitioned = retriever(rows);
+    let mut training = KMeansTreeTrainingOptions::new();
+    training.max_iterations = 5;
+    partitioned.build_partitions(8, &training).unwrap();
+    let options = SearchOptions { leaves_to_search: Some(4), ..with_token(cancelled_token()) };
+    let (results, stats) = partitioned.search_with_options(&query, &options).unwrap();
+    assert!(stats.truncated);
+    assert!(results.is_empty() && stats.leaves_searched == 0, "{:?}", results);
+
+    // An uncancelled token changes nothing.
+    let (results, stats) = brute_force.search_with_options(&query, &with_token(CancellationToken::new())).unwrap();
+    assert!(!stats.truncated);
+    assert_eq!(results, brute_force.search_with_options(&query, &SearchOptions::default()).unwrap().0);
+}
+
+#[test]
+fn a_deadline_mid_scan_returns_the_exact_top_k_of_the_scanned_prefix() {
+    let rows = random_rows(300_000, DIM, 2);
+    let retriever = retriever(rows.clone());
+    let query = random_rows(1, DIM, 3).remove(0);
+    let datapoint = DatapointPtr::new(query.clone());
+
+    // Deadlines short enough to fire mid-scan on any machine; take the
+    // first that leaves a partial, non-empty result.
+    let (results, stats) = [1, 2, 5, 10, 20, 50]
+        .into_iter()
+        .map(|millis| {
+            let options = with_token(CancellationToken::with_timeout(Duration::from_millis(millis)));
+            retriever.search_with_options(&datapoint, &options).unwrap()
+        })
+        .find(|(results, stats)| stats.truncated && results.len() == K)
+        .expect("no deadline fired mid-scan");
+    let scanned = stats.datapoints_scored;
+    assert!(scanned > 0 && scanned < rows.len(), "{}", scanned);
+
+    // Every returned distance is real, and the results are the best of the
+    // rows scanned before the deadline.
+    for &(docid, distance) in &results {
+        assert!(docid < scanned);
+        assert_eq!(distance, squared_l2(&query, &rows[docid]));
+    }
+    let mut prefix: Vec<(usize, f32)> =
+        rows[..scanned].iter().enumerate().map(|(i, row)| (i, squared_l2(&query, row))).collect();
+    prefix.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    assert_eq!(results, prefix[..K]);
+}
+
+#[test]
+fn clones_cancel_every_search_sharing_the_token() {
+    let shards = [retriever(random_rows(2000, DIM, 4)), retriever(random_rows(2000, DIM, 5))];
+    let query = DatapointPtr::new(vec![0.0; DIM]);
+    let token = CancellationToken::new();
+    let options = with_token(token.clone());
+    assert!(shards.iter().all(|shard| !shard.search_with_options(&query, &options).unwrap().1.truncated));
+
+    token.cancel();
+    assert!(options.cancellation.as_ref().unwrap().is_cancelled());
+    for shard in &shards {
+        let (results, stats) = shard.search_with_options(&query, &options).unwrap();
+        assert!(stats.truncated && results.is_empty());
+    }
+    let expired = CancellationToken::with_timeout(Duration::ZERO);
+    assert!(expired.is_cancelled() && expired.clone().is_cancelled());
+}
+
+#[test]
+fn error_on_cancel_and_truncated_results_are_not_cached() {
+    let retriever = retriever(random_rows(3000, DIM, 6));
+    retriever.enable_result_cache(8);
+    let query = DatapointPtr::new(vec![0.5; DIM]);
+
+    let options = SearchOptions { error_on_cancel: true, ..with_token(cancelled_token()) };
+    let error = retriever.search_with_options(&query, &options).unwrap_err();
+    assert!(error.to_string().contains("Search cancelled before completion"), "{}", error);
+
+    let options = with_token(cancelled_token());
+    assert!(retriever.search_with_options(&query, &options).unwrap().0.is_empty());
+    // Cancellation is not part of the cache key, so a cached truncated list
+    // would be served here.
+    let (results, stats) = retriever.search_with_options(&query, &SearchOptions::default()).unwrap();
+    assert!(!stats.truncated);
+    assert_eq!(results.len(), K);
+}
@@ -0,0 +1,124 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sampled certification: a healthy index passes and one scoring corrupted
+//! int8 codes fails.
+
+mod common;
+
+use common::scratch_dir;
+use scann::binfmt::{ByteOrder, Endianness, HEADER_LEN};
+use scann::distance_measures::SquaredL2Distance;
+use scann::leaf_codes::FileLeafCodeStore;
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DenseDataset, SplitMix64};
+use std::path::Path;
+use std::sync::Arc;
+
+const NUM_LEAVES: usize = 6;
+const DIM: usize = 8;
+
+fn partitioned_retriever() -> ScannRetriever {
+    let mut rng = SplitMix64::new(1);
+    let rows = (0..1500).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    retriever
+}
+
+fn all_leaves() -> SearchOptions {
+    SearchOptions { leaves_to_search: Some(NUM_LEAVES), ..SearchOptions::default() }
+}
+
+// Flips the sign bit of every stored code, leaving headers, row lists and
+// the (unverified at open) checksum alone.
+fn corrupt_codes(path: &Path) {
+    let mut bytes = std::fs::read(path).unwrap();
+    let order = ByteOrder(Endianness::Little);
+    let num_leaves = order.u64(&bytes, HEADER_LEN) as usize;
+    let dim = order.u64(&bytes, HEADER_LEN + 8) as usize;
+    let table = HEADER_LEN + 3 * 8 + 4 + dim * 4;
+    for leaf in 0..num_leaves {
+        let offset = order.u64(&bytes, table + leaf * 16) as usize;
+        let len = order.u64(&bytes, table + leaf * 16 + 8) as usize;
+        let count = order.u64(&bytes, offset) as usize;
+        for byte in &mut bytes[offset + 8 + count * 8..offset + len] {
+            *byte ^= 0x80;
+        }
+    }
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn a_healthy_index_passes_and_corrupted_codes_fail() {
+    let retriever = partitioned_retriever();
+    let report = retriever.certify(40, 10, 99, &all_leaves()).unwrap();
+    // Search kernels may sum in a different order than the exact pass, so
+    // even raw-vector distances differ in the last bits.
+    assert!(report.passed(1.0, 1e-5), "{:?}", report);
+    assert_eq!((report.seed, report.num_queries, report.k), (99, 40, 10));
+    assert_eq!(report.min_recall, 1.0);
+
+    // Intact int8 codes lose a little accuracy but still certify at a
+    // realistic bar.
+    let dir = scratch_dir("codes");
+    let codes = dir.join("leaf_codes.bin");
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever.write_leaf_codes(&codes).unwrap();
+    retriever.set_leaf_code_store(Some(Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap()))).unwrap();
+    let healthy = retriever.certify(40, 10, 99, &all_leaves()).unwrap();
+    assert!(healthy.passed(0.8, 0.5), "{:?}", healthy);
+
+    corrupt_codes(&codes);
+    retriever.set_leaf_code_store(Some(Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap()))).unwrap();
+    let corrupted = retriever.certify(40, 10, 99, &all_leaves()).unwrap();
+    assert!(!corrupted.passed(0.8, 0.5), "{:?}", corrupted);
+    assert!(corrupted.mean_recall < healthy.mean_recall);
+    assert!(corrupted.max_distance_error > 10.0 * healthy.max_distance_error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_are_reproducible_from_the_seed() {
+    let retriever = partitioned_retriever();
+    let options = SearchOptions { leaves_to_search: Some(1), ..SearchOptions::default() };
+    let first = retriever.certify(30, 10, 5, &options).unwrap();
+    let again = retriever.certify(30, 10, 5, &options).unwrap();
+    assert_eq!(
+        (first.mean_recall, first.min_recall, first.max_distance_error),
+        (again.mean_recall, again.min_recall, again.max_distance_error)
+    );
+    // One leaf of six misses some neighbors of the perturbed queries.
+    assert!(first.mean_recall < 1.0 && first.min_recall <= first.mean_recall, "{:?}", first);
+    assert!(first.violations.is_empty() && first.max_distance_error <= 1e-5, "{:?}", first);
+}
+
+#[test]
+fn removed_points_are_excluded_from_both_passes() {
+    let retriever = partitioned_retriever();
+    for docid in (0..1500).step_by(3) {
+        retriever.remove(docid).unwrap();
+    }
+    let report = retriever.certify(40, 10, 3, &all_leaves()).unwrap();
+    assert!(report.passed(1.0, 1e-5), "{:?}", report);
+
+    let empty = retriever.certify(0, 10, 3, &all_leaves()).unwrap();
+    assert_eq!((empty.num_queries, empty.mean_recall), (0, 0.0));
+}
@@ -0,0 +1,143 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ChebyshevDistance: the L-infinity formula, its thresholded early exit and
+//! the rows it prunes from a top-k scan, and retriever rankings against a
+//! brute-force reference.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{self, ChebyshevDistance, DistanceMeasure};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, RowBlock};
+use std::cell::Cell;
+
+const DIM: usize = 6;
+const K: usize = 10;
+
+fn reference(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max)
+}
+
+// Top K docids by the reference distance, ties broken by docid.
+fn brute_force(rows: &[Vec<f32>], query: &[f32]) -> Vec<(usize, f32)> {
+    let mut scored: Vec<(usize, f32)> = rows.iter().enumerate().map(|(d, r)| (d, reference(query, r))).collect();
+    scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    scored.truncate(K);
+    scored
+}
+
+#[test]
+fn distance_is_the_largest_coordinate_deviation() {
+    let measure = ChebyshevDistance::new();
+    assert_eq!(measure.compute_distance_f32(&[1.0, -2.0, 3.0], &[4.0, 2.0, 3.5]), 4.0);
+    assert_eq!(measure.compute_distance_f32(&[1.0, 2.0], &[1.0, 2.0]), 0.0);
+    assert_eq!(measure.compute_distance(&DatapointPtr::new(vec![0u8, 10]), &DatapointPtr::new(vec![7u8, 3])), 7.0);
+    for (a, b) in random_rows(50, DIM, 1).iter().zip(random_rows(50, DIM, 2).iter()) {
+        assert_eq!(measure.compute_distance_f32(a, b), reference(a, b));
+        assert_eq!(measure.compute_distance_f32(a, b), measure.compute_distance_f32(b, a));
+    }
+    let by_name = distance_measures::get_distance_measure_by_name("ChebyshevDistance").unwrap();
+    assert_eq!(by_name.name(), "ChebyshevDistance");
+}
+
+#[test]
+fn thresholded_distance_agrees_with_the_full_distance() {
+    let measure = ChebyshevDistance::new();
+    assert_eq!(measure.compute_distance_with_threshold(&[0.0, 0.0], &[1.0, 3.0], 3.0), Some(3.0));
+    // The first coordinate already exceeds the threshold.
+    assert_eq!(measure.compute_distance_with_threshold(&[0.0, 0.0], &[5.0, f32::NAN], 3.0), None);
+    for (a, b) in random_rows(50, DIM, 3).iter().zip(random_rows(50, DIM, 4).iter()) {
+        let full = measure.compute_distance_f32(a, b);
+        for threshold in [0.5, 1.0, 2.0, full] {
+            let expected = (full <= threshold).then_some(full);
+            assert_eq!(measure.compute_distance_with_threshold(a, b, threshold), expected);
+        }
+    }
+}
+
+#[test]
+fn retriever_ranks_like_brute_force() {
+    let rows = random_rows(1000, DIM, 5);
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), Box::new(ChebyshevDistance::new()), K);
+    let partitioned =
+        ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), Box::new(ChebyshevDistance::new()), K);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    partitioned.build_partitions(8, &options).unwrap();
+    let search_all = SearchOptions { leaves_to_search: Some(8), ..SearchOptions::default() };
+
+    for query in random_rows(20, DIM, 6) {
+        let expected = brute_force(&rows, &query);
+        let ptr = DatapointPtr::new(query.clone());
+        assert_eq!(retriever.search(&ptr).unwrap(), expected);
+        assert_eq!(partitioned.search_with_options(&ptr, &search_all).unwrap().0, expected);
+    }
+}
+
+// Whole numbers, so many distances tie and the scan's bound is often met
+// with equality.
+fn whole(rows: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+    rows.into_iter().map(|row| row.into_iter().map(|v| (2.0 * v).round()).collect()).collect()
+}
+
+#[test]
+fn thresholded_scans_prune_rows_and_keep_the_top_k() {
+    let measure = ChebyshevDistance::new();
+    let (rows, queries) = (random_rows(2000, DIM, 7), random_rows(5, DIM, 8));
+    for (rows, queries) in [(rows.clone(), queries.clone()), (whole(rows), whole(queries))] {
+        let flat = rows.concat();
+        for query in queries {
+            // Distances computed to the last coordinate, as opposed to ones
+            // cut off early.
+            let full = Cell::new(0);
+            let scan = distance_measures::top_k_with_threshold_segments(
+                &query,
+                [RowBlock::flat(&flat, DIM, rows.len())],
+                K,
+                |q, row, threshold| {
+                    let distance = match threshold {
+                        Some(threshold) => measure.compute_distance_with_threshold(q, row, threshold)?,
+                        None => measure.compute_distance_f32(q, row),
+                    };
+                    full.set(full.get() + 1);
+                    Some(distance)
+                },
+                |_| true,
+                |i| i,
+            );
+            assert_eq!(scan.neighbors, brute_force(&rows, &query));
+            assert_eq!(scan.scored, rows.len());
+            assert_eq!(full.get() + scan.early_exits, rows.len());
+            assert!(scan.early_exits > rows.len() / 2, "{} of {} pruned", scan.early_exits, rows.len());
+        }
+    }
+}
+
+#[test]
+fn retriever_searches_exit_early_past_the_kth_best() {
+    let rows = random_rows(2000, DIM, 9);
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), Box::new(ChebyshevDistance::new()), K);
+    for query in random_rows(5, DIM, 10) {
+        let ptr = DatapointPtr::new(query.clone());
+        let (results, stats) = retriever.search_with_options(&ptr, &SearchOptions::default()).unwrap();
+        assert_eq!(results, brute_force(&rows, &query));
+        assert_eq!(stats.datapoints_scored, rows.len());
+        assert!(stats.early_exits > rows.len() / 2, "{} of {} pruned", stats.early_exits, rows.len());
+    }
+}
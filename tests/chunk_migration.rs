@@ -0,0 +1,162 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chunk-size migration: re-chunking within documents, reuse of unchanged
+//! chunk embeddings, retrieval after rebuilding, and config checks.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::proto::RetroConfig;
+use scann::retrieval::ScannRetriever;
+use scann::retro::chunk_migration::{self, ChunkSizeMigration};
+use scann::retro::chunk_store::{ChunkStore, InMemoryChunkStore};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+use std::error::Error;
+
+const OLD_CHUNK_SIZE: usize = 4;
+const VOCAB: usize = 32;
+// Tokens 24.. appear only here, so the passage is easy to find.
+const PASSAGE: [u32; 8] = [24, 25, 26, 27, 28, 29, 30, 31];
+
+// Documents of varied length; the third holds the planted passage at an
+// offset that is a multiple of every chunk size tested.
+fn corpus() -> Vec<Vec<u32>> {
+    let mut rng = SplitMix64::new(1);
+    let mut random = |len: usize| (0..len).map(|_| rng.next_below(24) as u32).collect::<Vec<u32>>();
+    let mut planted = random(8);
+    planted.extend(PASSAGE);
+    planted.extend(random(5));
+    vec![random(10), random(3), planted, random(0), random(16), random(7)]
+}
+
+// Token counts: chunks with the same tokens embed identically.
+fn embed(tokens: &[u32]) -> Vec<f32> {
+    let mut row = vec![0.0; VOCAB];
+    tokens.iter().for_each(|&t| row[t as usize] += 1.0);
+    row
+}
+
+fn embed_store(store: &dyn ChunkStore) -> DenseDataset<f32> {
+    let rows = (0..store.num_chunks()).map(|c| embed(&store.get(c).unwrap().to_vec())).collect();
+    DenseDataset::new(rows, VOCAB)
+}
+
+fn documents_of(store: &dyn ChunkStore) -> Vec<Vec<u32>> {
+    let mut documents = vec![Vec::new(); store.num_documents()];
+    for chunk in 0..store.num_chunks() {
+        documents[store.document_of(chunk).unwrap()].extend(store.get(chunk).unwrap().to_vec());
+    }
+    documents
+}
+
+// Migrates `old` and its embeddings, returning the migration, the new
+// embeddings and the chunks sent to the embedder.
+fn migrate(old: &InMemoryChunkStore, new_size: usize) -> (ChunkSizeMigration, DenseDataset<f32>, Vec<Vec<u32>>) {
+    let migration = chunk_migration::migrate_chunk_size(old, new_size).unwrap();
+    let mut embedded = Vec::new();
+    let embeddings = chunk_migration::migrate_embeddings(&migration, &embed_store(old), |tokens| {
+        embedded.push(tokens.to_vec());
+        Ok::<_, Box<dyn Error>>(embed(tokens))
+    })
+    .unwrap();
+    (migration, embeddings, embedded)
+}
+
+#[test]
+fn migrating_up_and_down_keeps_documents_and_finds_the_passage() {
+    let old = InMemoryChunkStore::from_documents(&corpus(), OLD_CHUNK_SIZE).unwrap();
+    for new_size in [8, 2] {
+        let (migration, embeddings, embedded) = migrate(&old, new_size);
+        let store = &migration.store;
+        assert_eq!(store.chunk_size(), new_size);
+        assert_eq!(documents_of(store), corpus());
+        for chunk in 0..store.num_chunks() {
+            assert!(store.get(chunk).unwrap().len() <= new_size);
+        }
+        // Embeddings line up with the new chunks whether reused or not.
+        assert_eq!(embeddings.data, embed_store(store).data, "size {}", new_size);
+
+        // Only chunks without an identical old chunk went to the embedder,
+        // and reused chunks really are identical.
+        let recomputed: Vec<Vec<u32>> = (0..store.num_chunks())
+            .filter(|&c| migration.reused_from[c].is_none())
+            .map(|c| store.get(c).unwrap().to_vec())
+            .collect();
+        assert_eq!(embedded, recomputed, "size {}", new_size);
+        assert_eq!(embedded.len() + migration.num_reused(), store.num_chunks());
+        for (chunk, reused) in migration.reused_from.iter().enumerate() {
+            if let Some(old_id) = reused {
+                assert_eq!(store.get(chunk).unwrap().to_vec(), old.get(*old_id).unwrap().to_vec());
+            }
+        }
+
+        let retriever = ScannRetriever::new(embeddings, Box::new(SquaredL2Distance::new()), 1);
+        let query = DatapointPtr::new(embed(&PASSAGE[..new_size.min(PASSAGE.len())]));
+        let (chunk, distance) = retriever.search(&query).unwrap()[0];
+        assert_eq!(distance, 0.0);
+        assert_eq!(store.get(chunk).unwrap().to_vec(), PASSAGE[..new_size]);
+        assert_eq!(store.document_of(chunk).unwrap(), 2);
+    }
+}
+
+#[test]
+fn shrinking_reuses_short_tails_and_a_same_size_migration_reuses_everything() {
+    let old = InMemoryChunkStore::from_documents(&corpus(), OLD_CHUNK_SIZE).unwrap();
+    // Down to 2, only old tails of at most two tokens are unchanged: the
+    // 10-token document ends in two and the 21-token one in one.
+    let (migration, _, embedded) = migrate(&old, 2);
+    assert_eq!(migration.num_reused(), 2);
+    assert_eq!(embedded.len(), migration.store.num_chunks() - 2);
+
+    let (migration, embeddings, embedded) = migrate(&old, OLD_CHUNK_SIZE);
+    assert!(embedded.is_empty());
+    assert_eq!(migration.reused_from, (0..old.num_chunks()).map(Some).collect::<Vec<_>>());
+    assert_eq!(embeddings.data, embed_store(&old).data);
+}
+
+#[test]
+fn embedding_mismatches_are_reported() {
+    let old = InMemoryChunkStore::from_documents(&corpus(), OLD_CHUNK_SIZE).unwrap();
+    let migration = chunk_migration::migrate_chunk_size(&old, 8).unwrap();
+    let error = chunk_migration::migrate_embeddings(&migration, &embed_store(&old), |_| Ok(vec![0.0; 3]))
+        .unwrap_err();
+    assert!(error.to_string().contains("expected 32"), "{}", error);
+
+    let migration = chunk_migration::migrate_chunk_size(&old, OLD_CHUNK_SIZE).unwrap();
+    let truncated = DenseDataset::new(embed_store(&old).data[..2].to_vec(), VOCAB);
+    let error = chunk_migration::migrate_embeddings(&migration, &truncated, |tokens| Ok(embed(tokens))).unwrap_err();
+    assert!(error.to_string().contains("Old embeddings have 2 rows"), "{}", error);
+}
+
+#[test]
+fn config_migration_rejects_sizes_the_model_cannot_use() {
+    let mut config = RetroConfig::new();
+    config.max_seq_len = 64;
+    config.chunk_size = 4;
+    for new_size in [2, 8, 16] {
+        assert_eq!(chunk_migration::migrate_config(&config, new_size).unwrap().chunk_size, new_size as u32);
+    }
+    for (new_size, message) in [
+        (0, "out of range"),
+        (3, "does not divide max_seq_len"),
+        (128, "does not divide max_seq_len"),
+    ] {
+        let error = chunk_migration::migrate_config(&config, new_size).err().unwrap();
+        assert!(error.to_string().contains(message), "size {}: {}", new_size, error);
+    }
+    config.max_seq_len = 48;
+    config.chunk_size = 16;
+    let error = chunk_migration::migrate_config(&config, 6).err().unwrap();
+    assert!(error.to_string().contains("neither a divisor nor a multiple"), "{}", error);
+    assert_eq!(config.chunk_size, 16);
+}
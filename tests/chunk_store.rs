@@ -0,0 +1,153 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory and memory-mapped RETRO chunk stores built from one corpus.
+
+mod common;
+
+use common::scratch_dir;
+use scann::retro::chunk_store::{
+    self, ChunkStore, ChunkStoreWriter, ChunkTokens, FileChunkStore, InMemoryChunkStore, TokenWidth,
+};
+use scann::util::SplitMix64;
+use std::path::Path;
+
+const CHUNK_SIZE: usize = 4;
+
+// Documents of varied length, including an empty one and exact multiples
+// of the chunk size.
+fn corpus(max_token: u32) -> Vec<Vec<u32>> {
+    let mut rng = SplitMix64::new(1);
+    [9, 4, 0, 1, 13, 8, 3]
+        .iter()
+        .map(|&len| (0..len).map(|_| rng.next_below(max_token as usize + 1) as u32).collect())
+        .collect()
+}
+
+fn write_u32(path: &Path, documents: &[Vec<u32>]) {
+    let mut writer = ChunkStoreWriter::create(path, CHUNK_SIZE, TokenWidth::U32).unwrap();
+    for document in documents {
+        writer.add_document(document).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+fn assert_same_chunks(expected: &dyn ChunkStore, actual: &dyn ChunkStore) {
+    assert_eq!(actual.chunk_size(), expected.chunk_size());
+    assert_eq!(actual.num_chunks(), expected.num_chunks());
+    assert_eq!(actual.num_documents(), expected.num_documents());
+    for chunk in 0..expected.num_chunks() {
+        assert_eq!(actual.get(chunk).unwrap().to_vec(), expected.get(chunk).unwrap().to_vec(), "chunk {}", chunk);
+        assert_eq!(actual.document_of(chunk).unwrap(), expected.document_of(chunk).unwrap());
+        assert_eq!(
+            actual.continuation(chunk).unwrap().map(|c| c.to_vec()),
+            expected.continuation(chunk).unwrap().map(|c| c.to_vec()),
+            "continuation of chunk {}",
+            chunk
+        );
+    }
+    assert!(actual.get(expected.num_chunks()).is_err());
+}
+
+#[test]
+fn in_memory_chunks_follow_document_boundaries() {
+    let documents = corpus(1000);
+    let store = InMemoryChunkStore::from_documents(&documents, CHUNK_SIZE).unwrap();
+    // 9 -> 3 chunks, 4 -> 1, 0 -> 0, 1 -> 1, 13 -> 4, 8 -> 2, 3 -> 1.
+    assert_eq!((store.num_chunks(), store.num_documents()), (12, 7));
+    assert_eq!(store.get(2).unwrap().to_vec(), documents[0][8..]);
+    assert_eq!(store.document_of(3).unwrap(), 1);
+    assert_eq!(store.document_of(4).unwrap(), 3);
+    // The short tail of a document has no continuation; the next chunk
+    // belongs to another document.
+    assert_eq!(store.continuation(0).unwrap().unwrap().to_vec(), documents[0][4..8]);
+    assert!(store.continuation(2).unwrap().is_none());
+    assert!(store.continuation(11).unwrap().is_none());
+    assert!(InMemoryChunkStore::from_documents(&documents, 0).is_err());
+}
+
+#[test]
+fn file_backed_stores_match_the_in_memory_store() {
+    let dir = scratch_dir("match");
+    for max_token in [1000, 100_000] {
+        let documents = corpus(max_token);
+        let expected = InMemoryChunkStore::from_documents(&documents, CHUNK_SIZE).unwrap();
+
+        // write_chunk_store picks u16 tokens when the vocabulary allows.
+        let narrow = dir.join(format!("auto_{}.bin", max_token));
+        chunk_store::write_chunk_store(&narrow, &documents, CHUNK_SIZE).unwrap();
+        let mapped = FileChunkStore::open(&narrow).unwrap();
+        assert_eq!(matches!(mapped.get(0).unwrap(), ChunkTokens::U16(_)), max_token <= u16::MAX as u32);
+        assert_same_chunks(&expected, &mapped);
+
+        let wide = dir.join(format!("u32_{}.bin", max_token));
+        write_u32(&wide, &documents);
+        let mapped = FileChunkStore::open(&wide).unwrap();
+        assert!(matches!(mapped.get(0).unwrap(), ChunkTokens::U32(_)));
+        assert_same_chunks(&expected, &mapped);
+
+        // Below the threshold the file is loaded into memory, at or above
+        // it mapped; both read the same chunks.
+        for threshold in [0, u64::MAX] {
+            let opened = chunk_store::open_chunk_store(&narrow, threshold).unwrap();
+            assert_same_chunks(&expected, opened.as_ref());
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn u16_stores_reject_out_of_range_tokens_at_build_time() {
+    let dir = scratch_dir("u16");
+    let path = dir.join("chunks.bin");
+    assert_eq!(TokenWidth::for_vocab(65_536), TokenWidth::U16);
+    assert_eq!(TokenWidth::for_vocab(65_537), TokenWidth::U32);
+
+    let mut writer = ChunkStoreWriter::create(&path, CHUNK_SIZE, TokenWidth::U16).unwrap();
+    writer.add_document(&[1, 2, 65_535]).unwrap();
+    let error = writer.add_document(&[3, 65_536]).unwrap_err();
+    assert!(error.to_string().contains("Token id 65536 does not fit a u16 chunk store"), "{}", error);
+    // The rejected document left nothing behind.
+    writer.add_document(&[4, 5]).unwrap();
+    writer.finish().unwrap();
+    let store = FileChunkStore::open(&path).unwrap();
+    assert_eq!(store.num_documents(), 2);
+    assert_eq!(store.get(0).unwrap().to_vec(), vec![1, 2, 65_535]);
+    assert_eq!(store.get(1).unwrap().to_vec(), vec![4, 5]);
+
+    assert!(ChunkStoreWriter::create(&path, 0, TokenWidth::U16).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_files_are_rejected() {
+    let dir = scratch_dir("corrupt");
+    let path = dir.join("chunks.bin");
+    chunk_store::write_chunk_store(&path, &corpus(1000), CHUNK_SIZE).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    let expect_error = |bytes: &[u8], needle: &str| {
+        std::fs::write(&path, bytes).unwrap();
+        let error = FileChunkStore::open(&path).err().unwrap().to_string();
+        assert!(error.contains(needle), "{}: {}", needle, error);
+    };
+    expect_error(&bytes[..bytes.len() - 8], "tables do not match the file length");
+    let mut bad = bytes.clone();
+    bad[0] = b'X';
+    expect_error(&bad, "not a chunk store file");
+    let mut bad = bytes.clone();
+    bad[12] = 3;
+    expect_error(&bad, "invalid token width 3");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,273 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `scann` binary, run as a subprocess.
+
+mod common;
+
+use common::scratch_dir;
+use scann::util::{DenseDataset, SplitMix64};
+use scann::{artifacts, build, npy};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn scann(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_scann")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn write_dataset(dir: &Path, n: usize, dim: usize) -> PathBuf {
+    let mut rng = SplitMix64::new(11);
+    let rows = (0..n).map(|_| (0..dim).map(|_| rng.next_normal()).collect()).collect();
+    let path = dir.join("input.npy");
+    npy::save_dataset(&path, &DenseDataset::new(rows, dim), None).unwrap();
+    path
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn build_saves_artifacts_and_report_prints_them() {
+    let dir = scratch_dir("build");
+    let data = write_dataset(&dir, 300, 4);
+    let config = dir.join("config.json");
+    std::fs::write(&config, r#"{"k": 5, "num_leaves": 6, "int8_codes": true}"#).unwrap();
+    let out = dir.join("index");
+    let printed = stdout(&scann(&[
+        "build",
+        "--data",
+        data.to_str().unwrap(),
+        "--out",
+        out.to_str().unwrap(),
+        "--config",
+        config.to_str().unwrap(),
+    ]));
+    assert!(printed.contains("Partitioning: 6 leaves"), "{}", printed);
+
+    let report = build::load_build_report(&out).unwrap();
+    let phases: Vec<&str> = report.phases.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(phases, vec!["copy", "kmeans", "quantize"]);
+    assert_eq!(printed.trim_end(), report.to_string());
+    let loaded = artifacts::load_artifacts(&out).unwrap();
+    assert_eq!(loaded.dataset.size(), 300);
+    assert_eq!(loaded.tree.map(|tree| tree.num_leaves()), Some(6));
+
+    let printed = stdout(&scann(&["report", "--artifacts", out.to_str().unwrap()]));
+    assert_eq!(printed.trim_end(), report.to_string());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn streaming_build_copies_rows_without_partitioning() {
+    let dir = scratch_dir("streaming");
+    let data = write_dataset(&dir, 120, 3);
+    let out = dir.join("index");
+    let printed = stdout(&scann(&[
+        "build",
+        "--streaming",
+        "--data",
+        data.to_str().unwrap(),
+        "--out",
+        out.to_str().unwrap(),
+    ]));
+    let input = npy::load_dataset(&data, npy::LoadMode::Owned).unwrap();
+    assert!(printed.starts_with("Built with scann"), "{}", printed);
+    #[cfg(feature = "serde")]
+    {
+        let report = build::load_build_report(&out).unwrap();
+        assert_eq!(printed.trim_end(), report.to_string());
+        assert_eq!(report.phases.len(), 1);
+        assert_eq!(report.phases[0].0, "copy");
+        assert_eq!(report.dataset, build::DatasetSummary::of(&input));
+    }
+    let loaded = artifacts::load_artifacts(&out).unwrap();
+    assert_eq!(loaded.dataset.data, input.data);
+    assert!(loaded.tree.is_none());
+
+    let config = dir.join("config.txt");
+    std::fs::write(&config, "num_leaves: 4\n").unwrap();
+    let output = scann(&[
+        "build",
+        "--streaming",
+        "--data",
+        data.to_str().unwrap(),
+        "--out",
+        dir.join("partitioned").to_str().unwrap(),
+        "--config",
+        config.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only builds brute-force indexes"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn inspect_tree_writes_the_tree_exports() {
+    let dir = scratch_dir("inspect");
+    let data = write_dataset(&dir, 200, 3);
+    let out = dir.join("index");
+    let config = dir.join("config.txt");
+    std::fs::write(&config, "num_leaves: 4\n").unwrap();
+    let (data, out, config) = (data.to_str().unwrap(), out.to_str().unwrap(), config.to_str().unwrap());
+    stdout(&scann(&["build", "--data", data, "--out", out, "--config", config]));
+    let loaded = artifacts::load_artifacts(out).unwrap();
+    let tree = loaded.tree.unwrap();
+
+    let mut expected = Vec::new();
+    tree.export_json(&mut expected, &loaded.dataset).unwrap();
+    let printed = stdout(&scann(&["inspect", "--tree", "--artifacts", out, "--format", "json"]));
+    assert_eq!(printed.as_bytes(), expected);
+
+    let mut expected = Vec::new();
+    let options = scann::tree::TreeExportOptions { color_by_imbalance: true };
+    tree.export_dot(&mut expected, &loaded.dataset, &options).unwrap();
+    let printed = stdout(&scann(&["inspect", "--artifacts", out, "--tree", "--color-by-imbalance"]));
+    assert_eq!(printed.as_bytes(), expected);
+
+    let unpartitioned = dir.join("flat");
+    let unpartitioned = unpartitioned.to_str().unwrap();
+    stdout(&scann(&["build", "--data", data, "--out", unpartitioned]));
+    let output = scann(&["inspect", "--tree", "--artifacts", unpartitioned]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no trained partitioning"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn evaluate_reports_recall_for_a_described_benchmark() {
+    let dir = scratch_dir("evaluate");
+    let mut rng = SplitMix64::new(4);
+    let rows: Vec<Vec<f32>> = (0..300).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+    let queries: Vec<Vec<f32>> = (0..10).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+    let base = DenseDataset::new(rows, 4);
+    let queries = DenseDataset::new(queries, 4);
+    npy::save_dataset(dir.join("base.npy"), &base, None).unwrap();
+    npy::save_dataset(dir.join("query.npy"), &queries, None).unwrap();
+    let truth =
+        scann::evaluation::exact_ground_truth(&base, (0..300).collect(), "SquaredL2Distance", &queries, 10).unwrap();
+    let mut ivecs = Vec::new();
+    for row in truth {
+        ivecs.extend_from_slice(&10i32.to_le_bytes());
+        row.iter().for_each(|&id| ivecs.extend_from_slice(&(id as i32).to_le_bytes()));
+    }
+    std::fs::write(dir.join("gt.ivecs"), ivecs).unwrap();
+    let descriptor = dir.join("tiny.json");
+    std::fs::write(
+        &descriptor,
+        r#"{"name": "tiny", "base": "base.npy", "queries": "query.npy", "ground_truth": "gt.ivecs",
+            "format": "npy", "dimensionality": 4, "num_base": 300, "num_queries": 10, "ground_truth_k": 10,
+            "measure": "SquaredL2Distance"}"#,
+    )
+    .unwrap();
+    let config = dir.join("config.txt");
+    std::fs::write(&config, "num_leaves: 8\n").unwrap();
+    let (descriptor, dir_arg, config) = (descriptor.to_str().unwrap(), dir.to_str().unwrap(), config.to_str().unwrap());
+
+    let printed = stdout(&scann(&["evaluate", "--benchmark", descriptor, "--dir", dir_arg, "--config", config]));
+    let lines: Vec<&str> = printed.lines().collect();
+    assert_eq!(lines[0], "tiny: recall@10 over 10 queries");
+    let leaves: Vec<&str> = lines[2..].iter().map(|line| line.split_whitespace().next().unwrap()).collect();
+    assert_eq!(leaves, vec!["1", "2", "4", "8"]);
+    // Searching every leaf is exact.
+    assert_eq!(lines[5].split_whitespace().nth(1), Some("1.0000"), "{}", printed);
+
+    let printed = stdout(&scann(&["evaluate", "--benchmark", descriptor, "--dir", dir_arg]));
+    let row: Vec<&str> = printed.lines().nth(2).unwrap().split_whitespace().collect();
+    assert_eq!(row[..2], ["all", "1.0000"], "{}", printed);
+
+    let output = scann(&["evaluate", "--benchmark", "sift1m", "--dir", dir_arg]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sift_base.fvecs: cannot open"));
+    let output = scann(&["evaluate", "--benchmark", "deep1b", "--dir", dir_arg]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected one of sift1m, gist1m, glove100"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature = "serde"))]
+#[test]
+fn json_inputs_need_serde() {
+    let dir = scratch_dir("no_serde");
+    let data = write_dataset(&dir, 20, 3);
+    let out = dir.join("index");
+    stdout(&scann(&["build", "--data", data.to_str().unwrap(), "--out", out.to_str().unwrap()]));
+    assert!(!out.join(build::BUILD_REPORT_NAME).exists());
+    for args in [
+        vec!["report", "--artifacts", out.to_str().unwrap()],
+        vec!["evaluate", "--benchmark", "tiny.json", "--dir", dir.to_str().unwrap()],
+    ] {
+        let output = scann(&args);
+        assert!(!output.status.success(), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("needs scann built with --features serde"), "{:?}: {}", args, stderr);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn verify_checks_an_artifacts_directory() {
+    let dir = scratch_dir("verify");
+    let data = write_dataset(&dir, 100, 3);
+    let out = dir.join("index");
+    let config = dir.join("config.txt");
+    std::fs::write(&config, "num_leaves: 4\n").unwrap();
+    let (data, out, config) = (data.to_str().unwrap(), out.to_str().unwrap(), config.to_str().unwrap());
+    stdout(&scann(&["build", "--data", data, "--out", out, "--config", config]));
+    let printed = stdout(&scann(&["verify", "--artifacts", out]));
+    assert!(printed.starts_with(&format!("{}: 100 rows, ", out)), "{}", printed);
+    assert!(printed.trim_end().ends_with(" checks, 0 violations"), "{}", printed);
+
+    // A partitioning that drops row 99 loads, but fails verification.
+    let dataset = npy::load_dataset(data, npy::LoadMode::Owned).unwrap();
+    let centers = DenseDataset::new(vec![vec![0.0; 3]; 2], 3);
+    let tree = scann::tree::KMeansTree::from_parts(centers, vec![(0..50).collect(), (50..99).collect()], 1.0, 1);
+    let broken = dir.join("broken");
+    let artifacts_config = artifacts::ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: scann::util::Normalization::None,
+        dimensionality: 3,
+    };
+    let report = build::BuildReport::new(build::DatasetSummary::of(&dataset));
+    let docids: Vec<usize> = (0..100).collect();
+    artifacts::save_built_artifacts(&broken, &artifacts_config, &dataset, &docids, Some(&tree), &report).unwrap();
+    let output = scann(&["verify", "--artifacts", broken.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let printed = String::from_utf8_lossy(&output.stdout);
+    assert!(printed.contains("tree: 1 rows belong to no leaf, first is row 99"), "{}", printed);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed verification"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn bad_invocations_fail_with_usage() {
+    for args in [
+        vec![],
+        vec!["frobnicate"],
+        vec!["report"],
+        vec!["report", "--artifacts"],
+        vec!["report", "--bogus", "x"],
+        vec!["inspect", "--artifacts", "x"],
+        vec!["inspect", "--tree", "--artifacts", "x", "--format", "svg"],
+        vec!["build", "--streaming", "yes", "--data", "x.npy", "--out", "y"],
+        vec!["report", "--artifacts", "/nonexistent/scann"],
+        vec!["verify"],
+        vec!["verify", "--artifacts", "/nonexistent/scann"],
+    ] {
+        let output = scann(&args);
+        assert!(!output.status.success(), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("usage: scann"), "{:?}: {}", args, stderr);
+    }
+}
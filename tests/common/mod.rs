@@ -0,0 +1,40 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the integration tests, pulled in with `mod common;`.
+//! Every test binary compiles its own copy and uses a subset.
+
+#![allow(dead_code)]
+
+use scann::util::{DenseDataset, SplitMix64};
+use std::path::PathBuf;
+
+// `n` rows of `dim` standard normal values; a seed always yields the same
+// rows.
+pub fn random_rows(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n).map(|_| (0..dim).map(|_| rng.next_normal()).collect()).collect()
+}
+
+pub fn random_dataset(n: usize, dim: usize, seed: u64) -> DenseDataset<f32> {
+    DenseDataset::new(random_rows(n, dim, seed), dim)
+}
+
+// An empty directory private to this test binary, process and `name`.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scann_{}_{}_{}", env!("CARGO_CRATE_NAME"), std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,179 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compaction racing searches, upserts and deletes: every reader must see
+//! one whole snapshot, with rows, docids and tombstones that agree.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+const NUM_POINTS: usize = 2000;
+const NUM_LEAVES: usize = 8;
+const K: usize = 8;
+
+// Row of `docid` with upsert flag `flag`: [docid, flag, 0, 0]. A search
+// result whose distance does not match its docid's row under either flag
+// was scored against another docid's row.
+fn row(docid: usize, flag: f32) -> Vec<f32> {
+    vec![docid as f32, flag, 0.0, 0.0]
+}
+
+// Docids the deleter removes, in increasing order.
+fn is_deleted_class(docid: usize) -> bool {
+    docid % 4 == 3
+}
+
+// Docids the upserter rewrites.
+fn is_upserted_class(docid: usize) -> bool {
+    docid % 4 == 1
+}
+
+fn check_results(results: &[(usize, f32)], x: f32, deleted_below: usize, context: &str) {
+    let mut seen = std::collections::HashSet::new();
+    for &(docid, distance) in results {
+        assert!(seen.insert(docid), "{}: docid {} returned twice in {:?}", context, docid, results);
+        assert!(
+            !(is_deleted_class(docid) && docid < deleted_below),
+            "{}: deleted docid {} returned in {:?}",
+            context,
+            docid,
+            results
+        );
+        let base = (docid as f32 - x) * (docid as f32 - x);
+        assert!(
+            distance == base || distance == base + 1.0,
+            "{}: docid {} scored {} against a row it never held",
+            context,
+            docid,
+            distance
+        );
+    }
+    assert!(results.windows(2).all(|w| w[0].1 <= w[1].1), "{}: unsorted {:?}", context, results);
+}
+
+// Stops the compaction loop even when a reader panics, so a failure is
+// reported instead of hanging the scope.
+struct StopOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn stress(partitioned: bool) {
+    let dataset = DenseDataset::new((0..NUM_POINTS).map(|d| row(d, 0.0)).collect(), 4);
+    let retriever = ScannRetriever::new(dataset, Box::new(SquaredL2Distance::new()), K);
+    if partitioned {
+        let mut options = KMeansTreeTrainingOptions::new();
+        options.max_iterations = 5;
+        retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    }
+    let options = SearchOptions {
+        k: Some(K),
+        leaves_to_search: Some(NUM_LEAVES),
+        ..SearchOptions::default()
+    };
+    // Every deleted-class docid below this has been removed.
+    let deleted_below = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let compactions = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let _stop = StopOnDrop(&done);
+        scope.spawn(|| {
+            for docid in (0..NUM_POINTS).filter(|&d| is_deleted_class(d)) {
+                retriever.remove(docid).unwrap();
+                deleted_below.store(docid + 1, Ordering::SeqCst);
+            }
+        });
+        scope.spawn(|| {
+            let mut rng = SplitMix64::new(7);
+            for i in 0..4000 {
+                let docid = loop {
+                    let docid = rng.next_below(NUM_POINTS);
+                    if is_upserted_class(docid) {
+                        break docid;
+                    }
+                };
+                retriever.upsert(docid, &row(docid, (i % 2) as f32)).unwrap();
+            }
+        });
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                match retriever.compact() {
+                    Ok(_) => {
+                        compactions.fetch_add(1, Ordering::SeqCst);
+                    }
+                    // A concurrent write replaced the snapshot compaction
+                    // started from; nothing was swapped in.
+                    Err(e) => assert!(e.to_string().contains("changed during compaction"), "{}", e),
+                }
+            }
+        });
+        let readers: Vec<_> = (0..3)
+            .map(|seed| {
+                let retriever = &retriever;
+                let deleted_below = &deleted_below;
+                let options = &options;
+                scope.spawn(move || {
+                    let mut rng = SplitMix64::new(100 + seed);
+                    for i in 0..1500 {
+                        let below = deleted_below.load(Ordering::SeqCst);
+                        // Query at a deleted point when there is one, so a
+                        // resurrected row would rank first.
+                        let target = if below > 3 {
+                            rng.next_below(below / 4) * 4 + 3
+                        } else {
+                            rng.next_below(NUM_POINTS)
+                        };
+                        let x = target as f32;
+                        let query = DatapointPtr::new(vec![x, 0.0, 0.0, 0.0]);
+                        let (results, _) = retriever.search_with_options(&query, options).unwrap();
+                        check_results(&results, x, below, &format!("search {} of reader {}", i, seed));
+                        assert_eq!(results.len(), K);
+
+                        let upserted = target - target % 4 + 1;
+                        if let Some(values) = retriever.get_by_docid(upserted) {
+                            assert_eq!(values[0], upserted as f32, "row of docid {}", upserted);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    });
+
+    assert!(compactions.load(Ordering::SeqCst) > 0);
+    retriever.compact().unwrap();
+    assert_eq!(retriever.num_active(), NUM_POINTS - NUM_POINTS / 4);
+    let report = retriever.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+}
+
+#[test]
+fn brute_force_readers_never_observe_a_partial_compaction() {
+    stress(false);
+}
+
+#[test]
+fn partitioned_readers_never_observe_a_partial_compaction() {
+    stress(true);
+}
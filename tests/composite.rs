@@ -0,0 +1,128 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Concatenated multi-part datapoints scored with query-time part weights.
+
+use scann::distance_measures::{CompositeDistance, CompositePart, DotProductDistance, SquaredL2Distance};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn part(dims: std::ops::Range<usize>) -> CompositePart {
+    CompositePart {
+        dims,
+        measure: Box::new(SquaredL2Distance::new()),
+        weight: 1.0,
+    }
+}
+
+fn composite_retriever(rows: Vec<Vec<f32>>, split: usize, k: usize) -> ScannRetriever {
+    let dim = rows[0].len();
+    let measure = CompositeDistance::new(vec![part(0..split), part(split..dim)], dim).unwrap();
+    ScannRetriever::new(DenseDataset::new(rows, dim), Box::new(measure), k)
+}
+
+fn weighted(weights: &[f32]) -> SearchOptions {
+    SearchOptions {
+        part_weights: Some(weights.to_vec()),
+        ..SearchOptions::default()
+    }
+}
+
+#[test]
+fn zero_weight_on_the_second_part_matches_an_index_over_the_first() {
+    let mut rng = SplitMix64::new(21);
+    let rows: Vec<Vec<f32>> = (0..300).map(|_| (0..10).map(|_| rng.next_normal()).collect()).collect();
+    let first: Vec<Vec<f32>> = rows.iter().map(|row| row[..6].to_vec()).collect();
+    let composite = composite_retriever(rows, 6, 15);
+    let first_only = ScannRetriever::new(DenseDataset::new(first, 6), Box::new(SquaredL2Distance::new()), 15);
+
+    for _ in 0..20 {
+        let query: Vec<f32> = (0..10).map(|_| rng.next_normal()).collect();
+        let (results, _) = composite
+            .search_with_options(&DatapointPtr::new(query.clone()), &weighted(&[1.0, 0.0]))
+            .unwrap();
+        let expected = first_only.search(&DatapointPtr::new(query[..6].to_vec())).unwrap();
+        assert_eq!(results, expected);
+    }
+}
+
+#[test]
+fn changing_weights_reorders_results() {
+    // Row 0 matches the query's first part, row 1 its second, row 2 is
+    // halfway on both.
+    let rows = vec![
+        vec![0.0, 0.0, 4.0, 4.0],
+        vec![4.0, 4.0, 0.0, 0.0],
+        vec![2.0, 2.0, 2.0, 2.0],
+    ];
+    let retriever = composite_retriever(rows, 2, 3);
+    let query = DatapointPtr::new(vec![0.0; 4]);
+    let order = |weights: &[f32]| -> Vec<usize> {
+        let (results, _) = retriever.search_with_options(&query, &weighted(weights)).unwrap();
+        results.iter().map(|r| r.0).collect()
+    };
+    assert_eq!(order(&[1.0, 0.0]), vec![0, 2, 1]);
+    assert_eq!(order(&[0.0, 1.0]), vec![1, 2, 0]);
+    // Equal weights: 16 + 16 for the middle row beats 0 + 32.
+    assert_eq!(order(&[1.0, 1.0])[0], 2);
+    assert_eq!(order(&[1.0, 0.25]), vec![0, 2, 1]);
+    assert_eq!(order(&[0.25, 1.0]), vec![1, 2, 0]);
+
+    // Without part_weights the weights built into the measure apply.
+    let (results, _) = retriever.search_with_options(&query, &SearchOptions::default()).unwrap();
+    assert_eq!(results[0], (2, 16.0));
+}
+
+#[test]
+fn weighted_distance_is_the_sum_of_part_distances() {
+    let measure = CompositeDistance::new(
+        vec![
+            part(0..2),
+            CompositePart {
+                dims: 2..3,
+                measure: Box::new(DotProductDistance::new()),
+                weight: 1.0,
+            },
+        ],
+        3,
+    )
+    .unwrap();
+    assert_eq!(measure.layout(), vec![0..2, 2..3]);
+    let (a, b) = ([1.0, 2.0, 3.0], [0.0, 0.0, 2.0]);
+    assert_eq!(measure.compute_weighted(&a, &b, &[0.5, 2.0]).unwrap(), 0.5 * 5.0 + 2.0 * -6.0);
+    assert!(measure.compute_weighted(&a, &b, &[1.0]).is_err());
+}
+
+#[test]
+fn layouts_and_weights_are_validated() {
+    let error = |parts: Vec<CompositePart>, dim: usize| CompositeDistance::new(parts, dim).err().unwrap().to_string();
+    assert!(error(vec![], 4).contains("at least one part"));
+    assert!(error(vec![part(0..2), part(3..4)], 4).contains("expected a non-empty range starting at 2"));
+    assert!(error(vec![part(0..2), part(2..2)], 2).contains("non-empty"));
+    assert!(error(vec![part(0..2), part(2..3)], 4).contains("cover 3 dimensions but the dataset has 4"));
+
+    let retriever = composite_retriever(vec![vec![0.0; 4]; 3], 2, 3);
+    let query = DatapointPtr::new(vec![0.0; 4]);
+    let message = retriever.search_with_options(&query, &weighted(&[1.0])).unwrap_err().to_string();
+    assert!(message.contains("Got 1 part weights for 2 composite parts"), "{}", message);
+
+    let plain = ScannRetriever::new(
+        DenseDataset::new(vec![vec![0.0; 4]; 3], 4),
+        Box::new(SquaredL2Distance::new()),
+        3,
+    );
+    let message = plain.search_with_options(&query, &weighted(&[1.0, 1.0])).unwrap_err().to_string();
+    assert!(message.contains("requires the retriever to use a CompositeDistance"), "{}", message);
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! zstd-compressed `.npy.zst` dataset sections.
+
+mod common;
+
+use common::scratch_dir;
+use scann::npy::{self, LoadMode, ZstdCompression};
+use scann::util::{DenseDataset, SplitMix64};
+
+// Low-entropy rows so compression has something to gain.
+fn dataset() -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(17);
+    let rows = (0..500).map(|_| (0..16).map(|_| rng.next_below(4) as f32 * 0.25).collect()).collect();
+    DenseDataset::new(rows, 16)
+}
+
+#[test]
+fn mmap_is_refused_for_compressed_artifacts() {
+    let dir = scratch_dir("mmap");
+    let path = dir.join("dataset.npy.zst");
+    assert!(npy::is_compressed_path(&path));
+    assert!(!npy::is_compressed_path(&dir.join("dataset.npy")));
+    std::fs::write(&path, b"not even zstd").unwrap();
+    let message = npy::load_dataset(&path, LoadMode::Mmap).unwrap_err().to_string();
+    assert!(message.contains("Cannot memory-map compressed artifact"), "{}", message);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn uncompressed_sizes_match_the_file() {
+    let dir = scratch_dir("plain");
+    let path = dir.join("dataset.npy");
+    let data = dataset();
+    let sizes = npy::save_dataset(&path, &data, None).unwrap();
+    assert_eq!(sizes.uncompressed_bytes, sizes.stored_bytes);
+    assert_eq!(sizes.stored_bytes, std::fs::metadata(&path).unwrap().len());
+    assert_eq!(npy::load_dataset(&path, LoadMode::Owned).unwrap().data, data.data);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compressed_round_trip_is_byte_identical() {
+    let dir = scratch_dir("round_trip");
+    let data = dataset();
+    let plain = dir.join("dataset.npy");
+    npy::save_dataset(&plain, &data, None).unwrap();
+    for level in [1, 3, 19] {
+        let path = dir.join(format!("level{}.npy.zst", level));
+        let sizes = npy::save_dataset(&path, &data, Some(ZstdCompression { level })).unwrap();
+        assert_eq!(sizes.uncompressed_bytes, std::fs::metadata(&plain).unwrap().len());
+        assert_eq!(sizes.stored_bytes, std::fs::metadata(&path).unwrap().len());
+        assert!(sizes.stored_bytes < sizes.uncompressed_bytes / 4, "level {}: {:?}", level, sizes);
+
+        let loaded = npy::load_dataset(&path, LoadMode::Owned).unwrap();
+        assert_eq!(loaded.dimensionality(), 16);
+        let bits = |d: &DenseDataset<f32>| d.data.iter().flatten().map(|v| v.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(&loaded), bits(&data));
+        assert_eq!(npy::encode_npy_f32(&loaded), std::fs::read(&plain).unwrap());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn corrupt_compressed_artifacts_are_rejected() {
+    let dir = scratch_dir("corrupt");
+    let path = dir.join("dataset.npy.zst");
+    npy::save_dataset(&path, &dataset(), Some(ZstdCompression { level: 3 })).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    // A size header that disagrees with the frame.
+    let mut wrong_size = bytes.clone();
+    wrong_size[0] ^= 1;
+    std::fs::write(&path, &wrong_size).unwrap();
+    let message = npy::load_dataset(&path, LoadMode::Owned).unwrap_err().to_string();
+    assert!(message.contains("header declares"), "{}", message);
+
+    std::fs::write(&path, &bytes[..4]).unwrap();
+    let message = npy::load_dataset(&path, LoadMode::Owned).unwrap_err().to_string();
+    assert!(message.contains("Truncated .npy.zst size header"), "{}", message);
+
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+    assert!(npy::load_dataset(&path, LoadMode::Owned).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn compression_requires_the_zstd_feature() {
+    let dir = scratch_dir("no_feature");
+    let path = dir.join("dataset.npy.zst");
+    let message = npy::save_dataset(&path, &dataset(), Some(ZstdCompression { level: 3 }))
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("`zstd` feature"), "{}", message);
+    assert!(!path.exists());
+
+    std::fs::write(&path, [0u8; 16]).unwrap();
+    let message = npy::load_dataset(&path, LoadMode::Owned).unwrap_err().to_string();
+    assert!(message.contains("requires building scann with the `zstd` feature"), "{}", message);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
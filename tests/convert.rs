@@ -0,0 +1,101 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Checked dtype conversions and the query promotion policy.
+
+use scann::convert::{self, DType, Promotion};
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset};
+
+#[test]
+fn promotion_policy_covers_every_pair() {
+    let all = [DType::F64, DType::F32, DType::I8, DType::U8];
+    for query in all {
+        for index in all {
+            let expected = match (query, index) {
+                _ if query == index => Promotion::Identity,
+                (DType::F64 | DType::F32, DType::I8) => Promotion::QuantizeQuery,
+                _ => Promotion::ConvertQuery(index),
+            };
+            assert_eq!(convert::promotion_for(query, index), expected, "{:?} -> {:?}", query, index);
+        }
+    }
+    assert_eq!(convert::promotion_for(DType::F64, DType::F32), Promotion::ConvertQuery(DType::F32));
+    assert_eq!(convert::promotion_for(DType::U8, DType::F32), Promotion::ConvertQuery(DType::F32));
+    assert_eq!(convert::promotion_for(DType::U8, DType::I8), Promotion::ConvertQuery(DType::I8));
+}
+
+#[test]
+fn f64_to_f32_reports_the_narrowing_error() {
+    let data = DenseDataset::new(vec![vec![1.0, 0.1], vec![-2.5, 1.0 / 3.0]], 2);
+    let (narrowed, stats) = convert::f64_to_f32_dataset(&data).unwrap();
+    assert_eq!(narrowed.data, vec![vec![1.0f32, 0.1], vec![-2.5, 1.0 / 3.0]]);
+    let expected = [0.1f64, 1.0 / 3.0].iter().map(|&v| (v - v as f32 as f64).abs()).fold(0.0, f64::max);
+    assert_eq!(stats.max_abs_error, expected);
+    assert_eq!(stats.num_clipped, 0);
+
+    let (query, stats) = convert::f64_to_f32_datapoint(&DatapointPtr::new(vec![0.5, 2.0])).unwrap();
+    assert_eq!((query.values(), stats.max_abs_error), (&[0.5f32, 2.0][..], 0.0));
+}
+
+#[test]
+fn f64_to_f32_rejects_non_finite_values() {
+    for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300] {
+        let data = DenseDataset::new(vec![vec![0.0, 0.0], vec![0.0, bad]], 2);
+        let message = convert::f64_to_f32_dataset(&data).err().unwrap().to_string();
+        assert!(message.contains("at row 1, dimension 1"), "{}", message);
+        let message = convert::f64_to_f32_datapoint(&DatapointPtr::new(vec![bad])).err().unwrap().to_string();
+        assert!(message.contains("Non-finite value") && message.contains("dimension 0"), "{}", message);
+    }
+}
+
+#[test]
+fn f32_to_i8_with_given_and_computed_multipliers() {
+    let data = DenseDataset::new(vec![vec![1.0, -0.5], vec![-2.0, 0.25], vec![0.5, 0.5]], 2);
+    let (quantized, stats) = convert::f32_to_i8_dataset(&data, None).unwrap();
+    assert_eq!(quantized.multipliers, vec![127.0 / 2.0, 127.0 / 0.5]);
+    assert_eq!(quantized.codes.data[1], vec![-127, 64]);
+    assert_eq!(stats.num_clipped, 0);
+    assert!(stats.max_abs_error <= 0.5 / quantized.multipliers[0] as f64 + 1e-7);
+
+    // Multipliers chosen for a smaller range clip the out-of-range values.
+    let (quantized, stats) = convert::f32_to_i8_dataset(&data, Some(&[127.0, 127.0])).unwrap();
+    assert_eq!(quantized.codes.data[1], vec![-127, 32]);
+    assert_eq!(stats.num_clipped, 1);
+    assert!((stats.max_abs_error - 1.0).abs() < 1e-6, "{}", stats.max_abs_error);
+    assert!(convert::f32_to_i8_dataset(&data, Some(&[1.0])).is_err());
+
+    let (query, stats) = convert::f32_to_i8_datapoint(&DatapointPtr::new(vec![0.5, -0.25]), &[10.0, 4.0]).unwrap();
+    assert_eq!((query.values(), stats.num_clipped), (&[5i8, -1][..], 0));
+    assert!(convert::f32_to_i8_datapoint(&DatapointPtr::new(vec![0.5]), &[1.0, 1.0]).is_err());
+}
+
+#[test]
+fn u8_to_f32_is_lossless() {
+    let data = DenseDataset::new(vec![vec![0u8, 255], vec![7, 128]], 2);
+    assert_eq!(convert::u8_to_f32_dataset(&data).data, vec![vec![0.0, 255.0], vec![7.0, 128.0]]);
+    assert_eq!(convert::u8_to_f32_datapoint(&DatapointPtr::new(vec![3u8, 200])).values(), &[3.0, 200.0]);
+}
+
+#[test]
+fn f64_queries_are_narrowed_before_searching() {
+    let data = DenseDataset::new((0..10).map(|i| vec![i as f32, 0.0]).collect(), 2);
+    let retriever = ScannRetriever::new(data, Box::new(SquaredL2Distance::new()), 3);
+    let results = retriever.search_f64(&DatapointPtr::new(vec![4.2, 0.0])).unwrap();
+    let expected = retriever.search(&DatapointPtr::new(vec![4.2f32, 0.0])).unwrap();
+    assert_eq!(results, expected);
+    assert!(retriever.search_f64(&DatapointPtr::new(vec![f64::NAN, 0.0])).is_err());
+}
@@ -0,0 +1,221 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synthetic dataset generators: determinism, metadata that agrees with
+//! the rows, and ground truth from the reference searcher.
+
+use scann::testing::datasets::{self, BlobsSpec, GeneratedDataset, HeavyTailSpec, LowRankSpec, NearDuplicateSpec};
+use scann::util::DenseDataset;
+
+fn blobs(seed: u64) -> GeneratedDataset {
+    datasets::gaussian_blobs(&BlobsSpec {
+        num_points: 400,
+        dimensionality: 6,
+        num_clusters: 5,
+        center_spread: 10.0,
+        cluster_std: 0.5,
+        seed,
+    })
+    .unwrap()
+}
+
+fn low_rank(noise_std: f32) -> GeneratedDataset {
+    datasets::low_rank_plus_noise(&LowRankSpec { num_points: 50, dimensionality: 12, rank: 3, noise_std, seed: 4 })
+        .unwrap()
+}
+
+fn heavy_tailed(seed: u64) -> GeneratedDataset {
+    datasets::heavy_tailed_norms(&HeavyTailSpec { num_points: 500, dimensionality: 8, max_norm: 100.0, seed })
+        .unwrap()
+}
+
+fn near_duplicates(seed: u64) -> GeneratedDataset {
+    datasets::binary_near_duplicates(&NearDuplicateSpec {
+        num_points: 200,
+        dimensionality: 64,
+        num_planted: 20,
+        max_flips: 3,
+        seed,
+    })
+    .unwrap()
+}
+
+fn bits(dataset: &DenseDataset<f32>) -> Vec<u32> {
+    dataset.data.iter().flatten().map(|v| v.to_bits()).collect()
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// FNV-1a over the row bits, pinned below so a change to any generator, or a
+// platform producing different bits, fails loudly.
+fn fingerprint(dataset: &DenseDataset<f32>) -> u64 {
+    bits(dataset).iter().fold(0xcbf29ce484222325, |hash, &word| (hash ^ word as u64).wrapping_mul(0x100000001b3))
+}
+
+#[test]
+fn generators_are_deterministic_per_seed() {
+    for (a, b, c) in [
+        (blobs(1), blobs(1), blobs(2)),
+        (heavy_tailed(1), heavy_tailed(1), heavy_tailed(2)),
+        (near_duplicates(1), near_duplicates(1), near_duplicates(2)),
+    ] {
+        assert_eq!(bits(&a.dataset), bits(&b.dataset));
+        assert_eq!((a.labels.clone(), a.planted_pairs.clone()), (b.labels, b.planted_pairs));
+        assert_ne!(bits(&a.dataset), bits(&c.dataset));
+    }
+    assert_eq!(bits(&low_rank(0.1).dataset), bits(&low_rank(0.1).dataset));
+}
+
+#[test]
+fn generated_bits_are_pinned() {
+    let fingerprints = [
+        fingerprint(&blobs(1).dataset),
+        fingerprint(&low_rank(0.1).dataset),
+        fingerprint(&heavy_tailed(1).dataset),
+        fingerprint(&near_duplicates(1).dataset),
+    ];
+    assert_eq!(
+        fingerprints,
+        [12498665608734869432, 8895546766354351667, 1585263764428443833, 14652369958975114021]
+    );
+}
+
+#[test]
+fn blob_labels_match_the_nearest_cluster_mean() {
+    let generated = blobs(3);
+    assert_eq!(generated.dataset.size(), 400);
+    assert_eq!(generated.labels.len(), 400);
+    let mut means = vec![vec![0.0f32; 6]; 5];
+    let mut counts = vec![0usize; 5];
+    for (row, &label) in generated.dataset.data.iter().zip(&generated.labels) {
+        counts[label] += 1;
+        means[label].iter_mut().zip(row).for_each(|(m, v)| *m += v);
+    }
+    assert!(counts.iter().all(|&count| count > 0), "{:?}", counts);
+    for (mean, &count) in means.iter_mut().zip(&counts) {
+        mean.iter_mut().for_each(|m| *m /= count as f32);
+    }
+    for (i, (row, &label)) in generated.dataset.data.iter().zip(&generated.labels).enumerate() {
+        let nearest = (0..5).min_by(|&a, &b| squared_l2(row, &means[a]).total_cmp(&squared_l2(row, &means[b])));
+        assert_eq!(nearest, Some(label), "row {}", i);
+    }
+}
+
+#[test]
+fn noiseless_low_rank_rows_lie_in_the_latent_span() {
+    let generated = low_rank(0.0);
+    assert_eq!(generated.latent_basis.len(), 3);
+    // Gram-Schmidt over the basis, then check each row's residual.
+    let mut orthonormal: Vec<Vec<f64>> = Vec::new();
+    for direction in &generated.latent_basis {
+        let mut v: Vec<f64> = direction.iter().map(|&x| x as f64).collect();
+        for u in &orthonormal {
+            let dot: f64 = v.iter().zip(u).map(|(a, b)| a * b).sum();
+            v.iter_mut().zip(u).for_each(|(a, b)| *a -= dot * b);
+        }
+        let norm = v.iter().map(|a| a * a).sum::<f64>().sqrt();
+        orthonormal.push(v.into_iter().map(|a| a / norm).collect());
+    }
+    for row in &generated.dataset.data {
+        let mut residual: Vec<f64> = row.iter().map(|&x| x as f64).collect();
+        let norm = residual.iter().map(|a| a * a).sum::<f64>().sqrt();
+        for u in &orthonormal {
+            let dot: f64 = residual.iter().zip(u).map(|(a, b)| a * b).sum();
+            residual.iter_mut().zip(u).for_each(|(a, b)| *a -= dot * b);
+        }
+        assert!(residual.iter().map(|a| a * a).sum::<f64>().sqrt() < 1e-5 * norm.max(1.0));
+    }
+    assert!(low_rank(0.0).labels.is_empty() && low_rank(0.0).planted_pairs.is_empty());
+}
+
+#[test]
+fn heavy_tailed_norms_stay_in_range_with_a_long_tail() {
+    let generated = heavy_tailed(5);
+    let mut norms: Vec<f32> =
+        generated.dataset.data.iter().map(|row| row.iter().map(|v| v * v).sum::<f32>().sqrt()).collect();
+    norms.sort_by(f32::total_cmp);
+    assert!(norms[0] >= 1.0 - 1e-4 && norms[norms.len() - 1] <= 100.0 * (1.0 + 1e-4), "{:?}", norms);
+    // Pareto(1): the median norm is about 2 while the top rows reach tens.
+    assert!(norms[norms.len() / 2] < 3.0, "median {}", norms[norms.len() / 2]);
+    assert!(norms[norms.len() - 1] > 20.0 * norms[norms.len() / 2]);
+}
+
+#[test]
+fn planted_pairs_are_near_duplicates() {
+    let generated = near_duplicates(6);
+    assert_eq!(generated.planted_pairs.len(), 20);
+    assert!(generated.dataset.data.iter().flatten().all(|&v| v == 0.0 || v == 1.0));
+    for (i, &(original, copy)) in generated.planted_pairs.iter().enumerate() {
+        assert!(original < 180);
+        assert_eq!(copy, 180 + i);
+        let flips = squared_l2(&generated.dataset.data[original], &generated.dataset.data[copy]);
+        assert!(flips <= 3.0, "pair {:?} differs in {} coordinates", (original, copy), flips);
+    }
+}
+
+#[test]
+fn ground_truth_matches_a_brute_force_ranking() {
+    let generated = blobs(7);
+    let queries = blobs(8).dataset.data.into_iter().take(10).collect::<Vec<_>>();
+    let truth =
+        datasets::ground_truth(&generated, &DenseDataset::new(queries.clone(), 6), 5, "SquaredL2Distance").unwrap();
+    assert_eq!(truth.len(), 10);
+    for (query, found) in queries.iter().zip(&truth) {
+        let mut ranked: Vec<usize> = (0..generated.dataset.size()).collect();
+        ranked.sort_by(|&a, &b| {
+            squared_l2(query, &generated.dataset.data[a]).total_cmp(&squared_l2(query, &generated.dataset.data[b]))
+        });
+        assert_eq!(found, &ranked[..5]);
+    }
+
+    // Each planted copy is the nearest row to itself, and its original is
+    // within max_flips.
+    let generated = near_duplicates(9);
+    let copies: Vec<Vec<f32>> =
+        generated.planted_pairs.iter().map(|&(_, copy)| generated.dataset.data[copy].clone()).collect();
+    let truth = datasets::ground_truth(&generated, &DenseDataset::new(copies, 64), 1, "SquaredL2Distance").unwrap();
+    for (found, &(_, copy)) in truth.iter().zip(&generated.planted_pairs) {
+        let distance = squared_l2(&generated.dataset.data[found[0]], &generated.dataset.data[copy]);
+        assert_eq!(distance, 0.0);
+    }
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let blobs = BlobsSpec {
+        num_points: 10,
+        dimensionality: 2,
+        num_clusters: 0,
+        center_spread: 1.0,
+        cluster_std: 1.0,
+        seed: 0,
+    };
+    assert!(datasets::gaussian_blobs(&blobs).is_err());
+    assert!(datasets::gaussian_blobs(&BlobsSpec { num_clusters: 2, num_points: 0, ..blobs.clone() }).is_err());
+    assert!(datasets::gaussian_blobs(&BlobsSpec { num_clusters: 2, dimensionality: 0, ..blobs }).is_err());
+
+    let low_rank = LowRankSpec { num_points: 10, dimensionality: 4, rank: 5, noise_std: 0.0, seed: 0 };
+    assert!(datasets::low_rank_plus_noise(&low_rank).is_err());
+    assert!(datasets::low_rank_plus_noise(&LowRankSpec { rank: 0, ..low_rank }).is_err());
+
+    for max_norm in [0.5, f32::NAN, f32::INFINITY] {
+        let spec = HeavyTailSpec { num_points: 10, dimensionality: 4, max_norm, seed: 0 };
+        assert!(datasets::heavy_tailed_norms(&spec).is_err(), "max_norm {}", max_norm);
+    }
+
+    let spec = NearDuplicateSpec { num_points: 10, dimensionality: 4, num_planted: 10, max_flips: 1, seed: 0 };
+    assert!(datasets::binary_near_duplicates(&spec).is_err());
+}
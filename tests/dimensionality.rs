@@ -0,0 +1,208 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dimensionality mismatches are errors at every search entry point, never
+//! truncated or NaN distances.
+
+use nalgebra::DMatrix;
+use scann::distance_measures::{
+    self, CompositeDistance, CompositePart, DistanceMeasure, L1Distance, L2Distance, MahalanobisDistance,
+    SquaredL2Distance, WeightedSquaredL2Distance,
+};
+use scann::projection::PcaProjection;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DatapointRef, DenseDataset, SplitMix64};
+
+fn dataset(dim: usize) -> DenseDataset<f32> {
+    DenseDataset::new((0..20).map(|i| (0..dim).map(|d| (i * dim + d) as f32 * 0.1).collect()).collect(), dim)
+}
+
+fn assert_mismatch<T>(result: Result<T, Box<dyn std::error::Error>>, context: &str) {
+    let Err(error) = result else {
+        panic!("{}: expected a dimensionality error", context);
+    };
+    let error = error.to_string();
+    assert!(error.contains("imensionality"), "{}: {}", context, error);
+}
+
+#[test]
+fn queries_of_the_wrong_dimensionality_are_rejected_everywhere() {
+    let retriever = ScannRetriever::new(dataset(4), Box::new(SquaredL2Distance::new()), 3);
+    for dim in [3, 5] {
+        let query = DatapointPtr::new(vec![0.5; dim]);
+        assert_mismatch(retriever.search_with_options(&query, &SearchOptions::default()), "search_with_options");
+        assert_mismatch(retriever.prepare(&query), "prepare");
+        assert_mismatch(retriever.distance_to_docid(&query, 0), "distance_to_docid");
+        assert_mismatch(
+            retriever.search_small_k::<2>(DatapointRef::from_slice(&vec![0.5; dim])),
+            "search_small_k",
+        );
+    }
+}
+
+#[test]
+fn measures_with_a_fixed_dimensionality_fail_the_search_instead_of_returning_nan() {
+    let query = DatapointPtr::new(vec![0.5; 4]);
+    let measures: Vec<(&str, Box<dyn DistanceMeasure>)> = vec![
+        ("weighted", Box::new(WeightedSquaredL2Distance::new(vec![1.0; 3]).unwrap())),
+        ("mahalanobis", Box::new(MahalanobisDistance::new(DMatrix::identity(5, 5)).unwrap())),
+        (
+            "composite",
+            Box::new(
+                CompositeDistance::new(
+                    vec![CompositePart {
+                        dims: 0..6,
+                        measure: Box::new(SquaredL2Distance::new()),
+                        weight: 1.0,
+                    }],
+                    6,
+                )
+                .unwrap(),
+            ),
+        ),
+        (
+            "composite part",
+            Box::new(
+                CompositeDistance::new(
+                    vec![
+                        CompositePart {
+                            dims: 0..2,
+                            measure: Box::new(SquaredL2Distance::new()),
+                            weight: 1.0,
+                        },
+                        CompositePart {
+                            dims: 2..4,
+                            measure: Box::new(WeightedSquaredL2Distance::new(vec![1.0; 3]).unwrap()),
+                            weight: 1.0,
+                        },
+                    ],
+                    4,
+                )
+                .unwrap(),
+            ),
+        ),
+    ];
+    for (name, measure) in measures {
+        assert!(measure.check_dimensionality(4).is_err(), "{}", name);
+        assert_mismatch(
+            distance_measures::compute_pairwise_distances(measure.as_ref(), &dataset(4), &dataset(4)),
+            name,
+        );
+        let retriever = ScannRetriever::new(dataset(4), measure, 3);
+        assert_mismatch(retriever.search_with_options(&query, &SearchOptions::default()), name);
+    }
+
+    // Matching weights search normally.
+    let weighted = WeightedSquaredL2Distance::new(vec![1.0; 4]).unwrap();
+    assert!(weighted.check_dimensionality(4).is_ok());
+    let retriever = ScannRetriever::new(dataset(4), Box::new(weighted), 3);
+    let results = retriever.search(&query).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|(_, d)| d.is_finite()));
+}
+
+#[test]
+fn try_distance_rejects_mismatched_inputs() {
+    let (a, b) = ([1.0f32, 2.0, 3.0], [1.0f32, 2.0]);
+    assert_mismatch(SquaredL2Distance::new().try_distance(&a, &b), "squared l2");
+    assert_mismatch(L2Distance::new().try_distance(&a, &b), "l2");
+    assert_mismatch(
+        L1Distance::new().try_distance(&DatapointPtr::new(a.to_vec()), &DatapointPtr::new(b.to_vec())),
+        "l1",
+    );
+    assert_mismatch(WeightedSquaredL2Distance::new(vec![1.0; 3]).unwrap().try_distance(&a, &b), "weighted");
+    assert_mismatch(MahalanobisDistance::new(DMatrix::identity(3, 3)).unwrap().try_distance(&a, &b), "mahalanobis");
+    assert_eq!(SquaredL2Distance::new().try_distance(&a, &a).unwrap(), 0.0);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "Dimensionality mismatch")]
+fn kernels_assert_matching_lengths_in_debug_builds() {
+    SquaredL2Distance::new().compute_distance_f32(&[1.0, 2.0, 3.0], &[1.0, 2.0]);
+}
+
+#[test]
+fn l1_checks_dimensionality_for_every_input_type() {
+    let l1 = L1Distance::new();
+    let rows = dataset(3);
+    let mut out = vec![1.0; 7];
+    assert_mismatch(l1.one_to_many(&[0.0; 2], &rows, &mut out), "one_to_many");
+    l1.one_to_many(&[0.0; 3], &rows, &mut out).unwrap();
+    assert_eq!(out.len(), rows.size());
+    assert_eq!(out[1], rows.data[1].iter().sum::<f32>());
+
+    let (a, b) = (DatapointPtr::new(vec![1i8, -2, 3]), DatapointPtr::new(vec![0i8, 2]));
+    assert_mismatch(l1.try_distance(&a, &b), "i8");
+    assert_eq!(l1.try_distance(&a, &DatapointPtr::new(vec![0i8, 2, 0])).unwrap(), 8.0);
+    let (a, b) = (DatapointPtr::new(vec![1u8, 2]), DatapointPtr::new(vec![3u8]));
+    assert_mismatch(l1.try_distance(&a, &b), "u8");
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn probability_and_hyperbolic_kernels_assert_instead_of_returning_nan() {
+    use distance_measures::{CrossEntropyDistance, KLDivergenceDistance, PoincareDistance};
+    let measures: Vec<Box<dyn DistanceMeasure>> = vec![
+        Box::new(KLDivergenceDistance::new(true)),
+        Box::new(CrossEntropyDistance::new(true)),
+        Box::new(PoincareDistance::new(true)),
+    ];
+    for measure in measures {
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            measure.compute_distance_f32(&[0.1, 0.2, 0.3], &[0.1, 0.2])
+        }));
+        assert!(outcome.is_err(), "{} scored a mismatched pair", measure.name());
+    }
+}
+
+#[test]
+fn checked_construction_rejects_empty_and_mismatched_datapoints() {
+    let message = DatapointPtr::<f32>::try_new(Vec::new(), None).err().unwrap().to_string();
+    assert_eq!(message, "Empty datapoint");
+    let message = DatapointPtr::try_new(vec![1.0f32; 3], Some(768)).err().unwrap().to_string();
+    assert_eq!(message, "datapoint has dimensionality 3, expected 768");
+
+    let datapoint = DatapointPtr::try_new(vec![1.0f32, 2.0], Some(2)).unwrap();
+    assert_eq!((datapoint.dimensionality(), datapoint.is_empty()), (2, false));
+    assert_eq!(DatapointPtr::try_new(vec![1u8], None).unwrap().dimensionality(), 1);
+    let borrowed = [3.0f32, 4.0, 5.0];
+    assert_eq!(DatapointPtr::from_slice(&borrowed).values(), &borrowed);
+
+    // The unchecked constructor still accepts anything for internal use.
+    let unchecked = DatapointPtr::new(Vec::<f32>::new());
+    assert!(unchecked.is_empty());
+    assert_eq!(unchecked.check_dimensionality(None, "Query").unwrap_err().to_string(), "Empty Query");
+}
+
+#[test]
+fn entry_points_name_the_argument_in_dimensionality_errors() {
+    let retriever = ScannRetriever::new(dataset(4), Box::new(SquaredL2Distance::new()), 3);
+    let message = retriever.search(&DatapointPtr::new(Vec::new())).unwrap_err().to_string();
+    assert!(message.contains("Empty Query"), "{}", message);
+    let message = retriever.search(&DatapointPtr::from_slice(&[0.5; 3])).unwrap_err().to_string();
+    assert!(message.contains("Query has dimensionality 3, expected 4"), "{}", message);
+
+    let mut pca = PcaProjection::<f32>::new(4, 2).unwrap();
+    let mut rng = SplitMix64::new(2);
+    let training = DenseDataset::new((0..50).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect(), 4);
+    pca.create(&training, true, None).unwrap();
+    let mut projected = DatapointPtr::new(Vec::<f32>::new());
+    let message = pca.project_input(&DatapointPtr::new(Vec::new()), &mut projected).unwrap_err().to_string();
+    assert!(message.contains("Empty PCA input"), "{}", message);
+    let message = pca.project_input(&DatapointPtr::from_slice(&[1.0; 5]), &mut projected).unwrap_err().to_string();
+    assert!(message.contains("PCA input has dimensionality 5, expected 4"), "{}", message);
+    pca.project_input(&DatapointPtr::from_slice(&[1.0; 4]), &mut projected).unwrap();
+    assert_eq!(projected.dimensionality(), 2);
+}
@@ -0,0 +1,60 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scoring dataset rows in place through compute_distance_to_row.
+
+mod common;
+
+use common::random_dataset;
+use scann::distance_measures::{
+    compute_distance_to_row, CosineDistance, DistanceMeasure, DotProductDistance, SquaredL2Distance,
+};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, SegmentedDataset};
+
+const DIM: usize = 24;
+
+#[test]
+fn rows_score_like_cloned_datapoints_in_either_layout() {
+    let dense = random_dataset(50, DIM, 1);
+    let segmented = SegmentedDataset::from_dense(dense.clone());
+    let query = random_dataset(1, DIM, 2).data.remove(0);
+    let measures: [&dyn DistanceMeasure; 3] = [&SquaredL2Distance, &DotProductDistance, &CosineDistance];
+    for measure in measures {
+        for row in 0..dense.size() {
+            let expected = measure.compute_distance_f32(&query, &dense.data[row]);
+            assert_eq!(compute_distance_to_row(measure, &query, &dense, row), expected);
+            assert_eq!(compute_distance_to_row(measure, &query, &segmented, row), expected);
+        }
+    }
+    // Concrete measures go through the same helper.
+    let cloned =
+        SquaredL2Distance.compute_distance(&DatapointPtr::new(query.clone()), &DatapointPtr::new(dense.data[7].clone()));
+    assert_eq!(compute_distance_to_row(&SquaredL2Distance, &query, &dense, 7), cloned);
+}
+
+#[test]
+fn distance_to_docid_scores_the_stored_row() {
+    let dense = random_dataset(30, DIM, 3);
+    let retriever = ScannRetriever::new(dense.clone(), Box::new(DotProductDistance::new()), 5);
+    let query = random_dataset(1, DIM, 4).data.remove(0);
+    let ptr = DatapointPtr::new(query.clone());
+    for docid in 0..dense.size() {
+        assert_eq!(
+            retriever.distance_to_docid(&ptr, docid).unwrap(),
+            compute_distance_to_row(&DotProductDistance, &query, &dense, docid)
+        );
+    }
+    assert!(retriever.distance_to_docid(&ptr, 30).is_err());
+}
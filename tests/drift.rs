@@ -0,0 +1,153 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query drift monitoring: in-distribution queries stay below the
+//! threshold, shifted or rescaled ones cross it, and results never change.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::drift::{DriftConfig, DriftReference, QueryDriftMonitor};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const DIM: usize = 8;
+
+fn normal_rows(n: usize, seed: u64, scale: f32, offset: f32) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n).map(|_| (0..DIM).map(|_| rng.next_normal() * scale + offset).collect()).collect()
+}
+
+fn retriever() -> ScannRetriever {
+    ScannRetriever::new(DenseDataset::new(normal_rows(2000, 1, 1.0, 0.0), DIM), Box::new(SquaredL2Distance::new()), 5)
+}
+
+// A monitor over the retriever's rows that counts callback firings.
+fn attach_monitor(retriever: &ScannRetriever) -> (Arc<QueryDriftMonitor>, Arc<AtomicUsize>) {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let monitor = retriever
+        .drift_monitor_from_data(1000, 7, DriftConfig::default())
+        .unwrap()
+        .with_callback(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+    let monitor = Arc::new(monitor);
+    retriever.set_drift_monitor(Some(monitor.clone()));
+    (monitor, fired)
+}
+
+fn search_all(retriever: &ScannRetriever, queries: &[Vec<f32>]) -> Vec<Vec<(usize, f32)>> {
+    queries.iter().map(|q| retriever.search(&DatapointPtr::new(q.clone())).unwrap()).collect()
+}
+
+#[test]
+fn in_distribution_queries_do_not_trigger() {
+    let retriever = retriever();
+    let (monitor, fired) = attach_monitor(&retriever);
+    let queries = normal_rows(400, 2, 1.0, 0.0);
+    search_all(&retriever, &queries[..10]);
+    assert_eq!(retriever.metrics().query_drift, None);
+
+    search_all(&retriever, &queries[10..]);
+    let metrics = retriever.metrics();
+    let score = metrics.query_drift.unwrap();
+    assert!(score.value() < 0.5, "{:?}", score);
+    assert!(!metrics.query_drift_detected);
+    assert!(!monitor.is_drifted());
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn shifted_and_rescaled_queries_cross_the_threshold() {
+    for (scale, offset) in [(1.0, 1.0), (2.5, 0.0)] {
+        let retriever = retriever();
+        let (monitor, fired) = attach_monitor(&retriever);
+        search_all(&retriever, &normal_rows(300, 3, scale, offset));
+        let metrics = retriever.metrics();
+        let score = metrics.query_drift.unwrap();
+        assert!(score.value() > 0.5, "scale {} offset {}: {:?}", scale, offset, score);
+        assert!(metrics.query_drift_detected);
+        // A mean shift moves the mean; a rescale mostly moves the norm.
+        if offset != 0.0 {
+            assert!(score.mean_shift > 0.5, "{:?}", score);
+        } else {
+            assert!(score.norm_shift > score.mean_shift, "{:?}", score);
+        }
+        // The callback fires once per crossing, not once per query.
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+        monitor.reset();
+        assert!(!monitor.is_drifted());
+        assert_eq!(retriever.metrics().query_drift, None);
+        search_all(&retriever, &normal_rows(300, 4, 1.0, 0.0));
+        assert!(!retriever.metrics().query_drift_detected);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn monitoring_does_not_change_results() {
+    let plain = retriever();
+    let monitored = retriever();
+    attach_monitor(&monitored);
+    let queries = normal_rows(50, 5, 3.0, 2.0);
+    assert_eq!(search_all(&monitored, &queries), search_all(&plain, &queries));
+    assert!(monitored.metrics().query_drift_detected);
+    assert_eq!(plain.metrics().query_drift, None);
+
+    monitored.set_drift_monitor(None);
+    assert!(!monitored.metrics().query_drift_detected);
+}
+
+#[test]
+fn reference_statistics() {
+    // Norms 5 and 0; mean (1.5, 2); each row sits 2.5 from the mean.
+    let reference = DriftReference::from_rows(&[&[3.0, 4.0], &[0.0, 0.0]]).unwrap();
+    assert_eq!(
+        reference,
+        DriftReference { mean_norm: 2.5, norm_std: 2.5, mean: vec![1.5, 2.0], spread: 2.5 }
+    );
+    assert!(DriftReference::from_rows(&[]).is_err());
+    assert!(DriftReference::from_rows(&[&[1.0, 2.0], &[1.0]]).is_err());
+
+    // A reservoir at least as large as the dataset holds every row.
+    let rows = normal_rows(100, 6, 1.0, 0.0);
+    let dataset = DenseDataset::new(rows.clone(), DIM);
+    let all: Vec<&[f32]> = rows.iter().map(|r| r.as_slice()).collect();
+    assert_eq!(DriftReference::from_dataset(&dataset, 100, 1).unwrap(), DriftReference::from_rows(&all).unwrap());
+    let sampled = DriftReference::from_dataset(&dataset, 20, 1).unwrap();
+    assert_eq!(DriftReference::from_dataset(&dataset, 20, 1).unwrap(), sampled);
+    assert_ne!(DriftReference::from_dataset(&dataset, 20, 2).unwrap(), sampled);
+}
+
+#[test]
+fn monitor_configuration_and_input_checks() {
+    let reference = DriftReference::from_rows(&[&[1.0, 0.0], &[0.0, 1.0]]).unwrap();
+    for config in [
+        DriftConfig { window: 0, ..DriftConfig::default() },
+        DriftConfig { threshold: 0.0, ..DriftConfig::default() },
+        DriftConfig { threshold: f32::NAN, ..DriftConfig::default() },
+    ] {
+        assert!(QueryDriftMonitor::new(reference.clone(), config).is_err(), "{:?}", config);
+    }
+
+    let monitor = QueryDriftMonitor::new(reference, DriftConfig { min_queries: 2, ..DriftConfig::default() }).unwrap();
+    // Wrong-dimensionality queries are ignored.
+    monitor.observe(&[1.0, 2.0, 3.0]);
+    monitor.observe(&[1.0, 0.0]);
+    assert_eq!(monitor.score(), None);
+    monitor.observe(&[0.0, 1.0]);
+    assert_eq!(monitor.score().unwrap().value(), 0.0);
+}
@@ -0,0 +1,123 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Dry-run build estimates against the breakdown of an actual build.
+
+mod common;
+
+use common::random_dataset;
+use scann::build;
+use scann::convert::DType;
+use scann::distance_measures::SquaredL2Distance;
+use scann::estimate::{BuildPlan, CostModel, DatasetMeta};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::DenseDataset;
+
+fn component(estimate: &scann::estimate::BuildEstimate, name: &str) -> usize {
+    estimate.memory.iter().find(|(c, _)| c == name).map(|&(_, bytes)| bytes).unwrap_or(0)
+}
+
+#[test]
+fn memory_estimate_matches_a_small_build() {
+    let (n, dim) = (3000, 24);
+    let mut plan = BuildPlan::new();
+    plan.num_leaves = Some(30);
+    plan.kmeans_iterations = 3;
+    let meta = DatasetMeta { n, dim, dtype: DType::F32 };
+    let estimate = plan.estimate(&meta, &CostModel::default(), None);
+
+    let (retriever, report) = build::build_retriever(
+        random_dataset(n, dim, 1),
+        Box::new(SquaredL2Distance::new()),
+        10,
+        &plan,
+        &KMeansTreeTrainingOptions::new(),
+    )
+    .unwrap();
+    // memory_usage covers the resident index: the input copy is gone by then.
+    let predicted = component(&estimate, "dataset") + component(&estimate, "docids") + component(&estimate, "tree");
+    let measured = retriever.memory_usage();
+    let relative = (predicted as f64 - measured as f64).abs() / measured as f64;
+    assert!(relative < 0.02, "predicted {} measured {}", predicted, measured);
+    assert_eq!(component(&estimate, "input"), n * dim * 4);
+    assert_eq!(estimate.peak_bytes, predicted + component(&estimate, "input"));
+
+    let estimated_phases: Vec<&str> = estimate.phase_seconds.iter().map(|(name, _)| name.as_str()).collect();
+    let built_phases: Vec<&str> = report.phases.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(estimated_phases, built_phases);
+}
+
+#[test]
+fn components_follow_the_plan() {
+    let meta = DatasetMeta { n: 10_000, dim: 64, dtype: DType::F64 };
+    let plain = BuildPlan::new().estimate(&meta, &CostModel::default(), None);
+    let names: Vec<&str> = plain.memory.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["input", "dataset", "docids"]);
+    assert_eq!(component(&plain, "input"), 10_000 * 64 * 8);
+
+    let mut plan = BuildPlan::new();
+    plan.num_leaves = Some(100);
+    plan.projected_dims = Some(16);
+    plan.int8_codes = true;
+    plan.norm_cache = true;
+    let full = plan.estimate(&meta, &CostModel::default(), None);
+    let names: Vec<&str> = full.memory.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["input", "dataset", "docids", "projected_dataset", "tree", "int8_codes", "norms"]);
+    let phases: Vec<&str> = full.phase_seconds.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(phases, vec!["copy", "projection", "kmeans", "quantize", "norms"]);
+    assert_eq!(component(&full, "norms"), 10_000 * 4);
+    // Codes are sized for the projected rows.
+    assert_eq!(component(&full, "int8_codes"), 10_000 * (16 + std::mem::size_of::<Vec<i8>>()) + 16 * 4);
+
+    plan.spilling_factor = 2.0;
+    let spilled = plan.estimate(&meta, &CostModel::default(), None);
+    // Twice the leaf assignments at 4 bytes each.
+    assert_eq!(component(&spilled, "tree") - component(&full, "tree"), 10_000 * 4);
+}
+
+#[test]
+fn time_scales_with_the_work_and_budgets_are_flagged() {
+    let mut plan = BuildPlan::new();
+    plan.num_leaves = Some(50);
+    let small = plan.estimate(&DatasetMeta { n: 1000, dim: 32, dtype: DType::F32 }, &CostModel::default(), None);
+    let large = plan.estimate(&DatasetMeta { n: 4000, dim: 32, dtype: DType::F32 }, &CostModel::default(), None);
+    assert!((large.total_seconds / small.total_seconds - 4.0).abs() < 1e-9);
+    assert_eq!(small.total_seconds, small.phase_seconds.iter().map(|(_, s)| s).sum::<f64>());
+
+    let meta = DatasetMeta { n: 1000, dim: 32, dtype: DType::F32 };
+    let within = plan.estimate(&meta, &CostModel::default(), Some(small.peak_bytes));
+    assert!(!within.exceeds_budget);
+    let over = plan.estimate(&meta, &CostModel::default(), Some(small.peak_bytes - 1));
+    assert!(over.exceeds_budget);
+    assert_eq!(over.memory_budget, Some(small.peak_bytes - 1));
+}
+
+#[test]
+fn calibration_measures_positive_costs_on_a_sample() {
+    let mut plan = BuildPlan::new();
+    plan.num_leaves = Some(8);
+    plan.kmeans_iterations = 2;
+    plan.int8_codes = true;
+    let cost = plan.estimate_calibrate(&random_dataset(500, 16, 2)).unwrap();
+    for value in [cost.copy_ns_per_value, cost.kmeans_ns_per_mac, cost.quantize_ns_per_value, cost.norm_ns_per_value] {
+        assert!(value.is_finite() && value >= 0.0, "{:?}", cost);
+    }
+    assert!(cost.kmeans_ns_per_mac > 0.0, "{:?}", cost);
+    assert_eq!(cost.projection_ns_per_mac, CostModel::default().projection_ns_per_mac);
+
+    let estimate = plan.estimate(&DatasetMeta { n: 1_000_000, dim: 16, dtype: DType::F32 }, &cost, None);
+    assert!(estimate.total_seconds > 0.0);
+    assert!(plan.estimate_calibrate(&DenseDataset::new(Vec::new(), 16)).is_err());
+}
@@ -0,0 +1,162 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Facet counts over the candidate pool, checked against a brute-force
+//! recount and under filters, tombstones and score modifiers.
+
+use scann::attribute_store::Filter;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{FacetCounts, FacetSpec, ScannRetriever, SearchOptions};
+use scann::score_modifier::ScoreModifier;
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+const NUM_POINTS: usize = 300;
+const QUERY: f32 = 100.3;
+
+fn category(docid: usize) -> i64 {
+    (docid * 7 % 5) as i64
+}
+
+// Every fourth docid has no shard.
+fn shard(docid: usize) -> Option<i64> {
+    (!docid.is_multiple_of(4)).then_some((docid % 3) as i64)
+}
+
+// Row i sits at x = i, so distances to QUERY are distinct and the exact
+// ranking is known.
+fn line_retriever() -> ScannRetriever {
+    let rows = (0..NUM_POINTS).map(|i| vec![i as f32, 0.0]).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 5);
+    retriever.set_attribute_column("category", (0..NUM_POINTS).map(|d| (d, category(d))));
+    retriever.set_attribute_column("shard", (0..NUM_POINTS).filter_map(|d| Some((d, shard(d)?))));
+    retriever
+}
+
+fn query() -> DatapointPtr<f32> {
+    DatapointPtr::new(vec![QUERY, 0.0])
+}
+
+fn facet_options(k: usize, pool_size: usize) -> SearchOptions {
+    SearchOptions {
+        k: Some(k),
+        facets: Some(FacetSpec { columns: vec!["category".to_string(), "shard".to_string()], pool_size }),
+        ..SearchOptions::default()
+    }
+}
+
+// Recounts both columns over the `pool_size` eligible docids nearest QUERY.
+fn brute_force(eligible: impl Fn(usize) -> bool, pool_size: usize) -> Vec<FacetCounts> {
+    let mut ranked: Vec<usize> = (0..NUM_POINTS).filter(|&d| eligible(d)).collect();
+    ranked.sort_by(|&a, &b| (a as f32 - QUERY).abs().total_cmp(&(b as f32 - QUERY).abs()));
+    ranked.truncate(pool_size);
+    let count = |column: &str, value: &dyn Fn(usize) -> Option<i64>| {
+        let mut facet = FacetCounts { column: column.to_string(), pool_size: ranked.len(), ..FacetCounts::default() };
+        for &docid in &ranked {
+            match value(docid) {
+                Some(v) => *facet.counts.entry(v).or_insert(0) += 1,
+                None => facet.missing += 1,
+            }
+        }
+        facet
+    };
+    vec![count("category", &|d| Some(category(d))), count("shard", &shard)]
+}
+
+#[test]
+fn counts_match_a_brute_force_recount() {
+    let retriever = line_retriever();
+    let (results, stats) = retriever.search_with_options(&query(), &facet_options(5, 60)).unwrap();
+    assert_eq!(results.len(), 5);
+    assert_eq!(stats.facets, brute_force(|_| true, 60));
+    assert_eq!(stats.facets[1].missing, 15);
+
+    // The partitioned path counts the same pool when every leaf is searched.
+    let mut training = KMeansTreeTrainingOptions::new();
+    training.max_iterations = 5;
+    retriever.build_partitions(8, &training).unwrap();
+    let options = SearchOptions { leaves_to_search: Some(8), ..facet_options(5, 60) };
+    let (partitioned, stats) = retriever.search_with_options(&query(), &options).unwrap();
+    assert_eq!(partitioned, results);
+    assert_eq!(stats.facets, brute_force(|_| true, 60));
+}
+
+#[test]
+fn counts_respect_filters_and_tombstones() {
+    let retriever = line_retriever();
+    for docid in (90..110).step_by(3) {
+        retriever.remove(docid).unwrap();
+    }
+    let options = SearchOptions { filter: Some(Filter::parse("category != 2").unwrap()), ..facet_options(4, 40) };
+    let (results, stats) = retriever.search_with_options(&query(), &options).unwrap();
+    let eligible = |d: usize| category(d) != 2 && !((90..110).contains(&d) && (d - 90).is_multiple_of(3));
+    assert!(results.iter().all(|&(docid, _)| eligible(docid)));
+    assert_eq!(stats.facets, brute_force(eligible, 40));
+    assert!(!stats.facets[0].counts.contains_key(&2));
+}
+
+// Drops odd docids, as a deny-list modifier would.
+#[derive(Debug)]
+struct DropOdd;
+
+impl ScoreModifier for DropOdd {
+    fn modify(&self, _index: usize, docid: usize, raw_distance: f32) -> f32 {
+        if docid % 2 == 1 {
+            f32::INFINITY
+        } else {
+            raw_distance
+        }
+    }
+}
+
+#[test]
+fn counts_cover_the_pool_left_after_a_score_modifier() {
+    let retriever = line_retriever();
+    let options = SearchOptions { score_modifier: Some(Arc::new(DropOdd)), ..facet_options(3, 30) };
+    let (results, stats) = retriever.search_with_options(&query(), &options).unwrap();
+    assert!(results.iter().all(|&(docid, _)| docid % 2 == 0));
+    // The pool is refilled from candidates the modifier keeps.
+    assert_eq!(stats.facets, brute_force(|d| d % 2 == 0, 30));
+}
+
+#[test]
+fn a_pool_larger_than_the_survivors_counts_what_is_left() {
+    let retriever = line_retriever();
+    let options = SearchOptions { filter: Some(Filter::parse("category == 1").unwrap()), ..facet_options(5, 1000) };
+    let (_, stats) = retriever.search_with_options(&query(), &options).unwrap();
+    assert_eq!(stats.facets[0].pool_size, NUM_POINTS / 5);
+    assert_eq!(stats.facets[0].counts, BTreeMap::from([(1, NUM_POINTS / 5)]));
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let retriever = line_retriever();
+    retriever.set_f32_attribute_column("price", (0..NUM_POINTS).map(|d| (d, d as f32)));
+    let error = retriever.search_with_options(&query(), &facet_options(10, 5)).unwrap_err();
+    assert!(error.to_string().contains("must be at least k"), "{}", error);
+    for (column, message) in [("colour", "Unknown attribute column"), ("price", "facets count i64 columns")] {
+        let options = SearchOptions {
+            facets: Some(FacetSpec { columns: vec![column.to_string()], pool_size: 10 }),
+            ..SearchOptions::default()
+        };
+        let error = retriever.search_with_options(&query(), &options).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+
+    assert!(retriever.remove_attribute_column("shard"));
+    assert!(!retriever.remove_attribute_column("shard"));
+    assert!(retriever.search_with_options(&query(), &facet_options(5, 10)).is_err());
+}
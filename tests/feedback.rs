@@ -0,0 +1,114 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Pseudo-relevance feedback on a clustered dataset.
+
+use scann::distance_measures::{DotProductDistance, SquaredL2Distance};
+use scann::retrieval::{FeedbackOptions, ScannRetriever};
+use scann::util::{DatapointPtr, DenseDataset, Normalization, SplitMix64};
+
+const PER_CLUSTER: usize = 300;
+
+// Docids below PER_CLUSTER belong to the cluster at the origin, the rest to
+// the cluster at (6, 0).
+fn two_clusters() -> (ScannRetriever, Vec<Vec<f32>>) {
+    let mut rng = SplitMix64::new(14);
+    let rows: Vec<Vec<f32>> = (0..2 * PER_CLUSTER)
+        .map(|i| {
+            let x = if i < PER_CLUSTER { 0.0 } else { 6.0 };
+            vec![x + rng.next_normal(), rng.next_normal()]
+        })
+        .collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows.clone(), 2), Box::new(SquaredL2Distance::new()), 20);
+    (retriever, rows)
+}
+
+fn in_near_cluster(results: &[(usize, f32)]) -> usize {
+    results.iter().filter(|&&(docid, _)| docid < PER_CLUSTER).count()
+}
+
+#[test]
+fn feedback_pulls_in_more_same_cluster_results_between_clusters() {
+    let (retriever, _) = two_clusters();
+    // Between the clusters, a little closer to the one at the origin.
+    let query = DatapointPtr::new(vec![2.7, 0.0]);
+    let raw = retriever.search(&query).unwrap();
+    let feedback = FeedbackOptions { m: 10, alpha: 0.3, iterations: 3 };
+    let (expanded_results, expanded) = retriever.search_with_feedback(&query, 20, &feedback).unwrap();
+
+    assert_eq!(expanded_results.len(), 20);
+    assert!(in_near_cluster(&raw) < 20, "raw results are already pure");
+    assert!(
+        in_near_cluster(&expanded_results) > in_near_cluster(&raw),
+        "{} vs {}",
+        in_near_cluster(&expanded_results),
+        in_near_cluster(&raw)
+    );
+    assert!(expanded[0] < 2.7, "{:?}", expanded);
+    // The results are exactly a plain search for the expanded query.
+    assert_eq!(expanded_results, retriever.search(&DatapointPtr::new(expanded)).unwrap());
+}
+
+#[test]
+fn one_round_moves_the_query_towards_the_top_m_centroid() {
+    let (retriever, rows) = two_clusters();
+    let query = DatapointPtr::new(vec![2.7, 0.5]);
+    let top: Vec<usize> = retriever.search(&query).unwrap().iter().take(5).map(|r| r.0).collect();
+    let centroid: Vec<f32> = (0..2).map(|d| top.iter().map(|&i| rows[i][d]).sum::<f32>() / 5.0).collect();
+
+    let feedback = FeedbackOptions { m: 5, alpha: 0.25, iterations: 1 };
+    let (_, expanded) = retriever.search_with_feedback(&query, 10, &feedback).unwrap();
+    for d in 0..2 {
+        let expected = 0.25 * query.values()[d] + 0.75 * centroid[d];
+        assert!((expanded[d] - expected).abs() < 1e-5, "{:?} vs {:?}", expanded, centroid);
+    }
+}
+
+#[test]
+fn degenerate_feedback_is_a_plain_search() {
+    let (retriever, _) = two_clusters();
+    let query = DatapointPtr::new(vec![2.7, 0.0]);
+    let plain = retriever.search(&query).unwrap();
+    for feedback in [
+        FeedbackOptions { m: 10, alpha: 0.5, iterations: 0 },
+        FeedbackOptions { m: 0, alpha: 0.5, iterations: 3 },
+    ] {
+        let (results, expanded) = retriever.search_with_feedback(&query, 20, &feedback).unwrap();
+        assert_eq!((results, expanded), (plain.clone(), query.values().to_vec()));
+    }
+    let (results, expanded) =
+        retriever.search_with_feedback(&query, 20, &FeedbackOptions { m: 10, alpha: 1.0, iterations: 2 }).unwrap();
+    assert_eq!((results, expanded), (plain, query.values().to_vec()));
+
+    let message = retriever
+        .search_with_feedback(&query, 20, &FeedbackOptions { m: 10, alpha: 1.5, iterations: 1 })
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("Feedback alpha must be in [0, 1], got 1.5"), "{}", message);
+}
+
+#[test]
+fn expanded_queries_are_normalized_for_normalized_indexes() {
+    let (_, rows) = two_clusters();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(DotProductDistance::new()), 10)
+        .with_normalization(Normalization::UnitL2)
+        .unwrap();
+    let query = DatapointPtr::new(vec![0.6, 0.8]);
+    let (results, expanded) =
+        retriever.search_with_feedback(&query, 10, &FeedbackOptions { m: 5, alpha: 0.5, iterations: 2 }).unwrap();
+    let norm = expanded.iter().map(|v| v * v).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-5, "{:?}", expanded);
+    assert_eq!(results.len(), 10);
+}
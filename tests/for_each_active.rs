@@ -0,0 +1,125 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! for_each_active: visiting every active datapoint with borrowed rows,
+//! metadata and early termination.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::ScannRetriever;
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DenseDataset, SplitMix64};
+use std::ops::ControlFlow;
+
+fn random_retriever(n: usize) -> ScannRetriever {
+    let mut rng = SplitMix64::new(3);
+    let rows = (0..n).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 4), Box::new(SquaredL2Distance::new()), 5)
+}
+
+#[test]
+fn visits_every_active_record_with_its_stored_row() {
+    let retriever = random_retriever(500);
+    for docid in (0..500).step_by(7) {
+        retriever.remove(docid).unwrap();
+    }
+    let added = retriever.add(&[9.0, 9.0, 9.0, 9.0]).unwrap();
+
+    let mut visited = Vec::new();
+    let flow = retriever.for_each_active(|record| {
+        assert_eq!(Some(record.values.to_vec()), retriever.get_by_docid(record.docid));
+        assert_eq!(record.partition, None);
+        visited.push(record.docid);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    assert_eq!(visited.len(), retriever.num_active());
+    // Storage order: the original rows first, then the added one.
+    let expected: Vec<usize> = (0..500).filter(|d| d % 7 != 0).chain([added]).collect();
+    assert_eq!(visited, expected);
+}
+
+#[test]
+fn records_carry_partition_expiry_and_attributes() {
+    let retriever = random_retriever(300);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(6, &options).unwrap();
+    retriever.set_attribute_column("bucket", (0..300).map(|d| (d, (d % 3) as i64)));
+    retriever.set_expiry(4, Some(1234)).unwrap();
+
+    let mut count = 0;
+    let _ = retriever.for_each_active(|record| {
+        assert!(matches!(record.partition, Some(p) if p < 6), "{:?}", record.partition);
+        assert_eq!(record.attribute("bucket"), Some((record.docid % 3) as i64));
+        assert_eq!(record.attribute("missing"), None);
+        assert_eq!(record.expires_at, retriever.expiry(record.docid));
+        assert_eq!(record.expires_at.is_some(), record.docid == 4);
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    assert_eq!(count, 300);
+}
+
+#[test]
+fn break_stops_the_visit_immediately() {
+    let retriever = random_retriever(1000);
+    let mut visited = 0;
+    let flow = retriever.for_each_active(|_| {
+        visited += 1;
+        if visited == 10 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!((flow, visited), (ControlFlow::Break(()), 10));
+
+    let empty = random_retriever(3);
+    for docid in 0..3 {
+        empty.remove(docid).unwrap();
+    }
+    let flow = empty.for_each_active(|_| panic!("no active records"));
+    assert_eq!(flow, ControlFlow::Continue(()));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_visit_matches_and_stops_promptly() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let retriever = random_retriever(20_000);
+    for docid in (0..20_000).step_by(5) {
+        retriever.remove(docid).unwrap();
+    }
+    let visited = Mutex::new(Vec::new());
+    let flow = retriever.par_for_each_active(|record| {
+        assert_eq!(Some(record.values.to_vec()), retriever.get_by_docid(record.docid));
+        visited.lock().unwrap().push(record.docid);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    let mut visited = visited.into_inner().unwrap();
+    visited.sort_unstable();
+    assert_eq!(visited, (0..20_000).filter(|d| d % 5 != 0).collect::<Vec<_>>());
+
+    // Each thread finishes at most the record it is on after the break.
+    let calls = AtomicUsize::new(0);
+    let flow = retriever.par_for_each_active(|_| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        ControlFlow::Break(())
+    });
+    assert_eq!(flow, ControlFlow::Break(()));
+    assert!(calls.load(Ordering::SeqCst) <= 2 * rayon::current_num_threads(), "{:?}", calls);
+}
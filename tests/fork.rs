@@ -0,0 +1,183 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy-on-write forks: searches over the overlay, isolation from the
+//! parent, memory proportional to the deltas and visits over the merged
+//! view.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::ops::ControlFlow;
+
+fn line_retriever(n: usize) -> ScannRetriever {
+    let rows = (0..n).map(|i| vec![i as f32, 0.0]).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 3)
+}
+
+fn active_docids(retriever: &ScannRetriever) -> Vec<(usize, Vec<f32>)> {
+    let mut visited = Vec::new();
+    let flow = retriever.for_each_active(|record| {
+        visited.push((record.docid, record.values.to_vec()));
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    visited
+}
+
+#[test]
+fn for_each_active_on_a_fork_visits_unhidden_base_rows_then_its_own() {
+    let parent = line_retriever(5);
+    let fork = parent.fork().unwrap();
+    fork.remove(1).unwrap();
+    fork.upsert(3, &[30.0, 0.0]).unwrap();
+    let added = fork.add(&[50.0, 0.0]).unwrap();
+
+    assert_eq!(
+        active_docids(&fork),
+        vec![
+            (0, vec![0.0, 0.0]),
+            (2, vec![2.0, 0.0]),
+            (4, vec![4.0, 0.0]),
+            (3, vec![30.0, 0.0]),
+            (added, vec![50.0, 0.0]),
+        ]
+    );
+    // The parent is untouched.
+    assert_eq!(active_docids(&parent).len(), 5);
+
+    let mut first = None;
+    let flow = fork.for_each_active(|record| {
+        first = Some(record.docid);
+        ControlFlow::Break(())
+    });
+    assert_eq!((flow, first), (ControlFlow::Break(()), Some(0)));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_for_each_active_on_a_fork_matches_the_sequential_visit() {
+    use std::sync::Mutex;
+
+    let parent = line_retriever(200);
+    let fork = parent.fork().unwrap();
+    fork.remove(10).unwrap();
+    fork.add(&[500.0, 0.0]).unwrap();
+    let visited = Mutex::new(Vec::new());
+    let flow = fork.par_for_each_active(|record| {
+        visited.lock().unwrap().push(record.docid);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    let mut visited = visited.into_inner().unwrap();
+    visited.sort_unstable();
+    let mut expected: Vec<usize> = active_docids(&fork).into_iter().map(|(docid, _)| docid).collect();
+    expected.sort_unstable();
+    assert_eq!(visited, expected);
+}
+
+fn partitioned(rows: Vec<Vec<f32>>) -> ScannRetriever {
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 4), Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(8, &options).unwrap();
+    retriever
+}
+
+fn search(retriever: &ScannRetriever, query: &[f32]) -> Vec<(usize, f32)> {
+    let options = SearchOptions {
+        leaves_to_search: Some(8),
+        ..SearchOptions::default()
+    };
+    retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap().0
+}
+
+#[test]
+fn fork_searches_reflect_the_overlay_and_the_parent_is_unchanged() {
+    let rows = random_rows(2000, 4, 1);
+    let parent = partitioned(rows.clone());
+    let queries = random_rows(30, 4, 2);
+    let before: Vec<_> = queries.iter().map(|q| search(&parent, q)).collect();
+
+    let fork = parent.fork().unwrap();
+    // Remove each query's nearest row, move docid 5 onto query 0, and add a
+    // row on query 1.
+    let removed: Vec<usize> = before.iter().skip(2).map(|results| results[0].0).collect();
+    for &docid in &removed {
+        fork.remove(docid).unwrap();
+    }
+    fork.upsert(5, &queries[0]).unwrap();
+    let added = fork.add(&queries[1]).unwrap();
+
+    assert_eq!(search(&fork, &queries[0])[0], (5, 0.0));
+    assert_eq!(search(&fork, &queries[1])[0], (added, 0.0));
+    for query in &queries[2..] {
+        let results = search(&fork, query);
+        assert!(results.iter().all(|(docid, _)| !removed.contains(docid)), "{:?}", results);
+    }
+    assert_eq!(fork.num_active(), parent.num_active() - removed.len() + 1);
+    assert_eq!(fork.get_by_docid(5), Some(queries[0].clone()));
+    assert_eq!(parent.get_by_docid(5), Some(rows[5].clone()));
+
+    // The parent answers bit for bit as before the fork existed.
+    let after: Vec<_> = queries.iter().map(|q| search(&parent, q)).collect();
+    assert_eq!(after, before);
+    assert_eq!(parent.num_active(), 2000);
+
+    // Materializing gives a standalone retriever answering like the fork.
+    let materialized = fork.materialize().unwrap();
+    for query in &queries {
+        let ids = |results: Vec<(usize, f32)>| results.into_iter().map(|(docid, _)| docid).collect::<Vec<_>>();
+        assert_eq!(ids(search(&materialized, query)), ids(search(&fork, query)));
+    }
+}
+
+#[test]
+fn fork_memory_is_proportional_to_its_deltas() {
+    let small = partitioned(random_rows(1000, 4, 3));
+    let large = partitioned(random_rows(20000, 4, 3));
+    let edit = |fork: &ScannRetriever| {
+        for docid in 0..20 {
+            fork.upsert(docid, &[1.0, 2.0, 3.0, 4.0]).unwrap();
+            fork.remove(100 + docid).unwrap();
+        }
+    };
+    let small_fork = small.fork().unwrap();
+    let large_fork = large.fork().unwrap();
+    let empty = large_fork.memory_usage();
+    assert!(empty < 1024, "an untouched fork uses {} bytes", empty);
+    edit(&small_fork);
+    edit(&large_fork);
+    // The same edits cost the same whatever the size of the base.
+    assert_eq!(small_fork.memory_usage(), large_fork.memory_usage());
+    assert!(large_fork.memory_usage() > empty);
+    assert!(large_fork.memory_usage() * 50 < large.memory_usage());
+}
+
+#[test]
+fn forks_reject_whole_storage_operations() {
+    let parent = line_retriever(10);
+    let fork = parent.fork().unwrap();
+    assert!(fork.fork().is_err());
+    assert!(parent.materialize().is_err());
+    assert!(fork.compact().is_err());
+    assert!(fork.build_partitions(2, &KMeansTreeTrainingOptions::new()).is_err());
+    // The fork stays usable after a rejected operation.
+    fork.add(&[100.0, 0.0]).unwrap();
+    assert_eq!(fork.num_active(), 11);
+}
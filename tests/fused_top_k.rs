@@ -0,0 +1,112 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The fused top-k scan against sorting every distance: infinities are
+//! ranked, NaN is skipped and ties fall to the lower docid, so a search
+//! returns the same neighbors whether or not it takes the fused path.
+
+use scann::artifacts::{Artifacts, ArtifactsConfig};
+use scann::distance_measures;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{CancellationToken, DatapointPtr, DenseDataset, Normalization, RowBlock};
+
+// Each one-value row is its own distance, with repeats to tie on.
+fn distances() -> Vec<f32> {
+    let mut distances: Vec<f32> = (0..600).map(|i| (i % 7) as f32).collect();
+    distances[5] = f32::INFINITY;
+    distances[17] = f32::NEG_INFINITY;
+    distances[300] = f32::NAN;
+    distances[301] = f32::INFINITY;
+    distances
+}
+
+// Every non-NaN distance, ascending, ties by docid.
+fn sorted(distances: &[f32], docid: impl Fn(usize) -> usize) -> Vec<(usize, f32)> {
+    let mut ranked: Vec<(usize, f32)> = distances.iter().copied().enumerate().filter(|(_, d)| !d.is_nan()).collect();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1).then(docid(a.0).cmp(&docid(b.0))));
+    ranked
+}
+
+#[test]
+fn fused_scan_matches_sorting_every_distance() {
+    let distances = distances();
+    let n = distances.len();
+    let rows = RowBlock::flat(&distances, 1, n);
+    let identity = |i: usize| i;
+    let reversed = |i: usize| n - 1 - i;
+    let docids: [&dyn Fn(usize) -> usize; 2] = [&identity, &reversed];
+    for docid in docids {
+        let expected = sorted(&distances, docid);
+        for k in [1, 2, 10, 100, 599, n, 2 * n] {
+            let scan = distance_measures::top_k_one_to_many_rows(
+                &[],
+                rows,
+                k,
+                |_, rows, out| out.iter_mut().enumerate().for_each(|(j, d)| *d = rows.row(j)[0]),
+                |_| true,
+                docid,
+            );
+            let expected = &expected[..k.min(expected.len())];
+            assert_eq!(format!("{:?}", scan.neighbors), format!("{:?}", expected), "k {}", k);
+            assert_eq!(scan.scored, n);
+            assert_eq!(scan.non_finite_skipped, 1);
+        }
+    }
+}
+
+// Rows tied in groups of three with docids descending against row order,
+// plus one row whose squared distance overflows to infinity.
+fn retriever() -> ScannRetriever {
+    let n = 400;
+    let mut rows: Vec<Vec<f32>> = (0..n).map(|i| vec![(i / 3) as f32, 0.0]).collect();
+    rows[7] = vec![1e30, 0.0];
+    let artifacts = Artifacts {
+        config: ArtifactsConfig {
+            distance_measure: "SquaredL2Distance".to_string(),
+            normalization: Normalization::None,
+            dimensionality: 2,
+        },
+        dataset: DenseDataset::new(rows, 2),
+        docids: (0..n).map(|i| 10 * (n - i)).collect(),
+        tree: None,
+        attributes: None,
+    };
+    ScannRetriever::from_artifacts(artifacts, 10).unwrap()
+}
+
+#[test]
+fn fused_and_unfused_searches_agree() {
+    let retriever = retriever();
+    for query in [vec![0.0, 0.0], vec![20.0, 0.0], vec![20.5, 1.0], vec![-1e30, 0.0]] {
+        let query = DatapointPtr::new(query);
+        for k in [1, 4, 10, 25] {
+            let fused = SearchOptions {
+                k: Some(k),
+                ..SearchOptions::default()
+            };
+            // Cancellable searches never take the fused scan.
+            let unfused = SearchOptions {
+                cancellation: Some(CancellationToken::new()),
+                ..fused.clone()
+            };
+            let (fused, fused_stats) = retriever.search_with_options(&query, &fused).unwrap();
+            let (unfused, unfused_stats) = retriever.search_with_options(&query, &unfused).unwrap();
+            assert_eq!(fused, unfused, "query {:?}, k {}", query.values(), k);
+            assert_eq!(fused_stats.datapoints_scored, unfused_stats.datapoints_scored);
+            assert_eq!(fused_stats.non_finite_skipped, unfused_stats.non_finite_skipped);
+            // Tied rows come back by ascending docid.
+            assert!(fused.windows(2).all(|w| w[0].1 < w[1].1 || w[0].0 < w[1].0), "{:?}", fused);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reciprocal rank fusion against hand-computed scores.
+
+use scann::fusion::{reciprocal_rank_fusion, weighted_reciprocal_rank_fusion, Contribution, FusedResult};
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+fn docids(results: &[FusedResult]) -> Vec<usize> {
+    results.iter().map(|r| r.docid).collect()
+}
+
+#[test]
+fn scores_match_hand_computed_rrf() {
+    // Scores within each list are ignored; only the order counts.
+    let dense: &[(usize, f32)] = &[(7, 0.1), (3, 0.2), (9, 0.3)];
+    let lexical: &[(usize, f32)] = &[(3, 12.0), (5, 11.0), (7, 10.0)];
+    let other: &[(usize, f32)] = &[(3, 0.0)];
+    let results = reciprocal_rank_fusion(&[dense, lexical, other], 10, 60.0).unwrap();
+
+    // 3: ranks 2, 1 and 1 -> 1/62 + 1/61 + 1/61.
+    // 7: ranks 1 and 3 -> 1/61 + 1/63.
+    // 5: rank 2 -> 1/62. 9: rank 3 -> 1/63.
+    assert_eq!(docids(&results), vec![3, 7, 5, 9]);
+    let expected = [1.0 / 62.0 + 2.0 / 61.0, 1.0 / 61.0 + 1.0 / 63.0, 1.0 / 62.0, 1.0 / 63.0];
+    for (result, expected) in results.iter().zip(expected) {
+        assert!(close(result.score, expected), "{:?} vs {}", result, expected);
+    }
+    assert_eq!(
+        results[0].contributions,
+        vec![
+            Contribution { list: 0, rank: 2 },
+            Contribution { list: 1, rank: 1 },
+            Contribution { list: 2, rank: 1 },
+        ]
+    );
+    assert_eq!(results[2].contributions, vec![Contribution { list: 1, rank: 2 }]);
+
+    let top2 = reciprocal_rank_fusion(&[dense, lexical, other], 2, 60.0).unwrap();
+    assert_eq!(top2, results[..2]);
+}
+
+#[test]
+fn a_docid_in_every_list_outranks_one_that_tops_a_single_list() {
+    let a: &[(usize, f32)] = &[(1, 0.0), (2, 0.0)];
+    let b: &[(usize, f32)] = &[(3, 0.0), (2, 0.0)];
+    let c: &[(usize, f32)] = &[(4, 0.0), (2, 0.0)];
+    let results = reciprocal_rank_fusion(&[a, b, c], 4, 60.0).unwrap();
+    // 3/62 for docid 2 beats 1/61 for each list head.
+    assert_eq!(results[0].docid, 2);
+    assert!(close(results[0].score, 3.0 / 62.0));
+    assert_eq!(results[0].contributions.len(), 3);
+    // The three heads tie at 1/61 and fall back to docid order.
+    assert_eq!(docids(&results[1..]), vec![1, 3, 4]);
+    assert!(results[1..].iter().all(|r| r.score == results[1].score));
+}
+
+#[test]
+fn ties_break_by_lowest_docid_and_duplicates_count_once() {
+    // Mirror-image lists give both docids 1/61 + 1/62.
+    let a: &[(usize, f32)] = &[(20, 0.0), (10, 0.0)];
+    let b: &[(usize, f32)] = &[(10, 0.0), (20, 0.0)];
+    let results = reciprocal_rank_fusion(&[a, b], 2, 60.0).unwrap();
+    assert_eq!(results[0].score, results[1].score);
+    assert_eq!(docids(&results), vec![10, 20]);
+
+    // A docid repeated within a list counts at its best rank only.
+    let repeated: &[(usize, f32)] = &[(5, 0.0), (6, 0.0), (5, 0.0)];
+    let results = reciprocal_rank_fusion(&[repeated], 10, 0.0).unwrap();
+    assert_eq!(docids(&results), vec![5, 6]);
+    assert_eq!(results[0].score, 1.0);
+    assert_eq!(results[0].contributions, vec![Contribution { list: 0, rank: 1 }]);
+    assert!(reciprocal_rank_fusion(&[], 10, 60.0).unwrap().is_empty());
+}
+
+#[test]
+fn weights_scale_each_lists_terms() {
+    let dense: &[(usize, f32)] = &[(1, 0.0), (2, 0.0)];
+    let lexical: &[(usize, f32)] = &[(2, 0.0), (1, 0.0)];
+    // 1: 3/1 + 1/2 = 3.5; 2: 3/2 + 1/1 = 2.5 with rrf_k = 0.
+    let results = weighted_reciprocal_rank_fusion(&[dense, lexical], &[3.0, 1.0], 2, 0.0).unwrap();
+    assert_eq!(docids(&results), vec![1, 2]);
+    assert!(close(results[0].score, 3.5) && close(results[1].score, 2.5));
+    let results = weighted_reciprocal_rank_fusion(&[dense, lexical], &[1.0, 3.0], 2, 0.0).unwrap();
+    assert_eq!(docids(&results), vec![2, 1]);
+
+    let error = weighted_reciprocal_rank_fusion(&[dense, lexical], &[1.0], 2, 60.0).unwrap_err();
+    assert!(error.to_string().contains("Got 1 weights for 2 lists"), "{}", error);
+    for rrf_k in [-1.0, f32::NAN] {
+        let error = reciprocal_rank_fusion(&[dense], 2, rrf_k).unwrap_err();
+        assert!(error.to_string().contains("rrf_k must be non-negative"), "{}", error);
+    }
+}
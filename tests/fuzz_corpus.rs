@@ -0,0 +1,72 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replays the checked-in fuzz corpus through the fuzz target bodies, so
+//! regressions on known inputs show up without cargo-fuzz.
+
+use scann::testing::fuzz;
+use std::path::Path;
+
+fn replay(target: &str, body: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus").join(target);
+    let mut inputs = 0;
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let data = std::fs::read(&path).unwrap();
+        let outcome = std::panic::catch_unwind(|| body(&data));
+        assert!(outcome.is_ok(), "{} panicked on {}", target, path.display());
+        inputs += 1;
+    }
+    assert!(inputs > 0, "no corpus under {}", dir.display());
+}
+
+#[test]
+fn binfmt_corpus() {
+    replay("binfmt", fuzz::binfmt);
+}
+
+#[test]
+fn blob_corpus() {
+    replay("blob", fuzz::blob);
+}
+
+#[test]
+fn prepared_query_corpus() {
+    replay("prepared_query", fuzz::prepared_query);
+}
+
+#[test]
+fn search_cursor_corpus() {
+    replay("search_cursor", fuzz::search_cursor);
+}
+
+#[test]
+fn npy_corpus() {
+    replay("npy", fuzz::npy);
+}
+
+#[test]
+fn manifest_corpus() {
+    replay("manifest", fuzz::manifest);
+}
+
+#[test]
+fn chunk_store_corpus() {
+    replay("chunk_store", fuzz::chunk_store);
+}
+
+#[test]
+fn gfv_corpus() {
+    replay("gfv", fuzz::gfv);
+}
@@ -0,0 +1,127 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RETRO generation past max_seq_len: the default policy errors and the
+//! sliding window keeps decoding on chunk-aligned windows.
+
+use scann::proto::RetroConfig;
+use scann::retro::model::ChunkRetriever;
+use scann::retro::{ContextPolicy, GenerateOptions, RETRO};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+const CHUNK_SIZE: usize = 4;
+const MAX_SEQ_LEN: usize = 16;
+const NUM_TOKENS: u32 = 32;
+
+// Returns one fixed neighbor per complete chunk and records every sequence
+// it is asked about.
+struct TracingRetriever {
+    trace: Arc<Mutex<Vec<Vec<u32>>>>,
+}
+
+impl ChunkRetriever for TracingRetriever {
+    fn retrieve_chunks(&self, seq: &[u32], chunk_size: usize) -> Result<Vec<Vec<Vec<u32>>>, Box<dyn Error>> {
+        self.trace.lock().unwrap().push(seq.to_vec());
+        Ok((0..seq.len() / chunk_size).map(|c| vec![vec![1 + c as u32; chunk_size]]).collect())
+    }
+}
+
+fn model() -> (RETRO, Arc<Mutex<Vec<Vec<u32>>>>) {
+    let mut config = RetroConfig::new();
+    config.num_tokens = NUM_TOKENS;
+    config.max_seq_len = MAX_SEQ_LEN as u32;
+    config.enc_dim = 8;
+    config.dec_dim = 8;
+    config.enc_depth = 1;
+    config.dec_depth = 2;
+    config.heads = 2;
+    config.dim_head = 4;
+    config.chunk_size = CHUNK_SIZE as u32;
+    config.dec_cross_attn_layers = vec![1, 2];
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut model = RETRO::new(config, None);
+    model.set_retriever(Some(Box::new(TracingRetriever { trace: trace.clone() })));
+    (model, trace)
+}
+
+const PROMPT: [u32; 6] = [3, 1, 4, 1, 5, 9];
+
+fn options(max_new_tokens: usize, context_policy: ContextPolicy) -> GenerateOptions {
+    GenerateOptions { max_new_tokens, context_policy, eos_id: None }
+}
+
+#[test]
+fn the_default_policy_errors_past_max_seq_len() {
+    let (model, _) = model();
+    // Up to max_seq_len the default policy decodes normally.
+    let output = model.generate(&PROMPT, &options(MAX_SEQ_LEN - PROMPT.len() + 1, ContextPolicy::Error)).unwrap();
+    assert_eq!(output.tokens.len(), MAX_SEQ_LEN + 1);
+    assert!(output.window_starts.iter().all(|&start| start == 0));
+
+    let error = model.generate(&PROMPT, &GenerateOptions { max_new_tokens: 2 * MAX_SEQ_LEN, ..Default::default() });
+    let error = error.unwrap_err().to_string();
+    assert!(error.contains("exceeds max_seq_len 16"), "{}", error);
+    assert!(error.contains("SlidingWindow"), "{}", error);
+}
+
+#[test]
+fn sliding_window_decodes_to_twice_max_seq_len_on_chunk_boundaries() {
+    for window in [MAX_SEQ_LEN, 10, CHUNK_SIZE] {
+        let (model, trace) = model();
+        let policy = ContextPolicy::SlidingWindow { window };
+        let output = model.generate(&PROMPT, &options(2 * MAX_SEQ_LEN, policy)).unwrap();
+        assert_eq!(output.tokens.len(), PROMPT.len() + 2 * MAX_SEQ_LEN);
+        assert_eq!(output.tokens[..PROMPT.len()], PROMPT);
+        assert!(output.tokens.iter().all(|&token| token < NUM_TOKENS));
+
+        // Each step decodes from a chunk-aligned start that keeps at most
+        // `window` tokens, and retrieval sees exactly that window.
+        let trace = trace.lock().unwrap();
+        assert_eq!(trace.len(), 2 * MAX_SEQ_LEN, "window {}", window);
+        for (step, (&start, seen)) in output.window_starts.iter().zip(trace.iter()).enumerate() {
+            let len = PROMPT.len() + step;
+            assert_eq!(start % CHUNK_SIZE, 0, "window {} step {}", window, step);
+            assert!(len - start <= window && len - start + CHUNK_SIZE > window.min(len), "window {}", window);
+            assert_eq!(seen[..], output.tokens[start..len], "window {} step {}", window, step);
+        }
+        assert!(output.window_starts.windows(2).all(|w| w[0] <= w[1]));
+        assert!(*output.window_starts.last().unwrap() > 0);
+    }
+}
+
+#[test]
+fn generation_is_deterministic_and_stops_at_eos() {
+    let (model, _) = model();
+    let policy = ContextPolicy::SlidingWindow { window: 8 };
+    let first = model.generate(&PROMPT, &options(20, policy)).unwrap();
+    assert_eq!(model.generate(&PROMPT, &options(20, policy)).unwrap().tokens, first.tokens);
+
+    let eos = first.tokens[PROMPT.len() + 3];
+    let stop = PROMPT.len() + first.tokens[PROMPT.len()..].iter().position(|&t| t == eos).unwrap();
+    let stopped = model.generate(&PROMPT, &GenerateOptions { eos_id: Some(eos), ..options(20, policy) }).unwrap();
+    assert_eq!(stopped.tokens, first.tokens[..=stop]);
+}
+
+#[test]
+fn invalid_requests_are_rejected() {
+    let (model, _) = model();
+    for window in [CHUNK_SIZE - 1, MAX_SEQ_LEN + 1] {
+        let error = model.generate(&PROMPT, &options(4, ContextPolicy::SlidingWindow { window })).unwrap_err();
+        assert!(error.to_string().contains("must be between chunk_size 4 and max_seq_len 16"), "{}", error);
+    }
+    let error = model.generate(&[], &options(4, ContextPolicy::Error)).unwrap_err();
+    assert!(error.to_string().contains("non-empty prompt"), "{}", error);
+    assert_eq!(model.generate(&PROMPT, &options(0, ContextPolicy::Error)).unwrap().tokens, PROMPT);
+}
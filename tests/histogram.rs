@@ -0,0 +1,135 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-query distance histograms and sampled score distributions.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{DistanceHistogram, HistogramSpec, Int8Codes, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+
+// Rows at x = 0..n, so the distance from the origin to docid i is i^2.
+fn line_retriever(n: usize) -> ScannRetriever {
+    let rows = (0..n).map(|i| vec![i as f32, 0.0]).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 3)
+}
+
+fn origin() -> DatapointPtr<f32> {
+    DatapointPtr::new(vec![0.0, 0.0])
+}
+
+fn with_histogram(bins: usize, range: (f32, f32)) -> SearchOptions {
+    SearchOptions { collect_histogram: Some(HistogramSpec { bins, range }), ..SearchOptions::default() }
+}
+
+#[test]
+fn bin_counts_sum_to_the_scored_points() {
+    let retriever = line_retriever(10);
+    // Distances 0, 1, 4, ..., 81 over [0, 100) in bins of 10.
+    let (results, stats) = retriever.search_with_options(&origin(), &with_histogram(10, (0.0, 100.0))).unwrap();
+    assert_eq!(results.len(), 3);
+    let histogram = stats.histogram.unwrap();
+    assert_eq!(histogram.counts, vec![4, 1, 1, 1, 1, 0, 1, 0, 1, 0]);
+    assert_eq!(histogram.total(), stats.datapoints_scored as u64);
+    assert_eq!((histogram.below_range, histogram.above_range), (0, 0));
+    assert!(!histogram.approximate);
+
+    // Partitioned searches count only the rows they scored.
+    let retriever = line_retriever(500);
+    let mut training = KMeansTreeTrainingOptions::new();
+    training.max_iterations = 5;
+    retriever.build_partitions(5, &training).unwrap();
+    let options = SearchOptions { leaves_to_search: Some(2), ..with_histogram(8, (0.0, 1e5)) };
+    let (_, stats) = retriever.search_with_options(&origin(), &options).unwrap();
+    assert!(stats.datapoints_scored < 500);
+    assert_eq!(stats.histogram.unwrap().total(), stats.datapoints_scored as u64);
+
+    let (_, stats) = retriever.search_with_options(&origin(), &SearchOptions::default()).unwrap();
+    assert!(stats.histogram.is_none());
+}
+
+#[test]
+fn out_of_range_distances_clamp_into_the_edge_bins() {
+    let retriever = line_retriever(10);
+    // [10, 50): 0, 1, 4 and 9 fall below, 64 and 81 at or above 50.
+    let (_, stats) = retriever.search_with_options(&origin(), &with_histogram(4, (10.0, 50.0))).unwrap();
+    let histogram = stats.histogram.unwrap();
+    assert_eq!((histogram.below_range, histogram.above_range), (4, 2));
+    // 16, 25, 36 and 49 fall one per bin; clamped values land in the first
+    // and last bins.
+    assert_eq!(histogram.counts, vec![4 + 1, 1, 1, 1 + 2]);
+    assert_eq!(histogram.total(), 10);
+
+    // The upper bound is exclusive.
+    let mut direct = DistanceHistogram::new(HistogramSpec { bins: 2, range: (0.0, 1.0) });
+    for distance in [-1.0, 0.0, 0.5, 0.999, 1.0, 7.0] {
+        direct.record(distance);
+    }
+    assert_eq!(direct.counts, vec![2, 4]);
+    assert_eq!((direct.below_range, direct.above_range), (1, 2));
+}
+
+#[test]
+fn quantized_scans_mark_the_histogram_approximate() {
+    let retriever = line_retriever(200);
+    let mut training = KMeansTreeTrainingOptions::new();
+    training.max_iterations = 5;
+    retriever.build_partitions(4, &training).unwrap();
+    retriever.register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new()))).unwrap();
+    retriever.build_leaf_code_store().unwrap();
+    let options = SearchOptions { leaves_to_search: Some(4), ..with_histogram(4, (0.0, 4e4)) };
+    let (_, stats) = retriever.search_with_options(&origin(), &options).unwrap();
+    let histogram = stats.histogram.unwrap();
+    assert!(histogram.approximate);
+    assert_eq!(histogram.total(), 200);
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let retriever = line_retriever(10);
+    for (bins, range) in [(0, (0.0, 1.0)), (4, (1.0, 1.0)), (4, (2.0, 1.0)), (4, (0.0, f32::INFINITY))] {
+        let error = retriever.search_with_options(&origin(), &with_histogram(bins, range)).unwrap_err();
+        assert!(error.to_string().contains("Histogram needs bins > 0"), "{}", error);
+    }
+}
+
+#[test]
+fn score_distribution_samples_exact_distances() {
+    let retriever = line_retriever(1000);
+    let full = retriever.score_distribution(&origin(), 1.0, 10).unwrap();
+    assert_eq!(full.total(), 1000);
+    assert_eq!((full.below_range, full.above_range), (0, 0));
+    // Spans the sampled [min, max]: 0 and 999^2 land in the edge bins.
+    assert_eq!(full.spec.range.0, 0.0);
+    assert!(full.spec.range.1 > 998_001.0);
+    // Rows 0..=315 have i^2 in the first tenth of the range.
+    assert_eq!(full.counts[0], 316);
+    assert!(!full.approximate);
+
+    let sampled = retriever.score_distribution(&origin(), 0.2, 10).unwrap();
+    assert!((120..280).contains(&sampled.total()), "{}", sampled.total());
+    assert_eq!(retriever.score_distribution(&origin(), 0.2, 10).unwrap(), sampled);
+
+    // Removed rows are not sampled.
+    for docid in 0..500 {
+        retriever.remove(docid).unwrap();
+    }
+    assert_eq!(retriever.score_distribution(&origin(), 1.0, 10).unwrap().total(), 500);
+
+    for fraction in [0.0, 1.5, f32::NAN] {
+        let error = retriever.score_distribution(&origin(), fraction, 10).unwrap_err();
+        assert!(error.to_string().contains("sample_fraction must be in (0, 1]"), "{}", error);
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! IndexManager over artifacts directories: LRU eviction under a small
+//! budget, transparent reloads, and single-flight loading.
+
+mod common;
+
+use common::{random_dataset, scratch_dir};
+use scann::artifacts::{self, ArtifactsConfig};
+use scann::distance_measures::SquaredL2Distance;
+use scann::index_manager::IndexManager;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, Normalization};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+const DIM: usize = 4;
+const NAMES: [&str; 3] = ["a", "b", "c"];
+
+// Three equally sized indexes saved as artifacts and registered by path.
+fn manager(test: &str) -> (IndexManager, Vec<PathBuf>) {
+    let config = ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: DIM,
+    };
+    let manager = IndexManager::new();
+    let dirs = NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let dir = scratch_dir(&format!("{}_{}", test, name));
+            let docids: Vec<usize> = (0..300).map(|d| 1000 * (i + 1) + d).collect();
+            artifacts::save_artifacts(&dir, &config, &random_dataset(300, DIM, i as u64), &docids).unwrap();
+            manager.register(name, &dir);
+            dir
+        })
+        .collect();
+    (manager, dirs)
+}
+
+fn loaded(manager: &IndexManager) -> Vec<&'static str> {
+    NAMES.into_iter().filter(|name| manager.is_loaded(name)).collect()
+}
+
+fn search(manager: &IndexManager, name: &str) -> Vec<(usize, f32)> {
+    manager
+        .search(name, &DatapointPtr::new(vec![0.1; DIM]), &SearchOptions::default())
+        .unwrap()
+}
+
+#[test]
+fn least_recently_used_unpinned_indexes_are_evicted_first() {
+    let (manager, dirs) = manager("eviction");
+    assert!(loaded(&manager).is_empty());
+    search(&manager, "a");
+    let one = manager.resident_bytes();
+    assert!(one > 0);
+    // Room for two of the three.
+    manager.set_budget(2 * one + one / 2);
+
+    search(&manager, "b");
+    search(&manager, "c");
+    assert_eq!(loaded(&manager), ["b", "c"]);
+    // Touching b makes c the oldest.
+    search(&manager, "b");
+    search(&manager, "a");
+    assert_eq!(loaded(&manager), ["a", "b"]);
+
+    // A pinned index stays even when it is the oldest.
+    manager.pin("a");
+    search(&manager, "b");
+    search(&manager, "c");
+    assert_eq!(loaded(&manager), ["a", "c"]);
+    manager.unpin("a");
+    manager.set_budget(one + one / 2);
+    assert_eq!(loaded(&manager), ["c"]);
+    assert!(manager.resident_bytes() <= one + one / 2);
+
+    assert!(manager.search("missing", &DatapointPtr::new(vec![0.0; DIM]), &SearchOptions::default()).is_err());
+    for dir in dirs {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn evicted_indexes_reload_with_identical_results() {
+    let (manager, dirs) = manager("reload");
+    let expected: Vec<_> = NAMES.iter().map(|name| search(&manager, name)).collect();
+    for (i, results) in expected.iter().enumerate() {
+        let direct = ScannRetriever::new(random_dataset(300, DIM, i as u64), Box::new(SquaredL2Distance::new()), 10);
+        let (direct, _) =
+            direct.search_with_options(&DatapointPtr::new(vec![0.1; DIM]), &SearchOptions::default()).unwrap();
+        let offset = 1000 * (i + 1);
+        let direct: Vec<_> = direct.into_iter().map(|(docid, d)| (docid + offset, d)).collect();
+        assert_eq!(results, &direct, "{}", NAMES[i]);
+    }
+    // Budget for a single index: each search evicts the previous one.
+    manager.set_budget(1);
+    for _ in 0..2 {
+        for (name, results) in NAMES.iter().zip(&expected) {
+            assert_eq!(&search(&manager, name), results, "{}", name);
+            assert_eq!(loaded(&manager), [*name]);
+        }
+    }
+    for dir in dirs {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn concurrent_first_queries_share_one_load() {
+    let manager = IndexManager::new();
+    let loads = Arc::new(AtomicUsize::new(0));
+    {
+        let loads = loads.clone();
+        manager.register_loader(
+            "slow",
+            Arc::new(move || {
+                loads.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                Ok(ScannRetriever::new(random_dataset(300, DIM, 7), Box::new(SquaredL2Distance::new()), 5))
+            }),
+        );
+    }
+    let barrier = Barrier::new(8);
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    search(&manager, "slow")
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    assert!(results.windows(2).all(|w| w[0] == w[1]));
+    search(&manager, "slow");
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+    // After an eviction the next query loads it again, once.
+    manager.set_budget(1);
+    assert!(!manager.is_loaded("slow"));
+    search(&manager, "slow");
+    assert_eq!(loads.load(Ordering::SeqCst), 2);
+}
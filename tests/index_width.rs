@@ -0,0 +1,152 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact U32 and full U64 index widths: identical results, leaves and
+//! blobs stored at the chosen width, and the add-time limit of U32.
+
+mod common;
+
+use common::{random_dataset, scratch_dir};
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::{KMeansTree, KMeansTreeTrainingOptions};
+use scann::util::{DatapointPtr, DenseDataset, IndexList, IndexOverflowPolicy, IndexWidth};
+
+const DIM: usize = 8;
+const NUM_LEAVES: usize = 6;
+
+fn training_options() -> KMeansTreeTrainingOptions {
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    options
+}
+
+fn partitioned(data: &DenseDataset<f32>, width: IndexWidth, overflow: IndexOverflowPolicy) -> ScannRetriever {
+    let retriever = ScannRetriever::new(data.clone(), Box::new(SquaredL2Distance::new()), 10)
+        .with_index_width(width, overflow)
+        .unwrap();
+    retriever.build_partitions(NUM_LEAVES, &training_options()).unwrap();
+    retriever
+}
+
+fn search(retriever: &ScannRetriever, query: &[f32], leaves_to_search: Option<usize>) -> Vec<(usize, f32)> {
+    let options = SearchOptions {
+        k: Some(10),
+        leaves_to_search,
+        ..SearchOptions::default()
+    };
+    retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap().0
+}
+
+#[test]
+fn both_widths_return_identical_results() {
+    let data = random_dataset(1500, DIM, 1);
+    let compact = partitioned(&data, IndexWidth::U32, IndexOverflowPolicy::Upgrade);
+    let wide = partitioned(&data, IndexWidth::U64, IndexOverflowPolicy::Upgrade);
+    assert_eq!(compact.index_width(), IndexWidth::U32);
+    assert_eq!(wide.index_width(), IndexWidth::U64);
+    // Only the leaf assignments change size: four bytes per row.
+    assert_eq!(wide.memory_usage() - compact.memory_usage(), data.size() * 4);
+
+    for query in random_dataset(20, DIM, 2).data {
+        for leaves_to_search in [None, Some(2), Some(NUM_LEAVES)] {
+            assert_eq!(
+                search(&compact, &query, leaves_to_search),
+                search(&wide, &query, leaves_to_search),
+                "leaves_to_search {:?}",
+                leaves_to_search
+            );
+        }
+    }
+}
+
+#[test]
+fn tree_leaves_are_stored_at_the_tree_width() {
+    let data = random_dataset(400, DIM, 3);
+    let (mut tree, _) = KMeansTree::train(&data, 4, &training_options()).unwrap();
+    assert_eq!(tree.index_width(), IndexWidth::U32);
+    let rows: Vec<Vec<usize>> = (0..4).map(|leaf| tree.leaf(leaf).to_vec()).collect();
+    assert!((0..4).all(|leaf| matches!(tree.leaf(leaf), IndexList::U32(_))));
+    assert_eq!(rows.iter().map(Vec::len).sum::<usize>(), data.size());
+
+    tree.set_index_width(IndexWidth::U64).unwrap();
+    assert!((0..4).all(|leaf| matches!(tree.leaf(leaf), IndexList::U64(_))));
+    assert_eq!((0..4).map(|leaf| tree.leaf(leaf).to_vec()).collect::<Vec<_>>(), rows);
+    tree.set_index_width(IndexWidth::U32).unwrap();
+    assert_eq!((0..4).map(|leaf| tree.leaf(leaf).to_vec()).collect::<Vec<_>>(), rows);
+}
+
+#[test]
+fn index_lists_widen_on_push_and_refuse_to_narrow_past_u32() {
+    let mut list = IndexList::from_indices(IndexWidth::U32, [3, 1, 3]);
+    assert_eq!((list.width(), list.heap_bytes()), (IndexWidth::U32, 12));
+    let large = u32::MAX as usize + 1;
+    list.push(large);
+    assert_eq!(list.width(), IndexWidth::U64);
+    assert_eq!(list.to_vec(), vec![3, 1, 3, large]);
+    assert!(list.set_width(IndexWidth::U32).is_err());
+    assert_eq!(list.width(), IndexWidth::U64);
+    list.retain(|i| i != large);
+    list.sort_dedup();
+    list.set_width(IndexWidth::U32).unwrap();
+    assert_eq!(list, IndexList::U32(vec![1, 3]));
+}
+
+#[test]
+fn blobs_round_trip_each_width() {
+    let data = random_dataset(600, DIM, 4);
+    let dir = scratch_dir("round_trip");
+    let mut memory = Vec::new();
+    for width in [IndexWidth::U32, IndexWidth::U64] {
+        let original = partitioned(&data, width, IndexOverflowPolicy::Upgrade);
+        let path = dir.join(format!("{:?}.blob", width));
+        original.pack_blob(&path).unwrap();
+        let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 10).unwrap();
+        assert_eq!(loaded.index_width(), width);
+        memory.push(loaded.memory_usage());
+        for query in random_dataset(5, DIM, 5).data {
+            assert_eq!(search(&loaded, &query, Some(3)), search(&original, &query, Some(3)));
+        }
+    }
+    // The loaded trees keep the recorded width.
+    assert_eq!(memory[1] - memory[0], data.size() * 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_full_u32_index_errors_or_upgrades_per_policy() {
+    let data = random_dataset(300, DIM, 6);
+    let row = vec![0.25f32; DIM];
+    let beyond = u32::MAX as usize + 1;
+
+    let strict = partitioned(&data, IndexWidth::U32, IndexOverflowPolicy::Error);
+    let error = strict.upsert(beyond, &row).unwrap_err();
+    assert!(error.to_string().contains("index limit"), "{}", error);
+    assert_eq!(strict.index_width(), IndexWidth::U32);
+    assert_eq!(strict.num_active(), data.size());
+    assert!(strict.get_by_docid(beyond).is_none());
+    // The largest docid a U32 index holds is still accepted.
+    strict.upsert(u32::MAX as usize, &row).unwrap();
+    assert_eq!(strict.index_width(), IndexWidth::U32);
+
+    let upgrading = partitioned(&data, IndexWidth::U32, IndexOverflowPolicy::Upgrade);
+    let before = upgrading.memory_usage();
+    upgrading.upsert(beyond, &row).unwrap();
+    assert_eq!(upgrading.index_width(), IndexWidth::U64);
+    // Every leaf assignment was widened, plus the new row's.
+    assert!(upgrading.memory_usage() >= before + data.size() * 4);
+    assert_eq!(search(&upgrading, &row, Some(NUM_LEAVES))[0], (beyond, 0.0));
+    let report = upgrading.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+}
@@ -0,0 +1,155 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! verify_integrity against retrievers with one relationship deliberately
+//! broken: each corruption must surface as its own violation.
+
+use scann::attribute_store::AttributeStore;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{IntegrityCorruption, IntegrityViolation, NormCache, ScannRetriever};
+use scann::tree::{KMeansTree, KMeansTreeTrainingOptions};
+use scann::util::{DenseDataset, SplitMix64};
+
+const NUM_POINTS: usize = 40;
+
+fn retriever() -> ScannRetriever {
+    let mut rng = SplitMix64::new(3);
+    let rows = (0..NUM_POINTS).map(|_| (0..3).map(|_| rng.next_normal()).collect()).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 3), Box::new(SquaredL2Distance::new()), 5)
+}
+
+fn violations(corruption: IntegrityCorruption) -> Vec<IntegrityViolation> {
+    let retriever = retriever();
+    assert!(retriever.verify_integrity().unwrap().is_ok());
+    retriever.corrupt_for_testing(corruption).unwrap();
+    retriever.verify_integrity().unwrap().violations
+}
+
+fn violation(component: &'static str, message: &str) -> IntegrityViolation {
+    IntegrityViolation {
+        component,
+        message: message.to_string(),
+    }
+}
+
+// Two leaves over the given rows, with centers of the dataset's
+// dimensionality.
+fn tree(first: Vec<usize>, second: Vec<usize>) -> KMeansTree {
+    KMeansTree::from_parts(DenseDataset::new(vec![vec![0.0; 3]; 2], 3), vec![first, second], 1.0, 1)
+}
+
+#[test]
+fn an_intact_retriever_passes_every_check() {
+    let retriever = retriever();
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(4, &options).unwrap();
+    retriever.register_derived_data(Box::<NormCache>::default()).unwrap();
+    retriever.remove(7).unwrap();
+    let report = retriever.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.num_rows, NUM_POINTS);
+    assert!(report.checks_run > 2 * NUM_POINTS);
+}
+
+#[test]
+fn dataset_rows_must_match_the_dimensionality() {
+    let found = violations(IntegrityCorruption::DatasetDimensionality(4));
+    assert_eq!(found.len(), NUM_POINTS);
+    assert_eq!(found[0], violation("dataset", "row 0 has 3 values, dataset dimensionality is 4"));
+}
+
+#[test]
+fn a_docid_must_map_back_to_its_row() {
+    let found = violations(IntegrityCorruption::DocidMapEntry { docid: 5, row: 6 });
+    assert_eq!(found, vec![violation("docid_map", "docid 5 at row 5 maps back to Some(6)")]);
+}
+
+#[test]
+fn the_reverse_map_must_not_hold_extra_docids() {
+    let found = violations(IntegrityCorruption::DocidMapEntry { docid: 500, row: 90 });
+    assert_eq!(
+        found,
+        vec![
+            violation("docid_map", "reverse map has 41 entries for 40 docids (duplicate or missing docids)"),
+            violation("docid_map", "docid 500 maps to row 90, past 40 rows"),
+        ]
+    );
+}
+
+#[test]
+fn next_docid_must_exceed_every_docid() {
+    let found = violations(IntegrityCorruption::NextDocid(39));
+    assert_eq!(found, vec![violation("docids", "next docid 39 does not exceed largest docid Some(39)")]);
+}
+
+#[test]
+fn the_norm_cache_must_cover_every_row() {
+    let retriever = retriever();
+    let err = retriever.corrupt_for_testing(IntegrityCorruption::Norms(Vec::new())).unwrap_err();
+    assert!(err.to_string().contains("No NormCache"), "{}", err);
+    retriever.register_derived_data(Box::<NormCache>::default()).unwrap();
+    retriever.corrupt_for_testing(IntegrityCorruption::Norms(vec![1.0; 12])).unwrap();
+    let found = retriever.verify_integrity().unwrap().violations;
+    assert_eq!(found, vec![violation("norm_cache", "norm cache has 12 entries for 40 rows")]);
+}
+
+#[test]
+fn tree_centers_must_match_the_leaves_and_dimensionality() {
+    let leaves = vec![(0..20).collect(), (20..40).collect()];
+    let centers = DenseDataset::new(vec![vec![0.0; 2]; 3], 2);
+    let found = violations(IntegrityCorruption::Tree(KMeansTree::from_parts(centers, leaves, 1.0, 1)));
+    assert_eq!(
+        found,
+        vec![
+            violation("tree", "3 centers for 2 leaves"),
+            violation("tree", "centers have dimensionality 2, dataset has 3"),
+        ]
+    );
+}
+
+#[test]
+fn tree_leaves_must_reference_stored_rows() {
+    let found = violations(IntegrityCorruption::Tree(tree((0..20).collect(), (20..41).collect())));
+    assert_eq!(found, vec![violation("tree", "leaf 1 position 20 references row 40, past 40 rows")]);
+}
+
+#[test]
+fn a_leaf_must_not_list_a_row_twice() {
+    let mut second: Vec<usize> = (20..40).collect();
+    second.push(25);
+    let found = violations(IntegrityCorruption::Tree(tree((0..20).collect(), second)));
+    assert_eq!(found, vec![violation("tree", "leaf 1 lists row 25 more than once")]);
+}
+
+#[test]
+fn every_row_must_belong_to_a_leaf() {
+    let first = (0..20).filter(|&i| i != 3 && i != 8).collect();
+    let found = violations(IntegrityCorruption::Tree(tree(first, (20..40).collect())));
+    assert_eq!(found, vec![violation("tree", "2 rows belong to no leaf, first is row 3")]);
+}
+
+#[test]
+fn hidden_docids_must_be_stored() {
+    let found = violations(IntegrityCorruption::Tombstone(77));
+    assert_eq!(found, vec![violation("tombstones", "docid 77 is not stored")]);
+    let found = violations(IntegrityCorruption::Quarantine(78));
+    assert_eq!(found, vec![violation("quarantine", "docid 78 is not stored")]);
+}
+
+#[test]
+fn attributes_must_have_a_row_per_datapoint() {
+    let found = violations(IntegrityCorruption::Attributes(AttributeStore::new(NUM_POINTS - 1)));
+    assert_eq!(found, vec![violation("attributes", "attribute store has 39 rows for 40 rows")]);
+}
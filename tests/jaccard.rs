@@ -0,0 +1,81 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! General and binary Jaccard distances on empty, disjoint, identical and
+//! partially overlapping inputs.
+
+use scann::distance_measures::{self, BinaryJaccardDistance, DistanceMeasure, GeneralJaccardDistance};
+use scann::util::DatapointPtr;
+
+fn general(a: &[f32], b: &[f32]) -> f32 {
+    GeneralJaccardDistance::new().compute_distance_f32(a, b)
+}
+
+fn binary(a: &[f32], b: &[f32]) -> f32 {
+    BinaryJaccardDistance::new().compute_distance_f32(a, b)
+}
+
+#[test]
+fn all_zero_inputs_are_identical_empty_sets() {
+    let zero = [0.0; 4];
+    assert_eq!(general(&zero, &zero), 0.0);
+    assert_eq!(binary(&zero, &zero), 0.0);
+    // Against a non-empty set the intersection is empty.
+    assert_eq!(general(&zero, &[0.0, 2.0, 0.0, 0.0]), 1.0);
+    assert_eq!(binary(&zero, &[0.0, 2.0, 0.0, 0.0]), 1.0);
+    assert_eq!(general(&[], &[]), 0.0);
+}
+
+#[test]
+fn disjoint_inputs_are_at_distance_one() {
+    let a = [1.0, 3.0, 0.0, 0.0];
+    let b = [0.0, 0.0, 0.5, 2.0];
+    assert_eq!(general(&a, &b), 1.0);
+    assert_eq!(binary(&a, &b), 1.0);
+}
+
+#[test]
+fn identical_inputs_are_at_distance_zero() {
+    let a = [0.25, 0.0, 7.0, 1.0];
+    assert_eq!(general(&a, &a), 0.0);
+    assert_eq!(binary(&a, &a), 0.0);
+}
+
+#[test]
+fn partial_overlap_matches_the_formulas() {
+    let a = [1.0, 2.0, 0.0, 4.0];
+    let b = [3.0, 1.0, 1.0, 0.0];
+    // sum(min) = 1 + 1 + 0 + 0 = 2; sum(max) = 3 + 2 + 1 + 4 = 10.
+    assert!((general(&a, &b) - 0.8).abs() < 1e-7);
+    // Members {0, 1, 3} and {0, 1, 2}: intersection 2, union 4. Magnitudes
+    // do not matter.
+    assert_eq!(binary(&a, &b), 0.5);
+    assert_eq!(binary(&[9.0, 0.5, 0.0, 1.0], &b), 0.5);
+    // Both are symmetric.
+    assert_eq!(general(&a, &b), general(&b, &a));
+    assert_eq!(binary(&a, &b), binary(&b, &a));
+}
+
+#[test]
+fn typed_datapoints_and_lookup_by_name_agree() {
+    let a = DatapointPtr::new(vec![1u8, 0, 1, 1]);
+    let b = DatapointPtr::new(vec![1u8, 1, 0, 1]);
+    assert_eq!(BinaryJaccardDistance::new().compute_distance(&a, &b), 0.5);
+    assert_eq!(GeneralJaccardDistance::new().compute_distance(&a, &b), 0.5);
+    for name in ["GeneralJaccardDistance", "BinaryJaccardDistance"] {
+        let measure = distance_measures::get_distance_measure_by_name(name).unwrap();
+        assert_eq!(measure.name(), name);
+        assert_eq!(measure.compute_distance_f32(&[1.0, 0.0, 1.0, 1.0], &[1.0, 1.0, 0.0, 1.0]), 0.5);
+    }
+}
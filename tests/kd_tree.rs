@@ -0,0 +1,142 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Unrolled low-dimensional kernels and the exact k-d tree search path.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{self, DistanceMeasure, DotProductDistance, LowDimKernel, SquaredL2Distance};
+use scann::kd_tree::KdTree;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+
+fn brute_force(data: &[Vec<f32>], query: &[f32], k: usize, skip: &[usize]) -> Vec<(usize, f32)> {
+    let mut all: Vec<(usize, f32)> = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip.contains(i))
+        .map(|(i, row)| (i, row.iter().zip(query).map(|(a, b)| (a - b) * (a - b)).sum()))
+        .collect();
+    all.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    all.truncate(k);
+    all
+}
+
+#[test]
+fn specialized_kernels_match_the_generic_measures() {
+    let measures: [(LowDimKernel, Box<dyn DistanceMeasure>); 2] = [
+        (LowDimKernel::SquaredL2, Box::new(SquaredL2Distance::new())),
+        (LowDimKernel::DotProduct, Box::new(DotProductDistance::new())),
+    ];
+    for (kernel, measure) in &measures {
+        assert_eq!(measure.low_dim_kernel(), Some(*kernel));
+        for dim in 1..=10 {
+            // With the simd feature the vector kernels take over instead.
+            let Some(specialized) = distance_measures::select_low_dim_kernel(*kernel, dim) else {
+                assert!(cfg!(feature = "simd") || ![2, 3, 4, 8].contains(&dim), "{:?} dim {}", kernel, dim);
+                continue;
+            };
+            assert!([2, 3, 4, 8].contains(&dim));
+            for pair in random_rows(40, dim, dim as u64).chunks(2) {
+                let expected = measure.compute_distance_f32(&pair[0], &pair[1]);
+                let actual = specialized(&pair[0], &pair[1]);
+                assert!((expected - actual).abs() <= 1e-5 * (1.0 + expected.abs()), "{:?} dim {}", kernel, dim);
+            }
+        }
+    }
+}
+
+#[test]
+fn low_dimensional_retrievers_match_brute_force() {
+    for dim in [2, 3, 4, 8] {
+        let data = random_rows(300, dim, 40 + dim as u64);
+        let dataset = DenseDataset::new(data.clone(), dim);
+        let retriever = ScannRetriever::new(dataset, Box::new(SquaredL2Distance::new()), 7);
+        for query in random_rows(10, dim, 90 + dim as u64) {
+            let results = retriever.search(&DatapointPtr::new(query.clone())).unwrap();
+            let expected = brute_force(&data, &query, 7, &[]);
+            let docids = |results: &[(usize, f32)]| results.iter().map(|r| r.0).collect::<Vec<_>>();
+            assert_eq!(docids(&results), docids(&expected), "dim {}", dim);
+            for (actual, expected) in results.iter().zip(&expected) {
+                assert!((actual.1 - expected.1).abs() <= 1e-5 * (1.0 + expected.1), "dim {}", dim);
+            }
+        }
+    }
+}
+
+#[test]
+fn kd_tree_returns_exact_brute_force_results() {
+    for dim in [1, 2, 3, 5, 8, 16] {
+        let data = random_rows(500, dim, dim as u64);
+        let dataset = DenseDataset::new(data.clone(), dim);
+        for leaf_size in [1, 4, 32] {
+            let tree = KdTree::build(&dataset, leaf_size).unwrap();
+            assert_eq!(tree.dimensionality(), dim);
+            for (q, query) in random_rows(20, dim, 1000 + dim as u64).into_iter().enumerate() {
+                for k in [1, 10, 600] {
+                    let skip = [q, 2 * q + 1];
+                    let actual = tree.search(&dataset, &query, k, |i| !skip.contains(&i));
+                    let expected = brute_force(&data, &query, k, &skip);
+                    assert_eq!(
+                        actual.iter().map(|r| r.0).collect::<Vec<_>>(),
+                        expected.iter().map(|r| r.0).collect::<Vec<_>>(),
+                        "dim {} leaf size {} k {}",
+                        dim,
+                        leaf_size,
+                        k
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn kd_tree_breaks_ties_by_row_index() {
+    // A 6x6 integer grid has many equidistant rows around every query.
+    let data: Vec<Vec<f32>> = (0..36).map(|i| vec![(i % 6) as f32, (i / 6) as f32]).collect();
+    let dataset = DenseDataset::new(data.clone(), 2);
+    let tree = KdTree::build(&dataset, 2).unwrap();
+    for query in [[2.0, 2.0], [2.5, 2.5], [0.0, 5.0]] {
+        for k in [1, 4, 5, 9] {
+            assert_eq!(tree.search(&dataset, &query, k, |_| true), brute_force(&data, &query, k, &[]));
+        }
+    }
+    assert!(tree.search(&dataset, &[0.0, 0.0], 0, |_| true).is_empty());
+}
+
+#[test]
+fn retriever_kd_tree_path_is_exact_and_validated() {
+    let data = random_rows(400, 3, 77);
+    let retriever = ScannRetriever::new(DenseDataset::new(data.clone(), 3), Box::new(SquaredL2Distance::new()), 10);
+    retriever.build_kd_tree(8).unwrap();
+    retriever.remove(5).unwrap();
+    let query = data[5].clone();
+    let (results, stats) = retriever
+        .search_with_options(&DatapointPtr::new(query.clone()), &SearchOptions::default())
+        .unwrap();
+    assert_eq!(results, brute_force(&data, &query, 10, &[5]));
+    assert!(stats.datapoints_scored < 400, "{:?}", stats.datapoints_scored);
+
+    let dot = ScannRetriever::new(DenseDataset::new(data.clone(), 3), Box::new(DotProductDistance::new()), 10);
+    assert!(dot.build_kd_tree(8).unwrap_err().to_string().contains("squared L2"));
+    let wide = ScannRetriever::new(DenseDataset::new(random_rows(10, 17, 1), 17), Box::new(SquaredL2Distance::new()), 3);
+    assert!(wide.build_kd_tree(8).unwrap_err().to_string().contains("dimensionality 1..=16"));
+    let mut bad = data;
+    bad[0][1] = f32::NAN;
+    let nan = ScannRetriever::new(DenseDataset::new(bad, 3), Box::new(SquaredL2Distance::new()), 3);
+    assert!(nan.build_kd_tree(8).unwrap_err().to_string().contains("finite data"));
+}
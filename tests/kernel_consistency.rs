@@ -0,0 +1,140 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Single, one-to-many, match-dispatched and searched distances agree bit
+//! for bit, with and without `--features simd`.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{
+    self, CosineDistance, DistanceMeasure, DistanceMeasureKind, DotProductDistance, NormalizedDotProductDistance,
+    SquaredL2Distance,
+};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+
+const DIMS: [usize; 8] = [2, 3, 4, 7, 8, 16, 33, 100];
+
+#[test]
+fn every_path_returns_the_single_distance_bit_for_bit() {
+    let kinds = [
+        DistanceMeasureKind::SquaredL2,
+        DistanceMeasureKind::DotProduct,
+        DistanceMeasureKind::Cosine,
+        DistanceMeasureKind::NormalizedDotProduct,
+    ];
+    for dim in DIMS {
+        let data = random_rows(64, dim, dim as u64);
+        let query = random_rows(1, dim, 1000 + dim as u64).remove(0);
+        let dataset = DenseDataset::new(data.clone(), dim);
+        for kind in kinds {
+            let measure = kind.to_measure();
+            let context = format!("{:?} dim {}", kind, dim);
+            let single: Vec<u32> = data.iter().map(|row| measure.compute_distance_f32(&query, row).to_bits()).collect();
+
+            let mut out = vec![0.0f32; data.len()];
+            measure.compute_one_to_many(&DatapointPtr::new(query.clone()), &dataset, &mut out);
+            assert_eq!(out.iter().map(|d| d.to_bits()).collect::<Vec<_>>(), single, "{} one-to-many", context);
+
+            out.fill(0.0);
+            kind.compute_one_to_many_rows(&query, (&dataset.data).into(), &mut out);
+            assert_eq!(out.iter().map(|d| d.to_bits()).collect::<Vec<_>>(), single, "{} kind rows", context);
+
+            let dispatched: Vec<u32> = data.iter().map(|row| kind.compute(&query, row).to_bits()).collect();
+            assert_eq!(dispatched, single, "{} kind", context);
+
+            let top = distance_measures::top_k_one_to_many(measure.as_ref(), &DatapointPtr::new(query.clone()), &dataset, 5);
+            for (i, distance) in top {
+                assert_eq!(distance.to_bits(), single[i], "{} fused top-k row {}", context, i);
+            }
+        }
+    }
+}
+
+#[test]
+fn generic_f32_datapoints_use_the_slice_kernel() {
+    for dim in DIMS {
+        let [a, b]: [Vec<f32>; 2] = random_rows(2, dim, 7 * dim as u64).try_into().unwrap();
+        let (pa, pb) = (DatapointPtr::new(a.clone()), DatapointPtr::new(b.clone()));
+        assert_eq!(
+            SquaredL2Distance.compute_distance(&pa, &pb).to_bits(),
+            SquaredL2Distance.compute_distance_f32(&a, &b).to_bits()
+        );
+        assert_eq!(
+            DotProductDistance.compute_distance(&pa, &pb).to_bits(),
+            DotProductDistance.compute_distance_f32(&a, &b).to_bits()
+        );
+        assert_eq!(
+            CosineDistance.compute_distance(&pa, &pb).to_bits(),
+            CosineDistance.compute_distance_f32(&a, &b).to_bits()
+        );
+        assert_eq!(
+            NormalizedDotProductDistance.compute_distance(&pa, &pb).to_bits(),
+            NormalizedDotProductDistance.compute_distance_f32(&a, &b).to_bits()
+        );
+    }
+}
+
+#[test]
+fn searched_distances_match_the_single_distance() {
+    for dim in DIMS {
+        let data = random_rows(500, dim, 3 * dim as u64);
+        let query = random_rows(1, dim, 5000 + dim as u64).remove(0);
+        for kind in [DistanceMeasureKind::SquaredL2, DistanceMeasureKind::DotProduct, DistanceMeasureKind::Cosine] {
+            let measure = kind.to_measure();
+            let retrievers = [
+                ScannRetriever::new(DenseDataset::new(data.clone(), dim), kind.to_measure(), 10),
+                ScannRetriever::with_measure_kind(DenseDataset::new(data.clone(), dim), kind, 10),
+            ];
+            for retriever in &retrievers {
+                // k = 10 over 500 rows takes the fused scan; k = 100 the
+                // blocked one.
+                for k in [10, 100] {
+                    let options = SearchOptions {
+                        k: Some(k),
+                        ..SearchOptions::default()
+                    };
+                    let (results, _) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &options).unwrap();
+                    for (docid, distance) in results {
+                        let expected = measure.compute_distance_f32(&query, &data[docid]);
+                        assert_eq!(distance.to_bits(), expected.to_bits(), "{:?} dim {} k {}", kind, dim, k);
+                    }
+                }
+                let (ids, distances, filled) =
+                    retriever.search_small_k::<4>(scann::util::DatapointRef::from_slice(&query)).unwrap();
+                for (&docid, &distance) in ids.iter().zip(&distances).take(filled) {
+                    let expected = measure.compute_distance_f32(&query, &data[docid as usize]);
+                    assert_eq!(distance.to_bits(), expected.to_bits(), "{:?} dim {} small k", kind, dim);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn fixed_dimension_kernels_step_aside_for_simd() {
+    for kernel in [distance_measures::LowDimKernel::SquaredL2, distance_measures::LowDimKernel::DotProduct] {
+        for dim in [2, 3, 4, 8] {
+            assert_eq!(
+                distance_measures::select_low_dim_kernel(kernel, dim).is_some(),
+                !scann::simd::available(),
+                "{:?} dim {}",
+                kernel,
+                dim
+            );
+        }
+    }
+}
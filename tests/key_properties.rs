@@ -0,0 +1,367 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Generated-input properties of the key encodings in `serialize`: for
+//! every key type, decode inverts encode, encode is injective, and bytewise
+//! key order equals value order, for single and composite keys.
+//!
+//! Inputs mix uniformly random bit patterns, which cover NaN payloads,
+//! subnormals and infinities, with the boundary values where encodings
+//! tend to break. A failure reports the seed and case that produced it.
+
+use scann::serialize::{self, KeyBuilder, KeyReader};
+use scann::util::SplitMix64;
+use std::cmp::Ordering;
+
+const CASES: usize = 4000;
+
+// Runs `property` on CASES inputs drawn by `generate` from a fixed seed.
+fn check<T: std::fmt::Debug>(
+    seed: u64,
+    mut generate: impl FnMut(&mut SplitMix64) -> T,
+    mut property: impl FnMut(&T) -> Result<(), String>,
+) {
+    let mut rng = SplitMix64::new(seed);
+    for case in 0..CASES {
+        let input = generate(&mut rng);
+        if let Err(message) = property(&input) {
+            panic!("seed {} case {}: {:?}: {}", seed, case, input, message);
+        }
+    }
+}
+
+// One in four draws is an edge value.
+fn pick<T: Copy>(rng: &mut SplitMix64, edges: &[T], random: impl FnOnce(&mut SplitMix64) -> T) -> T {
+    if rng.next_below(4) == 0 {
+        edges[rng.next_below(edges.len())]
+    } else {
+        random(rng)
+    }
+}
+
+fn gen_u32(rng: &mut SplitMix64) -> u32 {
+    pick(rng, &[0, 1, 2, 0x7fff_ffff, 0x8000_0000, u32::MAX - 1, u32::MAX], |rng| match rng.next_below(2) {
+        0 => rng.next_u64() as u32,
+        _ => rng.next_below(16) as u32,
+    })
+}
+
+fn gen_i32(rng: &mut SplitMix64) -> i32 {
+    pick(rng, &[0, 1, -1, i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX], |rng| match rng.next_below(2) {
+        0 => rng.next_u64() as i32,
+        _ => rng.next_below(16) as i32 - 8,
+    })
+}
+
+fn gen_u64(rng: &mut SplitMix64) -> u64 {
+    pick(rng, &[0, 1, u32::MAX as u64, u32::MAX as u64 + 1, 1 << 63, u64::MAX - 1, u64::MAX], |rng| {
+        match rng.next_below(2) {
+            0 => rng.next_u64(),
+            _ => rng.next_below(16) as u64,
+        }
+    })
+}
+
+fn gen_i64(rng: &mut SplitMix64) -> i64 {
+    pick(rng, &[0, 1, -1, i32::MIN as i64 - 1, i32::MAX as i64 + 1, i64::MIN, i64::MAX], |rng| {
+        match rng.next_below(2) {
+            0 => rng.next_u64() as i64,
+            _ => rng.next_below(16) as i64 - 8,
+        }
+    })
+}
+
+fn gen_f32(rng: &mut SplitMix64) -> f32 {
+    let edges = [
+        0.0,
+        -0.0,
+        1.0,
+        -1.0,
+        f32::MIN_POSITIVE,
+        -f32::MIN_POSITIVE,
+        f32::from_bits(1),
+        -f32::from_bits(1),
+        f32::MAX,
+        f32::MIN,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::NAN,
+        -f32::NAN,
+        f32::from_bits(0x7f80_0001),
+        f32::from_bits(0xffff_ffff),
+    ];
+    pick(rng, &edges, |rng| match rng.next_below(2) {
+        0 => f32::from_bits(rng.next_u64() as u32),
+        _ => rng.next_normal() * 100.0,
+    })
+}
+
+fn gen_f64(rng: &mut SplitMix64) -> f64 {
+    let edges = [
+        0.0,
+        -0.0,
+        1.0,
+        -1.0,
+        f64::MIN_POSITIVE,
+        f64::from_bits(1),
+        -f64::from_bits(1),
+        f64::MAX,
+        f64::MIN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+        -f64::NAN,
+        f64::from_bits(0x7ff0_0000_0000_0001),
+        f32::MAX as f64 * 2.0,
+    ];
+    pick(rng, &edges, |rng| match rng.next_below(2) {
+        0 => f64::from_bits(rng.next_u64()),
+        _ => rng.next_normal() as f64 * 1e3,
+    })
+}
+
+// The documented total order: -0.0 equals +0.0 and every NaN equals every
+// other NaN and sorts after +infinity.
+fn float_order(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+fn canonical_f32_bits(x: f32) -> u32 {
+    if x.is_nan() {
+        f32::NAN.to_bits()
+    } else if x == 0.0 {
+        0
+    } else {
+        x.to_bits()
+    }
+}
+
+fn canonical_f64_bits(x: f64) -> u64 {
+    if x.is_nan() {
+        f64::NAN.to_bits()
+    } else if x == 0.0 {
+        0
+    } else {
+        x.to_bits()
+    }
+}
+
+fn same_order(keys: Ordering, values: Ordering) -> Result<(), String> {
+    match keys == values {
+        true => Ok(()),
+        false => Err(format!("keys compare {:?}, values {:?}", keys, values)),
+    }
+}
+
+// Decode inverts encode, and key order equals value order for pairs;
+// equal keys for unequal values would show up as an order mismatch, so
+// this also checks injectivity.
+macro_rules! integer_properties {
+    ($name:ident, $generate:ident, $encode:path, $decode:path, $seed:expr) => {
+        #[test]
+        fn $name() {
+            check($seed, $generate, |&x| {
+                let decoded = $decode(&$encode(x)).map_err(|e| e.to_string())?;
+                match decoded == x {
+                    true => Ok(()),
+                    false => Err(format!("decoded as {}", decoded)),
+                }
+            });
+            check($seed + 1, |rng| ($generate(rng), $generate(rng)), |&(a, b)| {
+                same_order($encode(a).cmp(&$encode(b)), a.cmp(&b))
+            });
+        }
+    };
+}
+
+integer_properties!(uint32_keys, gen_u32, serialize::uint32_to_key, serialize::key_to_uint32, 10);
+integer_properties!(int32_keys, gen_i32, serialize::int32_to_key, serialize::key_to_int32, 20);
+integer_properties!(uint64_keys, gen_u64, serialize::uint64_to_key, serialize::key_to_uint64, 30);
+integer_properties!(int64_keys, gen_i64, serialize::int64_to_key, serialize::key_to_int64, 40);
+
+#[test]
+fn float_keys() {
+    check(50, gen_f32, |&x| {
+        let key = serialize::float_to_key(x);
+        let strict = serialize::try_key_to_float(&key).map_err(|e| e.to_string())?;
+        let lenient = serialize::key_to_float(&key).map_err(|e| e.to_string())?;
+        match strict.to_bits() == canonical_f32_bits(x) && lenient.to_bits() == strict.to_bits() {
+            true => Ok(()),
+            false => Err(format!("decoded as {:?} / {:?}", strict, lenient)),
+        }
+    });
+    check(51, |rng| (gen_f32(rng), gen_f32(rng)), |&(a, b)| {
+        same_order(
+            serialize::float_to_key(a).cmp(&serialize::float_to_key(b)),
+            float_order(a as f64, b as f64),
+        )
+    });
+    // Every 4-byte key either decodes strictly to a value that encodes back
+    // to it, or is rejected; the lenient decoder always yields a canonical
+    // value.
+    check(52, |rng| (rng.next_u64() as u32).to_be_bytes(), |key| {
+        let lenient = serialize::key_to_float(key).map_err(|e| e.to_string())?;
+        if lenient.to_bits() != canonical_f32_bits(lenient) {
+            return Err(format!("lenient decode gave non-canonical {:?}", lenient));
+        }
+        match serialize::try_key_to_float(key) {
+            Ok(x) if serialize::float_to_key(x) != key => Err(format!("{:?} does not encode back", x)),
+            _ => Ok(()),
+        }
+    });
+}
+
+#[test]
+fn double_keys() {
+    check(60, gen_f64, |&x| {
+        let key = serialize::double_to_key(x);
+        let strict = serialize::try_key_to_double(&key).map_err(|e| e.to_string())?;
+        let lenient = serialize::key_to_double(&key).map_err(|e| e.to_string())?;
+        match strict.to_bits() == canonical_f64_bits(x) && lenient.to_bits() == strict.to_bits() {
+            true => Ok(()),
+            false => Err(format!("decoded as {:?} / {:?}", strict, lenient)),
+        }
+    });
+    check(61, |rng| (gen_f64(rng), gen_f64(rng)), |&(a, b)| {
+        same_order(serialize::double_to_key(a).cmp(&serialize::double_to_key(b)), float_order(a, b))
+    });
+    check(62, |rng| rng.next_u64().to_be_bytes(), |key| {
+        let lenient = serialize::key_to_double(key).map_err(|e| e.to_string())?;
+        if lenient.to_bits() != canonical_f64_bits(lenient) {
+            return Err(format!("lenient decode gave non-canonical {:?}", lenient));
+        }
+        match serialize::try_key_to_double(key) {
+            Ok(x) if serialize::double_to_key(x) != key => Err(format!("{:?} does not encode back", x)),
+            _ => Ok(()),
+        }
+    });
+}
+
+#[test]
+fn keys_of_the_wrong_length_are_errors() {
+    check(70, |rng| (0..rng.next_below(12)).map(|_| rng.next_u64() as u8).collect::<Vec<u8>>(), |key| {
+        let results = [
+            (4, serialize::key_to_uint32(key).is_ok()),
+            (4, serialize::key_to_int32(key).is_ok()),
+            (4, serialize::key_to_float(key).is_ok()),
+            (4, serialize::try_key_to_float(key).is_ok() || key.len() == 4),
+            (8, serialize::key_to_uint64(key).is_ok()),
+            (8, serialize::key_to_int64(key).is_ok()),
+            (8, serialize::key_to_double(key).is_ok()),
+            (8, serialize::try_key_to_double(key).is_ok() || key.len() == 8),
+        ];
+        match results.iter().all(|&(width, ok)| ok == (key.len() == width)) {
+            true => Ok(()),
+            false => Err(format!("accepted or rejected by length wrongly: {:?}", results)),
+        }
+    });
+}
+
+#[test]
+fn checked_and_saturating_narrowing_agree_with_the_range() {
+    check(80, gen_u64, |&n| {
+        let fits = n <= u32::MAX as u64;
+        let saturated = serialize::key_to_uint32(&serialize::saturating_uint32_to_key(n)).unwrap();
+        match (serialize::try_uint32_to_key(n).ok(), fits) {
+            (Some(key), true) if key == serialize::uint32_to_key(n as u32) && saturated as u64 == n => Ok(()),
+            (None, false) if saturated == u32::MAX => Ok(()),
+            (key, _) => Err(format!("try gave {:?}, saturating {}", key, saturated)),
+        }
+    });
+    check(81, gen_i64, |&n| {
+        let clamped = n.clamp(i32::MIN as i64, i32::MAX as i64);
+        let saturated = serialize::key_to_int32(&serialize::saturating_int32_to_key(n)).unwrap();
+        match (serialize::try_int32_to_key(n).ok(), clamped == n) {
+            (Some(key), true) if key == serialize::int32_to_key(n as i32) && saturated as i64 == n => Ok(()),
+            (None, false) if saturated as i64 == clamped => Ok(()),
+            (key, _) => Err(format!("try gave {:?}, saturating {}", key, saturated)),
+        }
+    });
+}
+
+type Composite = (u32, i32, f32, i64, f64, u64);
+
+fn gen_composite(rng: &mut SplitMix64) -> Composite {
+    // Few distinct leading parts, so later parts decide many comparisons.
+    let small = |rng: &mut SplitMix64| rng.next_below(3) as u32;
+    (small(rng), gen_i32(rng) % 3, gen_f32(rng), gen_i64(rng), gen_f64(rng), gen_u64(rng))
+}
+
+fn build(parts: &Composite) -> Vec<u8> {
+    KeyBuilder::new()
+        .push_uint32(parts.0)
+        .push_int32(parts.1)
+        .push_float(parts.2)
+        .push_int64(parts.3)
+        .push_double(parts.4)
+        .push_uint64(parts.5)
+        .build()
+}
+
+fn read(reader: &mut KeyReader) -> Result<Composite, Box<dyn std::error::Error>> {
+    Ok((
+        reader.read_uint32()?,
+        reader.read_int32()?,
+        reader.read_float()?,
+        reader.read_int64()?,
+        reader.read_double()?,
+        reader.read_uint64()?,
+    ))
+}
+
+fn composite_order(a: &Composite, b: &Composite) -> Ordering {
+    a.0.cmp(&b.0)
+        .then(a.1.cmp(&b.1))
+        .then(float_order(a.2 as f64, b.2 as f64))
+        .then(a.3.cmp(&b.3))
+        .then(float_order(a.4, b.4))
+        .then(a.5.cmp(&b.5))
+}
+
+#[test]
+fn composite_keys() {
+    check(90, gen_composite, |parts| {
+        let key = build(parts);
+        let mut reader = KeyReader::new(&key);
+        let decoded = read(&mut reader).map_err(|e| e.to_string())?;
+        reader.finish().map_err(|e| e.to_string())?;
+        match composite_order(&decoded, parts) == Ordering::Equal
+            && canonical_f32_bits(decoded.2) == canonical_f32_bits(parts.2)
+            && canonical_f64_bits(decoded.4) == canonical_f64_bits(parts.4)
+        {
+            true => Ok(()),
+            false => Err(format!("decoded as {:?}", decoded)),
+        }
+    });
+    check(91, |rng| (gen_composite(rng), gen_composite(rng)), |(a, b)| {
+        same_order(build(a).cmp(&build(b)), composite_order(a, b))
+    });
+    // Truncated and extended keys are errors, never panics.
+    check(92, |rng| (gen_composite(rng), rng.next_below(40)), |(parts, len)| {
+        let mut key = build(parts);
+        key.resize(*len, 0);
+        let mut reader = KeyReader::new(&key);
+        let complete = read(&mut reader).is_ok() && reader.finish().is_ok();
+        match complete == (*len == 36) {
+            true => Ok(()),
+            false => Err(format!("{}-byte key decoded: {}", len, complete)),
+        }
+    });
+}
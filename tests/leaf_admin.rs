@@ -0,0 +1,147 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Administrative leaf merges and splits, and their token remappings.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::{KMeansTreeTrainingOptions, TokenRemapping};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+use std::collections::BTreeSet;
+
+const NUM_LEAVES: usize = 10;
+
+fn partitioned_retriever() -> ScannRetriever {
+    let mut rng = SplitMix64::new(6);
+    let rows = (0..1500).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 4), Box::new(SquaredL2Distance::new()), 10);
+    retriever.build_partitions(NUM_LEAVES, &KMeansTreeTrainingOptions::new()).unwrap();
+    retriever
+}
+
+fn leaves(retriever: &ScannRetriever) -> Vec<BTreeSet<usize>> {
+    let tree = retriever.partitioning().unwrap();
+    (0..tree.num_leaves()).map(|leaf| tree.leaf(leaf).iter().collect()).collect()
+}
+
+fn exhaustive_results(retriever: &ScannRetriever, queries: &[Vec<f32>]) -> Vec<Vec<(usize, f32)>> {
+    let options = SearchOptions {
+        leaves_to_search: Some(retriever.partitioning().unwrap().num_leaves()),
+        ..SearchOptions::default()
+    };
+    queries
+        .iter()
+        .map(|q| retriever.search_with_options(&DatapointPtr::new(q.clone()), &options).unwrap().0)
+        .collect()
+}
+
+fn queries() -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(60);
+    (0..20).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect()
+}
+
+// Every row of old leaf `old` lies in one of the leaves it maps to.
+fn assert_remapping_follows_rows(before: &[BTreeSet<usize>], after: &[BTreeSet<usize>], remapping: &TokenRemapping) {
+    assert_eq!(remapping.old_to_new.len(), before.len());
+    for (old, rows) in before.iter().enumerate() {
+        let targets: BTreeSet<usize> =
+            remapping.old_to_new[old].iter().flat_map(|&new| after[new].iter().copied()).collect();
+        assert!(rows.is_subset(&targets), "rows of leaf {} lost", old);
+    }
+}
+
+#[test]
+fn merge_keeps_every_row_and_search_result() {
+    let retriever = partitioned_retriever();
+    let before = leaves(&retriever);
+    let results = exhaustive_results(&retriever, &queries());
+    let centers = retriever.partitioning().unwrap().centers().data.clone();
+
+    let remapping = retriever.merge_leaves(7, 2).unwrap();
+    let after = leaves(&retriever);
+    assert_eq!(after.len(), NUM_LEAVES - 1);
+    let expected: Vec<Vec<usize>> = [0, 1, 2, 3, 4, 5, 6, 2, 7, 8].iter().map(|&leaf| vec![leaf]).collect();
+    assert_eq!(remapping.old_to_new, expected);
+    assert_eq!(after[2], &before[2] | &before[7]);
+    assert_remapping_follows_rows(&before, &after, &remapping);
+
+    // The merged center is the size-weighted mean.
+    let (w2, w7) = (before[2].len() as f32, before[7].len() as f32);
+    let merged = retriever.partitioning().unwrap().centers().data[2].clone();
+    for d in 0..4 {
+        let expected = (w2 * centers[2][d] + w7 * centers[7][d]) / (w2 + w7);
+        assert!((merged[d] - expected).abs() < 1e-5);
+    }
+    assert_eq!(exhaustive_results(&retriever, &queries()), results);
+}
+
+#[test]
+fn split_keeps_every_row_and_search_result() {
+    let retriever = partitioned_retriever();
+    let before = leaves(&retriever);
+    let results = exhaustive_results(&retriever, &queries());
+
+    let remapping = retriever.split_leaf(4, 3, &KMeansTreeTrainingOptions::new()).unwrap();
+    let after = leaves(&retriever);
+    assert_eq!(after.len(), NUM_LEAVES + 2);
+    assert_eq!(remapping.old_to_new[4], vec![4, NUM_LEAVES, NUM_LEAVES + 1]);
+    for leaf in (0..NUM_LEAVES).filter(|&leaf| leaf != 4) {
+        assert_eq!(remapping.old_to_new[leaf], vec![leaf]);
+        assert_eq!(after[leaf], before[leaf]);
+    }
+    let pieces = [&after[4], &after[NUM_LEAVES], &after[NUM_LEAVES + 1]];
+    assert!(pieces.iter().all(|piece| !piece.is_empty()));
+    assert_eq!(pieces.iter().map(|piece| piece.len()).sum::<usize>(), before[4].len());
+    assert_remapping_follows_rows(&before, &after, &remapping);
+    assert_eq!(exhaustive_results(&retriever, &queries()), results);
+}
+
+#[test]
+fn remappings_round_trip_through_artifacts() {
+    let retriever = partitioned_retriever();
+    let dir = std::env::temp_dir().join(format!("scann_leaf_admin_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("token_remapping.txt");
+    for remapping in [
+        retriever.merge_leaves(0, 1).unwrap(),
+        retriever.split_leaf(0, 2, &KMeansTreeTrainingOptions::new()).unwrap(),
+    ] {
+        remapping.save(&path).unwrap();
+        assert_eq!(TokenRemapping::load(&path).unwrap(), remapping);
+    }
+
+    std::fs::write(&path, "0: 0\n2: 1\n").unwrap();
+    assert!(TokenRemapping::load(&path).unwrap_err().to_string().contains("Invalid token remapping line"));
+    std::fs::write(&path, "0: x\n").unwrap();
+    assert!(TokenRemapping::load(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_merges_and_splits_are_rejected() {
+    let retriever = partitioned_retriever();
+    assert!(retriever.merge_leaves(3, 3).is_err());
+    assert!(retriever.merge_leaves(0, NUM_LEAVES).is_err());
+    let options = KMeansTreeTrainingOptions::new();
+    assert!(retriever.split_leaf(NUM_LEAVES, 2, &options).is_err());
+    assert!(retriever.split_leaf(0, 1, &options).is_err());
+    let size = leaves(&retriever)[0].len();
+    let message = retriever.split_leaf(0, size + 1, &options).unwrap_err().to_string();
+    assert!(message.contains(&format!("Cannot split leaf 0 with {} rows", size)), "{}", message);
+    assert_eq!(leaves(&retriever).len(), NUM_LEAVES);
+
+    let flat = ScannRetriever::new(DenseDataset::new(vec![vec![0.0; 4]; 4], 4), Box::new(SquaredL2Distance::new()), 2);
+    assert!(flat.merge_leaves(0, 1).unwrap_err().to_string().contains("no partitioning"));
+}
@@ -0,0 +1,182 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leaf code stores: resident and file-backed leaves score identically, the
+//! file-backed LRU evicts down to its byte cap, and a search always scores
+//! codes built for the snapshot it reads.
+
+mod common;
+
+use common::{random_rows, scratch_dir};
+use scann::distance_measures::SquaredL2Distance;
+use scann::leaf_codes::{FileLeafCodeStore, LeafCodeStore};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, ScannRetriever, SearchOptions, SearchStats};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+const NUM_LEAVES: usize = 8;
+const DIM: usize = 8;
+
+fn partitioned_retriever() -> ScannRetriever {
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(random_rows(2000, DIM, 1), DIM), Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever
+}
+
+fn search(retriever: &ScannRetriever, query: &[f32], leaves: usize) -> (Vec<(usize, f32)>, SearchStats) {
+    let options = SearchOptions { leaves_to_search: Some(leaves), ..SearchOptions::default() };
+    retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap()
+}
+
+#[test]
+fn lazily_loaded_leaves_match_the_resident_store() {
+    let dir = scratch_dir("match");
+    let codes = dir.join("leaf_codes.bin");
+    let resident = partitioned_retriever();
+    let lazy = partitioned_retriever();
+    resident.build_leaf_code_store().unwrap();
+    resident.write_leaf_codes(&codes).unwrap();
+    lazy.set_leaf_code_store(Some(Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap()))).unwrap();
+
+    for query in random_rows(20, DIM, 2) {
+        let (expected, resident_stats) = search(&resident, &query, 3);
+        let (results, stats) = search(&lazy, &query, 3);
+        assert_eq!(results, expected);
+        assert_eq!(stats.leaves_searched, 3);
+        assert_eq!(stats.leaf_cache_hits + stats.leaf_cache_misses, 3);
+        // The resident store never misses.
+        assert_eq!((resident_stats.leaf_cache_hits, resident_stats.leaf_cache_misses), (3, 0));
+    }
+    // Scores come from dequantized codes, not the raw rows.
+    let query = random_rows(1, DIM, 3).remove(0);
+    let (codes_results, _) = search(&lazy, &query, NUM_LEAVES);
+    lazy.set_leaf_code_store(None).unwrap();
+    let (raw_results, stats) = search(&lazy, &query, NUM_LEAVES);
+    assert_ne!(codes_results, raw_results);
+    assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (0, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_tiny_cache_evicts_and_reloads_leaves() {
+    let dir = scratch_dir("evict");
+    let codes = dir.join("leaf_codes.bin");
+    let retriever = partitioned_retriever();
+    retriever.write_leaf_codes(&codes).unwrap();
+    let query = random_rows(1, DIM, 4).remove(0);
+
+    // A cache large enough for every leaf hits on the second search.
+    let store = Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap());
+    retriever.set_leaf_code_store(Some(store.clone())).unwrap();
+    let (first, stats) = search(&retriever, &query, NUM_LEAVES);
+    assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (0, NUM_LEAVES));
+    let (second, stats) = search(&retriever, &query, NUM_LEAVES);
+    assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (NUM_LEAVES, 0));
+    assert_eq!(first, second);
+    assert!(store.resident_bytes() > 0);
+
+    // A one-byte cap keeps only the newest leaf, which the next search's
+    // first load evicts, and still scores every prefetched leaf.
+    let tiny = Arc::new(FileLeafCodeStore::open(&codes, 1).unwrap());
+    retriever.set_leaf_code_store(Some(tiny.clone())).unwrap();
+    for _ in 0..2 {
+        let (results, stats) = search(&retriever, &query, NUM_LEAVES);
+        assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (0, NUM_LEAVES));
+        assert_eq!(results, first);
+        assert!(tiny.resident_bytes() > 0 && tiny.resident_bytes() < store.resident_bytes());
+    }
+    tiny.load(0).unwrap();
+    assert!(tiny.load(0).unwrap().1.cache_hit);
+    tiny.load(1).unwrap();
+    assert!(!tiny.load(0).unwrap().1.cache_hit);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stores_are_validated_and_dropped_on_mutation() {
+    let dir = scratch_dir("stale");
+    let codes = dir.join("leaf_codes.bin");
+    let retriever = partitioned_retriever();
+    retriever.write_leaf_codes(&codes).unwrap();
+    let store: Arc<dyn LeafCodeStore> = Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap());
+    assert_eq!((store.num_leaves(), store.num_rows(), store.dimensionality()), (NUM_LEAVES, 2000, DIM));
+
+    let other_rows = DenseDataset::new(random_rows(100, DIM, 5), DIM);
+    let other = ScannRetriever::new(other_rows, Box::new(SquaredL2Distance::new()), 10);
+    let error = other.set_leaf_code_store(Some(store.clone())).unwrap_err();
+    assert!(error.to_string().contains("does not match the index"), "{}", error);
+    assert!(other.build_leaf_code_store().is_err());
+
+    retriever.set_leaf_code_store(Some(store)).unwrap();
+    retriever.add(&[0.0; DIM]).unwrap();
+    let (_, stats) = search(&retriever, &[0.0; DIM], NUM_LEAVES);
+    assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (0, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Stops the readers even when the writer panics, so a failure is reported
+// instead of hanging the scope.
+struct StopOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+// Compaction shrinks the rows, so codes laid out for the previous snapshot
+// would index past the end of the one a search reads.
+#[test]
+fn searches_never_pair_a_snapshot_with_another_snapshots_codes() {
+    let retriever = partitioned_retriever();
+    retriever.build_leaf_code_store().unwrap();
+    // Every docid below this has been removed and compacted away.
+    let removed_below = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let _stop = StopOnDrop(&done);
+            for docid in 0..200 {
+                retriever.remove(docid).unwrap();
+                retriever.compact().unwrap();
+                removed_below.store(docid + 1, Ordering::SeqCst);
+                retriever.build_leaf_code_store().unwrap();
+            }
+        });
+        for seed in 0..3 {
+            let (retriever, removed_below, done) = (&retriever, &removed_below, &done);
+            scope.spawn(move || {
+                let queries = random_rows(50, DIM, 10 + seed);
+                for query in queries.iter().cycle() {
+                    if done.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let below = removed_below.load(Ordering::SeqCst);
+                    let (results, _) = search(retriever, query, 1);
+                    assert!(results.iter().all(|&(docid, _)| docid >= below), "{} in {:?}", below, results);
+                }
+            });
+        }
+    });
+}
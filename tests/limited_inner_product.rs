@@ -0,0 +1,81 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LimitedInnerProductDistance: the capped-norm formula, zero vectors, and
+//! agreement between the NormCache search path and the plain kernel.
+
+use scann::distance_measures::{l2_norm, DistanceMeasure, LimitedInnerProductDistance};
+use scann::retrieval::{NormCache, ScannRetriever};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+const DIM: usize = 16;
+
+fn spread_norm_rows(n: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n)
+        .map(|_| {
+            // Norms spread over two orders of magnitude, on both sides of
+            // the query norm.
+            let scale = 0.1 + 10.0 * rng.next_f32();
+            (0..DIM).map(|_| scale * rng.next_normal()).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn distance_is_negated_dot_over_query_norm_times_capped_norm() {
+    let measure = LimitedInnerProductDistance::new();
+    let query = [3.0, 4.0]; // |q| = 5
+    // Shorter row: -dot / |q|^2.
+    assert_eq!(measure.compute_distance_f32(&query, &[1.0, 0.0]), -3.0 / 25.0);
+    // Longer row: -dot / (|q| * |x|).
+    assert_eq!(measure.compute_distance_f32(&query, &[0.0, 10.0]), -40.0 / 50.0);
+    // A row ten times as long as another in the same direction no longer
+    // scores ten times better.
+    let short = measure.compute_distance_f32(&query, &[3.0, 4.0]);
+    let long = measure.compute_distance_f32(&query, &[30.0, 40.0]);
+    assert_eq!((short, long), (-1.0, -1.0));
+}
+
+#[test]
+fn zero_queries_and_rows_score_zero() {
+    let measure = LimitedInnerProductDistance::new();
+    assert_eq!(measure.compute_distance_f32(&[0.0, 0.0], &[1.0, 2.0]), 0.0);
+    assert_eq!(measure.compute_distance_f32(&[1.0, 2.0], &[0.0, 0.0]), 0.0);
+    assert_eq!(measure.compute_distance_f32(&[0.0, 0.0], &[0.0, 0.0]), 0.0);
+    assert_eq!(measure.distance_with_norms(&[0.0, 0.0], 0.0, &[1.0, 2.0], 5f32.sqrt()), 0.0);
+}
+
+#[test]
+fn cached_norms_score_exactly_like_the_plain_kernel() {
+    let rows = spread_norm_rows(500, 1);
+    let measure = LimitedInnerProductDistance::new();
+    let dataset = DenseDataset::new(rows.clone(), DIM);
+    let norms: Vec<f32> = rows.iter().map(|row| l2_norm(row)).collect();
+    let mut out = Vec::new();
+
+    let plain = ScannRetriever::new(dataset.clone(), Box::new(LimitedInnerProductDistance::new()), 20);
+    let cached = ScannRetriever::new(dataset.clone(), Box::new(LimitedInnerProductDistance::new()), 20);
+    cached.register_derived_data(Box::new(NormCache::default())).unwrap();
+
+    for query in spread_norm_rows(20, 2) {
+        measure.one_to_many_with_norms(&query, &dataset, &norms, &mut out).unwrap();
+        for (row, &distance) in rows.iter().zip(&out) {
+            assert_eq!(distance, measure.compute_distance_f32(&query, row));
+        }
+        let query = DatapointPtr::new(query);
+        assert_eq!(cached.search(&query).unwrap(), plain.search(&query).unwrap());
+    }
+    assert!(measure.one_to_many_with_norms(&rows[0], &dataset, &norms[1..], &mut out).is_err());
+}
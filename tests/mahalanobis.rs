@@ -0,0 +1,117 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mahalanobis distance: reduction to squared L2 under the identity,
+//! precision estimation, and validation of shapes and inputs.
+
+mod common;
+
+use common::random_rows;
+use nalgebra::DMatrix;
+use scann::distance_measures::{self, DistanceMeasure, MahalanobisDistance, SquaredL2Distance};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset};
+
+#[test]
+fn identity_precision_reduces_to_squared_l2() {
+    let measure = MahalanobisDistance::new(DMatrix::identity(7, 7)).unwrap();
+    let l2 = SquaredL2Distance::new();
+    let rows = random_rows(40, 7, 1);
+    for pair in rows.chunks(2) {
+        let expected = l2.compute_distance_f32(&pair[0], &pair[1]);
+        let distance = measure.compute_distance_f32(&pair[0], &pair[1]);
+        assert!((distance - expected).abs() <= 1e-5 * expected.max(1.0), "{} vs {}", distance, expected);
+        assert_eq!(measure.try_distance(&pair[0], &pair[1]).unwrap(), distance);
+        let typed = measure.compute_distance(&DatapointPtr::new(pair[0].clone()), &DatapointPtr::new(pair[1].clone()));
+        assert_eq!(typed, distance);
+    }
+
+    // Neighbours match a squared L2 retriever.
+    let dataset = DenseDataset::new(random_rows(300, 7, 2), 7);
+    let by_mahalanobis = ScannRetriever::new(dataset.clone(), Box::new(measure), 5);
+    let by_l2 = ScannRetriever::new(dataset, Box::new(l2), 5);
+    for query in random_rows(10, 7, 3) {
+        let query = DatapointPtr::new(query);
+        let ids = |results: Vec<(usize, f32)>| results.into_iter().map(|(docid, _)| docid).collect::<Vec<_>>();
+        assert_eq!(ids(by_mahalanobis.search(&query).unwrap()), ids(by_l2.search(&query).unwrap()));
+    }
+}
+
+#[test]
+fn general_precision_matches_the_quadratic_form() {
+    // P = [[2, 1], [1, 3]], a - b = (1, -2): 2 - 2 - 2 + 12 = 10.
+    let precision = DMatrix::from_row_slice(2, 2, &[2.0, 1.0, 1.0, 3.0]);
+    let measure = MahalanobisDistance::new(precision.clone()).unwrap();
+    assert_eq!(measure.precision(), &precision);
+    assert_eq!(measure.compute_distance_f32(&[3.0, 0.0], &[2.0, 2.0]), 10.0);
+    assert_eq!(measure.compute_distance_f32(&[2.0, 2.0], &[3.0, 0.0]), 10.0);
+    assert_eq!(measure.compute_distance_f32(&[5.0, -1.0], &[5.0, -1.0]), 0.0);
+    assert_eq!(measure.name(), "MahalanobisDistance");
+}
+
+#[test]
+fn estimated_precision_inverts_the_sample_covariance() {
+    // Correlated columns with different scales.
+    let rows: Vec<Vec<f32>> = random_rows(2000, 3, 4)
+        .into_iter()
+        .map(|z| vec![z[0] + 5.0, 2.0 * z[0] + z[1], 0.5 * z[2] - z[1]])
+        .collect();
+    let data = DenseDataset::new(rows.clone(), 3);
+    let precision = distance_measures::estimate_precision(&data, 0.0).unwrap();
+
+    let n = rows.len() as f64;
+    let mean: Vec<f64> = (0..3).map(|d| rows.iter().map(|r| r[d] as f64).sum::<f64>() / n).collect();
+    let covariance = DMatrix::from_fn(3, 3, |i, j| {
+        rows.iter().map(|r| (r[i] as f64 - mean[i]) * (r[j] as f64 - mean[j])).sum::<f64>() / (n - 1.0)
+    });
+    let product = precision.map(|v| v as f64) * &covariance;
+    assert!((product - DMatrix::<f64>::identity(3, 3)).abs().max() < 1e-4, "{}", precision);
+    assert!((&precision - precision.transpose()).abs().max() == 0.0);
+
+    // A ridge shrinks the precision towards zero.
+    let ridged = distance_measures::estimate_precision(&data, 10.0).unwrap();
+    assert!(ridged.trace() < precision.trace());
+}
+
+#[test]
+fn singular_covariance_needs_a_ridge() {
+    // The third column repeats the first.
+    let rows: Vec<Vec<f32>> = random_rows(50, 2, 5).into_iter().map(|r| vec![r[0], r[1], r[0]]).collect();
+    let data = DenseDataset::new(rows, 3);
+    let error = distance_measures::estimate_precision(&data, 0.0).unwrap_err();
+    assert!(error.to_string().contains("singular"), "{}", error);
+    let precision = distance_measures::estimate_precision(&data, 0.1).unwrap();
+    assert!(precision.iter().all(|v| v.is_finite()));
+}
+
+#[test]
+fn bad_shapes_and_inputs_are_rejected() {
+    for precision in [DMatrix::zeros(2, 3), DMatrix::zeros(0, 0)] {
+        let error = MahalanobisDistance::new(precision).err().unwrap();
+        assert!(error.to_string().contains("must be square and non-empty"), "{}", error);
+    }
+    let measure = MahalanobisDistance::new(DMatrix::identity(3, 3)).unwrap();
+    assert!(measure.try_distance(&[1.0, 2.0], &[1.0, 2.0]).is_err());
+    assert!(measure.try_distance(&[1.0, 2.0, 3.0], &[1.0, 2.0]).is_err());
+    assert!(measure.check_dimensionality(3).is_ok());
+    assert!(measure.check_dimensionality(4).is_err());
+
+    let one_row = DenseDataset::new(vec![vec![1.0, 2.0]], 2);
+    assert!(distance_measures::estimate_precision(&one_row, 1.0).is_err());
+    let data = DenseDataset::new(random_rows(10, 2, 6), 2);
+    for ridge in [-1.0, f32::NAN] {
+        let error = distance_measures::estimate_precision(&data, ridge).unwrap_err();
+        assert!(error.to_string().contains("Ridge must be >= 0"), "{}", error);
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MaintenanceRunner scheduling, failure and panic isolation, and shutdown.
+
+use scann::maintenance::{MaintenanceRunner, PeriodicTask};
+use scann::util;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn counting_task(name: &str, runs: &Arc<AtomicUsize>) -> PeriodicTask {
+    let runs = runs.clone();
+    PeriodicTask::new(
+        name,
+        Duration::from_millis(2),
+        Box::new(move || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }),
+    )
+}
+
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn tasks_repeat_until_shutdown_and_never_after() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut runner = MaintenanceRunner::start(vec![counting_task("count", &runs)]).unwrap();
+    wait_for(|| runs.load(Ordering::SeqCst) >= 5);
+
+    let start = Instant::now();
+    runner.shutdown();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    let after_shutdown = runs.load(Ordering::SeqCst);
+    assert_eq!(runner.stats()[0].runs as usize, after_shutdown);
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(runs.load(Ordering::SeqCst), after_shutdown);
+    // Idempotent, and drop after shutdown is a no-op.
+    runner.shutdown();
+}
+
+#[test]
+fn failures_and_panics_are_recorded_without_stopping_other_tasks() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let flaky = {
+        let attempts = attempts.clone();
+        PeriodicTask::new(
+            "flaky",
+            Duration::from_millis(2),
+            Box::new(move || match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(util::invalid_argument_error("first run fails")),
+                1 => panic!("second run panics"),
+                _ => Ok(()),
+            }),
+        )
+    };
+    let runner = MaintenanceRunner::start(vec![flaky, counting_task("count", &runs)]).unwrap();
+
+    wait_for(|| runner.stats()[0].panics == 1);
+    let stats = runner.stats();
+    assert_eq!((stats[0].failures, stats[0].panics), (1, 1));
+    assert_eq!(stats[0].last_error.as_deref(), Some("panicked: second run panics"));
+
+    // The next clean run clears the error; the other task kept running.
+    wait_for(|| runner.stats()[0].runs >= 3);
+    assert_eq!(runner.stats()[0].last_error, None);
+    wait_for(|| runs.load(Ordering::SeqCst) >= 3);
+}
+
+#[test]
+fn a_zero_interval_is_rejected() {
+    let task = PeriodicTask::new("busy", Duration::ZERO, Box::new(|| Ok(())));
+    assert!(MaintenanceRunner::start(vec![task]).is_err());
+}
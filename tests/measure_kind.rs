@@ -0,0 +1,115 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! DistanceMeasureKind: name resolution, bit-identical values to the boxed
+//! measures, and retrievers that score through the enum.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{self, DistanceMeasureKind};
+use scann::retrieval::{RebuildPlan, ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset, RowBlock, SplitMix64};
+
+const DIM: usize = 12;
+
+const KINDS: [DistanceMeasureKind; 12] = [
+    DistanceMeasureKind::DotProduct,
+    DistanceMeasureKind::AbsDotProduct,
+    DistanceMeasureKind::SquaredL2,
+    DistanceMeasureKind::NegatedSquaredL2,
+    DistanceMeasureKind::L2,
+    DistanceMeasureKind::L1,
+    DistanceMeasureKind::Cosine,
+    DistanceMeasureKind::NormalizedDotProduct,
+    DistanceMeasureKind::GeneralHamming,
+    DistanceMeasureKind::GeneralJaccard,
+    DistanceMeasureKind::BinaryJaccard,
+    DistanceMeasureKind::LimitedInnerProduct,
+];
+
+// Small non-negative integers, so the Hamming and Jaccard measures see
+// repeated and zero values alongside the real-valued rows.
+fn count_rows(n: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n).map(|_| (0..DIM).map(|_| rng.next_below(3) as f32).collect()).collect()
+}
+
+#[test]
+fn names_resolve_to_kinds_and_back() {
+    for kind in KINDS {
+        assert_eq!(DistanceMeasureKind::from_name(kind.name()), Some(kind));
+        assert_eq!(distance_measures::get_distance_measure_kind(kind.name()).unwrap(), kind);
+        assert_eq!(kind.to_measure().name(), kind.name());
+    }
+    // Parameterized and unknown measures have no kind.
+    for name in ["MinkowskiDistance", "WeightedSquaredL2Distance", "NoSuchDistance", ""] {
+        assert_eq!(DistanceMeasureKind::from_name(name), None);
+        let error = distance_measures::get_distance_measure_kind(name).unwrap_err().to_string();
+        assert!(error.contains("is not a built-in distance measure with static dispatch"), "{}", error);
+    }
+}
+
+#[test]
+fn kinds_return_the_boxed_values_bit_for_bit() {
+    for rows in [random_rows(40, DIM, 1), count_rows(40, 2)] {
+        let query = &rows[0];
+        for kind in KINDS {
+            let measure = kind.to_measure();
+            let single: Vec<u32> = rows.iter().map(|row| measure.compute_distance_f32(query, row).to_bits()).collect();
+            let dispatched: Vec<u32> = rows.iter().map(|row| kind.compute(query, row).to_bits()).collect();
+            assert_eq!(dispatched, single, "{:?}", kind);
+
+            let mut batched = vec![0.0f32; rows.len()];
+            kind.compute_one_to_many_rows(query, RowBlock::Rows(&rows), &mut batched);
+            let mut boxed = vec![0.0f32; rows.len()];
+            measure.compute_one_to_many_rows(query, RowBlock::Rows(&rows), &mut boxed);
+            let bits = |values: &[f32]| values.iter().map(|d| d.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&batched), bits(&boxed), "{:?} one-to-many", kind);
+        }
+    }
+}
+
+#[test]
+fn retrievers_built_from_a_kind_search_like_boxed_ones() {
+    let rows = random_rows(400, DIM, 3);
+    for kind in KINDS {
+        let by_kind = ScannRetriever::with_measure_kind(DenseDataset::new(rows.clone(), DIM), kind, 10);
+        let boxed = ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), kind.to_measure(), 10);
+        assert_eq!(by_kind.measure_kind(), Some(kind));
+        assert_eq!(boxed.measure_kind(), None);
+        // k = 10 takes the fused scan over 400 rows; k = 100 the blocked one.
+        for k in [10, 100] {
+            let options = SearchOptions {
+                k: Some(k),
+                ..SearchOptions::default()
+            };
+            for query in random_rows(3, DIM, 4) {
+                let query = DatapointPtr::new(query);
+                let expected = boxed.search_with_options(&query, &options).unwrap().0;
+                assert_eq!(by_kind.search_with_options(&query, &options).unwrap().0, expected, "{:?} k {}", kind, k);
+            }
+        }
+    }
+}
+
+#[test]
+fn rebuilt_replicas_keep_the_kind() {
+    let dataset = DenseDataset::new(random_rows(100, DIM, 5), DIM);
+    let retriever = ScannRetriever::with_measure_kind(dataset, DistanceMeasureKind::L1, 10);
+    let replica = retriever.rebuild_with(&RebuildPlan { k: Some(3), ..RebuildPlan::default() }).unwrap();
+    assert_eq!(replica.measure_kind(), Some(DistanceMeasureKind::L1));
+    let query = DatapointPtr::new(random_rows(1, DIM, 6).remove(0));
+    assert_eq!(replica.search(&query).unwrap(), retriever.search(&query).unwrap()[..3]);
+}
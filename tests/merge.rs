@@ -0,0 +1,137 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merging per-region artifacts directories into one searchable index.
+
+mod common;
+
+use common::scratch_dir;
+use scann::artifacts::{self, ArtifactsConfig, MergeConfig};
+use scann::leaf_codes::{FileLeafCodeStore, LeafCodeStore};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset, Normalization, SplitMix64};
+use std::path::Path;
+
+const DIM: usize = 6;
+
+fn config() -> ArtifactsConfig {
+    ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: Normalization::None,
+        dimensionality: DIM,
+    }
+}
+
+// Rows around `center` in every dimension, with docids 0..n.
+fn save_region(dir: &Path, n: usize, center: f32, seed: u64) -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(seed);
+    let rows = (0..n).map(|_| (0..DIM).map(|_| center + rng.next_normal()).collect()).collect();
+    let dataset = DenseDataset::new(rows, DIM);
+    artifacts::save_artifacts(dir, &config(), &dataset, &(0..n).collect::<Vec<_>>()).unwrap();
+    dataset
+}
+
+#[test]
+fn every_source_docid_is_findable_in_the_merged_index() {
+    let root = scratch_dir("findable");
+    let (west, east, merged) = (root.join("west"), root.join("east"), root.join("merged"));
+    let west_rows = save_region(&west, 300, -5.0, 1);
+    let east_rows = save_region(&east, 200, 5.0, 2);
+
+    let mut merge_config = MergeConfig::new();
+    merge_config.docid_offsets = vec![0, 1_000_000];
+    merge_config.num_leaves = Some(6);
+    merge_config.training_options.max_iterations = 5;
+    merge_config.int8_codes = Some(Int8QuantizationConfig::new());
+    let merged_config = artifacts::merge_artifacts(&[&west, &east], &merged, &merge_config).unwrap();
+    assert_eq!(merged_config, config());
+    let provenance = std::fs::read_to_string(merged.join("provenance.txt")).unwrap();
+    assert!(provenance.contains("rows: 0..300 docid_offset: 0"), "{}", provenance);
+    assert!(provenance.contains("rows: 300..500 docid_offset: 1000000"), "{}", provenance);
+
+    let codes = FileLeafCodeStore::open(merged.join("leaf_codes.bin"), 1 << 20).unwrap();
+    assert_eq!((codes.num_leaves(), codes.num_rows()), (6, 500));
+
+    let loaded = artifacts::load_artifacts(&merged).unwrap();
+    assert_eq!(loaded.dataset.size(), 500);
+    assert_eq!(loaded.tree.as_ref().map(|tree| tree.num_leaves()), Some(6));
+    let retriever = ScannRetriever::from_artifacts(loaded, 1).unwrap();
+    let options = SearchOptions { leaves_to_search: Some(2), ..SearchOptions::default() };
+    let sources = [(&west_rows, 0), (&east_rows, 1_000_000)];
+    for (rows, offset) in sources {
+        for (i, row) in rows.data.iter().enumerate() {
+            let docid = offset + i;
+            assert_eq!(retriever.get_by_docid(docid).as_deref(), Some(&row[..]), "docid {}", docid);
+            let (results, _) = retriever.search_with_options(&DatapointPtr::new(row.clone()), &options).unwrap();
+            assert_eq!(results, vec![(docid, 0.0)]);
+        }
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn colliding_docids_need_offsets() {
+    let root = scratch_dir("collide");
+    let (a, b) = (root.join("a"), root.join("b"));
+    save_region(&a, 20, 0.0, 3);
+    save_region(&b, 20, 1.0, 4);
+    let error = artifacts::merge_artifacts(&[&a, &b], root.join("out"), &MergeConfig::new()).unwrap_err();
+    assert!(error.to_string().contains("Docid 0 from"), "{}", error);
+    assert!(error.to_string().contains("set docid_offsets"), "{}", error);
+
+    let mut merge_config = MergeConfig::new();
+    merge_config.docid_offsets = vec![0];
+    let error = artifacts::merge_artifacts(&[&a, &b], root.join("out"), &merge_config).unwrap_err();
+    assert!(error.to_string().contains("Got 1 docid offsets for 2 sources"), "{}", error);
+
+    let mut merge_config = MergeConfig::new();
+    merge_config.int8_codes = Some(Int8QuantizationConfig::new());
+    merge_config.docid_offsets = vec![0, 100];
+    let error = artifacts::merge_artifacts(&[&a, &b], root.join("out"), &merge_config).unwrap_err();
+    assert!(error.to_string().contains("require num_leaves"), "{}", error);
+    assert!(artifacts::merge_artifacts(&[], root.join("out"), &MergeConfig::new()).is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn conflicting_configs_are_rejected_by_field() {
+    let root = scratch_dir("conflict");
+    let base = root.join("base");
+    save_region(&base, 10, 0.0, 5);
+    let dataset = DenseDataset::new(vec![vec![1.0; DIM]; 10], DIM);
+    let docids: Vec<usize> = (100..110).collect();
+    let conflicts = [
+        (
+            "measure",
+            ArtifactsConfig { distance_measure: "DotProductDistance".to_string(), ..config() },
+            "distance_measure",
+        ),
+        ("normalized", ArtifactsConfig { normalization: Normalization::UnitL2, ..config() }, "normalization"),
+    ];
+    for (name, other_config, field) in conflicts {
+        let other = root.join(name);
+        artifacts::save_artifacts(&other, &other_config, &dataset, &docids).unwrap();
+        let error = artifacts::merge_artifacts(&[&base, &other], root.join("out"), &MergeConfig::new()).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains(&format!("{} is", field)) && message.contains(name), "{}", message);
+    }
+    let narrow = root.join("narrow");
+    let narrow_rows = DenseDataset::new(vec![vec![1.0; 3]; 10], 3);
+    artifacts::save_artifacts(&narrow, &ArtifactsConfig { dimensionality: 3, ..config() }, &narrow_rows, &docids)
+        .unwrap();
+    let error = artifacts::merge_artifacts(&[&base, &narrow], root.join("out"), &MergeConfig::new()).unwrap_err();
+    assert!(error.to_string().contains("dimensionality is 3 but the first source has 6"), "{}", error);
+    std::fs::remove_dir_all(&root).unwrap();
+}
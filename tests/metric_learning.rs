@@ -0,0 +1,127 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Learning diagonal metric weights from triples on synthetic data with
+//! informative and noise dimensions.
+
+use scann::distance_measures::{self, DistanceMeasure};
+use scann::metric_learning::{self, DiagonalFitOptions, Triple};
+use scann::proto::DistanceMeasureConfig;
+use scann::util::{DenseDataset, SplitMix64};
+
+const INFORMATIVE: usize = 4;
+const NOISE: usize = 4;
+const DIM: usize = INFORMATIVE + NOISE;
+
+// Class means differ only in the informative dimensions, by 2 per
+// dimension, and the noise dimensions have much larger spread, so the
+// unweighted metric is dominated by noise.
+fn synthetic(seed: u64) -> (DenseDataset<f32>, Vec<Triple>) {
+    let mut rng = SplitMix64::new(seed);
+    let classes = 4;
+    let mut rows = Vec::new();
+    let mut labels = Vec::new();
+    for i in 0..400 {
+        let class = i % classes;
+        let row: Vec<f32> = (0..DIM)
+            .map(|d| {
+                if d < INFORMATIVE {
+                    2.0 * ((class >> (d % 2)) & 1) as f32 + 0.3 * rng.next_normal()
+                } else {
+                    3.0 * rng.next_normal()
+                }
+            })
+            .collect();
+        rows.push(row);
+        labels.push(class);
+    }
+    let mut triples = Vec::new();
+    while triples.len() < 600 {
+        let (query, positive, negative) = (rng.next_below(400), rng.next_below(400), rng.next_below(400));
+        if query != positive && labels[query] == labels[positive] && labels[query] != labels[negative] {
+            triples.push(Triple { query, positive, negative });
+        }
+    }
+    (DenseDataset::new(rows, DIM), triples)
+}
+
+#[test]
+fn noise_dimensions_are_driven_to_zero_and_accuracy_improves() {
+    let (dataset, triples) = synthetic(1);
+    let fit = metric_learning::fit_diagonal(&dataset, &triples, &DiagonalFitOptions::new()).unwrap();
+
+    let informative_min = fit.weights[..INFORMATIVE].iter().copied().fold(f32::INFINITY, f32::min);
+    let noise_max = fit.weights[INFORMATIVE..].iter().copied().fold(0.0, f32::max);
+    assert!(noise_max < 0.05 * informative_min, "{:?}", fit.weights);
+    assert!(fit.weights.iter().all(|&w| w >= 0.0));
+
+    assert!(fit.train_accuracy_after > fit.train_accuracy_before + 0.1, "{:?}", fit);
+    assert!(fit.validation_accuracy_after > fit.validation_accuracy_before + 0.1, "{:?}", fit);
+    assert!(fit.validation_accuracy_after > 0.95, "{:?}", fit);
+    assert!(fit.final_loss.is_finite());
+}
+
+#[test]
+fn learned_weights_plug_into_the_weighted_measure_and_config() {
+    let (dataset, triples) = synthetic(2);
+    let fit = metric_learning::fit_diagonal(&dataset, &triples, &DiagonalFitOptions::new()).unwrap();
+    let measure = fit.distance_measure().unwrap();
+    assert_eq!(measure.weights(), &fit.weights[..]);
+
+    // Weights survive the parameter string and build the same measure from
+    // a config.
+    let parameter = metric_learning::weights_to_parameter(&fit.weights);
+    let weights = metric_learning::weights_from_parameter(&parameter).unwrap();
+    assert_eq!(weights, fit.weights);
+    let config = DistanceMeasureConfig {
+        distance_measure: "WeightedSquaredL2Distance".to_string(),
+        weighted_l2_weights: weights,
+        ..Default::default()
+    };
+    let from_config = distance_measures::get_distance_measure(&config).unwrap();
+    let (a, b) = (&dataset.data[0], &dataset.data[1]);
+    assert_eq!(from_config.compute_distance_f32(a, b), measure.compute_distance_f32(a, b));
+
+    for bad in ["1 -2", "1 x", "inf"] {
+        let error = metric_learning::weights_from_parameter(bad).unwrap_err();
+        assert!(error.to_string().contains("Invalid metric weight"), "{}", error);
+    }
+}
+
+#[test]
+fn validation_split_and_inputs_are_checked() {
+    let (dataset, triples) = synthetic(3);
+    let mut options = DiagonalFitOptions::new();
+    options.epochs = 1;
+    options.validation_fraction = 0.0;
+    let fit = metric_learning::fit_diagonal(&dataset, &triples, &options).unwrap();
+    assert_eq!(fit.validation_accuracy_before, 0.0);
+
+    // The split is seeded: the same seed gives the same fit.
+    options.validation_fraction = 0.5;
+    let first = metric_learning::fit_diagonal(&dataset, &triples, &options).unwrap();
+    let again = metric_learning::fit_diagonal(&dataset, &triples, &options).unwrap();
+    assert_eq!(first.weights, again.weights);
+    assert_eq!(first.validation_accuracy_before, again.validation_accuracy_before);
+
+    options.validation_fraction = 1.0;
+    let error = metric_learning::fit_diagonal(&dataset, &triples, &options).unwrap_err();
+    assert!(error.to_string().contains("validation_fraction must be in [0, 1)"), "{}", error);
+    options.validation_fraction = 0.5;
+    let error = metric_learning::fit_diagonal(&dataset, &triples[..1], &options).unwrap_err();
+    assert!(error.to_string().contains("No training triples left"), "{}", error);
+    let outside = [Triple { query: 0, positive: 1, negative: 400 }];
+    let error = metric_learning::fit_diagonal(&dataset, &outside, &DiagonalFitOptions::new()).unwrap_err();
+    assert!(error.to_string().contains("outside the 400-row dataset"), "{}", error);
+}
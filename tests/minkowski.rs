@@ -0,0 +1,92 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MinkowskiDistance: construction from DistanceMeasureConfig, rejection of
+//! bad exponents, and agreement with L1, L2 and hand-computed Lp.
+
+use scann::distance_measures::{self, DistanceMeasure, L1Distance, L2Distance, MinkowskiDistance};
+use scann::proto::DistanceMeasureConfig;
+use scann::util::{DatapointPtr, ScannError, SplitMix64};
+
+fn config(p: Option<f32>) -> DistanceMeasureConfig {
+    DistanceMeasureConfig {
+        distance_measure: "MinkowskiDistance".to_string(),
+        minkowski_p: p,
+        ..DistanceMeasureConfig::default()
+    }
+}
+
+fn random_pairs(count: usize, dim: usize) -> Vec<(Vec<f32>, Vec<f32>)> {
+    let mut rng = SplitMix64::new(1);
+    let mut row = || (0..dim).map(|_| rng.next_normal()).collect::<Vec<f32>>();
+    (0..count).map(|_| (row(), row())).collect()
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= 1e-5 * b.abs().max(1.0)
+}
+
+#[test]
+fn p_one_and_two_match_l1_and_l2() {
+    let l1 = distance_measures::get_distance_measure(&config(Some(1.0))).unwrap();
+    let l2 = distance_measures::get_distance_measure(&config(Some(2.0))).unwrap();
+    assert_eq!(l1.name(), "MinkowskiDistance");
+    for (a, b) in random_pairs(50, 17) {
+        assert!(close(l1.compute_distance_f32(&a, &b), L1Distance::new().compute_distance_f32(&a, &b)));
+        assert!(close(l2.compute_distance_f32(&a, &b), L2Distance::new().compute_distance_f32(&a, &b)));
+        assert!(close(l1.compute_distance_f64_accumulated(&a, &b), l1.compute_distance_f32(&a, &b)));
+        assert!(close(l2.compute_distance_f64_accumulated(&a, &b), l2.compute_distance_f32(&a, &b)));
+    }
+}
+
+#[test]
+fn other_exponents_match_the_lp_formula() {
+    let a = [1.0, -2.0, 0.5];
+    let b = [4.0, 2.0, 0.5];
+    // Differences 3, 4, 0.
+    let cube = MinkowskiDistance::new(3.0).unwrap();
+    assert_eq!(cube.p(), 3.0);
+    assert!(close(cube.compute_distance_f32(&a, &b), 91.0f32.powf(1.0 / 3.0)));
+    let half = MinkowskiDistance::new(0.5).unwrap();
+    assert!(close(half.compute_distance_f32(&a, &b), (3.0f32.sqrt() + 2.0).powi(2)));
+    // Large p approaches the Chebyshev distance.
+    assert!((MinkowskiDistance::new(32.0).unwrap().compute_distance_f32(&a, &b) - 4.0).abs() < 0.01);
+
+    for p in [0.5, 1.5, 3.0, 7.0] {
+        let measure = MinkowskiDistance::new(p).unwrap();
+        for (a, b) in random_pairs(20, 9) {
+            let expected = a.iter().zip(&b).map(|(x, y)| ((x - y).abs() as f64).powf(p as f64)).sum::<f64>();
+            let expected = expected.powf(1.0 / p as f64) as f32;
+            assert!(close(measure.compute_distance_f32(&a, &b), expected), "p {}", p);
+            assert!(close(measure.compute_distance_f64_accumulated(&a, &b), expected), "p {}", p);
+            let typed = measure.compute_distance(&DatapointPtr::new(a.clone()), &DatapointPtr::new(b.clone()));
+            assert_eq!(typed, measure.compute_distance_f32(&a, &b));
+            assert_eq!(measure.compute_distance_f32(&a, &a), 0.0);
+        }
+    }
+}
+
+#[test]
+fn bad_exponents_are_invalid_arguments() {
+    for p in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        let error = MinkowskiDistance::new(p).err().unwrap();
+        assert!(error.downcast_ref::<ScannError>().is_some());
+        assert!(error.to_string().contains("requires a finite p > 0"), "p {}: {}", p, error);
+        assert!(distance_measures::get_distance_measure(&config(Some(p))).is_err(), "p {}", p);
+    }
+    let error = distance_measures::get_distance_measure(&config(None)).err().unwrap();
+    assert!(error.to_string().contains("requires minkowski_p"), "{}", error);
+    // The name alone carries no exponent.
+    assert!(distance_measures::get_distance_measure_by_name("MinkowskiDistance").is_err());
+}
@@ -0,0 +1,113 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{DistanceMeasure, DotProductDistance, NormalizedDotProductDistance};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::testing::datasets::{self, HeavyTailSpec};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+
+const NUM_LEAVES: usize = 16;
+
+fn partitioned(dataset: DenseDataset<f32>, measure: Box<dyn DistanceMeasure>) -> ScannRetriever {
+    let retriever = ScannRetriever::new(dataset, measure, 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 10;
+    options.seed = 7;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    retriever
+}
+
+fn all_leaves(k: usize, disable_leaf_pruning: bool) -> SearchOptions {
+    SearchOptions {
+        k: Some(k),
+        leaves_to_search: Some(NUM_LEAVES),
+        disable_leaf_pruning,
+        ..SearchOptions::default()
+    }
+}
+
+fn heavy_tailed(num_points: usize, dim: usize, seed: u64) -> DenseDataset<f32> {
+    datasets::heavy_tailed_norms(&HeavyTailSpec {
+        num_points,
+        dimensionality: dim,
+        max_norm: 100.0,
+        seed,
+    })
+    .unwrap()
+    .dataset
+}
+
+#[test]
+fn dot_product_distance_is_negated_inner_product() {
+    let a = [1.0, 2.0, 3.0];
+    let b = [4.0, -5.0, 6.0];
+    assert_eq!(DotProductDistance::new().compute_distance_f32(&a, &b), -12.0);
+    assert_eq!(
+        DotProductDistance::new().compute_distance(&DatapointPtr::new(a.to_vec()), &DatapointPtr::new(b.to_vec())),
+        -12.0
+    );
+}
+
+#[test]
+fn pruned_search_matches_unpruned_search() {
+    for (seed, dim) in [(1, 4), (2, 8), (3, 24)] {
+        let dataset = heavy_tailed(2000, dim, seed);
+        let retriever = partitioned(dataset, Box::new(DotProductDistance::new()));
+        for (q, query) in random_rows(100, dim, seed + 100).into_iter().enumerate() {
+            let query = DatapointPtr::new(query);
+            for k in [1, 10] {
+                let (pruned, _) = retriever.search_with_options(&query, &all_leaves(k, false)).unwrap();
+                let (unpruned, stats) = retriever.search_with_options(&query, &all_leaves(k, true)).unwrap();
+                assert_eq!(stats.leaves_pruned, 0);
+                assert_eq!(pruned, unpruned, "seed {} dim {} query {} k {}", seed, dim, q, k);
+            }
+        }
+    }
+}
+
+#[test]
+fn pruned_normalized_search_matches_unpruned_search() {
+    let mut dataset = heavy_tailed(1500, 8, 11);
+    dataset.normalize_rows().unwrap();
+    let retriever = partitioned(dataset, Box::new(NormalizedDotProductDistance::new()));
+    for query in random_rows(50, 8, 12) {
+        let norm = query.iter().map(|v| v * v).sum::<f32>().sqrt();
+        let query = DatapointPtr::new(query.iter().map(|v| v / norm).collect());
+        let (pruned, _) = retriever.search_with_options(&query, &all_leaves(5, false)).unwrap();
+        let (unpruned, _) = retriever.search_with_options(&query, &all_leaves(5, true)).unwrap();
+        assert_eq!(pruned, unpruned);
+    }
+}
+
+#[test]
+fn norm_skewed_data_prunes_leaves() {
+    let dim = 8;
+    let retriever = partitioned(heavy_tailed(4000, dim, 21), Box::new(DotProductDistance::new()));
+    let (mut pruned, mut searched) = (0, 0);
+    for query in random_rows(100, dim, 22) {
+        let (_, stats) = retriever
+            .search_with_options(&DatapointPtr::new(query), &all_leaves(1, false))
+            .unwrap();
+        assert_eq!(stats.leaves_pruned + stats.leaves_searched, NUM_LEAVES);
+        pruned += stats.leaves_pruned;
+        searched += stats.leaves_searched;
+    }
+    // A handful of very long rows dominate every inner product, so once
+    // they are scored many leaves cannot hold a better one.
+    assert!(4 * pruned >= pruned + searched, "pruned {} leaves, searched {}", pruned, searched);
+}
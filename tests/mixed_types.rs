@@ -0,0 +1,121 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! f32 queries against int8 rows: the mixed kernels agree with the f32
+//! kernels on dequantized rows, and int8-only retrieval ranks like f32
+//! retrieval over the same dequantized corpus.
+
+mod common;
+
+use common::random_dataset;
+use scann::distance_measures::{
+    ChebyshevDistance, CosineDistance, DistanceMeasure, DotProductDistance, SquaredL2Distance,
+};
+use scann::quantization::{quantize_int8, Int8QuantizationConfig};
+use scann::retrieval::{Int8Codes, Int8Retriever, ScannRetriever};
+use scann::util::{DatapointPtr, DenseDataset};
+
+const DIM: usize = 12;
+
+fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() <= 1e-5 * (1.0 + b.abs()), "{} vs {}", a, b);
+}
+
+fn check_measure<M: DistanceMeasure>(measure: &M) {
+    let data = random_dataset(50, DIM, 1);
+    let quantized = quantize_int8(&data, &Int8QuantizationConfig::new()).unwrap();
+    let query = random_dataset(1, DIM, 2).data.remove(0);
+    let mut scratch = Vec::new();
+    for (i, codes) in quantized.codes.data.iter().enumerate() {
+        let expected = measure.compute_distance_f32(&query, &quantized.dequantize_row(i));
+        let mixed = measure.compute_distance_mixed(
+            &DatapointPtr::new(query.clone()),
+            &DatapointPtr::new(codes.clone()),
+            Some(&quantized.multipliers),
+        );
+        assert_close(mixed, expected);
+        // The object-safe int8 entry point runs the same kernel.
+        let dynamic: &dyn DistanceMeasure = measure;
+        assert_eq!(dynamic.compute_distance_mixed_i8(&query, codes, Some(&quantized.multipliers), &mut scratch), mixed);
+
+        // Without multipliers the codes are taken as values.
+        let raw: Vec<f32> = codes.iter().map(|&c| c as f32).collect();
+        let unscaled =
+            measure.compute_distance_mixed(&DatapointPtr::new(query.clone()), &DatapointPtr::new(codes.clone()), None);
+        assert_close(unscaled, measure.compute_distance_f32(&query, &raw));
+    }
+}
+
+#[test]
+fn mixed_kernels_match_f32_kernels_on_dequantized_rows() {
+    check_measure(&SquaredL2Distance::new());
+    check_measure(&DotProductDistance::new());
+    check_measure(&CosineDistance::new());
+    // No fused kernel: falls back to dequantize-then-f32.
+    check_measure(&ChebyshevDistance::new());
+}
+
+#[test]
+fn zero_multipliers_dequantize_to_zero() {
+    let query = [1.0, 2.0, 3.0];
+    let codes: [i8; 3] = [10, 20, 30];
+    let distance = SquaredL2Distance::new().compute_distance_mixed(
+        &DatapointPtr::new(query.to_vec()),
+        &DatapointPtr::new(codes.to_vec()),
+        Some(&[10.0, 0.0, 10.0]),
+    );
+    assert_eq!(distance, 4.0);
+}
+
+#[test]
+fn int8_retrieval_ranks_like_f32_retrieval_over_dequantized_rows() {
+    let data = random_dataset(400, DIM, 3);
+    let config = Int8QuantizationConfig::new();
+    let quantized = quantize_int8(&data, &config).unwrap();
+    let dequantized = DenseDataset::new((0..data.size()).map(|i| quantized.dequantize_row(i)).collect(), DIM);
+
+    let int8 = Int8Retriever::build(&data, &config, Box::new(DotProductDistance::new()), 10).unwrap();
+    assert_eq!(int8.size(), 400);
+    assert_eq!(int8.dataset().data, quantized.codes.data);
+    let f32_retriever = ScannRetriever::new(dequantized, Box::new(DotProductDistance::new()), 10);
+    let hybrid = ScannRetriever::new(data, Box::new(DotProductDistance::new()), 10);
+    hybrid.register_derived_data(Box::new(Int8Codes::new(config))).unwrap();
+
+    for query in random_dataset(20, DIM, 4).data {
+        let results = int8.search(&query).unwrap();
+        assert_eq!(results.len(), 10);
+        let expected = f32_retriever.search(&DatapointPtr::new(query.clone())).unwrap();
+        let ids: Vec<usize> = results.iter().map(|r| r.0).collect();
+        let expected_ids: Vec<usize> = expected.iter().map(|r| r.0).collect();
+        assert_eq!(ids, expected_ids);
+        for (&(_, a), &(_, b)) in results.iter().zip(&expected) {
+            assert_close(a, b);
+        }
+        assert_eq!(hybrid.search_int8_codes(&query, 10).unwrap(), results);
+    }
+}
+
+#[test]
+fn int8_retrieval_rejects_queries_of_the_wrong_dimensionality() {
+    let int8 = Int8Retriever::build(
+        &random_dataset(10, DIM, 5),
+        &Int8QuantizationConfig::new(),
+        Box::new(SquaredL2Distance::new()),
+        3,
+    )
+    .unwrap();
+    let error = int8.search(&[0.0; DIM - 1]).unwrap_err();
+    assert!(error.to_string().contains("dimensionality"), "{}", error);
+    assert_eq!(int8.search_top_k(&[0.0; DIM], 100).unwrap().len(), 10);
+}
@@ -0,0 +1,114 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Near-tied distances grouped by epsilon_tie_threshold and ordered by docid.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+
+// Docids 0..3 sit one ulp apart just above 1.0, in decreasing order, so
+// their squared distances from the origin differ by about 2.4e-7 and rank
+// in reverse docid order. Docid 4 is far away.
+fn retriever() -> ScannRetriever {
+    let one_ulp = f32::EPSILON;
+    let rows = vec![
+        vec![1.0 + 3.0 * one_ulp],
+        vec![1.0 + 2.0 * one_ulp],
+        vec![1.0 + one_ulp],
+        vec![1.0],
+        vec![2.0],
+    ];
+    ScannRetriever::new(DenseDataset::new(rows, 1), Box::new(SquaredL2Distance::new()), 5)
+}
+
+fn order(retriever: &ScannRetriever, epsilon: f32) -> Vec<usize> {
+    let options = SearchOptions {
+        epsilon_tie_threshold: epsilon,
+        ..SearchOptions::default()
+    };
+    let (results, _) = retriever.search_with_options(&DatapointPtr::new(vec![0.0]), &options).unwrap();
+    assert!(results.windows(2).all(|w| w[0].1 <= w[1].1 + epsilon));
+    results.iter().map(|r| r.0).collect()
+}
+
+#[test]
+fn zero_epsilon_keeps_distance_order() {
+    let retriever = retriever();
+    let plain = retriever.search(&DatapointPtr::new(vec![0.0])).unwrap();
+    assert_eq!(plain.iter().map(|r| r.0).collect::<Vec<_>>(), vec![3, 2, 1, 0, 4]);
+    assert!(plain.windows(2).all(|w| w[0].1 < w[1].1), "{:?}", plain);
+    assert_eq!(order(&retriever, 0.0), vec![3, 2, 1, 0, 4]);
+}
+
+#[test]
+fn threshold_groups_near_ties_by_docid() {
+    let retriever = retriever();
+    assert_eq!(order(&retriever, 1e-6), vec![0, 1, 2, 3, 4]);
+    // Runs are anchored on their first member rather than chained: with a
+    // threshold of about two gaps only docids 3, 2 and 1 group together.
+    assert_eq!(order(&retriever, 5e-7), vec![1, 2, 3, 0, 4]);
+    assert_eq!(order(&retriever, 3e-7), vec![2, 3, 0, 1, 4]);
+    // Far rows never join a run.
+    assert_eq!(order(&retriever, 0.5), vec![0, 1, 2, 3, 4]);
+    assert_eq!(order(&retriever, 5.0), vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn grouping_is_deterministic_across_repeated_searches() {
+    let retriever = retriever();
+    let first = order(&retriever, 3e-7);
+    for _ in 0..10 {
+        assert_eq!(order(&retriever, 3e-7), first);
+    }
+    let fork = retriever.fork().unwrap();
+    assert_eq!(order(&fork, 3e-7), first);
+}
+
+// The same near-tied docids 0..3 among enough far rows that k = 2 would take
+// the fused top-k scan.
+fn large_retriever() -> ScannRetriever {
+    let one_ulp = f32::EPSILON;
+    let mut rows = vec![vec![1.0 + 3.0 * one_ulp], vec![1.0 + 2.0 * one_ulp], vec![1.0 + one_ulp], vec![1.0]];
+    rows.extend((0..100).map(|i| vec![10.0 + i as f32]));
+    ScannRetriever::new(DenseDataset::new(rows, 1), Box::new(SquaredL2Distance::new()), 2)
+}
+
+fn top_k(retriever: &ScannRetriever, k: usize, epsilon: f32) -> Vec<usize> {
+    let options = SearchOptions {
+        k: Some(k),
+        epsilon_tie_threshold: epsilon,
+        ..SearchOptions::default()
+    };
+    let (results, _) = retriever.search_with_options(&DatapointPtr::new(vec![0.0]), &options).unwrap();
+    results.iter().map(|r| r.0).collect()
+}
+
+#[test]
+fn runs_straddling_the_top_k_boundary_keep_their_lowest_docids() {
+    let retriever = large_retriever();
+    // Plain distance order takes the two closest rows.
+    assert_eq!(top_k(&retriever, 2, 0.0), vec![3, 2]);
+    // With the run 3, 2, 1, 0 tied, the boundary falls inside it and the
+    // lowest docids win, whichever side of k they were scored on.
+    assert_eq!(top_k(&retriever, 2, 1e-6), vec![0, 1]);
+    assert_eq!(top_k(&retriever, 3, 1e-6), vec![0, 1, 2]);
+    let all = top_k(&retriever, 6, 1e-6);
+    assert_eq!(all[..4], [0, 1, 2, 3]);
+    for k in 1..=6 {
+        assert_eq!(top_k(&retriever, k, 1e-6), all[..k], "k {}", k);
+        assert_eq!(top_k(&retriever.fork().unwrap(), k, 1e-6), all[..k], "fork, k {}", k);
+    }
+}
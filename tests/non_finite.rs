@@ -0,0 +1,109 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! NaN and Inf rows under each NonFiniteHandling policy.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, NonFiniteHandling};
+
+// Rows 0..20 on a line; rows 3 and 7 hold NaN and +Inf.
+fn rows() -> Vec<Vec<f32>> {
+    let mut rows: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, 0.0]).collect();
+    rows[3][1] = f32::NAN;
+    rows[7][0] = f32::INFINITY;
+    rows
+}
+
+fn retriever(policy: NonFiniteHandling) -> ScannRetriever {
+    ScannRetriever::try_new(DenseDataset::new(rows(), 2), Box::new(SquaredL2Distance::new()), 20, policy).unwrap()
+}
+
+fn search(retriever: &ScannRetriever, options: &SearchOptions) -> (Vec<(usize, f32)>, usize, usize) {
+    let (results, stats) = retriever.search_with_options(&DatapointPtr::new(vec![5.0, 0.0]), options).unwrap();
+    assert!(results.iter().all(|(_, d)| !d.is_nan()), "{:?}", results);
+    assert!(results.windows(2).all(|w| w[0].1 <= w[1].1), "{:?}", results);
+    (results, stats.non_finite_skipped, stats.non_finite_clamped)
+}
+
+#[test]
+fn reject_at_ingest_is_the_default_and_refuses_non_finite_rows() {
+    assert_eq!(NonFiniteHandling::default(), NonFiniteHandling::RejectAtIngest);
+    let message = ScannRetriever::try_new(
+        DenseDataset::new(rows(), 2),
+        Box::new(SquaredL2Distance::new()),
+        5,
+        NonFiniteHandling::RejectAtIngest,
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(message.contains("Row 3: Non-finite value NaN at dimension 1"), "{}", message);
+
+    let clean = DenseDataset::new((0..5).map(|i| vec![i as f32, 0.0]).collect(), 2);
+    let measure = Box::new(SquaredL2Distance::new());
+    let retriever = ScannRetriever::try_new(clean, measure, 5, NonFiniteHandling::RejectAtIngest).unwrap();
+    let message = retriever.add(&[f32::NEG_INFINITY, 0.0]).unwrap_err().to_string();
+    assert!(message.contains("Non-finite value -inf at dimension 0"), "{}", message);
+    assert!(retriever.upsert(1, &[0.0, f32::NAN]).is_err());
+    assert_eq!(retriever.num_active(), 5);
+    assert_eq!(retriever.get_by_docid(1).unwrap(), vec![1.0, 0.0]);
+}
+
+#[test]
+fn skip_at_search_never_returns_non_finite_rows() {
+    let retriever = retriever(NonFiniteHandling::SkipAtSearch);
+    let added = retriever.add(&[f32::NAN, f32::NAN]).unwrap();
+    let (results, skipped, clamped) = search(&retriever, &SearchOptions::default());
+    assert_eq!((results.len(), skipped, clamped), (18, 3, 0));
+    assert!(results.iter().all(|&(docid, _)| docid != 3 && docid != 7 && docid != added));
+    assert_eq!(results[0], (5, 0.0));
+    // Stored as-is.
+    assert!(retriever.get_by_docid(3).unwrap()[1].is_nan());
+}
+
+#[test]
+fn clamp_replaces_values_and_distances_with_finite_sentinels() {
+    let retriever = retriever(NonFiniteHandling::Clamp);
+    assert_eq!(retriever.get_by_docid(3).unwrap(), vec![3.0, f32::MAX]);
+    assert_eq!(retriever.get_by_docid(7).unwrap(), vec![f32::MAX, 0.0]);
+    let added = retriever.add(&[f32::NEG_INFINITY, 0.0]).unwrap();
+    assert_eq!(retriever.get_by_docid(added).unwrap(), vec![-f32::MAX, 0.0]);
+
+    let options = SearchOptions {
+        k: Some(21),
+        ..SearchOptions::default()
+    };
+    let (results, skipped, clamped) = search(&retriever, &options);
+    assert_eq!((results.len(), skipped, clamped), (21, 0, 3));
+    // The clamped rows rank last at distance f32::MAX, ties by docid.
+    let tail: Vec<(usize, f32)> = results[18..].to_vec();
+    assert_eq!(tail, vec![(3, f32::MAX), (7, f32::MAX), (added, f32::MAX)]);
+}
+
+#[test]
+fn partitioned_searches_skip_non_finite_distances_without_panicking() {
+    let retriever = retriever(NonFiniteHandling::SkipAtSearch);
+    retriever.build_partitions(2, &KMeansTreeTrainingOptions::new()).unwrap();
+    let options = SearchOptions {
+        k: Some(5),
+        leaves_to_search: Some(2),
+        ..SearchOptions::default()
+    };
+    let (results, _, _) = search(&retriever, &options);
+    assert_eq!(results.iter().map(|r| r.1).collect::<Vec<_>>(), vec![0.0, 1.0, 1.0, 9.0, 9.0]);
+    assert!(results.iter().all(|&(docid, _)| docid != 3 && docid != 7));
+}
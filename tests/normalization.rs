@@ -0,0 +1,126 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Incremental adds and upserts under UnitL2 normalization.
+
+use scann::distance_measures::{DotProductDistance, SquaredL2Distance};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, NormCache, ScannRetriever};
+use scann::util::{DatapointPtr, DenseDataset, Normalization, SplitMix64};
+use std::collections::BTreeMap;
+
+const DIM: usize = 6;
+
+fn random_vector(rng: &mut SplitMix64) -> Vec<f32> {
+    // Norms well away from 1 so a missed normalization shows.
+    let scale = 0.2 + 5.0 * rng.next_f32();
+    (0..DIM).map(|_| scale * rng.next_normal()).collect()
+}
+
+fn normalized_retriever(rows: Vec<Vec<f32>>) -> ScannRetriever {
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(DotProductDistance::new()), 8);
+    retriever.register_derived_data(Box::new(NormCache::default())).unwrap();
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever.with_normalization(Normalization::UnitL2).unwrap()
+}
+
+// Cosine top-k from a retriever rebuilt over the raw vectors in `model`,
+// mapped back to the model's docids.
+fn rebuilt_results(model: &BTreeMap<usize, Vec<f32>>, query: &[f32]) -> Vec<(usize, f32)> {
+    let docids: Vec<usize> = model.keys().copied().collect();
+    let rebuilt = normalized_retriever(model.values().cloned().collect());
+    let results = rebuilt.search(&DatapointPtr::new(query.to_vec())).unwrap();
+    results.into_iter().map(|(index, distance)| (docids[index], distance)).collect()
+}
+
+#[test]
+fn interleaved_mutations_match_a_from_scratch_rebuild() {
+    let mut rng = SplitMix64::new(31);
+    let initial: Vec<Vec<f32>> = (0..50).map(|_| random_vector(&mut rng)).collect();
+    let retriever = normalized_retriever(initial.clone());
+    let mut model: BTreeMap<usize, Vec<f32>> = initial.into_iter().enumerate().collect();
+    retriever.debug_validate_normalization(1e-5).unwrap();
+
+    for step in 0..120 {
+        match step % 3 {
+            0 => {
+                let values = random_vector(&mut rng);
+                let docid = retriever.add(&values).unwrap();
+                model.insert(docid, values);
+            }
+            1 => {
+                let docid = *model.keys().nth(rng.next_below(model.len())).unwrap();
+                let values = random_vector(&mut rng);
+                retriever.upsert(docid, &values).unwrap();
+                model.insert(docid, values);
+            }
+            _ => {
+                let query = random_vector(&mut rng);
+                let results = retriever.search(&DatapointPtr::new(query.clone())).unwrap();
+                let expected = rebuilt_results(&model, &query);
+                let docids = |r: &[(usize, f32)]| r.iter().map(|&(d, _)| d).collect::<Vec<_>>();
+                assert_eq!(docids(&results), docids(&expected), "step {}", step);
+                for (actual, expected) in results.iter().zip(&expected) {
+                    assert!((actual.1 - expected.1).abs() < 1e-5, "step {}: {:?} vs {:?}", step, actual, expected);
+                }
+            }
+        }
+        retriever.debug_validate_normalization(1e-5).unwrap();
+        // Int8 codes were computed from the normalized vectors. Updated rows
+        // keep the build-time scale and may clip slightly, but codes of a raw
+        // vector with norm up to ~15 would be far off.
+        let error = retriever.int8_error_summary().unwrap();
+        assert!(error.mean_abs_error < 0.01 && error.max_abs_error < 0.5, "step {}: {:?}", step, error);
+    }
+
+    for (&docid, raw) in &model {
+        let stored = retriever.get_by_docid(docid).unwrap();
+        let norm = raw.iter().map(|v| v * v).sum::<f32>().sqrt();
+        for (s, r) in stored.iter().zip(raw) {
+            assert!((s - r / norm).abs() < 1e-6, "docid {}", docid);
+        }
+    }
+}
+
+#[test]
+fn vectors_without_a_direction_are_rejected() {
+    let mut rng = SplitMix64::new(4);
+    let retriever = normalized_retriever((0..5).map(|_| random_vector(&mut rng)).collect());
+    let message = retriever.add(&[0.0; DIM]).unwrap_err().to_string();
+    assert!(message.contains("Zero vectors cannot be scored"), "{}", message);
+    // Nonzero, but the squares underflow so the norm is 0.
+    let message = retriever.add(&[1e-30; DIM]).unwrap_err().to_string();
+    assert!(message.contains("Cannot normalize a vector with L2 norm 0"), "{}", message);
+    assert!(retriever.upsert(2, &[1e-30; DIM]).is_err());
+    assert_eq!(retriever.num_active(), 5);
+    retriever.debug_validate_normalization(1e-5).unwrap();
+
+    let mut rows: Vec<Vec<f32>> = (0..3).map(|_| random_vector(&mut rng)).collect();
+    rows[1] = vec![1e-30; DIM];
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(SquaredL2Distance::new()), 3);
+    let message = retriever.with_normalization(Normalization::UnitL2).err().unwrap().to_string();
+    assert!(message.contains("Row 1: Cannot normalize"), "{}", message);
+}
+
+#[test]
+fn validation_is_a_no_op_without_normalization() {
+    let rows = vec![vec![3.0; DIM], vec![0.5; DIM]];
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(DotProductDistance::new()), 2);
+    retriever.debug_validate_normalization(1e-5).unwrap();
+    let docid = retriever.add(&[7.0; DIM]).unwrap();
+    assert_eq!(retriever.get_by_docid(docid).unwrap(), vec![7.0; DIM]);
+}
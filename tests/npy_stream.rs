@@ -0,0 +1,119 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streamed .npy files: batches written incrementally read back the same
+//! through the streaming reader and the regular loader, and an unclosed
+//! writer leaves a file every reader rejects.
+
+mod common;
+
+use common::{random_rows, scratch_dir};
+use scann::artifacts::{self, ArtifactsConfig};
+use scann::npy::{self, LoadMode, NpyStreamReader, NpyStreamWriter};
+use scann::util::{self, DenseDataset};
+
+#[test]
+fn many_small_batches_read_back_through_both_readers() {
+    let dir = scratch_dir("batches");
+    let path = dir.join("data.npy");
+    let rows = random_rows(1003, 5, 9);
+    let mut writer = NpyStreamWriter::create(&path, 5).unwrap();
+    for chunk in rows.chunks(7) {
+        match chunk.len() % 2 {
+            0 => writer.append_batch(&DenseDataset::new(chunk.to_vec(), 5)).unwrap(),
+            _ => chunk.iter().for_each(|row| writer.append_row(row).unwrap()),
+        }
+    }
+    assert_eq!(writer.rows(), 1003);
+    assert_eq!(writer.close().unwrap(), 1003);
+
+    let reader = NpyStreamReader::open(&path).unwrap();
+    assert_eq!((reader.rows(), reader.dimensionality()), (1003, 5));
+    let streamed: Vec<Vec<f32>> = reader.map(|row| row.unwrap()).collect();
+    assert_eq!(streamed, rows);
+    assert_eq!(npy::load_dataset(&path, LoadMode::Owned).unwrap().data, rows);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn dropping_the_writer_without_close_leaves_an_invalid_file() {
+    let dir = scratch_dir("unclosed");
+    let path = dir.join("data.npy");
+    let mut writer = NpyStreamWriter::create(&path, 3).unwrap();
+    writer.append_row(&[1.0, 2.0, 3.0]).unwrap();
+    drop(writer);
+    assert!(NpyStreamReader::open(&path).is_err());
+    assert!(npy::load_dataset(&path, LoadMode::Owned).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn append_row_rejects_the_wrong_dimensionality() {
+    let dir = scratch_dir("dimension");
+    let mut writer = NpyStreamWriter::create(dir.join("data.npy"), 3).unwrap();
+    let err = writer.append_row(&[1.0, 2.0]).unwrap_err();
+    assert!(err.to_string().contains("expected 3, got 2"), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn streaming_saves_load_like_regular_saves_and_stop_at_the_first_error() {
+    let dir = scratch_dir("artifacts");
+    let config = ArtifactsConfig {
+        distance_measure: "SquaredL2Distance".to_string(),
+        normalization: util::Normalization::None,
+        dimensionality: 4,
+    };
+    let rows = random_rows(50, 4, 9);
+    let docids: Vec<usize> = (0..50).map(|i| 1000 + i).collect();
+    let stream = docids.iter().copied().zip(rows.iter().cloned()).map(Ok);
+    assert_eq!(artifacts::save_artifacts_streaming(&dir, &config, stream).unwrap(), 50);
+    let loaded = artifacts::load_artifacts(&dir).unwrap();
+    assert_eq!(loaded.dataset.data, rows);
+    assert_eq!(loaded.docids, docids);
+
+    let failing = (0..10)
+        .map(|i| Ok((i, vec![0.0; 4])))
+        .chain(std::iter::once(Err(util::invalid_argument_error("source went away"))));
+    let err = artifacts::save_artifacts_streaming(&dir, &config, failing).unwrap_err();
+    assert!(err.to_string().contains("source went away"), "{}", err);
+    // The failed save never committed a manifest over the first one.
+    assert!(artifacts::load_artifacts(&dir).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rows_without_columns_are_rejected() {
+    let header = |shape: &str| {
+        let mut dict = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape).into_bytes();
+        dict.resize(117, b' ');
+        dict.push(b'\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+        bytes.extend(dict);
+        bytes
+    };
+    let zero_columns = header("(2, 0)");
+    assert!(npy::parse_npy_header(&zero_columns).is_err());
+    let err = npy::decode_npy_f32(&zero_columns).unwrap_err();
+    assert!(err.to_string().contains("no columns"), "{}", err);
+    let dir = scratch_dir("zero_columns");
+    std::fs::write(dir.join("data.npy"), &zero_columns).unwrap();
+    assert!(NpyStreamReader::open(dir.join("data.npy")).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // An empty dataset still decodes, whatever its width.
+    assert_eq!(npy::decode_npy_f32(&header("(0, 0)")).unwrap().size(), 0);
+    assert_eq!(npy::decode_npy_f32(&header("(0, 4)")).unwrap().size(), 0);
+}
@@ -0,0 +1,105 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hamming distance over bit vectors packed 8 dimensions per byte, and the
+//! packed binary retriever built on it.
+
+use scann::distance_measures::{packed_hamming, packed_len, BinaryHammingDistance, DistanceMeasure};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn random_bits(rng: &mut SplitMix64, n: usize) -> Vec<bool> {
+    (0..n).map(|_| rng.next_below(2) == 1).collect()
+}
+
+// LSB first, padding bits left at zero.
+fn pack(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0u8; packed_len(bits.len())];
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+fn unpacked_hamming(a: &[bool], b: &[bool]) -> u32 {
+    a.iter().zip(b).filter(|(x, y)| x != y).count() as u32
+}
+
+#[test]
+fn popcount_matches_an_unpacked_reference() {
+    let mut rng = SplitMix64::new(1);
+    for bits in [1, 7, 8, 9, 64, 100, 256] {
+        for _ in 0..20 {
+            let (a, b) = (random_bits(&mut rng, bits), random_bits(&mut rng, bits));
+            let expected = unpacked_hamming(&a, &b);
+            assert_eq!(packed_hamming(&pack(&a), &pack(&b), bits).unwrap(), expected, "{} bits", bits);
+            let as_f32 = |bytes: Vec<u8>| bytes.into_iter().map(f32::from).collect::<Vec<f32>>();
+            let measure = BinaryHammingDistance::with_dimensionality(bits);
+            assert_eq!(measure.compute_distance_f32(&as_f32(pack(&a)), &as_f32(pack(&b))), expected as f32);
+        }
+    }
+}
+
+#[test]
+fn padding_bits_past_the_dimensionality_are_ignored() {
+    // 13 bits in 2 bytes: the top 3 bits of the second byte are padding.
+    assert_eq!(packed_len(13), 2);
+    let a = [0b1010_1010, 0b0001_0101];
+    let b = [0b1010_1010, 0b1111_0101];
+    assert_eq!(packed_hamming(&a, &b, 13).unwrap(), 0);
+    assert_eq!(packed_hamming(&a, &b, 16).unwrap(), 3);
+    let flipped = [0b1010_1010, 0b0001_0100];
+    assert_eq!(packed_hamming(&a, &flipped, 13).unwrap(), 1);
+
+    let measure = BinaryHammingDistance::with_dimensionality(13);
+    let widened = |bytes: [u8; 2]| DatapointPtr::new(bytes.to_vec());
+    assert_eq!(measure.compute_distance(&widened(a), &widened(b)), 0.0);
+    assert_eq!(BinaryHammingDistance::new().compute_distance(&widened(a), &widened(b)), 3.0);
+}
+
+#[test]
+fn mismatched_byte_lengths_are_errors() {
+    let error = packed_hamming(&[0, 0], &[0], 13).unwrap_err();
+    assert!(error.to_string().contains("need 2 bytes, got 2 and 1"), "{}", error);
+    assert!(packed_hamming(&[0, 0, 0], &[0, 0, 0], 13).is_err());
+    assert!(packed_hamming(&[], &[], 0).is_ok());
+
+    let dataset = DenseDataset::new(vec![vec![0u8; 3]], 3);
+    assert!(ScannRetriever::new_packed_binary(&dataset, 13, 1).is_err());
+    let retriever = ScannRetriever::new_packed_binary(&DenseDataset::new(vec![vec![0u8; 2]], 2), 13, 1).unwrap();
+    assert!(retriever.search_packed(&DatapointPtr::new(vec![0u8; 3])).is_err());
+}
+
+#[test]
+fn search_packed_ranks_by_hamming_distance() {
+    const BITS: usize = 45;
+    let mut rng = SplitMix64::new(2);
+    let rows: Vec<Vec<bool>> = (0..200).map(|_| random_bits(&mut rng, BITS)).collect();
+    let dataset = DenseDataset::new(rows.iter().map(|bits| pack(bits)).collect(), packed_len(BITS));
+    let retriever = ScannRetriever::new_packed_binary(&dataset, BITS, 10).unwrap();
+
+    for _ in 0..10 {
+        let query = random_bits(&mut rng, BITS);
+        let results = retriever.search_packed(&DatapointPtr::new(pack(&query))).unwrap();
+        let mut expected: Vec<(usize, f32)> =
+            rows.iter().enumerate().map(|(i, row)| (i, unpacked_hamming(&query, row) as f32)).collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        assert_eq!(results, expected[..10]);
+    }
+    // A stored row is its own nearest neighbor.
+    let results = retriever.search_packed(&DatapointPtr::new(pack(&rows[17]))).unwrap();
+    assert_eq!(results[0], (17, 0.0));
+}
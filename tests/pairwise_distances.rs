@@ -0,0 +1,97 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! compute_pairwise_distances: every entry against the single distance,
+//! the matrix-multiply path for dot products, and dimensionality errors.
+
+mod common;
+
+use common::random_dataset;
+use scann::distance_measures::{
+    self, CosineDistance, DistanceMeasure, DotProductDistance, L1Distance, SquaredL2Distance,
+    WeightedSquaredL2Distance,
+};
+use scann::util::DenseDataset;
+
+const DIM: usize = 9;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= 1e-5 * b.abs().max(1.0)
+}
+
+#[test]
+fn entries_match_the_single_distance() {
+    let a = random_dataset(13, DIM, 1);
+    let b = random_dataset(21, DIM, 2);
+    let weights = (1..=DIM).map(|w| w as f32 * 0.25).collect();
+    let measures: Vec<Box<dyn DistanceMeasure>> = vec![
+        Box::new(SquaredL2Distance::new()),
+        Box::new(L1Distance::new()),
+        Box::new(CosineDistance::new()),
+        Box::new(WeightedSquaredL2Distance::new(weights).unwrap()),
+        Box::new(DotProductDistance::new()),
+    ];
+    for measure in measures {
+        let matrix = distance_measures::compute_pairwise_distances(measure.as_ref(), &a, &b).unwrap();
+        assert_eq!(matrix.shape(), (13, 21), "{}", measure.name());
+        for (i, x) in a.data.iter().enumerate() {
+            for (j, y) in b.data.iter().enumerate() {
+                let expected = measure.compute_distance_f32(x, y);
+                let found = matrix[(i, j)];
+                assert!(close(found, expected), "{} ({}, {}): {} vs {}", measure.name(), i, j, found, expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn dot_products_are_the_negated_matrix_product() {
+    let a = DenseDataset::new(vec![vec![1.0, 2.0], vec![-1.0, 0.5]], 2);
+    let b = DenseDataset::new(vec![vec![3.0, -1.0], vec![0.0, 4.0], vec![2.0, 2.0]], 2);
+    let matrix = distance_measures::compute_pairwise_distances(&DotProductDistance::new(), &a, &b).unwrap();
+    let expected = [[-1.0, -8.0, -6.0], [3.5, -2.0, 1.0]];
+    for (i, row) in expected.iter().enumerate() {
+        for (j, &value) in row.iter().enumerate() {
+            assert_eq!(matrix[(i, j)], value, "({}, {})", i, j);
+        }
+    }
+}
+
+#[test]
+fn either_set_may_be_empty() {
+    let empty = DenseDataset::new(Vec::new(), DIM);
+    let rows = random_dataset(4, DIM, 3);
+    for measure in [&SquaredL2Distance::new() as &dyn DistanceMeasure, &DotProductDistance::new()] {
+        let pairs = [(&empty, &rows, (0, 4)), (&rows, &empty, (4, 0)), (&empty, &empty, (0, 0))];
+        for (a, b, shape) in pairs {
+            let matrix = distance_measures::compute_pairwise_distances(measure, a, b).unwrap();
+            assert_eq!(matrix.shape(), shape, "{}", measure.name());
+        }
+    }
+}
+
+#[test]
+fn mismatched_dimensionality_is_an_error() {
+    let a = random_dataset(3, DIM, 4);
+    let b = random_dataset(3, DIM + 1, 5);
+    for measure in [&SquaredL2Distance::new() as &dyn DistanceMeasure, &DotProductDistance::new()] {
+        let error = distance_measures::compute_pairwise_distances(measure, &a, &b).unwrap_err().to_string();
+        assert!(error.contains("Dimensionality mismatch: 9 vs 10"), "{}: {}", measure.name(), error);
+        let error = distance_measures::compute_pairwise_distances(measure, &b, &a).unwrap_err().to_string();
+        assert!(error.contains("Dimensionality mismatch: 10 vs 9"), "{}: {}", measure.name(), error);
+    }
+    // The measure's own dimensionality is checked too.
+    let weighted = WeightedSquaredL2Distance::new(vec![1.0; DIM + 1]).unwrap();
+    assert!(distance_measures::compute_pairwise_distances(&weighted, &a, &a).is_err());
+}
@@ -0,0 +1,163 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Exporting one leaf for re-embedding and importing the new vectors back.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{self, Int8Codes, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+const NUM_LEAVES: usize = 4;
+const K: usize = 5;
+
+// Four tight, far-apart clusters so each becomes one leaf.
+fn cluster_center(c: usize) -> [f32; 2] {
+    [100.0 * (c % 2) as f32, 100.0 * (c / 2) as f32]
+}
+
+fn clustered_retriever() -> ScannRetriever {
+    let mut rng = SplitMix64::new(8);
+    let rows = (0..400)
+        .map(|i| {
+            let center = cluster_center(i % NUM_LEAVES);
+            vec![center[0] + rng.next_normal(), center[1] + rng.next_normal()]
+        })
+        .collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), K);
+    retriever.build_partitions(NUM_LEAVES, &KMeansTreeTrainingOptions::new()).unwrap();
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever
+}
+
+fn export(retriever: &ScannRetriever, leaf: usize) -> (DenseDataset<f32>, Vec<usize>) {
+    let mut bytes = Vec::new();
+    let count = retriever.export_partition(leaf, &mut bytes).unwrap();
+    let (leaf_id, vectors, docids) = retrieval::read_partition(&mut bytes.as_slice()).unwrap();
+    assert_eq!((leaf_id, vectors.size(), docids.len()), (leaf, count, count));
+    (vectors, docids)
+}
+
+fn search_all_leaves(retriever: &ScannRetriever, query: [f32; 2]) -> Vec<(usize, f32)> {
+    let options = SearchOptions {
+        leaves_to_search: Some(NUM_LEAVES),
+        ..SearchOptions::default()
+    };
+    retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap().0
+}
+
+#[test]
+fn exported_leaf_holds_its_rows_in_docid_alignment() {
+    let retriever = clustered_retriever();
+    let mut total = 0;
+    for leaf in 0..NUM_LEAVES {
+        let (vectors, docids) = export(&retriever, leaf);
+        assert_eq!(docids.len(), 100);
+        for (row, &docid) in vectors.data.iter().zip(&docids) {
+            assert_eq!(retriever.get_by_docid(docid).unwrap(), *row);
+        }
+        total += docids.len();
+    }
+    assert_eq!(total, 400);
+
+    let mut bytes = Vec::new();
+    assert!(retriever.export_partition(NUM_LEAVES, &mut bytes).is_err());
+    assert!(retrieval::read_partition(&mut &b"NOTAPART"[..]).is_err());
+    let (_, docids) = export(&retriever, 0);
+    retriever.remove(docids[0]).unwrap();
+    assert_eq!(export(&retriever, 0).1, docids[1..]);
+}
+
+#[test]
+fn reimport_changes_only_queries_near_that_leaf() {
+    let retriever = clustered_retriever();
+    let (mut vectors, docids) = export(&retriever, 1);
+    let queries: Vec<[f32; 2]> = (0..NUM_LEAVES).map(cluster_center).collect();
+    let before: Vec<_> = queries.iter().map(|&q| search_all_leaves(&retriever, q)).collect();
+
+    // Re-embed by moving every row of the leaf 3 towards -x; the vectors
+    // stay near their cluster but their distances all change.
+    for row in vectors.data.iter_mut() {
+        row[0] -= 3.0;
+    }
+    retriever.import_reembedded_partition(1, &vectors, &docids).unwrap();
+
+    for (row, &docid) in vectors.data.iter().zip(&docids) {
+        assert_eq!(retriever.get_by_docid(docid).unwrap(), *row, "docid {}", docid);
+    }
+    let leaf_center: Vec<f32> = (0..2)
+        .map(|d| vectors.data.iter().map(|row| row[d]).sum::<f32>() / vectors.size() as f32)
+        .collect();
+    let mut changed = 0;
+    for (query, before) in queries.iter().zip(&before) {
+        let after = search_all_leaves(&retriever, *query);
+        let near = (query[0] - leaf_center[0]).abs() < 10.0 && (query[1] - leaf_center[1]).abs() < 10.0;
+        if near {
+            assert_ne!(after, *before);
+            changed += 1;
+        } else {
+            assert_eq!(after, *before, "query {:?}", query);
+        }
+    }
+    assert_eq!(changed, 1);
+
+    // The int8 codes were re-encoded along with the rows: stale codes
+    // would be off by the whole shift.
+    let error = retriever.int8_error_summary().unwrap();
+    assert!(error.max_abs_error < 1.0, "{:?}", error);
+}
+
+#[test]
+fn partial_imports_change_nothing() {
+    let retriever = clustered_retriever();
+    let (vectors, docids) = export(&retriever, 2);
+    let (_, other_docids) = export(&retriever, 3);
+    let snapshot: Vec<_> = docids.iter().map(|&d| retriever.get_by_docid(d).unwrap()).collect();
+    let shifted = DenseDataset::new(vectors.data.iter().map(|row| vec![row[0] + 1.0, row[1]]).collect(), 2);
+
+    // The last docid belongs to another leaf.
+    let mut mixed = docids.clone();
+    *mixed.last_mut().unwrap() = other_docids[0];
+    let message = retriever.import_reembedded_partition(2, &shifted, &mixed).unwrap_err().to_string();
+    assert!(message.contains(&format!("Docid {} is not stored in leaf 2", other_docids[0])), "{}", message);
+
+    // The last vector has the wrong dimensionality.
+    let mut bad = shifted.clone();
+    bad.data.last_mut().unwrap().push(0.0);
+    assert!(retriever.import_reembedded_partition(2, &bad, &docids).is_err());
+
+    assert!(retriever.import_reembedded_partition(2, &shifted, &docids[1..]).is_err());
+    assert!(retriever.import_reembedded_partition(NUM_LEAVES, &shifted, &docids).is_err());
+
+    let after: Vec<_> = docids.iter().map(|&d| retriever.get_by_docid(d).unwrap()).collect();
+    assert_eq!(after, snapshot);
+}
+
+#[test]
+fn unpartitioned_retrievers_have_no_leaves_to_export() {
+    let retriever = ScannRetriever::new(
+        DenseDataset::new(vec![vec![0.0, 0.0]; 3], 2),
+        Box::new(SquaredL2Distance::new()),
+        K,
+    );
+    let mut bytes = Vec::new();
+    let message = retriever.export_partition(0, &mut bytes).unwrap_err().to_string();
+    assert!(message.contains("no partitioning"), "{}", message);
+    let vectors = DenseDataset::new(vec![vec![1.0, 1.0]], 2);
+    assert!(retriever.import_reembedded_partition(0, &vectors, &[0]).is_err());
+}
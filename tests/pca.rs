@@ -0,0 +1,237 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use scann::build;
+use scann::distance_measures::SquaredL2Distance;
+use scann::estimate::BuildPlan;
+use scann::projection::{PcaProjection, RankDeficiencyHandling};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::testing::datasets::{self, LowRankSpec};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+// Samples mean + sum_i std_i * z_i * axis_i over orthonormal `axes`.
+fn anisotropic_gaussian(n: usize, mean: &[f32], axes: &[Vec<f32>], stds: &[f32], seed: u64) -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(seed);
+    let rows = (0..n)
+        .map(|_| {
+            let mut row = mean.to_vec();
+            for (axis, &std) in axes.iter().zip(stds.iter()) {
+                let z = rng.next_normal() * std;
+                for (v, &a) in row.iter_mut().zip(axis.iter()) {
+                    *v += z * a;
+                }
+            }
+            row
+        })
+        .collect();
+    DenseDataset::new(rows, mean.len())
+}
+
+// A rotation of the standard basis in the first two coordinates.
+fn rotated_axes(dim: usize) -> Vec<Vec<f32>> {
+    let (s, c) = (0.6f32, 0.8f32);
+    (0..dim)
+        .map(|i| {
+            let mut axis = vec![0.0; dim];
+            match i {
+                0 => {
+                    axis[0] = c;
+                    axis[1] = s;
+                }
+                1 => {
+                    axis[0] = -s;
+                    axis[1] = c;
+                }
+                _ => axis[i] = 1.0,
+            }
+            axis
+        })
+        .collect()
+}
+
+#[test]
+fn recovers_principal_directions_of_offset_anisotropic_gaussian() {
+    let dim = 5;
+    let axes = rotated_axes(dim);
+    let stds = [10.0, 5.0, 2.0, 0.5, 0.1];
+    // A mean far from the origin dominates the uncentered second moment.
+    let mean = [40.0, -30.0, 25.0, 60.0, -50.0];
+    let data = anisotropic_gaussian(20000, &mean, &axes, &stds, 3);
+    for build_covariance in [true, false] {
+        let mut pca = PcaProjection::<f32>::new(dim as i32, 3).unwrap();
+        let report = pca.create(&data, build_covariance, None).unwrap();
+        assert_eq!(report.effective_dims, 3);
+        let directions = pca.get_directions().unwrap();
+        for (i, direction) in directions.data.iter().enumerate() {
+            let alignment = dot(direction, &axes[i]).abs();
+            assert!(alignment > 0.99, "direction {} alignment {} (covariance {})", i, alignment, build_covariance);
+        }
+        let eigenvalues = pca.eigenvalues().unwrap();
+        for (value, std) in eigenvalues.iter().zip(stds.iter()) {
+            let variance = std * std;
+            assert!((value - variance).abs() < 0.05 * variance, "eigenvalue {} vs variance {}", value, variance);
+        }
+
+        // Projections of two points differ by their offset along each axis.
+        let a = mean.to_vec();
+        let mut b = mean.to_vec();
+        for (j, v) in b.iter_mut().enumerate() {
+            *v += 3.0 * axes[0][j];
+        }
+        let mut projected_a = DatapointPtr::new(Vec::<f32>::new());
+        let mut projected_b = DatapointPtr::new(Vec::<f32>::new());
+        pca.project_input(&DatapointPtr::new(a), &mut projected_a).unwrap();
+        pca.project_input(&DatapointPtr::new(b), &mut projected_b).unwrap();
+        let delta: Vec<f32> = projected_b.values().iter().zip(projected_a.values()).map(|(x, y)| x - y).collect();
+        assert!((delta[0].abs() - 3.0).abs() < 0.05, "delta {:?}", delta);
+        assert!(delta[1].abs() < 0.05 && delta[2].abs() < 0.05, "delta {:?}", delta);
+    }
+}
+
+fn project_rows(pca: &PcaProjection<f32>, data: &DenseDataset<f32>) -> Vec<Vec<f32>> {
+    data.data
+        .iter()
+        .map(|row| {
+            let mut projected = DatapointPtr::new(Vec::<f32>::new());
+            pca.project_input(&DatapointPtr::new(row.clone()), &mut projected).unwrap();
+            projected.values().to_vec()
+        })
+        .collect()
+}
+
+fn assert_close(actual: &[f32], expected: &[f32]) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected.iter()) {
+        assert!((a - e).abs() <= 1e-4 * e.abs().max(1.0), "{:?} vs {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn batch_projection_matches_the_per_point_path() {
+    let dim = 6;
+    let stds = [5.0, 3.0, 2.0, 1.0, 0.5, 0.2];
+    let data = anisotropic_gaussian(1000, &[1.0, -2.0, 3.0, 0.5, 0.0, 4.0], &rotated_axes(dim), &stds, 7);
+    let mut pca = PcaProjection::<f32>::new(dim as i32, 4).unwrap();
+    pca.create(&data, true, None).unwrap();
+    let expected = project_rows(&pca, &data);
+
+    let projected = pca.project_dataset(&data).unwrap();
+    assert_eq!(projected.dimensionality(), 4);
+    for (actual, expected) in projected.data.iter().zip(expected.iter()) {
+        assert_close(actual, expected);
+    }
+    // Any block size gives the same rows, and the output can be reused.
+    let mut out = DenseDataset::new(vec![vec![9.0; 2]; 3], 2);
+    for block_rows in [1, 7, 999, 1000, 5000] {
+        pca.project_dataset_into(&data, &mut out, block_rows).unwrap();
+        assert_eq!(out.data, projected.data, "block_rows {}", block_rows);
+    }
+    assert!(pca.project_dataset_into(&data, &mut out, 0).is_err());
+    assert!(pca.project_dataset(&DenseDataset::new(vec![vec![0.0; 5]], 5)).is_err());
+}
+
+#[test]
+fn projected_builds_match_a_retriever_over_per_point_projections() {
+    let dim = 6;
+    let data = anisotropic_gaussian(600, &[0.0; 6], &rotated_axes(dim), &[5.0, 3.0, 2.0, 1.0, 0.5, 0.2], 11);
+    let mut plan = BuildPlan::new();
+    plan.projected_dims = Some(3);
+    plan.num_leaves = Some(6);
+    let options = KMeansTreeTrainingOptions::new();
+    let err = build::build_retriever(data.clone(), Box::new(SquaredL2Distance::new()), 5, &plan, &options)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("build_projected_retriever"), "{}", err);
+    let (retriever, pca, report) =
+        build::build_projected_retriever(data.clone(), Box::new(SquaredL2Distance::new()), 5, &plan, &options).unwrap();
+    let names: Vec<&str> = report.phases.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["projection", "copy", "kmeans"]);
+    assert_eq!(report.dataset.dimensionality, dim);
+
+    let expected = project_rows(&pca, &data);
+    for (docid, row) in expected.iter().enumerate() {
+        assert_close(&retriever.get_by_docid(docid).unwrap(), row);
+    }
+    let per_point = ScannRetriever::new(DenseDataset::new(expected, 3), Box::new(SquaredL2Distance::new()), 5);
+    let options = SearchOptions {
+        leaves_to_search: Some(6),
+        ..SearchOptions::default()
+    };
+    for query in data.data.iter().step_by(37) {
+        let mut projected = DatapointPtr::new(Vec::<f32>::new());
+        pca.project_input(&DatapointPtr::new(query.clone()), &mut projected).unwrap();
+        let (found, _) = retriever.search_with_options(&projected, &options).unwrap();
+        let exact = per_point.search(&projected).unwrap();
+        let ids = |results: &[(usize, f32)]| results.iter().map(|&(docid, _)| docid).collect::<Vec<_>>();
+        assert_eq!(ids(&found), ids(&exact));
+    }
+}
+
+// 100 points spanning three directions of a 16-dimensional space.
+fn rank_three_dataset() -> DenseDataset<f32> {
+    let spec = LowRankSpec { num_points: 100, dimensionality: 16, rank: 3, noise_std: 0.0, seed: 11 };
+    datasets::low_rank_plus_noise(&spec).unwrap().dataset
+}
+
+#[test]
+fn rank_deficient_training_data_truncates_the_projection() {
+    let data = rank_three_dataset();
+    for build_covariance in [true, false] {
+        let mut pca = PcaProjection::<f32>::new(16, 8).unwrap();
+        let report = pca.create(&data, build_covariance, None).unwrap();
+        assert_eq!(report.requested_dims, 8);
+        assert_eq!(report.numerical_rank, 3, "covariance {}", build_covariance);
+        assert_eq!(report.effective_dims, 3);
+        let warning = report.warning.expect("truncation warning");
+        assert!(warning.contains("numerical rank 3; truncated to 3"), "{}", warning);
+        assert_eq!(pca.projected_dims(), 3);
+        assert_eq!(pca.get_directions().unwrap().size(), 3);
+        assert!(project_rows(&pca, &data).iter().all(|row| row.len() == 3));
+
+        // Serialization keeps only the effective directions.
+        let serialized = pca.serialize_to_proto().unwrap();
+        assert_eq!(serialized.rotation_vec_size(), 3);
+        let mut restored = PcaProjection::<f32>::new(16, 8).unwrap();
+        restored.create_from_serialized(&serialized).unwrap();
+        assert_eq!(restored.projected_dims(), 3);
+        assert_eq!(project_rows(&restored, &data), project_rows(&pca, &data));
+    }
+}
+
+#[test]
+fn rank_deficient_training_data_errors_when_asked_to() {
+    let data = rank_three_dataset();
+    let mut pca = PcaProjection::<f32>::new(16, 8).unwrap();
+    pca.set_rank_deficiency_handling(RankDeficiencyHandling::Error, 1e-6);
+    let err = pca.create(&data, true, None).unwrap_err();
+    assert_eq!(err.to_string(), "Requested 8 PCA dimensions but the training data has numerical rank 3");
+    assert!(pca.get_directions().is_none());
+}
+
+#[test]
+fn full_rank_requests_carry_no_warning() {
+    let data = rank_three_dataset();
+    let mut pca = PcaProjection::<f32>::new(16, 2).unwrap();
+    pca.set_rank_deficiency_handling(RankDeficiencyHandling::Error, 1e-6);
+    let report = pca.create(&data, true, None).unwrap();
+    // Only the requested directions are solved for, so the rank seen is
+    // capped at the request.
+    assert_eq!((report.effective_dims, report.numerical_rank), (2, 2));
+    assert!(report.warning.is_none());
+}
@@ -0,0 +1,134 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PCA by thin SVD: the same subspace as the covariance route, and no d x d
+//! allocation. A counting allocator records the largest allocation made by
+//! the test thread while measuring.
+
+use scann::projection::PcaProjection;
+use scann::util::{DenseDataset, SplitMix64};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct LargestAllocation;
+
+thread_local! {
+    static MEASURING: Cell<bool> = const { Cell::new(false) };
+    static LARGEST: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record(size: usize) {
+    // try_with: thread-locals may already be gone while a thread exits.
+    let _ = MEASURING.try_with(|measuring| {
+        if measuring.get() {
+            let _ = LARGEST.try_with(|largest| largest.set(largest.get().max(size)));
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for LargestAllocation {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: LargestAllocation = LargestAllocation;
+
+// Largest single allocation `f` makes on this thread.
+fn largest_allocation(f: impl FnOnce()) -> usize {
+    LARGEST.with(|largest| largest.set(0));
+    MEASURING.with(|measuring| measuring.set(true));
+    f();
+    MEASURING.with(|measuring| measuring.set(false));
+    LARGEST.with(|largest| largest.get())
+}
+
+// Rows with independent, offset coordinates whose standard deviations
+// fall off geometrically, so the principal directions are well separated.
+fn skewed_rows(n: usize, dim: usize, seed: u64) -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(seed);
+    let rows = (0..n)
+        .map(|_| (0..dim).map(|j| j as f32 + rng.next_normal() * 4.0 * 0.8f32.powi(j as i32)).collect())
+        .collect();
+    DenseDataset::new(rows, dim)
+}
+
+fn directions(data: &DenseDataset<f32>, dims: usize, build_covariance: bool) -> (Vec<Vec<f32>>, Vec<f32>) {
+    let mut pca = PcaProjection::<f32>::new(data.dimensionality() as i32, dims as i32).unwrap();
+    pca.create(data, build_covariance, None).unwrap();
+    (pca.get_directions().unwrap().data.clone(), pca.eigenvalues().unwrap().to_vec())
+}
+
+// Orthogonal projector onto the span of `vectors`, which are orthonormal.
+fn projector(vectors: &[Vec<f32>]) -> Vec<f64> {
+    let dim = vectors[0].len();
+    let mut p = vec![0.0f64; dim * dim];
+    for v in vectors {
+        for i in 0..dim {
+            for j in 0..dim {
+                p[i * dim + j] += v[i] as f64 * v[j] as f64;
+            }
+        }
+    }
+    p
+}
+
+#[test]
+fn svd_and_covariance_routes_find_the_same_subspace() {
+    // Tall, square and wide training matrices.
+    for (n, dim, dims) in [(200, 6, 3), (12, 12, 4), (9, 20, 5)] {
+        let data = skewed_rows(n, dim, n as u64);
+        let (by_covariance, covariance_values) = directions(&data, dims, true);
+        let (by_svd, svd_values) = directions(&data, dims, false);
+        assert_eq!(by_svd.len(), dims);
+        let (p, q) = (projector(&by_covariance), projector(&by_svd));
+        let gap = p.iter().zip(&q).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        assert!(gap < 1e-3, "{}x{}: projectors differ by {}", n, dim, gap);
+        // Both routes fix signs the same way, so the vectors agree too.
+        for (a, b) in by_covariance.iter().zip(&by_svd) {
+            assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-3), "{:?} vs {:?}", a, b);
+        }
+        for (a, b) in covariance_values.iter().zip(&svd_values) {
+            assert!((a - b).abs() <= 1e-3 * a.max(1.0), "eigenvalue {} vs {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn the_svd_route_never_allocates_d_by_d() {
+    let (n, dim) = (8, 256);
+    let data = skewed_rows(n, dim, 1);
+    let d_by_d = dim * dim * std::mem::size_of::<f32>();
+
+    let covariance = largest_allocation(|| {
+        directions(&data, 4, true);
+    });
+    // The covariance route is the control: it does allocate d x d.
+    assert!(covariance >= d_by_d, "covariance route peaked at {} bytes", covariance);
+    let svd = largest_allocation(|| {
+        directions(&data, 4, false);
+    });
+    assert!(svd < d_by_d / 4, "SVD route allocated {} bytes, d x d f32 is {}", svd, d_by_d);
+}
@@ -0,0 +1,132 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PoincareDistance: hand-computed hyperbolic distances, metric properties,
+//! points on or outside the unit ball, and retrieval.
+
+use scann::distance_measures::{self, DistanceMeasure, PoincareDistance, POINCARE_MAX_NORM};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn reference(a: &[f32], b: &[f32]) -> f64 {
+    let sq = |v: &[f32]| v.iter().map(|&x| x as f64 * x as f64).sum::<f64>();
+    let diff: f64 = a.iter().zip(b).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+    (1.0 + 2.0 * diff / ((1.0 - sq(a)) * (1.0 - sq(b)))).acosh()
+}
+
+// Points spread inside the ball, up to norm 0.95.
+fn ball_points(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n)
+        .map(|_| {
+            let v: Vec<f32> = (0..dim).map(|_| rng.next_normal()).collect();
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let radius = 0.95 * rng.next_f32();
+            v.iter().map(|x| x / norm * radius).collect()
+        })
+        .collect()
+}
+
+fn close(a: f32, b: f64) -> bool {
+    (a as f64 - b).abs() <= 1e-5 * b.abs().max(1.0)
+}
+
+#[test]
+fn hand_computed_pairs() {
+    let measure = PoincareDistance::new(false);
+    let ln3 = 3.0f64.ln();
+    // From the origin, the distance is 2 artanh(|x|) = ln((1 + |x|) / (1 - |x|)).
+    assert!(close(measure.compute_distance_f32(&[0.0, 0.0], &[0.5, 0.0]), ln3));
+    assert!(close(measure.compute_distance_f32(&[0.0, 0.0], &[0.3, 0.4]), ln3));
+    // The geodesic between opposite points passes through the origin.
+    assert!(close(measure.compute_distance_f32(&[0.5, 0.0], &[-0.5, 0.0]), 2.0 * ln3));
+    // 1 + 2 * 0.13 / (0.95 * 0.9) = 1.3040935...
+    assert!(close(measure.compute_distance_f32(&[0.1, 0.2], &[0.3, -0.1]), 1.304_093_567_251_462f64.acosh()));
+    // Hyperbolic distances grow without bound towards the boundary.
+    let near = measure.compute_distance_f32(&[0.0], &[0.9]);
+    let nearer = measure.compute_distance_f32(&[0.0], &[0.99]);
+    assert!(nearer > near + 2.0, "{} {}", near, nearer);
+}
+
+#[test]
+fn symmetry_and_identity_of_indiscernibles() {
+    let measure = PoincareDistance::new(false);
+    let points = ball_points(40, 5, 1);
+    for a in &points {
+        assert_eq!(measure.compute_distance_f32(a, a), 0.0);
+        for b in &points {
+            let d = measure.compute_distance_f32(a, b);
+            assert_eq!(d, measure.compute_distance_f32(b, a));
+            assert!(close(d, reference(a, b)), "{} vs {}", d, reference(a, b));
+            if a != b {
+                assert!(d > 0.0);
+            }
+        }
+    }
+    // The batched path matches pairwise scoring.
+    let mut out = vec![0.0; points.len()];
+    let dataset = DenseDataset::new(points.clone(), 5);
+    measure.compute_one_to_many(&DatapointPtr::new(points[0].clone()), &dataset, &mut out);
+    for (row, &d) in points.iter().zip(&out) {
+        assert_eq!(d, measure.compute_distance_f32(&points[0], row));
+    }
+}
+
+#[test]
+fn points_outside_the_ball_are_rejected_or_clamped() {
+    let strict = PoincareDistance::new(false);
+    assert!(strict.compute_distance_f32(&[1.0, 0.0], &[0.0, 0.0]).is_nan());
+    assert!(strict.compute_distance_f32(&[0.0, 0.0], &[3.0, 4.0]).is_nan());
+    assert!(strict.try_distance(&[1.0, 0.0], &[0.0, 0.0]).is_err());
+    assert!(strict.try_distance(&[0.0, 0.0], &[f32::INFINITY, 0.0]).is_err());
+    assert!(strict.try_distance(&[0.0], &[0.0, 0.0]).is_err());
+    assert!(close(strict.try_distance(&[0.0, 0.0], &[0.5, 0.0]).unwrap(), 3.0f64.ln()));
+
+    // Clamping scales outside points back to POINCARE_MAX_NORM.
+    let clamping = PoincareDistance::new(true);
+    let expected = ((1.0 + POINCARE_MAX_NORM) / (1.0 - POINCARE_MAX_NORM)).ln();
+    assert!((clamping.compute_distance_f32(&[0.0, 0.0], &[3.0, 4.0]) as f64 - expected).abs() < 1e-3);
+    assert_eq!(
+        clamping.compute_distance_f32(&[0.0, 0.0], &[3.0, 4.0]),
+        clamping.compute_distance_f32(&[0.0, 0.0], &[0.6, 0.8])
+    );
+    assert!(clamping.try_distance(&[0.0, 0.0], &[3.0, 4.0]).unwrap().is_finite());
+    assert!(clamping.compute_distance_f32(&[0.0, 0.0], &[f32::NAN, 0.0]).is_nan());
+
+    let by_name = distance_measures::get_distance_measure_by_name("PoincareDistance").unwrap();
+    assert_eq!(by_name.name(), "PoincareDistance");
+    assert!(by_name.compute_distance_f32(&[2.0], &[0.0]).is_nan());
+}
+
+#[test]
+fn retriever_finds_the_hyperbolic_nearest_nodes() {
+    let mut points = ball_points(500, 4, 2);
+    // Rows on or outside the boundary are skipped by search.
+    points[7] = vec![1.0, 0.0, 0.0, 0.0];
+    points[8] = vec![2.0, 2.0, 0.0, 0.0];
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(points.clone(), 4), Box::new(PoincareDistance::new(false)), 10);
+    for query in ball_points(20, 4, 3) {
+        let mut expected: Vec<(usize, f64)> = points
+            .iter()
+            .enumerate()
+            .filter(|(d, _)| *d != 7 && *d != 8)
+            .map(|(d, p)| (d, reference(&query, p)))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let results = retriever.search(&DatapointPtr::new(query)).unwrap();
+        let ids: Vec<usize> = results.iter().map(|&(docid, _)| docid).collect();
+        assert_eq!(ids, expected[..10].iter().map(|&(docid, _)| docid).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prepared queries: identical results to raw queries, with validation and
+//! leaf ordering done once.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{PreparedQuery, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+
+const DIM: usize = 6;
+const NUM_LEAVES: usize = 10;
+
+fn partitioned_retriever() -> ScannRetriever {
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(random_rows(1000, DIM, 1), DIM), Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    retriever
+}
+
+fn leaves(n: usize) -> SearchOptions {
+    SearchOptions { leaves_to_search: Some(n), ..SearchOptions::default() }
+}
+
+#[test]
+fn prepared_and_raw_queries_return_identical_results() {
+    let retriever = partitioned_retriever();
+    for values in random_rows(10, DIM, 2) {
+        let raw = DatapointPtr::new(values);
+        let prepared = retriever.prepare(&raw).unwrap();
+        assert_eq!(prepared.values(), raw.values());
+        for n in [1, 3, NUM_LEAVES] {
+            let (expected, raw_stats) = retriever.search_with_options(&raw, &leaves(n)).unwrap();
+            let (results, stats) = retriever.search_prepared(&prepared, &leaves(n)).unwrap();
+            assert_eq!(results, expected);
+            assert_eq!(stats.leaves_searched, raw_stats.leaves_searched);
+        }
+    }
+}
+
+#[test]
+fn preparation_work_is_not_repeated() {
+    let retriever = partitioned_retriever();
+    let raw = DatapointPtr::new(random_rows(1, DIM, 3).remove(0));
+
+    // Raw searches validate and order leaves every time.
+    for _ in 0..2 {
+        let (_, stats) = retriever.search_with_options(&raw, &leaves(3)).unwrap();
+        assert_eq!((stats.query_preparations, stats.leaf_orderings), (1, 1));
+    }
+
+    // A prepared query orders leaves on its first search only, whatever
+    // number of leaves later searches ask for.
+    let prepared = retriever.prepare(&raw).unwrap();
+    let (_, stats) = retriever.search_prepared(&prepared, &leaves(3)).unwrap();
+    assert_eq!((stats.query_preparations, stats.leaf_orderings), (0, 1));
+    for n in [1, 3, 7, NUM_LEAVES] {
+        let (_, stats) = retriever.search_prepared(&prepared, &leaves(n)).unwrap();
+        assert_eq!((stats.query_preparations, stats.leaf_orderings), (0, 0), "{} leaves", n);
+    }
+
+    // A mutation invalidates the cached ordering; preparing again after it
+    // caches the new one.
+    retriever.add(&[0.0; DIM]).unwrap();
+    let (_, stats) = retriever.search_prepared(&prepared, &leaves(3)).unwrap();
+    assert_eq!(stats.leaf_orderings, 1);
+    let prepared = retriever.prepare(&raw).unwrap();
+    for expected in [1, 0] {
+        let (_, stats) = retriever.search_prepared(&prepared, &leaves(3)).unwrap();
+        assert_eq!(stats.leaf_orderings, expected);
+    }
+}
+
+#[test]
+fn prepared_queries_move_across_threads() {
+    let retriever = partitioned_retriever();
+    let raw = DatapointPtr::new(random_rows(1, DIM, 4).remove(0));
+    let (expected, _) = retriever.search_with_options(&raw, &leaves(4)).unwrap();
+    let prepared: PreparedQuery = retriever.prepare(&raw).unwrap();
+    std::thread::scope(|scope| {
+        let retriever = &retriever;
+        let results = scope.spawn(move || retriever.search_prepared(&prepared, &leaves(4)).unwrap().0);
+        assert_eq!(results.join().unwrap(), expected);
+    });
+}
+
+#[test]
+fn prepared_queries_are_bound_to_their_retriever_and_validated() {
+    let retriever = partitioned_retriever();
+    let other = partitioned_retriever();
+    let prepared = retriever.prepare(&DatapointPtr::new(vec![0.5; DIM])).unwrap();
+    let error = other.search_prepared(&prepared, &leaves(3)).unwrap_err();
+    assert!(error.to_string().contains("prepared by a different retriever"), "{}", error);
+
+    let mut values = vec![0.0; DIM];
+    values[2] = f32::NAN;
+    let error = retriever.prepare(&DatapointPtr::new(values)).err().unwrap();
+    assert!(error.to_string().contains("non-finite value NaN at dimension 2"), "{}", error);
+    assert!(retriever.prepare(&DatapointPtr::new(vec![0.0; DIM + 1])).is_err());
+}
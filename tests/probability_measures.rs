@@ -0,0 +1,122 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! KL divergence and cross-entropy on known distributions, including their
+//! asymmetry, the epsilon floor, renormalization and invalid inputs.
+
+use scann::distance_measures::{self, CrossEntropyDistance, DistanceMeasure, KLDivergenceDistance};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn assert_close(actual: f32, expected: f64) {
+    assert!((actual as f64 - expected).abs() < 1e-5, "{} vs {}", actual, expected);
+}
+
+fn kl(a: &[f32], b: &[f32]) -> f32 {
+    KLDivergenceDistance::new(false).compute_distance_f32(a, b)
+}
+
+fn cross_entropy(a: &[f32], b: &[f32]) -> f32 {
+    CrossEntropyDistance::new(false).compute_distance_f32(a, b)
+}
+
+#[test]
+fn known_distributions() {
+    let ln = f64::ln;
+    assert_close(kl(&[0.5, 0.5], &[0.5, 0.5]), 0.0);
+    assert_close(kl(&[0.5, 0.5], &[0.25, 0.75]), 0.5 * ln(4.0 / 3.0));
+    assert_close(kl(&[1.0, 0.0], &[0.5, 0.5]), ln(2.0));
+    assert_close(cross_entropy(&[0.5, 0.5], &[0.5, 0.5]), ln(2.0));
+    assert_close(cross_entropy(&[0.5, 0.5], &[0.25, 0.75]), -0.5 * (ln(0.25) + ln(0.75)));
+    assert_close(cross_entropy(&[0.0, 1.0, 0.0], &[0.2, 0.7, 0.1]), -ln(0.7));
+}
+
+#[test]
+fn both_measures_are_asymmetric() {
+    let (a, b) = ([0.9, 0.1], [0.5, 0.5]);
+    assert_close(kl(&a, &b), 0.9 * 1.8f64.ln() + 0.1 * 0.2f64.ln());
+    assert_close(kl(&b, &a), 0.5 * (0.5f64 / 0.9).ln() + 0.5 * 5.0f64.ln());
+    assert!(cross_entropy(&a, &b) != cross_entropy(&b, &a));
+
+    // Cross-entropy is KL plus the query's entropy.
+    let mut rng = SplitMix64::new(1);
+    for _ in 0..20 {
+        let p = softmax(&mut rng, 6);
+        let q = softmax(&mut rng, 6);
+        let entropy: f32 = -p.iter().map(|&x| x * x.ln()).sum::<f32>();
+        assert_close(cross_entropy(&p, &q), (kl(&p, &q) + entropy) as f64);
+        assert!(kl(&p, &q) >= 0.0);
+    }
+}
+
+#[test]
+fn zero_entries_are_floored_to_a_large_finite_distance() {
+    // The zero entry is floored at 1e-10 and contributes 0.5 * ln(0.5 / 1e-10).
+    let distance = kl(&[0.5, 0.5], &[1.0, 0.0]);
+    assert!(distance.is_finite());
+    assert_close(distance, 0.5 * (0.5f64 / 1e-10).ln() + 0.5 * 0.5f64.ln());
+    assert!(cross_entropy(&[0.5, 0.5], &[1.0, 0.0]).is_finite());
+    assert!(distance > kl(&[0.5, 0.5], &[0.99, 0.01]));
+}
+
+#[test]
+fn renormalization_scales_each_side_to_sum_to_one() {
+    let renormalized = KLDivergenceDistance::new(true);
+    assert_close(renormalized.compute_distance_f32(&[2.0, 2.0], &[1.0, 3.0]), kl(&[0.5, 0.5], &[0.25, 0.75]) as f64);
+    assert!(kl(&[2.0, 2.0], &[1.0, 3.0]) != renormalized.compute_distance_f32(&[2.0, 2.0], &[1.0, 3.0]));
+    let renormalized = CrossEntropyDistance::new(true);
+    assert_close(renormalized.compute_distance_f32(&[3.0, 3.0], &[5.0, 5.0]), 2.0f64.ln());
+}
+
+#[test]
+fn invalid_inputs_give_nan_or_errors() {
+    let kl_measure = KLDivergenceDistance::new(false);
+    let ce_measure = CrossEntropyDistance::new(false);
+    for (a, b) in [([0.5, -0.1], [0.5, 0.5]), ([0.5, 0.5], [f32::NAN, 0.5]), ([f32::INFINITY, 0.0], [0.5, 0.5])] {
+        assert!(kl_measure.compute_distance_f32(&a, &b).is_nan());
+        assert!(ce_measure.compute_distance_f32(&a, &b).is_nan());
+        let error = kl_measure.try_distance(&a, &b).unwrap_err();
+        assert!(error.to_string().contains("must be finite and non-negative"), "{}", error);
+        assert!(ce_measure.try_distance(&a, &b).is_err());
+    }
+    assert!(kl_measure.try_distance(&[0.5, 0.5], &[1.0]).is_err());
+    let error = KLDivergenceDistance::new(true).try_distance(&[0.0, 0.0], &[0.5, 0.5]).unwrap_err();
+    assert_eq!(error.to_string(), "Query probabilities sum to 0");
+    assert!(CrossEntropyDistance::new(true).compute_distance_f32(&[0.5, 0.5], &[0.0, 0.0]).is_nan());
+    assert_close(kl_measure.try_distance(&[0.5, 0.5], &[0.25, 0.75]).unwrap(), 0.5 * (4.0f64 / 3.0).ln());
+}
+
+fn softmax(rng: &mut SplitMix64, dim: usize) -> Vec<f32> {
+    let logits: Vec<f32> = (0..dim).map(|_| 2.0 * rng.next_normal()).collect();
+    let sum: f32 = logits.iter().map(|l| l.exp()).sum();
+    logits.iter().map(|l| l.exp() / sum).collect()
+}
+
+#[test]
+fn registered_by_name_and_usable_for_retrieval() {
+    let mut rng = SplitMix64::new(2);
+    let rows: Vec<Vec<f32>> = (0..200).map(|_| softmax(&mut rng, 8)).collect();
+    let mut rankings = Vec::new();
+    for name in ["KLDivergenceDistance", "CrossEntropyDistance"] {
+        let measure = distance_measures::get_distance_measure_by_name(name).unwrap();
+        assert_eq!(measure.name(), name);
+        let retriever = ScannRetriever::new(DenseDataset::new(rows.clone(), 8), measure, 10);
+        let results = retriever.search(&DatapointPtr::new(rows[17].clone())).unwrap();
+        rankings.push(results.iter().map(|&(docid, _)| docid).collect::<Vec<_>>());
+    }
+    // KL finds the query's own row at distance ~0, and cross-entropy ranks
+    // rows the same way for a fixed query.
+    assert_eq!(rankings[0][0], 17);
+    assert_eq!(rankings[0], rankings[1]);
+}
@@ -0,0 +1,102 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Column statistics behind int8 quantization, and the clip quantile
+//! persisted alongside leaf codes.
+
+mod common;
+
+use common::{random_dataset, scratch_dir};
+use scann::distance_measures::SquaredL2Distance;
+use scann::leaf_codes::{FileLeafCodeStore, LeafCodeStore};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, ScannRetriever};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DenseDataset, COLUMN_STATS_HISTOGRAM_BINS};
+use std::sync::Arc;
+
+fn int8_codes(clip_quantile: Option<f32>) -> Box<Int8Codes> {
+    Box::new(Int8Codes::new(Int8QuantizationConfig { clip_quantile }))
+}
+
+#[test]
+fn column_stats_match_a_per_column_scan() {
+    let mut data = random_dataset(5000, 6, 1);
+    // A constant column and a column with one far outlier.
+    for (i, row) in data.data.iter_mut().enumerate() {
+        row[2] = 3.0;
+        row[4] = if i == 17 { 1000.0 } else { i as f32 / 100.0 };
+    }
+    let stats = data.column_stats();
+    assert_eq!(stats.len(), 6);
+    for (d, column) in stats.iter().enumerate() {
+        let values: Vec<f32> = data.data.iter().map(|row| row[d]).collect();
+        let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        let var = values.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        assert_eq!((column.min, column.max), (min, max), "column {}", d);
+        assert!((column.mean - mean).abs() < 1e-9 * (1.0 + mean.abs()), "column {}", d);
+        assert!((column.std - var.sqrt()).abs() < 1e-9 * (1.0 + var.sqrt()), "column {}", d);
+
+        let mut histogram = vec![0u64; COLUMN_STATS_HISTOGRAM_BINS];
+        let width = (max - min).max(f32::MIN_POSITIVE);
+        for &v in &values {
+            let bin = (((v - min) / width) * COLUMN_STATS_HISTOGRAM_BINS as f32) as usize;
+            histogram[bin.min(COLUMN_STATS_HISTOGRAM_BINS - 1)] += 1;
+        }
+        assert_eq!(column.histogram, histogram, "column {}", d);
+    }
+    assert_eq!(stats[2].std, 0.0);
+    assert_eq!(stats[4].histogram[COLUMN_STATS_HISTOGRAM_BINS - 1], 1);
+
+    let empty = DenseDataset::<f32>::new(Vec::new(), 3).column_stats();
+    assert_eq!(empty.len(), 3);
+    assert!(empty.iter().all(|c| c.min == 0.0 && c.max == 0.0 && c.histogram.iter().all(|&n| n == 0)));
+}
+
+#[test]
+fn leaf_codes_persist_the_clip_quantile() {
+    let data = random_dataset(2000, 8, 2);
+    let dir = scratch_dir("clip");
+    let blob = dir.join("index.blob");
+    let codes = dir.join("leaf_codes.bin");
+    let clipped = ScannRetriever::new(data, Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    clipped.build_partitions(8, &options).unwrap();
+    clipped.pack_blob(&blob).unwrap();
+    // Same rows and partitioning, quantized without clipping.
+    let unclipped = ScannRetriever::load_blob(&blob, Box::new(SquaredL2Distance::new()), 10).unwrap();
+
+    clipped.register_derived_data(int8_codes(Some(0.99))).unwrap();
+    unclipped.register_derived_data(int8_codes(None)).unwrap();
+    clipped.write_leaf_codes(&codes).unwrap();
+
+    let store = Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap());
+    assert_eq!(store.clip_quantile(), Some(0.99));
+    let error = unclipped.set_leaf_code_store(Some(store.clone())).unwrap_err();
+    assert!(error.to_string().contains("clip_quantile"), "{}", error);
+    clipped.set_leaf_code_store(Some(store)).unwrap();
+    let report = clipped.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+
+    // Unclipped codes round-trip as None rather than a sentinel.
+    unclipped.write_leaf_codes(&codes).unwrap();
+    let store = Arc::new(FileLeafCodeStore::open(&codes, 1 << 20).unwrap());
+    assert_eq!(store.clip_quantile(), None);
+    unclipped.set_leaf_code_store(Some(store.clone())).unwrap();
+    assert!(clipped.set_leaf_code_store(Some(store)).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
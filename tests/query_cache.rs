@@ -0,0 +1,123 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The result cache: exact global capacity, LRU eviction, and hits and
+//! invalidation through a retriever.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::query_cache::{CacheCounters, CacheKey, QueryCache};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+
+fn key(i: usize) -> CacheKey {
+    CacheKey::new(&[i as f32], vec![0])
+}
+
+#[test]
+fn capacity_is_enforced_exactly_across_shards() {
+    for capacity in [1, 5, 17, 40] {
+        let cache = QueryCache::new(capacity);
+        for i in 0..200 {
+            cache.insert(key(i), i);
+            assert!(cache.len() <= capacity, "capacity {}: {} entries", capacity, cache.len());
+        }
+        assert_eq!(cache.len(), capacity);
+        assert_eq!(cache.capacity(), capacity);
+        assert_eq!(cache.counters().evictions, (200 - capacity) as u64);
+        // The survivors are the most recent inserts.
+        for i in 200 - capacity..200 {
+            assert_eq!(cache.get(&key(i)), Some(i), "capacity {}", capacity);
+        }
+    }
+    let disabled = QueryCache::new(0);
+    disabled.insert(key(0), 0);
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn eviction_drops_the_least_recently_used_entry() {
+    let cache = QueryCache::new(3);
+    for i in 0..3 {
+        cache.insert(key(i), i);
+    }
+    // Touch 0 so 1 becomes the oldest.
+    assert_eq!(cache.get(&key(0)), Some(0));
+    cache.insert(key(3), 3);
+    assert_eq!(cache.get(&key(1)), None);
+    // Re-inserting an existing key refreshes it without growing the cache.
+    cache.insert(key(2), 20);
+    assert_eq!(cache.len(), 3);
+    cache.insert(key(4), 4);
+    assert_eq!(cache.get(&key(0)), None);
+    assert_eq!(cache.get(&key(2)), Some(20));
+    assert_eq!(cache.get(&key(3)), Some(3));
+    assert_eq!(cache.get(&key(4)), Some(4));
+    assert_eq!(
+        cache.counters(),
+        CacheCounters {
+            hits: 4,
+            misses: 2,
+            evictions: 2,
+        }
+    );
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+fn line_retriever(n: usize) -> ScannRetriever {
+    let rows = (0..n).map(|i| vec![i as f32, 0.0]).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 3)
+}
+
+#[test]
+fn repeated_queries_hit_and_mutations_invalidate() {
+    let retriever = line_retriever(50);
+    retriever.enable_result_cache(8);
+    let query = DatapointPtr::new(vec![10.2, 0.0]);
+    let options = SearchOptions::default();
+
+    let (first, _) = retriever.search_with_options(&query, &options).unwrap();
+    let (second, _) = retriever.search_with_options(&query, &options).unwrap();
+    assert_eq!(first, second);
+    let metrics = retriever.metrics();
+    assert_eq!((metrics.cache_hits, metrics.cache_misses, metrics.cache_entries), (1, 1, 1));
+
+    // Different options are a different entry.
+    let k2 = SearchOptions {
+        k: Some(2),
+        ..SearchOptions::default()
+    };
+    assert_eq!(retriever.search_with_options(&query, &k2).unwrap().0, first[..2]);
+    assert_eq!(retriever.metrics().cache_misses, 2);
+
+    // A closer point added after caching must show up.
+    let added = retriever.add(&[10.2, 0.0]).unwrap();
+    let (after_add, _) = retriever.search_with_options(&query, &options).unwrap();
+    assert_eq!(after_add[0], (added, 0.0));
+    retriever.remove(added).unwrap();
+    assert_eq!(retriever.search_with_options(&query, &options).unwrap().0, first);
+    assert_eq!(retriever.metrics().cache_hits, 1);
+}
+
+#[test]
+fn the_retriever_cache_never_exceeds_its_capacity() {
+    let retriever = line_retriever(50);
+    retriever.enable_result_cache(3);
+    for i in 0..20 {
+        let query = DatapointPtr::new(vec![i as f32 + 0.5, 0.0]);
+        retriever.search_with_options(&query, &SearchOptions::default()).unwrap();
+    }
+    let metrics = retriever.metrics();
+    assert_eq!((metrics.cache_entries, metrics.cache_evictions), (3, 17));
+}
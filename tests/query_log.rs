@@ -0,0 +1,145 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Query logging and offline replay against the logged results.
+
+mod common;
+
+use common::{random_rows, scratch_dir};
+use scann::distance_measures::SquaredL2Distance;
+use scann::evaluation;
+use scann::query_log::{self, QueryLogger};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const NUM_LEAVES: usize = 16;
+
+fn log_path(name: &str) -> PathBuf {
+    scratch_dir(name).join("queries.log")
+}
+
+fn partitioned_retriever() -> ScannRetriever {
+    let retriever = ScannRetriever::new(
+        DenseDataset::new(random_rows(2000, 8, 12), 8),
+        Box::new(SquaredL2Distance::new()),
+        10,
+    );
+    retriever.build_partitions(NUM_LEAVES, &KMeansTreeTrainingOptions::new()).unwrap();
+    retriever
+}
+
+fn exhaustive() -> SearchOptions {
+    SearchOptions {
+        leaves_to_search: Some(NUM_LEAVES),
+        ..SearchOptions::default()
+    }
+}
+
+// Searches `queries` with logging on and returns their results.
+fn log_queries(retriever: &ScannRetriever, path: &Path, queries: &[Vec<f32>]) -> Vec<Vec<(usize, f32)>> {
+    let logger = Arc::new(QueryLogger::create(path, 1 << 20, 64).unwrap());
+    retriever.set_query_logger(Some(logger.clone()));
+    let results = queries
+        .iter()
+        .map(|q| retriever.search_with_options(&DatapointPtr::new(q.clone()), &exhaustive()).unwrap().0)
+        .collect();
+    retriever.set_query_logger(None);
+    logger.close();
+    assert_eq!(logger.dropped(), 0);
+    results
+}
+
+#[test]
+fn logged_records_hold_the_query_params_and_results() {
+    let retriever = partitioned_retriever();
+    let path = log_path("records");
+    let queries = random_rows(8, 8, 3);
+    let results = log_queries(&retriever, &path, &queries);
+
+    let records = query_log::read_query_log(&path).unwrap();
+    assert_eq!(records.len(), queries.len());
+    for ((record, query), results) in records.iter().zip(&queries).zip(&results) {
+        assert_eq!((record.k, record.leaves_to_search), (10, Some(NUM_LEAVES)));
+        assert_eq!(&record.query, query);
+        assert_eq!(&record.results, results);
+        assert!(record.timestamp_us > 0);
+    }
+    assert!(records.windows(2).all(|w| w[0].timestamp_us <= w[1].timestamp_us));
+
+    // A search without a logger writes nothing.
+    retriever.search(&DatapointPtr::new(queries[0].clone())).unwrap();
+    assert_eq!(query_log::read_query_log(&path).unwrap().len(), queries.len());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_with_identical_params_agrees_and_degraded_params_show_the_drop() {
+    let retriever = partitioned_retriever();
+    let path = log_path("replay");
+    let queries = random_rows(30, 8, 4);
+    log_queries(&retriever, &path, &queries);
+
+    let report = evaluation::replay_log(&retriever, &path, &SearchOptions::default()).unwrap();
+    assert_eq!(report.num_queries, 30);
+    assert_eq!(report.mean_recall, 1.0);
+    assert!(report.mean_replay_latency_us >= 0.0 && report.mean_logged_latency_us >= 0.0);
+
+    let degraded = SearchOptions {
+        leaves_to_search: Some(1),
+        ..SearchOptions::default()
+    };
+    let report = evaluation::replay_log(&retriever, &path, &degraded).unwrap();
+    assert!(report.mean_recall < 0.9, "{:?}", report);
+    assert!(report.mean_recall > 0.0, "{:?}", report);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn size_cap_drops_records_instead_of_growing_the_log() {
+    let retriever = partitioned_retriever();
+    let path = log_path("cap");
+    // Each record holds a 32-byte query and ten results, so 400 bytes
+    // fits only a couple of them.
+    let logger = Arc::new(QueryLogger::create(&path, 400, 64).unwrap());
+    retriever.set_query_logger(Some(logger.clone()));
+    for query in random_rows(10, 8, 5) {
+        retriever.search_with_options(&DatapointPtr::new(query), &exhaustive()).unwrap();
+    }
+    retriever.set_query_logger(None);
+    logger.close();
+
+    let records = query_log::read_query_log(&path).unwrap();
+    assert!(!records.is_empty() && records.len() < 10, "{} records", records.len());
+    assert_eq!(records.len() as u64 + logger.dropped(), 10);
+    assert!(std::fs::metadata(&path).unwrap().len() <= 400);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn truncated_logs_are_rejected() {
+    let retriever = partitioned_retriever();
+    let path = log_path("truncated");
+    log_queries(&retriever, &path, &random_rows(2, 8, 6));
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+    let message = query_log::read_query_log(&path).unwrap_err().to_string();
+    assert!(message.contains("Truncated record"), "{}", message);
+    assert!(evaluation::replay_log(&retriever, &path, &SearchOptions::default()).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(query_log::read_query_log(&path).is_err());
+}
@@ -0,0 +1,162 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! QuickConfig in its "key: value" text form and, with the serde feature, its
+//! JSON form: defaults, round trips, rejected keys and values, and
+//! `quick::build_index` taking either format.
+
+use scann::quick::{self, LeafCount, QuickConfig};
+use scann::util::DatapointPtr;
+
+#[test]
+fn text_configs_set_the_named_fields() {
+    let text = "# tuned by hand\nmeasure: DotProductDistance\nk: 4\n\nnum_leaves: 17\nspilling_factor: 1.5\nint8_codes: true\n";
+    let expected = QuickConfig {
+        measure: "DotProductDistance".to_string(),
+        k: 4,
+        num_leaves: LeafCount::Fixed(17),
+        spilling_factor: 1.5,
+        int8_codes: true,
+        ..QuickConfig::default()
+    };
+    assert_eq!(QuickConfig::parse(text).unwrap(), expected);
+    assert_eq!(QuickConfig::parse("").unwrap(), QuickConfig::default());
+    assert_eq!(QuickConfig::parse("num_leaves: none").unwrap().num_leaves, LeafCount::None);
+}
+
+#[test]
+fn unknown_keys_list_the_expected_ones() {
+    let err = QuickConfig::parse("k: 3\nleaves: 8").unwrap_err();
+    assert!(err.to_string().contains("line 2: unknown key 'leaves'"), "{}", err);
+    assert!(err.to_string().contains("num_leaves"), "{}", err);
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    for (text, needle) in [
+        ("k: 0", "k must be at least 1"),
+        ("k: -1", "invalid value '-1' for 'k'"),
+        ("k: 2.5", "invalid value '2.5' for 'k'"),
+        ("num_leaves: many", "'num_leaves'"),
+        ("int8_codes: 1", "'int8_codes'"),
+        ("spilling_factor: inf", "'spilling_factor'"),
+        ("k 3", "is not 'key: value'"),
+    ] {
+        let err = QuickConfig::parse(text).unwrap_err();
+        assert!(err.to_string().contains(needle), "{}: {}", text, err);
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn to_json_round_trips_through_from_json() {
+    let configs = [
+        QuickConfig::default(),
+        QuickConfig {
+            measure: "Dot\"Product\\Distance".to_string(),
+            k: 3,
+            num_leaves: LeafCount::Fixed(17),
+            kmeans_iterations: 2,
+            spilling_factor: 1.25,
+            int8_codes: true,
+            norm_cache: true,
+        },
+        QuickConfig {
+            num_leaves: LeafCount::None,
+            spilling_factor: 1e-7,
+            ..QuickConfig::default()
+        },
+    ];
+    for config in configs {
+        let json = config.to_json();
+        assert_eq!(QuickConfig::from_json(&json).unwrap(), config, "{}", json);
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn from_json_matches_the_text_format() {
+    let json = r#"{
+        "measure": "DotProductDistance",
+        "k": 4,
+        "num_leaves": "none",
+        "spilling_factor": 1.5,
+        "int8_codes": true
+    }"#;
+    let text = "measure: DotProductDistance\nk: 4\nnum_leaves: none\nspilling_factor: 1.5\nint8_codes: true\n";
+    assert_eq!(QuickConfig::from_json(json).unwrap(), QuickConfig::parse(text).unwrap());
+    assert_eq!(QuickConfig::from_json("").unwrap(), QuickConfig::default());
+    assert_eq!(QuickConfig::from_json(" {} ").unwrap(), QuickConfig::default());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn from_json_rejects_unknown_keys_with_the_expected_list() {
+    let err = QuickConfig::from_json(r#"{"k": 3, "leaves": 8}"#).unwrap_err();
+    assert!(err.to_string().contains("'leaves' is unknown"), "{}", err);
+    assert!(err.to_string().contains("num_leaves"), "{}", err);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn from_json_rejects_invalid_values() {
+    for (json, needle) in [
+        (r#"{"k": 0}"#, "k must be at least 1"),
+        (r#"{"k": -1}"#, "'k'"),
+        (r#"{"k": 2.5}"#, "'k'"),
+        (r#"{"k": "3"}"#, "'k'"),
+        (r#"{"measure": 3}"#, "'measure'"),
+        (r#"{"num_leaves": "many"}"#, "'num_leaves'"),
+        (r#"{"int8_codes": 1}"#, "'int8_codes'"),
+        (r#"{"spilling_factor": null}"#, "'spilling_factor': invalid value null"),
+        (r#"{"k": 3, "k": 4}"#, "appears twice"),
+        (r#"{"k": [3]}"#, "'k': invalid value [3]"),
+        (r#"[1, 2]"#, "expected a config object"),
+        (r#"{"k": 3"#, "not valid JSON"),
+        (r#"{"k": 3} x"#, "trailing characters"),
+        (r#"{"measure": "unterminated}"#, "EOF while parsing"),
+    ] {
+        let err = QuickConfig::from_json(json).unwrap_err();
+        assert!(err.to_string().contains(needle), "{}: {}", json, err);
+    }
+}
+
+#[test]
+fn build_index_takes_a_text_config() {
+    let data: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32, 0.0]).collect();
+    let query = DatapointPtr::new(vec![20.2, 0.0]);
+    let retriever = quick::build_index(&data, "k: 3\nnum_leaves: none").unwrap();
+    let docids: Vec<usize> = retriever.search(&query).unwrap().iter().map(|&(d, _)| d).collect();
+    assert_eq!(docids, vec![20, 21, 19]);
+    assert!(quick::build_index(&data, "bogus: 1").is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn build_index_takes_a_json_config() {
+    let data: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32, 0.0]).collect();
+    let query = DatapointPtr::new(vec![20.2, 0.0]);
+    let retriever = quick::build_index(&data, r#"{"k": 3, "num_leaves": "none"}"#).unwrap();
+    let docids: Vec<usize> = retriever.search(&query).unwrap().iter().map(|&(d, _)| d).collect();
+    assert_eq!(docids, vec![20, 21, 19]);
+    assert!(quick::build_index(&data, r#"{"bogus": 1}"#).is_err());
+}
+
+#[test]
+#[cfg(not(feature = "serde"))]
+fn json_configs_need_the_serde_feature() {
+    let data: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32, 0.0]).collect();
+    let err = quick::build_index(&data, r#"{"k": 3}"#).err().unwrap();
+    assert!(err.to_string().contains("needs scann built with --features serde"), "{}", err);
+}
@@ -0,0 +1,214 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory replicas from rebuild_with: untouched components stay shared,
+//! changed ones take effect, and the source keeps serving unchanged.
+
+mod common;
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::leaf_codes::FileLeafCodeStore;
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, RebuildPlan, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, Normalization};
+use std::sync::Arc;
+use std::thread;
+
+const DIM: usize = 8;
+
+// Rows spread wider than unit variance.
+fn spread_rows(n: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rows = common::random_rows(n, DIM, seed);
+    rows.iter_mut().flatten().for_each(|x| *x *= 3.0);
+    rows
+}
+
+fn training(max_iterations: i32) -> KMeansTreeTrainingOptions {
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = max_iterations;
+    options
+}
+
+fn source_retriever() -> ScannRetriever {
+    let retriever =
+        ScannRetriever::new(DenseDataset::new(spread_rows(1000, 1), DIM), Box::new(SquaredL2Distance::new()), 5);
+    retriever.build_partitions(8, &training(5)).unwrap();
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever
+}
+
+fn search(retriever: &ScannRetriever, query: &[f32]) -> Vec<(usize, f32)> {
+    let options = SearchOptions { leaves_to_search: Some(3), ..SearchOptions::default() };
+    retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap().0
+}
+
+fn all_results(retriever: &ScannRetriever, queries: &[Vec<f32>]) -> Vec<Vec<(usize, f32)>> {
+    queries.iter().map(|query| search(retriever, query)).collect()
+}
+
+#[test]
+fn untouched_components_are_shared_not_copied() {
+    let source = source_retriever();
+    let rescoring = Arc::new(DenseDataset::new(spread_rows(1000, 2), DIM));
+    source.attach_rescoring_dataset(rescoring.clone(), Box::new(SquaredL2Distance::new())).unwrap();
+    let dir = std::env::temp_dir().join(format!("scann_rebuild_{}_shared", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("leaf_codes.bin");
+    source.write_leaf_codes(&path).unwrap();
+    let store = Arc::new(FileLeafCodeStore::open(&path, 1 << 20).unwrap());
+    source.set_leaf_code_store(Some(store.clone())).unwrap();
+    let tree = source.partitioning().unwrap();
+    assert_eq!((Arc::strong_count(&tree), Arc::strong_count(&store), Arc::strong_count(&rescoring)), (2, 2, 2));
+
+    // Only k changes: the snapshot, rescoring attachment and store are the
+    // source's own. The store is held by the shared snapshot, so its count
+    // does not move either.
+    let replica = source.rebuild_with(&RebuildPlan { k: Some(3), ..RebuildPlan::default() }).unwrap();
+    assert!(Arc::ptr_eq(&replica.partitioning().unwrap(), &tree));
+    assert_eq!(Arc::strong_count(&tree), 2);
+    assert_eq!(Arc::strong_count(&store), 2);
+    assert_eq!(Arc::strong_count(&rescoring), 2);
+    let query = DatapointPtr::new(spread_rows(1, 3).remove(0));
+    let leaves = SearchOptions { leaves_to_search: Some(3), ..SearchOptions::default() };
+    let (_, stats) = replica.search_with_options(&query, &leaves).unwrap();
+    assert_eq!(stats.leaf_cache_hits + stats.leaf_cache_misses, 3);
+    assert_eq!(replica.search(&query).unwrap().len(), 3);
+    assert_eq!(source.search(&query).unwrap().len(), 5);
+    let rescored = SearchOptions { rescore_with_attached: true, ..SearchOptions::default() };
+    assert_eq!(
+        replica.search_with_options(&query, &rescored).unwrap().0,
+        source.search_with_options(&query, &SearchOptions { k: Some(3), ..rescored.clone() }).unwrap().0
+    );
+
+    // Re-quantizing copies the snapshot but keeps the partitioning and the
+    // rescoring attachment; the leaf code store is built from the old codes
+    // and is dropped.
+    let plan = RebuildPlan {
+        quantization: Some(Some(Int8QuantizationConfig { clip_quantile: Some(0.9) })),
+        ..RebuildPlan::default()
+    };
+    let requantized = source.rebuild_with(&plan).unwrap();
+    assert!(Arc::ptr_eq(&requantized.partitioning().unwrap(), &tree));
+    assert_eq!(Arc::strong_count(&tree), 3);
+    assert_eq!(Arc::strong_count(&store), 2);
+    assert_eq!(Arc::strong_count(&rescoring), 2);
+    let (_, stats) = requantized.search_with_options(&query, &leaves).unwrap();
+    assert_eq!((stats.leaf_cache_hits, stats.leaf_cache_misses), (0, 0));
+    drop((replica, requantized));
+    assert_eq!(Arc::strong_count(&store), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn changed_components_take_effect() {
+    let source = source_retriever();
+    let before = source.int8_error_summary().unwrap();
+
+    let clipped = Int8QuantizationConfig { clip_quantile: Some(0.6) };
+    let replica = source.rebuild_with(&RebuildPlan { quantization: Some(Some(clipped)), ..RebuildPlan::default() });
+    let after = replica.unwrap().int8_error_summary().unwrap();
+    assert!(after.max_abs_error > before.max_abs_error, "{:?} vs {:?}", after, before);
+    let unquantized = source.rebuild_with(&RebuildPlan { quantization: Some(None), ..RebuildPlan::default() });
+    assert!(unquantized.unwrap().int8_error_summary().is_err());
+
+    let retrained = source
+        .rebuild_with(&RebuildPlan { retrain_partitions: Some((4, training(3))), ..RebuildPlan::default() })
+        .unwrap();
+    assert_eq!(retrained.partitioning().unwrap().num_leaves(), 4);
+    assert_eq!(source.partitioning().unwrap().num_leaves(), 8);
+
+    let normalized = source
+        .rebuild_with(&RebuildPlan {
+            normalization: Some(Normalization::UnitL2),
+            retrain_partitions: Some((8, training(3))),
+            ..RebuildPlan::default()
+        })
+        .unwrap();
+    let norm = |row: Vec<f32>| row.iter().map(|v| v * v).sum::<f32>().sqrt();
+    assert!((norm(normalized.get_by_docid(7).unwrap()) - 1.0).abs() < 1e-5);
+    assert!((norm(source.get_by_docid(7).unwrap()) - 1.0).abs() > 1e-3);
+    // Later additions to the replica are normalized too.
+    let docid = normalized.add(&[3.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]).unwrap();
+    assert_eq!(normalized.get_by_docid(docid).unwrap()[..2], [0.6, 0.8]);
+
+    let rescoring = Arc::new(DenseDataset::new(spread_rows(1000, 2), DIM));
+    source.attach_rescoring_dataset(rescoring, Box::new(SquaredL2Distance::new())).unwrap();
+    let detached = source.rebuild_with(&RebuildPlan { reordering: Some(false), ..RebuildPlan::default() }).unwrap();
+    let rescored = SearchOptions { rescore_with_attached: true, ..SearchOptions::default() };
+    let query = DatapointPtr::new(spread_rows(1, 3).remove(0));
+    assert!(detached.search_with_options(&query, &rescored).is_err());
+    assert!(source.search_with_options(&query, &rescored).is_ok());
+}
+
+#[test]
+fn incompatible_plans_are_rejected_up_front() {
+    let source = source_retriever();
+    let rejected = |plan: RebuildPlan, message: &str| {
+        let error = source.rebuild_with(&plan).err().unwrap();
+        assert!(error.to_string().contains(message), "{}", error);
+    };
+    rejected(RebuildPlan { k: Some(0), ..RebuildPlan::default() }, "k must be at least 1");
+    rejected(RebuildPlan { retrain_partitions: Some((0, training(1))), ..RebuildPlan::default() }, "zero leaves");
+    rejected(RebuildPlan { reordering: Some(true), ..RebuildPlan::default() }, "no rescoring dataset");
+    rejected(
+        RebuildPlan { normalization: Some(Normalization::UnitL2), ..RebuildPlan::default() },
+        "must also retrain the partitioning",
+    );
+
+    let dataset = DenseDataset::new(spread_rows(50, 4), DIM);
+    let normalized = ScannRetriever::new(dataset, Box::new(SquaredL2Distance::new()), 5)
+        .with_normalization(Normalization::UnitL2)
+        .unwrap();
+    let error = normalized
+        .rebuild_with(&RebuildPlan { normalization: Some(Normalization::None), ..RebuildPlan::default() })
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("Cannot remove normalization"), "{}", error);
+
+    let fork = source.fork().unwrap();
+    assert!(fork.rebuild_with(&RebuildPlan::default()).is_err());
+}
+
+#[test]
+fn the_source_serves_identical_results_while_replicas_are_built() {
+    let source = source_retriever();
+    let queries = spread_rows(30, 5);
+    let expected = all_results(&source, &queries);
+    thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            for _ in 0..20 {
+                assert_eq!(all_results(&source, &queries), expected);
+            }
+        });
+        for plan in [
+            RebuildPlan { k: Some(2), ..RebuildPlan::default() },
+            RebuildPlan { quantization: Some(None), ..RebuildPlan::default() },
+            RebuildPlan { retrain_partitions: Some((6, training(3))), ..RebuildPlan::default() },
+            RebuildPlan {
+                normalization: Some(Normalization::UnitL2),
+                retrain_partitions: Some((8, training(3))),
+                ..RebuildPlan::default()
+            },
+        ] {
+            source.rebuild_with(&plan).unwrap();
+        }
+        reader.join().unwrap();
+    });
+    assert_eq!(all_results(&source, &queries), expected);
+    assert_eq!(source.partitioning().unwrap().num_leaves(), 8);
+}
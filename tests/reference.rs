@@ -0,0 +1,132 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The f64 reference searcher against hand-computed distances, and as an
+//! oracle for the f32 brute-force path.
+
+use scann::distance_measures::{AccumulatorPrecision, DotProductDistance, SquaredL2Distance};
+use scann::evaluation;
+use scann::reference::{self, BruteForceF64Searcher, ReferenceMetric};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+fn docids<T>(results: &[(usize, T)]) -> Vec<usize> {
+    results.iter().map(|&(docid, _)| docid).collect()
+}
+
+#[test]
+fn distances_match_hand_computed_values() {
+    let a = [1.0, 2.0, 2.0];
+    let b = [3.0, 0.0, 1.0];
+    // a.b = 3 + 0 + 2 = 5; |a - b|^2 = 4 + 4 + 1 = 9; |a| = 3, |b| = sqrt(10).
+    assert_eq!(reference::distance(ReferenceMetric::DotProduct, &a, &b), -5.0);
+    assert_eq!(reference::distance(ReferenceMetric::SquaredL2, &a, &b), 9.0);
+    assert_eq!(reference::distance(ReferenceMetric::L2, &a, &b), 3.0);
+    let cosine = reference::distance(ReferenceMetric::Cosine, &a, &b);
+    assert!((cosine - (1.0 - 5.0 / (3.0 * 10f64.sqrt()))).abs() < 1e-15, "{}", cosine);
+    assert_eq!(reference::distance(ReferenceMetric::Cosine, &a, &[0.0; 3]), 1.0);
+
+    assert_eq!(ReferenceMetric::from_measure_name("SquaredL2Distance"), Some(ReferenceMetric::SquaredL2));
+    assert_eq!(ReferenceMetric::from_measure_name("NormalizedDotProductDistance"), Some(ReferenceMetric::Cosine));
+    assert_eq!(ReferenceMetric::from_measure_name("L1Distance"), None);
+}
+
+#[test]
+fn sums_are_compensated() {
+    // 1e16 + ten ones: each one alone rounds away in plain f64 summation.
+    let mut a = vec![1e8];
+    a.extend([1.0; 10]);
+    assert_eq!(reference::distance(ReferenceMetric::DotProduct, &a, &a), -(1e16 + 10.0));
+    let naive: f64 = a.iter().map(|x| x * x).sum();
+    assert_eq!(naive, 1e16);
+}
+
+#[test]
+fn search_batched_and_range_agree() {
+    let rows = vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0], vec![3.0, 4.0]];
+    let searcher =
+        BruteForceF64Searcher::new(&DenseDataset::new(rows, 2), vec![40, 30, 20, 10], ReferenceMetric::L2).unwrap();
+    assert_eq!(searcher.metric(), ReferenceMetric::L2);
+    // Docids 30 and 20 tie at distance 1 and break by docid.
+    assert_eq!(searcher.search(&[0.0, 0.0], 3).unwrap(), vec![(40, 0.0), (20, 1.0), (30, 1.0)]);
+    assert_eq!(searcher.search_range(&[0.0, 0.0], 1.0).unwrap(), searcher.search(&[0.0, 0.0], 3).unwrap());
+    assert_eq!(searcher.search_range(&[0.0, 0.0], 4.99).unwrap().len(), 3);
+    assert_eq!(searcher.distance_to(&[0.0, 0.0], 3), 5.0);
+
+    let queries = DenseDataset::new(vec![vec![0.0, 0.0], vec![3.0, 3.5]], 2);
+    let batched = searcher.search_batched(&queries, 1).unwrap();
+    assert_eq!(docids(&batched[0]), vec![40]);
+    assert_eq!(docids(&batched[1]), vec![10]);
+
+    let error = searcher.search(&[0.0], 1).unwrap_err();
+    assert!(error.to_string().contains("Query has dimensionality 1, reference dataset has 2"), "{}", error);
+    let error = BruteForceF64Searcher::new(&queries, vec![1], ReferenceMetric::L2).err().unwrap();
+    assert!(error.to_string().contains("Got 1 docids for 2 rows"), "{}", error);
+}
+
+#[test]
+fn f32_l2_brute_force_matches_the_reference_far_from_the_origin() {
+    // Rows clustered at 1e4 differ only in the low bits; differences are
+    // taken before squaring, so f32 keeps the exact order.
+    let mut rng = SplitMix64::new(1);
+    let rows: Vec<Vec<f32>> = (0..500).map(|_| (0..8).map(|_| 1e4 + rng.next_f32()).collect()).collect();
+    let dataset = DenseDataset::new(rows, 8);
+    let searcher = BruteForceF64Searcher::new(&dataset, (0..500).collect(), ReferenceMetric::SquaredL2).unwrap();
+    let retriever = ScannRetriever::new(dataset, Box::new(SquaredL2Distance::new()), 10);
+    for _ in 0..20 {
+        let query: Vec<f32> = (0..8).map(|_| 1e4 + rng.next_f32()).collect();
+        let datapoint = DatapointPtr::new(query.clone());
+        let (results, _) = retriever.search_with_options(&datapoint, &SearchOptions::default()).unwrap();
+        let expected = searcher.search(&query, 10).unwrap();
+        assert_eq!(docids(&results), docids(&expected));
+        for (&(_, distance), &(_, exact)) in results.iter().zip(&expected) {
+            assert!((distance as f64 - exact).abs() <= 1e-5 * exact.max(1.0), "{} vs {}", distance, exact);
+        }
+    }
+}
+
+#[test]
+fn the_reference_exposes_f32_cancellation_in_dot_products() {
+    // A small leading term followed by terms of 1e8 that cancel: f32 loses
+    // the small term in any summation order.
+    let row = |small: f32| {
+        let mut row = vec![small];
+        row.extend([1e8; 8]);
+        row.extend([-1e8; 8]);
+        row
+    };
+    let dataset = DenseDataset::new(vec![row(1.0), row(3.0), row(2.0)], 17);
+    let query = vec![1.0; 17];
+    let searcher = BruteForceF64Searcher::new(&dataset, vec![0, 1, 2], ReferenceMetric::DotProduct).unwrap();
+    let expected = searcher.search(&query, 3).unwrap();
+    assert_eq!(expected, vec![(1, -3.0), (2, -2.0), (0, -1.0)]);
+
+    let retriever = ScannRetriever::new(dataset, Box::new(DotProductDistance::new()), 3);
+    let datapoint = DatapointPtr::new(query);
+    let (f32_results, _) = retriever.search_with_options(&datapoint, &SearchOptions::default()).unwrap();
+    assert_ne!(docids(&f32_results), docids(&expected));
+    let f64_options = SearchOptions { accumulator_precision: AccumulatorPrecision::F64, ..SearchOptions::default() };
+    let (f64_results, _) = retriever.search_with_options(&datapoint, &f64_options).unwrap();
+    assert_eq!(docids(&f64_results), docids(&expected));
+}
+
+#[test]
+fn exact_ground_truth_uses_the_reference() {
+    let dataset = DenseDataset::new(vec![vec![0.0], vec![2.0], vec![5.0]], 1);
+    let queries = DenseDataset::new(vec![vec![1.9], vec![4.0]], 1);
+    let truth = evaluation::exact_ground_truth(&dataset, vec![7, 8, 9], "SquaredL2Distance", &queries, 2).unwrap();
+    assert_eq!(truth, vec![vec![8, 7], vec![9, 8]]);
+    let error = evaluation::exact_ground_truth(&dataset, vec![7, 8, 9], "L1Distance", &queries, 2).unwrap_err();
+    assert!(error.to_string().contains("No reference implementation for distance measure 'L1Distance'"), "{}", error);
+}
@@ -0,0 +1,100 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Reordering sizes suggested from measured int8 rank displacement.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, ScannRetriever};
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+use std::collections::HashSet;
+
+const DIM: usize = 8;
+const K: usize = 10;
+
+// Gaussian rows plus one outlier of magnitude `outlier` in every dimension.
+// The outlier sets the int8 scale, so a larger one means coarser codes for
+// every other row.
+fn retriever_with_noise(outlier: f32) -> ScannRetriever {
+    let mut rng = SplitMix64::new(3);
+    let mut rows: Vec<Vec<f32>> = (0..800).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    rows.push(vec![outlier; DIM]);
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(SquaredL2Distance::new()), K);
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    retriever.build_reordering_summary(100, K, 11).unwrap();
+    retriever
+}
+
+#[test]
+fn suggestion_grows_with_recall_and_noise() {
+    let quiet = retriever_with_noise(8.0);
+    let noisy = retriever_with_noise(120.0);
+    let targets = [0.5, 0.8, 0.9, 0.95, 0.99];
+    for retriever in [&quiet, &noisy] {
+        let sizes: Vec<usize> = targets.iter().map(|&t| retriever.suggest_reordering_size(t, K)).collect();
+        assert!(sizes.windows(2).all(|w| w[0] <= w[1]), "{:?}", sizes);
+        assert!(sizes[0] >= K);
+    }
+    for &target in &targets {
+        assert!(noisy.suggest_reordering_size(target, K) >= quiet.suggest_reordering_size(target, K));
+    }
+    assert!(noisy.suggest_reordering_size(0.99, K) > noisy.suggest_reordering_size(0.5, K));
+    assert!(noisy.suggest_reordering_size(0.99, K) > quiet.suggest_reordering_size(0.99, K));
+}
+
+#[test]
+fn suggested_size_reaches_the_target_recall() {
+    let retriever = retriever_with_noise(120.0);
+    let mut rng = SplitMix64::new(29);
+    for target in [0.8f32, 0.95] {
+        let pre_k = retriever.suggest_reordering_size(target, K);
+        let mut found = 0;
+        let queries = 100;
+        for _ in 0..queries {
+            let query: Vec<f32> = (0..DIM).map(|_| rng.next_normal()).collect();
+            let query_ptr = DatapointPtr::new(query.clone());
+            let exact: HashSet<usize> = retriever.search(&query_ptr).unwrap().iter().map(|r| r.0).collect();
+            // First pass over int8 codes, then exact reordering of pre_k.
+            let mut reordered: Vec<(usize, f32)> = retriever
+                .search_int8_codes(&query, pre_k)
+                .unwrap()
+                .iter()
+                .map(|&(docid, _)| (docid, retriever.distance_to_docid(&query_ptr, docid).unwrap()))
+                .collect();
+            reordered.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            found += reordered.iter().take(K).filter(|r| exact.contains(&r.0)).count();
+        }
+        let recall = found as f32 / (queries * K) as f32;
+        assert!(recall >= target, "pre_k {} reached recall {} < {}", pre_k, recall, target);
+    }
+}
+
+#[test]
+fn summary_is_required_for_a_suggestion() {
+    let dataset = DenseDataset::new(vec![vec![0.0; DIM]; 4], DIM);
+    let retriever = ScannRetriever::new(dataset, Box::new(SquaredL2Distance::new()), K);
+    assert_eq!(retriever.suggest_reordering_size(0.99, K), K);
+    let error = retriever.build_reordering_summary(10, K, 0).unwrap_err();
+    assert!(error.to_string().contains("Int8 codes must be registered"), "{}", error);
+
+    let noisy = retriever_with_noise(120.0);
+    let summary = noisy.reordering_summary().unwrap();
+    assert_eq!((summary.sample_size, summary.k, summary.displacements.len()), (100, K, 100 * K));
+    assert!(summary.displacements.windows(2).all(|w| w[0] <= w[1]));
+    retriever.set_reordering_summary(Some(summary));
+    assert_eq!(retriever.suggest_reordering_size(0.99, K), noisy.suggest_reordering_size(0.99, K));
+}
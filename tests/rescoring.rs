@@ -0,0 +1,203 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! First-pass search on the primary index, final distances from an attached
+//! second representation of the corpus.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{
+    CompositeDistance, CompositePart, DistanceMeasure, DotProductDistance, SquaredL2Distance,
+};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DenseDataset};
+use std::sync::Arc;
+
+const N: usize = 200;
+
+// A 2-d retrieval embedding with an unrelated 5-d reranking embedding.
+fn retriever_with_attachment() -> (ScannRetriever, Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    let primary = random_rows(N, 2, 1);
+    let rich = random_rows(N, 5, 2);
+    let retriever = ScannRetriever::new(DenseDataset::new(primary.clone(), 2), Box::new(SquaredL2Distance::new()), 5);
+    retriever
+        .attach_rescoring_dataset(Arc::new(DenseDataset::new(rich.clone(), 5)), Box::new(DotProductDistance::new()))
+        .unwrap();
+    (retriever, primary, rich)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    DotProductDistance::new().compute_distance_f32(a, b)
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn rescoring_options(candidates: usize, blend: Option<f32>, query: &[f32]) -> SearchOptions {
+    SearchOptions {
+        rescore_with_attached: true,
+        rescore_candidates: Some(candidates),
+        rescore_blend_weight: blend,
+        rescoring_query: Some(query.to_vec()),
+        ..SearchOptions::default()
+    }
+}
+
+#[test]
+fn replacement_orders_by_the_attached_measure() {
+    let (retriever, _, rich) = retriever_with_attachment();
+    let primary_query = DatapointPtr::new(vec![0.3, -0.2]);
+    let rich_query = [0.5, -1.0, 0.25, 2.0, 0.0];
+
+    // Rescoring every row gives the attached measure's exact top-k.
+    let (results, _) = retriever
+        .search_with_options(&primary_query, &rescoring_options(N, None, &rich_query))
+        .unwrap();
+    let mut expected: Vec<(usize, f32)> = rich.iter().enumerate().map(|(i, row)| (i, dot(&rich_query, row))).collect();
+    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+    assert_eq!(results, expected[..5]);
+
+    // With fewer candidates only the primary top-20 are reranked.
+    let first_pass: Vec<usize> = retriever
+        .search_with_options(&primary_query, &SearchOptions { k: Some(20), ..SearchOptions::default() })
+        .unwrap()
+        .0
+        .iter()
+        .map(|r| r.0)
+        .collect();
+    let (results, _) = retriever
+        .search_with_options(&primary_query, &rescoring_options(20, None, &rich_query))
+        .unwrap();
+    let mut expected: Vec<(usize, f32)> = first_pass.iter().map(|&i| (i, dot(&rich_query, &rich[i]))).collect();
+    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+    assert_eq!(results, expected[..5]);
+}
+
+#[test]
+fn blending_mixes_primary_and_attached_distances() {
+    let (retriever, primary, rich) = retriever_with_attachment();
+    let primary_query = [0.3, -0.2];
+    let rich_query = [0.5, -1.0, 0.25, 2.0, 0.0];
+    let (results, _) = retriever
+        .search_with_options(&DatapointPtr::new(primary_query.to_vec()), &rescoring_options(N, Some(0.25), &rich_query))
+        .unwrap();
+    let mut expected: Vec<(usize, f32)> = (0..N)
+        .map(|i| (i, 0.75 * squared_l2(&primary_query, &primary[i]) + 0.25 * dot(&rich_query, &rich[i])))
+        .collect();
+    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+    assert_eq!(results.iter().map(|r| r.0).collect::<Vec<_>>(), expected[..5].iter().map(|r| r.0).collect::<Vec<_>>());
+    for (actual, expected) in results.iter().zip(&expected) {
+        assert!((actual.1 - expected.1).abs() < 1e-5, "{:?} vs {:?}", actual, expected);
+    }
+
+    let message = retriever
+        .search_with_options(&DatapointPtr::new(primary_query.to_vec()), &rescoring_options(N, Some(1.5), &rich_query))
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("rescore_blend_weight must be in [0, 1], got 1.5"), "{}", message);
+}
+
+#[test]
+fn rescored_ties_fall_to_the_lower_docid() {
+    // The primary ranks docids in reverse; the attached rows are all equal.
+    let primary: Vec<Vec<f32>> = (0..N).map(|i| vec![(N - i) as f32, 0.0]).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(primary, 2), Box::new(SquaredL2Distance::new()), 5);
+    let rich = Arc::new(DenseDataset::new(vec![vec![1.0; 5]; N], 5));
+    retriever.attach_rescoring_dataset(rich, Box::new(DotProductDistance::new())).unwrap();
+    let query = DatapointPtr::new(vec![0.0, 0.0]);
+    let (results, _) = retriever.search_with_options(&query, &rescoring_options(20, None, &[1.0; 5])).unwrap();
+    let docids: Vec<usize> = results.iter().map(|r| r.0).collect();
+    assert_eq!(docids, vec![N - 20, N - 19, N - 18, N - 17, N - 16]);
+    assert!(results.iter().all(|r| r.1 == -5.0), "{:?}", results);
+}
+
+#[test]
+fn size_mismatches_are_rejected_at_attach_time() {
+    let retriever = ScannRetriever::new(
+        DenseDataset::new(random_rows(N, 2, 1), 2),
+        Box::new(SquaredL2Distance::new()),
+        5,
+    );
+    let short = Arc::new(DenseDataset::new(random_rows(N - 1, 5, 2), 5));
+    let message = retriever
+        .attach_rescoring_dataset(short, Box::new(DotProductDistance::new()))
+        .unwrap_err()
+        .to_string();
+    assert_eq!(message, format!("Rescoring dataset has {} rows but the index has {}", N - 1, N));
+
+    let query = DatapointPtr::new(vec![0.0, 0.0]);
+    let message = retriever
+        .search_with_options(&query, &rescoring_options(N, None, &[0.0; 5]))
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("requires an attached rescoring dataset"), "{}", message);
+}
+
+#[test]
+fn stale_attachments_and_bad_queries_fail_the_search() {
+    let (retriever, _, _) = retriever_with_attachment();
+    let query = DatapointPtr::new(vec![0.0, 0.0]);
+    let message = retriever
+        .search_with_options(&query, &rescoring_options(N, None, &[0.0; 4]))
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("Rescoring query has dimensionality 4, rescoring dataset has 5"), "{}", message);
+
+    retriever.add(&[1.0, 1.0]).unwrap();
+    let message = retriever
+        .search_with_options(&query, &rescoring_options(N, None, &[0.0; 5]))
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("re-attach it"), "{}", message);
+
+    retriever.detach_rescoring_dataset();
+    assert!(retriever.search_with_options(&query, &SearchOptions::default()).is_ok());
+}
+
+#[test]
+fn blobs_record_the_attachment() {
+    let (retriever, _, _) = retriever_with_attachment();
+    let dir = std::env::temp_dir().join(format!("scann_rescoring_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.blob");
+    retriever.pack_blob(&path).unwrap();
+    let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), 5).unwrap();
+
+    let query = DatapointPtr::new(vec![0.3, -0.2]);
+    let options = rescoring_options(50, Some(0.5), &[0.5, -1.0, 0.25, 2.0, 0.0]);
+    assert_eq!(
+        loaded.search_with_options(&query, &options).unwrap().0,
+        retriever.search_with_options(&query, &options).unwrap().0
+    );
+
+    // Measures that cannot be rebuilt by name are refused rather than lost.
+    let composite = CompositeDistance::new(
+        vec![CompositePart {
+            dims: 0..5,
+            measure: Box::new(SquaredL2Distance::new()),
+            weight: 1.0,
+        }],
+        5,
+    )
+    .unwrap();
+    retriever
+        .attach_rescoring_dataset(Arc::new(DenseDataset::new(random_rows(N, 5, 3), 5)), Box::new(composite))
+        .unwrap();
+    let message = retriever.pack_blob(dir.join("composite.blob")).unwrap_err().to_string();
+    assert!(message.contains("cannot be recorded in a blob"), "{}", message);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,194 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! RETRO cross-attention capture and padding masks over ragged retrieved
+//! neighbors.
+
+use nalgebra::DMatrix;
+use scann::proto::RetroConfig;
+use scann::retro::model::ChunkRetriever;
+use scann::retro::RETRO;
+use std::error::Error;
+
+const CHUNK_SIZE: usize = 4;
+const PAD_ID: u32 = 0;
+
+// Returns fixed neighbors for each complete chunk of the sequence.
+struct MockRetriever {
+    chunks: Vec<Vec<Vec<u32>>>,
+}
+
+impl ChunkRetriever for MockRetriever {
+    fn retrieve_chunks(&self, seq: &[u32], chunk_size: usize) -> Result<Vec<Vec<Vec<u32>>>, Box<dyn Error>> {
+        Ok(self.chunks.iter().take(seq.len() / chunk_size).cloned().collect())
+    }
+}
+
+fn config() -> RetroConfig {
+    let mut config = RetroConfig::new();
+    config.num_tokens = 32;
+    config.max_seq_len = 64;
+    config.enc_dim = 8;
+    config.dec_dim = 8;
+    config.enc_depth = 1;
+    config.dec_depth = 2;
+    config.heads = 2;
+    config.dim_head = 4;
+    config.chunk_size = CHUNK_SIZE as u32;
+    config.dec_cross_attn_layers = vec![1, 2];
+    config.pad_id = PAD_ID;
+    config
+}
+
+fn model_with(chunks: Vec<Vec<Vec<u32>>>) -> RETRO {
+    let mut model = RETRO::new(config(), None);
+    model.set_retriever(Some(Box::new(MockRetriever { chunks })));
+    model
+}
+
+// Three chunks with 2, 3 and 1 neighbors; the second chunk's last neighbor
+// is all padding.
+fn ragged_chunks() -> Vec<Vec<Vec<u32>>> {
+    vec![
+        vec![vec![5, 6, 7], vec![9, 0]],
+        vec![vec![11], vec![12, 13, 14, 15], vec![PAD_ID; 4]],
+        vec![vec![20, 21]],
+    ]
+}
+
+fn seq(len: usize) -> Vec<u32> {
+    (0..len).map(|i| 1 + i as u32 % 31).collect()
+}
+
+fn assert_close(a: &DMatrix<f32>, b: &DMatrix<f32>) {
+    assert_eq!(a.shape(), b.shape());
+    for (x, y) in a.iter().zip(b.iter()) {
+        assert!((x - y).abs() <= 1e-4 * (1.0 + x.abs()), "{} vs {}", x, y);
+    }
+}
+
+#[test]
+fn capture_is_off_by_default() {
+    let model = model_with(ragged_chunks());
+    model.forward(&seq(12), None).unwrap();
+    assert!(model.attention_summary().is_empty());
+}
+
+#[test]
+fn summary_is_layers_by_chunks_by_neighbors() {
+    let mut model = model_with(ragged_chunks());
+    model.set_capture_attention(true);
+    model.forward(&seq(12), None).unwrap();
+    let summary = model.attention_summary();
+    assert_eq!(summary.iter().map(|s| s.layer).collect::<Vec<_>>(), vec![1, 2]);
+    for layer in &summary {
+        // Ragged chunks are padded to the most populated one.
+        assert_eq!(layer.mass.len(), 3);
+        assert!(layer.mass.iter().all(|chunk| chunk.len() == 3));
+        assert!(layer.head_weights.is_none());
+    }
+
+    model.set_capture_attention(false);
+    assert!(model.attention_summary().is_empty());
+}
+
+#[test]
+fn masses_per_chunk_sum_to_at_most_one() {
+    let mut model = model_with(ragged_chunks());
+    model.set_capture_attention(true);
+    model.forward(&seq(12), None).unwrap();
+    for layer in model.attention_summary() {
+        for chunk in &layer.mass {
+            let total: f32 = chunk.iter().sum();
+            assert!(total <= 1.0 + 1e-5, "layer {}: {:?}", layer.layer, chunk);
+            assert!(chunk.iter().all(|&m| m >= 0.0 && m.is_finite()), "{:?}", chunk);
+        }
+    }
+}
+
+#[test]
+fn padding_neighbors_receive_no_attention() {
+    let mut model = model_with(ragged_chunks());
+    model.set_capture_attention(true);
+    model.forward(&seq(12), None).unwrap();
+    for layer in model.attention_summary() {
+        let mass = &layer.mass;
+        // Padding slots of the short chunks.
+        assert!(mass[0][2].abs() < 1e-6, "{:?}", mass);
+        assert!(mass[2][1].abs() < 1e-6 && mass[2][2].abs() < 1e-6, "{:?}", mass);
+        // The all-padding neighbor.
+        assert!(mass[1][2].abs() < 1e-6, "{:?}", mass);
+        // Real neighbors take all of the mass.
+        for chunk in mass {
+            assert!((chunk.iter().sum::<f32>() - 1.0).abs() < 1e-4, "{:?}", mass);
+        }
+    }
+}
+
+#[test]
+fn padding_does_not_change_the_logits() {
+    let mut model = model_with(vec![vec![vec![5, 6]], vec![vec![11, 12]], vec![vec![20]]]);
+    let unpadded = model.forward(&seq(12), None).unwrap();
+    model.set_retriever(Some(Box::new(MockRetriever {
+        chunks: vec![
+            vec![vec![5, 6, PAD_ID], vec![PAD_ID; 3]],
+            vec![vec![11, 12], vec![]],
+            vec![vec![20]],
+        ],
+    })));
+    assert_close(&model.forward(&seq(12), None).unwrap(), &unpadded);
+}
+
+#[test]
+fn ragged_sequences_leave_the_partial_chunk_without_neighbors() {
+    let mut model = model_with(ragged_chunks());
+    model.set_capture_attention(true);
+    let logits = model.forward(&seq(10), None).unwrap();
+    assert_eq!(logits.shape(), (10, 32));
+    assert!(logits.iter().all(|v| v.is_finite()));
+    let summary = model.attention_summary();
+    assert_eq!(summary.len(), 2);
+    // Two complete chunks; the first two chunks' neighbors are padded to 3.
+    assert!(summary.iter().all(|layer| layer.mass.len() == 2));
+    assert!(summary.iter().all(|layer| layer.mass.iter().all(|chunk| chunk.len() == 3)));
+}
+
+#[test]
+fn explicit_masks_match_retrieved_neighbors() {
+    let model = RETRO::new(config(), None);
+    let retrieved = DMatrix::from_fn(6, 8, |r, c| ((r * 8 + c) as f32 * 0.37).sin());
+    let mask = [true, false, true, true, true, false];
+    let logits = model.forward_with_mask(&seq(12), &retrieved, &mask).unwrap();
+    assert!(logits.iter().all(|v| v.is_finite()));
+
+    // Masked rows have no effect on the output.
+    let mut scrambled = retrieved.clone();
+    scrambled.row_mut(1).fill(9.0);
+    scrambled.row_mut(5).fill(-9.0);
+    assert_close(&model.forward_with_mask(&seq(12), &scrambled, &mask).unwrap(), &logits);
+
+    let err = model.forward_with_mask(&seq(12), &retrieved, &mask[..5]).unwrap_err();
+    assert_eq!(err.to_string(), "Retrieved mask has 5 entries for 6 retrieved rows");
+}
+
+#[test]
+fn continuation_scores_use_the_masks() {
+    let model = model_with(ragged_chunks());
+    let prefix = seq(6);
+    let candidates = vec![vec![3, 4], vec![3, 4, 8, 9]];
+    let scores = model.score_continuations(&prefix, &candidates).unwrap();
+    assert_eq!(scores.len(), 2);
+    assert!(scores.iter().all(|s| s.is_finite()), "{:?}", scores);
+}
@@ -0,0 +1,111 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Encoded-neighbor caching across incremental RETRO decoding steps.
+
+use nalgebra::DMatrix;
+use scann::proto::RetroConfig;
+use scann::retro::decoder::DecoderState;
+use scann::retro::RETRO;
+
+const CHUNK_SIZE: usize = 4;
+const NEIGHBORS: usize = 2;
+const DIM: usize = 8;
+
+fn model() -> RETRO {
+    let mut config = RetroConfig::new();
+    config.num_tokens = 32;
+    config.max_seq_len = 256;
+    config.enc_dim = DIM as u32;
+    config.dec_dim = DIM as u32;
+    config.enc_depth = 1;
+    config.dec_depth = 3;
+    config.heads = 2;
+    config.dim_head = 4;
+    config.chunk_size = CHUNK_SIZE as u32;
+    // Every decoder layer cross-attends, sharing one encoding.
+    config.dec_cross_attn_layers = vec![1, 2, 3];
+    RETRO::new(config, None)
+}
+
+// Neighbor ids of each complete chunk of a `len`-token sequence, and their
+// embeddings, one row per neighbor.
+fn retrieve(len: usize) -> (Vec<Vec<usize>>, DMatrix<f32>) {
+    let ids: Vec<Vec<usize>> = (0..len / CHUNK_SIZE).map(|c| (0..NEIGHBORS).map(|n| c * 7 + n).collect()).collect();
+    let flat: Vec<usize> = ids.iter().flatten().copied().collect();
+    let rows = DMatrix::from_fn(flat.len(), DIM, |r, c| ((flat[r] * DIM + c) as f32 * 0.61).sin());
+    (ids, rows)
+}
+
+fn argmax(logits: &DMatrix<f32>) -> u32 {
+    let last = logits.row(logits.nrows() - 1);
+    last.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0 as u32
+}
+
+#[test]
+fn cross_attention_layers_share_one_encoding() {
+    let model = model();
+    let seq: Vec<u32> = (1..=12).collect();
+    let (ids, rows) = retrieve(seq.len());
+    let mut state = DecoderState::new();
+    model.forward_with_state(&seq, &rows, &ids, &mut state).unwrap();
+    assert_eq!(state.retrieved_cache.encoder_calls(), 1);
+}
+
+#[test]
+fn generation_encodes_once_per_retrieval_event() {
+    let model = model();
+    let mut tokens: Vec<u32> = vec![3, 1, 4, 1];
+    let mut state = DecoderState::new();
+    let mut retrieval_events = 0;
+    let mut last_chunks = 0;
+    for step in 0..200 {
+        let num_chunks = tokens.len() / CHUNK_SIZE;
+        if num_chunks != last_chunks {
+            retrieval_events += 1;
+            last_chunks = num_chunks;
+        }
+        let (ids, rows) = retrieve(tokens.len());
+        let cached = model.forward_with_state(&tokens, &rows, &ids, &mut state).unwrap();
+        let uncached = model.forward(&tokens, Some(&rows)).unwrap();
+        assert_eq!(cached, uncached, "step {}", step);
+        tokens.push(argmax(&cached));
+    }
+    assert_eq!(tokens.len(), 204);
+    assert_eq!(retrieval_events, 50);
+    assert_eq!(state.retrieved_cache.encoder_calls(), retrieval_events);
+}
+
+#[test]
+fn changed_neighbors_are_encoded_again() {
+    let model = model();
+    let seq: Vec<u32> = (1..=8).collect();
+    let (mut ids, rows) = retrieve(seq.len());
+    let mut state = DecoderState::new();
+    model.forward_with_state(&seq, &rows, &ids, &mut state).unwrap();
+    model.forward_with_state(&seq, &rows, &ids, &mut state).unwrap();
+    assert_eq!(state.retrieved_cache.encoder_calls(), 1);
+
+    ids[1][0] = 99;
+    model.forward_with_state(&seq, &rows, &ids, &mut state).unwrap();
+    assert_eq!(state.retrieved_cache.encoder_calls(), 2);
+
+    state.retrieved_cache.invalidate();
+    model.forward_with_state(&seq, &rows, &ids, &mut state).unwrap();
+    assert_eq!(state.retrieved_cache.encoder_calls(), 3);
+
+    let err = model.forward_with_state(&seq, &rows, &ids[..1], &mut state).unwrap_err();
+    assert_eq!(err.to_string(), "Got neighbor ids for 1 chunks, sequence has 2 complete chunks");
+}
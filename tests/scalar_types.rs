@@ -0,0 +1,121 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Non-f32 datapoint scalars: u8 descriptors and f64 rows score like their
+//! f32 conversions under every built-in measure.
+
+use scann::distance_measures::{
+    AngularDistance, BinaryJaccardDistance, ChebyshevDistance, CosineDistance, DistanceMeasure, DotProductDistance,
+    GeneralHammingDistance, GeneralJaccardDistance, L1Distance, L2Distance, SquaredL2Distance,
+};
+use scann::retrieval::ScannRetriever;
+use scann::util::{self, DatapointPtr, DenseDataset, SplitMix64, ToF32Scalar};
+
+const DIM: usize = 16;
+
+// Byte descriptors spanning the whole range, so products overflow any
+// narrower accumulator.
+fn descriptors(n: usize, seed: u64) -> DenseDataset<u8> {
+    let mut rng = SplitMix64::new(seed);
+    let rows = (0..n).map(|_| (0..DIM).map(|_| rng.next_below(256) as u8).collect()).collect();
+    DenseDataset::new(rows, DIM)
+}
+
+fn widen<T: ToF32Scalar>(row: &[T]) -> Vec<f32> {
+    row.iter().map(|&v| v.to_f32()).collect()
+}
+
+fn assert_same<M: DistanceMeasure, T: ToF32Scalar>(measure: &M, a: &[T], b: &[T]) {
+    let typed = measure.compute_distance(&DatapointPtr::new(a.to_vec()), &DatapointPtr::new(b.to_vec()));
+    let widened = measure.compute_distance_f32(&widen(a), &widen(b));
+    let tolerance = 1e-6 * widened.abs().max(1.0);
+    assert!((typed - widened).abs() <= tolerance, "{}: {} vs {}", measure.name(), typed, widened);
+}
+
+fn check_all_measures<T: ToF32Scalar>(a: &[T], b: &[T]) {
+    assert_same(&SquaredL2Distance::new(), a, b);
+    assert_same(&L2Distance::new(), a, b);
+    assert_same(&L1Distance::new(), a, b);
+    assert_same(&ChebyshevDistance::new(), a, b);
+    assert_same(&CosineDistance::new(), a, b);
+    assert_same(&AngularDistance::new(), a, b);
+    assert_same(&DotProductDistance::new(), a, b);
+    assert_same(&GeneralHammingDistance::new(), a, b);
+    assert_same(&GeneralJaccardDistance::new(), a, b);
+    assert_same(&BinaryJaccardDistance::new(), a, b);
+}
+
+#[test]
+fn u8_rows_score_like_their_f32_conversions() {
+    let data = descriptors(20, 1);
+    for pair in data.data.windows(2) {
+        check_all_measures(&pair[0], &pair[1]);
+    }
+    let max = vec![255u8; DIM];
+    assert_eq!(
+        SquaredL2Distance::new().compute_distance(&DatapointPtr::new(max.clone()), &DatapointPtr::new(vec![0u8; DIM])),
+        255.0 * 255.0 * DIM as f32
+    );
+    assert_eq!(util::dot_product(&DatapointPtr::new(max.clone()), &DatapointPtr::new(max)), 65025.0 * DIM as f32);
+}
+
+#[test]
+fn other_integer_and_f64_rows_score_like_their_f32_conversions() {
+    check_all_measures(&[-128i8, -1, 0, 1, 127], &[127i8, 5, -7, 0, -128]);
+    check_all_measures(&[-32768i16, -300, 0, 300, 32767], &[1i16, 2, 3, 4, 5]);
+    check_all_measures(&[0u16, 1, 1000, 40000, 65535], &[65535u16, 2, 3, 4, 0]);
+    check_all_measures(&[0.1f64, -2.5, 1e-3, 7.25, 3.0], &[0.3f64, 1.5, -4.0, 0.0, 2.0]);
+}
+
+#[test]
+fn conversions_are_exact_except_f64_rounding() {
+    assert_eq!(255u8.to_f32(), 255.0);
+    assert_eq!((-128i8).to_f32(), -128.0);
+    assert_eq!(i16::MIN.to_f32(), -32768.0);
+    assert_eq!(u16::MAX.to_f32(), 65535.0);
+    assert_eq!(0.1f64.to_f32(), 0.1f32);
+    assert_eq!(16_777_217f64.to_f32(), 16_777_216.0);
+    assert_eq!(1e300f64.to_f32(), f32::INFINITY);
+}
+
+#[test]
+fn u8_datasets_support_column_stats_and_nearest_neighbours() {
+    let data = descriptors(200, 2);
+    let stats = data.column_stats();
+    assert_eq!(stats.len(), DIM);
+    for (d, column) in stats.iter().enumerate() {
+        let values: Vec<f32> = data.data.iter().map(|row| row[d] as f32).collect();
+        assert_eq!(column.min, values.iter().copied().fold(f32::INFINITY, f32::min));
+        assert_eq!(column.max, values.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+        let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        assert!((column.mean - mean).abs() < 1e-9, "column {}", d);
+    }
+
+    // A brute-force scan over the typed rows agrees with a retriever over
+    // the widened ones.
+    let widened = DenseDataset::new(data.data.iter().map(|row| widen(row)).collect(), DIM);
+    let retriever = ScannRetriever::new(widened, Box::new(SquaredL2Distance::new()), 1);
+    let measure = SquaredL2Distance::new();
+    for query in descriptors(10, 3).data {
+        let typed_query = DatapointPtr::new(query.clone());
+        let (best, distance) = data
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (i, measure.compute_distance(&typed_query, &DatapointPtr::new(row.clone()))))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        assert_eq!(retriever.search(&DatapointPtr::new(widen(&query))).unwrap(), vec![(best, distance)]);
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RETRO::score_continuations: agreement with naive full forward passes,
+//! one pass over the shared prefix, and padded candidates.
+
+use nalgebra::DMatrix;
+use scann::proto::RetroConfig;
+use scann::retro::decoder::DecoderState;
+use scann::retro::model::ChunkRetriever;
+use scann::retro::RETRO;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+const CHUNK_SIZE: usize = 4;
+const MAX_SEQ_LEN: usize = 16;
+const NUM_TOKENS: u32 = 32;
+const PREFIX: [u32; 6] = [3, 1, 4, 1, 5, 9];
+
+// Two neighbors per complete chunk, derived from the chunk's tokens, and a
+// record of every sequence retrieved for.
+struct TracingRetriever {
+    trace: Arc<Mutex<Vec<Vec<u32>>>>,
+}
+
+impl ChunkRetriever for TracingRetriever {
+    fn retrieve_chunks(&self, seq: &[u32], chunk_size: usize) -> Result<Vec<Vec<Vec<u32>>>, Box<dyn Error>> {
+        self.trace.lock().unwrap().push(seq.to_vec());
+        Ok(seq
+            .chunks_exact(chunk_size)
+            .map(|chunk| vec![vec![1 + chunk[0] % 7; chunk_size], vec![2 + chunk[1] % 11; chunk_size]])
+            .collect())
+    }
+}
+
+fn config() -> RetroConfig {
+    let mut config = RetroConfig::new();
+    config.num_tokens = NUM_TOKENS;
+    config.max_seq_len = MAX_SEQ_LEN as u32;
+    config.enc_dim = 8;
+    config.dec_dim = 8;
+    config.enc_depth = 1;
+    config.dec_depth = 2;
+    config.heads = 2;
+    config.dim_head = 4;
+    config.chunk_size = CHUNK_SIZE as u32;
+    config.dec_cross_attn_layers = vec![1, 2];
+    config
+}
+
+fn model() -> (RETRO, Arc<Mutex<Vec<Vec<u32>>>>) {
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut model = RETRO::new(config(), None);
+    model.set_retriever(Some(Box::new(TracingRetriever { trace: trace.clone() })));
+    (model, trace)
+}
+
+fn log_softmax(logits: &DMatrix<f32>, row: usize, token: u32) -> f64 {
+    let row = logits.row(row);
+    let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+    let sum: f64 = row.iter().map(|&v| (v as f64 - max).exp()).sum();
+    row[token as usize] as f64 - max - sum.ln()
+}
+
+// Mean per-token log-probability from one full forward pass over the
+// prefix and the candidate, retrieving for the whole sequence.
+fn naive_score(model: &RETRO, candidate: &[u32]) -> f32 {
+    let seq: Vec<u32> = PREFIX.iter().chain(&candidate[..candidate.len() - 1]).copied().collect();
+    let logits = model.forward(&seq, None).unwrap();
+    let total: f64 = candidate.iter().enumerate().map(|(j, &t)| log_softmax(&logits, PREFIX.len() - 1 + j, t)).sum();
+    (total / candidate.len() as f64) as f32
+}
+
+fn assert_matches_naive(model: &RETRO, candidates: &[Vec<u32>]) {
+    let scores = model.score_continuations(&PREFIX, candidates).unwrap();
+    assert_eq!(scores.len(), candidates.len());
+    for (candidate, &score) in candidates.iter().zip(&scores) {
+        let expected = naive_score(model, candidate);
+        assert!(score.is_finite() && score < 0.0, "{:?}: {}", candidate, score);
+        assert!((score - expected).abs() < 1e-4, "{:?}: {} vs naive {}", candidate, score, expected);
+    }
+}
+
+#[test]
+fn scores_match_naive_forward_passes_within_the_prefix_chunk() {
+    // The prefix holds one complete chunk; none of these complete another.
+    let (model, _) = model();
+    assert_matches_naive(&model, &[vec![2], vec![6, 5], vec![31, 30], vec![7]]);
+
+    let unretrieved = RETRO::new(config(), None);
+    let scores = unretrieved.score_continuations(&PREFIX, &[vec![2, 6]]).unwrap();
+    let logits = unretrieved.forward_without_retrieval(&[3, 1, 4, 1, 5, 9, 2]).unwrap();
+    let expected = (log_softmax(&logits, 5, 2) + log_softmax(&logits, 6, 6)) / 2.0;
+    assert!((scores[0] as f64 - expected).abs() < 1e-4, "{} vs {}", scores[0], expected);
+}
+
+#[test]
+fn scores_match_naive_forward_passes_across_chunk_boundaries() {
+    // Candidates of three or more tokens complete the second chunk, and the
+    // longest reaches the fourth.
+    let (model, _) = model();
+    assert_matches_naive(&model, &[vec![2, 6, 5], vec![8, 9, 7, 9, 3], vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]]);
+}
+
+#[test]
+fn the_prefix_is_decoded_and_retrieved_for_once() {
+    let (model, trace) = model();
+    let candidates = vec![vec![2], vec![6, 5], vec![3, 5, 8], vec![9, 7, 9, 3, 2]];
+    let mut state = DecoderState::new();
+    model.score_continuations_with_state(&PREFIX, &candidates, &mut state).unwrap();
+
+    // One pass over the prefix, then one per candidate with tokens to feed
+    // back; every position is decoded exactly once.
+    assert_eq!(state.forward_calls(), 1 + 3);
+    assert_eq!(state.rows_decoded(), PREFIX.len() + candidates.iter().map(|c| c.len() - 1).sum::<usize>());
+    // Only the two candidates completing a new chunk retrieve again, and
+    // only for the tokens past the prefix's complete chunks.
+    let trace = trace.lock().unwrap();
+    assert_eq!(trace.len(), 1 + 2);
+    assert_eq!(trace[0], PREFIX);
+    assert_eq!(trace[1], vec![5, 9, 3, 5]);
+    assert_eq!(trace[2], vec![5, 9, 9, 7, 9, 3]);
+}
+
+#[test]
+fn padding_and_unequal_lengths() {
+    let (model, _) = model();
+    let unpadded = model.score_continuations(&PREFIX, &[vec![2], vec![6, 5, 3]]).unwrap();
+    let padded = model.score_continuations(&PREFIX, &[vec![2, 0, 0], vec![6, 5, 3]]).unwrap();
+    assert_eq!(padded, unpadded);
+    // Scores are per token, so candidates of different lengths compare.
+    let single = model.score_continuations(&PREFIX, &[vec![6]]).unwrap()[0];
+    let longer = model.score_continuations(&PREFIX, &[vec![6, 5, 3]]).unwrap()[0];
+    assert_ne!(single, longer);
+    assert_eq!(model.score_continuations(&PREFIX, &[]).unwrap(), Vec::<f32>::new());
+
+    assert!(model.score_continuations(&PREFIX, &[vec![2], vec![0, 0]]).is_err());
+    assert!(model.score_continuations(&[], &[vec![2]]).is_err());
+    assert!(model.score_continuations(&PREFIX, &[vec![NUM_TOKENS]]).is_err());
+    // Up to max_seq_len positions are fed to the decoder.
+    let fits = vec![1; MAX_SEQ_LEN - PREFIX.len() + 1];
+    assert!(model.score_continuations(&PREFIX, std::slice::from_ref(&fits)).is_ok());
+    let error = model.score_continuations(&PREFIX, &[[fits, vec![1]].concat()]).err().unwrap();
+    assert!(error.to_string().contains("max_seq_len is 16"), "{}", error);
+}
@@ -0,0 +1,136 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rank-time score modifiers: reordering, dropping with backfill, and the
+//! payload field boost.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::score_modifier::{BoostMode, PayloadFieldBoost, ScoreModifier};
+use scann::util::{DatapointPtr, DenseDataset};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Rows at x = 0..n, so the distance to the origin is docid^2.
+fn line_retriever(n: usize) -> ScannRetriever {
+    let rows = (0..n).map(|i| vec![i as f32, 0.0]).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 4)
+}
+
+fn origin() -> DatapointPtr<f32> {
+    DatapointPtr::new(vec![0.0, 0.0])
+}
+
+fn docids(results: &[(usize, f32)]) -> Vec<usize> {
+    results.iter().map(|&(docid, _)| docid).collect()
+}
+
+#[derive(Debug)]
+struct Negate;
+
+impl ScoreModifier for Negate {
+    fn modify(&self, _index: usize, _docid: usize, raw_distance: f32) -> f32 {
+        -raw_distance
+    }
+}
+
+// Drops odd docids and records every call.
+#[derive(Debug, Default)]
+struct DropOdd {
+    calls: Mutex<Vec<usize>>,
+}
+
+impl ScoreModifier for DropOdd {
+    fn modify(&self, _index: usize, docid: usize, raw_distance: f32) -> f32 {
+        self.calls.lock().unwrap().push(docid);
+        if docid % 2 == 1 {
+            f32::INFINITY
+        } else {
+            raw_distance
+        }
+    }
+}
+
+fn with_modifier(modifier: Arc<dyn ScoreModifier>, candidates: Option<usize>) -> SearchOptions {
+    SearchOptions { score_modifier: Some(modifier), rescore_candidates: candidates, ..SearchOptions::default() }
+}
+
+#[test]
+fn an_inverting_modifier_reverses_the_candidate_pool() {
+    let retriever = line_retriever(20);
+    // Only the top k are rescored by default, so they come back reversed.
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(Arc::new(Negate), None)).unwrap();
+    assert_eq!(results, vec![(3, -9.0), (2, -4.0), (1, -1.0), (0, -0.0)]);
+    // With every row in the pool the farthest rows win.
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(Arc::new(Negate), Some(20))).unwrap();
+    assert_eq!(docids(&results), vec![19, 18, 17, 16]);
+}
+
+#[derive(Debug)]
+struct Constant;
+
+impl ScoreModifier for Constant {
+    fn modify(&self, _index: usize, _docid: usize, _raw_distance: f32) -> f32 {
+        1.0
+    }
+}
+
+#[test]
+fn modified_ties_fall_to_the_lower_docid() {
+    // Rows at x = n - docid, so the pool holds the highest docids.
+    let rows = (0..20).map(|i| vec![(20 - i) as f32, 0.0]).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), 4);
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(Arc::new(Constant), Some(8))).unwrap();
+    assert_eq!(results, vec![(12, 1.0), (13, 1.0), (14, 1.0), (15, 1.0)]);
+}
+
+#[test]
+fn dropped_candidates_are_backfilled_and_each_is_scored_once() {
+    let retriever = line_retriever(20);
+    let modifier = Arc::new(DropOdd::default());
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(modifier.clone(), None)).unwrap();
+    assert_eq!(results, vec![(0, 0.0), (2, 4.0), (4, 16.0), (6, 36.0)]);
+    // Calls stop once k candidates survive: 0..=6 in rank order, once each.
+    assert_eq!(*modifier.calls.lock().unwrap(), (0..=6).collect::<Vec<_>>());
+
+    // Fewer than k come back when the corpus runs out of survivors.
+    let retriever = line_retriever(3);
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(modifier, None)).unwrap();
+    assert_eq!(results, vec![(0, 0.0), (2, 4.0)]);
+}
+
+#[test]
+fn payload_field_boost_reorders_by_a_stored_float() {
+    let retriever = line_retriever(6);
+    // Freshness factors at offset 4, after a 4-byte prefix. Docid 5 has no
+    // payload and docid 4's payload is too short; both keep their distance.
+    let mut payloads = HashMap::new();
+    for (docid, factor) in [(0, 100.0f32), (1, 10.0), (2, 0.5), (3, 0.125)] {
+        let mut payload = vec![0xAB; 4];
+        payload.extend_from_slice(&factor.to_le_bytes());
+        payloads.insert(docid, payload);
+    }
+    payloads.insert(4, vec![0; 6]);
+    let payloads = Arc::new(payloads);
+
+    let multiply = Arc::new(PayloadFieldBoost::new(payloads.clone(), 4, BoostMode::Multiply, 1.0));
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(multiply, Some(6))).unwrap();
+    // 0: 0 * 100, 3: 9 * 0.125, 2: 4 * 0.5, 1: 1 * 10, then 4: 16 unchanged.
+    assert_eq!(results, vec![(0, 0.0), (3, 1.125), (2, 2.0), (1, 10.0)]);
+
+    let add = Arc::new(PayloadFieldBoost::new(payloads, 4, BoostMode::Add, -1.0));
+    let (results, _) = retriever.search_with_options(&origin(), &with_modifier(add, Some(6))).unwrap();
+    // 0: 0 - 100, 1: 1 - 10, 2: 4 - 0.5, 3: 9 - 0.125.
+    assert_eq!(results, vec![(0, -100.0), (1, -9.0), (2, 3.5), (3, 8.875)]);
+}
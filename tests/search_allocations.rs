@@ -0,0 +1,133 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counts heap allocations made by search_with_options once the thread's
+//! search arena is warm. Lives in its own test binary because it installs a
+//! global allocator.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{CancellationToken, DatapointPtr, DenseDataset, SplitMix64};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+const DIM: usize = 8;
+const K: usize = 10;
+
+fn retriever(n: usize, num_leaves: Option<usize>) -> ScannRetriever {
+    let mut rng = SplitMix64::new(n as u64);
+    let rows = (0..n).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, DIM), Box::new(SquaredL2Distance::new()), K);
+    if let Some(num_leaves) = num_leaves {
+        let mut options = KMeansTreeTrainingOptions::new();
+        options.max_iterations = 5;
+        retriever.build_partitions(num_leaves, &options).unwrap();
+    }
+    retriever.remove(3).unwrap();
+    retriever
+}
+
+// Allocations of one warm search, checked to be the same on every repeat.
+fn allocations_per_search(retriever: &ScannRetriever, query: &DatapointPtr<f32>, options: &SearchOptions) -> usize {
+    // Warm up: the swapped candidate buffers come back in either order, so
+    // both need to have grown once.
+    for _ in 0..2 {
+        retriever.search_with_options(query, options).unwrap();
+    }
+    let counts: Vec<usize> = (0..5)
+        .map(|_| {
+            allocations_during(|| {
+                let (results, _) = retriever.search_with_options(query, options).unwrap();
+                assert_eq!(results.len(), options.k.unwrap_or(K));
+            })
+        })
+        .collect();
+    assert!(counts.windows(2).all(|w| w[0] == w[1]), "{:?}", counts);
+    counts[0]
+}
+
+#[test]
+fn warm_searches_allocate_a_bounded_amount_independent_of_n() {
+    let query = DatapointPtr::new(vec![0.25f32; DIM]);
+    for num_leaves in [None, Some(16)] {
+        let options = SearchOptions {
+            leaves_to_search: num_leaves.map(|_| 4),
+            ..SearchOptions::default()
+        };
+        let small = allocations_per_search(&retriever(2000, num_leaves), &query, &options);
+        let large = allocations_per_search(&retriever(20000, num_leaves), &query, &options);
+        // The returned Vec plus the fused scan's fixed bookkeeping; none of
+        // it scales with the number of rows or candidates.
+        assert_eq!(small, large, "leaves {:?}", num_leaves);
+        assert!(small <= 4, "leaves {:?}: {} allocations per search", num_leaves, small);
+    }
+}
+
+#[test]
+fn an_early_error_hands_the_arena_buffers_back() {
+    let query = DatapointPtr::new(vec![0.25f32; DIM]);
+    let retriever = retriever(5000, None);
+    let cancelled = CancellationToken::new();
+    cancelled.cancel();
+    let failing = SearchOptions {
+        cancellation: Some(cancelled),
+        error_on_cancel: true,
+        ..SearchOptions::default()
+    };
+    // Cancellation skips the fused scan, so this path grows the arena's
+    // candidate buffers like any unfused search.
+    let unfused = SearchOptions {
+        cancellation: Some(CancellationToken::new()),
+        ..SearchOptions::default()
+    };
+    let steady = allocations_per_search(&retriever, &query, &unfused);
+    assert!(retriever.search_with_options(&query, &failing).is_err());
+    // A stranded buffer would be regrown by the next search.
+    let after_error = allocations_during(|| {
+        retriever.search_with_options(&query, &unfused).unwrap();
+    });
+    assert_eq!(after_error, steady);
+}
@@ -0,0 +1,116 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! AbsDotProductDistance and NegatedSquaredL2Distance: sign conventions and
+//! the neighbours the retriever returns under them.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{
+    AbsDotProductDistance, DistanceMeasure, DistanceMeasureKind, DotProductDistance, NegatedSquaredL2Distance,
+    SquaredL2Distance,
+};
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::collections::HashSet;
+
+const NUM_POINTS: usize = 300;
+const DIM: usize = 8;
+const K: usize = 10;
+
+fn retriever(measure: Box<dyn DistanceMeasure>, k: usize) -> ScannRetriever {
+    ScannRetriever::new(DenseDataset::new(random_rows(NUM_POINTS, DIM, 1), DIM), measure, k)
+}
+
+fn docids(results: &[(usize, f32)]) -> HashSet<usize> {
+    results.iter().map(|&(docid, _)| docid).collect()
+}
+
+#[test]
+fn distances_follow_the_sign_conventions() {
+    let a = [1.0, 2.0, -3.0];
+    let b = [4.0, -1.0, 2.0];
+    // <a, b> = 4 - 2 - 6 = -4; |a - b|^2 = 9 + 9 + 25 = 43.
+    assert_eq!(AbsDotProductDistance::new().compute_distance_f32(&a, &b), -4.0);
+    assert_eq!(DotProductDistance::new().compute_distance_f32(&a, &b), 4.0);
+    assert_eq!(NegatedSquaredL2Distance::new().compute_distance_f32(&a, &b), -43.0);
+    assert_eq!(SquaredL2Distance::new().compute_distance_f32(&a, &b), 43.0);
+    assert_eq!(AbsDotProductDistance::new().name(), "AbsDotProductDistance");
+    assert_eq!(NegatedSquaredL2Distance::new().name(), "NegatedSquaredL2Distance");
+
+    for (a, b) in random_rows(20, DIM, 2).chunks(2).map(|pair| (&pair[0], &pair[1])) {
+        for measure in [
+            Box::new(AbsDotProductDistance::new()) as Box<dyn DistanceMeasure>,
+            Box::new(NegatedSquaredL2Distance::new()),
+        ] {
+            let f32_path = measure.compute_distance_f32(a, b);
+            let f64_path = measure.compute_distance_f64_accumulated(a, b);
+            assert!((f32_path - f64_path).abs() <= 1e-5 * f64_path.abs().max(1.0), "{}", measure.name());
+            assert_eq!(measure.compute_distance_f32(a, b), measure.compute_distance_f32(b, a));
+            assert!(measure.compute_distance_f32(a, a) <= 0.0);
+        }
+    }
+}
+
+#[test]
+fn negated_squared_l2_neighbours_are_the_farthest_squared_l2_rows() {
+    let l2 = retriever(Box::new(SquaredL2Distance::new()), NUM_POINTS);
+    let negated = retriever(Box::new(NegatedSquaredL2Distance::new()), K);
+    for query in random_rows(20, DIM, 3) {
+        let query = DatapointPtr::new(query);
+        let ranked = l2.search(&query).unwrap();
+        let farthest: Vec<(usize, f32)> = ranked.iter().rev().take(K).map(|&(docid, d)| (docid, -d)).collect();
+        let found = negated.search(&query).unwrap();
+        assert_eq!(docids(&found), docids(&farthest));
+        for (&(_, got), &(_, expected)) in found.iter().zip(&farthest) {
+            assert!((got - expected).abs() <= 1e-5 * expected.abs(), "{} vs {}", got, expected);
+        }
+    }
+}
+
+#[test]
+fn abs_dot_neighbours_are_the_largest_inner_products_of_either_sign() {
+    let abs_dot = retriever(Box::new(AbsDotProductDistance::new()), K);
+    let dot = retriever(Box::new(DotProductDistance::new()), NUM_POINTS);
+    for query in random_rows(20, DIM, 4) {
+        let found = abs_dot.search(&DatapointPtr::new(query.clone())).unwrap();
+        // Rank every row by |<q, x>| from the signed scores.
+        let mut expected = dot.search(&DatapointPtr::new(query.clone())).unwrap();
+        expected.iter_mut().for_each(|(_, d)| *d = -d.abs());
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        expected.truncate(K);
+        assert_eq!(docids(&found), docids(&expected));
+        // The query and its negation have the same neighbours.
+        let negated: Vec<f32> = query.iter().map(|v| -v).collect();
+        assert_eq!(abs_dot.search(&DatapointPtr::new(negated)).unwrap(), found);
+    }
+}
+
+#[test]
+fn kinds_match_the_boxed_measures() {
+    for (name, kind) in [
+        ("AbsDotProductDistance", DistanceMeasureKind::AbsDotProduct),
+        ("NegatedSquaredL2Distance", DistanceMeasureKind::NegatedSquaredL2),
+    ] {
+        assert_eq!(DistanceMeasureKind::from_name(name), Some(kind));
+        let dataset = DenseDataset::new(random_rows(NUM_POINTS, DIM, 1), DIM);
+        let by_kind = ScannRetriever::with_measure_kind(dataset, kind, K);
+        let boxed = retriever(kind.to_measure(), K);
+        for query in random_rows(5, DIM, 5) {
+            let query = DatapointPtr::new(query);
+            assert_eq!(by_kind.search(&query).unwrap(), boxed.search(&query).unwrap(), "{}", name);
+        }
+    }
+}
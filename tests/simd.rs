@@ -0,0 +1,108 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SIMD kernels against scalar references on random vectors whose lengths
+//! exercise the tail handling.
+#![cfg(feature = "simd")]
+
+use scann::distance_measures::{CosineDistance, DistanceMeasure, DotProductDistance, SquaredL2Distance};
+use scann::simd;
+use scann::util::{DatapointPtr, DenseDataset, SplitMix64};
+
+// Odd lengths below, between and above the 8- and 4-lane widths.
+const LENGTHS: [usize; 12] = [1, 3, 5, 7, 9, 15, 17, 31, 33, 63, 65, 257];
+
+type Reference = fn(&[f32], &[f32]) -> f64;
+
+fn random_vector(rng: &mut SplitMix64, len: usize) -> Vec<f32> {
+    (0..len).map(|_| rng.next_normal()).collect()
+}
+
+fn dot_reference(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum()
+}
+
+fn squared_l2_reference(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum()
+}
+
+fn cosine_reference(a: &[f32], b: &[f32]) -> f64 {
+    1.0 - dot_reference(a, b) / (dot_reference(a, a).sqrt() * dot_reference(b, b).sqrt())
+}
+
+// Sums of `len` products of unit normals carry rounding error that grows
+// with the length.
+fn assert_close(actual: f32, expected: f64, len: usize, what: &str) {
+    let tolerance = 1e-5 * (len as f64).sqrt() * expected.abs().max(1.0);
+    assert!((actual as f64 - expected).abs() <= tolerance, "{} at length {}: {} vs {}", what, len, actual, expected);
+}
+
+#[test]
+fn kernels_match_scalar_sums_at_odd_lengths() {
+    if !simd::available() {
+        assert!(simd::dot(&[1.0], &[1.0]).is_none());
+        return;
+    }
+    let mut rng = SplitMix64::new(11);
+    for len in LENGTHS {
+        let a = random_vector(&mut rng, len);
+        let b = random_vector(&mut rng, len);
+        assert_close(simd::dot(&a, &b).unwrap(), dot_reference(&a, &b), len, "dot");
+        assert_close(simd::squared_l2(&a, &b).unwrap(), squared_l2_reference(&a, &b), len, "squared_l2");
+        let (dot, norm_a, norm_b) = simd::cosine_parts(&a, &b).unwrap();
+        assert_close(dot, dot_reference(&a, &b), len, "cosine dot");
+        assert_close(norm_a, dot_reference(&a, &a), len, "cosine |a|^2");
+        assert_close(norm_b, dot_reference(&b, &b), len, "cosine |b|^2");
+        // Every kernel accumulates the same way.
+        assert_eq!(norm_a, simd::dot(&a, &a).unwrap());
+        assert_eq!(norm_b, simd::dot(&b, &b).unwrap());
+    }
+}
+
+#[test]
+fn kernels_compare_the_common_prefix_of_unequal_lengths() {
+    if !simd::available() {
+        return;
+    }
+    let mut rng = SplitMix64::new(12);
+    let a = random_vector(&mut rng, 19);
+    let b = random_vector(&mut rng, 13);
+    assert_eq!(simd::dot(&a, &b), simd::dot(&a[..13], &b));
+    assert_eq!(simd::squared_l2(&a, &b), simd::squared_l2(&a[..13], &b));
+    assert_eq!(simd::dot(&[], &[]), Some(0.0));
+}
+
+#[test]
+fn distance_measures_match_scalar_references() {
+    let mut rng = SplitMix64::new(13);
+    let measures: [(Box<dyn DistanceMeasure>, Reference); 3] = [
+        (Box::new(DotProductDistance::new()), |a, b| -dot_reference(a, b)),
+        (Box::new(SquaredL2Distance::new()), squared_l2_reference),
+        (Box::new(CosineDistance::new()), cosine_reference),
+    ];
+    for len in LENGTHS {
+        let query = random_vector(&mut rng, len);
+        let rows: Vec<Vec<f32>> = (0..5).map(|_| random_vector(&mut rng, len)).collect();
+        let dataset = DenseDataset::new(rows.clone(), len);
+        for (measure, reference) in &measures {
+            let mut out = vec![0.0; rows.len()];
+            measure.compute_one_to_many(&DatapointPtr::new(query.clone()), &dataset, &mut out);
+            for (row, &distance) in rows.iter().zip(&out) {
+                let expected = reference(&query, row);
+                assert_close(measure.compute_distance_f32(&query, row), expected, len, measure.name());
+                assert_close(distance, expected, len, measure.name());
+            }
+        }
+    }
+}
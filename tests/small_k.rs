@@ -0,0 +1,214 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! search_small_k against search_with_options, and its C ABI.
+
+mod common;
+
+use common::random_rows;
+use scann::artifacts::{Artifacts, ArtifactsConfig};
+use scann::distance_measures::{CosineDistance, DistanceMeasure, DotProductDistance, SquaredL2Distance};
+use scann::ffi;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::util::{DatapointPtr, DatapointRef, DenseDataset, Normalization, ZeroVectorPolicy};
+use std::ffi::CString;
+
+// Random rows with every third row duplicated into the next, so ties occur.
+fn corpus_with_ties(n: usize, dim: usize, seed: u64) -> DenseDataset<f32> {
+    let mut rows = random_rows(n, dim, seed);
+    for i in (0..n.saturating_sub(1)).step_by(3) {
+        rows[i + 1] = rows[i].clone();
+    }
+    DenseDataset::new(rows, dim)
+}
+
+fn assert_small_k_matches<const K: usize>(retriever: &ScannRetriever, query: &[f32], context: &str) {
+    let options = SearchOptions {
+        k: Some(K),
+        ..SearchOptions::default()
+    };
+    let (expected, _) = retriever.search_with_options(&DatapointPtr::new(query.to_vec()), &options).unwrap();
+    let (ids, distances, filled) = retriever.search_small_k::<K>(DatapointRef::from_slice(query)).unwrap();
+    assert_eq!(filled, expected.len(), "{}", context);
+    for (slot, (docid, distance)) in expected.iter().enumerate() {
+        assert_eq!(ids[slot], *docid as i64, "{} slot {}", context, slot);
+        assert!((distances[slot] - distance).abs() <= 1e-5 * distance.abs().max(1.0), "{} slot {}", context, slot);
+    }
+    for slot in filled..K {
+        assert_eq!(ids[slot], -1, "{}", context);
+        assert_eq!(distances[slot], f32::INFINITY, "{}", context);
+    }
+}
+
+fn assert_all_k(retriever: &ScannRetriever, query: &[f32], context: &str) {
+    assert_small_k_matches::<1>(retriever, query, context);
+    assert_small_k_matches::<2>(retriever, query, context);
+    assert_small_k_matches::<4>(retriever, query, context);
+}
+
+fn measures() -> Vec<Box<dyn DistanceMeasure>> {
+    vec![
+        Box::new(SquaredL2Distance::new()),
+        Box::new(DotProductDistance::new()),
+        Box::new(CosineDistance::new()),
+    ]
+}
+
+#[test]
+fn matches_general_search() {
+    for dim in [2, 3, 16] {
+        for (m, measure) in measures().into_iter().enumerate() {
+            let retriever = ScannRetriever::new(corpus_with_ties(60, dim, dim as u64), measure, 4);
+            for (q, query) in random_rows(20, dim, 100 + m as u64).iter().enumerate() {
+                assert_all_k(&retriever, query, &format!("dim {} measure {} query {}", dim, m, q));
+            }
+        }
+    }
+}
+
+#[test]
+fn ties_fall_to_the_lower_docid() {
+    // Docids run against row order, so a tie kept by row order would come
+    // back with the higher docid first.
+    let (n, dim) = (60, 3);
+    let dataset = corpus_with_ties(n, dim, 14);
+    let mut queries: Vec<Vec<f32>> = dataset.data.iter().step_by(3).cloned().collect();
+    queries.extend(random_rows(10, dim, 15));
+    let artifacts = Artifacts {
+        config: ArtifactsConfig {
+            distance_measure: "SquaredL2Distance".to_string(),
+            normalization: Normalization::None,
+            dimensionality: dim,
+        },
+        dataset,
+        docids: (0..n).map(|i| 5 * (n - i)).collect(),
+        tree: None,
+        attributes: None,
+    };
+    let retriever = ScannRetriever::from_artifacts(artifacts, 4).unwrap();
+    for (q, query) in queries.iter().enumerate() {
+        assert_all_k(&retriever, query, &format!("query {}", q));
+    }
+    // A query at a duplicated row ties it with its copy at distance zero.
+    let (ids, distances, _) = retriever.search_small_k::<2>(DatapointRef::from_slice(&queries[0])).unwrap();
+    assert_eq!((ids, distances), ([5 * (n as i64 - 1), 5 * n as i64], [0.0, 0.0]));
+}
+
+#[test]
+fn dataset_smaller_than_k() {
+    for n in [1, 3] {
+        let retriever = ScannRetriever::new(corpus_with_ties(n, 3, 5), Box::new(SquaredL2Distance::new()), 4);
+        for query in random_rows(5, 3, 6) {
+            assert_all_k(&retriever, &query, &format!("n {}", n));
+        }
+    }
+}
+
+#[test]
+fn skips_removed_quarantined_and_expired_rows() {
+    let dim = 4;
+    let mut rows = random_rows(40, dim, 9);
+    rows[7] = vec![0.0; dim];
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, dim), Box::new(CosineDistance::new()), 4)
+        .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+        .unwrap();
+    assert_eq!(retriever.num_quarantined(), 1);
+    let queries = random_rows(10, dim, 10);
+    // Remove and expire each query's current nearest rows, so the exclusions
+    // change the answer.
+    for query in queries.iter().take(3) {
+        let (ids, _, _) = retriever.search_small_k::<2>(DatapointRef::from_slice(query)).unwrap();
+        retriever.remove(ids[0] as usize).unwrap();
+        retriever.set_expiry(ids[1] as usize, Some(1)).unwrap();
+        let (after, _, _) = retriever.search_small_k::<4>(DatapointRef::from_slice(query)).unwrap();
+        assert!(!after.contains(&ids[0]) && !after.contains(&ids[1]));
+    }
+    // A future expiry does not hide the row.
+    retriever.set_expiry(20, Some(i64::MAX)).unwrap();
+    for (q, query) in queries.iter().enumerate() {
+        let (ids, _, _) = retriever.search_small_k::<4>(DatapointRef::from_slice(query)).unwrap();
+        assert!(!ids.contains(&7));
+        assert_all_k(&retriever, query, &format!("query {}", q));
+    }
+}
+
+#[test]
+fn rejects_invalid_queries() {
+    let retriever = ScannRetriever::new(corpus_with_ties(10, 3, 1), Box::new(CosineDistance::new()), 2);
+    for query in [vec![0.0, 0.0, 0.0], vec![1.0, f32::NAN, 0.0], vec![f32::INFINITY, 0.0, 0.0], vec![1.0, 2.0], vec![]] {
+        assert!(retriever.search_small_k::<2>(DatapointRef::from_slice(&query)).is_err(), "{:?}", query);
+        let general = retriever.search_with_options(&DatapointPtr::new(query.clone()), &SearchOptions::default());
+        assert!(general.is_err(), "{:?}", query);
+    }
+    // Zero vectors are fine under a measure that can score them.
+    let retriever = ScannRetriever::new(corpus_with_ties(10, 3, 1), Box::new(SquaredL2Distance::new()), 2);
+    assert!(retriever.search_small_k::<2>(DatapointRef::from_slice(&[0.0, 0.0, 0.0])).is_ok());
+}
+
+#[test]
+fn c_abi_round_trip() {
+    let dim = 5;
+    let data = corpus_with_ties(30, dim, 12);
+    let retriever = ScannRetriever::new(data, Box::new(SquaredL2Distance::new()), 4);
+    let dir = std::env::temp_dir().join(format!("scann_small_k_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.blob");
+    retriever.pack_blob(&path).unwrap();
+
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    let measure = CString::new("SquaredL2Distance").unwrap();
+    let bad_measure = CString::new("NoSuchDistance").unwrap();
+    unsafe {
+        assert!(ffi::scann_retriever_load_blob(c_path.as_ptr(), bad_measure.as_ptr(), 4).is_null());
+        let handle = ffi::scann_retriever_load_blob(c_path.as_ptr(), measure.as_ptr(), 4);
+        assert!(!handle.is_null());
+        for query in random_rows(10, dim, 13) {
+            for k in 1..=ffi::SCANN_SMALL_K_MAX {
+                let mut ids = vec![0i64; k];
+                let mut distances = vec![0f32; k];
+                let filled = ffi::scann_search_small_k(
+                    handle,
+                    query.as_ptr(),
+                    dim,
+                    k,
+                    ids.as_mut_ptr(),
+                    distances.as_mut_ptr(),
+                );
+                assert_eq!(filled, k as i64);
+                let options = SearchOptions {
+                    k: Some(k),
+                    ..SearchOptions::default()
+                };
+                let (expected, _) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &options).unwrap();
+                let expected_ids: Vec<i64> = expected.iter().map(|r| r.0 as i64).collect();
+                assert_eq!(ids, expected_ids);
+            }
+        }
+        let query = vec![1.0f32; dim];
+        let (mut ids, mut distances) = ([0i64; 8], [0f32; 8]);
+        // Unsupported k, wrong dimensionality and null pointers are errors.
+        for (k, query_dim) in [(0, dim), (ffi::SCANN_SMALL_K_MAX + 1, dim), (2, dim - 1)] {
+            let filled =
+                ffi::scann_search_small_k(handle, query.as_ptr(), query_dim, k, ids.as_mut_ptr(), distances.as_mut_ptr());
+            assert_eq!(filled, -1, "k {} dim {}", k, query_dim);
+        }
+        assert_eq!(
+            ffi::scann_search_small_k(handle, std::ptr::null(), dim, 2, ids.as_mut_ptr(), distances.as_mut_ptr()),
+            -1
+        );
+        ffi::scann_retriever_free(handle);
+        ffi::scann_retriever_free(std::ptr::null_mut());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
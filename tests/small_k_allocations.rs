@@ -0,0 +1,106 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counts heap allocations made by search_small_k. Lives in its own test
+//! binary because it installs a global allocator.
+
+use scann::distance_measures::{CosineDistance, DistanceMeasure, SquaredL2Distance};
+use scann::ffi;
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointRef, DenseDataset, SplitMix64, ZeroVectorPolicy};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn retriever(dim: usize, measure: Box<dyn DistanceMeasure>) -> ScannRetriever {
+    let mut rng = SplitMix64::new(dim as u64);
+    let rows = (0..200).map(|_| (0..dim).map(|_| rng.next_normal()).collect()).collect();
+    ScannRetriever::new(DenseDataset::new(rows, dim), measure, 4)
+}
+
+#[test]
+fn steady_state_search_does_not_allocate() {
+    assert_eq!(allocations_during(|| drop(std::hint::black_box(vec![1u8; 16]))), 1);
+    for dim in [3, 16, 100] {
+        let cases = [
+            retriever(dim, Box::new(SquaredL2Distance::new())),
+            retriever(dim, Box::new(CosineDistance::new()))
+                .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+                .unwrap(),
+        ];
+        let query = vec![0.5f32; dim];
+        for retriever in &cases {
+            retriever.remove(3).unwrap();
+            retriever.set_expiry(5, Some(1)).unwrap();
+            // Warm up: the first search after an expiry change caches the
+            // expired window.
+            retriever.search_small_k::<4>(DatapointRef::from_slice(&query)).unwrap();
+            let allocations = allocations_during(|| {
+                for _ in 0..10 {
+                    retriever.search_small_k::<1>(DatapointRef::from_slice(&query)).unwrap();
+                    retriever.search_small_k::<2>(DatapointRef::from_slice(&query)).unwrap();
+                    retriever.search_small_k::<4>(DatapointRef::from_slice(&query)).unwrap();
+                }
+            });
+            assert_eq!(allocations, 0, "dim {}", dim);
+        }
+    }
+}
+
+#[test]
+fn c_abi_search_does_not_allocate() {
+    let dim = 8;
+    let retriever = retriever(dim, Box::new(SquaredL2Distance::new()));
+    let query = vec![0.25f32; dim];
+    let (mut ids, mut distances) = ([0i64; 4], [0f32; 4]);
+    let allocations = allocations_during(|| {
+        for k in 1..=ffi::SCANN_SMALL_K_MAX {
+            let filled = unsafe {
+                ffi::scann_search_small_k(&retriever, query.as_ptr(), dim, k, ids.as_mut_ptr(), distances.as_mut_ptr())
+            };
+            assert_eq!(filled, k as i64);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
@@ -0,0 +1,113 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Spilled partitions: a row held by several searched leaves is scored once
+//! per query.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::collections::BTreeSet;
+use std::thread;
+
+const DIM: usize = 4;
+const NUM_LEAVES: usize = 16;
+const LEAVES_TO_SEARCH: usize = 6;
+const K: usize = 10;
+
+fn spilled_retriever() -> (ScannRetriever, Vec<Vec<f32>>) {
+    let rows = random_rows(1500, DIM, 1);
+    let retriever = ScannRetriever::new(DenseDataset::new(rows.clone(), DIM), Box::new(SquaredL2Distance::new()), K);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 10;
+    options.per_node_spilling_factor = 3.0;
+    options.max_spill_centers = 4;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+    (retriever, rows)
+}
+
+fn options() -> SearchOptions {
+    SearchOptions {
+        k: Some(K),
+        leaves_to_search: Some(LEAVES_TO_SEARCH),
+        ..SearchOptions::default()
+    }
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// Scores every leaf entry, duplicates included, and dedups only when
+// picking the top k.
+fn dedup_late(rows: &[Vec<f32>], leaves: &[Vec<usize>], query: &[f32]) -> Vec<usize> {
+    let mut scored: Vec<(f32, usize)> =
+        leaves.iter().flatten().map(|&row| (squared_l2(query, &rows[row]), row)).collect();
+    scored.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut seen = BTreeSet::new();
+    scored.into_iter().filter(|&(_, row)| seen.insert(row)).take(K).map(|(_, row)| row).collect()
+}
+
+#[test]
+fn spilled_rows_are_scored_once_per_query() {
+    let (retriever, rows) = spilled_retriever();
+    let tree = retriever.partitioning().expect("partitioned");
+    let total_entries: usize = (0..tree.num_leaves()).map(|leaf| tree.leaf(leaf).len()).sum();
+    assert!(total_entries > rows.len() * 3 / 2, "{} entries for {} rows", total_entries, rows.len());
+
+    for query in random_rows(50, DIM, 2) {
+        let leaves: Vec<Vec<usize>> = tree
+            .tokens_for_query(&query, LEAVES_TO_SEARCH)
+            .into_iter()
+            .map(|leaf| tree.leaf(leaf).to_vec())
+            .collect();
+        let unique: BTreeSet<usize> = leaves.iter().flatten().copied().collect();
+        assert!(unique.len() < leaves.iter().map(Vec::len).sum::<usize>());
+
+        let (results, stats) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &options()).unwrap();
+        assert_eq!(stats.leaves_searched, LEAVES_TO_SEARCH);
+        assert_eq!(stats.datapoints_scored, unique.len());
+        let docids: Vec<usize> = results.iter().map(|&(docid, _)| docid).collect();
+        assert_eq!(docids, dedup_late(&rows, &leaves, &query));
+    }
+}
+
+#[test]
+fn concurrent_searches_keep_their_own_visited_sets() {
+    let (retriever, _) = spilled_retriever();
+    let queries = random_rows(40, DIM, 3);
+    let expected: Vec<_> = queries
+        .iter()
+        .map(|q| retriever.search_with_options(&DatapointPtr::new(q.clone()), &options()).unwrap())
+        .map(|(results, stats)| (results, stats.datapoints_scored))
+        .collect();
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..5 {
+                    for (query, expected) in queries.iter().zip(&expected) {
+                        let (results, stats) =
+                            retriever.search_with_options(&DatapointPtr::new(query.clone()), &options()).unwrap();
+                        assert_eq!((results, stats.datapoints_scored), *expected);
+                    }
+                }
+            });
+        }
+    });
+}
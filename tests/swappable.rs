@@ -0,0 +1,155 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hot-swapping the index behind a stable handle, and shadow agreement
+//! accounting.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::ScannRetriever;
+use scann::swappable::SwappableRetriever;
+use scann::util::{DatapointPtr, DenseDataset};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+const N: usize = 2000;
+const K: usize = 5;
+
+// Rows at (i, y): for a query on the x axis every distance from the index
+// with offset y is a perfect square plus y^2.
+fn line_retriever(n: usize, y: f32) -> Arc<ScannRetriever> {
+    let rows = (0..n).map(|i| vec![i as f32, y]).collect();
+    Arc::new(ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(SquaredL2Distance::new()), K))
+}
+
+fn query(x: f32) -> DatapointPtr<f32> {
+    DatapointPtr::new(vec![x, 0.0])
+}
+
+// Which index a response came from: the offset every distance agrees on.
+fn source(results: &[(usize, f32)], x: f32) -> Option<f32> {
+    let offsets: Vec<f32> = results
+        .iter()
+        .map(|&(docid, distance)| distance - (docid as f32 - x) * (docid as f32 - x))
+        .collect();
+    offsets.iter().all(|&o| o == offsets[0]).then_some(offsets[0])
+}
+
+#[test]
+fn every_response_comes_wholly_from_one_index_across_a_swap() {
+    let handle = SwappableRetriever::new(line_retriever(N, 0.0));
+    let swapped = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let readers: Vec<_> = (0..4)
+            .map(|t| {
+                let (handle, swapped) = (&handle, &swapped);
+                scope.spawn(move || {
+                    let mut sources = Vec::new();
+                    for i in 0..300 {
+                        let after_swap = swapped.load(Ordering::SeqCst);
+                        let x = ((t * 300 + i) % N) as f32;
+                        let results = handle.search(&query(x)).unwrap();
+                        assert_eq!(results.len(), K);
+                        let offset = source(&results, x).unwrap_or_else(|| panic!("mixed response {:?}", results));
+                        // A search that starts after the swap never sees the
+                        // old index.
+                        assert!(!after_swap || offset == 1.0, "{:?}", results);
+                        sources.push(offset);
+                    }
+                    sources
+                })
+            })
+            .collect();
+        let old = handle.swap(line_retriever(N, 1.0));
+        swapped.store(true, Ordering::SeqCst);
+        assert_eq!(old.num_active(), N);
+        for reader in readers {
+            assert!(reader.join().unwrap().iter().all(|&o| o == 0.0 || o == 1.0));
+        }
+        // Nothing but this handle's caller holds the old index now.
+        assert_eq!(Arc::strong_count(&old), 1);
+    });
+    assert_eq!(source(&handle.search(&query(7.0)).unwrap(), 7.0), Some(1.0));
+}
+
+#[test]
+fn shadow_agreement_counts_overlap_and_exact_matches() {
+    let handle = SwappableRetriever::new(line_retriever(50, 0.0));
+    // The candidate lost its two leftmost rows.
+    let candidate = line_retriever(50, 0.0);
+    candidate.remove(0).unwrap();
+    candidate.remove(1).unwrap();
+    handle.start_shadow(candidate.clone(), 1.0).unwrap();
+
+    // At x = 0 the candidate returns 2..7 against 0..5: overlap 3/5. At
+    // x = 25 both return the same list.
+    handle.search(&query(0.0)).unwrap();
+    handle.search(&query(25.0)).unwrap();
+    let agreement = handle.shadow_agreement();
+    assert_eq!((agreement.queries, agreement.exact_matches, agreement.errors), (2, 1, 0));
+    assert!((agreement.mean_overlap - 0.8).abs() < 1e-6, "{:?}", agreement);
+
+    // Restarting resets the counters; a zero fraction shadows nothing.
+    handle.start_shadow(candidate.clone(), 0.0).unwrap();
+    for x in 0..20 {
+        handle.search(&query(x as f32)).unwrap();
+    }
+    assert_eq!(handle.shadow_agreement().queries, 0);
+
+    // A fraction samples about that share of queries.
+    handle.start_shadow(candidate, 0.25).unwrap();
+    for x in 0..2000 {
+        handle.search(&query((x % 50) as f32)).unwrap();
+    }
+    let sampled = handle.shadow_agreement().queries;
+    assert!((400..600).contains(&sampled), "{}", sampled);
+
+    let error = handle.start_shadow(line_retriever(5, 0.0), 1.5).unwrap_err();
+    assert!(error.to_string().contains("Shadow fraction must be in [0, 1]"), "{}", error);
+}
+
+#[test]
+fn shadow_failures_never_affect_the_served_response() {
+    let handle = SwappableRetriever::new(line_retriever(50, 0.0));
+    // A 3-d candidate rejects every 2-d query.
+    let wide = Arc::new(ScannRetriever::new(
+        DenseDataset::new(vec![vec![0.0, 0.0, 0.0]; 10], 3),
+        Box::new(SquaredL2Distance::new()),
+        K,
+    ));
+    handle.start_shadow(wide, 1.0).unwrap();
+    assert_eq!(handle.search(&query(3.0)).unwrap()[0], (3, 0.0));
+    let agreement = handle.shadow_agreement();
+    assert_eq!((agreement.queries, agreement.errors), (0, 1));
+}
+
+#[test]
+fn commit_promotes_the_shadow_candidate() {
+    let handle = SwappableRetriever::new(line_retriever(50, 0.0));
+    let error = handle.commit_shadow().err().unwrap();
+    assert!(error.to_string().contains("No shadow candidate to commit"), "{}", error);
+
+    handle.start_shadow(line_retriever(60, 1.0), 1.0).unwrap();
+    let old = handle.commit_shadow().unwrap();
+    assert_eq!(old.num_active(), 50);
+    assert_eq!(handle.num_active(), 60);
+    assert!(handle.stop_shadow().is_none());
+    assert_eq!(source(&handle.search(&query(4.0)).unwrap(), 4.0), Some(1.0));
+
+    // A plain swap also drops any pending shadow.
+    handle.start_shadow(line_retriever(70, 0.0), 1.0).unwrap();
+    handle.swap(line_retriever(80, 0.0));
+    assert!(handle.stop_shadow().is_none());
+    assert_eq!(handle.current().num_active(), 80);
+}
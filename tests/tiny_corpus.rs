@@ -0,0 +1,176 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Degenerate but legal corpora: one, two and five points in one and two
+//! dimensions, through build, artifacts, blobs, PCA and search.
+
+mod common;
+
+use common::scratch_dir;
+use scann::artifacts::{self, ArtifactsConfig};
+use scann::build::{self, PartitioningRecord};
+use scann::distance_measures::SquaredL2Distance;
+use scann::estimate::BuildPlan;
+use scann::projection::PcaProjection;
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, DenseDataset, Normalization, SplitMix64};
+
+const SIZES: [usize; 3] = [1, 2, 5];
+const DIMS: [usize; 2] = [1, 2];
+
+// Distinct rows, so exact rankings are unambiguous after tie-breaking.
+fn corpus(n: usize, dim: usize) -> DenseDataset<f32> {
+    let rows = (0..n).map(|i| (0..dim).map(|j| (i * 3 + j) as f32 * 0.5 - 1.0).collect()).collect();
+    DenseDataset::new(rows, dim)
+}
+
+fn queries(dim: usize) -> Vec<Vec<f32>> {
+    let mut rng = SplitMix64::new(dim as u64);
+    (0..8).map(|_| (0..dim).map(|_| rng.next_f32() * 8.0 - 2.0).collect()).collect()
+}
+
+fn brute_force(data: &DenseDataset<f32>, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+    let mut scored: Vec<(usize, f32)> = data
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, row)| (i, row.iter().zip(query).map(|(a, b)| (a - b) * (a - b)).sum()))
+        .collect();
+    scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    scored.truncate(k);
+    scored
+}
+
+fn assert_exact(retriever: &ScannRetriever, data: &DenseDataset<f32>, context: &str) {
+    let options = SearchOptions {
+        k: Some(data.size()),
+        leaves_to_search: Some(2),
+        ..SearchOptions::default()
+    };
+    for query in queries(data.dimensionality()) {
+        let (results, _) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &options).unwrap();
+        let expected = brute_force(data, &query, data.size());
+        let ids: Vec<usize> = results.iter().map(|r| r.0).collect();
+        let expected_ids: Vec<usize> = expected.iter().map(|r| r.0).collect();
+        assert_eq!(ids, expected_ids, "{} query {:?}", context, query);
+        for (got, want) in results.iter().zip(expected.iter()) {
+            assert!((got.1 - want.1).abs() <= 1e-5 * want.1.max(1.0), "{}: {:?} vs {:?}", context, got, want);
+        }
+    }
+}
+
+#[test]
+fn build_succeeds_and_searches_exactly() {
+    for n in SIZES {
+        for dim in DIMS {
+            let context = format!("n={} dim={}", n, dim);
+            let data = corpus(n, dim);
+            let mut plan = BuildPlan::new();
+            plan.num_leaves = Some(2);
+            plan.int8_codes = true;
+            plan.norm_cache = true;
+            let mut options = KMeansTreeTrainingOptions::new();
+            options.min_points_to_partition = 3;
+            let (retriever, report) =
+                build::build_retriever(data.clone(), Box::new(SquaredL2Distance::new()), n, &plan, &options)
+                    .unwrap_or_else(|e| panic!("{}: {}", context, e));
+            match (&report.partitioning, n < 3) {
+                (PartitioningRecord::Skipped { reason }, true) => assert!(!reason.is_empty(), "{}", context),
+                (PartitioningRecord::Built { num_leaves, .. }, false) => assert_eq!(*num_leaves, 2, "{}", context),
+                (other, _) => panic!("{}: unexpected partitioning {:?}", context, other),
+            }
+            assert!(report.quantization.is_some(), "{}", context);
+            assert_exact(&retriever, &data, &context);
+
+            for query in queries(dim) {
+                let mut ids: Vec<usize> = retriever.search_int8_codes(&query, n).unwrap().iter().map(|r| r.0).collect();
+                ids.sort_unstable();
+                assert_eq!(ids, (0..n).collect::<Vec<_>>(), "{}", context);
+            }
+        }
+    }
+}
+
+#[test]
+fn artifacts_round_trip() {
+    for n in SIZES {
+        for dim in DIMS {
+            let context = format!("n={} dim={}", n, dim);
+            let data = corpus(n, dim);
+            let docids: Vec<usize> = (0..n).map(|i| 100 + i).collect();
+            let config = ArtifactsConfig {
+                distance_measure: "SquaredL2Distance".to_string(),
+                normalization: Normalization::None,
+                dimensionality: dim,
+            };
+            let dir = scratch_dir(&format!("artifacts_{}_{}", n, dim));
+            artifacts::save_artifacts(&dir, &config, &data, &docids).unwrap();
+            let loaded = artifacts::load_artifacts(&dir).unwrap_or_else(|e| panic!("{}: {}", context, e));
+            assert_eq!(loaded.config, config, "{}", context);
+            assert_eq!(loaded.dataset.data, data.data, "{}", context);
+            assert_eq!(loaded.docids, docids, "{}", context);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
+
+#[test]
+fn blob_round_trip_keeps_exact_results() {
+    for n in SIZES {
+        for dim in DIMS {
+            let context = format!("n={} dim={}", n, dim);
+            let data = corpus(n, dim);
+            let retriever = ScannRetriever::new(data.clone(), Box::new(SquaredL2Distance::new()), n);
+            let mut options = KMeansTreeTrainingOptions::new();
+            options.min_points_to_partition = 3;
+            retriever.build_partitions(2, &options).unwrap();
+            let dir = scratch_dir(&format!("blob_{}_{}", n, dim));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("index.blob");
+            retriever.pack_blob(&path).unwrap();
+            let loaded = ScannRetriever::load_blob(&path, Box::new(SquaredL2Distance::new()), n)
+                .unwrap_or_else(|e| panic!("{}: {}", context, e));
+            assert_exact(&loaded, &data, &context);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
+
+#[test]
+fn pca_truncates_to_feasible_rank() {
+    for n in SIZES {
+        for dim in DIMS {
+            let context = format!("n={} dim={}", n, dim);
+            let data = corpus(n, dim);
+            let mut pca = PcaProjection::<f32>::new(dim as i32, dim as i32).unwrap();
+            let report = pca.create(&data, true, None).unwrap_or_else(|e| panic!("{}: {}", context, e));
+            // Centered, n points span n - 1 directions; the rows here are
+            // collinear, so at most one.
+            let feasible = (n - 1).min(1);
+            assert_eq!(report.numerical_rank, feasible, "{}", context);
+            assert_eq!(report.effective_dims, feasible.max(1), "{}", context);
+            assert_eq!(report.warning.is_some(), feasible < dim, "{}", context);
+            assert_eq!(pca.projected_dims(), report.effective_dims, "{}", context);
+
+            let serialized = pca.serialize_to_proto().unwrap();
+            let mut restored = PcaProjection::<f32>::new(dim as i32, dim as i32).unwrap();
+            restored.create_from_serialized(&serialized).unwrap();
+            assert_eq!(restored.projected_dims(), report.effective_dims, "{}", context);
+            let mut projected = DatapointPtr::new(Vec::<f32>::new());
+            restored.project_input(&DatapointPtr::new(data.data[0].clone()), &mut projected).unwrap();
+            assert_eq!(projected.dimensionality(), report.effective_dims, "{}", context);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graphviz and JSON exports of a trained k-means tree: the JSON parses
+//! back into a consistent hierarchy and both exports are deterministic.
+
+use serde_json::Value;
+use scann::tree::{KMeansTree, KMeansTreeTrainingOptions, TreeExportOptions};
+use scann::util::{DenseDataset, SplitMix64};
+
+fn trained(num_leaves: usize) -> (KMeansTree, DenseDataset<f32>) {
+    let mut rng = SplitMix64::new(21);
+    let rows = (0..500).map(|_| (0..3).map(|_| rng.next_normal()).collect()).collect();
+    let data = DenseDataset::new(rows, 3);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    let (tree, _) = KMeansTree::train(&data, num_leaves, &options).unwrap();
+    (tree, data)
+}
+
+fn export_json(tree: &KMeansTree, data: &DenseDataset<f32>) -> String {
+    let mut out = Vec::new();
+    tree.export_json(&mut out, data).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn export_dot(tree: &KMeansTree, data: &DenseDataset<f32>, color_by_imbalance: bool) -> String {
+    let mut out = Vec::new();
+    tree.export_dot(&mut out, data, &TreeExportOptions { color_by_imbalance }).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn as_usize(value: &Value) -> Option<usize> {
+    value.as_u64().map(|v| v as usize)
+}
+
+#[test]
+fn json_export_is_a_consistent_hierarchy() {
+    let (tree, data) = trained(7);
+    let root: Value = serde_json::from_str(&export_json(&tree, &data)).unwrap();
+    assert_eq!(as_usize(&root["num_leaves"]), Some(7));
+    assert_eq!(as_usize(&root["num_points"]), Some(500));
+    assert_eq!(as_usize(&root["dimensionality"]), Some(3));
+    let nodes = root["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 8);
+
+    for (i, node) in nodes.iter().enumerate() {
+        assert_eq!(as_usize(&node["id"]), Some(i));
+        for child in node["children"].as_array().unwrap() {
+            let child = as_usize(child).unwrap();
+            assert_eq!(as_usize(&nodes[child]["parent"]), Some(i));
+        }
+        match node.get("parent") {
+            Some(Value::Null) => assert_eq!(i, 0),
+            Some(parent) => {
+                let siblings = nodes[as_usize(parent).unwrap()]["children"].as_array().unwrap();
+                assert!(siblings.iter().any(|c| as_usize(c) == Some(i)), "node {}", i);
+            }
+            None => panic!("node {} has no parent field", i),
+        }
+    }
+
+    let leaf_sizes: Vec<usize> = nodes[1..].iter().map(|node| as_usize(&node["size"]).unwrap()).collect();
+    assert_eq!(leaf_sizes.iter().sum::<usize>(), 500);
+    assert_eq!(as_usize(&nodes[0]["size"]), Some(500));
+    let stats = tree.leaf_stats(&data).unwrap();
+    for (node, stats) in nodes[1..].iter().zip(&stats) {
+        assert_eq!(as_usize(&node["leaf"]), Some(stats.leaf));
+        assert_eq!(as_usize(&node["size"]), Some(stats.size));
+    }
+}
+
+#[test]
+fn exports_are_deterministic_across_builds() {
+    let (first, data) = trained(6);
+    let (second, _) = trained(6);
+    assert_eq!(export_json(&first, &data), export_json(&second, &data));
+    assert_eq!(export_dot(&first, &data, true), export_dot(&second, &data, true));
+}
+
+#[test]
+fn dot_export_lists_every_leaf_in_order() {
+    let (tree, data) = trained(5);
+    let plain = export_dot(&tree, &data, false);
+    assert!(plain.starts_with("digraph kmeans_tree {"), "{}", plain);
+    let mut last = 0;
+    for leaf in 0..5 {
+        let position = plain.find(&format!("  leaf_{} [", leaf)).unwrap();
+        assert!(position > last, "leaf {} out of order", leaf);
+        last = position;
+        assert!(plain.contains(&format!("root -> leaf_{};", leaf)));
+    }
+    assert!(!plain.contains("fillcolor"));
+    assert!(export_dot(&tree, &data, true).contains("fillcolor"));
+}
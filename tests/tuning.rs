@@ -0,0 +1,134 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Search parameter tuning: Pareto flags, recall-target selection and,
+//! with the serde feature, serializable results.
+
+use scann::distance_measures::SquaredL2Distance;
+use scann::evaluation::{self, TuningPoint};
+use scann::retrieval::{ScannRetriever, SearchOptions};
+use scann::testing::datasets::{self, BlobsSpec};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::DenseDataset;
+
+fn point(leaves: usize, recall: f32, mean_latency_us: f64) -> TuningPoint {
+    TuningPoint {
+        options: SearchOptions {
+            leaves_to_search: Some(leaves),
+            ..SearchOptions::default()
+        },
+        recall,
+        mean_latency_us,
+        pareto_optimal: false,
+    }
+}
+
+fn blobs(num_points: usize, seed: u64) -> datasets::GeneratedDataset {
+    datasets::gaussian_blobs(&BlobsSpec {
+        num_points,
+        dimensionality: 8,
+        num_clusters: 16,
+        center_spread: 1.0,
+        cluster_std: 1.0,
+        seed,
+    })
+    .unwrap()
+}
+
+#[test]
+fn tune_flags_exactly_the_undominated_points() {
+    let data = blobs(600, 5);
+    let queries = blobs(20, 6).dataset;
+    let ground_truth = datasets::ground_truth(&data, &queries, 10, "SquaredL2Distance").unwrap();
+    let retriever = ScannRetriever::new(data.dataset, Box::new(SquaredL2Distance::new()), 10);
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(16, &options).unwrap();
+
+    let grid = evaluation::search_grid(&SearchOptions::default(), &[1, 4, 16], &[None, Some(40)]);
+    assert_eq!(grid.len(), 6);
+    let points = evaluation::tune(&retriever, &queries, &ground_truth, &grid).unwrap();
+    assert_eq!(points.len(), 6);
+    for (point, options) in points.iter().zip(&grid) {
+        assert_eq!(point.options.leaves_to_search, options.leaves_to_search);
+        assert_eq!(point.options.rescore_candidates, options.rescore_candidates);
+    }
+    // Scanning every leaf is exact.
+    assert_eq!(points[4].recall, 1.0);
+    assert!(points[0].recall <= points[4].recall);
+    for (i, p) in points.iter().enumerate() {
+        let dominated = points.iter().enumerate().any(|(j, q)| {
+            j != i
+                && q.recall >= p.recall
+                && q.mean_latency_us <= p.mean_latency_us
+                && (q.recall > p.recall || q.mean_latency_us < p.mean_latency_us)
+        });
+        assert_eq!(p.pareto_optimal, !dominated, "point {}: {:?}", i, p);
+    }
+    assert!(points.iter().any(|p| p.pareto_optimal));
+}
+
+#[test]
+fn tune_rejects_mismatched_ground_truth() {
+    let data = DenseDataset::new(vec![vec![0.0, 0.0], vec![1.0, 0.0]], 2);
+    let retriever = ScannRetriever::new(data, Box::new(SquaredL2Distance::new()), 1);
+    let queries = DenseDataset::new(vec![vec![0.0, 0.0]], 2);
+    let err = evaluation::tune(&retriever, &queries, &[], &[SearchOptions::default()]).unwrap_err();
+    assert!(err.to_string().contains("1 queries but 0 ground truth"), "{}", err);
+}
+
+#[test]
+fn cheapest_meeting_recall_picks_the_fastest_qualifying_point() {
+    let points = vec![
+        point(1, 0.5, 10.0),
+        point(2, 0.9, 30.0),
+        point(3, 0.95, 20.0),
+        point(4, 0.95, 20.0),
+        point(5, 1.0, 80.0),
+    ];
+    let pick = |target: f32| evaluation::cheapest_meeting_recall(&points, target).map(|p| p.options.leaves_to_search);
+    assert_eq!(pick(0.4), Some(Some(1)));
+    assert_eq!(pick(0.9), Some(Some(3)));
+    // Ties go to the earlier grid entry.
+    assert_eq!(pick(0.95), Some(Some(3)));
+    assert_eq!(pick(1.0), Some(Some(5)));
+    assert_eq!(pick(1.01), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn tuning_points_round_trip_through_serde() {
+    use scann::attribute_store::{CompareOp, Filter, Literal};
+
+    let mut original = point(7, 0.875, 12.5);
+    original.pareto_optimal = true;
+    original.options.rescore_candidates = Some(30);
+    original.options.filter = Some(Filter::Compare {
+        column: "lang".to_string(),
+        op: CompareOp::Eq,
+        value: Literal::Str("en".to_string()),
+    });
+    original.options.cancellation = Some(scann::util::CancellationToken::new());
+    let json = serde_json::to_string(&original).unwrap();
+    assert!(!json.contains("cancellation"), "{}", json);
+    let loaded: TuningPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.recall, original.recall);
+    assert_eq!(loaded.mean_latency_us, original.mean_latency_us);
+    assert!(loaded.pareto_optimal);
+    assert_eq!(loaded.options.leaves_to_search, Some(7));
+    assert_eq!(loaded.options.rescore_candidates, Some(30));
+    assert_eq!(loaded.options.filter, original.options.filter);
+    // Runtime handles are not serialized.
+    assert!(loaded.options.cancellation.is_none());
+}
@@ -0,0 +1,119 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upserts re-encode every derived representation, and copy-on-write
+//! storage shares what a write does not touch.
+
+mod common;
+
+use common::random_dataset;
+use scann::distance_measures::{CosineDistance, SquaredL2Distance};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, NormCache, ScannRetriever, SearchOptions};
+use scann::tree::KMeansTreeTrainingOptions;
+use scann::util::{DatapointPtr, SegmentedVec, SEGMENT_LEN};
+
+const NUM_LEAVES: usize = 8;
+
+fn exhaustive(k: usize) -> SearchOptions {
+    SearchOptions {
+        k: Some(k),
+        leaves_to_search: Some(NUM_LEAVES),
+        ..SearchOptions::default()
+    }
+}
+
+fn partitioned(retriever: &ScannRetriever) {
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    retriever.build_partitions(NUM_LEAVES, &options).unwrap();
+}
+
+fn cached_norm(retriever: &ScannRetriever, row: usize) -> f32 {
+    retriever
+        .with_derived_data("norms", |d| d.as_any().downcast_ref::<NormCache>().unwrap().norms[row])
+        .unwrap()
+}
+
+#[test]
+fn upserted_vector_ranks_first_under_int8_first_pass_and_exact_reordering() {
+    let dim = 16;
+    // Several storage segments.
+    let retriever = ScannRetriever::new(random_dataset(3000, dim, 1), Box::new(SquaredL2Distance::new()), 10);
+    partitioned(&retriever);
+    retriever
+        .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+        .unwrap();
+    let queries = random_dataset(4, dim, 2);
+    // Existing rows in different segments, then a docid not stored yet.
+    for (query, docid) in queries.data.iter().zip([5, SEGMENT_LEN + 7, 2 * SEGMENT_LEN + 11, 5000]) {
+        let before = retriever.search_int8_codes(query, 1).unwrap();
+        assert_ne!(before[0].0, docid);
+
+        retriever.upsert(docid, query).unwrap();
+        let quantized = retriever.search_int8_codes(query, 10).unwrap();
+        assert_eq!(quantized[0].0, docid, "int8 first pass for docid {}", docid);
+        let (exact, _) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &exhaustive(10)).unwrap();
+        assert_eq!(exact[0], (docid, 0.0), "exact reordering for docid {}", docid);
+        assert_eq!(retriever.get_by_docid(docid).unwrap(), *query);
+    }
+    let report = retriever.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+}
+
+#[test]
+fn upsert_updates_cached_norms_for_cosine() {
+    let dim = 8;
+    let retriever = ScannRetriever::new(random_dataset(2500, dim, 3), Box::new(CosineDistance::new()), 5);
+    partitioned(&retriever);
+    retriever.register_derived_data(Box::new(NormCache::default())).unwrap();
+    let query = random_dataset(1, dim, 4).data.remove(0);
+    let docid = SEGMENT_LEN + 100;
+    // Same direction, different length: cosine ranks it first only if the
+    // cached norm is the new one.
+    let scaled: Vec<f32> = query.iter().map(|v| v * 7.5).collect();
+    retriever.upsert(docid, &scaled).unwrap();
+    let norm = scaled.iter().map(|v| v * v).sum::<f32>().sqrt();
+    assert!((cached_norm(&retriever, docid) - norm).abs() < 1e-4 * norm);
+    let (results, _) = retriever.search_with_options(&DatapointPtr::new(query), &exhaustive(5)).unwrap();
+    assert_eq!(results[0].0, docid);
+    assert!(results[0].1.abs() < 1e-5, "{:?}", results[0]);
+}
+
+#[test]
+fn segmented_clones_share_untouched_segments() {
+    let values: Vec<usize> = (0..5 * SEGMENT_LEN + 3).collect();
+    let original = SegmentedVec::from_vec(values.clone());
+    assert_eq!(original.num_segments(), 6);
+    assert_eq!(original.to_vec(), values);
+
+    let mut updated = original.clone();
+    updated.set(2 * SEGMENT_LEN + 1, 0);
+    assert_eq!(updated.shared_segments(&original), original.num_segments() - 1);
+    assert_eq!(original[2 * SEGMENT_LEN + 1], 2 * SEGMENT_LEN + 1);
+    assert_eq!(updated[2 * SEGMENT_LEN + 1], 0);
+
+    // Appending copies at most the partly filled tail segment.
+    let mut appended = original.clone();
+    appended.push(7);
+    assert_eq!(appended.shared_segments(&original), original.num_segments() - 1);
+    assert_eq!(appended.len(), values.len() + 1);
+    assert_eq!(original.len(), values.len());
+
+    let slice = original.slice(SEGMENT_LEN, 2 * SEGMENT_LEN);
+    assert_eq!(slice.len(), SEGMENT_LEN);
+    assert_eq!(slice[0], SEGMENT_LEN);
+    let rebuilt: SegmentedVec<usize> = values.iter().copied().collect();
+    assert_eq!(rebuilt, original);
+}
@@ -0,0 +1,141 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vocabulary remapping between a model's tokenizer and the corpus's.
+
+use nalgebra::DMatrix;
+use scann::proto::RetroConfig;
+use scann::retro::chunk_store::{ChunkStore, InMemoryChunkStore};
+use scann::retro::embeddings::TokenEmbedding;
+use scann::retro::vocab_remap::{RemappedChunkStore, UnmappedPolicy, UnmappedRowInit};
+use scann::retro::{VocabRemap, RETRO};
+
+const VOCAB: u32 = 16;
+const DIM: usize = 8;
+
+fn model() -> RETRO {
+    let mut config = RetroConfig::new();
+    config.num_tokens = VOCAB;
+    config.max_seq_len = 64;
+    config.enc_dim = DIM as u32;
+    config.dec_dim = DIM as u32;
+    config.enc_depth = 1;
+    config.dec_depth = 2;
+    config.heads = 2;
+    config.dim_head = 4;
+    config.chunk_size = 4;
+    RETRO::new(config, None)
+}
+
+// The corpus tokenizer numbers model token t as (5 t + 3) mod VOCAB, a
+// permutation since 5 is coprime to 16.
+fn corpus_id(model_id: u32) -> u32 {
+    (5 * model_id + 3) % VOCAB
+}
+
+// Remap from corpus ids (old) to model ids (new).
+fn corpus_to_model() -> VocabRemap {
+    let pairs: Vec<(u32, u32)> = (0..VOCAB).map(|t| (corpus_id(t), t)).collect();
+    VocabRemap::from_pairs(&pairs).unwrap()
+}
+
+#[test]
+fn a_remapped_model_on_remapped_inputs_reproduces_the_logits() {
+    let mut model = model();
+    let seq: Vec<u32> = vec![1, 7, 2, 9, 15, 0, 4, 4, 11, 3];
+    let expected = model.forward_without_retrieval(&seq).unwrap();
+
+    let corpus_seq: Vec<u32> = seq.iter().map(|&t| corpus_id(t)).collect();
+    assert_ne!(model.forward_without_retrieval(&corpus_seq).unwrap(), expected);
+    model.set_input_remap(Some(corpus_to_model()));
+    // Output columns stay in the model's vocabulary.
+    assert_eq!(model.forward_without_retrieval(&corpus_seq).unwrap(), expected);
+
+    model.set_input_remap(None);
+    assert_eq!(model.forward_without_retrieval(&seq).unwrap(), expected);
+}
+
+#[test]
+fn unmapped_tokens_follow_the_policy() {
+    let mut model = model();
+    // Only corpus ids 0..8 are mapped.
+    let partial = VocabRemap::from_pairs(&(0..8).map(|t| (t, t + 8)).collect::<Vec<_>>()).unwrap();
+    assert_eq!(partial.len(), 8);
+    assert_eq!(partial.apply(&[0, 7]).unwrap(), vec![8, 15]);
+    let error = partial.apply(&[1, 12]).unwrap_err();
+    assert!(error.to_string().contains("Token 12 has no mapping"), "{}", error);
+
+    model.set_input_remap(Some(partial.clone()));
+    let error = model.forward_without_retrieval(&[1, 2, 12]).unwrap_err();
+    assert!(error.to_string().contains("Token 12 has no mapping"), "{}", error);
+
+    // Unk maps every unknown id to one new id.
+    let unk = partial.with_unmapped_policy(UnmappedPolicy::Unk(0));
+    assert_eq!(unk.apply(&[1, 12, 13]).unwrap(), vec![9, 0, 0]);
+    model.set_input_remap(Some(unk));
+    let logits = model.forward_without_retrieval(&[1, 12, 13]).unwrap();
+    model.set_input_remap(None);
+    assert_eq!(logits, model.forward_without_retrieval(&[9, 0, 0]).unwrap());
+}
+
+#[test]
+fn pairs_must_be_invertible() {
+    let error = VocabRemap::from_pairs(&[(1, 2), (1, 3)]).unwrap_err();
+    assert!(error.to_string().contains("Old token 1 is mapped to both 2 and 3"), "{}", error);
+    let error = VocabRemap::from_pairs(&[(1, 2), (4, 2)]).unwrap_err();
+    assert!(error.to_string().contains("New token 2 is the target of both 1 and 4"), "{}", error);
+    assert!(VocabRemap::from_pairs(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn embedding_rows_move_to_their_new_ids() {
+    let old = TokenEmbedding::from_weights(DMatrix::from_fn(4, 3, |r, c| (r * 10 + c) as f32));
+    // Old 0 -> new 3, old 2 -> new 0; new rows 1, 2 and 4 are unmapped.
+    let remap = VocabRemap::from_pairs(&[(0, 3), (2, 0)]).unwrap();
+    let new = remap.remap_embedding(&old, 5, UnmappedRowInit::Zeros).unwrap();
+    assert_eq!(new.weights().nrows(), 5);
+    assert_eq!(new.forward(&[3, 0]).unwrap(), old.forward(&[0, 2]).unwrap());
+    assert!(new.weights().row(1).iter().chain(new.weights().row(4).iter()).all(|&v| v == 0.0));
+
+    let mean = remap.remap_embedding(&old, 5, UnmappedRowInit::MeanOfMapped).unwrap();
+    // Mean of old rows 0 and 2: [10, 11, 12].
+    assert_eq!(mean.forward(&[1]).unwrap().row(0).iter().copied().collect::<Vec<_>>(), vec![10.0, 11.0, 12.0]);
+
+    let init = UnmappedRowInit::Normal { std: 0.5, seed: 9 };
+    let normal = remap.remap_embedding(&old, 5, init).unwrap();
+    assert_eq!(normal.weights(), remap.remap_embedding(&old, 5, init).unwrap().weights());
+    assert_eq!(normal.forward(&[3]).unwrap(), old.forward(&[0]).unwrap());
+    assert!(normal.weights().row(4).iter().all(|v| v.is_finite() && *v != 0.0));
+
+    for (pairs, new_vocab) in [(vec![(4, 0)], 5), (vec![(0, 5)], 5)] {
+        let error = VocabRemap::from_pairs(&pairs).unwrap().remap_embedding(&old, new_vocab, init).err().unwrap();
+        assert!(error.to_string().contains("outside vocabularies of size 4 and 5"), "{}", error);
+    }
+}
+
+#[test]
+fn chunk_stores_are_remapped_as_they_are_read() {
+    let documents: Vec<Vec<u32>> = vec![(0..6).collect(), vec![15, 14]];
+    let store = InMemoryChunkStore::from_documents(&documents, 4).unwrap();
+    let remap = corpus_to_model();
+    let view = RemappedChunkStore::new(&store, &remap);
+    assert_eq!(view.num_chunks(), store.num_chunks());
+    for chunk in 0..store.num_chunks() {
+        assert_eq!(view.get(chunk).unwrap(), remap.apply(&store.get(chunk).unwrap().to_vec()).unwrap());
+    }
+    assert_eq!(view.continuation(0).unwrap(), Some(remap.apply(&[4, 5]).unwrap()));
+    assert_eq!(view.continuation(1).unwrap(), None);
+    // The underlying store is untouched.
+    assert_eq!(store.get(0).unwrap().to_vec(), vec![0, 1, 2, 3]);
+}
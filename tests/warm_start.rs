@@ -0,0 +1,95 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Warm-started k-means: seeding Lloyd iterations from a previous run's
+//! centers.
+
+use scann::tree::{self, KMeansTreeTrainingOptions};
+use scann::util::{DenseDataset, SplitMix64};
+
+const BLOBS: usize = 8;
+const DIM: usize = 4;
+
+// Points around BLOBS overlapping centers, so Lloyd iterations from a cold
+// start take a while to settle; `jitter` moves every point a
+// little, as between two nightly snapshots of a corpus.
+fn blobs(seed: u64, jitter: f32) -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(seed);
+    let mut jitter_rng = SplitMix64::new(seed + 1000);
+    let rows = (0..BLOBS * 100)
+        .map(|i| {
+            (0..DIM)
+                .map(|d| {
+                    let center = if d == i % DIM { 2.5 * (i % BLOBS) as f32 } else { 0.0 };
+                    center + rng.next_normal() + jitter * jitter_rng.next_normal()
+                })
+                .collect()
+        })
+        .collect();
+    DenseDataset::new(rows, DIM)
+}
+
+fn options() -> KMeansTreeTrainingOptions {
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 100;
+    options.convergence_epsilon = 1e-6;
+    options.seed = 3;
+    options
+}
+
+#[test]
+fn warm_start_converges_in_fewer_iterations_to_an_objective_as_good() {
+    let yesterday = tree::train_kmeans(&blobs(1, 0.0), BLOBS, &options()).unwrap();
+    let today = blobs(1, 0.05);
+
+    let cold = tree::train_kmeans(&today, BLOBS, &options()).unwrap();
+    let mut warm_options = options();
+    warm_options.warm_start_centers = Some(yesterday.centers.clone());
+    let warm = tree::train_kmeans(&today, BLOBS, &warm_options).unwrap();
+
+    assert!(warm.stats.initial_objective < cold.stats.initial_objective, "{:?} vs {:?}", warm.stats, cold.stats);
+    assert!(warm.stats.iterations < cold.stats.iterations, "{:?} vs {:?}", warm.stats, cold.stats);
+    assert!(
+        warm.stats.final_objective <= cold.stats.final_objective * (1.0 + 1e-6),
+        "{:?} vs {:?}",
+        warm.stats,
+        cold.stats
+    );
+    assert!(warm.stats.final_objective <= warm.stats.initial_objective);
+}
+
+#[test]
+fn warm_start_centers_are_split_or_dropped_to_the_requested_count() {
+    let data = blobs(2, 0.0);
+    let previous = tree::train_kmeans(&data, BLOBS, &options()).unwrap();
+    let mut warm_options = options();
+    warm_options.warm_start_centers = Some(previous.centers);
+    for num_centers in [BLOBS - 3, BLOBS, BLOBS + 4] {
+        let result = tree::train_kmeans(&data, num_centers, &warm_options).unwrap();
+        assert_eq!(result.centers.size(), num_centers);
+        assert_eq!(result.assignments.len(), data.size());
+        assert!(result.assignments.iter().all(|&c| c < num_centers));
+        assert!(result.stats.final_objective <= result.stats.initial_objective);
+    }
+}
+
+#[test]
+fn warm_start_centers_must_match_the_data() {
+    let data = blobs(3, 0.0);
+    let mut options = options();
+    options.warm_start_centers = Some(DenseDataset::new(vec![vec![0.0; DIM + 1]], DIM + 1));
+    let err = tree::train_kmeans(&data, BLOBS, &options).err().expect("dimensionality mismatch");
+    assert!(err.to_string().contains("expected dimensionality 4"), "{}", err);
+}
@@ -0,0 +1,122 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! WeightedSquaredL2Distance: the weighted formula on every path, weight
+//! validation, construction from DistanceMeasureConfig, and search ranking
+//! by the weighted distance.
+
+mod common;
+
+use common::random_rows;
+use scann::distance_measures::{self, DistanceMeasure, SquaredL2Distance, WeightedSquaredL2Distance};
+use scann::proto::DistanceMeasureConfig;
+use scann::retrieval::ScannRetriever;
+use scann::util::{DatapointPtr, DenseDataset, RowBlock, ScannError};
+
+const DIM: usize = 6;
+
+fn config(weights: Vec<f32>) -> DistanceMeasureConfig {
+    DistanceMeasureConfig {
+        distance_measure: "WeightedSquaredL2Distance".to_string(),
+        weighted_l2_weights: weights,
+        ..DistanceMeasureConfig::default()
+    }
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= 1e-5 * b.abs().max(1.0)
+}
+
+#[test]
+fn distances_follow_the_weighted_formula() {
+    // Differences 3, -4, 0.5, weighted 2, 0.5, 0.
+    let measure = WeightedSquaredL2Distance::new(vec![2.0, 0.5, 0.0]).unwrap();
+    assert_eq!(measure.name(), "WeightedSquaredL2Distance");
+    assert_eq!(measure.weights(), &[2.0, 0.5, 0.0]);
+    let (a, b) = ([4.0, -2.0, 1.0], [1.0, 2.0, 0.5]);
+    assert_eq!(measure.compute_distance_f32(&a, &b), 26.0);
+    assert_eq!(measure.try_distance(&a, &b).unwrap(), 26.0);
+    assert_eq!(measure.compute_distance_f32(&a, &a), 0.0);
+
+    let unit = WeightedSquaredL2Distance::new(vec![1.0; DIM]).unwrap();
+    let weights: Vec<f32> = (0..DIM).map(|d| 0.5 + d as f32).collect();
+    let weighted = WeightedSquaredL2Distance::new(weights.clone()).unwrap();
+    let rows = random_rows(30, DIM, 1);
+    for (a, b) in rows.iter().zip(rows.iter().skip(1)) {
+        assert!(close(unit.compute_distance_f32(a, b), SquaredL2Distance::new().compute_distance_f32(a, b)));
+        let expected: f64 = a.iter().zip(b).zip(&weights).map(|((x, y), w)| *w as f64 * ((x - y) as f64).powi(2)).sum();
+        assert!(close(weighted.compute_distance_f32(a, b), expected as f32));
+        assert!(close(weighted.compute_distance_f64_accumulated(a, b), expected as f32));
+        let typed = weighted.compute_distance(&DatapointPtr::new(a.clone()), &DatapointPtr::new(b.clone()));
+        assert_eq!(typed, weighted.compute_distance_f32(a, b));
+    }
+}
+
+#[test]
+fn one_to_many_matches_the_single_distance() {
+    let measure = WeightedSquaredL2Distance::new((0..DIM).map(|d| d as f32 * 0.3).collect()).unwrap();
+    let rows = random_rows(50, DIM, 2);
+    let query = random_rows(1, DIM, 3).remove(0);
+    let mut batched = vec![0.0f32; rows.len()];
+    measure.compute_one_to_many_rows(&query, RowBlock::Rows(&rows), &mut batched);
+    for (row, &distance) in rows.iter().zip(&batched) {
+        assert_eq!(distance.to_bits(), measure.compute_distance_f32(&query, row).to_bits());
+    }
+}
+
+#[test]
+fn bad_weights_are_invalid_arguments() {
+    let error = WeightedSquaredL2Distance::new(Vec::new()).err().unwrap();
+    assert!(error.downcast_ref::<ScannError>().is_some());
+    assert!(error.to_string().contains("requires at least one weight"), "{}", error);
+    for bad in [-1.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        let error = WeightedSquaredL2Distance::new(vec![1.0, bad]).err().unwrap();
+        assert!(error.to_string().contains("weight 1 is"), "{}: {}", bad, error);
+        assert!(error.to_string().contains("expected finite and non-negative"), "{}: {}", bad, error);
+        assert!(distance_measures::get_distance_measure(&config(vec![1.0, bad])).is_err(), "{}", bad);
+    }
+    // Zero weights are allowed: they ignore a dimension.
+    assert!(WeightedSquaredL2Distance::new(vec![0.0; 3]).is_ok());
+}
+
+#[test]
+fn configs_build_the_measure_from_their_weights() {
+    let weights = vec![1.0, 0.25, 4.0];
+    let from_config = distance_measures::get_distance_measure(&config(weights.clone())).unwrap();
+    let direct = WeightedSquaredL2Distance::new(weights).unwrap();
+    assert_eq!(from_config.name(), "WeightedSquaredL2Distance");
+    let (a, b) = ([0.5, 1.0, -1.0], [2.0, -3.0, 0.0]);
+    assert_eq!(from_config.compute_distance_f32(&a, &b), direct.compute_distance_f32(&a, &b));
+
+    let error = distance_measures::get_distance_measure(&config(Vec::new())).err().unwrap();
+    assert!(error.to_string().contains("requires weighted_l2_weights"), "{}", error);
+    // The name alone carries no weights.
+    assert!(distance_measures::get_distance_measure_by_name("WeightedSquaredL2Distance").is_err());
+}
+
+#[test]
+fn search_ranks_by_the_weighted_distance() {
+    // Unweighted, row 1 is farthest from the origin; with the first
+    // dimension ignored, it is nearest.
+    let rows = vec![vec![0.5, 2.0], vec![3.0, 0.1], vec![1.0, 1.0]];
+    let query = DatapointPtr::new(vec![0.0, 0.0]);
+    let plain = ScannRetriever::new(DenseDataset::new(rows.clone(), 2), Box::new(SquaredL2Distance::new()), 3);
+    let order = |results: Vec<(usize, f32)>| results.into_iter().map(|(docid, _)| docid).collect::<Vec<_>>();
+    assert_eq!(order(plain.search(&query).unwrap()), vec![2, 0, 1]);
+    let weighted = WeightedSquaredL2Distance::new(vec![0.0, 1.0]).unwrap();
+    let retriever = ScannRetriever::new(DenseDataset::new(rows, 2), Box::new(weighted), 3);
+    let results = retriever.search(&query).unwrap();
+    assert_eq!(order(results.clone()), vec![1, 2, 0]);
+    assert!(close(results[0].1, 0.01));
+}
@@ -0,0 +1,196 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wire encodings of search cursors, prepared queries, calibrators and
+//! cancellation tokens: round trips, pinned bytes, corrupted input and
+//! stale cursors.
+
+use scann::binfmt::{self, WIRE_KIND_CALIBRATOR, WIRE_KIND_SEARCH_CURSOR};
+use scann::calibration::Calibrator;
+use scann::distance_measures::SquaredL2Distance;
+use scann::retrieval::{PreparedQuery, ScannRetriever, SearchCursor, SearchOptions};
+use scann::util::{CancellationToken, DatapointPtr, DenseDataset, SplitMix64};
+use std::error::Error;
+use std::time::Duration;
+
+type Decoder = Box<dyn Fn(&[u8]) -> Result<(), Box<dyn Error>>>;
+
+fn retriever() -> ScannRetriever {
+    let mut rng = SplitMix64::new(1);
+    let rows = (0..500).map(|_| (0..4).map(|_| rng.next_normal()).collect()).collect();
+    ScannRetriever::new(DenseDataset::new(rows, 4), Box::new(SquaredL2Distance::new()), 10)
+}
+
+fn page_options() -> SearchOptions {
+    SearchOptions {
+        k: Some(7),
+        ..SearchOptions::default()
+    }
+}
+
+// One valid encoding of each value, with a decoder for it.
+fn encodings() -> Vec<(&'static str, Vec<u8>, Decoder)> {
+    let retriever = retriever();
+    let query = DatapointPtr::new(vec![0.5, -0.5, 0.25, 1.0]);
+    let (_, cursor) = retriever.search_page(&query, &page_options(), None).unwrap();
+    let prepared = retriever.prepare(&query).unwrap().to_bytes();
+    vec![
+        (
+            "SearchCursor",
+            cursor.unwrap().to_bytes(),
+            Box::new(|bytes| SearchCursor::from_bytes(bytes).map(drop)),
+        ),
+        (
+            "PreparedQuery",
+            prepared,
+            Box::new(move |bytes| PreparedQuery::from_bytes(bytes, &retriever).map(drop)),
+        ),
+        (
+            "Calibrator",
+            Calibrator { a: -1.5, b: 0.25 }.to_bytes(),
+            Box::new(|bytes| Calibrator::from_bytes(bytes).map(drop)),
+        ),
+        (
+            "CancellationToken",
+            CancellationToken::with_timeout(Duration::from_secs(60)).to_bytes(),
+            Box::new(|bytes| CancellationToken::from_bytes(bytes).map(drop)),
+        ),
+    ]
+}
+
+#[test]
+fn values_round_trip() {
+    let calibrator = Calibrator { a: -1.5, b: 0.1 };
+    assert_eq!(Calibrator::from_bytes(&calibrator.to_bytes()).unwrap(), calibrator);
+
+    let token = CancellationToken::new();
+    assert!(!CancellationToken::from_bytes(&token.to_bytes()).unwrap().is_cancelled());
+    token.cancel();
+    assert!(CancellationToken::from_bytes(&token.to_bytes()).unwrap().is_cancelled());
+    // Deadlines travel as the time left.
+    let expiring = CancellationToken::from_bytes(&CancellationToken::with_timeout(Duration::ZERO).to_bytes()).unwrap();
+    assert!(expiring.is_cancelled());
+    let later = CancellationToken::from_bytes(&CancellationToken::with_timeout(Duration::from_secs(60)).to_bytes());
+    assert!(!later.unwrap().is_cancelled());
+
+    let retriever = retriever();
+    let query = DatapointPtr::new(vec![0.1, 0.2, 0.3, 0.4]);
+    let prepared = retriever.prepare(&query).unwrap();
+    let decoded = PreparedQuery::from_bytes(&prepared.to_bytes(), &retriever).unwrap();
+    assert_eq!(decoded.values(), prepared.values());
+    let options = SearchOptions::default();
+    assert_eq!(
+        retriever.search_prepared(&decoded, &options).unwrap().0,
+        retriever.search_prepared(&prepared, &options).unwrap().0
+    );
+    // Decoding validates the query against the retriever it binds to.
+    let other = ScannRetriever::new(
+        DenseDataset::new(vec![vec![0.0; 3]], 3),
+        Box::new(SquaredL2Distance::new()),
+        1,
+    );
+    assert!(PreparedQuery::from_bytes(&prepared.to_bytes(), &other).is_err());
+}
+
+#[test]
+fn pages_resume_from_decoded_cursors() {
+    let retriever = retriever();
+    let query = DatapointPtr::new(vec![0.5, -0.5, 0.25, 1.0]);
+    let mut pages = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (page, next) = retriever.search_page(&query, &page_options(), cursor.as_ref()).unwrap();
+        pages.extend(page);
+        match next {
+            // Each cursor crosses a process boundary as bytes.
+            Some(next) => cursor = Some(SearchCursor::from_bytes(&next.to_bytes()).unwrap()),
+            None => break,
+        }
+    }
+    let all = SearchOptions {
+        k: Some(500),
+        ..SearchOptions::default()
+    };
+    assert_eq!(pages, retriever.search_with_options(&query, &all).unwrap().0);
+}
+
+#[test]
+fn cursors_issued_before_a_mutation_are_stale() {
+    let retriever = retriever();
+    let query = DatapointPtr::new(vec![0.5, -0.5, 0.25, 1.0]);
+    let (_, cursor) = retriever.search_page(&query, &page_options(), None).unwrap();
+    let bytes = cursor.unwrap().to_bytes();
+
+    let other_query = DatapointPtr::new(vec![1.0, 1.0, 1.0, 1.0]);
+    let cursor = SearchCursor::from_bytes(&bytes).unwrap();
+    let error = retriever.search_page(&other_query, &page_options(), Some(&cursor)).unwrap_err();
+    assert!(error.to_string().contains("different query"), "{}", error);
+
+    retriever.add(&[9.0, 9.0, 9.0, 9.0]).unwrap();
+    let error = retriever.search_page(&query, &page_options(), Some(&cursor)).unwrap_err();
+    assert!(error.to_string().contains("Stale SearchCursor"), "{}", error);
+}
+
+#[test]
+fn current_calibrator_bytes_are_pinned() {
+    // version 1 | kind 3 | a f64 LE | b f64 LE | fnv1a64 LE. Later versions
+    // must keep decoding these bytes.
+    let calibrator = Calibrator { a: -2.0, b: 0.5 };
+    let mut expected = vec![1, WIRE_KIND_CALIBRATOR];
+    expected.extend_from_slice(&(-2.0f64).to_le_bytes());
+    expected.extend_from_slice(&0.5f64.to_le_bytes());
+    let checksum = scann::blob::fnv1a64(&expected);
+    expected.extend_from_slice(&checksum.to_le_bytes());
+    assert_eq!(calibrator.to_bytes(), expected);
+    assert_eq!(Calibrator::from_bytes(&expected).unwrap(), calibrator);
+}
+
+#[test]
+fn truncated_and_bit_flipped_encodings_are_rejected() {
+    for (name, bytes, decode) in encodings() {
+        decode(&bytes).unwrap();
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "{} truncated to {} bytes", name, len);
+        }
+        for bit in 0..bytes.len() * 8 {
+            let mut flipped = bytes.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert!(decode(&flipped).is_err(), "{} with bit {} flipped", name, bit);
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(decode(&extended).is_err(), "{} with a trailing byte", name);
+    }
+}
+
+#[test]
+fn newer_versions_other_kinds_and_trailing_payload_are_rejected() {
+    let payload = [0u8; 16];
+    let newer = binfmt::write_wire(WIRE_KIND_CALIBRATOR, 2, &payload);
+    let error = Calibrator::from_bytes(&newer).unwrap_err();
+    assert!(error.to_string().contains("version 2"), "{}", error);
+    assert!(error.to_string().contains("1..=1"), "{}", error);
+
+    // A calibrator's bytes never decode as a cursor.
+    let calibrator = Calibrator { a: 1.0, b: 2.0 }.to_bytes();
+    assert!(SearchCursor::from_bytes(&calibrator).is_err());
+    let relabeled = binfmt::write_wire(WIRE_KIND_SEARCH_CURSOR, 1, &payload);
+    assert!(Calibrator::from_bytes(&relabeled).is_err());
+
+    // Correctly framed but with an extra payload byte.
+    assert!(Calibrator::from_bytes(&binfmt::write_wire(WIRE_KIND_CALIBRATOR, 1, &[0u8; 17])).is_err());
+    let mut not_finite = f64::NAN.to_le_bytes().to_vec();
+    not_finite.extend_from_slice(&1.0f64.to_le_bytes());
+    assert!(Calibrator::from_bytes(&binfmt::write_wire(WIRE_KIND_CALIBRATOR, 1, &not_finite)).is_err());
+}
@@ -0,0 +1,203 @@
+// Copyright 2025 The Google Research Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Zero vectors under cosine and normalized configurations: rejected or
+//! quarantined at ingest, never scored, and never collapsing k-means
+//! centers.
+
+use scann::distance_measures::{CosineDistance, NormalizedDotProductDistance, SquaredL2Distance};
+use scann::quantization::Int8QuantizationConfig;
+use scann::retrieval::{Int8Codes, ScannRetriever, SearchOptions};
+use scann::tree::{self, KMeansTreeTrainingOptions};
+use scann::util::{DatapointPtr, DenseDataset, Normalization, SplitMix64, ZeroVectorPolicy};
+use std::ops::ControlFlow;
+
+const NUM_POINTS: usize = 500;
+const DIM: usize = 8;
+const NUM_LEAVES: usize = 8;
+
+fn is_zero_row(docid: usize) -> bool {
+    docid.is_multiple_of(10)
+}
+
+// Every tenth row is zero.
+fn dataset_with_zero_rows() -> DenseDataset<f32> {
+    let mut rng = SplitMix64::new(1);
+    let rows = (0..NUM_POINTS)
+        .map(|d| match is_zero_row(d) {
+            true => vec![0.0; DIM],
+            false => (0..DIM).map(|_| rng.next_normal()).collect(),
+        })
+        .collect();
+    DenseDataset::new(rows, DIM)
+}
+
+fn training() -> KMeansTreeTrainingOptions {
+    let mut options = KMeansTreeTrainingOptions::new();
+    options.max_iterations = 5;
+    options
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Pipeline {
+    BruteForce,
+    Tree,
+    Int8,
+}
+
+fn quarantining(normalized: bool, pipeline: Pipeline) -> ScannRetriever {
+    let retriever = if normalized {
+        ScannRetriever::new(dataset_with_zero_rows(), Box::new(NormalizedDotProductDistance::new()), 20)
+            .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+            .unwrap()
+            .with_normalization(Normalization::UnitL2)
+            .unwrap()
+    } else {
+        ScannRetriever::new(dataset_with_zero_rows(), Box::new(CosineDistance::new()), 20)
+            .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+            .unwrap()
+    };
+    if let Pipeline::Tree | Pipeline::Int8 = pipeline {
+        retriever.build_partitions(NUM_LEAVES, &training()).unwrap();
+    }
+    if let Pipeline::Int8 = pipeline {
+        retriever
+            .register_derived_data(Box::new(Int8Codes::new(Int8QuantizationConfig::new())))
+            .unwrap();
+        retriever.build_leaf_code_store().unwrap();
+    }
+    retriever
+}
+
+#[test]
+fn quarantined_rows_never_reach_results_in_any_pipeline() {
+    let mut rng = SplitMix64::new(2);
+    let queries: Vec<Vec<f32>> = (0..20).map(|_| (0..DIM).map(|_| rng.next_normal()).collect()).collect();
+    let options = SearchOptions { leaves_to_search: Some(NUM_LEAVES), ..SearchOptions::default() };
+    for normalized in [false, true] {
+        for pipeline in [Pipeline::BruteForce, Pipeline::Tree, Pipeline::Int8] {
+            let context = format!("normalized {} {:?}", normalized, pipeline);
+            let retriever = quarantining(normalized, pipeline);
+            assert_eq!(retriever.num_quarantined(), NUM_POINTS / 10, "{}", context);
+            for query in &queries {
+                let (results, _) = retriever.search_with_options(&DatapointPtr::new(query.clone()), &options).unwrap();
+                assert_eq!(results.len(), 20, "{}", context);
+                for &(docid, distance) in &results {
+                    assert!(distance.is_finite(), "{}: docid {} scored {}", context, docid, distance);
+                    assert!(!is_zero_row(docid), "{}: quarantined docid {} returned", context, docid);
+                }
+            }
+
+            let mut visited = 0;
+            let _ = retriever.for_each_active(|record| {
+                assert!(!is_zero_row(record.docid), "{}", context);
+                visited += 1;
+                ControlFlow::Continue(())
+            });
+            assert_eq!(visited, NUM_POINTS - NUM_POINTS / 10, "{}", context);
+            if let Pipeline::Tree | Pipeline::Int8 = pipeline {
+                let exported: usize = (0..NUM_LEAVES)
+                    .map(|leaf| retriever.export_partition(leaf, &mut Vec::new()).unwrap())
+                    .sum();
+                assert_eq!(exported, NUM_POINTS - NUM_POINTS / 10, "{}", context);
+            }
+            let report = retriever.certify(10, 10, 3, &options).unwrap();
+            assert_eq!(report.quarantined, NUM_POINTS / 10, "{}", context);
+            assert!(report.max_distance_error.is_finite(), "{}: {:?}", context, report);
+        }
+    }
+}
+
+#[test]
+fn the_reject_policy_refuses_zero_rows() {
+    let error = ScannRetriever::new(dataset_with_zero_rows(), Box::new(CosineDistance::new()), 5)
+        .with_zero_vector_policy(ZeroVectorPolicy::Reject)
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("Docid 0 is a zero vector"), "{}", error);
+    assert!(ScannRetriever::new_cosine(dataset_with_zero_rows(), 5, true).is_err());
+
+    let rows = DenseDataset::new(vec![vec![1.0, 0.0], vec![0.0, 1.0]], 2);
+    let retriever = ScannRetriever::new(rows, Box::new(CosineDistance::new()), 5);
+    let error = retriever.add(&[0.0, 0.0]).unwrap_err();
+    assert!(error.to_string().contains("Zero vectors cannot be scored"), "{}", error);
+    assert!(retriever.upsert(1, &[0.0, 0.0]).is_err());
+    assert_eq!(retriever.get_by_docid(1), Some(vec![0.0, 1.0]));
+    assert_eq!(retriever.num_quarantined(), 0);
+}
+
+#[test]
+fn normalize_rows_leaves_the_dataset_unchanged_on_a_zero_row() {
+    let rows = vec![vec![3.0, 4.0], vec![0.0, 0.0], vec![2.0, 0.0]];
+    let mut dataset = DenseDataset::new(rows.clone(), 2);
+    let error = dataset.normalize_rows().unwrap_err();
+    assert!(error.to_string().contains("Row 1"), "{}", error);
+    assert_eq!(dataset.data, rows);
+
+    dataset.data.remove(1);
+    dataset.normalize_rows().unwrap();
+    assert_eq!(dataset.data, vec![vec![0.6, 0.8], vec![1.0, 0.0]]);
+}
+
+#[test]
+fn quarantine_follows_adds_and_upserts() {
+    let rows = DenseDataset::new(vec![vec![1.0, 0.0], vec![0.0, 1.0]], 2);
+    let retriever = ScannRetriever::new(rows, Box::new(CosineDistance::new()), 5)
+        .with_zero_vector_policy(ZeroVectorPolicy::Quarantine)
+        .unwrap();
+    let docid = retriever.add(&[0.0, 0.0]).unwrap();
+    retriever.upsert(1, &[0.0, 0.0]).unwrap();
+    assert_eq!(retriever.num_quarantined(), 2);
+    let query = DatapointPtr::new(vec![1.0, 1.0]);
+    assert_eq!(retriever.search(&query).unwrap().iter().map(|r| r.0).collect::<Vec<_>>(), vec![0]);
+
+    // Upserting a nonzero row releases it.
+    retriever.upsert(docid, &[1.0, 1.0]).unwrap();
+    assert_eq!(retriever.num_quarantined(), 1);
+    assert_eq!(retriever.search(&query).unwrap()[0].0, docid);
+    retriever.remove(1).unwrap();
+    retriever.compact().unwrap();
+    assert_eq!(retriever.num_quarantined(), 0);
+}
+
+#[test]
+fn zero_queries_are_rejected_only_where_they_have_no_direction() {
+    let zero = DatapointPtr::new(vec![0.0; DIM]);
+    for normalized in [false, true] {
+        let error = quarantining(normalized, Pipeline::BruteForce).search(&zero).unwrap_err();
+        assert!(error.to_string().contains("Query is a zero vector"), "{}", error);
+    }
+    // Squared L2 scores zero rows and queries like any other.
+    let retriever = ScannRetriever::new(dataset_with_zero_rows(), Box::new(SquaredL2Distance::new()), 5);
+    assert_eq!(retriever.num_quarantined(), 0);
+    let results = retriever.search(&zero).unwrap();
+    assert!(results.iter().all(|&(docid, distance)| is_zero_row(docid) && distance == 0.0));
+}
+
+#[test]
+fn kmeans_reseeds_centers_that_collapse_to_zero() {
+    // The first center starts at the origin and owns the two antipodal
+    // points, so its mean stays at zero.
+    let data = DenseDataset::new(vec![vec![0.0, 1.0], vec![0.0, -1.0], vec![5.0, 0.0], vec![5.1, 0.0]], 2);
+    let mut options = training();
+    options.max_iterations = 1;
+    options.warm_start_centers = Some(DenseDataset::new(vec![vec![0.0, 0.0], vec![5.0, 0.0]], 2));
+    let norm = |center: &Vec<f32>| center.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+    let collapsed = tree::train_kmeans(&data, 2, &options).unwrap();
+    assert_eq!(norm(&collapsed.centers.data[0]), 0.0);
+    options.reinitialize_zero_centers = true;
+    let reseeded = tree::train_kmeans(&data, 2, &options).unwrap();
+    assert!(reseeded.centers.data.iter().all(|center| norm(center) >= 1e-6), "{:?}", reseeded.centers);
+}